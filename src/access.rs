@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use base64;
+
+use ::errors::AccessError;
+
+// Who may use the daemon's own HTTP endpoints. With nothing configured every request is refused.
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct AccessConfig {
+    // Accepted as `Authorization: Bearer <token>`
+    pub tokens: Option<Vec<String>>,
+    // Usernames and passwords accepted with basic authentication
+    pub users: Option<BTreeMap<String, String>>,
+    // Addresses or CIDR ranges, such as "10.0.0.0/8", that requests must come from
+    pub allow: Option<Vec<String>>
+}
+
+impl AccessConfig {
+    // `authorization` is the value of the request's Authorization header
+    pub fn check(&self, remote: &IpAddr, authorization: Option<&str>) -> Result<(), AccessError> {
+        if let Some(ref allow) = self.allow {
+            if !allow.iter().any(|range| in_range(remote, range)) {
+                return Err(AccessError::Forbidden(*remote));
+            }
+        }
+
+        let tokens = self.tokens.as_ref().map_or(0, Vec::len);
+        let users = self.users.as_ref().map_or(0, BTreeMap::len);
+        if tokens + users == 0 {
+            return match self.allow {
+                Some(_) => Ok(()),
+                None => Err(AccessError::Unauthorized)
+            };
+        }
+
+        match authorization {
+            Some(authorization) if self.authenticates(authorization) => Ok(()),
+            _ => Err(AccessError::Unauthorized)
+        }
+    }
+
+    fn authenticates(&self, authorization: &str) -> bool {
+        if let Some(token) = authorization.strip_prefix("Bearer ") {
+            let token = token.trim();
+            return self.tokens.as_ref().map_or(false, |tokens| {
+                tokens.iter().any(|expected| constant_time_eq(expected.as_bytes(), token.as_bytes()))
+            });
+        }
+        if let Some(basic) = authorization.strip_prefix("Basic ") {
+            let decoded = match base64::decode(basic.trim()).map(String::from_utf8) {
+                Ok(Ok(decoded)) => decoded,
+                _ => return false
+            };
+            // Without a colon the whole value is the username, with an empty password
+            let mut parts = decoded.splitn(2, ':');
+            let username = parts.next().unwrap_or("");
+            let password = parts.next().unwrap_or("");
+            return self.users.as_ref()
+                .and_then(|users| users.get(username))
+                .map_or(false, |expected| constant_time_eq(expected.as_bytes(), password.as_bytes()));
+        }
+        false
+    }
+}
+
+fn in_range(remote: &IpAddr, range: &str) -> bool {
+    let mut parts = range.splitn(2, '/');
+    let network = match parts.next().and_then(|address| address.parse::<IpAddr>().ok()) {
+        Some(network) => network,
+        None => return false
+    };
+    let (remote, network) = match (*remote, network) {
+        (IpAddr::V4(remote), IpAddr::V4(network)) => (remote.octets().to_vec(), network.octets().to_vec()),
+        (IpAddr::V6(remote), IpAddr::V6(network)) => (remote.octets().to_vec(), network.octets().to_vec()),
+        _ => return false
+    };
+    let prefix = match parts.next() {
+        Some(prefix) => match prefix.parse::<usize>() {
+            Ok(prefix) if prefix <= network.len() * 8 => prefix,
+            _ => return false
+        },
+        None => network.len() * 8
+    };
+
+    remote.iter().zip(network.iter()).enumerate().all(|(index, (remote, network))| {
+        let bits = prefix.saturating_sub(index * 8).min(8);
+        let mask = (0xff00u16 >> bits) as u8;
+        remote & mask == network & mask
+    })
+}
+
+// Takes the same time wherever the first difference is, so that secrets cannot be guessed byte by byte
+pub fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter().zip(right.iter()).fold(0, |difference, (left, right)| difference | (left ^ right)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::net::IpAddr;
+    use errors::AccessError;
+    use super::{constant_time_eq, in_range, AccessConfig};
+
+    fn address(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn config(allow: Option<Vec<String>>) -> AccessConfig {
+        let mut users = BTreeMap::new();
+        users.insert("admin".to_owned(), "hunter2".to_owned());
+        AccessConfig {
+            tokens: Some(vec!["s3cr3t".to_owned()]),
+            users: Some(users),
+            allow: allow
+        }
+    }
+
+    #[test]
+    fn configured_credentials_are_accepted() {
+        let config = config(None);
+        let local = address("127.0.0.1");
+        assert!(config.check(&local, Some("Bearer s3cr3t")).is_ok());
+        // admin:hunter2
+        assert!(config.check(&local, Some("Basic YWRtaW46aHVudGVyMg==")).is_ok());
+    }
+
+    #[test]
+    fn missing_or_wrong_credentials_are_rejected() {
+        let config = config(None);
+        let local = address("127.0.0.1");
+        for authorization in [None, Some("Bearer wrong"), Some("Basic YWRtaW46d3Jvbmc="), Some("Digest x")].iter() {
+            match config.check(&local, *authorization) {
+                Err(AccessError::Unauthorized) => {},
+                _ => panic!("Expected {:?} to be rejected", authorization)
+            }
+        }
+    }
+
+    #[test]
+    fn requests_outside_the_allowlist_are_forbidden() {
+        let config = config(Some(vec!["10.0.0.0/8".to_owned()]));
+        assert!(config.check(&address("10.1.2.3"), Some("Bearer s3cr3t")).is_ok());
+        match config.check(&address("192.168.0.1"), Some("Bearer s3cr3t")) {
+            Err(AccessError::Forbidden(_)) => {},
+            _ => panic!("Expected the request to be forbidden")
+        }
+    }
+
+    #[test]
+    fn nothing_configured_refuses_everything() {
+        let config = AccessConfig { tokens: None, users: None, allow: None };
+        assert!(config.check(&address("127.0.0.1"), Some("Bearer s3cr3t")).is_err());
+    }
+
+    #[test]
+    fn ranges_match_on_their_prefix() {
+        assert!(in_range(&address("192.168.1.20"), "192.168.1.0/27"));
+        assert!(!in_range(&address("192.168.1.20"), "192.168.1.0/28"));
+        assert!(in_range(&address("10.0.0.1"), "10.0.0.1"));
+        assert!(in_range(&address("fd00::1"), "fd00::/8"));
+        assert!(!in_range(&address("10.0.0.1"), "fd00::/8"));
+    }
+
+    #[test]
+    fn comparison_requires_equal_lengths() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+}
@@ -0,0 +1,784 @@
+use std::sync::Arc;
+
+use hyper;
+use serde::Serialize;
+use serde_json;
+use time;
+
+use ::credentials;
+use ::errors::{ApiError, ConfigError, RepositoryError};
+use ::events;
+use ::fanout;
+use ::registry;
+use ::i18n;
+use ::rest;
+use ::templates;
+
+static DEFAULT_BASE_URL: &'static str = "https://dev.azure.com";
+static API_VERSION: &'static str = "api-version=7.0";
+// The old object of a reference that is being created
+static NO_COMMIT: &'static str = "0000000000000000000000000000000000000000";
+// Reviewers vote 10 to approve and 5 to approve with suggestions
+static APPROVED_VOTE: i32 = 5;
+// The most pull requests Azure DevOps serves at once
+static PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct List<T> {
+    value: Vec<T>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PullRequest {
+    pull_request_id: i32,
+    title: String,
+    description: Option<String>,
+    source_ref_name: String,
+    target_ref_name: String,
+    last_merge_source_commit: Option<CommitReference>,
+    created_by: Identity,
+    #[serde(default)]
+    reviewers: Vec<Identity>,
+    // Only set for pull requests from forks
+    fork_source: Option<serde_json::Value>,
+    creation_date: Option<String>,
+    closed_date: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CommitReference {
+    commit_id: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Identity {
+    id: String,
+    display_name: String,
+    // The account's sign-in name, usually its email address
+    unique_name: Option<String>,
+    // Only for reviewers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vote: Option<i32>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Thread {
+    id: i64,
+    #[serde(default)]
+    comments: Vec<Comment>,
+    #[serde(default)]
+    is_deleted: bool,
+    last_updated_date: Option<String>
+}
+
+// Each push to the source branch of a pull request makes an iteration of it
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Iteration {
+    created_date: Option<String>,
+    updated_date: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Comment {
+    id: i64,
+    content: Option<String>,
+    author: Identity
+}
+
+// The daemon posts each of its comments as the first comment of a thread of its own
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ThreadComment {
+    thread_id: i64,
+    id: i64,
+    text: String
+}
+
+impl templates::OwnComment for ThreadComment {
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn to_event(&self) -> events::PostedComment {
+        events::PostedComment {
+            id: self.thread_id,
+            // Azure DevOps comments are not versioned
+            version: 0,
+            text: self.text.to_owned()
+        }
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    match timestamp.get(..19).map(|seconds| time::strptime(seconds, "%Y-%m-%dT%H:%M:%S")) {
+        Some(Ok(tm)) => Some(tm.to_timespec().sec * 1000),
+        _ => None
+    }
+}
+
+// The latest of `timestamps`, skipping those that are not set
+fn latest<'a, I>(timestamps: I) -> Option<i64> where I: IntoIterator<Item = &'a Option<String>> {
+    timestamps.into_iter().filter_map(|timestamp| timestamp.as_ref().and_then(|timestamp| parse_timestamp(timestamp)))
+        .max()
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Commit {
+    commit_id: String,
+    comment: String,
+    author: Option<CommitAuthor>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitAuthor {
+    name: String,
+    email: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Reference {
+    name: String,
+    object_id: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    state: StatusState,
+    description: String,
+    context: StatusContext,
+    target_url: String
+}
+
+// Branch policies requiring a status match on its name and genre
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct StatusContext {
+    name: String,
+    genre: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+enum StatusState {
+    Pending,
+    Succeeded,
+    Failed
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct AzureDevopsCredentials {
+    // The sign-in name of the account the daemon comments as, so that it finds its own comments again
+    pub username: String,
+    // A personal access token, unless `credential_provider` is set
+    #[serde(default)]
+    pub token: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    // https://dev.azure.com unless set to an Azure DevOps Server
+    pub base_url: Option<String>,
+    // The organization, or the collection on Azure DevOps Server
+    pub organization: String,
+    pub project: String,
+    pub repository: String,
+    pub post_build: bool,
+    pub templates: Option<templates::Templates>,
+    pub superseded_comments: Option<String>
+}
+
+pub struct AzureDevopsFactory;
+
+impl registry::RepositoryFactory for AzureDevopsFactory {
+    fn create(&self, settings: &serde_json::Value, messages: &i18n::Messages,
+        fanout: &fanout::Fanout<fanout::Message>) -> Result<Box<::Repository>, ConfigError> {
+        let credentials = match serde_json::from_value::<AzureDevopsCredentials>(settings.to_owned()) {
+            Ok(credentials) => credentials,
+            Err(err) => return Err(ConfigError::Parse(err))
+        };
+        match AzureDevops::new(&credentials, messages, fanout) {
+            Ok(azure_devops) => Ok(Box::new(azure_devops)),
+            Err(err) => Err(ConfigError::Invalid(format!("Failed to load comment templates: {}", err)))
+        }
+    }
+}
+
+pub struct AzureDevops {
+    pub credentials: AzureDevopsCredentials,
+    broadcaster: fanout::Fanout<fanout::Message>,
+    renderer: templates::Renderer
+}
+
+fn is_commit(start_point: &str) -> bool {
+    start_point.len() == 40 && start_point.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl ::Repository for AzureDevops {
+    fn get_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.get_pr_list_by_status("active")
+    }
+
+    fn get_merged_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.get_pr_list_by_status("completed")
+    }
+
+    fn get_pr_diff(&self, _: &::PullRequest) -> Result<String, RepositoryError> {
+        Err(RepositoryError::api("getting diff",
+            ApiError::Remote("Azure DevOps does not serve the diffs of pull requests".to_owned())))
+    }
+
+    fn get_pr_commits(&self, pr: &::PullRequest) -> Result<Vec<::Commit>, RepositoryError> {
+        let url = format!("{}/pullRequests/{}/commits?{}", self.repository_url(), pr.id, API_VERSION);
+
+        let commits = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<List<Commit>>(&url, &headers.headers)
+        });
+        // Azure DevOps does not say which commits are merges
+        match commits {
+            Ok(commits) => Ok(commits.value.iter().map(|commit| ::Commit {
+                id: commit.commit_id.to_owned(),
+                message: commit.comment.to_owned(),
+                merge: false,
+                author: commit.author.as_ref().map(|author| ::User {
+                    name: author.name.to_owned(),
+                    email: author.email.to_owned().unwrap_or_default(),
+                    username: None
+                })
+            }).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of commits", err))
+        }
+    }
+
+    // Threads are told apart by their IDs, as the IDs of their comments only count up within each thread
+    fn get_pr_comments(&self, pr: &::PullRequest) -> Result<Vec<::Comment>, RepositoryError> {
+        match self.get_comments(pr.id) {
+            Ok(comments) => Ok(comments.into_iter().map(|(comment, author)| ::Comment {
+                id: comment.thread_id,
+                author: author.unique_name.unwrap_or_default(),
+                text: comment.text
+            }).collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    // Approvals are listed along with the pull requests
+    fn get_pr_approvals(&self, pr: &::PullRequest) -> Result<Vec<String>, RepositoryError> {
+        Ok(pr.approvals.to_owned())
+    }
+
+    fn build_queued(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_running(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_success(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "success")
+    }
+
+    fn build_failure(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "failure")
+    }
+
+    fn build_matrix(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>) -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_matrix(pr, builds) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::matrix(pr);
+        let comment = templates::upsert_comment(self, pr, &text, &key, "MatrixComment", |comment| {
+            events::MatrixCommentEvent {
+                pr: pr.to_owned(),
+                builds: builds.to_owned(),
+                comment: comment
+            }
+        });
+        if let Err(err) = comment {
+            return Err(err);
+        }
+        if self.credentials.post_build {
+            for build in builds {
+                if let Err(err) = self.post_status(pr, &AzureDevops::make_status(build)) {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn upsert_comment(&self, pr: &::PullRequest, key: &templates::CommentKey, text: &str)
+            -> Result<(), RepositoryError> {
+        templates::upsert_comment(self, pr, text, key, "Notice", |comment| events::NoticeEvent {
+            pr: pr.to_owned(),
+            kind: key.kind.to_owned(),
+            comment: comment
+        }).map(|_| ())
+    }
+
+    fn report_check(&self, pr: &::PullRequest, check: &::CheckResult) -> Result<(), RepositoryError> {
+        let status = Status {
+            state: match check.passed {
+                true => StatusState::Succeeded,
+                false => StatusState::Failed
+            },
+            description: check.summary.to_owned(),
+            context: StatusContext {
+                name: check.name.to_owned(),
+                genre: "pr_demon".to_owned()
+            },
+            target_url: pr.web_url.to_owned()
+        };
+        self.post_status(pr, &status)
+    }
+
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+            -> Result<::PullRequest, RepositoryError> {
+        let body = json!({
+            "title": title,
+            "description": description,
+            "sourceRefName": from_ref,
+            "targetRefName": to_ref
+        }).to_string();
+        let url = format!("{}/pullrequests?{}", self.repository_url(), API_VERSION);
+
+        let pr = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match pr {
+            Ok(pr) => Ok(self.to_pull_request(&pr)),
+            Err(err) => Err(RepositoryError::api("creating Pull Request", err))
+        }
+    }
+
+    fn decline_pr(&self, pr: &::PullRequest) -> Result<(), RepositoryError> {
+        let url = format!("{}/pullrequests/{}?{}", self.repository_url(), pr.id, API_VERSION);
+        let body = json!({ "status": "abandoned" }).to_string();
+        let declined = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::patch::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match declined {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("abandoning Pull Request", err))
+        }
+    }
+
+    // The strategy is one of Azure DevOps' merge strategies, such as "noFastForward", "squash" or "rebase"
+    fn merge_pr(&self, pr: &::PullRequest, strategy: Option<&str>, message: Option<&str>)
+            -> Result<(), RepositoryError> {
+        let mut options = serde_json::Map::new();
+        if let Some(strategy) = strategy {
+            options.insert("mergeStrategy".to_owned(), json!(strategy));
+        }
+        if let Some(message) = message {
+            options.insert("mergeCommitMessage".to_owned(), json!(message));
+        }
+        // Completing fails rather than merging a commit that has not been built should the branch have moved on
+        let body = json!({
+            "status": "completed",
+            "lastMergeSourceCommit": { "commitId": pr.from_commit },
+            "completionOptions": options
+        }).to_string();
+        let url = format!("{}/pullrequests/{}?{}", self.repository_url(), pr.id, API_VERSION);
+        let merged = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::patch::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match merged {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("completing Pull Request", err))
+        }
+    }
+
+    // Reviewers are added by their IDs, which are looked up by their sign-in names
+    fn add_reviewers(&self, pr: &::PullRequest, reviewers: &[String]) -> Result<(), RepositoryError> {
+        for reviewer in reviewers {
+            let identity_url = format!("{}/_apis/identities?searchFilter=General&filterValue={}&{}",
+                self.identity_url(), reviewer, API_VERSION);
+            let identities = credentials::authorized(&*self.provider(), |mut headers| {
+                headers.add_accept_json_header();
+                rest::get::<List<Identity>>(&identity_url, &headers.headers)
+            });
+            let id = match identities {
+                Ok(ref identities) if identities.value.is_empty() => {
+                    println!("Unable to add unknown reviewer {}", reviewer);
+                    continue;
+                },
+                Ok(identities) => identities.value[0].id.to_owned(),
+                Err(err) => return Err(RepositoryError::api("adding reviewer", err))
+            };
+            let url = format!("{}/pullRequests/{}/reviewers/{}?{}", self.repository_url(), pr.id, id, API_VERSION);
+            let added = credentials::authorized(&*self.provider(), |mut headers| {
+                headers.add_accept_json_header()
+                    .add_content_type_json_header();
+                rest::put::<serde_json::Value>(&url, "{\"vote\": 0}", &headers.headers, &hyper::StatusCode::OK)
+            });
+            if let Err(err) = added {
+                return Err(RepositoryError::api("adding reviewer", err));
+            }
+        }
+        Ok(())
+    }
+
+    fn create_branch(&self, name: &str, start_point: &str) -> Result<(), RepositoryError> {
+        // References are created from a commit, so a branch given as the start point is resolved first
+        let commit = match is_commit(start_point) {
+            true => start_point.to_owned(),
+            false => {
+                let start_branch = start_point.trim_start_matches("refs/heads/");
+                let refs_url = format!("{}/refs?filter=heads/{}&{}", self.repository_url(), start_branch,
+                    API_VERSION);
+                let refs = credentials::authorized(&*self.provider(), |mut headers| {
+                    headers.add_accept_json_header();
+                    rest::get::<List<Reference>>(&refs_url, &headers.headers)
+                });
+                let wanted = format!("refs/heads/{}", start_branch);
+                match refs {
+                    Ok(refs) => match refs.value.into_iter().find(|reference| reference.name == wanted) {
+                        Some(reference) => reference.object_id,
+                        None => return Err(RepositoryError::api("creating branch",
+                            ApiError::Remote(format!("There is no branch {}", start_branch))))
+                    },
+                    Err(err) => return Err(RepositoryError::api("creating branch", err))
+                }
+            }
+        };
+        let url = format!("{}/refs?{}", self.repository_url(), API_VERSION);
+        let body = json!([{
+            "name": format!("refs/heads/{}", name.trim_start_matches("refs/heads/")),
+            "oldObjectId": NO_COMMIT,
+            "newObjectId": commit
+        }]).to_string();
+        let created = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match created {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("creating branch", err))
+        }
+    }
+}
+
+impl AzureDevops {
+    pub fn new(credentials: &AzureDevopsCredentials, messages: &i18n::Messages,
+        broadcaster: &fanout::Fanout<fanout::Message>) -> Result<AzureDevops, String> {
+        let renderer = match templates::Renderer::new(&credentials.templates, messages, &credentials.repository) {
+            Ok(renderer) => renderer,
+            Err(err) => return Err(err)
+        };
+
+        Ok(AzureDevops {
+            credentials: credentials.to_owned(),
+            broadcaster: broadcaster.to_owned(),
+            renderer: renderer
+        })
+    }
+
+    fn base_url(&self) -> &str {
+        self.credentials.base_url.as_ref().map_or(DEFAULT_BASE_URL, String::as_str).trim_end_matches('/')
+    }
+
+    fn repository_url(&self) -> String {
+        format!("{}/{}/{}/_apis/git/repositories/{}", self.base_url(), self.credentials.organization,
+            self.credentials.project, self.credentials.repository)
+    }
+
+    // Identities are served from their own host in the cloud
+    fn identity_url(&self) -> String {
+        match self.credentials.base_url {
+            None => format!("https://vssps.dev.azure.com/{}", self.credentials.organization),
+            Some(_) => format!("{}/{}", self.base_url(), self.credentials.organization)
+        }
+    }
+
+    // Pull requests only say when they were created and closed, see `last_activity` for the rest
+    fn to_pull_request(&self, pr: &PullRequest) -> ::PullRequest {
+        ::PullRequest {
+            id: pr.pull_request_id,
+            web_url: format!("{}/{}/{}/_git/{}/pullrequest/{}", self.base_url(), self.credentials.organization,
+                self.credentials.project, self.credentials.repository, pr.pull_request_id),
+            from_ref: pr.source_ref_name.to_owned(),
+            from_commit: pr.last_merge_source_commit.as_ref().map(|commit| commit.commit_id.to_owned())
+                .unwrap_or_default(),
+            to_ref: pr.target_ref_name.to_owned(),
+            from_fork: pr.fork_source.is_some(),
+            title: pr.title.to_owned(),
+            description: pr.description.to_owned().unwrap_or_default(),
+            updated: latest(vec![&pr.creation_date, &pr.closed_date]),
+            author: ::User {
+                name: pr.created_by.display_name.to_owned(),
+                email: pr.created_by.unique_name.to_owned().unwrap_or_default(),
+                username: pr.created_by.unique_name.to_owned()
+            },
+            reviewers: pr.reviewers.iter().filter_map(|reviewer| reviewer.unique_name.to_owned()).collect(),
+            approvals: pr.reviewers.iter().filter(|reviewer| reviewer.vote.map_or(false, |vote| vote >= APPROVED_VOTE))
+                .filter_map(|reviewer| reviewer.unique_name.to_owned()).collect(),
+            size: None,
+            open_tasks: None,
+            labels: vec![]
+        }
+    }
+
+    // Pages are asked for by skipping the pull requests of those before, until one comes back short. Open pull
+    // requests are updated as of their last activity.
+    fn get_pr_list_by_status(&self, status: &str) -> Result<Vec<::PullRequest>, RepositoryError> {
+        let mut prs = vec![];
+        loop {
+            let url = format!("{}/pullrequests?searchCriteria.status={}&$top={}&$skip={}&{}", self.repository_url(),
+                status, PAGE_SIZE, prs.len(), API_VERSION);
+            let page = credentials::authorized(&*self.provider(), |mut headers| {
+                headers.add_accept_json_header();
+                rest::get::<List<PullRequest>>(&url, &headers.headers)
+            });
+            let page = match page {
+                Ok(page) => page.value,
+                Err(err) => return Err(RepositoryError::api("getting list of Pull Requests", err))
+            };
+            let last = page.len() < PAGE_SIZE;
+            prs.extend(page);
+            if last {
+                break;
+            }
+        }
+        let mut pull_requests = vec![];
+        for pr in &prs {
+            let mut pull_request = self.to_pull_request(pr);
+            if status == "active" {
+                match self.last_activity(pr) {
+                    Ok(updated) => pull_request.updated = updated,
+                    Err(err) => return Err(err)
+                }
+            }
+            pull_requests.push(pull_request);
+        }
+        Ok(pull_requests)
+    }
+
+    // When anyone last commented on a pull request or pushed to it, which Azure DevOps only tells from its threads
+    // and iterations
+    fn last_activity(&self, pr: &PullRequest) -> Result<Option<i64>, RepositoryError> {
+        let url = format!("{}/pullRequests/{}/threads?{}", self.repository_url(), pr.pull_request_id, API_VERSION);
+        let threads = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<List<Thread>>(&url, &headers.headers)
+        });
+        let threads = match threads {
+            Ok(threads) => threads.value,
+            Err(err) => return Err(RepositoryError::api("getting list of comments", err))
+        };
+        let url = format!("{}/pullRequests/{}/iterations?{}", self.repository_url(), pr.pull_request_id, API_VERSION);
+        let iterations = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<List<Iteration>>(&url, &headers.headers)
+        });
+        let iterations = match iterations {
+            Ok(iterations) => iterations.value,
+            Err(err) => return Err(RepositoryError::api("getting list of iterations", err))
+        };
+        Ok(latest(Some(&pr.creation_date).into_iter()
+            .chain(threads.iter().map(|thread| &thread.last_updated_date))
+            .chain(iterations.iter().flat_map(|iteration| vec![&iteration.created_date, &iteration.updated_date]))))
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credentials.credential_provider, credentials::Credential::Basic {
+            username: "".to_owned(),
+            password: self.credentials.token.to_owned()
+        })
+    }
+
+    fn report_build(&self, pr: &::PullRequest, build: &::BuildDetails, template: &str)
+            -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_build(template, pr, build) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::status(pr, build);
+        let comment = templates::upsert_comment(self, pr, &text, &key, "Comment", |comment| events::CommentEvent {
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            comment: comment
+        });
+        if let Err(err) = comment {
+            return Err(err);
+        }
+        match self.credentials.post_build {
+            true => self.post_status(pr, &AzureDevops::make_status(build)),
+            false => Ok(())
+        }
+    }
+
+    // The first comment of every thread, replies being left out
+    fn get_comments(&self, pr_id: i32) -> Result<Vec<(ThreadComment, Identity)>, RepositoryError> {
+        let url = format!("{}/pullRequests/{}/threads?{}", self.repository_url(), pr_id, API_VERSION);
+
+        let threads = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<List<Thread>>(&url, &headers.headers)
+        });
+        match threads {
+            Ok(threads) => Ok(threads.value.into_iter()
+                .filter(|thread| !thread.is_deleted)
+                .filter_map(|thread| {
+                    let id = thread.id;
+                    thread.comments.into_iter().next().map(|comment| (ThreadComment {
+                        thread_id: id,
+                        id: comment.id,
+                        text: comment.content.unwrap_or_default()
+                    }, comment.author))
+                })
+                .collect()),
+            Err(err) => Err(RepositoryError::api("getting list of comments", err))
+        }
+    }
+
+    // Statuses are posted on the pull request rather than its commit, where branch policies can require them
+    fn post_status(&self, pr: &::PullRequest, status: &Status) -> Result<(), RepositoryError> {
+        let body = serde_json::to_string(status).unwrap();
+        let url = format!("{}/pullRequests/{}/statuses?{}", self.repository_url(), pr.id, API_VERSION);
+
+        let posted = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match posted {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("posting build", err))
+        }
+    }
+
+    fn make_status(build: &::BuildDetails) -> Status {
+        let state = match build.state {
+            ::BuildState::Finished => {
+                match build.status {
+                    ::BuildStatus::Success => StatusState::Succeeded,
+                    _ => StatusState::Failed
+                }
+            },
+            _ => StatusState::Pending
+        };
+
+        Status {
+            state: state,
+            description: build.status_text.to_owned().unwrap_or_default(),
+            context: StatusContext {
+                name: build.build_id.to_owned(),
+                genre: "pr_demon".to_owned()
+            },
+            target_url: build.web_url.to_owned()
+        }
+    }
+}
+
+impl templates::CommentApi for AzureDevops {
+    type Comment = ThreadComment;
+
+    fn renderer(&self) -> &templates::Renderer {
+        &self.renderer
+    }
+
+    fn superseded_comments(&self) -> Option<&str> {
+        self.credentials.superseded_comments.as_ref().map(String::as_str)
+    }
+
+    // The first comments of the threads the daemon started
+    fn get_own_comments(&self, pr_id: i32) -> Result<Vec<ThreadComment>, RepositoryError> {
+        match self.get_comments(pr_id) {
+            Ok(comments) => Ok(comments.into_iter()
+                .filter(|&(_, ref author)| author.unique_name.as_ref() == Some(&self.credentials.username))
+                .map(|(comment, _)| comment)
+                .collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn post_comment(&self, pr_id: i32, text: &str) -> Result<ThreadComment, RepositoryError> {
+        // A comment of type 1 is a plain text one, and a thread of status 1 is active
+        let body = json!({
+            "comments": [{ "parentCommentId": 0, "content": text, "commentType": 1 }],
+            "status": 1
+        }).to_string();
+        let url = format!("{}/pullRequests/{}/threads?{}", self.repository_url(), pr_id, API_VERSION);
+
+        let thread = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Thread>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match thread {
+            Ok(ref thread) if thread.comments.is_empty() => Err(RepositoryError::api("posting comment",
+                ApiError::Remote(format!("Thread {} was created without its comment", thread.id)))),
+            Ok(thread) => Ok(ThreadComment {
+                thread_id: thread.id,
+                id: thread.comments[0].id,
+                text: text.to_owned()
+            }),
+            Err(err) => Err(RepositoryError::api("posting comment", err))
+        }
+    }
+
+    fn edit_comment(&self, pr_id: i32, comment: &ThreadComment, text: &str)
+            -> Result<ThreadComment, RepositoryError> {
+        let body = json!({ "content": text }).to_string();
+        let url = format!("{}/pullRequests/{}/threads/{}/comments/{}?{}", self.repository_url(), pr_id,
+            comment.thread_id, comment.id, API_VERSION);
+
+        let edited = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::patch::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match edited {
+            Ok(_) => Ok(ThreadComment { text: text.to_owned(), ..comment.to_owned() }),
+            Err(err) => Err(RepositoryError::api("editing comment", err))
+        }
+    }
+
+    fn delete_comment(&self, pr_id: i32, comment: &ThreadComment) -> Result<(), RepositoryError> {
+        let url = format!("{}/pullRequests/{}/threads/{}/comments/{}?{}", self.repository_url(), pr_id,
+            comment.thread_id, comment.id, API_VERSION);
+
+        match credentials::authorized(&*self.provider(), |headers| rest::delete_raw(&url, &headers.headers)) {
+            Ok(ref response) if !response.status.is_success() => {
+                Err(RepositoryError::api("deleting comment", ApiError::Status(response.status)))
+            },
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("deleting comment", err))
+        }
+    }
+
+    fn broadcast<T>(&self, opcode: &str, payload: &T) where T : Serialize {
+        let opcode = fanout::OpCode::Custom {
+            payload: format!("AzureDevops::{}", opcode)
+        };
+        let message = fanout::Message::new(opcode, payload);
+        self.broadcaster.broadcast(&message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::latest;
+
+    #[test]
+    fn the_latest_timestamp_wins() {
+        let timestamps = vec![Some("2024-03-01T10:00:00.1234567Z".to_owned()), None,
+            Some("2024-03-02T08:30:00Z".to_owned()), Some("not a date".to_owned())];
+        assert_eq!(Some(1709368200000), latest(&timestamps));
+        assert_eq!(None, latest(&[None]));
+    }
+}
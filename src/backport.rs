@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use regex::Regex;
+
+use ::git::{GitConfig, LocalClone};
+use ::i18n::Messages;
+use ::templates::CommentKey;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct BackportConfig {
+    // Backports are pushed to `<prefix><pull request>/<release branch>`, `backport/` unless set
+    pub branch_prefix: Option<String>
+}
+
+lazy_static! {
+    static ref TRAILER: Regex = Regex::new(r"(?im)^backport:[ \t]*(\S+)[ \t]*$")
+        .expect("Backport trailer pattern should be valid");
+    // The merged pull requests seen so far
+    static ref MERGED: Mutex<Option<HashSet<i32>>> = Mutex::new(None);
+}
+
+// The release branches asked for with `Backport: release/1.2` lines in the description or the commit messages
+pub fn targets(pr: &::PullRequest, commits: &[::Commit]) -> Vec<String> {
+    let mut targets: Vec<String> = vec![];
+    let texts = Some(&pr.description).into_iter().chain(commits.iter().map(|commit| &commit.message));
+    for text in texts {
+        for captures in TRAILER.captures_iter(text) {
+            let target = captures[1].trim_start_matches("refs/heads/").to_owned();
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    targets
+}
+
+pub fn branch_name(config: &BackportConfig, pr: &::PullRequest, target: &str) -> String {
+    format!("{}{}/{}", config.branch_prefix.as_ref().map_or("backport/", String::as_str), pr.id, target)
+}
+
+// Cherry-picks the pull request's commits onto `target` and opens a pull request with them, or comments on the files
+// that need to be resolved by hand
+fn backport(repository: &::Repository, clone: &LocalClone, config: &BackportConfig, pr: &::PullRequest,
+        commits: &[String], target: &str, messages: &Messages) -> Result<(), String> {
+    let onto = format!("refs/pr_demon/backport/{}", target);
+    let branch = branch_name(config, pr, target);
+    let conflicts = clone.fetch(&[format!("+{}:refs/pr_demon/{}/to", pr.to_ref, pr.id),
+            format!("+refs/heads/{}:{}", target, onto)])
+        .and_then(|_| clone.cherry_pick(commits, &onto, &branch));
+    let conflicts = match conflicts {
+        Ok(conflicts) => conflicts,
+        Err(err) => return Err(err.to_string())
+    };
+
+    let labels = messages.labels();
+    let key = CommentKey::notice(&format!("backport:{}", target), pr);
+    if !conflicts.is_empty() {
+        let files = conflicts.iter().map(|file| format!("- `{}`", file)).collect::<Vec<_>>().join("\n");
+        let text = messages.format("backport.conflicts", &[
+            ("marker", labels.failure()),
+            ("target", target),
+            ("files", &files)
+        ]);
+        return repository.upsert_comment(pr, &key, &text).map_err(|err| err.to_string());
+    }
+
+    if let Err(err) = clone.push(&[format!("+refs/heads/{}:refs/heads/{}", branch, branch)]) {
+        return Err(err.to_string());
+    }
+    let description = messages.format("backport.description", &[
+        ("id", &pr.id.to_string()),
+        ("url", &pr.web_url),
+        ("description", &pr.description)
+    ]);
+    let backport = repository.create_pull_request(&format!("refs/heads/{}", branch), &format!("refs/heads/{}", target),
+        &format!("[{}] {}", target, pr.title), &description);
+    let backport = match backport {
+        Ok(backport) => backport,
+        Err(err) => return Err(err.to_string())
+    };
+    let text = messages.format("backport.created", &[
+        ("marker", labels.success()),
+        ("target", target),
+        ("id", &backport.id.to_string()),
+        ("url", &backport.web_url)
+    ]);
+    repository.upsert_comment(pr, &key, &text).map_err(|err| err.to_string())
+}
+
+// Backports the pull requests merged since the last poll. Those merged before the daemon started are left alone.
+pub fn backport_merged(repository: &::Repository, git: &GitConfig, config: &BackportConfig, messages: &Messages) {
+    let pull_requests = match repository.get_merged_pr_list() {
+        Ok(pull_requests) => pull_requests,
+        Err(err) => {
+            println!("Error getting merged Pull Requests: {}", err);
+            return;
+        }
+    };
+
+    let mut merged = MERGED.lock().expect("Merged pull requests should not be poisoned");
+    let first_poll = merged.is_none();
+    let seen = merged.get_or_insert_with(HashSet::new);
+    let merged_now: Vec<_> = pull_requests.into_iter().filter(|pr| seen.insert(pr.id) && !first_poll).collect();
+    for pr in merged_now {
+        let commits = match repository.get_pr_commits(&pr) {
+            Ok(commits) => commits,
+            Err(err) => {
+                println!("Unable to get the commits of Pull Request #{}: {}", pr.id, err);
+                continue;
+            }
+        };
+        let targets = targets(&pr, &commits);
+        if targets.is_empty() {
+            continue;
+        }
+        let clone = match LocalClone::open(git) {
+            Ok(clone) => clone,
+            Err(err) => {
+                println!("Unable to open the local clone: {}", err);
+                return;
+            }
+        };
+        let picked: Vec<String> = commits.into_iter().filter(|commit| !commit.merge).map(|commit| commit.id).collect();
+        for target in targets {
+            match backport(repository, &clone, config, &pr, &picked, &target, messages) {
+                Ok(_) => println!("Backported Pull Request #{} to {}", pr.id, target),
+                Err(err) => println!("Unable to backport Pull Request #{} to {}: {}", pr.id, target, err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stubs::pull_request;
+    use super::{branch_name, targets, BackportConfig};
+
+    fn commit(message: &str) -> ::Commit {
+        ::Commit {
+            id: "abc".to_owned(),
+            message: message.to_owned(),
+            merge: false,
+            author: None
+        }
+    }
+
+    #[test]
+    fn targets_are_read_from_trailers() {
+        let pr = ::PullRequest {
+            description: "Fixes the parser\n\nBackport: release/1.2\nbackport: refs/heads/release/1.1".to_owned(),
+            ..pull_request()
+        };
+        let commits = vec![commit("Fix the parser\n\nBackport: release/1.2"), commit("Backport the fix to 1.0")];
+        assert_eq!(vec!["release/1.2".to_owned(), "release/1.1".to_owned()], targets(&pr, &commits));
+        assert!(targets(&pull_request(), &[]).is_empty());
+    }
+
+    #[test]
+    fn backports_are_pushed_to_prefixed_branches() {
+        let pr = ::PullRequest { id: 7, ..pull_request() };
+        assert_eq!("backport/7/release/1.2", branch_name(&BackportConfig { branch_prefix: None }, &pr, "release/1.2"));
+        assert_eq!("bp-7/main", branch_name(&BackportConfig { branch_prefix: Some("bp-".to_owned()) }, &pr, "main"));
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use hyper::StatusCode;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+
+use ::access::AccessConfig;
+use ::errors::ConfigError;
+use ::fanout::{Message, OpCode};
+use ::listener::{self, ListenerConfig, Listening, Request, Response};
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct BadgeConfig {
+    pub listener: ListenerConfig,
+    // The name the repository's badges are served under, such as "pr_demon" for `/badge/pr_demon/12`
+    pub repository: String,
+    // Badges are public unless set
+    pub access: Option<AccessConfig>
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum BadgeState {
+    Passing,
+    Failing,
+    Running,
+    Queued,
+    Unknown
+}
+
+impl BadgeState {
+    fn text(&self) -> &'static str {
+        match *self {
+            BadgeState::Passing => "passing",
+            BadgeState::Failing => "failing",
+            BadgeState::Running => "running",
+            BadgeState::Queued => "queued",
+            BadgeState::Unknown => "unknown"
+        }
+    }
+
+    fn colour(&self) -> &'static str {
+        match *self {
+            BadgeState::Passing => "#4c1",
+            BadgeState::Failing => "#e05d44",
+            BadgeState::Running => "#dfb317",
+            BadgeState::Queued | BadgeState::Unknown => "#9f9f9f"
+        }
+    }
+}
+
+struct Tracked {
+    id: i32,
+    branch: String,
+    commit: String,
+    // The latest state of each build configuration, for pull requests built on several
+    builds: BTreeMap<String, BadgeState>
+}
+
+impl Tracked {
+    fn state(&self) -> BadgeState {
+        let states = self.builds.values().collect::<Vec<_>>();
+        let order = [BadgeState::Failing, BadgeState::Running, BadgeState::Queued, BadgeState::Passing];
+        order.iter().find(|state| states.contains(state)).cloned().unwrap_or(BadgeState::Unknown)
+    }
+}
+
+// The latest build state of every pull request the daemon has seen, learnt from its broadcasts
+pub struct Badges {
+    // Least recently changed first
+    tracked: Vec<Tracked>,
+    // Builds whose commit is unknown belong to the pull request being handled
+    current: Option<i32>
+}
+
+impl Badges {
+    pub fn new() -> Badges {
+        Badges {
+            tracked: vec![],
+            current: None
+        }
+    }
+
+    pub fn observe(&mut self, message: &Message) {
+        let state = match message.opcode {
+            OpCode::OpenPullRequest => {
+                if let Ok(pr) = message.payload::<::PullRequest>() {
+                    self.open(&pr);
+                }
+                return;
+            },
+            OpCode::BuildScheduled | OpCode::BuildQueued => BadgeState::Queued,
+            OpCode::BuildRunning => BadgeState::Running,
+            OpCode::BuildFinished { success: true } => BadgeState::Passing,
+            OpCode::BuildFinished { success: false } => BadgeState::Failing,
+            _ => return
+        };
+        let build = match message.payload::<::BuildDetails>() {
+            Ok(build) => build,
+            Err(_) => return
+        };
+        let current = self.current;
+        let tracked = match build.commit {
+            Some(ref commit) => self.tracked.iter_mut().rev().find(|tracked| &tracked.commit == commit),
+            None => self.tracked.iter_mut().find(|tracked| Some(tracked.id) == current)
+        };
+        if let Some(tracked) = tracked {
+            tracked.builds.insert(build.build_id, state);
+        }
+    }
+
+    // A new commit starts the pull request over, without builds
+    fn open(&mut self, pr: &::PullRequest) {
+        self.current = Some(pr.id);
+        let position = self.tracked.iter().position(|tracked| tracked.id == pr.id);
+        if let Some(position) = position {
+            if self.tracked[position].commit == pr.from_commit {
+                return;
+            }
+            self.tracked.remove(position);
+        }
+        self.tracked.push(Tracked {
+            id: pr.id,
+            branch: pr.branch_name(),
+            commit: pr.from_commit.to_owned(),
+            builds: BTreeMap::new()
+        });
+    }
+
+    pub fn pull_request(&self, id: i32) -> BadgeState {
+        self.tracked.iter().find(|tracked| tracked.id == id).map_or(BadgeState::Unknown, Tracked::state)
+    }
+
+    // Of the pull request from the branch that changed last
+    pub fn branch(&self, branch: &str) -> BadgeState {
+        self.tracked.iter().rev().find(|tracked| tracked.branch == branch).map_or(BadgeState::Unknown, Tracked::state)
+    }
+
+    // For `/badge/{repository}/{pull request}` or `/badge/{repository}/{branch}`, where branches may contain slashes
+    pub fn for_path(&self, repository: &str, path: &str) -> Option<BadgeState> {
+        let path = path.split('?').next().unwrap_or("");
+        if !path.starts_with("/badge/") {
+            return None;
+        }
+        let mut parts = path["/badge/".len()..].splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(subject)) if name == repository && !subject.is_empty() => {
+                match subject.parse::<i32>() {
+                    Ok(id) => Some(self.pull_request(id)),
+                    Err(_) => Some(self.branch(subject))
+                }
+            },
+            _ => None
+        }
+    }
+}
+
+// A flat badge in the style of shields.io, sized for the width of its text
+pub fn svg(label: &str, state: BadgeState) -> String {
+    let text = state.text();
+    let label_width = label.len() * 7 + 10;
+    let text_width = text.len() * 7 + 10;
+    format!(concat!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"20\" role=\"img\" ",
+        "aria-label=\"{label}: {text}\"><title>{label}: {text}</title>",
+        "<rect width=\"{label_width}\" height=\"20\" fill=\"#555\"/>",
+        "<rect x=\"{label_width}\" width=\"{text_width}\" height=\"20\" fill=\"{colour}\"/>",
+        "<g fill=\"#fff\" text-anchor=\"middle\" font-family=\"Verdana,DejaVu Sans,sans-serif\" font-size=\"11\">",
+        "<text x=\"{label_middle}\" y=\"14\">{label}</text><text x=\"{text_middle}\" y=\"14\">{text}</text></g></svg>"),
+        width = label_width + text_width,
+        label = label,
+        text = text,
+        label_width = label_width,
+        text_width = text_width,
+        colour = state.colour(),
+        label_middle = label_width / 2,
+        text_middle = label_width + text_width / 2)
+}
+
+// Follows the broadcasts in the background and serves badges for as long as the returned listener is kept
+pub fn serve(config: &BadgeConfig, subscriber: Receiver<Message>) -> Result<Listening, ConfigError> {
+    let badges = Arc::new(Mutex::new(Badges::new()));
+    let observed = badges.clone();
+    thread::spawn(move || {
+        for message in subscriber.iter() {
+            observed.lock().expect("Badges should not be poisoned").observe(&message);
+        }
+    });
+    let repository = config.repository.to_owned();
+    let access = config.access.to_owned();
+    listener::listen(&config.listener, move |request: Request| {
+        if let Some(ref access) = access {
+            if let Err(err) = access.check(&request.remote_addr.ip(), request.header("Authorization")) {
+                return Response::new(err.status(), err.to_string().as_bytes());
+            }
+        }
+        let state = badges.lock().expect("Badges should not be poisoned").for_path(&repository, &request.path);
+        match state {
+            Some(state) => Response::new(StatusCode::OK, svg("build", state).as_bytes())
+                .with_header(CONTENT_TYPE, "image/svg+xml")
+                .with_header(CACHE_CONTROL, "no-cache"),
+            None => Response::new(StatusCode::NOT_FOUND, b"Not found")
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use hyper::{HeaderMap, StatusCode};
+    use fanout::{Message, OpCode};
+    use listener::ListenerConfig;
+    use rest;
+    use stubs::pull_request;
+    use super::{serve, svg, BadgeConfig, BadgeState, Badges};
+
+    fn build(build_id: &str, commit: Option<&str>) -> ::BuildDetails {
+        ::BuildDetails {
+            id: 1,
+            build_id: build_id.to_owned(),
+            web_url: "http://www.goodbuilds.com/1".to_owned(),
+            commit: commit.map(str::to_owned),
+            state: ::BuildState::Finished,
+            status: ::BuildStatus::Success,
+            status_text: None,
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+    #[test]
+    fn pull_requests_show_their_worst_build() {
+        let mut badges = Badges::new();
+        let pr = pull_request();
+        badges.observe(&Message::new(OpCode::OpenPullRequest, &pr));
+        assert_eq!(BadgeState::Unknown, badges.pull_request(111));
+
+        badges.observe(&Message::new(OpCode::BuildQueued, &build("Linux", Some(&pr.from_commit))));
+        badges.observe(&Message::new(OpCode::BuildFinished { success: true }, &build("Windows", None)));
+        assert_eq!(BadgeState::Queued, badges.pull_request(111));
+        badges.observe(&Message::new(OpCode::BuildFinished { success: false }, &build("Linux", None)));
+        assert_eq!(BadgeState::Failing, badges.pull_request(111));
+        assert_eq!(BadgeState::Failing, badges.branch("branch_name"));
+
+        // A new commit has not been built yet
+        badges.observe(&Message::new(OpCode::OpenPullRequest, &::PullRequest {
+            from_commit: "0000000".to_owned(),
+            ..pr
+        }));
+        assert_eq!(BadgeState::Unknown, badges.pull_request(111));
+    }
+
+    #[test]
+    fn paths_name_a_pull_request_or_a_branch() {
+        let mut badges = Badges::new();
+        let pr = ::PullRequest { from_ref: "refs/heads/feature/login".to_owned(), ..pull_request() };
+        badges.observe(&Message::new(OpCode::OpenPullRequest, &pr));
+        badges.observe(&Message::new(OpCode::BuildRunning, &build("Linux", None)));
+
+        assert_eq!(Some(BadgeState::Running), badges.for_path("pr_demon", "/badge/pr_demon/111"));
+        assert_eq!(Some(BadgeState::Running), badges.for_path("pr_demon", "/badge/pr_demon/feature/login?v=2"));
+        assert_eq!(Some(BadgeState::Unknown), badges.for_path("pr_demon", "/badge/pr_demon/112"));
+        assert_eq!(None, badges.for_path("pr_demon", "/badge/other/111"));
+        assert_eq!(None, badges.for_path("pr_demon", "/badge/pr_demon/"));
+        assert_eq!(None, badges.for_path("pr_demon", "/status/pr_demon/111"));
+    }
+
+    #[test]
+    fn badges_are_served() {
+        let (_tx, rx) = channel::<Message>();
+        let config = BadgeConfig {
+            listener: ListenerConfig { address: "127.0.0.1:0".to_owned(), tls: None },
+            repository: "pr_demon".to_owned(),
+            access: None
+        };
+        let listening = serve(&config, rx).unwrap();
+        let headers = HeaderMap::new();
+
+        let badge = rest::get_raw(&format!("http://{}/badge/pr_demon/111", listening.socket), &headers).unwrap();
+        assert_eq!(StatusCode::OK, badge.status);
+        assert_eq!(svg("build", BadgeState::Unknown), badge.body);
+        let missing = rest::get_raw(&format!("http://{}/badge/other/111", listening.socket), &headers).unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, missing.status);
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use serde_json;
+
+use ::checks::{self, Report};
+use ::coverage::builder_name;
+use ::errors::{ConfigError, Error};
+use ::i18n::Messages;
+use ::pipeline::{Context, Outcome, Stage};
+use ::registry;
+use ::store::JsonStore;
+use ::templates::CommentKey;
+
+static DEFAULT_THRESHOLD: f64 = 10.0;
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct BenchmarkConfig {
+    // A JSON file keeping the benchmarks of each target branch across restarts. Kept in memory only unless set.
+    pub path: Option<String>,
+    // By how many percent a benchmark may get worse before failing the check, 10 unless set, and per benchmark
+    pub threshold: Option<f64>,
+    pub thresholds: Option<HashMap<String, f64>>,
+    // Benchmarks such as throughput that improve as they grow. Lower is better for the others, such as timings.
+    pub higher_is_better: Option<Vec<String>>
+}
+
+// The benchmarks a builder measured at the head of a target branch
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct Baseline {
+    pub branch: String,
+    pub builder: String,
+    pub commit: String,
+    pub benchmarks: Vec<::Benchmark>
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Comparison {
+    pub name: String,
+    pub unit: Option<String>,
+    pub value: f64,
+    pub baseline: Option<f64>,
+    // In percent of the baseline
+    pub change: Option<f64>,
+    pub higher_is_better: bool,
+    pub threshold: f64
+}
+
+pub struct BenchmarkStore {
+    baselines: JsonStore<Vec<Baseline>>
+}
+
+lazy_static! {
+    static ref STORE: Mutex<Option<BenchmarkStore>> = Mutex::new(None);
+    // The merged pull requests seen so far
+    static ref MERGED: Mutex<Option<HashSet<i32>>> = Mutex::new(None);
+}
+
+impl BenchmarkStore {
+    // A store without a file yet is empty
+    pub fn load(path: &Option<String>) -> Result<BenchmarkStore, ConfigError> {
+        JsonStore::load(path).map(|baselines| BenchmarkStore { baselines: baselines })
+    }
+
+    pub fn get(&self, branch: &str, builder: &str) -> Option<&Baseline> {
+        self.baselines.iter().find(|baseline| baseline.branch == branch && baseline.builder == builder)
+    }
+
+    // Replaces the branch's previous baseline of the same builder and rewrites the file
+    pub fn record(&mut self, baseline: Baseline) -> Result<(), ConfigError> {
+        self.baselines.retain(|old| old.branch != baseline.branch || old.builder != baseline.builder);
+        self.baselines.push(baseline);
+        self.baselines.save()
+    }
+}
+
+fn with_store<T, F>(config: &BenchmarkConfig, action: F) -> Result<T, ConfigError>
+        where F: FnOnce(&mut BenchmarkStore) -> T {
+    let mut store = STORE.lock().expect("Benchmark store should not be poisoned");
+    if store.is_none() {
+        match BenchmarkStore::load(&config.path) {
+            Ok(loaded) => *store = Some(loaded),
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(action(store.as_mut().expect("Benchmark store should be loaded")))
+}
+
+// The benchmarks of the latest finished build of the pull request's commit on `ci`
+fn measure(pr: &::PullRequest, ci: &::ContinuousIntegrator) -> Result<Vec<::Benchmark>, Error> {
+    match ::get_latest_build(pr, ci) {
+        Some(ref build) if build.state == ::BuildState::Finished => ci.get_benchmarks(build).map_err(Error::from),
+        _ => Ok(vec![])
+    }
+}
+
+// Like coverage, the benchmarks of the last build of a merged pull request become those of the target branch's head
+pub fn record_merged(repository: &::Repository, matrix: &Vec<&::ContinuousIntegrator>, config: &BenchmarkConfig) {
+    let pull_requests = match repository.get_merged_pr_list() {
+        Ok(pull_requests) => pull_requests,
+        Err(err) => {
+            println!("Error getting merged Pull Requests: {}", err);
+            return;
+        }
+    };
+
+    let mut merged = MERGED.lock().expect("Merged pull requests should not be poisoned");
+    let first_poll = merged.is_none();
+    let seen = merged.get_or_insert_with(HashSet::new);
+    for pr in pull_requests {
+        if !seen.insert(pr.id) || first_poll {
+            continue;
+        }
+        for (index, ci) in matrix.iter().enumerate() {
+            let benchmarks = match measure(&pr, *ci) {
+                Ok(ref benchmarks) if benchmarks.is_empty() => continue,
+                Ok(benchmarks) => benchmarks,
+                Err(err) => {
+                    println!("Unable to get the benchmarks of merged Pull Request #{}: {}", pr.id, err);
+                    continue;
+                }
+            };
+            let baseline = Baseline {
+                branch: pr.target_branch_name(),
+                builder: builder_name(*ci, index),
+                commit: pr.from_commit.to_owned(),
+                benchmarks: benchmarks
+            };
+            match with_store(config, |store| store.record(baseline)) {
+                Ok(Ok(())) => {},
+                Ok(Err(err)) | Err(err) => {
+                    println!("Unable to store the benchmarks of {}: {}", pr.target_branch_name(), err)
+                }
+            }
+        }
+    }
+}
+
+impl Comparison {
+    // In percent, positive when the benchmark got better
+    pub fn improvement(&self) -> Option<f64> {
+        match self.higher_is_better {
+            true => self.change,
+            false => self.change.map(|change| -change)
+        }
+    }
+
+    pub fn regressed(&self) -> bool {
+        self.improvement().map_or(false, |improvement| -improvement > self.threshold)
+    }
+
+    pub fn improved(&self) -> bool {
+        self.improvement().map_or(false, |improvement| improvement > self.threshold)
+    }
+}
+
+fn format_value(value: f64, unit: &Option<String>) -> String {
+    match *unit {
+        Some(ref unit) => format!("{:.2} {}", value, unit),
+        None => format!("{:.2}", value)
+    }
+}
+
+impl BenchmarkConfig {
+    pub fn compare(&self, benchmarks: &[::Benchmark], baseline: &[::Benchmark]) -> Vec<Comparison> {
+        benchmarks.iter().map(|benchmark| {
+            let base = baseline.iter().find(|base| base.name == benchmark.name).map(|base| base.value);
+            let higher_is_better = self.higher_is_better.as_ref()
+                .map_or(false, |names| names.contains(&benchmark.name));
+            let change = base.and_then(|base| match base.abs() < f64::EPSILON {
+                true => None,
+                false => Some((benchmark.value - base) / base.abs() * 100.0)
+            });
+            Comparison {
+                name: benchmark.name.to_owned(),
+                unit: benchmark.unit.to_owned(),
+                value: benchmark.value,
+                baseline: base,
+                change: change,
+                higher_is_better: higher_is_better,
+                threshold: self.thresholds.as_ref().and_then(|thresholds| thresholds.get(&benchmark.name).cloned())
+                    .unwrap_or(self.threshold.unwrap_or(DEFAULT_THRESHOLD))
+            }
+        }).collect()
+    }
+}
+
+// A markdown table of the benchmarks with the target branch's values, marking regressions and improvements
+pub fn table(comparisons: &[Comparison], branch: &str, messages: &Messages) -> String {
+    let labels = messages.labels();
+    let rows = comparisons.iter().map(|comparison| {
+        let change = match comparison.change {
+            Some(change) if comparison.regressed() => format!("{:+.2}% {}", change, labels.failure()),
+            Some(change) if comparison.improved() => format!("{:+.2}% {}", change, labels.success()),
+            Some(change) => format!("{:+.2}%", change),
+            None => messages.get("benchmarks.new").to_owned()
+        };
+        format!("| {} | {} | {} | {} |", comparison.name,
+            comparison.baseline.map_or("".to_owned(), |baseline| format_value(baseline, &comparison.unit)),
+            format_value(comparison.value, &comparison.unit), change)
+    }).collect::<Vec<_>>();
+    format!("{}\n{}", messages.format("benchmarks.header", &[("branch", branch)]), rows.join("\n"))
+}
+
+// Compares the benchmarks of the pull request's builds with the target branch's, once the builds have finished. The
+// result is reported as a build status and the comparison posted as a table.
+pub struct BenchmarkStage;
+
+impl BenchmarkStage {
+    fn check(&self, context: &Context, comparisons: &[Comparison], branch: &str) -> Result<::CheckResult, Error> {
+        let violations = comparisons.iter().filter(|comparison| comparison.regressed())
+            .map(|comparison| context.messages.format("check.benchmarks.regressed", &[
+                ("name", &comparison.name),
+                ("change", &format!("{:.2}", -comparison.improvement().unwrap_or(0.0))),
+                ("branch", branch),
+                ("threshold", &format!("{:.2}", comparison.threshold))
+            ]))
+            .collect::<Vec<_>>();
+        let labels = context.messages.labels();
+        let (marker, summary) = match violations.is_empty() {
+            true => (labels.success(), context.messages.get("check.benchmarks.passed").to_owned()),
+            false => (labels.failure(), context.messages.format("check.benchmarks.failed",
+                &[("count", &violations.len().to_string())]))
+        };
+
+        let text = context.messages.format("benchmarks.comment", &[
+            ("marker", marker),
+            ("summary", &summary),
+            ("table", &table(comparisons, branch, context.messages))
+        ]);
+        if let Err(err) = context.repository.upsert_comment(context.pr, &CommentKey::check("benchmarks", context.pr),
+                &text) {
+            return Err(Error::from(err));
+        }
+        Ok(::CheckResult {
+            name: "benchmarks".to_owned(),
+            passed: violations.is_empty(),
+            summary: summary,
+            violations: violations
+        })
+    }
+}
+
+impl Stage for BenchmarkStage {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let config = match context.config.benchmarks {
+            Some(ref config) => config,
+            None => {
+                return Err(Error::from(ConfigError::Invalid("The benchmarks stage needs a benchmarks section"
+                    .to_owned())))
+            }
+        };
+        let branch = context.pr.target_branch_name();
+
+        let mut comparisons = vec![];
+        let mut revision = context.pr.from_commit.to_owned();
+        for (index, ci) in context.matrix.iter().enumerate() {
+            let benchmarks = match measure(context.pr, *ci) {
+                Ok(benchmarks) => benchmarks,
+                Err(err) => return Err(err)
+            };
+            if benchmarks.is_empty() {
+                continue;
+            }
+            let builder = builder_name(*ci, index);
+            let stored = match with_store(config, |store| store.get(&branch, &builder).cloned()) {
+                Ok(stored) => stored,
+                Err(err) => return Err(Error::from(err))
+            };
+            let baseline = match stored {
+                Some(stored) => {
+                    revision = format!("{}\n{}:{}", revision, builder, stored.commit);
+                    stored.benchmarks
+                },
+                None => vec![]
+            };
+            let mut compared = config.compare(&benchmarks, &baseline);
+            if context.matrix.len() > 1 {
+                for comparison in &mut compared {
+                    comparison.name = format!("{}: {}", builder, comparison.name);
+                }
+            }
+            comparisons.extend(compared);
+        }
+        if comparisons.is_empty() {
+            return Ok(Outcome::Continue);
+        }
+
+        checks::run_check(context, "benchmarks", &revision, Report::Status,
+            |context| self.check(context, &comparisons, &branch))
+    }
+}
+
+pub struct BenchmarkStageFactory;
+
+impl registry::StageFactory for BenchmarkStageFactory {
+    fn create(&self, _: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        Ok(Box::new(BenchmarkStage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use i18n::Messages;
+    use super::{table, BenchmarkConfig};
+
+    fn benchmark(name: &str, value: f64) -> ::Benchmark {
+        ::Benchmark {
+            name: name.to_owned(),
+            value: value,
+            unit: Some("ms".to_owned())
+        }
+    }
+
+    fn config() -> BenchmarkConfig {
+        let mut thresholds = HashMap::new();
+        thresholds.insert("startup".to_owned(), 50.0);
+        BenchmarkConfig {
+            path: None,
+            threshold: None,
+            thresholds: Some(thresholds),
+            higher_is_better: Some(vec!["requests".to_owned()])
+        }
+    }
+
+    #[test]
+    fn benchmarks_regress_beyond_their_threshold() {
+        let baseline = [benchmark("parse", 100.0), benchmark("startup", 100.0), benchmark("requests", 100.0)];
+        let benchmarks = [
+            benchmark("parse", 120.0),
+            benchmark("startup", 120.0),
+            benchmark("requests", 120.0),
+            benchmark("render", 5.0)
+        ];
+        let comparisons = config().compare(&benchmarks, &baseline);
+        assert_eq!(vec![true, false, false, false],
+            comparisons.iter().map(|comparison| comparison.regressed()).collect::<Vec<_>>());
+        assert_eq!(vec![false, false, true, false],
+            comparisons.iter().map(|comparison| comparison.improved()).collect::<Vec<_>>());
+        assert_eq!(None, comparisons[3].change);
+    }
+
+    #[test]
+    fn comparisons_are_tabulated() {
+        let messages = Messages::new(&None, &None);
+        let comparisons = config().compare(&[benchmark("parse", 120.0), benchmark("render", 5.0)],
+            &[benchmark("parse", 100.0)]);
+        assert_eq!("| Benchmark | master | This pull request | Change |\n\
+                    | --- | ---: | ---: | ---: |\n\
+                    | parse | 100.00 ms | 120.00 ms | +20.00% ❌ |\n\
+                    | render |  | 5.00 ms | new |", table(&comparisons, "master", &messages));
+    }
+}
@@ -1,26 +1,27 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::vec::Vec;
 use std::option::Option;
+use std::sync::{Arc, Mutex};
 
 use hyper;
-use rustc_serialize::{json, Encodable};
-
+use base64;
+use serde::Serialize;
+use serde_json;
+use url::form_urlencoded;
+
+use ::credentials;
+use ::errors::{ApiError, ConfigError, RepositoryError};
+use ::events;
 use ::fanout;
-use ::json_dictionary;
+use ::registry;
+use ::i18n;
 use ::rest;
+use ::templates::{self, CommentApi, OwnComment};
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
-struct PagedApi<T> {
-    size: i32,
-    limit: i32,
-    isLastPage: bool,
-    values: Vec<T>,
-    start: i32
-}
+static SUMMARY_MARKER: &'static str = "[//]: # (pr_demon-summary:";
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 struct PullRequest {
     id: i32,
     version: i32,
@@ -29,49 +30,108 @@ struct PullRequest {
     state: String,
     open:  bool,
     closed: bool,
-    createdDate: i64,
-    updatedDate: i64,
-    fromRef: GitReference,
-    toRef: GitReference,
+    created_date: i64,
+    updated_date: i64,
+    from_ref: GitReference,
+    to_ref: GitReference,
     locked: bool,
     author: PullRequestParticipant,
     reviewers: Vec<PullRequestParticipant>,
     participants: Vec<PullRequestParticipant>,
-    links: BTreeMap<String, Vec<Link>>
+    links: BTreeMap<String, Vec<Link>>,
+    properties: Option<PullRequestProperties>
 }
 
-#[derive(RustcDecodable, RustcEncodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
-struct Comment {
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PullRequestProperties {
+    open_task_count: Option<i32>
+}
+
+impl PullRequest {
+    fn to_pull_request(&self) -> ::PullRequest {
+        ::PullRequest {
+            id: self.id,
+            web_url: self.links["self"][0].href.to_owned(),
+            from_ref: self.from_ref.id.to_owned(),
+            from_commit: self.from_ref.latest_commit.to_owned(),
+            to_ref: self.to_ref.id.to_owned(),
+            from_fork: self.from_ref.repository.slug != self.to_ref.repository.slug
+                || self.from_ref.repository.project.key != self.to_ref.repository.project.key,
+            title: self.title.to_owned(),
+            description: self.description.to_owned().unwrap_or_default(),
+            updated: Some(self.updated_date),
+            author: ::User {
+                name: self.author.user.display_name.to_owned(),
+                email: self.author.user.email_address.to_owned(),
+                username: Some(self.author.user.name.to_owned())
+            },
+            reviewers: self.reviewers.iter().map(|reviewer| reviewer.user.name.to_owned()).collect(),
+            approvals: self.reviewers.iter().filter(|reviewer| reviewer.approved)
+                .map(|reviewer| reviewer.user.name.to_owned()).collect(),
+            size: None,
+            open_tasks: self.properties.as_ref().and_then(|properties| properties.open_task_count),
+            labels: vec![]
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Comment {
     id: i32,
     version: i32,
     text: String,
     author: User,
-    createdDate: i64,
-    updatedDate: i64
+    created_date: i64,
+    updated_date: i64
+}
+
+impl templates::OwnComment for Comment {
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn to_event(&self) -> events::PostedComment {
+        events::PostedComment {
+            id: i64::from(self.id),
+            version: self.version,
+            text: self.text.to_owned()
+        }
+    }
+}
+
+impl Comment {
+    fn to_comment(&self) -> ::Comment {
+        ::Comment {
+            id: i64::from(self.id),
+            author: self.author.name.to_owned(),
+            text: self.text.to_owned()
+        }
+    }
 }
 
-#[derive(RustcDecodable, RustcEncodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
 struct CommentSubmit {
     text: String
 }
 
-#[derive(RustcDecodable, RustcEncodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
 struct CommentEdit {
     text: String,
     version: i32
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 struct GitReference {
     id: String,
     repository: Repository,
-    displayId: String,
-    latestCommit: String
+    display_id: String,
+    latest_commit: String
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 struct Repository {
     slug: String,
     name: Option<String>,
@@ -80,7 +140,7 @@ struct Repository {
     links: BTreeMap<String, Vec<Link>>
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 struct Project {
     key: String,
     id: i32,
@@ -90,44 +150,64 @@ struct Project {
     links: BTreeMap<String, Vec<Link>>
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 struct PullRequestParticipant {
     user: User,
     role: String,
     approved: bool
 }
 
-#[derive(RustcDecodable, RustcEncodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 struct User {
     name: String,
-    emailAddress: String,
+    email_address: String,
     id: i32,
-    displayName: String,
+    display_name: String,
     active: bool,
     slug: String,
     links: BTreeMap<String, Vec<Link>>
     // type: String
 }
 
-#[derive(RustcDecodable, RustcEncodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
 struct Link {
     href: String,
     name: Option<String>
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Commit {
+    id: String,
+    message: String,
+    parents: Vec<CommitParent>,
+    author: Option<CommitAuthor>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CommitAuthor {
+    name: String,
+    email_address: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitParent {
+    id: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 struct Activity {
     id: i32,
-    createdDate: i64,
+    created_date: i64,
     user: User,
     action: String,
-    commentAction: Option<String>,
+    comment_action: Option<String>,
     comment: Option<Comment>
 }
 
-#[derive(RustcDecodable, RustcEncodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
 struct Build {
     state: BuildState,
     key: String,
@@ -136,27 +216,59 @@ struct Build {
     description: String
 }
 
-#[derive(RustcDecodable, RustcEncodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_camel_case_types)]
-enum BuildState{
-    INPROGRESS,
-    FAILED,
-    SUCCESSFUL
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+enum BuildState {
+    InProgress,
+    Failed,
+    Successful
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
 pub struct BitbucketCredentials {
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
     pub base_url: String,
     pub project_slug: String,
     pub repo_slug: String,
-    pub post_build: bool
+    pub post_build: bool,
+    pub templates: Option<templates::Templates>,
+    pub summary_comment: Option<bool>,
+    pub superseded_comments: Option<String>,
+    // Posts build statuses to every commit of the pull request rather than only to the latest
+    pub all_commits: Option<bool>,
+    // Approves pull requests whose builds succeeded and withdraws the approval when one fails
+    pub approve: Option<bool>
+}
+
+pub struct BitbucketFactory;
+
+impl registry::RepositoryFactory for BitbucketFactory {
+    fn create(&self, settings: &serde_json::Value, messages: &i18n::Messages,
+        fanout: &fanout::Fanout<fanout::Message>) -> Result<Box<::Repository>, ConfigError> {
+        let credentials = match serde_json::from_value::<BitbucketCredentials>(settings.to_owned()) {
+            Ok(credentials) => credentials,
+            Err(err) => return Err(ConfigError::Parse(err))
+        };
+        match Bitbucket::new(&credentials, messages, fanout) {
+            Ok(bitbucket) => Ok(Box::new(bitbucket)),
+            Err(err) => Err(ConfigError::Invalid(format!("Failed to load comment templates: {}", err)))
+        }
+    }
 }
 
 pub struct Bitbucket {
     pub credentials: BitbucketCredentials,
-    broadcaster: fanout::Fanout<fanout::Message>
+    broadcaster: fanout::Fanout<fanout::Message>,
+    renderer: templates::Renderer,
+    // The state last posted to the older commits of a pull request, by pull request, build and latest commit
+    older_statuses: Mutex<HashMap<(i32, String, String), BuildState>>,
+    // The open pull requests as last listed, by ID, so that reviewing them needs no request of its own
+    listed: Mutex<HashMap<i32, PullRequest>>,
+    // The daemon's user slug, once looked up
+    user_slug: Mutex<Option<String>>
 }
 
 impl ::UsernameAndPassword for Bitbucket {
@@ -170,43 +282,81 @@ impl ::UsernameAndPassword for Bitbucket {
 }
 
 impl ::Repository for Bitbucket {
-    fn get_pr_list(&self) -> Result<Vec<::PullRequest>, String> {
-        let mut headers = rest::Headers::new();
-        headers.add_authorization_header(self as &::UsernameAndPassword)
-            .add_accept_json_header();
-        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests",
-            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug);
+    fn get_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        match self.get_pr_list_by_state("OPEN") {
+            Ok(prs) => {
+                let pull_requests = prs.iter().map(PullRequest::to_pull_request).collect();
+                *self.listed.lock().expect("Listed Pull Requests should not be poisoned") = prs.into_iter()
+                    .map(|pr| (pr.id, pr))
+                    .collect();
+                Ok(pull_requests)
+            },
+            Err(err) => Err(err)
+        }
+    }
 
-        match rest::get::<PagedApi<PullRequest>>(&url, &headers.headers) {
-            Ok(ref prs) => {
-                Ok(prs.values.iter().map( |ref pr| {
-                    ::PullRequest {
-                        id: pr.id,
-                        web_url: pr.links["self"][0].href.to_owned(),
-                        from_ref: pr.fromRef.id.to_owned(),
-                        from_commit: pr.fromRef.latestCommit.to_owned(),
-                        title: pr.title.to_owned(),
-                        author: ::User {
-                            name: pr.author.user.displayName.to_owned(),
-                            email: pr.author.user.emailAddress.to_owned()
-                        }
-                    }
-                }).collect())
+    fn get_merged_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.get_pr_list_by_state("MERGED").map(|prs| prs.iter().map(PullRequest::to_pull_request).collect())
+    }
+
+    fn get_pr_diff(&self, pr: &::PullRequest) -> Result<String, RepositoryError> {
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}.diff",
+            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug, pr.id);
+
+        match credentials::authorized(&*self.provider(), |headers| rest::get_raw(&url, &headers.headers)) {
+            Ok(ref response) if response.status != hyper::StatusCode::OK => {
+                Err(RepositoryError::api("getting diff", ApiError::Status(response.status)))
             },
-            Err(err) =>  Err(format!("Error getting list of Pull Requests {}", err))
+            Ok(response) => Ok(response.body),
+            Err(err) => Err(RepositoryError::api("getting diff", err))
         }
     }
 
-    fn build_queued(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), String> {
-        match self.update_pr_build_status_comment(&pr, &build, &BuildState::INPROGRESS) {
+    fn get_pr_commits(&self, pr: &::PullRequest) -> Result<Vec<::Commit>, RepositoryError> {
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/commits",
+            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug, pr.id);
+
+        let commits = rest::get_paged(&url, |url| credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<rest::Page<Commit>>(url, &headers.headers)
+        }));
+        match commits {
+            Ok(commits) => Ok(commits.iter().map(|commit| ::Commit {
+                id: commit.id.to_owned(),
+                message: commit.message.to_owned(),
+                merge: commit.parents.len() > 1,
+                author: commit.author.as_ref().map(|author| ::User {
+                    name: author.name.to_owned(),
+                    email: author.email_address.to_owned().unwrap_or_default(),
+                    username: None
+                })
+            }).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of commits", err))
+        }
+    }
+
+    fn get_pr_comments(&self, pr: &::PullRequest) -> Result<Vec<::Comment>, RepositoryError> {
+        match self.get_comments(pr.id) {
+            Ok(comments) => Ok(comments.iter().map(Comment::to_comment).collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    // Approvals are listed along with the pull requests
+    fn get_pr_approvals(&self, pr: &::PullRequest) -> Result<Vec<String>, RepositoryError> {
+        Ok(pr.approvals.to_owned())
+    }
+
+    fn build_queued(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        match self.update_pr_build_status_comment(pr, build, &BuildState::InProgress) {
             Ok(_) => {},
-            Err(err) => return Err(format!("Error submitting comment: {}", err))
+            Err(err) => return Err(err)
         };
         match self.credentials.post_build {
             true => {
-                match self.post_build(&build, &pr) {
+                match self.post_build(build, pr) {
                     Ok(_) => Ok(()),
-                    Err(err) => return Err(format!("Error posting build: {}", err))
+                    Err(err) => Err(err)
                 }
             },
             false => Ok(())
@@ -214,214 +364,492 @@ impl ::Repository for Bitbucket {
 
     }
 
-    fn build_running(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), String>  {
-        self.build_queued(&pr, &build)
+    fn build_running(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError>  {
+        self.build_queued(pr, build)
     }
 
-    fn build_success(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), String> {
-        match self.update_pr_build_status_comment(&pr, &build, &BuildState::SUCCESSFUL) {
+    fn build_success(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        match self.update_pr_build_status_comment(pr, build, &BuildState::Successful) {
             Ok(_) => {},
-            Err(err) => return Err(format!("Error submitting comment: {}", err))
+            Err(err) => return Err(err)
         };
+        if let Err(err) = self.review(pr, true) {
+            println!("Unable to approve Pull Request #{}: {}", pr.id, err);
+        }
         match self.credentials.post_build {
             true => {
-                match self.post_build(&build, &pr) {
+                match self.post_build(build, pr) {
                     Ok(_) => Ok(()),
-                    Err(err) => Err(format!("Error posting build: {}", err))
+                    Err(err) => Err(err)
                 }
             },
             false => Ok(())
         }
     }
 
-    fn build_failure(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), String> {
-        match self.update_pr_build_status_comment(&pr, &build, &BuildState::FAILED) {
+    fn build_failure(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        match self.update_pr_build_status_comment(pr, build, &BuildState::Failed) {
             Ok(_) => {},
-            Err(err) => return Err(format!("Error submitting comment: {}", err))
+            Err(err) => return Err(err)
         };
+        if let Err(err) = self.review(pr, false) {
+            println!("Unable to withdraw approval of Pull Request #{}: {}", pr.id, err);
+        }
         match self.credentials.post_build {
             true => {
-                match self.post_build(&build, &pr) {
+                match self.post_build(build, pr) {
                     Ok(_) => Ok(()),
-                    Err(err) => Err(format!("Error posting build: {}", err))
+                    Err(err) => Err(err)
                 }
             },
             false => Ok(())
         }
     }
-}
 
-impl Bitbucket {
-    pub fn new(credentials: &BitbucketCredentials, broadcaster: &fanout::Fanout<fanout::Message>)
-    -> Bitbucket {
-        Bitbucket {
-            credentials: credentials.to_owned(),
-            broadcaster: broadcaster.to_owned()
+    fn build_matrix(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>) -> Result<(), RepositoryError> {
+        match self.update_pr_matrix_comment(pr, builds) {
+            Ok(_) => {},
+            Err(err) => return Err(err)
+        };
+        // Approved once every build has succeeded, but withdrawn as soon as one fails
+        let failed = builds.iter()
+            .any(|build| build.state == ::BuildState::Finished && build.status != ::BuildStatus::Success);
+        let reviewed = match failed {
+            true => self.review(pr, false),
+            false if builds.iter().all(|build| build.state == ::BuildState::Finished) => self.review(pr, true),
+            false => Ok(())
+        };
+        if let Err(err) = reviewed {
+            println!("Unable to review Pull Request #{}: {}", pr.id, err);
         }
+        if self.credentials.post_build {
+            for build in builds {
+                if let Err(err) = self.post_build(build, pr) {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn broadcast<T>(&self, opcode: &str, payload: &T) where T : Encodable {
-        let opcode = fanout::OpCode::Custom {
-            payload: format!("Bitbucket::{}", opcode).to_owned()
+    fn upsert_comment(&self, pr: &::PullRequest, key: &templates::CommentKey, text: &str)
+            -> Result<(), RepositoryError> {
+        templates::upsert_comment(self, pr, text, key, "Notice", |comment| events::NoticeEvent {
+            pr: pr.to_owned(),
+            kind: key.kind.to_owned(),
+            comment: comment
+        }).map(|_| ())
+    }
+
+    fn report_check(&self, pr: &::PullRequest, check: &::CheckResult) -> Result<(), RepositoryError> {
+        let status = Build {
+            state: match check.passed {
+                true => BuildState::Successful,
+                false => BuildState::Failed
+            },
+            key: format!("pr_demon.{}", check.name),
+            name: check.name.to_owned(),
+            url: pr.web_url.to_owned(),
+            description: check.summary.to_owned()
         };
-        let message = fanout::Message::new(opcode, payload);
-        self.broadcaster.broadcast(&message);
+        self.post_status(&pr.from_commit, &status)
+    }
+
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+            -> Result<::PullRequest, RepositoryError> {
+        let repository = json!({
+            "slug": self.credentials.repo_slug,
+            "project": { "key": self.credentials.project_slug }
+        });
+        let body = json!({
+            "title": title,
+            "description": description,
+            "fromRef": { "id": from_ref, "repository": repository },
+            "toRef": { "id": to_ref, "repository": repository }
+        }).to_string();
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests",
+            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug);
+
+        let pr = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match pr {
+            Ok(pr) => Ok(pr.to_pull_request()),
+            Err(err) => Err(RepositoryError::api("creating Pull Request", err))
+        }
+    }
+
+    fn decline_pr(&self, pr: &::PullRequest) -> Result<(), RepositoryError> {
+        let (url, version) = match self.current_version(pr) {
+            Ok(current) => current,
+            Err(err) => return Err(err)
+        };
+        let decline_url = format!("{}/decline?version={}", url, version);
+        let declined = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&decline_url, "{}", &headers.headers, &hyper::StatusCode::OK)
+        });
+        match declined {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("declining Pull Request", err))
+        }
     }
 
-    fn matching_comments(comments: &Vec<Comment>, text: &str) -> Option<Comment> {
-        let found_comment = comments.iter().find(|&comment| comment.text == text);
-        match found_comment {
-            Some(comment) => Some(comment.clone().to_owned()),
-            None => None
+    fn merge_pr(&self, pr: &::PullRequest, strategy: Option<&str>, message: Option<&str>)
+            -> Result<(), RepositoryError> {
+        let (url, version) = match self.current_version(pr) {
+            Ok(current) => current,
+            Err(err) => return Err(err)
+        };
+        let mut body = serde_json::Map::new();
+        if let Some(message) = message {
+            body.insert("message".to_owned(), json!(message));
+        }
+        if let Some(strategy) = strategy {
+            body.insert("strategyId".to_owned(), json!(strategy));
+        }
+        let body = serde_json::Value::Object(body).to_string();
+        let merge_url = format!("{}/merge?version={}", url, version);
+        let merged = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&merge_url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match merged {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("merging Pull Request", err))
         }
     }
 
-    fn matching_comments_substring(comments: &Vec<Comment>, substr: &str) -> Option<Comment> {
-        let found_comment = comments.iter().find(|&comment| comment.text.as_str().contains(substr));
-        match found_comment {
-            Some(comment) => Some(comment.clone().to_owned()),
-            None => None
+    fn add_reviewers(&self, pr: &::PullRequest, reviewers: &[String]) -> Result<(), RepositoryError> {
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/participants",
+            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug, pr.id);
+        for reviewer in reviewers {
+            let body = json!({ "user": { "name": reviewer }, "role": "REVIEWER" }).to_string();
+            let added = credentials::authorized(&*self.provider(), |mut headers| {
+                headers.add_accept_json_header()
+                    .add_content_type_json_header();
+                rest::post::<PullRequestParticipant>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+            });
+            if let Err(err) = added {
+                return Err(RepositoryError::api("adding reviewer", err));
+            }
+        }
+        Ok(())
+    }
+
+    fn create_branch(&self, name: &str, start_point: &str) -> Result<(), RepositoryError> {
+        let url = format!("{}/branch-utils/latest/projects/{}/repos/{}/branches",
+            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug);
+        let body = json!({ "name": name, "startPoint": start_point }).to_string();
+        let created = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match created {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("creating branch", err))
+        }
+    }
+}
+
+impl Bitbucket {
+    // Bitbucket only declines or merges the version of the pull request it was last seen at. Returns the pull
+    // request's URL along with it.
+    fn current_version(&self, pr: &::PullRequest) -> Result<(String, i32), RepositoryError> {
+        self.get_pull_request(pr).map(|(url, current)| (url, current.version))
+    }
+
+    fn get_pull_request(&self, pr: &::PullRequest) -> Result<(String, PullRequest), RepositoryError> {
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}",
+            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug, pr.id);
+        let current = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<PullRequest>(&url, &headers.headers)
+        });
+        match current {
+            Ok(current) => Ok((url, current)),
+            Err(err) => Err(RepositoryError::api("getting Pull Request", err))
         }
     }
 
+    pub fn new(credentials: &BitbucketCredentials, messages: &i18n::Messages,
+        broadcaster: &fanout::Fanout<fanout::Message>) -> Result<Bitbucket, String> {
+        let renderer = match templates::Renderer::new(&credentials.templates, messages, &credentials.repo_slug) {
+            Ok(renderer) => renderer,
+            Err(err) => return Err(err)
+        };
+
+        Ok(Bitbucket {
+            credentials: credentials.to_owned(),
+            broadcaster: broadcaster.to_owned(),
+            renderer: renderer,
+            older_statuses: Mutex::new(HashMap::new()),
+            listed: Mutex::new(HashMap::new()),
+            user_slug: Mutex::new(None)
+        })
+    }
+
+    fn get_pr_list_by_state(&self, state: &str) -> Result<Vec<PullRequest>, RepositoryError> {
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests?state={}",
+            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug, state);
+
+        let prs = rest::get_paged(&url, |url| credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<rest::Page<PullRequest>>(url, &headers.headers)
+        }));
+        prs.map_err(|err| RepositoryError::api("getting list of Pull Requests", err))
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credentials.credential_provider, credentials::Credential::Basic {
+            username: self.credentials.username.to_owned(),
+            password: self.credentials.password.to_owned()
+        })
+    }
+
     fn update_pr_build_status_comment(&self, pr: &::PullRequest,
         build: &::BuildDetails, state: &BuildState)
-            -> Result<Comment, String> {
-        let text = match *state {
-            BuildState::INPROGRESS => make_queued_comment(&build.web_url, &pr.from_commit),
-            BuildState::FAILED => {
-                let status_text = match build.status_text {
-                    None => "".to_owned(),
-                    Some(ref text) => text.to_owned()
-                };
-                make_failure_comment(&build.web_url, &pr.from_commit, &status_text)
-            },
-            BuildState::SUCCESSFUL => {
-                let status_text = match build.status_text {
-                    None => "".to_owned(),
-                    Some(ref text) => text.to_owned()
-                };
-                make_success_comment(&build.web_url, &pr.from_commit, &status_text)
-            }
+            -> Result<Comment, RepositoryError> {
+        if let Some(true) = self.credentials.summary_comment {
+            return self.update_pr_summary_comment(pr, build);
+        }
+
+        let template = match *state {
+            BuildState::InProgress => "queued",
+            BuildState::Failed => "failure",
+            BuildState::Successful => "success"
+        };
+        let text = match self.renderer.render_build(template, pr, build) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
         };
 
-        let mut event_payload = json_dictionary::JsonDictionary::new();
-        event_payload.insert("pr", &pr).expect("PR should be RustcEncodable");
-        event_payload.insert("build", &build).expect("Build should be RustcEncodable");
-
-        let (comment, opcode) = match self.get_comments(pr.id) {
-            Ok(ref comments) => {
-                match Bitbucket::matching_comments(&comments, &text) {
-                    Some(comment) => (Ok(comment), "Existing"),
-                    None => {
-                        // Have to post or edit comment
-                        match Bitbucket::matching_comments_substring(&comments, &pr.from_commit) {
-                            Some(comment) => {
-                                (self.edit_comment(pr.id, &comment, &text), "Update")
-                            },
-                            None => (self.post_comment(pr.id, &text), "Post")
-                        }
-                    }
-                }
-            },
-            Err(err) => (Err(format!("Error getting list of comments {}", err)), "Error")
+        let key = templates::CommentKey::status(pr, build);
+        templates::upsert_comment(self, pr, &text, &key, "Comment", |comment| events::CommentEvent {
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            comment: comment
+        })
+    }
+
+    fn update_pr_summary_comment(&self, pr: &::PullRequest, build: &::BuildDetails)
+            -> Result<Comment, RepositoryError> {
+        let comments = match self.get_own_comments(pr.id) {
+            Ok(comments) => comments,
+            Err(err) => return Err(err)
         };
+        let key = templates::CommentKey::summary(pr);
+        let existing = self.matching_comment(&comments, &key);
 
-        match comment {
-            Ok(ref comment) => {
-                event_payload.insert("comment", comment) .expect("Comment should be RustcEncodable");
-            },
-            Err(_) => {}
+        let mut rows = match existing {
+            Some(comment) => Bitbucket::parse_summary_rows(&comment.text),
+            None => vec![]
+        };
+        let row = templates::SummaryRow {
+            commit: pr.from_commit.to_owned(),
+            build_id: build.id,
+            web_url: build.web_url.to_owned(),
+            state: build.state.to_owned(),
+            status: build.status.to_owned()
+        };
+        match rows.iter().position(|existing_row| existing_row.commit == row.commit) {
+            Some(index) => rows[index] = row,
+            None => rows.push(row)
         };
 
-        self.broadcast(&format!("Comment::{}", opcode), &event_payload);
+        let text = match self.renderer.render_summary(pr, build, &rows) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let encoded_rows = serde_json::to_string(&rows).expect("Summary rows should be serializable");
+        // The rows are kept whole, the rendered text making room for them if the comment would be too long
+        let rows_trailer = format!("\n\n{}{})", SUMMARY_MARKER, base64::encode(encoded_rows.as_bytes()));
+        let text = self.renderer.mark_with(&key, &text, &rows_trailer);
+
+        let (comment, opcode) = match existing {
+            Some(comment) if comment.text == text => (Ok(comment.to_owned()), "Existing"),
+            Some(comment) => (self.edit_comment(pr.id, comment, &text), "Update"),
+            None => (self.post_comment(pr.id, &text), "Post")
+        };
+
+        self.broadcast(&format!("Comment::{}", opcode), &events::CommentEvent {
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            comment: comment.as_ref().ok().map(OwnComment::to_event)
+        });
         comment
     }
 
-    fn get_comments(&self, pr_id: i32) -> Result<Vec<Comment>, String> {
-        let mut headers = rest::Headers::new();
-        headers.add_authorization_header(self as &::UsernameAndPassword)
-            .add_accept_json_header();
-        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/activities?fromType=COMMENT",
-                self.credentials.base_url, self.credentials.project_slug,
-                self.credentials.repo_slug, pr_id);
+    fn parse_summary_rows(text: &str) -> Vec<templates::SummaryRow> {
+        let encoded = match text.find(SUMMARY_MARKER) {
+            Some(index) => text[index + SUMMARY_MARKER.len()..].split(')').next().unwrap_or(""),
+            None => return vec![]
+        };
 
-        match rest::get::<PagedApi<Activity>>(&url, &headers.headers) {
-            Ok(activities) =>{
-                Ok(
-                    activities.values.iter()
-                        .filter(|&activity| activity.comment.is_some())
-                        .filter(|&activity| activity.user.name == self.credentials.username)
-                        .map(|ref activity| {
-                            // won't panic because of filter above
-                            activity.comment.as_ref().unwrap().to_owned()
-                        })
-                        .collect()
-                )
-            },
-            Err(err) =>  Err(format!("Error getting comments {}", err))
+        match base64::decode(encoded).map(String::from_utf8) {
+            Ok(Ok(decoded)) => serde_json::from_str(&decoded).unwrap_or(vec![]),
+            _ => vec![]
         }
     }
 
-    fn post_comment(&self, pr_id: i32, text: &str) -> Result<Comment, String> {
-        let mut headers = rest::Headers::new();
-        headers.add_authorization_header(self as &::UsernameAndPassword)
-            .add_accept_json_header()
-            .add_content_type_json_header();
+    fn update_pr_matrix_comment(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>)
+            -> Result<Comment, RepositoryError> {
+        let text = match self.renderer.render_matrix(pr, builds) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
 
-        let body = json::encode(&CommentSubmit {
-            text: text.to_owned()
-        }).unwrap();
-        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/comments",
+        let key = templates::CommentKey::matrix(pr);
+        templates::upsert_comment(self, pr, &text, &key, "MatrixComment", |comment| events::MatrixCommentEvent {
+            pr: pr.to_owned(),
+            builds: builds.to_owned(),
+            comment: comment
+        })
+    }
+
+    fn get_comments(&self, pr_id: i32) -> Result<Vec<Comment>, RepositoryError> {
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/activities?fromType=COMMENT",
                 self.credentials.base_url, self.credentials.project_slug,
                 self.credentials.repo_slug, pr_id);
 
-        match rest::post::<Comment>(&url, &body, &headers.headers, &hyper::status::StatusCode::Created) {
-            Ok(comment) => Ok(comment.to_owned()),
-            Err(err) =>  Err(format!("Error posting comment {}", err))
+        let activities = rest::get_paged(&url, |url| credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<rest::Page<Activity>>(url, &headers.headers)
+        }));
+        match activities {
+            Ok(activities) => Ok(activities.into_iter().filter_map(|activity| activity.comment).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of comments", err))
         }
     }
 
-    fn edit_comment(&self, pr_id: i32, comment: &Comment, text: &str) -> Result<Comment, String> {
-        let mut headers = rest::Headers::new();
-        headers.add_authorization_header(self as &::UsernameAndPassword)
-            .add_accept_json_header()
-            .add_content_type_json_header();
-
-        let body = json::encode(&CommentEdit {
-            text: text.to_owned(),
-            version: comment.version
-        }).unwrap();
-        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/comments/{}",
-                self.credentials.base_url, self.credentials.project_slug,
-                self.credentials.repo_slug, pr_id, comment.id);
-
-        match rest::put::<Comment>(&url, &body, &headers.headers, &hyper::status::StatusCode::Ok) {
-            Ok(comment) => Ok(comment.to_owned()),
-            Err(err) =>  Err(format!("Error posting comment {}", err))
+    // Approves the pull request as the daemon, or withdraws its approval, when `approve` is set. Approvals that are
+    // already as they should be are left alone, as told by the pull request as listed unless it has moved on since.
+    fn review(&self, pr: &::PullRequest, approved: bool) -> Result<(), RepositoryError> {
+        if self.credentials.approve != Some(true) {
+            return Ok(());
+        }
+        let listed = self.listed.lock().expect("Listed Pull Requests should not be poisoned").get(&pr.id).cloned();
+        let current = match listed {
+            Some(listed) if listed.from_ref.latest_commit == pr.from_commit => listed,
+            _ => match self.get_pull_request(pr) {
+                Ok((_, current)) => current,
+                Err(err) => return Err(err)
+            }
+        };
+        let participant = current.reviewers.iter().chain(current.participants.iter())
+            .find(|participant| participant.user.name == self.credentials.username);
+        let slug = match participant {
+            Some(participant) if participant.approved == approved => return Ok(()),
+            Some(participant) => participant.user.slug.to_owned(),
+            // Only participants can have approved
+            None if !approved => return Ok(()),
+            None => match self.user_slug() {
+                Ok(slug) => slug,
+                Err(err) => return Err(err)
+            }
+        };
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/participants/{}",
+            self.credentials.base_url, self.credentials.project_slug, self.credentials.repo_slug, pr.id, slug);
+        let status = match approved {
+            true => "APPROVED",
+            false => "UNAPPROVED"
+        };
+        let body = json!({ "user": { "name": self.credentials.username }, "approved": approved, "status": status })
+            .to_string();
+        let reviewed = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<PullRequestParticipant>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match reviewed {
+            Ok(_) => {
+                // The listed approval is no longer current
+                self.listed.lock().expect("Listed Pull Requests should not be poisoned").remove(&pr.id);
+                Ok(())
+            },
+            Err(err) => Err(RepositoryError::api("approving Pull Request", err))
         }
     }
 
-    fn post_build(&self, build: &::BuildDetails, pr: &::PullRequest) -> Result<Build, String> {
-        let bitbucket_build = Bitbucket::make_build(&build);
+    // Users are addressed by their slug, which differs from their name when it has characters URLs do not take
+    fn user_slug(&self) -> Result<String, RepositoryError> {
+        let mut user_slug = self.user_slug.lock().expect("User slug should not be poisoned");
+        if let Some(ref slug) = *user_slug {
+            return Ok(slug.to_owned());
+        }
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("filter", &self.credentials.username)
+            .finish();
+        let url = format!("{}/api/latest/users?{}", self.credentials.base_url, query);
+        let users = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<rest::Page<User>>(&url, &headers.headers)
+        });
+        let users = match users {
+            Ok(users) => users.values,
+            Err(err) => return Err(RepositoryError::api("looking up user", err))
+        };
+        match users.into_iter().find(|user| user.name == self.credentials.username) {
+            Some(user) => {
+                *user_slug = Some(user.slug.to_owned());
+                Ok(user.slug)
+            },
+            None => Err(RepositoryError::api("looking up user",
+                ApiError::Remote(format!("No user is named {}", self.credentials.username))))
+        }
+    }
 
-        let mut headers = rest::Headers::new();
-        headers.add_authorization_header(self as &::UsernameAndPassword)
-            .add_accept_json_header()
-            .add_content_type_json_header();
+    // With `all_commits`, older commits only get the statuses of finished builds, and each state once, as every
+    // poll would otherwise post to all of them again
+    fn post_build(&self, build: &::BuildDetails, pr: &::PullRequest) -> Result<Build, RepositoryError> {
+        let bitbucket_build = Bitbucket::make_build(build);
+        let key = (pr.id, bitbucket_build.key.to_owned(), pr.from_commit.to_owned());
+        let older = self.credentials.all_commits == Some(true) && bitbucket_build.state != BuildState::InProgress
+            && self.older_statuses.lock().expect("Older statuses should not be poisoned").get(&key)
+                != Some(&bitbucket_build.state);
+        let mut commits = vec![pr.from_commit.to_owned()];
+        if older {
+            match ::Repository::get_pr_commits(self, pr) {
+                Ok(pr_commits) => {
+                    commits.extend(pr_commits.into_iter().map(|commit| commit.id).filter(|id| id != &pr.from_commit))
+                },
+                Err(err) => return Err(err)
+            }
+        }
+        for commit in commits.iter() {
+            if let Err(err) = self.post_status(commit, &bitbucket_build) {
+                return Err(err);
+            }
+        }
+        if older {
+            self.older_statuses.lock().expect("Older statuses should not be poisoned")
+                .insert(key, bitbucket_build.state.to_owned());
+        }
+        Ok(bitbucket_build)
+    }
 
-        let body = json::encode(&bitbucket_build).unwrap();
-        let url = format!("{}/build-status/1.0/commits/{}", self.credentials.base_url,
-            pr.from_commit);
+    fn post_status(&self, commit: &str, build: &Build) -> Result<(), RepositoryError> {
+        let body = serde_json::to_string(build).unwrap();
+        let url = format!("{}/build-status/1.0/commits/{}", self.credentials.base_url, commit);
 
-        match rest::post_raw(&url, &body, &headers.headers) {
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post_raw(&url, &body, &headers.headers)
+        });
+        match response {
             Ok(response) => {
                 match response.status {
-                    ref status if status == &hyper::status::StatusCode::NoContent => Ok(bitbucket_build),
-                    e @ _ => Err(e.to_string())
+                    hyper::StatusCode::NO_CONTENT => Ok(()),
+                    e @ _ => Err(RepositoryError::api("posting build", ApiError::Status(e)))
                 }
             },
-            Err(err) =>  Err(format!("Error posting build {}", err))
+            Err(err) => Err(RepositoryError::api("posting build", err))
         }
     }
 
@@ -429,11 +857,11 @@ impl Bitbucket {
         let build_status = match build.state {
             ::BuildState::Finished => {
                 match build.status {
-                    ::BuildStatus::Success => BuildState::SUCCESSFUL,
-                    _ => BuildState::FAILED
+                    ::BuildStatus::Success => BuildState::Successful,
+                    _ => BuildState::Failed
                 }
             },
-            _ => BuildState::INPROGRESS
+            _ => BuildState::InProgress
         };
 
         let description = match build.status_text {
@@ -451,14 +879,106 @@ impl Bitbucket {
     }
 }
 
-fn make_queued_comment(build_url: &str, commit_id: &str) -> String {
-    format!("⏳ [Build]({}) for commit {} queued", build_url, commit_id)
-}
+impl templates::CommentApi for Bitbucket {
+    type Comment = Comment;
 
-fn make_success_comment(build_url: &str, commit_id: &str, build_message: &str) -> String {
-    format!("✔️ [Build]({}) for commit {} is **successful**: {}", build_url, commit_id, build_message)
-}
+    fn renderer(&self) -> &templates::Renderer {
+        &self.renderer
+    }
+
+    fn superseded_comments(&self) -> Option<&str> {
+        self.credentials.superseded_comments.as_ref().map(String::as_str)
+    }
+
+    fn get_own_comments(&self, pr_id: i32) -> Result<Vec<Comment>, RepositoryError> {
+        match self.get_comments(pr_id) {
+            Ok(comments) => Ok(comments.into_iter()
+                .filter(|comment| comment.author.name == self.credentials.username)
+                .collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn post_comment(&self, pr_id: i32, text: &str) -> Result<Comment, RepositoryError> {
+        let body = serde_json::to_string(&CommentSubmit {
+            text: text.to_owned()
+        }).unwrap();
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/comments",
+                self.credentials.base_url, self.credentials.project_slug,
+                self.credentials.repo_slug, pr_id);
 
-fn make_failure_comment(build_url: &str, commit_id: &str, build_message: &str) -> String {
-    format!("❌ [Build]({}) for commit {} has **failed**: {}", build_url, commit_id, build_message)
+        let comment = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match comment {
+            Ok(comment) => Ok(comment.to_owned()),
+            Err(err) => Err(RepositoryError::api("posting comment", err))
+        }
+    }
+
+    fn edit_comment(&self, pr_id: i32, comment: &Comment, text: &str) -> Result<Comment, RepositoryError> {
+        let body = serde_json::to_string(&CommentEdit {
+            text: text.to_owned(),
+            version: comment.version
+        }).unwrap();
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/comments/{}",
+                self.credentials.base_url, self.credentials.project_slug,
+                self.credentials.repo_slug, pr_id, comment.id);
+
+        let edited = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match edited {
+            Ok(comment) => Ok(comment.to_owned()),
+            Err(err) => Err(RepositoryError::api("editing comment", err))
+        }
+    }
+
+    fn delete_comment(&self, pr_id: i32, comment: &Comment) -> Result<(), RepositoryError> {
+        let url = format!("{}/api/latest/projects/{}/repos/{}/pull-requests/{}/comments/{}?version={}",
+                self.credentials.base_url, self.credentials.project_slug,
+                self.credentials.repo_slug, pr_id, comment.id, comment.version);
+
+        match credentials::authorized(&*self.provider(), |headers| rest::delete_raw(&url, &headers.headers)) {
+            Ok(ref response) if !response.status.is_success() => {
+                Err(RepositoryError::api("deleting comment", ApiError::Status(response.status)))
+            },
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("deleting comment", err))
+        }
+    }
+
+    fn broadcast<T>(&self, opcode: &str, payload: &T) where T : Serialize {
+        let opcode = fanout::OpCode::Custom {
+            payload: format!("Bitbucket::{}", opcode).to_owned()
+        };
+        let message = fanout::Message::new(opcode, payload);
+        self.broadcaster.broadcast(&message);
+    }
+
+    fn matching_comment<'a>(&self, comments: &'a [Comment], key: &templates::CommentKey) -> Option<&'a Comment> {
+        comments.iter()
+            .find(|&comment| templates::CommentKey::parse(&comment.text).as_ref() == Some(key))
+            .or_else(|| {
+                // Comments posted before markers were introduced can only be found by their contents
+                comments.iter()
+                    .filter(|&comment| templates::CommentKey::parse(&comment.text).is_none())
+                    .find(|&comment| match key.commit {
+                        Some(ref commit) => comment.text.contains(commit.as_str()),
+                        None => comment.text.contains(SUMMARY_MARKER)
+                    })
+            })
+    }
+
+    // Summary comments are kept for every commit
+    fn is_outdated(&self, pr: &::PullRequest, comment: &Comment) -> bool {
+        match templates::CommentKey::parse(&comment.text) {
+            Some(key) => key.commit.is_some() && key.commit.as_ref() != Some(&pr.from_commit),
+            None => !comment.text.contains(SUMMARY_MARKER)
+        }
+    }
 }
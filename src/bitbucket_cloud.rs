@@ -0,0 +1,706 @@
+use std::sync::Arc;
+
+use hyper;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use time;
+
+use ::credentials;
+use ::errors::{ApiError, ConfigError, RepositoryError};
+use ::events;
+use ::fanout;
+use ::registry;
+use ::i18n;
+use ::rest;
+use ::templates;
+
+static DEFAULT_API_URL: &'static str = "https://api.bitbucket.org/2.0";
+
+// Pages link to the next rather than counting from a start
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Page<T> {
+    values: Vec<T>,
+    next: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct PullRequest {
+    id: i32,
+    title: String,
+    description: Option<String>,
+    updated_on: String,
+    source: Endpoint,
+    destination: Endpoint,
+    author: User,
+    #[serde(default)]
+    reviewers: Vec<User>,
+    links: Links
+}
+
+impl PullRequest {
+    fn to_pull_request(&self) -> ::PullRequest {
+        ::PullRequest {
+            id: self.id,
+            web_url: self.links.html.href.to_owned(),
+            from_ref: format!("refs/heads/{}", self.source.branch.name),
+            from_commit: self.source.commit.as_ref().map(|commit| commit.hash.to_owned()).unwrap_or_default(),
+            to_ref: format!("refs/heads/{}", self.destination.branch.name),
+            // The source repository is gone when the fork has been deleted
+            from_fork: match (&self.source.repository, &self.destination.repository) {
+                (&Some(ref source), &Some(ref destination)) => source.full_name != destination.full_name,
+                _ => true
+            },
+            title: self.title.to_owned(),
+            description: self.description.to_owned().unwrap_or_default(),
+            updated: parse_timestamp(&self.updated_on),
+            author: ::User {
+                name: self.author.display_name.to_owned(),
+                email: "".to_owned(),
+                username: self.author.account_id.to_owned()
+            },
+            reviewers: self.reviewers.iter().filter_map(|reviewer| reviewer.account_id.to_owned()).collect(),
+            approvals: vec![],
+            size: None,
+            open_tasks: None,
+            labels: vec![]
+        }
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Endpoint {
+    branch: Branch,
+    commit: Option<CommitReference>,
+    repository: Option<Repository>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Branch {
+    name: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitReference {
+    hash: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Repository {
+    full_name: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Links {
+    html: Link
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Link {
+    href: String
+}
+
+// Bitbucket Cloud no longer has usernames, and tells accounts apart by their IDs
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct User {
+    display_name: String,
+    account_id: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Participants {
+    #[serde(default)]
+    participants: Vec<Participant>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Participant {
+    user: User,
+    approved: bool
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Comment {
+    id: i64,
+    content: Content,
+    user: User,
+    #[serde(default)]
+    deleted: bool
+}
+
+impl templates::OwnComment for Comment {
+    fn text(&self) -> &str {
+        &self.content.raw
+    }
+
+    fn to_event(&self) -> events::PostedComment {
+        events::PostedComment {
+            id: self.id,
+            // Bitbucket Cloud comments are not versioned
+            version: 0,
+            text: self.content.raw.to_owned()
+        }
+    }
+}
+
+impl Comment {
+    fn to_comment(&self) -> ::Comment {
+        ::Comment {
+            id: self.id,
+            author: self.user.account_id.to_owned().unwrap_or_default(),
+            text: self.content.raw.to_owned()
+        }
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Content {
+    raw: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Commit {
+    hash: String,
+    message: String,
+    author: Option<CommitAuthor>,
+    parents: Vec<CommitReference>
+}
+
+// The author as written in the commit, such as "Jane Doe <jane@example.com>"
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitAuthor {
+    raw: String
+}
+
+impl CommitAuthor {
+    fn to_user(&self) -> ::User {
+        let mut parts = self.raw.splitn(2, '<');
+        ::User {
+            name: parts.next().unwrap_or("").trim().to_owned(),
+            email: parts.next().unwrap_or("").trim_end_matches('>').trim().to_owned(),
+            username: None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct Build {
+    state: BuildState,
+    key: String,
+    name: String,
+    url: String,
+    description: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+enum BuildState {
+    InProgress,
+    Failed,
+    Successful
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct BitbucketCloudCredentials {
+    // Signs in with an app password, unless `credential_provider` is set, such as to an OAuth consumer
+    pub username: String,
+    #[serde(default)]
+    pub app_password: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    // The account ID the daemon comments as, so that it finds its own comments again
+    pub account_id: String,
+    // https://api.bitbucket.org/2.0 unless set
+    pub api_url: Option<String>,
+    pub workspace: String,
+    pub repo_slug: String,
+    pub post_build: bool,
+    pub templates: Option<templates::Templates>,
+    pub superseded_comments: Option<String>
+}
+
+pub struct BitbucketCloudFactory;
+
+impl registry::RepositoryFactory for BitbucketCloudFactory {
+    fn create(&self, settings: &serde_json::Value, messages: &i18n::Messages,
+        fanout: &fanout::Fanout<fanout::Message>) -> Result<Box<::Repository>, ConfigError> {
+        let credentials = match serde_json::from_value::<BitbucketCloudCredentials>(settings.to_owned()) {
+            Ok(credentials) => credentials,
+            Err(err) => return Err(ConfigError::Parse(err))
+        };
+        match BitbucketCloud::new(&credentials, messages, fanout) {
+            Ok(bitbucket) => Ok(Box::new(bitbucket)),
+            Err(err) => Err(ConfigError::Invalid(format!("Failed to load comment templates: {}", err)))
+        }
+    }
+}
+
+pub struct BitbucketCloud {
+    pub credentials: BitbucketCloudCredentials,
+    broadcaster: fanout::Fanout<fanout::Message>,
+    renderer: templates::Renderer
+}
+
+// Bitbucket Cloud reports times such as "2020-05-04T09:52:29.123456+00:00", which are turned into milliseconds like
+// Bitbucket Server's
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let local = match timestamp.get(..19).map(|seconds| time::strptime(seconds, "%Y-%m-%dT%H:%M:%S")) {
+        Some(Ok(tm)) => tm.to_timespec().sec,
+        _ => return None
+    };
+    let zone = match timestamp.len() {
+        length if length >= 25 => &timestamp[length - 6..],
+        _ => return Some(local * 1000)
+    };
+    let sign = if zone.starts_with('-') { -1 } else { 1 };
+    match (zone.get(1..3).map(str::parse::<i64>), zone.get(4..6).map(str::parse::<i64>)) {
+        (Some(Ok(hours)), Some(Ok(minutes))) => Some((local - sign * (hours * 3600 + minutes * 60)) * 1000),
+        _ => None
+    }
+}
+
+fn branch(reference: &str) -> &str {
+    reference.trim_start_matches("refs/heads/")
+}
+
+impl ::Repository for BitbucketCloud {
+    fn get_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.get_pr_list_by_state("OPEN")
+    }
+
+    fn get_merged_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.get_pr_list_by_state("MERGED")
+    }
+
+    // Bitbucket Cloud redirects to the diff, which the HTTP client follows
+    fn get_pr_diff(&self, pr: &::PullRequest) -> Result<String, RepositoryError> {
+        let url = format!("{}/pullrequests/{}/diff", self.repository_url(), pr.id);
+
+        match credentials::authorized(&*self.provider(), |headers| rest::get_raw(&url, &headers.headers)) {
+            Ok(ref response) if response.status != hyper::StatusCode::OK => {
+                Err(RepositoryError::api("getting diff", ApiError::Status(response.status)))
+            },
+            Ok(response) => Ok(response.body),
+            Err(err) => Err(RepositoryError::api("getting diff", err))
+        }
+    }
+
+    fn get_pr_commits(&self, pr: &::PullRequest) -> Result<Vec<::Commit>, RepositoryError> {
+        let url = format!("{}/pullrequests/{}/commits?pagelen=100", self.repository_url(), pr.id);
+
+        let commits = self.get_all::<Commit>(&url);
+        match commits {
+            Ok(commits) => Ok(commits.iter().map(|commit| ::Commit {
+                id: commit.hash.to_owned(),
+                message: commit.message.to_owned(),
+                merge: commit.parents.len() > 1,
+                author: commit.author.as_ref().map(CommitAuthor::to_user)
+            }).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of commits", err))
+        }
+    }
+
+    fn get_pr_comments(&self, pr: &::PullRequest) -> Result<Vec<::Comment>, RepositoryError> {
+        match self.get_comments(pr.id) {
+            Ok(comments) => Ok(comments.iter().map(Comment::to_comment).collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    // Only the pull request itself, not the list, tells its participants
+    fn get_pr_approvals(&self, pr: &::PullRequest) -> Result<Vec<String>, RepositoryError> {
+        let url = format!("{}/pullrequests/{}", self.repository_url(), pr.id);
+
+        let participants = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Participants>(&url, &headers.headers)
+        });
+        match participants {
+            Ok(participants) => Ok(participants.participants.into_iter()
+                .filter(|participant| participant.approved)
+                .filter_map(|participant| participant.user.account_id)
+                .collect()),
+            Err(err) => Err(RepositoryError::api("getting participants", err))
+        }
+    }
+
+    fn build_queued(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_running(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_success(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "success")
+    }
+
+    fn build_failure(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "failure")
+    }
+
+    fn build_matrix(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>) -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_matrix(pr, builds) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::matrix(pr);
+        let comment = templates::upsert_comment(self, pr, &text, &key, "MatrixComment", |comment| {
+            events::MatrixCommentEvent {
+                pr: pr.to_owned(),
+                builds: builds.to_owned(),
+                comment: comment
+            }
+        });
+        if let Err(err) = comment {
+            return Err(err);
+        }
+        if self.credentials.post_build {
+            for build in builds {
+                if let Err(err) = self.post_status(&pr.from_commit, &BitbucketCloud::make_build(build)) {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn upsert_comment(&self, pr: &::PullRequest, key: &templates::CommentKey, text: &str)
+            -> Result<(), RepositoryError> {
+        templates::upsert_comment(self, pr, text, key, "Notice", |comment| events::NoticeEvent {
+            pr: pr.to_owned(),
+            kind: key.kind.to_owned(),
+            comment: comment
+        }).map(|_| ())
+    }
+
+    fn report_check(&self, pr: &::PullRequest, check: &::CheckResult) -> Result<(), RepositoryError> {
+        let status = Build {
+            state: match check.passed {
+                true => BuildState::Successful,
+                false => BuildState::Failed
+            },
+            key: format!("pr_demon.{}", check.name),
+            name: check.name.to_owned(),
+            url: pr.web_url.to_owned(),
+            description: check.summary.to_owned()
+        };
+        self.post_status(&pr.from_commit, &status)
+    }
+
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+            -> Result<::PullRequest, RepositoryError> {
+        let body = json!({
+            "title": title,
+            "description": description,
+            "source": { "branch": { "name": branch(from_ref) } },
+            "destination": { "branch": { "name": branch(to_ref) } }
+        }).to_string();
+        let url = format!("{}/pullrequests", self.repository_url());
+
+        let pr = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match pr {
+            Ok(pr) => Ok(pr.to_pull_request()),
+            Err(err) => Err(RepositoryError::api("creating Pull Request", err))
+        }
+    }
+
+    fn decline_pr(&self, pr: &::PullRequest) -> Result<(), RepositoryError> {
+        let url = format!("{}/pullrequests/{}/decline", self.repository_url(), pr.id);
+        let declined = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&url, "{}", &headers.headers, &hyper::StatusCode::OK)
+        });
+        match declined {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("declining Pull Request", err))
+        }
+    }
+
+    // The strategy is one of Bitbucket Cloud's, "merge_commit", "squash" or "fast_forward"
+    fn merge_pr(&self, pr: &::PullRequest, strategy: Option<&str>, message: Option<&str>)
+            -> Result<(), RepositoryError> {
+        let mut body = serde_json::Map::new();
+        if let Some(message) = message {
+            body.insert("message".to_owned(), json!(message));
+        }
+        if let Some(strategy) = strategy {
+            body.insert("merge_strategy".to_owned(), json!(strategy));
+        }
+        let body = serde_json::Value::Object(body).to_string();
+        let url = format!("{}/pullrequests/{}/merge", self.repository_url(), pr.id);
+        let merged = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post_raw(&url, &body, &headers.headers)
+        });
+        // Merges that take long are finished in the background
+        match merged {
+            Ok(ref response) if response.status == hyper::StatusCode::OK
+                || response.status == hyper::StatusCode::ACCEPTED => Ok(()),
+            Ok(response) => Err(RepositoryError::api("merging Pull Request", ApiError::Status(response.status))),
+            Err(err) => Err(RepositoryError::api("merging Pull Request", err))
+        }
+    }
+
+    // Reviewers are named by their account IDs. The pull request is updated with all of its reviewers at once.
+    fn add_reviewers(&self, pr: &::PullRequest, reviewers: &[String]) -> Result<(), RepositoryError> {
+        let url = format!("{}/pullrequests/{}", self.repository_url(), pr.id);
+        let current = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<PullRequest>(&url, &headers.headers)
+        });
+        let current = match current {
+            Ok(current) => current,
+            Err(err) => return Err(RepositoryError::api("adding reviewer", err))
+        };
+        let mut account_ids = current.reviewers.iter().filter_map(|reviewer| reviewer.account_id.to_owned())
+            .collect::<Vec<_>>();
+        for reviewer in reviewers {
+            if !account_ids.contains(reviewer) {
+                account_ids.push(reviewer.to_owned());
+            }
+        }
+        let body = json!({
+            "title": current.title,
+            "reviewers": account_ids.iter().map(|account_id| json!({ "account_id": account_id }))
+                .collect::<Vec<_>>()
+        }).to_string();
+        let added = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match added {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("adding reviewer", err))
+        }
+    }
+
+    fn create_branch(&self, name: &str, start_point: &str) -> Result<(), RepositoryError> {
+        // Branches are created from a commit, so a branch given as the start point is resolved first
+        let commit_url = format!("{}/commit/{}", self.repository_url(), branch(start_point));
+        let commit = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<CommitReference>(&commit_url, &headers.headers)
+        });
+        let commit = match commit {
+            Ok(commit) => commit,
+            Err(err) => return Err(RepositoryError::api("creating branch", err))
+        };
+        let url = format!("{}/refs/branches", self.repository_url());
+        let body = json!({ "name": branch(name), "target": { "hash": commit.hash } }).to_string();
+        let created = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match created {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("creating branch", err))
+        }
+    }
+}
+
+impl BitbucketCloud {
+    pub fn new(credentials: &BitbucketCloudCredentials, messages: &i18n::Messages,
+        broadcaster: &fanout::Fanout<fanout::Message>) -> Result<BitbucketCloud, String> {
+        let renderer = match templates::Renderer::new(&credentials.templates, messages, &credentials.repo_slug) {
+            Ok(renderer) => renderer,
+            Err(err) => return Err(err)
+        };
+
+        Ok(BitbucketCloud {
+            credentials: credentials.to_owned(),
+            broadcaster: broadcaster.to_owned(),
+            renderer: renderer
+        })
+    }
+
+    fn repository_url(&self) -> String {
+        let api_url = self.credentials.api_url.as_ref().map_or(DEFAULT_API_URL, String::as_str);
+        format!("{}/repositories/{}/{}", api_url.trim_end_matches('/'), self.credentials.workspace,
+            self.credentials.repo_slug)
+    }
+
+    fn get_pr_list_by_state(&self, state: &str) -> Result<Vec<::PullRequest>, RepositoryError> {
+        let url = format!("{}/pullrequests?state={}&pagelen=50", self.repository_url(), state);
+
+        let prs = self.get_all::<PullRequest>(&url);
+        match prs {
+            Ok(ref prs) => Ok(prs.iter().map(PullRequest::to_pull_request).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of Pull Requests", err))
+        }
+    }
+
+    // Collects the values of every page, following the `next` links
+    fn get_all<T>(&self, url: &str) -> Result<Vec<T>, ApiError> where T: DeserializeOwned {
+        let mut values = vec![];
+        let mut next = Some(url.to_owned());
+        while let Some(url) = next {
+            let page = credentials::authorized(&*self.provider(), |mut headers| {
+                headers.add_accept_json_header();
+                rest::get::<Page<T>>(&url, &headers.headers)
+            });
+            let page = match page {
+                Ok(page) => page,
+                Err(err) => return Err(err)
+            };
+            values.extend(page.values);
+            next = page.next;
+        }
+        Ok(values)
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credentials.credential_provider, credentials::Credential::Basic {
+            username: self.credentials.username.to_owned(),
+            password: self.credentials.app_password.to_owned()
+        })
+    }
+
+    fn report_build(&self, pr: &::PullRequest, build: &::BuildDetails, template: &str)
+            -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_build(template, pr, build) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::status(pr, build);
+        let comment = templates::upsert_comment(self, pr, &text, &key, "Comment", |comment| events::CommentEvent {
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            comment: comment
+        });
+        if let Err(err) = comment {
+            return Err(err);
+        }
+        match self.credentials.post_build {
+            true => self.post_status(&pr.from_commit, &BitbucketCloud::make_build(build)),
+            false => Ok(())
+        }
+    }
+
+    fn get_comments(&self, pr_id: i32) -> Result<Vec<Comment>, RepositoryError> {
+        let url = format!("{}/pullrequests/{}/comments?pagelen=100", self.repository_url(), pr_id);
+
+        let comments = self.get_all::<Comment>(&url);
+        match comments {
+            Ok(comments) => Ok(comments.into_iter().filter(|comment| !comment.deleted).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of comments", err))
+        }
+    }
+
+    // A status is created the first time its key is posted for a commit, and updated after
+    fn post_status(&self, commit: &str, build: &Build) -> Result<(), RepositoryError> {
+        let body = serde_json::to_string(build).unwrap();
+        let url = format!("{}/commit/{}/statuses/build", self.repository_url(), commit);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post_raw(&url, &body, &headers.headers)
+        });
+        match response {
+            Ok(ref response) if response.status == hyper::StatusCode::CREATED
+                || response.status == hyper::StatusCode::OK => Ok(()),
+            Ok(response) => Err(RepositoryError::api("posting build", ApiError::Status(response.status))),
+            Err(err) => Err(RepositoryError::api("posting build", err))
+        }
+    }
+
+    fn make_build(build: &::BuildDetails) -> Build {
+        let state = match build.state {
+            ::BuildState::Finished => {
+                match build.status {
+                    ::BuildStatus::Success => BuildState::Successful,
+                    _ => BuildState::Failed
+                }
+            },
+            _ => BuildState::InProgress
+        };
+
+        Build {
+            state: state,
+            key: build.build_id.to_owned(),
+            name: build.id.to_string(),
+            url: build.web_url.to_owned(),
+            description: build.status_text.to_owned().unwrap_or_default()
+        }
+    }
+}
+
+impl templates::CommentApi for BitbucketCloud {
+    type Comment = Comment;
+
+    fn renderer(&self) -> &templates::Renderer {
+        &self.renderer
+    }
+
+    fn superseded_comments(&self) -> Option<&str> {
+        self.credentials.superseded_comments.as_ref().map(String::as_str)
+    }
+
+    fn get_own_comments(&self, pr_id: i32) -> Result<Vec<Comment>, RepositoryError> {
+        match self.get_comments(pr_id) {
+            Ok(comments) => Ok(comments.into_iter()
+                .filter(|comment| comment.user.account_id.as_ref() == Some(&self.credentials.account_id))
+                .collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn post_comment(&self, pr_id: i32, text: &str) -> Result<Comment, RepositoryError> {
+        let body = json!({ "content": { "raw": text } }).to_string();
+        let url = format!("{}/pullrequests/{}/comments", self.repository_url(), pr_id);
+
+        let comment = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match comment {
+            Ok(comment) => Ok(comment),
+            Err(err) => Err(RepositoryError::api("posting comment", err))
+        }
+    }
+
+    fn edit_comment(&self, pr_id: i32, comment: &Comment, text: &str) -> Result<Comment, RepositoryError> {
+        let body = json!({ "content": { "raw": text } }).to_string();
+        let url = format!("{}/pullrequests/{}/comments/{}", self.repository_url(), pr_id, comment.id);
+
+        let edited = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match edited {
+            Ok(comment) => Ok(comment),
+            Err(err) => Err(RepositoryError::api("editing comment", err))
+        }
+    }
+
+    fn delete_comment(&self, pr_id: i32, comment: &Comment) -> Result<(), RepositoryError> {
+        let url = format!("{}/pullrequests/{}/comments/{}", self.repository_url(), pr_id, comment.id);
+
+        match credentials::authorized(&*self.provider(), |headers| rest::delete_raw(&url, &headers.headers)) {
+            Ok(ref response) if !response.status.is_success() => {
+                Err(RepositoryError::api("deleting comment", ApiError::Status(response.status)))
+            },
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("deleting comment", err))
+        }
+    }
+
+    fn broadcast<T>(&self, opcode: &str, payload: &T) where T : Serialize {
+        let opcode = fanout::OpCode::Custom {
+            payload: format!("BitbucketCloud::{}", opcode)
+        };
+        let message = fanout::Message::new(opcode, payload);
+        self.broadcaster.broadcast(&message);
+    }
+}
@@ -0,0 +1,247 @@
+use serde_json;
+
+use ::components;
+use ::errors::{ConfigError, Error};
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+
+// Branch names matching one of `include`, any branch unless set, and none of `exclude`
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Patterns {
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>
+}
+
+// Pull requests from and to matching branches are built on `builders` only, named like the `components` builders
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct BranchRoute {
+    pub source: Option<Patterns>,
+    pub target: Option<Patterns>,
+    pub builders: Vec<String>
+}
+
+// Skips pull requests whose source or target branch does not match. With `routes`, it takes the place of the
+// `build` stage, building on the builders of the first matching route, or on every builder if none matches.
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct BranchFilters {
+    pub source: Option<Patterns>,
+    pub target: Option<Patterns>,
+    pub routes: Option<Vec<BranchRoute>>
+}
+
+// `*` stands for any run of characters, slashes included, and `?` for any one character
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Where the last `*` was seen in the pattern, and the position in the text it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(&'*') => {
+                star = Some((p, t));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                },
+                None => return false
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl Patterns {
+    pub fn matches(&self, branch: &str) -> bool {
+        let any = |patterns: &Option<Vec<String>>| patterns.as_ref()
+            .map(|patterns| patterns.iter().any(|pattern| glob_matches(pattern, branch)));
+        any(&self.include).unwrap_or(true) && !any(&self.exclude).unwrap_or(false)
+    }
+}
+
+fn matches(patterns: &Option<Patterns>, branch: &str) -> bool {
+    patterns.as_ref().map_or(true, |patterns| patterns.matches(branch))
+}
+
+impl BranchRoute {
+    pub fn matches(&self, pr: &::PullRequest) -> bool {
+        matches(&self.source, &pr.branch_name()) && matches(&self.target, &pr.target_branch_name())
+    }
+}
+
+impl BranchFilters {
+    // Why the pull request is not built, if it is not
+    pub fn skipped(&self, pr: &::PullRequest) -> Option<String> {
+        if !matches(&self.source, &pr.branch_name()) {
+            Some(format!("Source branch {} is not built", pr.branch_name()))
+        } else if !matches(&self.target, &pr.target_branch_name()) {
+            Some(format!("Target branch {} is not built", pr.target_branch_name()))
+        } else {
+            None
+        }
+    }
+
+    // The builders, by their index in `names`, of the first route the pull request matches. Every builder otherwise.
+    pub fn route(&self, pr: &::PullRequest, names: &[Option<&str>]) -> Result<Vec<usize>, ConfigError> {
+        let route = match self.routes.as_ref().and_then(|routes| routes.iter().find(|route| route.matches(pr))) {
+            Some(route) => route,
+            None => return Ok((0..names.len()).collect())
+        };
+        let mut indices = vec![];
+        for builder in &route.builders {
+            match components::position(names, builder, "branch routing") {
+                Ok(index) => indices.push(index),
+                Err(err) => return Err(err)
+            }
+        }
+        Ok(indices)
+    }
+}
+
+impl Stage for BranchFilters {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        if let Some(reason) = self.skipped(context.pr) {
+            return Ok(Outcome::Skip(reason));
+        }
+        if self.routes.is_none() {
+            return Ok(Outcome::Continue);
+        }
+        let names: Vec<_> = context.matrix.iter().map(|ci| ci.name()).collect();
+        let matrix = match self.route(context.pr, &names) {
+            Ok(indices) => indices.into_iter().map(|index| context.matrix[index]).collect::<Vec<_>>(),
+            Err(err) => return Err(Error::from(err))
+        };
+        match matrix.is_empty() {
+            true => Ok(Outcome::Skip("Routed to no builder".to_owned())),
+            false => pipeline::build_on(context, &matrix)
+        }
+    }
+}
+
+pub struct BranchFiltersFactory;
+
+impl registry::StageFactory for BranchFiltersFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match pipeline::parse_settings::<BranchFilters>(settings) {
+            Ok(filters) => Ok(Box::new(filters)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use fanout::{Fanout, Message};
+    use i18n::Messages;
+    use pipeline::{Context, Outcome, Stage};
+    use stubs::{pull_request, StubRepository};
+    use super::{glob_matches, BranchFilters, BranchRoute, Patterns};
+
+    fn patterns(include: &[&str], exclude: &[&str]) -> Option<Patterns> {
+        let owned = |patterns: &[&str]| match patterns.is_empty() {
+            true => None,
+            false => Some(patterns.iter().map(|&pattern| pattern.to_owned()).collect())
+        };
+        Some(Patterns { include: owned(include), exclude: owned(exclude) })
+    }
+
+    fn pr(from: &str, to: &str) -> ::PullRequest {
+        ::PullRequest {
+            from_ref: format!("refs/heads/{}", from),
+            to_ref: format!("refs/heads/{}", to),
+            ..pull_request()
+        }
+    }
+
+    #[test]
+    fn globs_match_whole_branch_names() {
+        assert!(glob_matches("release/*", "release/1.2"));
+        assert!(glob_matches("release/*", "release/1.2/hotfix"));
+        assert!(glob_matches("*-wip", "feature/search-wip"));
+        assert!(glob_matches("v?.x", "v2.x"));
+        assert!(glob_matches("master", "master"));
+        assert!(!glob_matches("release/*", "releases/1.2"));
+        assert!(!glob_matches("master", "master-old"));
+        assert!(!glob_matches("v?.x", "v10.x"));
+    }
+
+    #[test]
+    fn pull_requests_outside_the_patterns_are_skipped() {
+        let filters = BranchFilters {
+            source: patterns(&[], &["dependabot/*"]),
+            target: patterns(&["master", "release/*"], &["release/legacy*"]),
+            routes: None
+        };
+        assert_eq!(None, filters.skipped(&pr("feature/search", "release/2.0")));
+        assert_eq!(Some("Target branch develop is not built".to_owned()),
+            filters.skipped(&pr("feature/search", "develop")));
+        assert!(filters.skipped(&pr("feature/search", "release/legacy-1")).is_some());
+        assert_eq!(Some("Source branch dependabot/npm/lodash is not built".to_owned()),
+            filters.skipped(&pr("dependabot/npm/lodash", "master")));
+    }
+
+    #[test]
+    fn pull_requests_are_routed_by_the_first_matching_route() {
+        let filters = BranchFilters {
+            source: None,
+            target: None,
+            routes: Some(vec![
+                BranchRoute {
+                    source: None,
+                    target: patterns(&["release/*"], &[]),
+                    builders: vec!["Release".to_owned()]
+                },
+                BranchRoute {
+                    source: patterns(&["docs/*"], &[]),
+                    target: None,
+                    builders: vec!["Docs".to_owned()]
+                }
+            ])
+        };
+        let names = [Some("Build"), Some("Release"), Some("Docs")];
+        assert_eq!(vec![1], filters.route(&pr("docs/typo", "release/2.0"), &names).unwrap());
+        assert_eq!(vec![2], filters.route(&pr("docs/typo", "master"), &names).unwrap());
+        assert_eq!(vec![0, 1, 2], filters.route(&pr("feature/search", "master"), &names).unwrap());
+        assert!(filters.route(&pr("docs/typo", "master"), &[Some("Build")]).is_err());
+    }
+
+    #[test]
+    fn pull_requests_routed_to_no_builder_are_skipped() {
+        let filters = BranchFilters {
+            source: None,
+            target: None,
+            routes: Some(vec![BranchRoute { source: None, target: patterns(&["docs"], &[]), builders: vec![] }])
+        };
+        let repository = StubRepository {
+            pr_list: Ok(vec![]),
+            queued: Ok(()),
+            running: Ok(()),
+            success: Ok(()),
+            failure: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+        let config = ::parse_config(r#"{ "run_interval": 0 }"#).unwrap();
+        let outcome = filters.run(&Context {
+            pr: &pr("feature/readme", "docs"),
+            repository: &repository,
+            matrix: &vec![],
+            config: &config,
+            messages: &Messages::new(&None, &None),
+            fanout: &Fanout::<Message>::new()
+        });
+        assert_eq!(Some(Outcome::Skip("Routed to no builder".to_owned())), outcome.ok());
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use ::credentials;
+use ::errors::{ApiError, BuilderError, ConfigError};
+use ::registry;
+use ::rest;
+use hyper;
+use serde_json;
+use time;
+use url::form_urlencoded;
+
+static DEFAULT_API_URL: &'static str = "https://api.buildkite.com/v2";
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct BuildkiteCredentials {
+    // An API access token with the read_builds and write_builds scopes, unless `credential_provider` is set
+    #[serde(default)]
+    pub token: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    pub api_url: Option<String>,
+    pub organization: String,
+    // The slug of the pipeline, and of any further pipelines in `matrix`
+    pub pipeline: String,
+    pub matrix: Option<Vec<String>>,
+    // Environment variables every build is created with
+    pub env: Option<BTreeMap<String, String>>
+}
+
+pub struct BuildkiteFactory;
+
+impl registry::BuilderFactory for BuildkiteFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+        match serde_json::from_value::<BuildkiteCredentials>(settings.to_owned()) {
+            Ok(credentials) => Ok(credentials.pipelines().into_iter()
+                .map(|pipeline| Box::new(pipeline) as Box<::ContinuousIntegrator>)
+                .collect()),
+            Err(err) => Err(ConfigError::Parse(err))
+        }
+    }
+}
+
+// Buildkite reports times in UTC such as "2015-05-09T21:05:59.874Z", turned into seconds
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    match timestamp.get(..19).map(|seconds| time::strptime(seconds, "%Y-%m-%dT%H:%M:%S")) {
+        Some(Ok(tm)) => Some(tm.to_timespec().sec),
+        _ => None
+    }
+}
+
+// Builds and their jobs in states of their own, blocked ones waiting for someone to unblock them
+fn build_state(state: &str) -> (::BuildState, ::BuildStatus) {
+    match state {
+        "passed" => (::BuildState::Finished, ::BuildStatus::Success),
+        "failed" | "canceled" | "not_run" | "timed_out" | "broken" | "expired" | "waiting_failed"
+            | "blocked_failed" | "unblocked_failed" => (::BuildState::Finished, ::BuildStatus::Failure),
+        "skipped" => (::BuildState::Finished, ::BuildStatus::Unknown),
+        "running" | "failing" | "canceling" | "timing_out" | "blocked" | "assigned" | "accepted" => {
+            (::BuildState::Running, ::BuildStatus::Unknown)
+        },
+        _ => (::BuildState::Queued, ::BuildStatus::Unknown)
+    }
+}
+
+impl BuildkiteCredentials {
+    pub fn pipelines(&self) -> Vec<BuildkiteCredentials> {
+        let mut pipelines = vec![self.pipeline.to_owned()];
+        if let Some(ref matrix) = self.matrix {
+            for pipeline in matrix {
+                if !pipelines.contains(pipeline) {
+                    pipelines.push(pipeline.to_owned());
+                }
+            }
+        }
+
+        pipelines.into_iter().map(|pipeline| {
+            BuildkiteCredentials {
+                pipeline: pipeline,
+                matrix: None,
+                ..self.clone()
+            }
+        }).collect()
+    }
+
+    fn builds_url(&self) -> String {
+        format!("{}/organizations/{}/pipelines/{}/builds",
+            self.api_url.as_ref().map_or(DEFAULT_API_URL, String::as_str).trim_end_matches('/'),
+            self.organization, self.pipeline)
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credential_provider, credentials::Credential::Bearer(self.token.to_owned()))
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Build {
+    pub id: String,
+    pub number: i32,
+    pub state: String,
+    pub web_url: String,
+    pub commit: String,
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub jobs: Vec<Job>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Job {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: Option<String>,
+    pub state: Option<String>,
+    pub web_url: Option<String>
+}
+
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct BuildRequest {
+    pub commit: String,
+    pub branch: String,
+    pub message: String,
+    pub env: BTreeMap<String, String>
+}
+
+impl Build {
+    // Builds of `HEAD` only tell which commit they build once an agent has checked it out, and count as queued
+    // until then. Failed builds link to their first failed job.
+    fn to_build_details(&self, pipeline: &str) -> ::BuildDetails {
+        let commit = match self.commit.len() == 40 && self.commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => Some(self.commit.to_owned()),
+            false => None
+        };
+        let (state, status) = build_state(&self.state);
+        let state = match (state, &commit) {
+            (::BuildState::Running, &None) => ::BuildState::Queued,
+            (state, _) => state
+        };
+        let parts: Vec<::BuildPart> = self.jobs.iter()
+            .filter(|job| job.kind == "script" || job.kind == "trigger")
+            .map(|job| {
+                let (state, status) = build_state(job.state.as_ref().map_or("", String::as_str));
+                ::BuildPart {
+                    name: job.name.to_owned().unwrap_or(job.id.to_owned()),
+                    web_url: job.web_url.to_owned().unwrap_or(self.web_url.to_owned()),
+                    state: state,
+                    status: status
+                }
+            })
+            .collect();
+        let web_url = match status {
+            ::BuildStatus::Failure => parts.iter()
+                .find(|part| part.status == ::BuildStatus::Failure)
+                .map_or(self.web_url.to_owned(), |part| part.web_url.to_owned()),
+            _ => self.web_url.to_owned()
+        };
+        let queue_wait = match state {
+            ::BuildState::Queued => self.created_at.as_ref()
+                .and_then(|created_at| parse_timestamp(created_at))
+                .map(|created_at| time::now_utc().to_timespec().sec - created_at),
+            _ => None
+        };
+        ::BuildDetails {
+            id: self.number,
+            build_id: pipeline.to_owned(),
+            web_url: web_url,
+            commit: commit,
+            state: state,
+            status: status,
+            status_text: Some(self.state.to_owned()),
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: queue_wait,
+            parts: parts,
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+}
+
+impl ::ContinuousIntegrator for BuildkiteCredentials {
+    fn name(&self) -> Option<&str> {
+        Some(&self.pipeline)
+    }
+
+    fn get_build_list(&self, branch: &str) -> Result<Vec<::Build>, BuilderError> {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("branch", branch)
+            .append_pair("per_page", "20")
+            .finish();
+        let url = format!("{}?{}", self.builds_url(), query);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Vec<Build>>(&url, &headers.headers)
+        });
+        match response {
+            Ok(builds) => Ok(builds.iter().map(|build| ::Build { id: build.number }).collect()),
+            Err(err) => Err(BuilderError::api("getting list of builds", err))
+        }
+    }
+
+    fn get_build(&self, build_id: i32) -> Result<::BuildDetails, BuilderError> {
+        let url = format!("{}/{}", self.builds_url(), build_id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Build>(&url, &headers.headers)
+        });
+        match response {
+            Ok(build) => Ok(build.to_build_details(&self.pipeline)),
+            Err(err) => Err(BuilderError::api("getting build", err))
+        }
+    }
+
+    fn queue_build(&self, branch: &str) -> Result<::BuildDetails, BuilderError> {
+        let body = serde_json::to_string(&BuildRequest {
+            commit: "HEAD".to_owned(),
+            branch: branch.to_owned(),
+            message: "Triggered by PR Demon".to_owned(),
+            env: self.env.to_owned().unwrap_or_default()
+        }).unwrap();
+        let url = self.builds_url();
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Build>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match response {
+            Ok(build) => Ok(build.to_build_details(&self.pipeline)),
+            Err(err) => Err(BuilderError::api("queuing build", err))
+        }
+    }
+
+    fn get_personal_build_list(&self, _: &str) -> Result<Vec<::Build>, BuilderError> {
+        Ok(vec![])
+    }
+
+    fn queue_personal_build(&self, _: &str, _: &str) -> Result<::BuildDetails, BuilderError> {
+        Err(BuilderError::InvalidResponse("Buildkite does not support personal builds".to_owned()))
+    }
+
+    // Buildkite builds cannot be tagged, pinned or commented on once created
+    fn tag_build(&self, _: &::BuildDetails, _: &Vec<String>) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn pin_build(&self, _: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn comment_build(&self, _: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn cancel_build(&self, build: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        let url = format!("{}/{}/cancel", self.builds_url(), build.id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::put_raw(&url, "", &headers.headers)
+        });
+        match response {
+            Ok(response) => {
+                match response.status {
+                    status if status.is_success() => Ok(()),
+                    e @ _ => Err(BuilderError::api("cancelling build", ApiError::Status(e)))
+                }
+            },
+            Err(err) => Err(BuilderError::api("cancelling build", err))
+        }
+    }
+
+    fn get_coverage(&self, _: &::BuildDetails, _: &str) -> Result<Option<::Coverage>, BuilderError> {
+        Ok(None)
+    }
+}
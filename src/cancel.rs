@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct CancelConfig {
+    // Cancels the unfinished build of a commit a pull request has moved on from, true unless set
+    pub superseded: Option<bool>,
+    // Cancels the unfinished builds of pull requests that were merged or declined, true unless set
+    pub closed: Option<bool>
+}
+
+lazy_static! {
+    static ref CONFIG: Mutex<Option<CancelConfig>> = Mutex::new(None);
+    // The pull requests open as of the last poll, by the watch they belong to
+    static ref OPEN: Mutex<HashMap<String, Vec<::PullRequest>>> = Mutex::new(HashMap::new());
+}
+
+pub fn enable(config: &CancelConfig) {
+    *CONFIG.lock().expect("Cancellation settings should not be poisoned") = Some(config.to_owned());
+}
+
+fn enabled<F: Fn(&CancelConfig) -> Option<bool>>(setting: F) -> bool {
+    CONFIG.lock().expect("Cancellation settings should not be poisoned").as_ref()
+        .map_or(false, |config| setting(config) != Some(false))
+}
+
+// Cancels `build` of a commit other than the one `pr` is at, unless it has finished
+pub fn superseded(pr: &::PullRequest, ci: &::ContinuousIntegrator, build: &::BuildDetails) {
+    if build.state == ::BuildState::Finished || !enabled(|config| config.superseded) {
+        return;
+    }
+    let comment = format!("Superseded by commit {} of Pull Request #{}", pr.from_commit, pr.id);
+    match ci.cancel_build(build, &comment) {
+        Ok(_) => println!("Cancelled superseded build {}", build.web_url),
+        Err(err) => println!("Error cancelling superseded build {}: {}", build.web_url, err)
+    }
+}
+
+// The pull requests open as of the previous call for the same repository that no longer are, none on the first call
+pub fn closed(open: &mut HashMap<String, Vec<::PullRequest>>, repository: Option<&str>, pull_requests: &[::PullRequest])
+        -> Vec<::PullRequest> {
+    let previous = open.insert(repository.unwrap_or("").to_owned(), pull_requests.to_vec()).unwrap_or(vec![]);
+    previous.into_iter().filter(|closed| pull_requests.iter().all(|pr| pr.id != closed.id)).collect()
+}
+
+// Cancels the unfinished builds of the pull requests of the repository that have been closed since the last poll
+pub fn cancel_closed<F>(repository: Option<&str>, pull_requests: &[::PullRequest],
+        matrix: &Vec<&::ContinuousIntegrator>, owns: F) where F: Fn(&::PullRequest) -> bool {
+    if !enabled(|config| config.closed) {
+        return;
+    }
+    let gone = closed(&mut OPEN.lock().expect("Open pull requests should not be poisoned"), repository,
+        pull_requests);
+    for pr in gone.iter().filter(|pr| owns(pr)) {
+        println!("Pull Request #{} was closed", pr.id);
+        let comment = format!("Pull Request #{} was closed", pr.id);
+        for ci in matrix {
+            let build = match ::get_latest_build(pr, *ci) {
+                Some(ref build) if build.state != ::BuildState::Finished => build.to_owned(),
+                _ => continue
+            };
+            match ci.cancel_build(&build, &comment) {
+                Ok(_) => println!("Cancelled build {}", build.web_url),
+                Err(err) => println!("Error cancelling build {}: {}", build.web_url, err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use stubs::pull_request;
+    use super::closed;
+
+    fn pr(id: i32) -> ::PullRequest {
+        ::PullRequest { id: id, ..pull_request() }
+    }
+
+    #[test]
+    fn pull_requests_gone_since_the_last_poll_are_closed() {
+        let mut open = HashMap::new();
+        assert!(closed(&mut open, None, &[pr(1), pr(2), pr(3)]).is_empty());
+        let gone = closed(&mut open, None, &[pr(1), pr(4)]);
+        assert_eq!(vec![2, 3], gone.iter().map(|pr| pr.id).collect::<Vec<_>>());
+        assert!(closed(&mut open, None, &[pr(1), pr(4)]).is_empty());
+    }
+
+    #[test]
+    fn repositories_are_told_apart() {
+        let mut open = HashMap::new();
+        assert!(closed(&mut open, Some("frontend"), &[pr(1)]).is_empty());
+        assert!(closed(&mut open, Some("backend"), &[]).is_empty());
+        assert_eq!(1, closed(&mut open, Some("frontend"), &[]).len());
+    }
+}
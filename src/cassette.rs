@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use serde_json;
+
+use ::errors::{ApiError, ConfigError};
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    Record,
+    Replay
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    pub path: String
+}
+
+// A request and the response it got. Only the response headers that paging follows are recorded, so cassettes
+// never contain credentials.
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    pub body: Option<String>,
+    pub status: u16,
+    pub response: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>
+}
+
+pub struct Cassette {
+    config: CassetteConfig,
+    interactions: Vec<Interaction>,
+    played: Vec<bool>
+}
+
+lazy_static! {
+    static ref CASSETTE: Mutex<Option<Cassette>> = Mutex::new(None);
+}
+
+impl Cassette {
+    pub fn load(config: &CassetteConfig) -> Result<Cassette, ConfigError> {
+        let interactions = match config.mode {
+            CassetteMode::Record => vec![],
+            CassetteMode::Replay => {
+                let mut json = String::new();
+                if let Err(err) = File::open(&config.path).and_then(|mut file| file.read_to_string(&mut json)) {
+                    return Err(ConfigError::Read(err));
+                }
+                match serde_json::from_str::<Vec<Interaction>>(&json) {
+                    Ok(interactions) => interactions,
+                    Err(err) => return Err(ConfigError::Parse(err))
+                }
+            }
+        };
+
+        Ok(Cassette {
+            config: config.to_owned(),
+            played: vec![false; interactions.len()],
+            interactions: interactions
+        })
+    }
+
+    // Interactions are replayed in the order they were recorded, each at most once
+    pub fn replay(&mut self, method: &str, url: &str, body: &Option<String>) -> Option<Interaction> {
+        let index = match self.interactions.iter().enumerate()
+                .position(|(index, interaction)| !self.played[index] && interaction.method == method
+                    && interaction.url == url && &interaction.body == body) {
+            Some(index) => index,
+            None => return None
+        };
+        self.played[index] = true;
+        Some(self.interactions[index].to_owned())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.played.iter().all(|&played| played)
+    }
+
+    pub fn record(&mut self, interaction: Interaction) -> Result<(), ApiError> {
+        self.interactions.push(interaction);
+        self.played.push(true);
+
+        // The whole cassette is rewritten so that it is usable even if the daemon is killed while recording
+        let json = serde_json::to_string_pretty(&self.interactions).expect("Interactions should be serializable");
+        match File::create(&self.config.path).and_then(|mut file| file.write_all(json.as_bytes())) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(ApiError::Io(err))
+        }
+    }
+}
+
+// Every request made through `rest` is recorded to or replayed from the inserted cassette
+pub fn insert(cassette: Cassette) {
+    *CASSETTE.lock().expect("Cassette lock should not be poisoned") = Some(cassette);
+}
+
+pub fn eject() -> Option<Cassette> {
+    CASSETTE.lock().expect("Cassette lock should not be poisoned").take()
+}
+
+// `None` when requests should go out over the network
+pub fn replay(method: &str, url: &str, body: &Option<String>) -> Option<Result<Interaction, ApiError>> {
+    let mut cassette = CASSETTE.lock().expect("Cassette lock should not be poisoned");
+    match *cassette {
+        Some(ref mut cassette) if cassette.config.mode == CassetteMode::Replay => {
+            match cassette.replay(method, url, body) {
+                Some(interaction) => Some(Ok(interaction)),
+                None => Some(Err(ApiError::Remote(format!("No recorded response for {} {}", method, url))))
+            }
+        },
+        _ => None
+    }
+}
+
+pub fn record(interaction: Interaction) -> Result<(), ApiError> {
+    let mut cassette = CASSETTE.lock().expect("Cassette lock should not be poisoned");
+    match *cassette {
+        Some(ref mut cassette) if cassette.config.mode == CassetteMode::Record => cassette.record(interaction),
+        _ => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use super::{Cassette, CassetteConfig, CassetteMode, Interaction};
+
+    fn interaction(url: &str, response: &str) -> Interaction {
+        Interaction {
+            method: "GET".to_owned(),
+            url: url.to_owned(),
+            body: None,
+            status: 200,
+            response: response.to_owned(),
+            headers: BTreeMap::new()
+        }
+    }
+
+    fn config(mode: CassetteMode, name: &str) -> CassetteConfig {
+        CassetteConfig {
+            mode: mode,
+            path: env::temp_dir().join(name).to_string_lossy().into_owned()
+        }
+    }
+
+    #[test]
+    fn recorded_interactions_are_replayed_in_order() {
+        let mut recorder = Cassette::load(&config(CassetteMode::Record, "pr_demon_cassette_order.json")).unwrap();
+        recorder.record(interaction("http://example.com/builds", "[1]")).unwrap();
+        recorder.record(interaction("http://example.com/builds", "[1, 2]")).unwrap();
+
+        let mut player = Cassette::load(&config(CassetteMode::Replay, "pr_demon_cassette_order.json")).unwrap();
+        assert_eq!(Some("[1]".to_owned()),
+            player.replay("GET", "http://example.com/builds", &None).map(|played| played.response));
+        assert_eq!(Some("[1, 2]".to_owned()),
+            player.replay("GET", "http://example.com/builds", &None).map(|played| played.response));
+        assert_eq!(None, player.replay("GET", "http://example.com/builds", &None));
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn requests_are_matched_on_method_url_and_body() {
+        let mut recorder = Cassette::load(&config(CassetteMode::Record, "pr_demon_cassette_match.json")).unwrap();
+        recorder.record(interaction("http://example.com/builds", "[]")).unwrap();
+
+        let mut player = Cassette::load(&config(CassetteMode::Replay, "pr_demon_cassette_match.json")).unwrap();
+        assert_eq!(None, player.replay("POST", "http://example.com/builds", &None));
+        assert_eq!(None, player.replay("GET", "http://example.com/builds", &Some("body".to_owned())));
+        assert_eq!(None, player.replay("GET", "http://example.com/pull-requests", &None));
+        assert!(player.replay("GET", "http://example.com/builds", &None).is_some());
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Mutex;
+use time;
+
+use ::errors::ConfigError;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    // The request fails as if it had timed out, without being sent
+    Timeout,
+    // The request gets a 500 response without being sent
+    ServerError,
+    // The request is sent, but its response body is cut in half
+    MalformedJson
+}
+
+static ALL: &'static [Fault] = &[Fault::Timeout, Fault::ServerError, Fault::MalformedJson];
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct ChaosConfig {
+    // The share of outgoing requests that fail, from 0 to 1
+    pub rate: f64,
+    // The faults picked from, all of them unless set
+    pub faults: Option<Vec<Fault>>,
+    // Makes the same requests fail from run to run. Seeded from the clock unless set.
+    pub seed: Option<u64>
+}
+
+// Picks the requests to fail and how, with a xorshift generator so that no dependency is needed for it
+pub struct Chaos {
+    rate: f64,
+    faults: Vec<Fault>,
+    state: u64
+}
+
+lazy_static! {
+    static ref CHAOS: Mutex<Option<Chaos>> = Mutex::new(None);
+}
+
+impl Chaos {
+    pub fn new(config: &ChaosConfig) -> Result<Chaos, ConfigError> {
+        if !(0.0..=1.0).contains(&config.rate) {
+            return Err(ConfigError::Invalid(format!("The chaos rate must be between 0 and 1, not {}", config.rate)));
+        }
+        let faults = config.faults.to_owned().unwrap_or_else(|| ALL.to_vec());
+        if faults.is_empty() {
+            return Err(ConfigError::Invalid("Chaos mode needs at least one fault".to_owned()));
+        }
+        let seed = config.seed.unwrap_or_else(time::precise_time_ns);
+        Ok(Chaos {
+            rate: config.rate,
+            faults: faults,
+            // A zero state would never change
+            state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed }
+        })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn pick(&mut self) -> Option<Fault> {
+        let chance = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        if chance >= self.rate {
+            return None;
+        }
+        let index = (self.next() % self.faults.len() as u64) as usize;
+        Some(self.faults[index])
+    }
+}
+
+// Every request made through `rest` may fail while chaos mode is enabled
+pub fn enable(chaos: Chaos) {
+    *CHAOS.lock().expect("Chaos lock should not be poisoned") = Some(chaos);
+}
+
+pub fn disable() -> Option<Chaos> {
+    CHAOS.lock().expect("Chaos lock should not be poisoned").take()
+}
+
+// The fault to inject into the next request, if any
+pub fn fault() -> Option<Fault> {
+    CHAOS.lock().expect("Chaos lock should not be poisoned").as_mut().and_then(Chaos::pick)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chaos, ChaosConfig, Fault};
+
+    fn config(rate: f64, faults: Option<Vec<Fault>>) -> ChaosConfig {
+        ChaosConfig { rate: rate, faults: faults, seed: Some(42) }
+    }
+
+    fn picks(config: &ChaosConfig) -> Vec<Option<Fault>> {
+        let mut chaos = Chaos::new(config).unwrap();
+        (0..1000).map(|_| chaos.pick()).collect()
+    }
+
+    #[test]
+    fn faults_are_injected_at_the_configured_rate() {
+        assert!(picks(&config(0.0, None)).iter().all(Option::is_none));
+        assert!(picks(&config(1.0, None)).iter().all(Option::is_some));
+
+        let picked = picks(&config(0.2, None));
+        let failed = picked.iter().filter(|fault| fault.is_some()).count();
+        assert!(failed > 150 && failed < 250, "{} of 1000 requests failed", failed);
+        for fault in &[Fault::Timeout, Fault::ServerError, Fault::MalformedJson] {
+            assert!(picked.contains(&Some(*fault)));
+        }
+    }
+
+    #[test]
+    fn seeded_faults_repeat() {
+        let config = config(0.5, Some(vec![Fault::ServerError]));
+        assert_eq!(picks(&config), picks(&config));
+        assert!(picks(&config).iter().all(|fault| fault.map_or(true, |fault| fault == Fault::ServerError)));
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(Chaos::new(&config(1.5, None)).is_err());
+        assert!(Chaos::new(&config(-0.1, None)).is_err());
+        assert!(Chaos::new(&config(0.5, Some(vec![]))).is_err());
+    }
+}
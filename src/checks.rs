@@ -0,0 +1,598 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use regex::{self, Regex};
+use serde_json;
+
+use ::components;
+use ::errors::{ConfigError, Error};
+use ::i18n::Messages;
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+use ::size;
+use ::templates::CommentKey;
+
+// Types allowed by the Conventional Commits specification and its common extensions
+static CONVENTIONAL_TYPES: &'static [&'static str] = &[
+    "build", "chore", "ci", "docs", "feat", "fix", "perf", "refactor", "revert", "style", "test"
+];
+
+// How a check's result is reported: as a build status next to the builds, as a comment listing its violations, or both
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Report {
+    Status,
+    Comment,
+    Both
+}
+
+lazy_static! {
+    // Results by check, pull request and revision, so that each revision is checked and reported once
+    static ref RESULTS: Mutex<HashMap<(String, i32, String), ::CheckResult>> = Mutex::new(HashMap::new());
+}
+
+// Runs `check` unless it has already run for `revision` of the pull request, and skips the pull request if it fails
+pub fn run_check<F>(context: &Context, name: &str, revision: &str, report: Report, check: F) -> Result<Outcome, Error>
+        where F: Fn(&Context) -> Result<::CheckResult, Error> {
+    let key = (name.to_owned(), context.pr.id, revision.to_owned());
+    let (known, commented_before) = {
+        let results = RESULTS.lock().expect("Check results should not be poisoned");
+        let commented_before = results.iter()
+            .any(|(&(ref check, pr, _), result)| check == name && pr == context.pr.id && !result.violations.is_empty());
+        (results.get(&key).cloned(), commented_before)
+    };
+    let result = match known {
+        Some(result) => result,
+        None => {
+            let result = match check(context) {
+                Ok(result) => result,
+                Err(err) => return Err(err)
+            };
+            if let Err(err) = report_result(context, &result, report, commented_before) {
+                return Err(err);
+            }
+            RESULTS.lock().expect("Check results should not be poisoned").insert(key, result.to_owned());
+            result
+        }
+    };
+
+    match result.passed {
+        true => Ok(Outcome::Continue),
+        false => Ok(Outcome::Skip(result.summary))
+    }
+}
+
+// Comments are only posted for violations, and for the first result without any after them, so that fixed problems
+// are acknowledged. Violations of a passed check are warnings.
+fn report_result(context: &Context, result: &::CheckResult, report: Report, commented_before: bool)
+        -> Result<(), Error> {
+    println!("{}: {}", result.name, result.summary);
+    if report != Report::Comment {
+        if let Err(err) = context.repository.report_check(context.pr, result) {
+            return Err(Error::from(err));
+        }
+    }
+    if report == Report::Status {
+        return Ok(());
+    }
+
+    let key = CommentKey::check(&result.name, context.pr);
+    let labels = context.messages.labels();
+    let reported = match result.violations.is_empty() {
+        false => {
+            let violations = result.violations.iter()
+                .map(|violation| format!("- {}", violation))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let text = context.messages.format("check.comment", &[
+                ("marker", if result.passed { labels.warning() } else { labels.failure() }),
+                ("summary", &result.summary),
+                ("violations", &violations)
+            ]);
+            context.repository.upsert_comment(context.pr, &key, &text)
+        },
+        true if commented_before => {
+            let text = context.messages.format("check.comment.passed", &[
+                ("marker", labels.success()),
+                ("summary", &result.summary)
+            ]);
+            context.repository.upsert_comment(context.pr, &key, &text)
+        },
+        true => Ok(())
+    };
+    reported.map_err(Error::from)
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct CommitMessageRules {
+    // Subjects must read `type(scope): description`, with one of `types` or the Conventional Commits types
+    pub conventional: Option<bool>,
+    pub types: Option<Vec<String>>,
+    // A regular expression each message must match, such as "[A-Z]+-[0-9]+" for a Jira issue key
+    pub ticket_pattern: Option<String>,
+    pub max_subject_length: Option<usize>,
+    // `status` unless configured
+    pub report: Option<Report>
+}
+
+// Checks the messages of the pull request's commits, other than merges, before it is built
+pub struct CommitMessageCheck {
+    rules: CommitMessageRules,
+    conventional: Option<Regex>,
+    ticket: Option<Regex>
+}
+
+impl CommitMessageCheck {
+    pub fn new(rules: &CommitMessageRules) -> Result<CommitMessageCheck, ConfigError> {
+        let conventional = match rules.conventional {
+            Some(true) => {
+                let types = match rules.types {
+                    Some(ref types) => types.iter().map(|kind| regex::escape(kind)).collect::<Vec<_>>(),
+                    None => CONVENTIONAL_TYPES.iter().map(|kind| regex::escape(kind)).collect()
+                };
+                Some(Regex::new(&format!(r"^(?:{})(?:\([^()]+\))?!?: \S", types.join("|")))
+                    .expect("Conventional Commits pattern should be valid"))
+            },
+            _ => None
+        };
+        let ticket = match rules.ticket_pattern {
+            Some(ref pattern) => match Regex::new(pattern) {
+                Ok(ticket) => Some(ticket),
+                Err(err) => return Err(ConfigError::Invalid(format!("Invalid ticket_pattern {}: {}", pattern, err)))
+            },
+            None => None
+        };
+        Ok(CommitMessageCheck {
+            rules: rules.to_owned(),
+            conventional: conventional,
+            ticket: ticket
+        })
+    }
+
+    pub fn violations(&self, commit: &::Commit, messages: &Messages) -> Vec<String> {
+        let id = commit.id.chars().take(11).collect::<String>();
+        let subject = commit.message.lines().next().unwrap_or("").trim_end();
+        let mut violations = vec![];
+
+        if let Some(ref conventional) = self.conventional {
+            if !conventional.is_match(subject) {
+                violations.push(messages.format("check.commit_messages.conventional",
+                    &[("commit", &id), ("subject", subject)]));
+            }
+        }
+        if let Some(ref ticket) = self.ticket {
+            if !ticket.is_match(&commit.message) {
+                violations.push(messages.format("check.commit_messages.ticket",
+                    &[("commit", &id), ("pattern", ticket.as_str())]));
+            }
+        }
+        if let Some(max) = self.rules.max_subject_length {
+            let length = subject.chars().count();
+            if length > max {
+                violations.push(messages.format("check.commit_messages.length",
+                    &[("commit", &id), ("length", &length.to_string()), ("max", &max.to_string())]));
+            }
+        }
+        violations
+    }
+
+    fn check(&self, context: &Context) -> Result<::CheckResult, Error> {
+        let commits = match context.repository.get_pr_commits(context.pr) {
+            Ok(commits) => commits,
+            Err(err) => return Err(Error::from(err))
+        };
+        let violations = commits.iter()
+            .filter(|commit| !commit.merge)
+            .flat_map(|commit| self.violations(commit, context.messages))
+            .collect::<Vec<_>>();
+        let summary = match violations.is_empty() {
+            true => context.messages.get("check.commit_messages.passed").to_owned(),
+            false => context.messages.get("check.commit_messages.failed").to_owned()
+        };
+        Ok(::CheckResult {
+            name: "commit_messages".to_owned(),
+            passed: violations.is_empty(),
+            summary: summary,
+            violations: violations
+        })
+    }
+}
+
+impl Stage for CommitMessageCheck {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let report = self.rules.report.unwrap_or(Report::Status);
+        run_check(context, "commit_messages", &context.pr.from_commit, report, |context| self.check(context))
+    }
+}
+
+pub struct CommitMessageCheckFactory;
+
+impl registry::StageFactory for CommitMessageCheckFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        let rules = match pipeline::parse_settings::<CommitMessageRules>(settings) {
+            Ok(rules) => rules,
+            Err(err) => return Err(err)
+        };
+        match CommitMessageCheck::new(&rules) {
+            Ok(check) => Ok(Box::new(check)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct PullRequestRules {
+    // A regular expression the title must match, such as "^\\[[A-Z]+-[0-9]+\\] "
+    pub title_pattern: Option<String>,
+    pub require_description: Option<bool>,
+    // Markdown headings the description must have, each followed by some text, such as "Testing"
+    pub required_sections: Option<Vec<String>>,
+    // `status` unless configured
+    pub report: Option<Report>
+}
+
+// Checks the title and description of the pull request, again whenever either is edited
+pub struct PullRequestPolicy {
+    rules: PullRequestRules,
+    title: Option<Regex>
+}
+
+// The text under the markdown heading named `name`, up to the next heading, or None without such a heading
+pub fn section(description: &str, name: &str) -> Option<String> {
+    let heading = |line: &str| match line.trim_start().starts_with('#') {
+        true => Some(line.trim().trim_start_matches('#').trim().to_lowercase()),
+        false => None
+    };
+    let name = name.trim().to_lowercase();
+    let mut lines = description.lines().skip_while(|line| heading(line).as_ref() != Some(&name));
+    match lines.next() {
+        Some(_) => Some(lines.take_while(|line| heading(line).is_none()).collect::<Vec<_>>().join("\n")),
+        None => None
+    }
+}
+
+impl PullRequestPolicy {
+    pub fn new(rules: &PullRequestRules) -> Result<PullRequestPolicy, ConfigError> {
+        let title = match rules.title_pattern {
+            Some(ref pattern) => match Regex::new(pattern) {
+                Ok(title) => Some(title),
+                Err(err) => return Err(ConfigError::Invalid(format!("Invalid title_pattern {}: {}", pattern, err)))
+            },
+            None => None
+        };
+        Ok(PullRequestPolicy {
+            rules: rules.to_owned(),
+            title: title
+        })
+    }
+
+    pub fn violations(&self, pr: &::PullRequest, messages: &Messages) -> Vec<String> {
+        let mut violations = vec![];
+        if let Some(ref title) = self.title {
+            if !title.is_match(&pr.title) {
+                violations.push(messages.format("check.policy.title", &[("pattern", title.as_str())]));
+            }
+        }
+        if self.rules.require_description == Some(true) && pr.description.trim().is_empty() {
+            violations.push(messages.get("check.policy.description").to_owned());
+        }
+        for name in self.rules.required_sections.iter().flat_map(|sections| sections.iter()) {
+            match section(&pr.description, name) {
+                None => violations.push(messages.format("check.policy.missing_section", &[("section", name)])),
+                Some(ref text) if text.trim().is_empty() => {
+                    violations.push(messages.format("check.policy.empty_section", &[("section", name)]))
+                },
+                Some(_) => {}
+            }
+        }
+        violations
+    }
+
+    fn check(&self, context: &Context) -> Result<::CheckResult, Error> {
+        let violations = self.violations(context.pr, context.messages);
+        let summary = match violations.is_empty() {
+            true => context.messages.get("check.policy.passed").to_owned(),
+            false => context.messages.get("check.policy.failed").to_owned()
+        };
+        Ok(::CheckResult {
+            name: "policy".to_owned(),
+            passed: violations.is_empty(),
+            summary: summary,
+            violations: violations
+        })
+    }
+}
+
+impl Stage for PullRequestPolicy {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        // Edits to the title or description do not change the commit, so they are part of what is checked
+        let revision = format!("{}\n{}\n{}", context.pr.from_commit, context.pr.title, context.pr.description);
+        let report = self.rules.report.unwrap_or(Report::Status);
+        run_check(context, "policy", &revision, report, |context| self.check(context))
+    }
+}
+
+pub struct PullRequestPolicyFactory;
+
+impl registry::StageFactory for PullRequestPolicyFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        let rules = match pipeline::parse_settings::<PullRequestRules>(settings) {
+            Ok(rules) => rules,
+            Err(err) => return Err(err)
+        };
+        match PullRequestPolicy::new(&rules) {
+            Ok(policy) => Ok(Box::new(policy)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct ChangelogRules {
+    // Changing any of these files, such as "CHANGELOG.md" or "changelog.d", adds a changelog entry
+    pub paths: Vec<String>,
+    // Only changes under these paths need an entry. Any change does unless set.
+    pub sources: Option<Vec<String>>,
+    // Pull requests whose title or description contains one of these, such as "#no-changelog", need no entry
+    pub exempt: Option<Vec<String>>,
+    // `status` unless configured
+    pub report: Option<Report>
+}
+
+// Checks that pull requests changing the sources also add a changelog entry
+pub struct ChangelogCheck {
+    rules: ChangelogRules
+}
+
+impl ChangelogCheck {
+    pub fn new(rules: &ChangelogRules) -> ChangelogCheck {
+        ChangelogCheck { rules: rules.to_owned() }
+    }
+
+    // The key of the message explaining why the check passes, or None if it fails
+    pub fn evaluate(&self, pr: &::PullRequest, files: &[String]) -> Option<&'static str> {
+        let text = format!("{}\n{}", pr.title, pr.description);
+        let exempt = self.rules.exempt.iter().flat_map(|exempt| exempt.iter())
+            .any(|exempt| text.contains(exempt.as_str()));
+        let source = |file: &String| match self.rules.sources {
+            Some(ref sources) => sources.iter().any(|source| components::is_under(file, source)),
+            None => true
+        };
+        let changelog = |file: &String| self.rules.paths.iter().any(|path| components::is_under(file, path));
+
+        if exempt {
+            Some("check.changelog.exempt")
+        } else if !files.iter().any(|file| source(file) && !changelog(file)) {
+            Some("check.changelog.unneeded")
+        } else if files.iter().any(changelog) {
+            Some("check.changelog.passed")
+        } else {
+            None
+        }
+    }
+
+    fn check(&self, context: &Context) -> Result<::CheckResult, Error> {
+        let files = match size::changes(context.pr, context.repository, context.config) {
+            Ok((files, _, _)) => files,
+            Err(err) => return Err(Error::from(ConfigError::Invalid(format!("Unable to find the changes: {}", err))))
+        };
+        let passed = self.evaluate(context.pr, &files);
+        let violations = match passed {
+            Some(_) => vec![],
+            None => vec![context.messages.format("check.changelog.add", &[("paths", &self.rules.paths.join(", "))])]
+        };
+        Ok(::CheckResult {
+            name: "changelog".to_owned(),
+            passed: passed.is_some(),
+            summary: context.messages.get(passed.unwrap_or("check.changelog.missing")).to_owned(),
+            violations: violations
+        })
+    }
+}
+
+impl Stage for ChangelogCheck {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let revision = format!("{}\n{}\n{}", context.pr.from_commit, context.pr.title, context.pr.description);
+        let report = self.rules.report.unwrap_or(Report::Status);
+        run_check(context, "changelog", &revision, report, |context| self.check(context))
+    }
+}
+
+pub struct ChangelogCheckFactory;
+
+impl registry::StageFactory for ChangelogCheckFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match pipeline::parse_settings::<ChangelogRules>(settings) {
+            Ok(rules) => Ok(Box::new(ChangelogCheck::new(&rules))),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use errors::ConfigError;
+    use fanout::{Fanout, Message};
+    use i18n::Messages;
+    use pipeline::{Context, Outcome, Stage};
+    use stubs::{pull_request, StubRepository};
+    use super::{section, ChangelogCheck, ChangelogRules, CommitMessageCheck, CommitMessageRules, PullRequestPolicy,
+                PullRequestRules, Report};
+
+    fn rules() -> CommitMessageRules {
+        CommitMessageRules {
+            conventional: Some(true),
+            types: None,
+            ticket_pattern: Some("[A-Z]+-[0-9]+".to_owned()),
+            max_subject_length: Some(40),
+            report: None
+        }
+    }
+
+    fn commit(message: &str) -> ::Commit {
+        ::Commit { id: "363c1dfda4cdf5a01c2d".to_owned(), message: message.to_owned(), merge: false, author: None }
+    }
+
+    #[test]
+    fn conforming_messages_have_no_violations() {
+        let check = CommitMessageCheck::new(&rules()).unwrap();
+        let messages = Messages::new(&None, &None);
+        assert!(check.violations(&commit("feat(api)!: Add pagination\n\nRefs PD-12"), &messages).is_empty());
+    }
+
+    #[test]
+    fn every_rule_is_reported() {
+        let check = CommitMessageCheck::new(&rules()).unwrap();
+        let messages = Messages::new(&None, &None);
+        assert_eq!(vec![
+            "`363c1dfda4c` does not follow Conventional Commits: Added a very long subject line that goes on and on",
+            "`363c1dfda4c` does not reference a ticket matching `[A-Z]+-[0-9]+`",
+            "`363c1dfda4c` has a subject of 50 characters, more than 40"
+        ], check.violations(&commit("Added a very long subject line that goes on and on \n"), &messages));
+    }
+
+    #[test]
+    fn invalid_ticket_patterns_are_rejected() {
+        let rules = CommitMessageRules { ticket_pattern: Some("[".to_owned()), ..rules() };
+        match CommitMessageCheck::new(&rules) {
+            Err(ConfigError::Invalid(_)) => {},
+            _ => panic!("Expected the pattern to be rejected")
+        }
+    }
+
+    #[test]
+    fn violations_skip_the_build_and_are_reported() {
+        let repository = StubRepository {
+            pr_list: Ok(vec![]),
+            queued: Ok(()),
+            running: Ok(()),
+            success: Ok(()),
+            failure: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![commit("fix: Handle PD-1"), commit("wip"), ::Commit { merge: true, ..commit("Merge") }]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+        let check = CommitMessageCheck::new(&CommitMessageRules { report: Some(Report::Comment), ..rules() }).unwrap();
+        let config = ::parse_config(r#"{ "run_interval": 0 }"#).unwrap();
+        let pr = ::PullRequest { id: 475, ..pull_request() };
+        let context = Context {
+            pr: &pr,
+            repository: &repository,
+            matrix: &vec![],
+            config: &config,
+            messages: &Messages::new(&None, &None),
+            fanout: &Fanout::<Message>::new()
+        };
+
+        let expected = Outcome::Skip("Commit messages do not follow the conventions".to_owned());
+        assert_eq!(Some(expected.to_owned()), check.run(&context).ok());
+        assert_eq!(Some(expected), check.run(&context).ok());
+        let comments = repository.comments.lock().unwrap();
+        assert_eq!(1, comments.len());
+        assert_eq!("❌ **Commit messages do not follow the conventions**\n\n\
+            - `363c1dfda4c` does not follow Conventional Commits: wip\n\
+            - `363c1dfda4c` does not reference a ticket matching `[A-Z]+-[0-9]+`", comments[0]);
+    }
+
+    fn policy() -> PullRequestRules {
+        PullRequestRules {
+            title_pattern: Some("^PD-[0-9]+ ".to_owned()),
+            require_description: Some(true),
+            required_sections: Some(vec!["Testing".to_owned(), "Changes".to_owned()]),
+            report: None
+        }
+    }
+
+    #[test]
+    fn sections_end_at_the_next_heading() {
+        let description = "Intro\n\n## Changes\nMore frobnication\n\n### Testing\n\n## Notes\nNone";
+        assert_eq!(Some("More frobnication\n".to_owned()), section(description, "changes"));
+        assert_eq!(Some("".to_owned()), section(description, "Testing"));
+        assert_eq!(None, section(description, "Screenshots"));
+    }
+
+    #[test]
+    fn pull_requests_breaking_the_policy_are_reported() {
+        let policy = PullRequestPolicy::new(&policy()).unwrap();
+        let messages = Messages::new(&None, &None);
+        let pr = ::PullRequest { description: "## Testing\n".to_owned(), ..pull_request() };
+        assert_eq!(vec![
+            "The title does not match `^PD-[0-9]+ `",
+            "The \"Testing\" section of the description is empty",
+            "The description has no \"Changes\" section"
+        ], policy.violations(&pr, &messages));
+
+        let pr = ::PullRequest {
+            title: "PD-476 Check titles".to_owned(),
+            description: "## Changes\nPolicies\n## Testing\nUnit tests".to_owned(),
+            ..pull_request()
+        };
+        assert!(policy.violations(&pr, &messages).is_empty());
+        let pr = ::PullRequest { description: " \n".to_owned(), ..pr };
+        assert_eq!(3, policy.violations(&pr, &messages).len());
+    }
+
+    #[test]
+    fn fixed_pull_requests_are_acknowledged() {
+        let repository = StubRepository {
+            pr_list: Ok(vec![]),
+            queued: Ok(()),
+            running: Ok(()),
+            success: Ok(()),
+            failure: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+        let policy = PullRequestPolicy::new(&PullRequestRules {
+            required_sections: None,
+            report: Some(Report::Comment),
+            ..policy()
+        }).unwrap();
+        let config = ::parse_config(r#"{ "run_interval": 0 }"#).unwrap();
+        let messages = Messages::new(&None, &None);
+        let fanout = Fanout::<Message>::new();
+        let run = |pr: &::PullRequest| {
+            policy.run(&Context {
+                pr: pr,
+                repository: &repository,
+                matrix: &vec![],
+                config: &config,
+                messages: &messages,
+                fanout: &fanout
+            }).unwrap()
+        };
+
+        let pr = ::PullRequest { id: 476, description: "Frobnicates".to_owned(), ..pull_request() };
+        assert_eq!(Outcome::Skip("The pull request does not follow the policy".to_owned()), run(&pr));
+        let pr = ::PullRequest { title: "PD-476 Check titles".to_owned(), ..pr };
+        assert_eq!(Outcome::Continue, run(&pr));
+        assert_eq!(Outcome::Continue, run(&pr));
+        assert_eq!(vec![
+            "❌ **The pull request does not follow the policy**\n\n- The title does not match `^PD-[0-9]+ `",
+            "✔️ **The pull request follows the policy**"
+        ], *repository.comments.lock().unwrap());
+    }
+
+    #[test]
+    fn source_changes_need_a_changelog_entry() {
+        let check = ChangelogCheck::new(&ChangelogRules {
+            paths: vec!["CHANGELOG.md".to_owned(), "changelog.d".to_owned()],
+            sources: Some(vec!["src".to_owned()]),
+            exempt: Some(vec!["#no-changelog".to_owned()]),
+            report: None
+        });
+        let files = |files: &[&str]| files.iter().map(|&file| file.to_owned()).collect::<Vec<_>>();
+        let pr = pull_request();
+
+        assert_eq!(None, check.evaluate(&pr, &files(&["src/lib.rs", "README.md"])));
+        assert_eq!(Some("check.changelog.passed"), check.evaluate(&pr, &files(&["src/lib.rs", "changelog.d/481.md"])));
+        assert_eq!(Some("check.changelog.unneeded"), check.evaluate(&pr, &files(&["README.md", "srcs/a.rs"])));
+        let exempt = ::PullRequest { description: "Refactoring only #no-changelog".to_owned(), ..pull_request() };
+        assert_eq!(Some("check.changelog.exempt"), check.evaluate(&exempt, &files(&["src/lib.rs"])));
+    }
+}
@@ -0,0 +1,260 @@
+use serde_json;
+
+use ::errors::{ConfigError, Error};
+use ::i18n::Messages;
+use ::permissions::Permissions;
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+use ::seen;
+use ::templates::CommentKey;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct CommandConfig {
+    // Who may issue which command. Unless set, anyone may `rebuild` and nobody may do anything else.
+    pub permissions: Option<Permissions>,
+    // The word commands start with, `pr_demon` unless set
+    pub prefix: Option<String>
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Command {
+    Rebuild,
+    Cancel,
+    Skip,
+    Resume
+}
+
+impl Command {
+    // The name permissions know the command by, rebuilding being a retest
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Command::Rebuild => "retest",
+            Command::Cancel => "cancel",
+            Command::Skip => "skip",
+            Command::Resume => "resume"
+        }
+    }
+
+    fn parse(word: &str) -> Option<Command> {
+        match word {
+            "rebuild" | "retest" => Some(Command::Rebuild),
+            "cancel" => Some(Command::Cancel),
+            "skip" => Some(Command::Skip),
+            "resume" => Some(Command::Resume),
+            _ => None
+        }
+    }
+}
+
+// A command found in a comment, which is answered once by a comment keyed with the comment's ID
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Issued {
+    pub comment: i64,
+    pub author: String,
+    pub command: Command
+}
+
+impl Issued {
+    fn key(&self, pr: &::PullRequest) -> CommentKey {
+        CommentKey::notice(&format!("command-{}", self.comment), pr)
+    }
+}
+
+impl CommandConfig {
+    fn prefix(&self) -> &str {
+        self.prefix.as_ref().map_or("pr_demon", String::as_str)
+    }
+
+    // The first line of the comment naming a known command counts. The daemon's own comments never do.
+    pub fn parse(&self, comment: &::Comment) -> Option<Issued> {
+        if CommentKey::parse(&comment.text).is_some() {
+            return None;
+        }
+        comment.text.lines().filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some(word) if word == self.prefix() => words.next().and_then(Command::parse),
+                _ => None
+            }
+        }).next().map(|command| Issued {
+            comment: comment.id,
+            author: comment.author.to_owned(),
+            command: command
+        })
+    }
+
+    // Commands in the order they were issued
+    pub fn issued(&self, comments: &[::Comment]) -> Vec<Issued> {
+        let mut issued: Vec<Issued> = comments.iter().filter_map(|comment| self.parse(comment)).collect();
+        issued.sort_by_key(|issued| issued.comment);
+        issued
+    }
+
+    // Whether the latest `skip` or `resume` its author was allowed to issue leaves the pull request skipped.
+    // Repositories do not report the groups of comment authors, so only roles of users and the default role apply.
+    pub fn skipped(&self, issued: &[Issued], messages: &Messages) -> bool {
+        let permissions = self.permissions.to_owned().unwrap_or_default();
+        issued.iter()
+            .filter(|issued| issued.command == Command::Skip || issued.command == Command::Resume).rfind(|issued| permissions.authorize(issued.command.name(), &issued.author, &[], messages).is_ok())
+            .map_or(false, |issued| issued.command == Command::Skip)
+    }
+
+    fn execute(&self, issued: &Issued, context: &Context) -> Result<String, Error> {
+        let messages = context.messages;
+        let user = issued.author.as_str();
+        match issued.command {
+            Command::Rebuild => {
+                for ci in context.matrix {
+                    if let Err(err) = ::schedule_build(context.pr, *ci, context.repository) {
+                        return Err(err);
+                    }
+                }
+                seen::record(context.fanout.tag(), context.pr);
+                Ok(messages.format("command.rebuild", &[("user", user)]))
+            },
+            Command::Cancel => {
+                let comment = messages.format("command.cancel.build", &[("user", user)]);
+                let mut cancelled = 0;
+                for ci in context.matrix {
+                    let build = match ::get_latest_build(context.pr, *ci) {
+                        Some(build) => build,
+                        None => continue
+                    };
+                    if build.state == ::BuildState::Finished {
+                        continue;
+                    }
+                    if let Err(err) = ci.cancel_build(&build, &comment) {
+                        return Err(Error::from(err));
+                    }
+                    cancelled += 1;
+                }
+                match cancelled {
+                    0 => Ok(messages.format("command.cancel.none", &[("user", user)])),
+                    _ => Ok(messages.format("command.cancel", &[("user", user)]))
+                }
+            },
+            Command::Skip => Ok(messages.format("command.skip", &[("user", user)])),
+            Command::Resume => Ok(messages.format("command.resume", &[("user", user)]))
+        }
+    }
+}
+
+impl Stage for CommandConfig {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let comments = match context.repository.get_pr_comments(context.pr) {
+            Ok(comments) => comments,
+            Err(err) => return Err(Error::from(err))
+        };
+        let answered: Vec<CommentKey> = comments.iter()
+            .filter_map(|comment| CommentKey::parse(&comment.text))
+            .collect();
+        let issued = self.issued(&comments);
+        let permissions = self.permissions.to_owned().unwrap_or_default();
+        for command in issued.iter() {
+            let key = command.key(context.pr);
+            if answered.contains(&key) {
+                continue;
+            }
+            println!("Pull Request #{}: {} asked to {}", context.pr.id, command.author, command.command.name());
+            let reply = match permissions.authorize(command.command.name(), &command.author, &[], context.messages) {
+                Ok(_) => match self.execute(command, context) {
+                    Ok(reply) => reply,
+                    Err(err) => return Err(err)
+                },
+                Err(denial) => denial
+            };
+            if let Err(err) = context.repository.upsert_comment(context.pr, &key, &reply) {
+                return Err(Error::from(err));
+            }
+        }
+        match self.skipped(&issued, context.messages) {
+            true => Ok(Outcome::Skip("Skipped on request".to_owned())),
+            false => Ok(Outcome::Continue)
+        }
+    }
+}
+
+pub struct CommandFactory;
+
+impl registry::StageFactory for CommandFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        let config = match pipeline::parse_settings::<CommandConfig>(settings) {
+            Ok(config) => config,
+            Err(err) => return Err(err)
+        };
+        match config.prefix {
+            Some(ref prefix) if prefix.trim().is_empty() || prefix.contains(char::is_whitespace) => {
+                Err(ConfigError::Invalid("The command prefix has to be a single word".to_owned()))
+            },
+            _ => Ok(Box::new(config))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use i18n::Messages;
+    use permissions::{Permissions, Role};
+    use templates::CommentKey;
+    use stubs::pull_request;
+    use super::{Command, CommandConfig};
+
+    fn config() -> CommandConfig {
+        CommandConfig {
+            permissions: None,
+            prefix: None
+        }
+    }
+
+    fn comment(id: i64, text: &str) -> ::Comment {
+        ::Comment {
+            id: id,
+            author: "alice".to_owned(),
+            text: text.to_owned()
+        }
+    }
+
+    #[test]
+    fn known_commands_after_the_prefix_are_parsed() {
+        let config = config();
+        let parse = |text: &str| config.parse(&comment(1, text)).map(|issued| issued.command);
+        assert_eq!(Some(Command::Rebuild), parse("pr_demon rebuild"));
+        assert_eq!(Some(Command::Rebuild), parse("Flaky test, sorry\npr_demon retest please"));
+        assert_eq!(Some(Command::Skip), parse("  pr_demon   skip"));
+        assert_eq!(None, parse("pr_demon deploy"));
+        assert_eq!(None, parse("Should we pr_demon cancel?"));
+        assert_eq!(None, parse("pr_demon_bot rebuild"));
+        assert_eq!(Some(Command::Cancel), CommandConfig { prefix: Some("/ci".to_owned()), ..config.clone() }
+            .parse(&comment(1, "/ci cancel")).map(|issued| issued.command));
+    }
+
+    #[test]
+    fn comments_of_the_daemon_are_not_commands() {
+        let text = CommentKey::notice("command-1", &pull_request()).mark_plain("pr_demon rebuild");
+        assert_eq!(None, config().parse(&comment(2, &text)));
+    }
+
+    #[test]
+    fn commands_are_issued_in_order() {
+        let comments = vec![comment(7, "pr_demon resume"), comment(3, "pr_demon skip"), comment(5, "LGTM")];
+        let commands: Vec<Command> = config().issued(&comments).iter().map(|issued| issued.command).collect();
+        assert_eq!(vec![Command::Skip, Command::Resume], commands);
+    }
+
+    #[test]
+    fn the_latest_allowed_skip_or_resume_wins() {
+        let mut users = BTreeMap::new();
+        users.insert("alice".to_owned(), Role::Admin);
+        let restricted = CommandConfig {
+            permissions: Some(Permissions { users: Some(users), ..Permissions::default() }),
+            ..config()
+        };
+        let messages = Messages::new(&None, &None);
+        let skip = comment(1, "pr_demon skip");
+        let resume = ::Comment { author: "mallory".to_owned(), ..comment(2, "pr_demon resume") };
+        assert!(restricted.skipped(&restricted.issued(&[skip.clone(), resume.clone()]), &messages));
+        assert!(!restricted.skipped(&restricted.issued(&[skip.clone(), comment(3, "pr_demon resume")]), &messages));
+        assert!(!config().skipped(&config().issued(&[skip]), &messages));
+    }
+}
@@ -0,0 +1,199 @@
+use serde_json;
+
+use ::errors::{ConfigError, Error};
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+use ::size;
+
+// A part of a monorepo, built by `builders` whenever a file under one of `paths` changes
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Component {
+    pub name: String,
+    pub paths: Vec<String>,
+    pub builders: Vec<String>
+}
+
+// Builds pull requests only on the builders of the components they change, in place of the `build` stage
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct ComponentRouting {
+    pub components: Vec<Component>,
+    // Builders for changes outside every component. Every builder unless set.
+    pub other: Option<Vec<String>>
+}
+
+// Whether `file` is `path` or in the directory `path`
+pub fn is_under(file: &str, path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    file == path || (file.starts_with(path) && file[path.len()..].starts_with('/'))
+}
+
+impl Component {
+    pub fn contains(&self, file: &str) -> bool {
+        self.paths.iter().any(|path| is_under(file, path))
+    }
+}
+
+// The index of `builder` among the builders' `names`, which `owner` names it for
+pub fn position(names: &[Option<&str>], builder: &str, owner: &str) -> Result<usize, ConfigError> {
+    match names.iter().position(|name| *name == Some(builder)) {
+        Some(index) => Ok(index),
+        None => Err(ConfigError::Invalid(format!("Unknown builder {} for {}", builder, owner)))
+    }
+}
+
+impl ComponentRouting {
+    // The builders, by their index in `names`, that `files` should be built on, each with the components it builds
+    pub fn route(&self, files: &[String], names: &[Option<&str>])
+            -> Result<Vec<(usize, Option<String>)>, ConfigError> {
+        let mut components: Vec<Vec<&str>> = names.iter().map(|_| vec![]).collect();
+        let mut routed = vec![false; names.len()];
+
+        let changed = self.components.iter().filter(|component| files.iter().any(|file| component.contains(file)));
+        for component in changed {
+            for builder in &component.builders {
+                let index = match position(names, builder, &component.name) {
+                    Ok(index) => index,
+                    Err(err) => return Err(err)
+                };
+                routed[index] = true;
+                if !components[index].contains(&component.name.as_str()) {
+                    components[index].push(&component.name);
+                }
+            }
+        }
+
+        let other = files.iter().any(|file| self.components.iter().all(|component| !component.contains(file)));
+        if other {
+            match self.other {
+                Some(ref builders) => for builder in builders {
+                    match position(names, builder, "other changes") {
+                        Ok(index) => routed[index] = true,
+                        Err(err) => return Err(err)
+                    }
+                },
+                None => routed = vec![true; names.len()]
+            }
+        }
+
+        Ok(routed.iter().zip(components.iter()).enumerate()
+            .filter(|&(_, (&built, _))| built)
+            .map(|(index, (_, components))| match components.is_empty() {
+                true => (index, None),
+                false => (index, Some(components.join(", ")))
+            })
+            .collect())
+    }
+}
+
+impl Stage for ComponentRouting {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let routes = match size::changes(context.pr, context.repository, context.config) {
+            Ok((files, _, _)) => {
+                let names: Vec<_> = context.matrix.iter().map(|ci| ci.name()).collect();
+                match self.route(&files, &names) {
+                    Ok(routes) => routes.into_iter().map(|(index, component)| (context.matrix[index], component))
+                        .collect::<Vec<_>>(),
+                    Err(err) => return Err(Error::from(err))
+                }
+            },
+            Err(err) => {
+                println!("Unable to find the changed components, building everything: {}", err);
+                context.matrix.iter().map(|ci| (*ci, None)).collect()
+            }
+        };
+        if routes.is_empty() {
+            return Ok(Outcome::Skip("No component with builders changed".to_owned()));
+        }
+
+        ::handle_pull_request_components(context.pr, context.repository, &routes, &context.config.queue_alert,
+            context.messages, context.fanout).map(|_| Outcome::Continue)
+    }
+}
+
+pub struct ComponentRoutingFactory;
+
+impl registry::StageFactory for ComponentRoutingFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match pipeline::parse_settings::<ComponentRouting>(settings) {
+            Ok(routing) => Ok(Box::new(routing)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use errors::ConfigError;
+    use super::{Component, ComponentRouting};
+
+    fn routing(other: Option<Vec<String>>) -> ComponentRouting {
+        ComponentRouting {
+            components: vec![
+                Component {
+                    name: "frontend".to_owned(),
+                    paths: vec!["web/".to_owned(), "shared".to_owned()],
+                    builders: vec!["Web".to_owned()]
+                },
+                Component {
+                    name: "backend".to_owned(),
+                    paths: vec!["server".to_owned(), "shared".to_owned()],
+                    builders: vec!["Server".to_owned(), "Integration".to_owned()]
+                }
+            ],
+            other: other
+        }
+    }
+
+    fn files(files: &[&str]) -> Vec<String> {
+        files.iter().map(|&file| file.to_owned()).collect()
+    }
+
+    static NAMES: &'static [Option<&'static str>] = &[Some("Server"), Some("Web"), Some("Integration"), None];
+
+    #[test]
+    fn components_contain_files_under_their_paths() {
+        let component = &routing(None).components[0];
+        assert!(component.contains("web/index.html"));
+        assert!(component.contains("shared"));
+        assert!(!component.contains("shared_old/lib.rs"));
+        assert!(!component.contains("server/web/main.rs"));
+    }
+
+    #[test]
+    fn pull_requests_are_routed_to_the_builders_of_changed_components() {
+        let routing = routing(Some(vec![]));
+        assert_eq!(vec![(1, Some("frontend".to_owned()))], routing.route(&files(&["web/app.js"]), NAMES).unwrap());
+        assert_eq!(vec![
+            (0, Some("backend".to_owned())),
+            (1, Some("frontend".to_owned())),
+            (2, Some("backend".to_owned()))
+        ], routing.route(&files(&["web/app.js", "server/main.rs"]), NAMES).unwrap());
+        let routing = ComponentRouting {
+            components: vec![
+                routing.components[0].clone(),
+                Component { builders: vec!["Web".to_owned()], ..routing.components[1].clone() }
+            ],
+            ..routing
+        };
+        assert_eq!(vec![(1, Some("frontend, backend".to_owned()))],
+            routing.route(&files(&["shared/api.proto"]), NAMES).unwrap());
+        assert!(routing.route(&files(&["README.md"]), NAMES).unwrap().is_empty());
+    }
+
+    #[test]
+    fn other_changes_are_built_everywhere_unless_configured() {
+        assert_eq!(vec![(0, None), (1, Some("frontend".to_owned())), (2, None), (3, None)],
+            routing(None).route(&files(&["web/app.js", "README.md"]), NAMES).unwrap());
+        assert_eq!(vec![(1, Some("frontend".to_owned())), (2, None)],
+            routing(Some(vec!["Integration".to_owned()])).route(&files(&["web/app.js", "README.md"]), NAMES)
+                .unwrap());
+    }
+
+    #[test]
+    fn unknown_builders_are_rejected() {
+        match routing(Some(vec!["Docs".to_owned()])).route(&files(&["README.md"]), NAMES) {
+            Err(ConfigError::Invalid(message)) => assert_eq!("Unknown builder Docs for other changes", message),
+            _ => panic!("Expected the builder to be rejected")
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use serde_json;
+
+use ::checks::{self, Report};
+use ::errors::{ConfigError, Error};
+use ::i18n::Messages;
+use ::pipeline::{Context, Outcome, Stage};
+use ::registry;
+use ::store::JsonStore;
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct CoverageConfig {
+    // A JSON file keeping the coverage of each target branch across restarts. Kept in memory only unless set.
+    pub path: Option<String>,
+    // How many percentage points coverage may drop by before the check complains. No drop at all unless set.
+    pub tolerance: Option<f64>,
+    // Drops fail the check rather than only warning about it
+    pub fail: Option<bool>,
+    // `status` unless configured
+    pub report: Option<Report>
+}
+
+// The coverage a builder measured at the head of a target branch
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct Baseline {
+    pub branch: String,
+    pub builder: String,
+    pub commit: String,
+    pub percentage: f64
+}
+
+// The coverage a builder measured for a pull request, and the target branch's to compare it with, if known
+#[derive(PartialEq, Clone, Debug)]
+pub struct Measurement {
+    pub builder: String,
+    pub percentage: f64,
+    pub baseline: Option<f64>
+}
+
+pub struct CoverageStore {
+    baselines: JsonStore<Vec<Baseline>>
+}
+
+lazy_static! {
+    static ref STORE: Mutex<Option<CoverageStore>> = Mutex::new(None);
+    // The merged pull requests seen so far
+    static ref MERGED: Mutex<Option<HashSet<i32>>> = Mutex::new(None);
+}
+
+impl CoverageStore {
+    // A store without a file yet is empty
+    pub fn load(path: &Option<String>) -> Result<CoverageStore, ConfigError> {
+        JsonStore::load(path).map(|baselines| CoverageStore { baselines: baselines })
+    }
+
+    pub fn get(&self, branch: &str, builder: &str) -> Option<&Baseline> {
+        self.baselines.iter().find(|baseline| baseline.branch == branch && baseline.builder == builder)
+    }
+
+    // Replaces the branch's previous baseline of the same builder and rewrites the file
+    pub fn record(&mut self, baseline: Baseline) -> Result<(), ConfigError> {
+        self.baselines.retain(|old| old.branch != baseline.branch || old.builder != baseline.builder);
+        self.baselines.push(baseline);
+        self.baselines.save()
+    }
+}
+
+fn with_store<T, F>(config: &CoverageConfig, action: F) -> Result<T, ConfigError>
+        where F: FnOnce(&mut CoverageStore) -> T {
+    let mut store = STORE.lock().expect("Coverage store should not be poisoned");
+    if store.is_none() {
+        match CoverageStore::load(&config.path) {
+            Ok(loaded) => *store = Some(loaded),
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(action(store.as_mut().expect("Coverage store should be loaded")))
+}
+
+// Builders are told apart by name, or by their place in the matrix when they have none
+pub fn builder_name(ci: &::ContinuousIntegrator, index: usize) -> String {
+    ci.name().map_or(format!("#{}", index + 1), str::to_owned)
+}
+
+// The coverage of the latest finished build of the pull request's commit on `ci`
+fn measure(pr: &::PullRequest, ci: &::ContinuousIntegrator) -> Result<Option<::Coverage>, Error> {
+    match ::get_latest_build(pr, ci) {
+        Some(ref build) if build.state == ::BuildState::Finished => {
+            match ci.get_coverage(build, &pr.target_branch_name()) {
+                Ok(coverage) => Ok(coverage),
+                Err(err) => Err(Error::from(err))
+            }
+        },
+        _ => Ok(None)
+    }
+}
+
+// The head of a target branch is where its last merged pull request left it, so the coverage of the pull request's
+// last build becomes the branch's baseline. Pull requests merged before the daemon started are left to the builders.
+pub fn record_merged(repository: &::Repository, matrix: &Vec<&::ContinuousIntegrator>, config: &CoverageConfig) {
+    let pull_requests = match repository.get_merged_pr_list() {
+        Ok(pull_requests) => pull_requests,
+        Err(err) => {
+            println!("Error getting merged Pull Requests: {}", err);
+            return;
+        }
+    };
+
+    let mut merged = MERGED.lock().expect("Merged pull requests should not be poisoned");
+    let first_poll = merged.is_none();
+    let seen = merged.get_or_insert_with(HashSet::new);
+    for pr in pull_requests {
+        if !seen.insert(pr.id) || first_poll {
+            continue;
+        }
+        for (index, ci) in matrix.iter().enumerate() {
+            let coverage = match measure(&pr, *ci) {
+                Ok(Some(coverage)) => coverage,
+                Ok(None) => continue,
+                Err(err) => {
+                    println!("Unable to get the coverage of merged Pull Request #{}: {}", pr.id, err);
+                    continue;
+                }
+            };
+            let baseline = Baseline {
+                branch: pr.target_branch_name(),
+                builder: builder_name(*ci, index),
+                commit: pr.from_commit.to_owned(),
+                percentage: coverage.percentage
+            };
+            match with_store(config, |store| store.record(baseline)) {
+                Ok(Ok(())) => {},
+                Ok(Err(err)) | Err(err) => {
+                    println!("Unable to store the coverage of {}: {}", pr.target_branch_name(), err)
+                }
+            }
+        }
+    }
+}
+
+impl CoverageConfig {
+    pub fn evaluate(&self, measurements: &[Measurement], branch: &str, messages: &Messages) -> ::CheckResult {
+        let tolerance = self.tolerance.unwrap_or(0.0);
+        let mut deltas = vec![];
+        let mut violations = vec![];
+        for measurement in measurements {
+            let percentage = format!("{:.2}", measurement.percentage);
+            let delta = match measurement.baseline {
+                Some(baseline) => measurement.percentage - baseline,
+                None => {
+                    deltas.push(messages.format("check.coverage.unknown", &[
+                        ("builder", &measurement.builder),
+                        ("percentage", &percentage),
+                        ("branch", branch)
+                    ]));
+                    continue;
+                }
+            };
+            deltas.push(messages.format("check.coverage.delta", &[
+                ("builder", &measurement.builder),
+                ("percentage", &percentage),
+                ("delta", &format!("{:+.2}", delta)),
+                ("branch", branch)
+            ]));
+            if -delta > tolerance {
+                violations.push(messages.format("check.coverage.dropped", &[
+                    ("builder", &measurement.builder),
+                    ("delta", &format!("{:.2}", -delta)),
+                    ("tolerance", &format!("{:.2}", tolerance))
+                ]));
+            }
+        }
+
+        let passed = violations.is_empty() || self.fail != Some(true);
+        let summary = match (violations.is_empty(), passed) {
+            (true, _) => "check.coverage.passed",
+            (false, true) => "check.coverage.warned",
+            (false, false) => "check.coverage.failed"
+        };
+        ::CheckResult {
+            name: "coverage".to_owned(),
+            passed: passed,
+            summary: messages.format(summary, &[("coverage", &deltas.join(", "))]),
+            violations: violations
+        }
+    }
+}
+
+// Compares the coverage of the pull request's builds with the target branch's, once every build has finished
+pub struct CoverageStage;
+
+impl Stage for CoverageStage {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let config = match context.config.coverage {
+            Some(ref config) => config,
+            None => {
+                return Err(Error::from(ConfigError::Invalid("The coverage stage needs a coverage section".to_owned())))
+            }
+        };
+        let branch = context.pr.target_branch_name();
+
+        let mut measurements = vec![];
+        let mut revision = context.pr.from_commit.to_owned();
+        for (index, ci) in context.matrix.iter().enumerate() {
+            let coverage = match measure(context.pr, *ci) {
+                Ok(Some(coverage)) => coverage,
+                Ok(None) => continue,
+                Err(err) => return Err(err)
+            };
+            let builder = builder_name(*ci, index);
+            let stored = match with_store(config, |store| store.get(&branch, &builder).cloned()) {
+                Ok(stored) => stored,
+                Err(err) => return Err(Error::from(err))
+            };
+            // Builders comparing with the target branch themselves stand in until a pull request is merged into it
+            let baseline = match stored {
+                Some(stored) => {
+                    revision = format!("{}\n{}:{}", revision, builder, stored.commit);
+                    Some(stored.percentage)
+                },
+                None => coverage.delta.map(|delta| coverage.percentage - delta)
+            };
+            measurements.push(Measurement {
+                builder: builder,
+                percentage: coverage.percentage,
+                baseline: baseline
+            });
+        }
+        if measurements.is_empty() {
+            return Ok(Outcome::Continue);
+        }
+
+        let report = config.report.unwrap_or(Report::Status);
+        checks::run_check(context, "coverage", &revision, report,
+            |context| Ok(config.evaluate(&measurements, &branch, context.messages)))
+    }
+}
+
+pub struct CoverageStageFactory;
+
+impl registry::StageFactory for CoverageStageFactory {
+    fn create(&self, _: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        Ok(Box::new(CoverageStage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use i18n::Messages;
+    use super::{Baseline, CoverageConfig, CoverageStore, Measurement};
+
+    fn config(fail: Option<bool>) -> CoverageConfig {
+        CoverageConfig {
+            path: None,
+            tolerance: Some(0.5),
+            fail: fail,
+            report: None
+        }
+    }
+
+    fn measurement(percentage: f64, baseline: Option<f64>) -> Measurement {
+        Measurement {
+            builder: "Build".to_owned(),
+            percentage: percentage,
+            baseline: baseline
+        }
+    }
+
+    #[test]
+    fn drops_within_the_tolerance_pass() {
+        let messages = Messages::new(&None, &None);
+        let result = config(Some(true)).evaluate(&[measurement(80.0, Some(80.4))], "master", &messages);
+        assert!(result.passed);
+        assert!(result.violations.is_empty());
+        assert_eq!("Coverage did not drop: Build 80.00% (-0.40% vs master)", result.summary);
+    }
+
+    #[test]
+    fn larger_drops_warn_or_fail() {
+        let messages = Messages::new(&None, &None);
+        let measurements = [measurement(78.0, Some(80.0)), measurement(90.0, None)];
+        let result = config(None).evaluate(&measurements, "master", &messages);
+        assert!(result.passed);
+        assert_eq!(vec!["Build dropped by 2.00%, more than the 0.50% tolerated"], result.violations);
+        assert_eq!("Coverage dropped: Build 78.00% (-2.00% vs master), Build 90.00% (nothing to compare with on \
+            master)", result.summary);
+
+        assert!(!config(Some(true)).evaluate(&measurements, "master", &messages).passed);
+    }
+
+    #[test]
+    fn baselines_are_replaced_per_branch_and_builder() {
+        let mut store = CoverageStore::load(&None).unwrap();
+        let baseline = Baseline {
+            branch: "master".to_owned(),
+            builder: "Build".to_owned(),
+            commit: "abc".to_owned(),
+            percentage: 80.0
+        };
+        store.record(baseline.clone()).unwrap();
+        store.record(Baseline { branch: "release".to_owned(), ..baseline.clone() }).unwrap();
+        store.record(Baseline { commit: "def".to_owned(), percentage: 81.0, ..baseline.clone() }).unwrap();
+        assert_eq!(Some(81.0), store.get("master", "Build").map(|baseline| baseline.percentage));
+        assert_eq!(Some("abc"), store.get("release", "Build").map(|baseline| baseline.commit.as_str()));
+        assert_eq!(None, store.get("master", "Lint"));
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "spnego")]
+use base64;
+use hyper;
+#[cfg(feature = "spnego")]
+use libgssapi::context::{ClientCtx, CtxFlags};
+#[cfg(feature = "spnego")]
+use libgssapi::name::Name;
+#[cfg(feature = "spnego")]
+use libgssapi::oid::{GSS_MECH_KRB5, GSS_MECH_SPNEGO, GSS_NT_HOSTBASED_SERVICE};
+use serde_json;
+
+use ::errors::ApiError;
+use ::rest;
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Credential {
+    Anonymous,
+    Basic { username: String, password: String },
+    Bearer(String),
+    // A base64 encoded SPNEGO token
+    Negotiate(String)
+}
+
+// Supplies the credential sent with each request, and obtains a new one when a request is rejected with 401
+pub trait CredentialProvider: Send + Sync {
+    fn credential(&self) -> Result<Credential, ApiError>;
+
+    // Returns whether a new credential was obtained, so that the rejected request is worth retrying
+    fn refresh(&self) -> Result<bool, ApiError> {
+        Ok(false)
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProviderConfig {
+    // Runs `command` and uses its output as a bearer token, or as the password of `username`
+    Command {
+        command: String,
+        args: Option<Vec<String>>,
+        username: Option<String>
+    },
+    // Exchanges an OAuth 2 refresh token for access tokens at `token_url`
+    OAuth {
+        token_url: String,
+        client_id: String,
+        client_secret: Option<String>,
+        refresh_token: String
+    },
+    // Authenticates with the Kerberos ticket of the user running the daemon, as obtained by `kinit`, to the `service`
+    // (`HTTP` unless configured) on `host`. Needs the `spnego` feature.
+    Negotiate {
+        host: String,
+        service: Option<String>
+    }
+}
+
+lazy_static! {
+    static ref PROVIDERS: Mutex<HashMap<String, Arc<CredentialProvider>>> = Mutex::new(HashMap::new());
+}
+
+// Backends configured with the same provider share it, along with the credential it has obtained
+pub fn shared(config: &ProviderConfig) -> Arc<CredentialProvider> {
+    let key = serde_json::to_string(config).expect("Provider configuration should be serializable");
+    let mut providers = PROVIDERS.lock().expect("Credential providers lock should not be poisoned");
+    let provider = providers.entry(key).or_insert_with(|| match *config {
+        ProviderConfig::Command { .. } => Arc::new(CommandProvider::new(config)) as Arc<CredentialProvider>,
+        ProviderConfig::OAuth { .. } => Arc::new(OAuthProvider::new(config)) as Arc<CredentialProvider>,
+        ProviderConfig::Negotiate { .. } => Arc::new(NegotiateProvider::new(config)) as Arc<CredentialProvider>
+    });
+    provider.clone()
+}
+
+// The configured provider, or else one always giving the backend's own `fallback` credential
+pub fn provider_for(config: &Option<ProviderConfig>, fallback: Credential) -> Arc<CredentialProvider> {
+    match *config {
+        Some(ref config) => shared(config),
+        None => Arc::new(StaticProvider::new(fallback)) as Arc<CredentialProvider>
+    }
+}
+
+// Sends `request` with headers carrying the provider's credential, refreshing it and retrying once if the request is
+// rejected with 401
+pub fn authorized<T, F>(provider: &CredentialProvider, request: F) -> Result<T, ApiError>
+        where F: Fn(rest::Headers) -> Result<T, ApiError> {
+    let send = |credential: &Credential| {
+        let mut headers = rest::Headers::new();
+        headers.add_credential_header(credential);
+        request(headers)
+    };
+    let credential = match provider.credential() {
+        Ok(credential) => credential,
+        Err(err) => return Err(err)
+    };
+    match send(&credential) {
+        Err(ApiError::Status(hyper::StatusCode::UNAUTHORIZED)) => {},
+        response => return response
+    };
+
+    match provider.refresh() {
+        Ok(true) => {},
+        Ok(false) => return Err(ApiError::Status(hyper::StatusCode::UNAUTHORIZED)),
+        Err(err) => return Err(err)
+    };
+    match provider.credential() {
+        Ok(credential) => send(&credential),
+        Err(err) => Err(err)
+    }
+}
+
+pub struct StaticProvider {
+    credential: Credential
+}
+
+impl StaticProvider {
+    pub fn new(credential: Credential) -> StaticProvider {
+        StaticProvider {
+            credential: credential
+        }
+    }
+}
+
+impl CredentialProvider for StaticProvider {
+    fn credential(&self) -> Result<Credential, ApiError> {
+        Ok(self.credential.to_owned())
+    }
+}
+
+pub struct CommandProvider {
+    config: ProviderConfig,
+    cached: Mutex<Option<Credential>>
+}
+
+impl CommandProvider {
+    pub fn new(config: &ProviderConfig) -> CommandProvider {
+        CommandProvider {
+            config: config.to_owned(),
+            cached: Mutex::new(None)
+        }
+    }
+
+    fn run(&self) -> Result<Credential, ApiError> {
+        let (command, args, username) = match self.config {
+            ProviderConfig::Command { ref command, ref args, ref username } => (command, args, username),
+            _ => return Err(ApiError::Remote("Not a command credential provider".to_owned()))
+        };
+
+        let output = match Command::new(command).args(args.clone().unwrap_or(vec![])).output() {
+            Ok(output) => output,
+            Err(err) => return Err(ApiError::Io(err))
+        };
+        if !output.status.success() {
+            return Err(ApiError::Remote(format!("Credential command {} exited with {}", command, output.status)));
+        }
+
+        let secret = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        Ok(match *username {
+            Some(ref username) => Credential::Basic { username: username.to_owned(), password: secret },
+            None => Credential::Bearer(secret)
+        })
+    }
+}
+
+impl CredentialProvider for CommandProvider {
+    fn credential(&self) -> Result<Credential, ApiError> {
+        let mut cached = self.cached.lock().expect("Credential lock should not be poisoned");
+        if let Some(ref credential) = *cached {
+            return Ok(credential.to_owned());
+        }
+        match self.run() {
+            Ok(credential) => {
+                *cached = Some(credential.to_owned());
+                Ok(credential)
+            },
+            Err(err) => Err(err)
+        }
+    }
+
+    fn refresh(&self) -> Result<bool, ApiError> {
+        let credential = match self.run() {
+            Ok(credential) => credential,
+            Err(err) => return Err(err)
+        };
+        let mut cached = self.cached.lock().expect("Credential lock should not be poisoned");
+        let changed = cached.as_ref() != Some(&credential);
+        *cached = Some(credential);
+        Ok(changed)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>
+}
+
+struct OAuthTokens {
+    access_token: Option<String>,
+    refresh_token: String
+}
+
+pub struct OAuthProvider {
+    config: ProviderConfig,
+    tokens: Mutex<OAuthTokens>
+}
+
+impl OAuthProvider {
+    pub fn new(config: &ProviderConfig) -> OAuthProvider {
+        let refresh_token = match *config {
+            ProviderConfig::OAuth { ref refresh_token, .. } => refresh_token.to_owned(),
+            _ => "".to_owned()
+        };
+        OAuthProvider {
+            config: config.to_owned(),
+            tokens: Mutex::new(OAuthTokens {
+                access_token: None,
+                refresh_token: refresh_token
+            })
+        }
+    }
+
+    fn exchange(&self, tokens: &mut OAuthTokens) -> Result<(), ApiError> {
+        let (token_url, client_id, client_secret) = match self.config {
+            ProviderConfig::OAuth { ref token_url, ref client_id, ref client_secret, .. } => {
+                (token_url, client_id, client_secret)
+            },
+            _ => return Err(ApiError::Remote("Not an OAuth credential provider".to_owned()))
+        };
+
+        let mut form = vec![
+            ("grant_type", "refresh_token".to_owned()),
+            ("refresh_token", tokens.refresh_token.to_owned()),
+            ("client_id", client_id.to_owned())
+        ];
+        if let Some(ref client_secret) = *client_secret {
+            form.push(("client_secret", client_secret.to_owned()));
+        }
+        let body = form.iter()
+            .map(|&(key, ref value)| format!("{}={}", key, form_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let mut headers = rest::Headers::new();
+        headers.add_accept_json_header()
+            .add_content_type_form_header();
+        let response = rest::post_unrecorded::<TokenResponse>(token_url, &body, &headers.headers,
+            &hyper::StatusCode::OK);
+        match response {
+            Ok(response) => {
+                tokens.access_token = Some(response.access_token);
+                if let Some(refresh_token) = response.refresh_token {
+                    tokens.refresh_token = refresh_token;
+                }
+                Ok(())
+            },
+            Err(err) => Err(err)
+        }
+    }
+}
+
+impl CredentialProvider for OAuthProvider {
+    fn credential(&self) -> Result<Credential, ApiError> {
+        let mut tokens = self.tokens.lock().expect("Credential lock should not be poisoned");
+        if tokens.access_token.is_none() {
+            if let Err(err) = self.exchange(&mut tokens) {
+                return Err(err);
+            }
+        }
+        match tokens.access_token {
+            Some(ref access_token) => Ok(Credential::Bearer(access_token.to_owned())),
+            None => Err(ApiError::Remote("No access token was issued".to_owned()))
+        }
+    }
+
+    fn refresh(&self) -> Result<bool, ApiError> {
+        let mut tokens = self.tokens.lock().expect("Credential lock should not be poisoned");
+        self.exchange(&mut tokens).map(|_| true)
+    }
+}
+
+pub struct NegotiateProvider {
+    config: ProviderConfig
+}
+
+impl NegotiateProvider {
+    pub fn new(config: &ProviderConfig) -> NegotiateProvider {
+        NegotiateProvider {
+            config: config.to_owned()
+        }
+    }
+}
+
+#[cfg(feature = "spnego")]
+impl CredentialProvider for NegotiateProvider {
+    // Kerberos rejects replayed authenticators, so every request gets a token of its own
+    fn credential(&self) -> Result<Credential, ApiError> {
+        let (host, service) = match self.config {
+            ProviderConfig::Negotiate { ref host, ref service } => {
+                (host, service.as_ref().map_or("HTTP", String::as_str))
+            },
+            _ => return Err(ApiError::Remote("Not a Negotiate credential provider".to_owned()))
+        };
+        let principal = format!("{}@{}", service, host);
+        let target = Name::new(principal.as_bytes(), Some(&GSS_NT_HOSTBASED_SERVICE))
+            .and_then(|name| name.canonicalize(Some(&GSS_MECH_KRB5)));
+        let target = match target {
+            Ok(target) => target,
+            Err(err) => return Err(ApiError::Remote(format!("Unable to resolve {}: {}", principal, err)))
+        };
+
+        let mut context = ClientCtx::new(None, target, CtxFlags::empty(), Some(&GSS_MECH_SPNEGO));
+        match context.step(None, None) {
+            Ok(Some(token)) => Ok(Credential::Negotiate(base64::encode(&*token))),
+            Ok(None) => Err(ApiError::Remote(format!("No SPNEGO token was issued for {}", principal))),
+            Err(err) => Err(ApiError::Remote(format!("Unable to authenticate to {}: {}", principal, err)))
+        }
+    }
+
+    // A new ticket is only obtained outside the daemon, but the service ticket may have expired in the meantime
+    fn refresh(&self) -> Result<bool, ApiError> {
+        Ok(true)
+    }
+}
+
+#[cfg(not(feature = "spnego"))]
+impl CredentialProvider for NegotiateProvider {
+    fn credential(&self) -> Result<Credential, ApiError> {
+        let host = match self.config {
+            ProviderConfig::Negotiate { ref host, .. } => host.as_str(),
+            _ => ""
+        };
+        Err(ApiError::Remote(format!("Negotiate authentication to {} needs the spnego feature", host)))
+    }
+}
+
+fn form_encode(value: &str) -> String {
+    value.bytes().map(|byte| match (byte as char).is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+        true => (byte as char).to_string(),
+        false => format!("%{:02X}", byte)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::Mutex;
+    use hyper::StatusCode;
+    use errors::ApiError;
+    use rest;
+    use super::{authorized, form_encode, CommandProvider, Credential, CredentialProvider, ProviderConfig};
+
+    struct RotatingProvider {
+        generation: Mutex<u32>,
+        refreshable: bool
+    }
+
+    impl CredentialProvider for RotatingProvider {
+        fn credential(&self) -> Result<Credential, ApiError> {
+            Ok(Credential::Bearer(format!("token-{}", *self.generation.lock().unwrap())))
+        }
+
+        fn refresh(&self) -> Result<bool, ApiError> {
+            *self.generation.lock().unwrap() += 1;
+            Ok(self.refreshable)
+        }
+    }
+
+    fn rotating(refreshable: bool) -> RotatingProvider {
+        RotatingProvider {
+            generation: Mutex::new(0),
+            refreshable: refreshable
+        }
+    }
+
+    fn send(headers: &rest::Headers, attempts: &Cell<u32>) -> Result<(), ApiError> {
+        attempts.set(attempts.get() + 1);
+        match headers.headers.get("Authorization").map_or(false, |value| value == "Bearer token-1") {
+            true => Ok(()),
+            false => Err(ApiError::Status(StatusCode::UNAUTHORIZED))
+        }
+    }
+
+    #[test]
+    fn rejected_requests_are_retried_with_a_refreshed_credential() {
+        let attempts = Cell::new(0);
+        assert!(authorized(&rotating(true), |headers| send(&headers, &attempts)).is_ok());
+        assert_eq!(2, attempts.get());
+    }
+
+    #[test]
+    fn requests_are_not_retried_without_a_refresh() {
+        let attempts = Cell::new(0);
+        match authorized(&rotating(false), |headers| send(&headers, &attempts)) {
+            Err(ApiError::Status(StatusCode::UNAUTHORIZED)) => {},
+            _ => panic!("Expected the request to stay unauthorized")
+        }
+        assert_eq!(1, attempts.get());
+    }
+
+    #[test]
+    fn command_output_is_the_password_of_the_configured_user() {
+        let provider = CommandProvider::new(&ProviderConfig::Command {
+            command: "sh".to_owned(),
+            args: Some(vec!["-c".to_owned(), "echo hunter2".to_owned()]),
+            username: Some("pr_demon".to_owned())
+        });
+        assert_eq!(Credential::Basic { username: "pr_demon".to_owned(), password: "hunter2".to_owned() },
+            provider.credential().unwrap());
+        assert!(!provider.refresh().unwrap());
+    }
+
+    #[test]
+    fn form_values_are_percent_encoded() {
+        assert_eq!("a%2Fb%3Dc%20d", form_encode("a/b=c d"));
+    }
+}
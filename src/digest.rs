@@ -0,0 +1,181 @@
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json;
+
+use ::fanout::{Message, OpCode};
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct DigestConfig {
+    // Seconds without a new event for a pull request before its latest one is passed on, 60 unless set
+    pub quiet_period: Option<u64>
+}
+
+// What events are coalesced by: the pull request they concern, or the commit for events about a build alone
+pub fn subject(message: &Message) -> Option<String> {
+    let payload = match serde_json::from_str::<serde_json::Value>(&message.payload) {
+        Ok(payload) => payload,
+        Err(_) => return None
+    };
+    let pr = match payload.get("from_ref") {
+        Some(_) => payload.get("id"),
+        None => payload.pointer("/pr/id")
+    };
+    match pr.and_then(serde_json::Value::as_i64) {
+        Some(id) => Some(format!("pr:{}", id)),
+        None => payload.get("commit").and_then(serde_json::Value::as_str).map(|commit| format!("commit:{}", commit))
+    }
+}
+
+struct Pending {
+    subject: String,
+    // Every distinct event since the last one passed on. Repeats neither replace the latest event nor restart the
+    // quiet period.
+    events: Vec<Message>,
+    latest: Message,
+    quiet_since: Instant
+}
+
+// Holds back the events of each pull request until they stop coming, then passes on only the latest
+pub struct Digest {
+    quiet_period: Duration,
+    pending: Vec<Pending>
+}
+
+impl Digest {
+    pub fn new(config: &DigestConfig) -> Digest {
+        Digest {
+            quiet_period: Duration::from_secs(config.quiet_period.unwrap_or(60)),
+            pending: vec![]
+        }
+    }
+
+    // Returns the message straight back when it concerns no pull request or commit
+    pub fn push(&mut self, message: Message, now: Instant) -> Option<Message> {
+        let subject = match subject(&message) {
+            Some(subject) => subject,
+            None => return Some(message)
+        };
+        if let Some(pending) = self.pending.iter_mut().find(|pending| pending.subject == subject) {
+            // The pull request is seen again on every poll, which is no news next to what happened to it
+            if message.opcode != OpCode::OpenPullRequest && !pending.events.contains(&message) {
+                pending.events.push(message.clone());
+                pending.latest = message;
+                pending.quiet_since = now;
+            }
+            return None;
+        }
+        self.pending.push(Pending {
+            subject: subject,
+            events: vec![message.clone()],
+            latest: message,
+            quiet_since: now
+        });
+        None
+    }
+
+    // When the next pull request will have been quiet for long enough
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.iter().map(|pending| pending.quiet_since + self.quiet_period).min()
+    }
+
+    // Takes the latest events of the pull requests that have been quiet for long enough
+    pub fn due(&mut self, now: Instant) -> Vec<Message> {
+        let quiet_period = self.quiet_period;
+        let (due, pending): (Vec<Pending>, Vec<Pending>) = self.pending.drain(..)
+            .partition(|pending| pending.quiet_since + quiet_period <= now);
+        self.pending = pending;
+        due.into_iter().map(|pending| pending.latest).collect()
+    }
+
+    pub fn flush(&mut self) -> Vec<Message> {
+        self.pending.drain(..).map(|pending| pending.latest).collect()
+    }
+}
+
+// Passes the subscriber's events on through a digest, for a notifier to read instead
+pub fn coalesce(subscriber: Receiver<Message>, config: &DigestConfig) -> Receiver<Message> {
+    let (tx, rx) = channel::<Message>();
+    let mut digest = Digest::new(config);
+    thread::spawn(move || {
+        loop {
+            let now = Instant::now();
+            let received = match digest.next_due() {
+                Some(due) if due > now => subscriber.recv_timeout(due - now),
+                Some(_) => Err(RecvTimeoutError::Timeout),
+                None => subscriber.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            };
+            let mut outgoing = match received {
+                Ok(message) => digest.push(message, Instant::now()).into_iter().collect::<Vec<_>>(),
+                Err(RecvTimeoutError::Timeout) => vec![],
+                Err(RecvTimeoutError::Disconnected) => {
+                    for message in digest.flush() {
+                        let _ = tx.send(message);
+                    }
+                    return;
+                }
+            };
+            outgoing.extend(digest.due(Instant::now()));
+            for message in outgoing {
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use fanout::{Message, OpCode};
+    use stubs::pull_request;
+    use super::{subject, Digest, DigestConfig};
+
+    fn build(commit: &str) -> Message {
+        Message::new(OpCode::BuildRunning, &json!({ "id": 1, "commit": commit }))
+    }
+
+    #[test]
+    fn events_are_told_apart_by_pull_request_or_commit() {
+        let pr = pull_request();
+        assert_eq!(Some("pr:111".to_owned()), subject(&Message::new(OpCode::OpenPullRequest, &pr)));
+        assert_eq!(Some("pr:111".to_owned()), subject(&Message::new(OpCode::Custom {
+            payload: "Bitbucket::Comment::Update".to_owned()
+        }, &json!({ "pr": pr }))));
+        assert_eq!(Some("commit:abc".to_owned()), subject(&build("abc")));
+        assert_eq!(None, subject(&Message::new(OpCode::BuildQueueDelayed, &json!({ "id": 1 }))));
+    }
+
+    #[test]
+    fn only_the_latest_event_is_passed_on_once_quiet() {
+        let mut digest = Digest::new(&DigestConfig { quiet_period: Some(60) });
+        let start = Instant::now();
+        let opened = Message::new(OpCode::OpenPullRequest, &pull_request());
+        let queued = Message::new(OpCode::BuildQueued, &json!({ "pr": pull_request(), "state": "Queued" }));
+        let finished = Message::new(OpCode::BuildFinished { success: true },
+            &json!({ "pr": pull_request(), "state": "Finished" }));
+
+        assert_eq!(None, digest.push(opened, start));
+        assert_eq!(None, digest.push(queued, start + Duration::from_secs(10)));
+        assert_eq!(None, digest.push(finished.clone(), start + Duration::from_secs(30)));
+        assert_eq!(None, digest.push(finished.clone(), start + Duration::from_secs(40)));
+        // The pull request seen again on the next poll, even changed, is no news
+        let seen = Message::new(OpCode::OpenPullRequest, &::PullRequest { updated: Some(1), ..pull_request() });
+        assert_eq!(None, digest.push(seen, start + Duration::from_secs(50)));
+        assert_eq!(Some(start + Duration::from_secs(90)), digest.next_due());
+        assert!(digest.due(start + Duration::from_secs(89)).is_empty());
+        assert_eq!(vec![finished], digest.due(start + Duration::from_secs(90)));
+        assert_eq!(None, digest.next_due());
+    }
+
+    #[test]
+    fn events_without_a_pull_request_are_passed_on_at_once() {
+        let mut digest = Digest::new(&DigestConfig { quiet_period: None });
+        let delayed = Message::new(OpCode::BuildQueueDelayed, &json!({ "id": 1 }));
+        assert_eq!(Some(delayed.clone()), digest.push(delayed, Instant::now()));
+        digest.push(build("abc"), Instant::now());
+        assert_eq!(vec![build("abc")], digest.flush());
+    }
+}
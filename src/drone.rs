@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use ::credentials;
+use ::errors::{ApiError, BuilderError, ConfigError};
+use ::registry;
+use ::rest;
+use hyper;
+use serde_json;
+use time;
+use url::form_urlencoded;
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct DroneCredentials {
+    // A personal token, unless `credential_provider` is set
+    #[serde(default)]
+    pub token: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    pub server: String,
+    // The repository as Drone names it, `owner/name`
+    pub repo: String,
+    // Further parameters every build is created with, which the pipeline sees as environment variables
+    pub parameters: Option<BTreeMap<String, String>>
+}
+
+pub struct DroneFactory;
+
+impl registry::BuilderFactory for DroneFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+        match serde_json::from_value::<DroneCredentials>(settings.to_owned()) {
+            Ok(credentials) => Ok(vec![Box::new(credentials) as Box<::ContinuousIntegrator>]),
+            Err(err) => Err(ConfigError::Parse(err))
+        }
+    }
+}
+
+// Builds, their stages and steps share their statuses. Blocked builds wait for someone to approve them.
+fn build_state(status: &str) -> (::BuildState, ::BuildStatus) {
+    match status {
+        "success" => (::BuildState::Finished, ::BuildStatus::Success),
+        "failure" | "error" | "killed" | "declined" => (::BuildState::Finished, ::BuildStatus::Failure),
+        "skipped" => (::BuildState::Finished, ::BuildStatus::Unknown),
+        "running" | "blocked" => (::BuildState::Running, ::BuildStatus::Unknown),
+        _ => (::BuildState::Queued, ::BuildStatus::Unknown)
+    }
+}
+
+impl DroneCredentials {
+    fn server_url(&self) -> &str {
+        self.server.trim_end_matches('/')
+    }
+
+    fn builds_url(&self) -> String {
+        format!("{}/api/repos/{}/builds", self.server_url(), self.repo)
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credential_provider, credentials::Credential::Bearer(self.token.to_owned()))
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Build {
+    pub id: i64,
+    pub number: i32,
+    pub status: String,
+    pub source: String,
+    pub after: String,
+    pub created: Option<i64>,
+    #[serde(default)]
+    pub stages: Vec<Stage>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Stage {
+    pub number: i32,
+    pub name: String,
+    pub status: String,
+    #[serde(default)]
+    pub errignore: bool,
+    #[serde(default)]
+    pub steps: Vec<Step>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Step {
+    pub name: String,
+    pub status: String,
+    pub exit_code: Option<i32>
+}
+
+impl Stage {
+    // Stages whose failures are ignored do not fail the build
+    fn failed(&self) -> bool {
+        !self.errignore && build_state(&self.status).1 == ::BuildStatus::Failure
+    }
+
+    fn failure(&self) -> String {
+        let steps: Vec<String> = self.steps.iter()
+            .filter(|step| build_state(&step.status).1 == ::BuildStatus::Failure)
+            .map(|step| match step.exit_code {
+                Some(code) if code != 0 => format!("{} (exit code {})", step.name, code),
+                _ => step.name.to_owned()
+            })
+            .collect();
+        match steps.is_empty() {
+            true => format!("{} {}", self.name, self.status),
+            false => format!("{} failed in {}", self.name, steps.join(", "))
+        }
+    }
+}
+
+impl Build {
+    // Each stage is a part of the build, and those that failed are named in the status text
+    fn to_build_details(&self, credentials: &DroneCredentials) -> ::BuildDetails {
+        let (state, status) = build_state(&self.status);
+        let web_url = format!("{}/{}/{}", credentials.server_url(), credentials.repo, self.number);
+        let failures: Vec<String> = self.stages.iter().filter(|stage| stage.failed()).map(Stage::failure).collect();
+        let queue_wait = match state {
+            ::BuildState::Queued => self.created.map(|created| time::now_utc().to_timespec().sec - created),
+            _ => None
+        };
+        ::BuildDetails {
+            id: self.number,
+            build_id: credentials.repo.to_owned(),
+            commit: match self.after.is_empty() {
+                true => None,
+                false => Some(self.after.to_owned())
+            },
+            state: state,
+            status: status,
+            status_text: match failures.is_empty() {
+                true => Some(self.status.to_owned()),
+                false => Some(failures.join("; "))
+            },
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: queue_wait,
+            parts: self.stages.iter().map(|stage| {
+                let (state, status) = build_state(&stage.status);
+                ::BuildPart {
+                    name: stage.name.to_owned(),
+                    web_url: format!("{}/{}", web_url, stage.number),
+                    state: state,
+                    status: status
+                }
+            }).collect(),
+            steps: vec![],
+            notes: vec![],
+            component: None,
+            web_url: web_url
+        }
+    }
+}
+
+impl ::ContinuousIntegrator for DroneCredentials {
+    fn name(&self) -> Option<&str> {
+        Some(&self.repo)
+    }
+
+    // Drone lists builds of every branch, so only the latest ones are looked through
+    fn get_build_list(&self, branch: &str) -> Result<Vec<::Build>, BuilderError> {
+        let url = format!("{}?page=1&per_page=100", self.builds_url());
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Vec<Build>>(&url, &headers.headers)
+        });
+        match response {
+            Ok(builds) => Ok(builds.iter()
+                .filter(|build| build.source == branch)
+                .map(|build| ::Build { id: build.number })
+                .collect()),
+            Err(err) => Err(BuilderError::api("getting list of builds", err))
+        }
+    }
+
+    fn get_build(&self, build_id: i32) -> Result<::BuildDetails, BuilderError> {
+        let url = format!("{}/{}", self.builds_url(), build_id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Build>(&url, &headers.headers)
+        });
+        match response {
+            Ok(build) => Ok(build.to_build_details(self)),
+            Err(err) => Err(BuilderError::api("getting build", err))
+        }
+    }
+
+    fn queue_build(&self, branch: &str) -> Result<::BuildDetails, BuilderError> {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query.append_pair("branch", branch);
+        if let Some(ref parameters) = self.parameters {
+            for (name, value) in parameters {
+                query.append_pair(name, value);
+            }
+        }
+        let url = format!("{}?{}", self.builds_url(), query.finish());
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::post::<Build>(&url, "", &headers.headers, &hyper::StatusCode::OK)
+        });
+        match response {
+            Ok(build) => Ok(build.to_build_details(self)),
+            Err(err) => Err(BuilderError::api("queuing build", err))
+        }
+    }
+
+    fn get_personal_build_list(&self, _: &str) -> Result<Vec<::Build>, BuilderError> {
+        Ok(vec![])
+    }
+
+    fn queue_personal_build(&self, _: &str, _: &str) -> Result<::BuildDetails, BuilderError> {
+        Err(BuilderError::InvalidResponse("Drone does not support personal builds".to_owned()))
+    }
+
+    // Drone builds cannot be tagged, pinned or commented on
+    fn tag_build(&self, _: &::BuildDetails, _: &Vec<String>) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn pin_build(&self, _: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn comment_build(&self, _: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn cancel_build(&self, build: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        let url = format!("{}/{}", self.builds_url(), build.id);
+
+        let response = credentials::authorized(&*self.provider(), |headers| rest::delete_raw(&url, &headers.headers));
+        match response {
+            Ok(response) => {
+                match response.status {
+                    status if status.is_success() => Ok(()),
+                    e @ _ => Err(BuilderError::api("cancelling build", ApiError::Status(e)))
+                }
+            },
+            Err(err) => Err(BuilderError::api("cancelling build", err))
+        }
+    }
+
+    fn get_coverage(&self, _: &::BuildDetails, _: &str) -> Result<Option<::Coverage>, BuilderError> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,316 @@
+use serde_json::{Map, Value};
+
+use ::errors::ConfigError;
+use ::netrc::{self, Netrc};
+
+// Credentials looked up in the environment when a backend's settings leave them out
+static DISCOVERED: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("bitbucket", "username", "BITBUCKET_USERNAME"),
+    ("bitbucket", "password", "BITBUCKET_PASSWORD"),
+    ("teamcity", "username", "TEAMCITY_USERNAME"),
+    ("teamcity", "password", "TEAMCITY_PASSWORD"),
+    ("teamcity", "token", "TEAMCITY_TOKEN"),
+    ("telegram", "api_token", "TELEGRAM_API_TOKEN")
+];
+
+// Backends whose `username` and `password` can be found in .netrc by the host of their `base_url`
+static NETRC_BACKENDS: &'static [&'static str] = &["bitbucket", "teamcity"];
+
+// Settings that authenticate a backend on their own, without another of them
+static SECRETS: &'static [&'static str] = &["password", "token", "credential_provider"];
+
+// Replaces `"${NAME}"` strings anywhere in the configuration with the variable's value, fills in repository and
+// builder credentials from the named sets of the `credentials` section, and then fills in those still missing from the
+// variables in `DISCOVERED` and from .netrc
+pub fn resolve<F>(config: Value, lookup: &F) -> Result<Value, ConfigError> where F: Fn(&str) -> Option<String> {
+    let mut config = match substitute(config, lookup) {
+        Ok(config) => config,
+        Err(err) => return Err(err)
+    };
+    let netrc = netrc_path(lookup).and_then(|path| Netrc::read(&path));
+
+    let sets = match config.get("credentials") {
+        Some(&Value::Object(ref sets)) => sets.to_owned(),
+        Some(&Value::Null) | None => Map::new(),
+        Some(_) => return Err(ConfigError::Invalid("credentials must map names to credential sets".to_owned()))
+    };
+    for &section in ["bitbucket", "teamcity"].iter() {
+        if let Some(settings) = config.get_mut(section).and_then(Value::as_object_mut) {
+            if let Err(err) = apply_credentials(settings, &sets) {
+                return Err(err);
+            }
+        }
+    }
+    for &section in ["repository", "builder"].iter() {
+        let settings = config.get_mut(section).and_then(|backend| backend.get_mut("settings"))
+            .and_then(Value::as_object_mut);
+        if let Some(settings) = settings {
+            if let Err(err) = apply_credentials(settings, &sets) {
+                return Err(err);
+            }
+        }
+    }
+
+    for &kind in ["bitbucket", "teamcity", "telegram"].iter() {
+        if let Some(settings) = config.get_mut(kind).and_then(Value::as_object_mut) {
+            discover(kind, settings, lookup, &netrc);
+        }
+    }
+    for &section in ["repository", "builder"].iter() {
+        if let Some(backend) = config.get_mut(section) {
+            discover_backend(backend, lookup, &netrc);
+        }
+    }
+    if let Some(notifiers) = config.get_mut("notifiers").and_then(Value::as_array_mut) {
+        for notifier in notifiers.iter_mut() {
+            discover_backend(notifier, lookup, &netrc);
+        }
+    }
+    Ok(config)
+}
+
+fn netrc_path<F>(lookup: &F) -> Option<String> where F: Fn(&str) -> Option<String> {
+    lookup("NETRC").or_else(|| lookup("HOME").map(|home| format!("{}/.netrc", home)))
+}
+
+fn variable_name(text: &str) -> Option<&str> {
+    if text.starts_with("${") && text.ends_with('}') && text.len() > 3 {
+        Some(&text[2..text.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn substitute<F>(value: Value, lookup: &F) -> Result<Value, ConfigError> where F: Fn(&str) -> Option<String> {
+    match value {
+        Value::String(text) => {
+            let name = variable_name(&text).map(str::to_owned);
+            match name {
+                Some(name) => match lookup(&name) {
+                    Some(resolved) => Ok(Value::String(resolved)),
+                    None => Err(ConfigError::Invalid(format!("Environment variable {} is not set", name)))
+                },
+                None => Ok(Value::String(text))
+            }
+        },
+        Value::Array(values) => {
+            let mut substituted = vec![];
+            for value in values {
+                match substitute(value, lookup) {
+                    Ok(value) => substituted.push(value),
+                    Err(err) => return Err(err)
+                }
+            }
+            Ok(Value::Array(substituted))
+        },
+        Value::Object(values) => {
+            let mut substituted = Map::new();
+            for (key, value) in values {
+                match substitute(value, lookup) {
+                    Ok(value) => substituted.insert(key, value),
+                    Err(err) => return Err(err)
+                };
+            }
+            Ok(Value::Object(substituted))
+        },
+        value => Ok(value)
+    }
+}
+
+fn has_secret(settings: &Map<String, Value>) -> bool {
+    SECRETS.iter().any(|&key| settings.get(key).map_or(false, |value| !value.is_null()))
+}
+
+// Backends use the set named by their `credentials` setting, or the `default` set. Settings of the backend itself take
+// precedence, and a backend with a secret of its own takes no secret from the set.
+fn apply_credentials(settings: &mut Map<String, Value>, sets: &Map<String, Value>) -> Result<(), ConfigError> {
+    let name = match settings.remove("credentials") {
+        Some(Value::String(name)) => name,
+        Some(Value::Null) | None => "default".to_owned(),
+        Some(_) => return Err(ConfigError::Invalid("credentials must be the name of a credential set".to_owned()))
+    };
+    let set = match sets.get(&name) {
+        Some(&Value::Object(ref set)) => set,
+        None if name == "default" => return Ok(()),
+        None => return Err(ConfigError::Invalid(format!("Unknown credential set {}", name))),
+        Some(_) => return Err(ConfigError::Invalid(format!("Credential set {} must be an object", name)))
+    };
+
+    let own_secret = has_secret(settings);
+    for (key, value) in set {
+        if own_secret && SECRETS.contains(&key.as_str()) {
+            continue;
+        }
+        if settings.get(key).map_or(true, Value::is_null) {
+            settings.insert(key.to_owned(), value.to_owned());
+        }
+    }
+    Ok(())
+}
+
+fn discover_backend<F>(backend: &mut Value, lookup: &F, netrc: &Option<Netrc>) where F: Fn(&str) -> Option<String> {
+    let kind = match backend.get("type").and_then(Value::as_str) {
+        Some(kind) => kind.to_owned(),
+        None => return
+    };
+    if backend.get("settings").map_or(true, Value::is_null) {
+        if let Some(backend) = backend.as_object_mut() {
+            backend.insert("settings".to_owned(), Value::Object(Map::new()));
+        }
+    }
+    if let Some(settings) = backend.get_mut("settings").and_then(Value::as_object_mut) {
+        discover(&kind, settings, lookup, netrc);
+    }
+}
+
+fn discover<F>(kind: &str, settings: &mut Map<String, Value>, lookup: &F, netrc: &Option<Netrc>)
+        where F: Fn(&str) -> Option<String> {
+    for &(backend, key, name) in DISCOVERED.iter().filter(|&&(backend, _, _)| backend == kind) {
+        if settings.get(key).map_or(false, |value| !value.is_null()) {
+            continue;
+        }
+        if let Some(value) = lookup(name) {
+            println!("Using {} from the environment for the {} {}", name, backend, key);
+            settings.insert(key.to_owned(), Value::String(value));
+        }
+    }
+
+    if let Some(ref netrc) = *netrc {
+        if NETRC_BACKENDS.contains(&kind) {
+            discover_netrc(kind, settings, netrc);
+        }
+    }
+}
+
+fn discover_netrc(kind: &str, settings: &mut Map<String, Value>, netrc: &Netrc) {
+    if has_secret(settings) {
+        return;
+    }
+    let entry = match settings.get("base_url").and_then(Value::as_str).and_then(netrc::host) {
+        Some(host) => match netrc.find(host) {
+            Some(entry) => entry.to_owned(),
+            None => return
+        },
+        None => return
+    };
+    let username = settings.get("username").and_then(Value::as_str).map(str::to_owned);
+    match (username, entry.login, entry.password) {
+        (Some(ref username), Some(ref login), _) if username != login => {},
+        (username, login, Some(password)) => {
+            println!("Using .netrc for the {} credentials", kind);
+            if let Some(login) = username.or(login) {
+                settings.insert("username".to_owned(), Value::String(login));
+            }
+            settings.insert("password".to_owned(), Value::String(password));
+        },
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use errors::ConfigError;
+    use super::resolve;
+
+    fn environment() -> HashMap<String, String> {
+        let mut environment = HashMap::new();
+        environment.insert("BITBUCKET_PASSWORD".to_owned(), "hunter2".to_owned());
+        environment.insert("TEAMCITY_TOKEN".to_owned(), "token".to_owned());
+        environment.insert("DEPLOY_PASSWORD".to_owned(), "swordfish".to_owned());
+        environment
+    }
+
+    fn resolve_json(json: &str) -> Result<::serde_json::Value, ConfigError> {
+        let environment = environment();
+        resolve(::serde_json::from_str(json).unwrap(), &|name: &str| environment.get(name).cloned())
+    }
+
+    #[test]
+    fn referenced_variables_are_substituted() {
+        let resolved = resolve_json(r#"{ "bitbucket": { "password": "${DEPLOY_PASSWORD}" } }"#).unwrap();
+        assert_eq!(json!({ "bitbucket": { "password": "swordfish" } }), resolved);
+    }
+
+    #[test]
+    fn missing_referenced_variables_are_rejected() {
+        match resolve_json(r#"{ "bitbucket": { "password": "${MISSING}" } }"#) {
+            Err(ConfigError::Invalid(ref message)) => assert_eq!("Environment variable MISSING is not set", message),
+            _ => panic!("Expected a missing variable error")
+        }
+    }
+
+    #[test]
+    fn missing_credentials_are_discovered() {
+        let resolved = resolve_json(r#"{
+            "bitbucket": { "username": "pr_demon" },
+            "builder": { "type": "teamcity" }
+        }"#).unwrap();
+        assert_eq!(json!({
+            "bitbucket": { "username": "pr_demon", "password": "hunter2" },
+            "builder": { "type": "teamcity", "settings": { "token": "token" } }
+        }), resolved);
+    }
+
+    #[test]
+    fn missing_credentials_are_found_in_netrc() {
+        let path = env::temp_dir().join("pr_demon_environment_netrc");
+        File::create(&path)
+            .and_then(|mut file| file.write_all(b"machine bitbucket.example.com login pr_demon password hunter2"))
+            .unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let resolved = resolve(::serde_json::from_str(r#"{
+            "bitbucket": { "base_url": "https://bitbucket.example.com" },
+            "repository": { "type": "bitbucket", "settings": { "base_url": "https://bitbucket.example.com", "username": "x" } }
+        }"#).unwrap(), &|name: &str| if name == "NETRC" { Some(path.to_owned()) } else { None }).unwrap();
+        assert_eq!(json!({
+            "bitbucket": { "base_url": "https://bitbucket.example.com", "username": "pr_demon", "password": "hunter2" },
+            "repository": { "type": "bitbucket", "settings": { "base_url": "https://bitbucket.example.com", "username": "x" } }
+        }), resolved);
+    }
+
+    #[test]
+    fn backends_use_their_credential_set() {
+        let resolved = resolve_json(r#"{
+            "credentials": {
+                "default": { "username": "pr_demon", "password": "${DEPLOY_PASSWORD}" },
+                "mobile": { "username": "mobile_ci", "token": "mobile" }
+            },
+            "bitbucket": { "base_url": "https://bitbucket.example.com" },
+            "builder": { "type": "teamcity", "settings": { "credentials": "mobile" } }
+        }"#).unwrap();
+        assert_eq!(json!({
+            "base_url": "https://bitbucket.example.com",
+            "username": "pr_demon",
+            "password": "swordfish"
+        }), resolved["bitbucket"]);
+        assert_eq!(json!({ "username": "mobile_ci", "token": "mobile" }), resolved["builder"]["settings"]);
+    }
+
+    #[test]
+    fn own_secrets_override_credential_sets() {
+        let resolved = resolve_json(r#"{
+            "credentials": { "default": { "username": "pr_demon", "password": "shared" } },
+            "teamcity": { "token": "own" }
+        }"#).unwrap();
+        assert_eq!(json!({ "username": "pr_demon", "token": "own" }), resolved["teamcity"]);
+    }
+
+    #[test]
+    fn unknown_credential_sets_are_rejected() {
+        match resolve_json(r#"{ "bitbucket": { "credentials": "missing" } }"#) {
+            Err(ConfigError::Invalid(ref message)) => assert_eq!("Unknown credential set missing", message),
+            _ => panic!("Expected an unknown credential set error")
+        }
+    }
+
+    #[test]
+    fn configured_credentials_take_precedence() {
+        let resolved = resolve_json(r#"{ "bitbucket": { "password": "configured" } }"#).unwrap();
+        assert_eq!(json!({ "bitbucket": { "password": "configured" } }), resolved);
+    }
+}
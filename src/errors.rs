@@ -0,0 +1,286 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+#[cfg(feature = "git")]
+use git2;
+use hyper::StatusCode;
+use serde_json;
+
+#[derive(Debug)]
+pub enum Error {
+    Config(ConfigError),
+    Repository(RepositoryError),
+    Builder(BuilderError)
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(io::Error),
+    Write(io::Error),
+    Parse(serde_json::Error),
+    UnknownType(String),
+    Invalid(String)
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    Http(Box<StdError + Send + Sync>),
+    Io(io::Error),
+    Status(StatusCode),
+    Parse { body: String, source: serde_json::Error },
+    Remote(String)
+}
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    Api { context: String, source: ApiError },
+    Template(String)
+}
+
+#[derive(Debug)]
+pub enum BuilderError {
+    Api { context: String, source: ApiError },
+    InvalidResponse(String)
+}
+
+// Why a request to one of the daemon's own endpoints was refused
+#[derive(Debug)]
+pub enum AccessError {
+    Unauthorized,
+    Forbidden(IpAddr)
+}
+
+// Why an incoming webhook delivery was rejected
+#[derive(Debug)]
+pub enum WebhookError {
+    Unsigned,
+    InvalidSignature,
+    UnknownRepository(String),
+    Replayed(String),
+    // How many seconds the delivery's timestamp is away from now
+    Stale(i64)
+}
+
+#[derive(Debug)]
+pub enum GitError {
+    #[cfg(feature = "git")]
+    Git { context: String, source: git2::Error },
+    UnknownRevision(String)
+}
+
+impl AccessError {
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            AccessError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AccessError::Forbidden(_) => StatusCode::FORBIDDEN
+        }
+    }
+}
+
+impl RepositoryError {
+    pub fn api(context: &str, source: ApiError) -> RepositoryError {
+        RepositoryError::Api { context: context.to_owned(), source: source }
+    }
+}
+
+impl BuilderError {
+    pub fn api(context: &str, source: ApiError) -> BuilderError {
+        BuilderError::Api { context: context.to_owned(), source: source }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Error {
+        Error::Config(err)
+    }
+}
+
+impl From<RepositoryError> for Error {
+    fn from(err: RepositoryError) -> Error {
+        Error::Repository(err)
+    }
+}
+
+impl From<BuilderError> for Error {
+    fn from(err: BuilderError) -> Error {
+        Error::Builder(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Config(ref err) => write!(f, "{}", err),
+            Error::Repository(ref err) => write!(f, "{}", err),
+            Error::Builder(ref err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Read(ref err) => write!(f, "Unable to read config: {}", err),
+            ConfigError::Write(ref err) => write!(f, "Unable to write file: {}", err),
+            ConfigError::Parse(ref err) => write!(f, "Unable to decode JSON value {}", err),
+            ConfigError::UnknownType(ref kind) => write!(f, "No backend is registered for type {}", kind),
+            ConfigError::Invalid(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            // The client's own errors only say which step failed, and their sources why
+            ApiError::Http(ref err) => {
+                let mut message = err.to_string();
+                let mut source = err.source();
+                while let Some(cause) = source {
+                    message.push_str(&format!(": {}", cause));
+                    source = cause.source();
+                }
+                write!(f, "{}", message)
+            },
+            ApiError::Io(ref err) => write!(f, "{}", err),
+            ApiError::Status(ref status) => write!(f, "{}", status),
+            ApiError::Parse { ref body, ref source } => write!(f, "Error parsing response: {} {}", body, source),
+            ApiError::Remote(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RepositoryError::Api { ref context, ref source } => write!(f, "Error {}: {}", context, source),
+            RepositoryError::Template(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuilderError::Api { ref context, ref source } => write!(f, "Error {}: {}", context, source),
+            BuilderError::InvalidResponse(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AccessError::Unauthorized => write!(f, "Missing or invalid credentials"),
+            AccessError::Forbidden(ref remote) => write!(f, "Requests from {} are not allowed", remote)
+        }
+    }
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WebhookError::Unsigned => write!(f, "Delivery is not signed"),
+            WebhookError::InvalidSignature => write!(f, "Delivery signature does not match"),
+            WebhookError::UnknownRepository(ref repository) => {
+                write!(f, "No webhook secret is configured for {}", repository)
+            },
+            WebhookError::Replayed(ref id) => write!(f, "Delivery {} has already been received", id),
+            WebhookError::Stale(seconds) => write!(f, "Delivery timestamp is {} seconds away", seconds)
+        }
+    }
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            #[cfg(feature = "git")]
+            GitError::Git { ref context, ref source } => write!(f, "Error {}: {}", context, source),
+            GitError::UnknownRevision(ref revision) => write!(f, "Unknown revision {}", revision)
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            Error::Config(ref err) => Some(err),
+            Error::Repository(ref err) => Some(err),
+            Error::Builder(ref err) => Some(err)
+        }
+    }
+}
+
+impl StdError for ConfigError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            ConfigError::Read(ref err) | ConfigError::Write(ref err) => Some(err),
+            ConfigError::Parse(ref err) => Some(err),
+            ConfigError::UnknownType(_) | ConfigError::Invalid(_) => None
+        }
+    }
+}
+
+impl StdError for ApiError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            ApiError::Http(ref err) => Some(&**err),
+            ApiError::Io(ref err) => Some(err),
+            ApiError::Status(_) | ApiError::Remote(_) => None,
+            ApiError::Parse { ref source, .. } => Some(source)
+        }
+    }
+}
+
+impl StdError for RepositoryError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            RepositoryError::Api { ref source, .. } => Some(source),
+            RepositoryError::Template(_) => None
+        }
+    }
+}
+
+impl StdError for BuilderError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            BuilderError::Api { ref source, .. } => Some(source),
+            BuilderError::InvalidResponse(_) => None
+        }
+    }
+}
+
+impl StdError for GitError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            #[cfg(feature = "git")]
+            GitError::Git { ref source, .. } => Some(source),
+            GitError::UnknownRevision(_) => None
+        }
+    }
+}
+
+impl StdError for AccessError {}
+
+impl StdError for WebhookError {}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+    use hyper::StatusCode;
+    use super::{ApiError, BuilderError, Error};
+
+    #[test]
+    fn api_errors_are_reported_with_their_context() {
+        let err = Error::from(BuilderError::api("queuing build", ApiError::Status(StatusCode::FORBIDDEN)));
+        assert_eq!("Error queuing build: 403 Forbidden", err.to_string());
+    }
+
+    #[test]
+    fn api_errors_chain_to_their_source() {
+        let err = Error::from(BuilderError::api("queuing build", ApiError::Status(StatusCode::FORBIDDEN)));
+        let source = err.source().and_then(|builder_error| builder_error.source());
+        assert_eq!(Some("403 Forbidden".to_owned()), source.map(|api_error| api_error.to_string()));
+    }
+}
@@ -0,0 +1,34 @@
+// Payloads of the custom events broadcast by repositories, decoded by subscribers with `Message::payload`
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct PostedComment {
+    // Wide enough for GitHub's comment IDs
+    pub id: i64,
+    pub version: i32,
+    pub text: String
+}
+
+// Broadcast as `<Repository>::Comment::{Existing,Update,Post,Error}` when a build status comment is posted
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct CommentEvent {
+    pub pr: ::PullRequest,
+    pub build: ::BuildDetails,
+    pub comment: Option<PostedComment>
+}
+
+// Broadcast as `<Repository>::MatrixComment::{Existing,Update,Post,Error}` when a build matrix comment is posted
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct MatrixCommentEvent {
+    pub pr: ::PullRequest,
+    pub builds: Vec<::BuildDetails>,
+    pub comment: Option<PostedComment>
+}
+
+// Broadcast as `<Repository>::Notice::{Existing,Update,Post,Error}` when a comment about the pull request as a whole,
+// such as a size warning, is posted
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct NoticeEvent {
+    pub pr: ::PullRequest,
+    pub kind: String,
+    pub comment: Option<PostedComment>
+}
@@ -0,0 +1,410 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use serde_json;
+use time;
+
+use ::errors::{BuilderError, ConfigError};
+use ::registry;
+
+// How many builds are remembered, older ones being forgotten
+static KEPT_BUILDS: usize = 100;
+
+// Builds pull requests on the daemon's own machine, in a clone of the repository kept at `workspace`
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct ExecConfig {
+    // The repository to clone, which pull request branches are fetched from
+    pub url: String,
+    pub workspace: String,
+    // Run with `sh -c` in the workspace, failing the build unless it exits with 0
+    pub command: String,
+    // The builder's name for `components` routing, `exec` unless set
+    pub name: Option<String>,
+    // Where the output of every build is written, `<workspace>.logs` unless set
+    pub logs: Option<String>,
+    // The URL the logs are served at, which builds link to. Their path otherwise.
+    pub log_url: Option<String>,
+    // How many lines at the end of the output failed builds show, 20 unless set
+    pub log_lines: Option<usize>,
+    // Seconds after which the command is killed
+    pub timeout: Option<u64>
+}
+
+#[derive(Clone, Debug)]
+struct LocalBuild {
+    id: i32,
+    branch: String,
+    commit: Option<String>,
+    state: ::BuildState,
+    status: ::BuildStatus,
+    status_text: Option<String>,
+    log_excerpt: Option<String>,
+    queued: i64,
+    cancelled: bool
+}
+
+pub struct Exec {
+    config: ExecConfig,
+    builds: Arc<Mutex<Vec<LocalBuild>>>,
+    // Held by the build using the workspace, so that builds run one at a time
+    workspace: Arc<Mutex<()>>
+}
+
+pub struct ExecFactory;
+
+impl registry::BuilderFactory for ExecFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+        match serde_json::from_value::<ExecConfig>(settings.to_owned()) {
+            Ok(config) => Ok(vec![Box::new(Exec::new(&config)) as Box<::ContinuousIntegrator>]),
+            Err(err) => Err(ConfigError::Parse(err))
+        }
+    }
+}
+
+// The last `lines` lines of `output`
+pub fn tail(output: &str, lines: usize) -> String {
+    let mut tail = output.lines().rev().take(lines).collect::<Vec<_>>();
+    tail.reverse();
+    tail.join("\n")
+}
+
+// What a command that exited with `code`, or was killed without one, did to the build
+pub fn outcome(code: Option<i32>, timed_out: bool) -> (::BuildStatus, String) {
+    match (code, timed_out) {
+        (_, true) => (::BuildStatus::Failure, "Timed out".to_owned()),
+        (Some(0), _) => (::BuildStatus::Success, "Exited with code 0".to_owned()),
+        (Some(code), _) => (::BuildStatus::Failure, format!("Exited with code {}", code)),
+        (None, _) => (::BuildStatus::Failure, "Killed by a signal".to_owned())
+    }
+}
+
+// IDs are the second a build was queued, or one more than the last, so they stay unique across restarts
+pub fn next_id(last: Option<i32>, now: i64) -> i32 {
+    match last {
+        Some(last) if i64::from(last) >= now => last + 1,
+        _ => now as i32
+    }
+}
+
+fn log_path(logs: &str, id: i32) -> String {
+    format!("{}/{}.log", logs.trim_end_matches('/'), id)
+}
+
+fn git(workspace: Option<&str>, args: &[&str], log: &mut File) -> Result<String, String> {
+    let mut command = Command::new("git");
+    if let Some(workspace) = workspace {
+        command.arg("-C").arg(workspace);
+    }
+    match command.args(args).output() {
+        Ok(output) => {
+            let _ = log.write_all(&output.stderr);
+            match output.status.success() {
+                true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned()),
+                false => Err(format!("git {} exited with {}", args.join(" "), output.status))
+            }
+        },
+        Err(err) => Err(format!("Unable to run git: {}", err))
+    }
+}
+
+impl Exec {
+    pub fn new(config: &ExecConfig) -> Exec {
+        Exec {
+            config: config.to_owned(),
+            builds: Arc::new(Mutex::new(vec![])),
+            workspace: Arc::new(Mutex::new(()))
+        }
+    }
+
+    fn logs(&self) -> String {
+        self.config.logs.to_owned().unwrap_or(format!("{}.logs", self.config.workspace.trim_end_matches('/')))
+    }
+
+    fn web_url(&self, id: i32) -> String {
+        match self.config.log_url {
+            Some(ref log_url) => format!("{}/{}.log", log_url.trim_end_matches('/'), id),
+            None => log_path(&self.logs(), id)
+        }
+    }
+
+    fn to_build_details(&self, build: &LocalBuild) -> ::BuildDetails {
+        ::BuildDetails {
+            id: build.id,
+            build_id: self.config.name.to_owned().unwrap_or("exec".to_owned()),
+            web_url: self.web_url(build.id),
+            commit: build.commit.to_owned(),
+            state: build.state.to_owned(),
+            status: build.status.to_owned(),
+            status_text: build.status_text.to_owned(),
+            log_excerpt: build.log_excerpt.to_owned(),
+            coverage: None,
+            test_failures: None,
+            queue_wait: match build.state {
+                ::BuildState::Queued => Some(time::now_utc().to_timespec().sec - build.queued),
+                _ => None
+            },
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+}
+
+// Checks the branch out in the workspace, cloning it first if need be, and runs the command there
+struct Runner {
+    config: ExecConfig,
+    builds: Arc<Mutex<Vec<LocalBuild>>>,
+    id: i32,
+    branch: String,
+    log_path: String
+}
+
+impl Runner {
+    fn update<F>(&self, update: F) -> Option<LocalBuild> where F: FnOnce(&mut LocalBuild) {
+        let mut builds = self.builds.lock().expect("Local builds should not be poisoned");
+        match builds.iter_mut().find(|build| build.id == self.id) {
+            Some(build) => {
+                update(build);
+                Some(build.to_owned())
+            },
+            None => None
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.update(|_| {}).map_or(true, |build| build.cancelled)
+    }
+
+    fn finish(&self, status: ::BuildStatus, status_text: &str) {
+        let lines = self.config.log_lines.unwrap_or(20);
+        let mut output = String::new();
+        let _ = File::open(&self.log_path).and_then(|mut log| log.read_to_string(&mut output));
+        self.update(|build| {
+            build.state = ::BuildState::Finished;
+            build.log_excerpt = match status {
+                ::BuildStatus::Success => None,
+                _ => Some(tail(&output, lines))
+            };
+            build.status = status;
+            build.status_text = Some(status_text.to_owned());
+        });
+    }
+
+    fn checkout(&self, log: &mut File) -> Result<String, String> {
+        let workspace = self.config.workspace.as_str();
+        if !Path::new(workspace).join(".git").exists() {
+            if let Err(err) = git(None, &["clone", "--no-checkout", &self.config.url, workspace], log) {
+                return Err(err);
+            }
+        }
+        let steps: Vec<Vec<&str>> = vec![
+            vec!["fetch", "--force", "origin", &self.branch],
+            vec!["checkout", "--force", "--detach", "FETCH_HEAD"],
+            vec!["clean", "-ffdx"]
+        ];
+        for args in steps {
+            if let Err(err) = git(Some(workspace), &args, log) {
+                return Err(err);
+            }
+        }
+        git(Some(workspace), &["rev-parse", "HEAD"], log)
+    }
+
+    fn run(&self, workspace: &Mutex<()>) {
+        let _workspace = workspace.lock().expect("The workspace should not be poisoned");
+        if self.cancelled() {
+            return self.finish(::BuildStatus::Failure, "Cancelled");
+        }
+        let mut log = match File::create(&self.log_path) {
+            Ok(log) => log,
+            Err(err) => return self.finish(::BuildStatus::Failure, &format!("Unable to write the log: {}", err))
+        };
+        let commit = match self.checkout(&mut log) {
+            Ok(commit) => commit,
+            Err(err) => return self.finish(::BuildStatus::Failure, &err)
+        };
+        self.update(|build| {
+            build.commit = Some(commit.to_owned());
+            build.state = ::BuildState::Running;
+        });
+
+        let (stdout, stderr) = match (log.try_clone(), log.try_clone()) {
+            (Ok(stdout), Ok(stderr)) => (stdout, stderr),
+            (Err(err), _) | (_, Err(err)) => {
+                return self.finish(::BuildStatus::Failure, &format!("Unable to write the log: {}", err))
+            }
+        };
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.config.command)
+            .current_dir(&self.config.workspace)
+            .env("PR_DEMON_BRANCH", &self.branch)
+            .env("PR_DEMON_COMMIT", &commit)
+            .env("PR_DEMON_BUILD_ID", self.id.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::from(stdout))
+            .stderr(Stdio::from(stderr))
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => return self.finish(::BuildStatus::Failure, &format!("Unable to run the command: {}", err))
+        };
+
+        // Polled rather than waited for, so that the build can be cancelled or time out
+        let started = time::now_utc().to_timespec().sec;
+        loop {
+            match child.try_wait() {
+                Ok(Some(exit)) => {
+                    let (status, text) = outcome(exit.code(), false);
+                    return self.finish(status, &text);
+                },
+                Ok(None) => {},
+                Err(err) => {
+                    return self.finish(::BuildStatus::Failure, &format!("Unable to wait for the command: {}", err))
+                }
+            }
+            let timed_out = self.config.timeout
+                .map_or(false, |timeout| time::now_utc().to_timespec().sec - started >= timeout as i64);
+            if timed_out || self.cancelled() {
+                let _ = child.kill();
+                let _ = child.wait();
+                return match timed_out {
+                    true => {
+                        let (status, text) = outcome(None, true);
+                        self.finish(status, &text)
+                    },
+                    false => self.finish(::BuildStatus::Failure, "Cancelled")
+                };
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+impl ::ContinuousIntegrator for Exec {
+    fn name(&self) -> Option<&str> {
+        Some(self.config.name.as_ref().map_or("exec", String::as_str))
+    }
+
+    fn get_build_list(&self, branch: &str) -> Result<Vec<::Build>, BuilderError> {
+        let builds = self.builds.lock().expect("Local builds should not be poisoned");
+        Ok(builds.iter().rev()
+            .filter(|build| build.branch == branch)
+            .map(|build| ::Build { id: build.id })
+            .collect())
+    }
+
+    fn get_build(&self, build_id: i32) -> Result<::BuildDetails, BuilderError> {
+        let builds = self.builds.lock().expect("Local builds should not be poisoned");
+        match builds.iter().find(|build| build.id == build_id) {
+            Some(build) => Ok(self.to_build_details(build)),
+            None => Err(BuilderError::InvalidResponse(format!("Unknown local build {}", build_id)))
+        }
+    }
+
+    fn queue_build(&self, branch: &str) -> Result<::BuildDetails, BuilderError> {
+        let logs = self.logs();
+        if let Err(err) = fs::create_dir_all(&logs) {
+            return Err(BuilderError::InvalidResponse(format!("Unable to create {}: {}", logs, err)));
+        }
+        let now = time::now_utc().to_timespec().sec;
+        let build = {
+            let mut builds = self.builds.lock().expect("Local builds should not be poisoned");
+            let build = LocalBuild {
+                id: next_id(builds.last().map(|build| build.id), now),
+                branch: branch.to_owned(),
+                commit: None,
+                state: ::BuildState::Queued,
+                status: ::BuildStatus::Unknown,
+                status_text: None,
+                log_excerpt: None,
+                queued: now,
+                cancelled: false
+            };
+            builds.push(build.to_owned());
+            if builds.len() > KEPT_BUILDS {
+                let forgotten = builds.len() - KEPT_BUILDS;
+                builds.drain(..forgotten);
+            }
+            build
+        };
+
+        let runner = Runner {
+            config: self.config.to_owned(),
+            builds: self.builds.clone(),
+            id: build.id,
+            branch: branch.to_owned(),
+            log_path: log_path(&logs, build.id)
+        };
+        let workspace = self.workspace.clone();
+        thread::spawn(move || runner.run(&workspace));
+        Ok(self.to_build_details(&build))
+    }
+
+    fn get_personal_build_list(&self, _: &str) -> Result<Vec<::Build>, BuilderError> {
+        Ok(vec![])
+    }
+
+    fn queue_personal_build(&self, _: &str, _: &str) -> Result<::BuildDetails, BuilderError> {
+        Err(BuilderError::InvalidResponse("Local builds do not support personal builds".to_owned()))
+    }
+
+    fn tag_build(&self, _: &::BuildDetails, _: &Vec<String>) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn pin_build(&self, _: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn comment_build(&self, _: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    // Queued builds are dropped when their turn comes, running ones are killed within a second
+    fn cancel_build(&self, build: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        let mut builds = self.builds.lock().expect("Local builds should not be poisoned");
+        match builds.iter_mut().find(|local| local.id == build.id) {
+            Some(local) => {
+                local.cancelled = true;
+                Ok(())
+            },
+            None => Err(BuilderError::InvalidResponse(format!("Unknown local build {}", build.id)))
+        }
+    }
+
+    fn get_coverage(&self, _: &::BuildDetails, _: &str) -> Result<Option<::Coverage>, BuilderError> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_id, outcome, tail};
+
+    #[test]
+    fn the_tail_of_the_output_is_kept() {
+        assert_eq!("three\nfour", tail("one\ntwo\nthree\nfour\n", 2));
+        assert_eq!("one", tail("one", 20));
+    }
+
+    #[test]
+    fn exit_codes_decide_the_status() {
+        assert_eq!((::BuildStatus::Success, "Exited with code 0".to_owned()), outcome(Some(0), false));
+        assert_eq!((::BuildStatus::Failure, "Exited with code 2".to_owned()), outcome(Some(2), false));
+        assert_eq!((::BuildStatus::Failure, "Killed by a signal".to_owned()), outcome(None, false));
+        assert_eq!((::BuildStatus::Failure, "Timed out".to_owned()), outcome(None, true));
+    }
+
+    #[test]
+    fn ids_increase_even_within_a_second() {
+        assert_eq!(1500000000, next_id(None, 1500000000));
+        assert_eq!(1500000001, next_id(Some(1500000000), 1500000000));
+        assert_eq!(1500000010, next_id(Some(1500000000), 1500000010));
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Mutex;
+
+use ::errors::ConfigError;
+use ::i18n::Messages;
+use ::retry::Action;
+use ::store::JsonStore;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct FlakyConfig {
+    // A JSON file keeping the outcomes across restarts. Kept in memory only unless set.
+    pub path: Option<String>,
+    // Builds a commit once more the first time it fails after having passed
+    pub retry: Option<bool>
+}
+
+// How a finished build of a pull request's commit on one of its builders turned out
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Outcome {
+    // The watch the pull request belongs to, when several repositories are watched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    pub pr: i32,
+    pub builder: String,
+    pub commit: String,
+    pub build: i32,
+    pub passed: bool,
+    // Whether the commit was built again because of this failure
+    #[serde(default)]
+    pub retried: bool
+}
+
+// A failure of a commit that has passed before, on the same builder and without any change
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Verdict {
+    Reliable,
+    Flaky,
+    // Flaky for the first time, so worth building once more
+    FlakyRetry
+}
+
+pub struct OutcomeStore {
+    retry: bool,
+    outcomes: JsonStore<Vec<Outcome>>
+}
+
+lazy_static! {
+    static ref STORE: Mutex<Option<OutcomeStore>> = Mutex::new(None);
+}
+
+impl OutcomeStore {
+    // A store without a file yet is empty
+    pub fn load(config: &FlakyConfig) -> Result<OutcomeStore, ConfigError> {
+        JsonStore::load(&config.path).map(|outcomes| OutcomeStore {
+            retry: config.retry == Some(true),
+            outcomes: outcomes
+        })
+    }
+
+    // Records `outcome` unless its build has been already, replacing those of earlier commits of the pull request on
+    // the builder, and tells whether it is a flaky failure
+    pub fn record(&mut self, outcome: Outcome) -> Result<Verdict, ConfigError> {
+        let same_commit = |other: &Outcome| other.repository == outcome.repository && other.pr == outcome.pr
+            && other.builder == outcome.builder && other.commit == outcome.commit;
+        let passed_before = self.outcomes.iter().any(|other| same_commit(other) && other.passed);
+        let retried = self.outcomes.iter().any(|other| same_commit(other) && other.retried);
+        let verdict = match (outcome.passed, passed_before, retried) {
+            (false, true, false) if self.retry => Verdict::FlakyRetry,
+            (false, true, _) => Verdict::Flaky,
+            _ => Verdict::Reliable
+        };
+        if self.outcomes.iter().any(|other| same_commit(other) && other.build == outcome.build) {
+            return Ok(verdict);
+        }
+
+        self.outcomes.retain(|other| other.repository != outcome.repository || other.pr != outcome.pr
+            || other.builder != outcome.builder || other.commit == outcome.commit);
+        self.outcomes.push(Outcome { retried: verdict == Verdict::FlakyRetry, ..outcome });
+        match self.outcomes.save() {
+            Ok(_) => Ok(verdict),
+            Err(err) => Err(err)
+        }
+    }
+
+    // Pull requests of the repository that are no longer open will not be built again
+    pub fn forget_closed(&mut self, repository: Option<&str>, open: &[::PullRequest]) -> Result<(), ConfigError> {
+        let before = self.outcomes.len();
+        self.outcomes.retain(|outcome| outcome.repository.as_ref().map(String::as_str) != repository
+            || open.iter().any(|pr| pr.id == outcome.pr));
+        match self.outcomes.len() == before {
+            true => Ok(()),
+            false => self.outcomes.save()
+        }
+    }
+}
+
+pub fn enable(store: OutcomeStore) {
+    *STORE.lock().expect("Build outcomes should not be poisoned") = Some(store);
+}
+
+// Records how a finished `build` turned out, noting on it when it is a flaky failure, unless its commit is to be
+// built again. Builds are always reported when no store is enabled.
+pub fn check(repository: Option<&str>, pr: &::PullRequest, ci: &::ContinuousIntegrator, build: ::BuildDetails,
+        messages: &Messages) -> Action {
+    if build.state != ::BuildState::Finished || build.status == ::BuildStatus::Unknown {
+        return Action::Report(build);
+    }
+    let outcome = Outcome {
+        repository: repository.map(str::to_owned),
+        pr: pr.id,
+        builder: ci.name().unwrap_or("").to_owned(),
+        commit: build.commit.to_owned().unwrap_or(pr.from_commit.to_owned()),
+        build: build.id,
+        passed: build.status == ::BuildStatus::Success,
+        retried: false
+    };
+    let verdict = match *STORE.lock().expect("Build outcomes should not be poisoned") {
+        Some(ref mut store) => store.record(outcome),
+        None => return Action::Report(build)
+    };
+    match verdict {
+        Ok(Verdict::Reliable) => Action::Report(build),
+        Ok(Verdict::Flaky) => {
+            let mut build = build;
+            build.notes.push(messages.format("flaky.note", &[("marker", messages.labels().warning())]));
+            Action::Report(build)
+        },
+        Ok(Verdict::FlakyRetry) => Action::Retry(1),
+        Err(err) => {
+            println!("Unable to store the outcome of build {}: {}", build.web_url, err);
+            Action::Report(build)
+        }
+    }
+}
+
+pub fn forget_closed(repository: Option<&str>, open: &[::PullRequest]) {
+    if let Some(ref mut store) = *STORE.lock().expect("Build outcomes should not be poisoned") {
+        if let Err(err) = store.forget_closed(repository, open) {
+            println!("Unable to store the build outcomes of open Pull Requests: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stubs::pull_request;
+    use super::{FlakyConfig, Outcome, OutcomeStore, Verdict};
+
+    fn store(retry: bool) -> OutcomeStore {
+        OutcomeStore::load(&FlakyConfig { path: None, retry: Some(retry) }).unwrap()
+    }
+
+    fn outcome(commit: &str, build: i32, passed: bool) -> Outcome {
+        Outcome {
+            repository: None,
+            pr: 1,
+            builder: "Build".to_owned(),
+            commit: commit.to_owned(),
+            build: build,
+            passed: passed,
+            retried: false
+        }
+    }
+
+    #[test]
+    fn failures_of_commits_that_passed_before_are_flaky() {
+        let mut store = store(false);
+        assert_eq!(Verdict::Reliable, store.record(outcome("abc", 1, false)).unwrap());
+        assert_eq!(Verdict::Reliable, store.record(outcome("abc", 2, true)).unwrap());
+        assert_eq!(Verdict::Flaky, store.record(outcome("abc", 3, false)).unwrap());
+        assert_eq!(Verdict::Flaky, store.record(outcome("abc", 3, false)).unwrap());
+        assert_eq!(Verdict::Reliable, store.record(outcome("def", 4, false)).unwrap());
+        assert_eq!(Verdict::Reliable, store.record(Outcome { builder: "Lint".to_owned(), ..outcome("abc", 5, false) })
+            .unwrap());
+    }
+
+    #[test]
+    fn flaky_commits_are_retried_once() {
+        let mut store = store(true);
+        store.record(outcome("abc", 1, true)).unwrap();
+        assert_eq!(Verdict::FlakyRetry, store.record(outcome("abc", 2, false)).unwrap());
+        assert_eq!(Verdict::Flaky, store.record(outcome("abc", 2, false)).unwrap());
+        assert_eq!(Verdict::Flaky, store.record(outcome("abc", 3, false)).unwrap());
+    }
+
+    #[test]
+    fn closed_pull_requests_are_forgotten() {
+        let mut store = store(false);
+        store.record(outcome("abc", 1, true)).unwrap();
+        store.forget_closed(None, &[::PullRequest { id: 2, ..pull_request() }]).unwrap();
+        assert_eq!(Verdict::Reliable, store.record(outcome("abc", 2, false)).unwrap());
+    }
+}
@@ -0,0 +1,393 @@
+use std::path::Path;
+use git2::{self, Commit, Cred, Delta, Diff, FetchOptions, Index, ObjectType, PushOptions, RemoteCallbacks, Repository,
+           Sort};
+use git2::build::RepoBuilder;
+
+use ::errors::GitError;
+use ::large_files::AddedFile;
+
+// A local clone of the watched repository, answering questions about pull requests without calls to the REST API
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct GitConfig {
+    pub url: String,
+    // Where the clone is kept. It is cloned there if it does not exist yet.
+    pub path: String,
+    pub username: Option<String>,
+    pub password: Option<String>
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Changes {
+    pub files: Vec<String>,
+    pub insertions: usize,
+    pub deletions: usize
+}
+
+impl Changes {
+    pub fn lines(&self) -> usize {
+        self.insertions + self.deletions
+    }
+}
+
+pub struct LocalClone {
+    config: GitConfig,
+    repository: Repository
+}
+
+fn failed(context: &str, source: git2::Error) -> GitError {
+    GitError::Git { context: context.to_owned(), source: source }
+}
+
+impl LocalClone {
+    pub fn open(config: &GitConfig) -> Result<LocalClone, GitError> {
+        let repository = match Path::new(&config.path).exists() {
+            true => Repository::open(&config.path).map_err(|err| failed(&format!("opening {}", config.path), err)),
+            false => {
+                println!("Cloning {} into {}", config.url, config.path);
+                let mut builder = RepoBuilder::new();
+                builder.bare(true).fetch_options(fetch_options(config));
+                builder.clone(&config.url, Path::new(&config.path))
+                    .map_err(|err| failed(&format!("cloning {}", config.url), err))
+            }
+        };
+        match repository {
+            Ok(repository) => Ok(LocalClone { config: config.to_owned(), repository: repository }),
+            Err(err) => Err(err)
+        }
+    }
+
+    pub fn fetch(&self, refspecs: &[String]) -> Result<(), GitError> {
+        let mut remote = match self.repository.find_remote("origin") {
+            Ok(remote) => remote,
+            Err(err) => return Err(failed("finding the origin remote", err))
+        };
+        let refspecs: Vec<&str> = refspecs.iter().map(String::as_str).collect();
+        remote.fetch(&refspecs, Some(&mut fetch_options(&self.config)), None)
+            .map_err(|err| failed(&format!("fetching {}", refspecs.join(" ")), err))
+    }
+
+    pub fn push(&self, refspecs: &[String]) -> Result<(), GitError> {
+        let mut remote = match self.repository.find_remote("origin") {
+            Ok(remote) => remote,
+            Err(err) => return Err(failed("finding the origin remote", err))
+        };
+        let refspecs: Vec<&str> = refspecs.iter().map(String::as_str).collect();
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks(&self.config));
+        remote.push(&refspecs, Some(&mut options))
+            .map_err(|err| failed(&format!("pushing {}", refspecs.join(" ")), err))
+    }
+
+    // Fetches both sides of `pr`. Branches of forks are fetched from the `refs/pull-requests` Bitbucket keeps for them.
+    pub fn fetch_pull_request(&self, pr: &::PullRequest) -> Result<(), GitError> {
+        let from = match pr.from_fork {
+            true => format!("refs/pull-requests/{}/from", pr.id),
+            false => pr.from_ref.to_owned()
+        };
+        self.fetch(&[
+            format!("+{}:refs/pr_demon/{}/from", from, pr.id),
+            format!("+{}:refs/pr_demon/{}/to", pr.to_ref, pr.id)
+        ])
+    }
+
+    fn commit<'a>(&'a self, revision: &str) -> Result<Commit<'a>, GitError> {
+        let object = self.repository.revparse_single(revision).and_then(|object| object.peel(ObjectType::Commit));
+        match object.map(|object| object.into_commit()) {
+            Ok(Ok(commit)) => Ok(commit),
+            _ => Err(GitError::UnknownRevision(revision.to_owned()))
+        }
+    }
+
+    // What `head` changes since it branched off `base`
+    // The changes `head` makes since it diverged from `base`
+    fn diff<'a>(&'a self, base: &str, head: &str) -> Result<(Diff<'a>, String), GitError> {
+        let (base, head) = match (self.commit(base), self.commit(head)) {
+            (Ok(base), Ok(head)) => (base, head),
+            (Err(err), _) | (_, Err(err)) => return Err(err)
+        };
+        let context = format!("comparing {} with {}", head.id(), base.id());
+        let diff = self.repository.merge_base(base.id(), head.id())
+            .and_then(|merge_base| self.repository.find_commit(merge_base))
+            .and_then(|merge_base| merge_base.tree())
+            .and_then(|from| head.tree().map(|to| (from, to)))
+            .and_then(|(from, to)| self.repository.diff_tree_to_tree(Some(&from), Some(&to), None));
+        match diff {
+            Ok(diff) => Ok((diff, context)),
+            Err(err) => Err(failed(&context, err))
+        }
+    }
+
+    pub fn changes(&self, base: &str, head: &str) -> Result<Changes, GitError> {
+        let (diff, context) = match self.diff(base, head) {
+            Ok(diff) => diff,
+            Err(err) => return Err(err)
+        };
+        let stats = match diff.stats() {
+            Ok(stats) => stats,
+            Err(err) => return Err(failed(&context, err))
+        };
+
+        let files = diff.deltas()
+            .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        Ok(Changes {
+            files: files,
+            insertions: stats.insertions(),
+            deletions: stats.deletions()
+        })
+    }
+
+    // The files `head` adds or changes since it diverged from `base`, as they are at `head`
+    pub fn added_files(&self, base: &str, head: &str) -> Result<Vec<AddedFile>, GitError> {
+        let (diff, context) = match self.diff(base, head) {
+            Ok(diff) => diff,
+            Err(err) => return Err(err)
+        };
+        let mut files = vec![];
+        for delta in diff.deltas().filter(|delta| delta.status() != Delta::Deleted) {
+            let path = match delta.new_file().path() {
+                Some(path) => path.to_string_lossy().into_owned(),
+                None => continue
+            };
+            // Submodules are commits rather than blobs
+            let blob = match self.repository.find_blob(delta.new_file().id()) {
+                Ok(blob) => blob,
+                Err(ref err) if err.code() == git2::ErrorCode::NotFound => continue,
+                Err(err) => return Err(failed(&context, err))
+            };
+            files.push(AddedFile {
+                path: path,
+                size: blob.size() as u64,
+                binary: blob.is_binary()
+            });
+        }
+        Ok(files)
+    }
+
+    // The files that conflict when `head` is merged into `base`
+    pub fn conflicts(&self, base: &str, head: &str) -> Result<Vec<String>, GitError> {
+        let (base, head) = match (self.commit(base), self.commit(head)) {
+            (Ok(base), Ok(head)) => (base, head),
+            (Err(err), _) | (_, Err(err)) => return Err(err)
+        };
+        let context = format!("merging {} into {}", head.id(), base.id());
+        let index = match self.repository.merge_commits(&base, &head, None) {
+            Ok(index) => index,
+            Err(err) => return Err(failed(&context, err))
+        };
+        conflict_paths(&index, &context)
+    }
+
+    // `commits` with parents before their children, leaving out those `base` already has
+    fn topological<'a>(&'a self, commits: &[String], base: &Commit) -> Result<Vec<Commit<'a>>, GitError> {
+        let context = format!("ordering {}", commits.join(" "));
+        let mut walk = match self.repository.revwalk() {
+            Ok(walk) => walk,
+            Err(err) => return Err(failed(&context, err))
+        };
+        if let Err(err) = walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE).and_then(|_| walk.hide(base.id())) {
+            return Err(failed(&context, err));
+        }
+        let mut ids = vec![];
+        for commit in commits {
+            let id = match self.commit(commit) {
+                Ok(commit) => commit.id(),
+                Err(err) => return Err(err)
+            };
+            if let Err(err) = walk.push(id) {
+                return Err(failed(&context, err));
+            }
+            ids.push(id);
+        }
+
+        let mut ordered = vec![];
+        for id in walk {
+            match id.and_then(|id| self.repository.find_commit(id)) {
+                Ok(commit) => if ids.contains(&commit.id()) {
+                    ordered.push(commit);
+                },
+                Err(err) => return Err(failed(&context, err))
+            }
+        }
+        Ok(ordered)
+    }
+
+    // Cherry-picks `commits` onto `onto` and points the local `branch` at the result. When one of them does not apply,
+    // the files it conflicts in are returned and `branch` is left alone.
+    pub fn cherry_pick(&self, commits: &[String], onto: &str, branch: &str) -> Result<Vec<String>, GitError> {
+        let mut head = match self.commit(onto) {
+            Ok(head) => head,
+            Err(err) => return Err(err)
+        };
+        let ordered = match self.topological(commits, &head) {
+            Ok(ordered) => ordered,
+            Err(err) => return Err(err)
+        };
+
+        for commit in ordered {
+            let context = format!("cherry-picking {} onto {}", commit.id(), head.id());
+            let index = commit.parent(0)
+                .and_then(|parent| parent.tree())
+                .and_then(|ancestor| head.tree().map(|ours| (ancestor, ours)))
+                .and_then(|(ancestor, ours)| commit.tree().map(|theirs| (ancestor, ours, theirs)))
+                .and_then(|(ancestor, ours, theirs)| self.repository.merge_trees(&ancestor, &ours, &theirs, None));
+            let mut index = match index {
+                Ok(index) => index,
+                Err(err) => return Err(failed(&context, err))
+            };
+            if index.has_conflicts() {
+                return conflict_paths(&index, &context);
+            }
+
+            let message = format!("{}\n\n(cherry picked from commit {})", commit.message().unwrap_or("").trim_end(),
+                commit.id());
+            let picked = index.write_tree_to(&self.repository)
+                .and_then(|tree| self.repository.find_tree(tree))
+                .and_then(|tree| {
+                    self.repository.commit(None, &commit.author(), &commit.committer(), &message, &tree, &[&head])
+                })
+                .and_then(|picked| self.repository.find_commit(picked));
+            head = match picked {
+                Ok(picked) => picked,
+                Err(err) => return Err(failed(&context, err))
+            };
+        }
+
+        let reference = format!("refs/heads/{}", branch);
+        match self.repository.reference(&reference, head.id(), true, &format!("cherry-pick onto {}", onto)) {
+            Ok(_) => Ok(vec![]),
+            Err(err) => Err(failed(&format!("updating {}", reference), err))
+        }
+    }
+}
+
+fn conflict_paths(index: &Index, context: &str) -> Result<Vec<String>, GitError> {
+    if !index.has_conflicts() {
+        return Ok(vec![]);
+    }
+    let conflicts = match index.conflicts() {
+        Ok(conflicts) => conflicts,
+        Err(err) => return Err(failed(context, err))
+    };
+
+    let mut paths = vec![];
+    for conflict in conflicts {
+        let conflict = match conflict {
+            Ok(conflict) => conflict,
+            Err(err) => return Err(failed(context, err))
+        };
+        if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+            paths.push(String::from_utf8_lossy(&entry.path).into_owned());
+        }
+    }
+    Ok(paths)
+}
+
+fn callbacks<'a>(config: &'a GitConfig) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    if let (&Some(ref username), &Some(ref password)) = (&config.username, &config.password) {
+        callbacks.credentials(move |_, _, _| Cred::userpass_plaintext(username, password));
+    }
+    callbacks
+}
+
+fn fetch_options<'a>(config: &'a GitConfig) -> FetchOptions<'a> {
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks(config));
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use git2::{Oid, Repository, Signature};
+    use stubs;
+    use large_files::AddedFile;
+    use super::{Changes, GitConfig, LocalClone};
+
+    fn commit(repository: &Repository, branch: &str, parent: Option<Oid>, files: &[(&str, &str)]) -> Oid {
+        let parent = parent.map(|parent| repository.find_commit(parent).unwrap());
+        let parent_tree = parent.as_ref().map(|parent| parent.tree().unwrap());
+        let mut tree = repository.treebuilder(parent_tree.as_ref()).unwrap();
+        for &(name, contents) in files {
+            tree.insert(name, repository.blob(contents.as_bytes()).unwrap(), 0o100644).unwrap();
+        }
+        let tree = repository.find_tree(tree.write().unwrap()).unwrap();
+        let signature = Signature::now("pr_demon", "pr_demon@example.com").unwrap();
+        let parents: Vec<_> = parent.iter().collect();
+        repository.commit(Some(&format!("refs/heads/{}", branch)), &signature, &signature, branch, &tree, &parents)
+            .unwrap()
+    }
+
+    // master changes the line feature appends to, so merging feature into master conflicts
+    fn origin(name: &str) -> String {
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&path);
+        let repository = Repository::init_bare(&path).unwrap();
+        let base = commit(&repository, "master", None, &[("a.txt", "one\n")]);
+        commit(&repository, "feature", Some(base), &[("a.txt", "one\ntwo\n"), ("b.txt", "new\n")]);
+        commit(&repository, "master", Some(base), &[("a.txt", "uno\n")]);
+        commit(&repository, "docs", Some(base), &[("c.txt", "docs\n")]);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn clone(name: &str) -> LocalClone {
+        let path = env::temp_dir().join(format!("{}_clone", name));
+        let _ = fs::remove_dir_all(&path);
+        LocalClone::open(&GitConfig {
+            url: origin(name),
+            path: path.to_string_lossy().into_owned(),
+            username: None,
+            password: None
+        }).unwrap()
+    }
+
+    #[test]
+    fn changes_are_counted_from_the_merge_base() {
+        let clone = clone("pr_demon_git_changes");
+        let changes = clone.changes("origin/master", "origin/feature").unwrap();
+        assert_eq!(Changes { files: vec!["a.txt".to_owned(), "b.txt".to_owned()], insertions: 2, deletions: 0 },
+            changes);
+        assert_eq!(2, changes.lines());
+    }
+
+    #[test]
+    fn added_files_are_measured() {
+        let clone = clone("pr_demon_git_added_files");
+        let files = clone.added_files("origin/master", "origin/feature").unwrap();
+        assert_eq!(vec![
+            AddedFile { path: "a.txt".to_owned(), size: 8, binary: false },
+            AddedFile { path: "b.txt".to_owned(), size: 4, binary: false }
+        ], files);
+    }
+
+    #[test]
+    fn conflicting_files_are_found() {
+        let clone = clone("pr_demon_git_conflicts");
+        assert_eq!(vec!["a.txt".to_owned()], clone.conflicts("origin/master", "origin/feature").unwrap());
+        assert!(clone.conflicts("origin/feature~1", "origin/feature").unwrap().is_empty());
+    }
+
+    #[test]
+    fn commits_are_cherry_picked_unless_they_conflict() {
+        let clone = clone("pr_demon_git_cherry_pick");
+        assert!(clone.cherry_pick(&["origin/docs".to_owned()], "origin/master", "backport").unwrap().is_empty());
+        assert_eq!(vec!["c.txt".to_owned()], clone.changes("origin/master", "backport").unwrap().files);
+        assert_eq!(vec!["a.txt".to_owned()],
+            clone.cherry_pick(&["origin/feature".to_owned()], "origin/master", "conflicted").unwrap());
+        assert!(clone.commit("conflicted").is_err());
+    }
+
+    #[test]
+    fn pull_request_refs_are_fetched() {
+        let clone = clone("pr_demon_git_fetch");
+        let pr = ::PullRequest {
+            from_ref: "refs/heads/feature".to_owned(),
+            ..stubs::pull_request()
+        };
+        clone.fetch_pull_request(&pr).unwrap();
+        assert_eq!(2, clone.changes("refs/pr_demon/111/to", "refs/pr_demon/111/from").unwrap().files.len());
+    }
+}
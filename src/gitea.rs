@@ -0,0 +1,649 @@
+use std::sync::Arc;
+
+use hyper;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use time;
+
+use ::credentials;
+use ::errors::{ApiError, ConfigError, RepositoryError};
+use ::events;
+use ::fanout;
+use ::registry;
+use ::i18n;
+use ::rest;
+use ::templates;
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct PullRequest {
+    number: i32,
+    html_url: String,
+    title: String,
+    body: Option<String>,
+    updated_at: String,
+    #[serde(default)]
+    merged: bool,
+    head: GitReference,
+    base: GitReference,
+    user: User,
+    // Null rather than empty when nobody has been asked
+    requested_reviewers: Option<Vec<User>>
+}
+
+impl PullRequest {
+    fn to_pull_request(&self) -> ::PullRequest {
+        ::PullRequest {
+            id: self.number,
+            web_url: self.html_url.to_owned(),
+            from_ref: format!("refs/heads/{}", self.head.branch),
+            from_commit: self.head.sha.to_owned(),
+            to_ref: format!("refs/heads/{}", self.base.branch),
+            // The head repository is gone when the fork has been deleted
+            from_fork: match (&self.head.repo, &self.base.repo) {
+                (&Some(ref head), &Some(ref base)) => head.full_name != base.full_name,
+                _ => true
+            },
+            title: self.title.to_owned(),
+            description: self.body.to_owned().unwrap_or_default(),
+            updated: parse_timestamp(&self.updated_at),
+            author: ::User {
+                name: match self.user.full_name.as_ref().map(String::as_str) {
+                    Some("") | None => self.user.login.to_owned(),
+                    Some(full_name) => full_name.to_owned()
+                },
+                email: self.user.email.to_owned().unwrap_or_default(),
+                username: Some(self.user.login.to_owned())
+            },
+            reviewers: self.requested_reviewers.iter().flat_map(|reviewers| reviewers.iter())
+                .map(|reviewer| reviewer.login.to_owned())
+                .collect(),
+            approvals: vec![],
+            size: None,
+            open_tasks: None,
+            labels: vec![]
+        }
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct GitReference {
+    #[serde(rename = "ref")]
+    branch: String,
+    sha: String,
+    repo: Option<Repository>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Repository {
+    full_name: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct User {
+    login: String,
+    full_name: Option<String>,
+    email: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Review {
+    user: Option<User>,
+    state: String,
+    #[serde(default)]
+    dismissed: bool
+}
+
+// The reviewers whose latest review, in the order they were submitted, approves and has not been dismissed. Comments
+// neither approve nor take an approval back.
+fn approvers(reviews: &[Review]) -> Vec<String> {
+    let mut latest: Vec<(&str, bool)> = vec![];
+    let deciding = ["APPROVED", "REQUEST_CHANGES"];
+    for review in reviews.iter().filter(|review| deciding.contains(&review.state.as_str())) {
+        if let Some(ref user) = review.user {
+            latest.retain(|&(login, _)| login != user.login);
+            latest.push((&user.login, review.state == "APPROVED" && !review.dismissed));
+        }
+    }
+    latest.into_iter().filter(|&(_, approved)| approved).map(|(login, _)| login.to_owned()).collect()
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Comment {
+    id: i64,
+    body: String,
+    user: User
+}
+
+impl templates::OwnComment for Comment {
+    fn text(&self) -> &str {
+        &self.body
+    }
+
+    fn to_event(&self) -> events::PostedComment {
+        events::PostedComment {
+            id: self.id,
+            // Gitea comments are not versioned
+            version: 0,
+            text: self.body.to_owned()
+        }
+    }
+}
+
+impl Comment {
+    fn to_comment(&self) -> ::Comment {
+        ::Comment {
+            id: self.id,
+            author: self.user.login.to_owned(),
+            text: self.body.to_owned()
+        }
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Commit {
+    sha: String,
+    commit: CommitDetails,
+    parents: Vec<CommitParent>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitDetails {
+    message: String,
+    author: Option<CommitAuthor>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitAuthor {
+    name: String,
+    email: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitParent {
+    sha: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct Status {
+    state: StatusState,
+    context: String,
+    target_url: String,
+    description: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+enum StatusState {
+    Pending,
+    Success,
+    Failure
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct GiteaCredentials {
+    // The login the daemon comments as, so that it finds its own comments again
+    pub username: String,
+    // An access token, unless `credential_provider` is set
+    #[serde(default)]
+    pub token: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    // The instance's URL, such as "https://codeberg.org"
+    pub base_url: String,
+    pub owner: String,
+    pub repo: String,
+    pub post_build: bool,
+    pub templates: Option<templates::Templates>,
+    pub superseded_comments: Option<String>
+}
+
+pub struct GiteaFactory;
+
+impl registry::RepositoryFactory for GiteaFactory {
+    fn create(&self, settings: &serde_json::Value, messages: &i18n::Messages,
+        fanout: &fanout::Fanout<fanout::Message>) -> Result<Box<::Repository>, ConfigError> {
+        let credentials = match serde_json::from_value::<GiteaCredentials>(settings.to_owned()) {
+            Ok(credentials) => credentials,
+            Err(err) => return Err(ConfigError::Parse(err))
+        };
+        match Gitea::new(&credentials, messages, fanout) {
+            Ok(gitea) => Ok(Box::new(gitea)),
+            Err(err) => Err(ConfigError::Invalid(format!("Failed to load comment templates: {}", err)))
+        }
+    }
+}
+
+pub struct Gitea {
+    pub credentials: GiteaCredentials,
+    broadcaster: fanout::Fanout<fanout::Message>,
+    renderer: templates::Renderer
+}
+
+// Gitea reports times in its own time zone, such as "2019-04-08T16:37:46+08:00", which are turned into milliseconds
+// since the epoch like Bitbucket's
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let local = match timestamp.get(..19).map(|seconds| time::strptime(seconds, "%Y-%m-%dT%H:%M:%S")) {
+        Some(Ok(tm)) => tm.to_timespec().sec,
+        _ => return None
+    };
+    let offset = match timestamp.get(19..) {
+        Some("Z") | Some("") => 0,
+        Some(zone) if zone.len() == 6 => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            match (zone[1..3].parse::<i64>(), zone[4..6].parse::<i64>()) {
+                (Ok(hours), Ok(minutes)) => sign * (hours * 3600 + minutes * 60),
+                _ => return None
+            }
+        },
+        _ => return None
+    };
+    Some((local - offset) * 1000)
+}
+
+fn branch(reference: &str) -> &str {
+    reference.trim_start_matches("refs/heads/")
+}
+
+impl ::Repository for Gitea {
+    fn get_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.get_pr_list_by_state("open").map(|prs| prs.iter().map(PullRequest::to_pull_request).collect())
+    }
+
+    fn get_merged_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        // Closed pull requests include the declined ones
+        self.get_pr_list_by_state("closed").map(|prs| {
+            prs.iter().filter(|pr| pr.merged).map(PullRequest::to_pull_request).collect()
+        })
+    }
+
+    fn get_pr_diff(&self, pr: &::PullRequest) -> Result<String, RepositoryError> {
+        let url = format!("{}/pulls/{}.diff", self.repository_url(), pr.id);
+
+        let diff = credentials::authorized(&*self.provider(), |headers| rest::get_raw(&url, &headers.headers));
+        match diff {
+            Ok(ref response) if response.status != hyper::StatusCode::OK => {
+                Err(RepositoryError::api("getting diff", ApiError::Status(response.status)))
+            },
+            Ok(response) => Ok(response.body),
+            Err(err) => Err(RepositoryError::api("getting diff", err))
+        }
+    }
+
+    fn get_pr_commits(&self, pr: &::PullRequest) -> Result<Vec<::Commit>, RepositoryError> {
+        let url = format!("{}/pulls/{}/commits?limit=50", self.repository_url(), pr.id);
+
+        let commits = self.get_all::<Commit>(&url);
+        match commits {
+            Ok(commits) => Ok(commits.iter().map(|commit| ::Commit {
+                id: commit.sha.to_owned(),
+                message: commit.commit.message.to_owned(),
+                merge: commit.parents.len() > 1,
+                author: commit.commit.author.as_ref().map(|author| ::User {
+                    name: author.name.to_owned(),
+                    email: author.email.to_owned().unwrap_or_default(),
+                    username: None
+                })
+            }).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of commits", err))
+        }
+    }
+
+    fn get_pr_comments(&self, pr: &::PullRequest) -> Result<Vec<::Comment>, RepositoryError> {
+        match self.get_comments(pr.id) {
+            Ok(comments) => Ok(comments.iter().map(Comment::to_comment).collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn get_pr_approvals(&self, pr: &::PullRequest) -> Result<Vec<String>, RepositoryError> {
+        let url = format!("{}/pulls/{}/reviews?limit=50", self.repository_url(), pr.id);
+
+        match self.get_all::<Review>(&url) {
+            Ok(reviews) => Ok(approvers(&reviews)),
+            Err(err) => Err(RepositoryError::api("getting reviews", err))
+        }
+    }
+
+    fn build_queued(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_running(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_success(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "success")
+    }
+
+    fn build_failure(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "failure")
+    }
+
+    fn build_matrix(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>) -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_matrix(pr, builds) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::matrix(pr);
+        let comment = templates::upsert_comment(self, pr, &text, &key, "MatrixComment", |comment| {
+            events::MatrixCommentEvent {
+                pr: pr.to_owned(),
+                builds: builds.to_owned(),
+                comment: comment
+            }
+        });
+        if let Err(err) = comment {
+            return Err(err);
+        }
+        if self.credentials.post_build {
+            for build in builds {
+                if let Err(err) = self.post_status(&pr.from_commit, &Gitea::make_status(build)) {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn upsert_comment(&self, pr: &::PullRequest, key: &templates::CommentKey, text: &str)
+            -> Result<(), RepositoryError> {
+        templates::upsert_comment(self, pr, text, key, "Notice", |comment| events::NoticeEvent {
+            pr: pr.to_owned(),
+            kind: key.kind.to_owned(),
+            comment: comment
+        }).map(|_| ())
+    }
+
+    fn report_check(&self, pr: &::PullRequest, check: &::CheckResult) -> Result<(), RepositoryError> {
+        let status = Status {
+            state: match check.passed {
+                true => StatusState::Success,
+                false => StatusState::Failure
+            },
+            context: format!("pr_demon/{}", check.name),
+            target_url: pr.web_url.to_owned(),
+            description: check.summary.to_owned()
+        };
+        self.post_status(&pr.from_commit, &status)
+    }
+
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+            -> Result<::PullRequest, RepositoryError> {
+        let body = json!({
+            "title": title,
+            "body": description,
+            "head": branch(from_ref),
+            "base": branch(to_ref)
+        }).to_string();
+        let url = format!("{}/pulls", self.repository_url());
+
+        let pr = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match pr {
+            Ok(pr) => Ok(pr.to_pull_request()),
+            Err(err) => Err(RepositoryError::api("creating Pull Request", err))
+        }
+    }
+
+    fn decline_pr(&self, pr: &::PullRequest) -> Result<(), RepositoryError> {
+        let url = format!("{}/pulls/{}", self.repository_url(), pr.id);
+        let body = json!({ "state": "closed" }).to_string();
+        let declined = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::patch::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match declined {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("declining Pull Request", err))
+        }
+    }
+
+    // The strategy is one of Gitea's merge styles, "merge" unless set, "rebase", "rebase-merge" or "squash"
+    fn merge_pr(&self, pr: &::PullRequest, strategy: Option<&str>, message: Option<&str>)
+            -> Result<(), RepositoryError> {
+        let mut body = serde_json::Map::new();
+        body.insert("Do".to_owned(), json!(strategy.unwrap_or("merge")));
+        // The message's first line is the merge commit's title, as Gitea takes them separately
+        if let Some(message) = message {
+            let mut parts = message.splitn(2, '\n');
+            body.insert("MergeTitleField".to_owned(), json!(parts.next().unwrap_or("")));
+            body.insert("MergeMessageField".to_owned(), json!(parts.next().unwrap_or("").trim()));
+        }
+        // Merging fails rather than merging a commit that has not been built should the branch have moved on
+        body.insert("head_commit_id".to_owned(), json!(pr.from_commit));
+        let body = serde_json::Value::Object(body).to_string();
+        let url = format!("{}/pulls/{}/merge", self.repository_url(), pr.id);
+        // Gitea answers with an empty body
+        let merged = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_content_type_json_header();
+            rest::post_raw(&url, &body, &headers.headers)
+        });
+        match merged {
+            Ok(ref response) if response.status != hyper::StatusCode::OK => {
+                Err(RepositoryError::api("merging Pull Request", ApiError::Status(response.status)))
+            },
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("merging Pull Request", err))
+        }
+    }
+
+    fn add_reviewers(&self, pr: &::PullRequest, reviewers: &[String]) -> Result<(), RepositoryError> {
+        let url = format!("{}/pulls/{}/requested_reviewers", self.repository_url(), pr.id);
+        let body = json!({ "reviewers": reviewers }).to_string();
+        let added = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match added {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("adding reviewer", err))
+        }
+    }
+
+    // Older Gitea versions only branch off other branches, and ignore `old_ref_name`
+    fn create_branch(&self, name: &str, start_point: &str) -> Result<(), RepositoryError> {
+        let url = format!("{}/branches", self.repository_url());
+        let body = json!({
+            "new_branch_name": branch(name),
+            "old_ref_name": start_point,
+            "old_branch_name": branch(start_point)
+        }).to_string();
+        let created = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match created {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("creating branch", err))
+        }
+    }
+}
+
+impl Gitea {
+    pub fn new(credentials: &GiteaCredentials, messages: &i18n::Messages,
+        broadcaster: &fanout::Fanout<fanout::Message>) -> Result<Gitea, String> {
+        let renderer = match templates::Renderer::new(&credentials.templates, messages, &credentials.repo) {
+            Ok(renderer) => renderer,
+            Err(err) => return Err(err)
+        };
+
+        Ok(Gitea {
+            credentials: credentials.to_owned(),
+            broadcaster: broadcaster.to_owned(),
+            renderer: renderer
+        })
+    }
+
+    fn repository_url(&self) -> String {
+        format!("{}/api/v1/repos/{}/{}", self.credentials.base_url.trim_end_matches('/'),
+            self.credentials.owner, self.credentials.repo)
+    }
+
+    fn get_pr_list_by_state(&self, state: &str) -> Result<Vec<PullRequest>, RepositoryError> {
+        let url = format!("{}/pulls?state={}&sort=recentupdate&limit=50", self.repository_url(), state);
+
+        let prs = self.get_all::<PullRequest>(&url);
+        match prs {
+            Ok(prs) => Ok(prs),
+            Err(err) => Err(RepositoryError::api("getting list of Pull Requests", err))
+        }
+    }
+
+    // Collects the values of every page, following the `next` links of the `Link` header
+    fn get_all<T>(&self, url: &str) -> Result<Vec<T>, ApiError> where T: DeserializeOwned {
+        rest::get_all(url, |url| credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get_raw(url, &headers.headers)
+        }), rest::next_link)
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credentials.credential_provider,
+            credentials::Credential::Bearer(self.credentials.token.to_owned()))
+    }
+
+    fn report_build(&self, pr: &::PullRequest, build: &::BuildDetails, template: &str)
+            -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_build(template, pr, build) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::status(pr, build);
+        let comment = templates::upsert_comment(self, pr, &text, &key, "Comment", |comment| events::CommentEvent {
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            comment: comment
+        });
+        if let Err(err) = comment {
+            return Err(err);
+        }
+        match self.credentials.post_build {
+            true => self.post_status(&pr.from_commit, &Gitea::make_status(build)),
+            false => Ok(())
+        }
+    }
+
+    // Pull request comments are the comments of the issue every pull request is, and only the daemon's own are
+    // returned
+    fn get_comments(&self, pr_id: i32) -> Result<Vec<Comment>, RepositoryError> {
+        let url = format!("{}/issues/{}/comments", self.repository_url(), pr_id);
+
+        let comments = self.get_all::<Comment>(&url);
+        match comments {
+            Ok(comments) => Ok(comments),
+            Err(err) => Err(RepositoryError::api("getting list of comments", err))
+        }
+    }
+
+    fn post_status(&self, commit: &str, status: &Status) -> Result<(), RepositoryError> {
+        let body = serde_json::to_string(status).unwrap();
+        let url = format!("{}/statuses/{}", self.repository_url(), commit);
+
+        let posted = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match posted {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("posting build", err))
+        }
+    }
+
+    fn make_status(build: &::BuildDetails) -> Status {
+        let state = match build.state {
+            ::BuildState::Finished => {
+                match build.status {
+                    ::BuildStatus::Success => StatusState::Success,
+                    _ => StatusState::Failure
+                }
+            },
+            _ => StatusState::Pending
+        };
+
+        Status {
+            state: state,
+            context: build.build_id.to_owned(),
+            target_url: build.web_url.to_owned(),
+            description: build.status_text.to_owned().unwrap_or_default()
+        }
+    }
+}
+
+impl templates::CommentApi for Gitea {
+    type Comment = Comment;
+
+    fn renderer(&self) -> &templates::Renderer {
+        &self.renderer
+    }
+
+    fn superseded_comments(&self) -> Option<&str> {
+        self.credentials.superseded_comments.as_ref().map(String::as_str)
+    }
+
+    fn get_own_comments(&self, pr_id: i32) -> Result<Vec<Comment>, RepositoryError> {
+        match self.get_comments(pr_id) {
+            Ok(comments) => Ok(comments.into_iter()
+                .filter(|comment| comment.user.login == self.credentials.username)
+                .collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn post_comment(&self, pr_id: i32, text: &str) -> Result<Comment, RepositoryError> {
+        let body = json!({ "body": text }).to_string();
+        let url = format!("{}/issues/{}/comments", self.repository_url(), pr_id);
+
+        let comment = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match comment {
+            Ok(comment) => Ok(comment),
+            Err(err) => Err(RepositoryError::api("posting comment", err))
+        }
+    }
+
+    fn edit_comment(&self, _: i32, comment: &Comment, text: &str) -> Result<Comment, RepositoryError> {
+        let body = json!({ "body": text }).to_string();
+        let url = format!("{}/issues/comments/{}", self.repository_url(), comment.id);
+
+        let edited = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::patch::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match edited {
+            Ok(comment) => Ok(comment),
+            Err(err) => Err(RepositoryError::api("editing comment", err))
+        }
+    }
+
+    fn delete_comment(&self, _: i32, comment: &Comment) -> Result<(), RepositoryError> {
+        let url = format!("{}/issues/comments/{}", self.repository_url(), comment.id);
+
+        match credentials::authorized(&*self.provider(), |headers| rest::delete_raw(&url, &headers.headers)) {
+            Ok(ref response) if !response.status.is_success() => {
+                Err(RepositoryError::api("deleting comment", ApiError::Status(response.status)))
+            },
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("deleting comment", err))
+        }
+    }
+
+    fn broadcast<T>(&self, opcode: &str, payload: &T) where T : Serialize {
+        let opcode = fanout::OpCode::Custom {
+            payload: format!("Gitea::{}", opcode)
+        };
+        let message = fanout::Message::new(opcode, payload);
+        self.broadcaster.broadcast(&message);
+    }
+}
@@ -0,0 +1,987 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use hyper;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use time;
+
+use ::credentials;
+use ::errors::{ApiError, ConfigError, RepositoryError};
+use ::events;
+use ::fanout;
+use ::registry;
+use ::i18n;
+use ::rest;
+use ::templates;
+
+static DEFAULT_API_URL: &'static str = "https://api.github.com";
+
+// The open pull requests with what the daemon looks up about each of them, a page at a time
+static PULL_REQUESTS_QUERY: &'static str = "query($owner: String!, $repo: String!, $after: String) {
+  repository(owner: $owner, name: $repo) {
+    pullRequests(states: OPEN, first: 50, after: $after, orderBy: {field: UPDATED_AT, direction: DESC}) {
+      pageInfo { hasNextPage endCursor }
+      nodes {
+        number url title body updatedAt headRefName headRefOid baseRefName isCrossRepository
+        author { __typename login }
+        labels(first: 20) { nodes { name } }
+        reviewRequests(first: 20) { nodes { requestedReviewer { ... on User { login } } } }
+        latestReviews(first: 20) { nodes { state author { __typename login } } }
+        comments(last: 50) { totalCount nodes { fullDatabaseId body author { __typename login } } }
+      }
+    }
+  }
+}";
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct PullRequest {
+    number: i32,
+    html_url: String,
+    title: String,
+    body: Option<String>,
+    updated_at: String,
+    merged_at: Option<String>,
+    head: GitReference,
+    base: GitReference,
+    user: User,
+    #[serde(default)]
+    requested_reviewers: Vec<User>,
+    #[serde(default)]
+    labels: Vec<Label>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Label {
+    name: String
+}
+
+// Deleted accounts have no user
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct PullRequestReview {
+    user: Option<User>,
+    state: String
+}
+
+// The reviewers whose latest review, in the order they were submitted, approves. Comments neither approve nor take an
+// approval back.
+fn approvers(reviews: &[PullRequestReview]) -> Vec<String> {
+    let mut latest: Vec<(&str, &str)> = vec![];
+    for review in reviews.iter().filter(|review| review.state != "COMMENTED" && review.state != "PENDING") {
+        if let Some(ref user) = review.user {
+            latest.retain(|&(login, _)| login != user.login);
+            latest.push((&user.login, &review.state));
+        }
+    }
+    latest.into_iter().filter(|&(_, state)| state == "APPROVED").map(|(login, _)| login.to_owned()).collect()
+}
+
+impl PullRequest {
+    fn to_pull_request(&self) -> ::PullRequest {
+        ::PullRequest {
+            id: self.number,
+            web_url: self.html_url.to_owned(),
+            from_ref: format!("refs/heads/{}", self.head.branch),
+            from_commit: self.head.sha.to_owned(),
+            to_ref: format!("refs/heads/{}", self.base.branch),
+            // The head repository is gone when the fork has been deleted
+            from_fork: match (&self.head.repo, &self.base.repo) {
+                (&Some(ref head), &Some(ref base)) => head.full_name != base.full_name,
+                _ => true
+            },
+            title: self.title.to_owned(),
+            description: self.body.to_owned().unwrap_or_default(),
+            updated: parse_timestamp(&self.updated_at),
+            author: ::User {
+                name: self.user.login.to_owned(),
+                email: "".to_owned(),
+                username: Some(self.user.login.to_owned())
+            },
+            reviewers: self.requested_reviewers.iter().map(|reviewer| reviewer.login.to_owned()).collect(),
+            approvals: vec![],
+            size: None,
+            open_tasks: None,
+            labels: self.labels.iter().map(|label| label.name.to_owned()).collect()
+        }
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct GraphqlResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphqlError>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct GraphqlError {
+    message: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct RepositoryData {
+    repository: PullRequestsData
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PullRequestsData {
+    pull_requests: Connection<PullRequestNode>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Connection<T> {
+    page_info: Option<PageInfo>,
+    total_count: Option<usize>,
+    nodes: Vec<T>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PullRequestNode {
+    number: i32,
+    url: String,
+    title: String,
+    body: String,
+    updated_at: String,
+    head_ref_name: String,
+    head_ref_oid: String,
+    base_ref_name: String,
+    is_cross_repository: bool,
+    author: Option<Actor>,
+    labels: Connection<Label>,
+    review_requests: Connection<ReviewRequest>,
+    latest_reviews: Connection<Review>,
+    comments: Connection<CommentNode>
+}
+
+// Deleted accounts are no longer the author of anything
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Actor {
+    #[serde(rename = "__typename")]
+    typename: String,
+    login: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReviewRequest {
+    requested_reviewer: Option<Reviewer>
+}
+
+// Only users have a login, teams being asked for reviews too
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Reviewer {
+    login: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Review {
+    state: String,
+    author: Option<Actor>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CommentNode {
+    full_database_id: Option<String>,
+    body: String,
+    author: Option<Actor>
+}
+
+// The REST API names apps by their login with "[bot]" appended, as `username` does
+fn login(actor: &Option<Actor>) -> String {
+    match *actor {
+        Some(ref actor) if actor.typename == "Bot" => format!("{}[bot]", actor.login),
+        Some(ref actor) => actor.login.to_owned(),
+        None => "ghost".to_owned()
+    }
+}
+
+impl PullRequestNode {
+    fn to_pull_request(&self) -> ::PullRequest {
+        let author = login(&self.author);
+        ::PullRequest {
+            id: self.number,
+            web_url: self.url.to_owned(),
+            from_ref: format!("refs/heads/{}", self.head_ref_name),
+            from_commit: self.head_ref_oid.to_owned(),
+            to_ref: format!("refs/heads/{}", self.base_ref_name),
+            from_fork: self.is_cross_repository,
+            title: self.title.to_owned(),
+            description: self.body.to_owned(),
+            updated: parse_timestamp(&self.updated_at),
+            author: ::User {
+                name: author.to_owned(),
+                email: "".to_owned(),
+                username: Some(author)
+            },
+            reviewers: self.review_requests.nodes.iter()
+                .filter_map(|request| request.requested_reviewer.as_ref())
+                .filter_map(|reviewer| reviewer.login.to_owned())
+                .collect(),
+            approvals: self.latest_reviews.nodes.iter().filter(|review| review.state == "APPROVED")
+                .map(|review| login(&review.author)).collect(),
+            size: None,
+            open_tasks: None,
+            labels: self.labels.nodes.iter().map(|label| label.name.to_owned()).collect()
+        }
+    }
+
+    // The comments of the pull request, unless there are more than the query asks for
+    fn all_comments(&self) -> Option<Vec<Comment>> {
+        if self.comments.total_count.map_or(true, |count| count > self.comments.nodes.len()) {
+            return None;
+        }
+        Some(self.comments.nodes.iter().filter_map(|comment| {
+            comment.full_database_id.as_ref().and_then(|id| id.parse().ok()).map(|id| Comment {
+                id: id,
+                body: comment.body.to_owned(),
+                user: User { login: login(&comment.author) }
+            })
+        }).collect())
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct GitReference {
+    #[serde(rename = "ref")]
+    branch: String,
+    sha: String,
+    repo: Option<Repository>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Repository {
+    full_name: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct User {
+    login: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Comment {
+    id: i64,
+    body: String,
+    user: User
+}
+
+impl templates::OwnComment for Comment {
+    fn text(&self) -> &str {
+        &self.body
+    }
+
+    fn to_event(&self) -> events::PostedComment {
+        events::PostedComment {
+            id: self.id,
+            // GitHub comments are not versioned
+            version: 0,
+            text: self.body.to_owned()
+        }
+    }
+}
+
+impl Comment {
+    fn to_comment(&self) -> ::Comment {
+        ::Comment {
+            id: self.id,
+            author: self.user.login.to_owned(),
+            text: self.body.to_owned()
+        }
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Commit {
+    sha: String,
+    commit: CommitDetails,
+    parents: Vec<CommitParent>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitDetails {
+    message: String,
+    author: Option<CommitAuthor>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitAuthor {
+    name: String,
+    email: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct CommitParent {
+    sha: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct Status {
+    state: StatusState,
+    context: String,
+    target_url: String,
+    description: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+enum StatusState {
+    Pending,
+    Success,
+    Failure
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct GithubCredentials {
+    // The login the daemon comments as, so that it finds its own comments again
+    pub username: String,
+    // A personal access token, unless `credential_provider` is set
+    #[serde(default)]
+    pub token: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    // https://api.github.com unless set, such as to the `/api/v3` URL of a GitHub Enterprise server
+    pub api_url: Option<String>,
+    pub owner: String,
+    pub repo: String,
+    pub post_build: bool,
+    pub templates: Option<templates::Templates>,
+    pub superseded_comments: Option<String>,
+    // Lists open pull requests through the GraphQL API, along with their reviews and comments
+    pub graphql: Option<bool>,
+    // Days back merged pull requests are listed for, 7 unless set, as every closed pull request would otherwise be
+    // paged through on every poll
+    pub merged_within: Option<i64>
+}
+
+pub struct GithubFactory;
+
+impl registry::RepositoryFactory for GithubFactory {
+    fn create(&self, settings: &serde_json::Value, messages: &i18n::Messages,
+        fanout: &fanout::Fanout<fanout::Message>) -> Result<Box<::Repository>, ConfigError> {
+        let credentials = match serde_json::from_value::<GithubCredentials>(settings.to_owned()) {
+            Ok(credentials) => credentials,
+            Err(err) => return Err(ConfigError::Parse(err))
+        };
+        match Github::new(&credentials, messages, fanout) {
+            Ok(github) => Ok(Box::new(github)),
+            Err(err) => Err(ConfigError::Invalid(format!("Failed to load comment templates: {}", err)))
+        }
+    }
+}
+
+pub struct Github {
+    pub credentials: GithubCredentials,
+    broadcaster: fanout::Fanout<fanout::Message>,
+    renderer: templates::Renderer,
+    // The comments of the pull requests last listed through the GraphQL API, each used once and then looked up again
+    listed_comments: Mutex<HashMap<i32, Vec<Comment>>>
+}
+
+// GitHub reports times such as "2011-01-26T19:01:12Z", which are turned into milliseconds like Bitbucket's
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    time::strptime(timestamp, "%Y-%m-%dT%H:%M:%SZ").ok().map(|tm| tm.to_timespec().sec * 1000)
+}
+
+fn branch(reference: &str) -> &str {
+    reference.trim_start_matches("refs/heads/")
+}
+
+impl ::Repository for Github {
+    fn get_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        match self.credentials.graphql {
+            Some(true) => self.query_pr_list(),
+            _ => self.get_pr_list_by_state("open").map(|prs| prs.iter().map(PullRequest::to_pull_request).collect())
+        }
+    }
+
+    fn get_merged_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        let now = time::now_utc().to_timespec().sec * 1000;
+        let since = now - self.credentials.merged_within.unwrap_or(7) * 24 * 60 * 60 * 1000;
+        let recent = |pr: &PullRequest| parse_timestamp(&pr.updated_at).map_or(true, |updated| updated >= since);
+        // Closed pull requests include the declined ones, and are listed from the most recently updated
+        let url = format!("{}/pulls?state=closed&sort=updated&direction=desc&per_page=100", self.repository_url());
+        let prs = rest::get_all_while(&url, |url| credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get_raw(url, &headers.headers)
+        }), rest::next_link, |page: &[PullRequest]| page.last().map_or(false, &recent));
+        match prs {
+            Ok(prs) => Ok(prs.iter().filter(|pr| pr.merged_at.is_some() && recent(pr))
+                .map(PullRequest::to_pull_request).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of Pull Requests", err))
+        }
+    }
+
+    fn get_pr_diff(&self, pr: &::PullRequest) -> Result<String, RepositoryError> {
+        let url = format!("{}/pulls/{}", self.repository_url(), pr.id);
+
+        let diff = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.set("Accept", "application/vnd.github.v3.diff");
+            rest::get_raw(&url, &headers.headers)
+        });
+        match diff {
+            Ok(ref response) if response.status != hyper::StatusCode::OK => {
+                Err(RepositoryError::api("getting diff", ApiError::Status(response.status)))
+            },
+            Ok(response) => Ok(response.body),
+            Err(err) => Err(RepositoryError::api("getting diff", err))
+        }
+    }
+
+    fn get_pr_commits(&self, pr: &::PullRequest) -> Result<Vec<::Commit>, RepositoryError> {
+        let url = format!("{}/pulls/{}/commits?per_page=100", self.repository_url(), pr.id);
+
+        let commits = self.get_all::<Commit>(&url);
+        match commits {
+            Ok(commits) => Ok(commits.iter().map(|commit| ::Commit {
+                id: commit.sha.to_owned(),
+                message: commit.commit.message.to_owned(),
+                merge: commit.parents.len() > 1,
+                author: commit.commit.author.as_ref().map(|author| ::User {
+                    name: author.name.to_owned(),
+                    email: author.email.to_owned().unwrap_or_default(),
+                    username: None
+                })
+            }).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of commits", err))
+        }
+    }
+
+    fn get_pr_comments(&self, pr: &::PullRequest) -> Result<Vec<::Comment>, RepositoryError> {
+        match self.get_comments(pr.id) {
+            Ok(comments) => Ok(comments.iter().map(Comment::to_comment).collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    // The GraphQL query lists approvals along with the pull requests, the REST API only their reviews
+    fn get_pr_approvals(&self, pr: &::PullRequest) -> Result<Vec<String>, RepositoryError> {
+        if let Some(true) = self.credentials.graphql {
+            return Ok(pr.approvals.to_owned());
+        }
+        let url = format!("{}/pulls/{}/reviews?per_page=100", self.repository_url(), pr.id);
+
+        match self.get_all::<PullRequestReview>(&url) {
+            Ok(reviews) => Ok(approvers(&reviews)),
+            Err(err) => Err(RepositoryError::api("getting reviews", err))
+        }
+    }
+
+    fn build_queued(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_running(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_success(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "success")
+    }
+
+    fn build_failure(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "failure")
+    }
+
+    fn build_matrix(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>) -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_matrix(pr, builds) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::matrix(pr);
+        let comment = templates::upsert_comment(self, pr, &text, &key, "MatrixComment", |comment| {
+            events::MatrixCommentEvent {
+                pr: pr.to_owned(),
+                builds: builds.to_owned(),
+                comment: comment
+            }
+        });
+        if let Err(err) = comment {
+            return Err(err);
+        }
+        if self.credentials.post_build {
+            for build in builds {
+                if let Err(err) = self.post_status(&pr.from_commit, &Github::make_status(build)) {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn upsert_comment(&self, pr: &::PullRequest, key: &templates::CommentKey, text: &str)
+            -> Result<(), RepositoryError> {
+        templates::upsert_comment(self, pr, text, key, "Notice", |comment| events::NoticeEvent {
+            pr: pr.to_owned(),
+            kind: key.kind.to_owned(),
+            comment: comment
+        }).map(|_| ())
+    }
+
+    fn report_check(&self, pr: &::PullRequest, check: &::CheckResult) -> Result<(), RepositoryError> {
+        let status = Status {
+            state: match check.passed {
+                true => StatusState::Success,
+                false => StatusState::Failure
+            },
+            context: format!("pr_demon/{}", check.name),
+            target_url: pr.web_url.to_owned(),
+            description: check.summary.to_owned()
+        };
+        self.post_status(&pr.from_commit, &status)
+    }
+
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+            -> Result<::PullRequest, RepositoryError> {
+        let body = json!({
+            "title": title,
+            "body": description,
+            "head": branch(from_ref),
+            "base": branch(to_ref)
+        }).to_string();
+        let url = format!("{}/pulls", self.repository_url());
+
+        let pr = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match pr {
+            Ok(pr) => Ok(pr.to_pull_request()),
+            Err(err) => Err(RepositoryError::api("creating Pull Request", err))
+        }
+    }
+
+    fn decline_pr(&self, pr: &::PullRequest) -> Result<(), RepositoryError> {
+        let url = format!("{}/pulls/{}", self.repository_url(), pr.id);
+        let body = json!({ "state": "closed" }).to_string();
+        let declined = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::patch::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match declined {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("declining Pull Request", err))
+        }
+    }
+
+    // The strategy is GitHub's merge method, one of "merge", "squash" or "rebase"
+    fn merge_pr(&self, pr: &::PullRequest, strategy: Option<&str>, message: Option<&str>)
+            -> Result<(), RepositoryError> {
+        let mut body = serde_json::Map::new();
+        // The message's first line is the merge commit's title, as GitHub takes them separately
+        if let Some(message) = message {
+            let mut parts = message.splitn(2, '\n');
+            body.insert("commit_title".to_owned(), json!(parts.next().unwrap_or("")));
+            body.insert("commit_message".to_owned(), json!(parts.next().unwrap_or("").trim()));
+        }
+        if let Some(strategy) = strategy {
+            body.insert("merge_method".to_owned(), json!(strategy));
+        }
+        // Merging fails rather than merging a commit that has not been built should the branch have moved on
+        body.insert("sha".to_owned(), json!(pr.from_commit));
+        let body = serde_json::Value::Object(body).to_string();
+        let url = format!("{}/pulls/{}/merge", self.repository_url(), pr.id);
+        let merged = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match merged {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("merging Pull Request", err))
+        }
+    }
+
+    fn add_reviewers(&self, pr: &::PullRequest, reviewers: &[String]) -> Result<(), RepositoryError> {
+        let url = format!("{}/pulls/{}/requested_reviewers", self.repository_url(), pr.id);
+        let body = json!({ "reviewers": reviewers }).to_string();
+        let added = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<PullRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match added {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("adding reviewer", err))
+        }
+    }
+
+    fn create_branch(&self, name: &str, start_point: &str) -> Result<(), RepositoryError> {
+        // References are created from a commit, so a branch given as the start point is resolved first
+        let commit_url = format!("{}/commits/{}", self.repository_url(), start_point);
+        let commit = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Commit>(&commit_url, &headers.headers)
+        });
+        let commit = match commit {
+            Ok(commit) => commit,
+            Err(err) => return Err(RepositoryError::api("creating branch", err))
+        };
+        let url = format!("{}/git/refs", self.repository_url());
+        let body = json!({ "ref": format!("refs/heads/{}", branch(name)), "sha": commit.sha }).to_string();
+        let created = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match created {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("creating branch", err))
+        }
+    }
+}
+
+impl Github {
+    pub fn new(credentials: &GithubCredentials, messages: &i18n::Messages,
+        broadcaster: &fanout::Fanout<fanout::Message>) -> Result<Github, String> {
+        let renderer = match templates::Renderer::new(&credentials.templates, messages, &credentials.repo) {
+            Ok(renderer) => renderer,
+            Err(err) => return Err(err)
+        };
+
+        Ok(Github {
+            credentials: credentials.to_owned(),
+            broadcaster: broadcaster.to_owned(),
+            renderer: renderer,
+            listed_comments: Mutex::new(HashMap::new())
+        })
+    }
+
+    fn api_url(&self) -> &str {
+        self.credentials.api_url.as_ref().map_or(DEFAULT_API_URL, String::as_str).trim_end_matches('/')
+    }
+
+    fn repository_url(&self) -> String {
+        format!("{}/repos/{}/{}", self.api_url(), self.credentials.owner, self.credentials.repo)
+    }
+
+    // GitHub Enterprise serves GraphQL next to its REST API at `/api/v3`
+    fn graphql_url(&self) -> String {
+        let api_url = self.api_url();
+        match api_url.ends_with("/v3") {
+            true => format!("{}/graphql", api_url.trim_end_matches("/v3")),
+            false => format!("{}/graphql", api_url)
+        }
+    }
+
+    // Lists the open pull requests a page of the query at a time, keeping the comments of those that have no more
+    // than it asks for
+    fn query_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        let url = self.graphql_url();
+        let mut nodes = vec![];
+        let mut after: Option<String> = None;
+        loop {
+            let body = json!({
+                "query": PULL_REQUESTS_QUERY,
+                "variables": { "owner": self.credentials.owner, "repo": self.credentials.repo, "after": after }
+            }).to_string();
+            let response = credentials::authorized(&*self.provider(), |mut headers| {
+                headers.add_accept_json_header()
+                    .add_content_type_json_header();
+                rest::post::<GraphqlResponse<RepositoryData>>(&url, &body, &headers.headers,
+                    &hyper::StatusCode::OK)
+            });
+            let connection = match response {
+                Ok(GraphqlResponse { data: Some(data), ref errors }) if errors.is_empty() => {
+                    data.repository.pull_requests
+                },
+                Ok(response) => {
+                    let errors = response.errors.iter().map(|error| error.message.as_str()).collect::<Vec<_>>();
+                    return Err(RepositoryError::api("querying Pull Requests", ApiError::Remote(errors.join("; "))));
+                },
+                Err(err) => return Err(RepositoryError::api("querying Pull Requests", err))
+            };
+            nodes.extend(connection.nodes);
+            match connection.page_info {
+                Some(PageInfo { has_next_page: true, end_cursor: Some(cursor) }) => after = Some(cursor),
+                _ => break
+            }
+        }
+        *self.listed_comments.lock().expect("Listed comments should not be poisoned") = nodes.iter()
+            .filter_map(|node| node.all_comments().map(|comments| (node.number, comments)))
+            .collect();
+        Ok(nodes.iter().map(PullRequestNode::to_pull_request).collect())
+    }
+
+    fn get_pr_list_by_state(&self, state: &str) -> Result<Vec<PullRequest>, RepositoryError> {
+        let url = format!("{}/pulls?state={}&sort=updated&direction=desc&per_page=100", self.repository_url(), state);
+
+        let prs = self.get_all::<PullRequest>(&url);
+        match prs {
+            Ok(prs) => Ok(prs),
+            Err(err) => Err(RepositoryError::api("getting list of Pull Requests", err))
+        }
+    }
+
+    // Collects the values of every page, following the `next` links of the `Link` header
+    fn get_all<T>(&self, url: &str) -> Result<Vec<T>, ApiError> where T: DeserializeOwned {
+        rest::get_all(url, |url| credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get_raw(url, &headers.headers)
+        }), rest::next_link)
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credentials.credential_provider,
+            credentials::Credential::Bearer(self.credentials.token.to_owned()))
+    }
+
+    fn report_build(&self, pr: &::PullRequest, build: &::BuildDetails, template: &str)
+            -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_build(template, pr, build) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::status(pr, build);
+        let comment = templates::upsert_comment(self, pr, &text, &key, "Comment", |comment| events::CommentEvent {
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            comment: comment
+        });
+        if let Err(err) = comment {
+            return Err(err);
+        }
+        match self.credentials.post_build {
+            true => self.post_status(&pr.from_commit, &Github::make_status(build)),
+            false => Ok(())
+        }
+    }
+
+    // Pull request comments are the comments of the issue every pull request is, and only the daemon's own are
+    // returned
+    fn get_comments(&self, pr_id: i32) -> Result<Vec<Comment>, RepositoryError> {
+        if let Some(comments) = self.listed_comments.lock().expect("Listed comments should not be poisoned")
+                .remove(&pr_id) {
+            return Ok(comments);
+        }
+        let url = format!("{}/issues/{}/comments?per_page=100", self.repository_url(), pr_id);
+
+        let comments = self.get_all::<Comment>(&url);
+        match comments {
+            Ok(comments) => Ok(comments),
+            Err(err) => Err(RepositoryError::api("getting list of comments", err))
+        }
+    }
+
+    fn post_status(&self, commit: &str, status: &Status) -> Result<(), RepositoryError> {
+        let body = serde_json::to_string(status).unwrap();
+        let url = format!("{}/statuses/{}", self.repository_url(), commit);
+
+        let posted = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match posted {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("posting build", err))
+        }
+    }
+
+    fn make_status(build: &::BuildDetails) -> Status {
+        let state = match build.state {
+            ::BuildState::Finished => {
+                match build.status {
+                    ::BuildStatus::Success => StatusState::Success,
+                    _ => StatusState::Failure
+                }
+            },
+            _ => StatusState::Pending
+        };
+
+        // GitHub rejects descriptions longer than 140 characters
+        let description = build.status_text.to_owned().unwrap_or_default().chars().take(140).collect();
+        Status {
+            state: state,
+            context: build.build_id.to_owned(),
+            target_url: build.web_url.to_owned(),
+            description: description
+        }
+    }
+}
+
+impl templates::CommentApi for Github {
+    type Comment = Comment;
+
+    fn renderer(&self) -> &templates::Renderer {
+        &self.renderer
+    }
+
+    fn superseded_comments(&self) -> Option<&str> {
+        self.credentials.superseded_comments.as_ref().map(String::as_str)
+    }
+
+    fn get_own_comments(&self, pr_id: i32) -> Result<Vec<Comment>, RepositoryError> {
+        match self.get_comments(pr_id) {
+            Ok(comments) => Ok(comments.into_iter()
+                .filter(|comment| comment.user.login == self.credentials.username)
+                .collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn post_comment(&self, pr_id: i32, text: &str) -> Result<Comment, RepositoryError> {
+        let body = json!({ "body": text }).to_string();
+        let url = format!("{}/issues/{}/comments", self.repository_url(), pr_id);
+
+        let comment = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match comment {
+            Ok(comment) => Ok(comment),
+            Err(err) => Err(RepositoryError::api("posting comment", err))
+        }
+    }
+
+    fn edit_comment(&self, _: i32, comment: &Comment, text: &str) -> Result<Comment, RepositoryError> {
+        let body = json!({ "body": text }).to_string();
+        let url = format!("{}/issues/comments/{}", self.repository_url(), comment.id);
+
+        let edited = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::patch::<Comment>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match edited {
+            Ok(comment) => Ok(comment),
+            Err(err) => Err(RepositoryError::api("editing comment", err))
+        }
+    }
+
+    fn delete_comment(&self, _: i32, comment: &Comment) -> Result<(), RepositoryError> {
+        let url = format!("{}/issues/comments/{}", self.repository_url(), comment.id);
+
+        match credentials::authorized(&*self.provider(), |headers| rest::delete_raw(&url, &headers.headers)) {
+            Ok(ref response) if !response.status.is_success() => {
+                Err(RepositoryError::api("deleting comment", ApiError::Status(response.status)))
+            },
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("deleting comment", err))
+        }
+    }
+
+    fn broadcast<T>(&self, opcode: &str, payload: &T) where T : Serialize {
+        let opcode = fanout::OpCode::Custom {
+            payload: format!("Github::{}", opcode)
+        };
+        let message = fanout::Message::new(opcode, payload);
+        self.broadcaster.broadcast(&message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use fanout::Fanout;
+    use i18n::Messages;
+    use super::{approvers, Github, GithubCredentials, GraphqlResponse, PullRequestReview, RepositoryData, User};
+
+    fn github(api_url: Option<&str>) -> Github {
+        let credentials = GithubCredentials {
+            username: "pr_demon".to_owned(),
+            token: "token".to_owned(),
+            credential_provider: None,
+            api_url: api_url.map(str::to_owned),
+            owner: "sanguine6".to_owned(),
+            repo: "pr_demon".to_owned(),
+            post_build: false,
+            templates: None,
+            superseded_comments: None,
+            graphql: Some(true),
+            merged_within: None
+        };
+        Github::new(&credentials, &Messages::new(&None, &None), &Fanout::new()).unwrap()
+    }
+
+    fn response(total_count: usize) -> GraphqlResponse<RepositoryData> {
+        serde_json::from_value(json!({
+            "data": { "repository": { "pullRequests": {
+                "pageInfo": { "hasNextPage": false, "endCursor": "Y3Vyc29y" },
+                "nodes": [{
+                    "number": 7,
+                    "url": "https://github.com/sanguine6/pr_demon/pull/7",
+                    "title": "Poll with GraphQL",
+                    "body": "",
+                    "updatedAt": "2026-10-01T12:00:00Z",
+                    "headRefName": "graphql",
+                    "headRefOid": "abc",
+                    "baseRefName": "master",
+                    "isCrossRepository": true,
+                    "author": null,
+                    "labels": { "nodes": [{ "name": "release" }] },
+                    "reviewRequests": { "nodes": [
+                        { "requestedReviewer": { "login": "reviewer" } },
+                        { "requestedReviewer": {} }
+                    ] },
+                    "latestReviews": { "nodes": [
+                        { "state": "APPROVED", "author": { "__typename": "User", "login": "approver" } },
+                        { "state": "CHANGES_REQUESTED", "author": { "__typename": "User", "login": "critic" } }
+                    ] },
+                    "comments": { "totalCount": total_count, "nodes": [
+                        { "fullDatabaseId": "42", "body": "Build queued",
+                            "author": { "__typename": "Bot", "login": "pr_demon" } }
+                    ] }
+                }]
+            } } }
+        })).unwrap()
+    }
+
+    #[test]
+    fn graphql_is_served_next_to_the_rest_api() {
+        assert_eq!("https://api.github.com/graphql", github(None).graphql_url());
+        assert_eq!("https://github.example.com/api/graphql",
+            github(Some("https://github.example.com/api/v3/")).graphql_url());
+    }
+
+    #[test]
+    fn queried_pull_requests_are_mapped_like_listed_ones() {
+        let node = response(1).data.unwrap().repository.pull_requests.nodes.remove(0);
+        let pr = node.to_pull_request();
+        assert_eq!(7, pr.id);
+        assert_eq!("refs/heads/graphql", pr.from_ref);
+        assert_eq!("refs/heads/master", pr.to_ref);
+        assert!(pr.from_fork);
+        assert_eq!(Some("ghost".to_owned()), pr.author.username);
+        assert_eq!(vec!["reviewer".to_owned()], pr.reviewers);
+        assert_eq!(vec!["approver".to_owned()], pr.approvals);
+        assert_eq!(vec!["release".to_owned()], pr.labels);
+        assert!(pr.updated.is_some());
+    }
+
+    #[test]
+    fn the_latest_review_of_each_reviewer_decides_on_approval() {
+        let reviews: Vec<PullRequestReview> = serde_json::from_value(json!([
+            { "user": { "login": "bea" }, "state": "APPROVED" },
+            { "user": { "login": "cid" }, "state": "APPROVED" },
+            { "user": { "login": "bea" }, "state": "COMMENTED" },
+            { "user": { "login": "cid" }, "state": "CHANGES_REQUESTED" },
+            { "user": null, "state": "APPROVED" },
+            { "user": { "login": "dee" }, "state": "DISMISSED" }
+        ])).unwrap();
+        assert_eq!(vec!["bea".to_owned()], approvers(&reviews));
+    }
+
+    #[test]
+    fn comments_are_kept_only_when_all_were_queried() {
+        let node = response(1).data.unwrap().repository.pull_requests.nodes.remove(0);
+        let comments = node.all_comments().unwrap();
+        assert_eq!(42, comments[0].id);
+        assert_eq!(User { login: "pr_demon[bot]".to_owned() }, comments[0].user);
+        let node = response(2).data.unwrap().repository.pull_requests.nodes.remove(0);
+        assert_eq!(None, node.all_comments());
+    }
+}
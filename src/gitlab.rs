@@ -0,0 +1,954 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use hyper;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use time;
+use url::form_urlencoded;
+
+use ::credentials;
+use ::errors::{ApiError, BuilderError, ConfigError, RepositoryError};
+use ::events;
+use ::fanout;
+use ::registry;
+use ::i18n;
+use ::rest;
+use ::templates;
+
+static DEFAULT_BASE_URL: &'static str = "https://gitlab.com";
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct MergeRequest {
+    iid: i32,
+    web_url: String,
+    title: String,
+    description: Option<String>,
+    updated_at: String,
+    source_branch: String,
+    target_branch: String,
+    sha: String,
+    source_project_id: i64,
+    target_project_id: i64,
+    author: User,
+    #[serde(default)]
+    reviewers: Vec<User>
+}
+
+impl MergeRequest {
+    fn to_pull_request(&self) -> ::PullRequest {
+        ::PullRequest {
+            id: self.iid,
+            web_url: self.web_url.to_owned(),
+            from_ref: format!("refs/heads/{}", self.source_branch),
+            from_commit: self.sha.to_owned(),
+            to_ref: format!("refs/heads/{}", self.target_branch),
+            from_fork: self.source_project_id != self.target_project_id,
+            title: self.title.to_owned(),
+            description: self.description.to_owned().unwrap_or_default(),
+            updated: parse_timestamp(&self.updated_at),
+            author: ::User {
+                name: self.author.name.to_owned(),
+                email: "".to_owned(),
+                username: Some(self.author.username.to_owned())
+            },
+            reviewers: self.reviewers.iter().map(|reviewer| reviewer.username.to_owned()).collect(),
+            approvals: vec![],
+            size: None,
+            open_tasks: None,
+            labels: vec![]
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct User {
+    id: i64,
+    username: String,
+    name: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Approvals {
+    approved_by: Vec<Approver>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Approver {
+    user: User
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Note {
+    id: i64,
+    body: String,
+    author: User,
+    // Notes GitLab adds itself, such as when commits are pushed
+    #[serde(default)]
+    system: bool
+}
+
+impl templates::OwnComment for Note {
+    fn text(&self) -> &str {
+        &self.body
+    }
+
+    fn to_event(&self) -> events::PostedComment {
+        events::PostedComment {
+            id: self.id,
+            // GitLab notes are not versioned
+            version: 0,
+            text: self.body.to_owned()
+        }
+    }
+}
+
+impl Note {
+    fn to_comment(&self) -> ::Comment {
+        ::Comment {
+            id: self.id,
+            author: self.author.username.to_owned(),
+            text: self.body.to_owned()
+        }
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Changes {
+    changes: Vec<Change>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Change {
+    old_path: String,
+    new_path: String,
+    diff: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Commit {
+    id: String,
+    message: String,
+    #[serde(default)]
+    parent_ids: Vec<String>,
+    author_name: String,
+    author_email: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct Status {
+    state: StatusState,
+    name: String,
+    target_url: String,
+    description: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+enum StatusState {
+    Pending,
+    Running,
+    Success,
+    Failed
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct GitlabCredentials {
+    // The username the daemon comments as, so that it finds its own notes again
+    pub username: String,
+    // A personal, project or group access token, unless `credential_provider` is set
+    #[serde(default)]
+    pub token: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    // https://gitlab.com unless set to a self-hosted instance
+    pub base_url: Option<String>,
+    // The project's path, such as "group/subgroup/project"
+    pub project: String,
+    pub post_build: bool,
+    pub templates: Option<templates::Templates>,
+    pub superseded_comments: Option<String>
+}
+
+pub struct GitlabFactory;
+
+impl registry::RepositoryFactory for GitlabFactory {
+    fn create(&self, settings: &serde_json::Value, messages: &i18n::Messages,
+        fanout: &fanout::Fanout<fanout::Message>) -> Result<Box<::Repository>, ConfigError> {
+        let credentials = match serde_json::from_value::<GitlabCredentials>(settings.to_owned()) {
+            Ok(credentials) => credentials,
+            Err(err) => return Err(ConfigError::Parse(err))
+        };
+        match Gitlab::new(&credentials, messages, fanout) {
+            Ok(gitlab) => Ok(Box::new(gitlab)),
+            Err(err) => Err(ConfigError::Invalid(format!("Failed to load comment templates: {}", err)))
+        }
+    }
+}
+
+pub struct Gitlab {
+    pub credentials: GitlabCredentials,
+    broadcaster: fanout::Fanout<fanout::Message>,
+    renderer: templates::Renderer
+}
+
+// GitLab reports times in UTC such as "2017-04-29T08:46:00.000Z", turned into milliseconds like Bitbucket's
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    match timestamp.get(..19).map(|seconds| time::strptime(seconds, "%Y-%m-%dT%H:%M:%S")) {
+        Some(Ok(tm)) => Some(tm.to_timespec().sec * 1000),
+        _ => None
+    }
+}
+
+fn branch(reference: &str) -> &str {
+    reference.trim_start_matches("refs/heads/")
+}
+
+fn base_url(base_url: &Option<String>) -> &str {
+    base_url.as_ref().map_or(DEFAULT_BASE_URL, String::as_str).trim_end_matches('/')
+}
+
+// Projects are named by their path with its slashes encoded
+fn project_url(base_url: &Option<String>, project: &str) -> String {
+    format!("{}/api/v4/projects/{}", self::base_url(base_url), project.replace("/", "%2F"))
+}
+
+impl ::Repository for Gitlab {
+    fn get_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.get_pr_list_by_state("opened")
+    }
+
+    fn get_merged_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.get_pr_list_by_state("merged")
+    }
+
+    // GitLab gives the diff of each file without its headers, which are put back for a diff of the whole change
+    fn get_pr_diff(&self, pr: &::PullRequest) -> Result<String, RepositoryError> {
+        let url = format!("{}/merge_requests/{}/changes", self.project_url(), pr.id);
+
+        let changes = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Changes>(&url, &headers.headers)
+        });
+        match changes {
+            Ok(changes) => Ok(changes.changes.iter().map(|change| {
+                format!("diff --git a/{old} b/{new}\n--- a/{old}\n+++ b/{new}\n{diff}",
+                    old = change.old_path, new = change.new_path, diff = change.diff)
+            }).collect()),
+            Err(err) => Err(RepositoryError::api("getting diff", err))
+        }
+    }
+
+    fn get_pr_commits(&self, pr: &::PullRequest) -> Result<Vec<::Commit>, RepositoryError> {
+        let url = format!("{}/merge_requests/{}/commits?per_page=100", self.project_url(), pr.id);
+
+        let commits = self.get_all::<Commit>(&url);
+        match commits {
+            Ok(commits) => Ok(commits.iter().map(|commit| ::Commit {
+                id: commit.id.to_owned(),
+                message: commit.message.to_owned(),
+                merge: commit.parent_ids.len() > 1,
+                author: Some(::User {
+                    name: commit.author_name.to_owned(),
+                    email: commit.author_email.to_owned().unwrap_or_default(),
+                    username: None
+                })
+            }).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of commits", err))
+        }
+    }
+
+    fn get_pr_comments(&self, pr: &::PullRequest) -> Result<Vec<::Comment>, RepositoryError> {
+        match self.get_notes(pr.id) {
+            Ok(notes) => Ok(notes.iter().filter(|note| !note.system).map(Note::to_comment).collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn get_pr_approvals(&self, pr: &::PullRequest) -> Result<Vec<String>, RepositoryError> {
+        let url = format!("{}/merge_requests/{}/approvals", self.project_url(), pr.id);
+
+        let approvals = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Approvals>(&url, &headers.headers)
+        });
+        match approvals {
+            Ok(approvals) => Ok(approvals.approved_by.into_iter().map(|approver| approver.user.username).collect()),
+            Err(err) => Err(RepositoryError::api("getting approvals", err))
+        }
+    }
+
+    fn build_queued(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_running(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "queued")
+    }
+
+    fn build_success(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "success")
+    }
+
+    fn build_failure(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.report_build(pr, build, "failure")
+    }
+
+    fn build_matrix(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>) -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_matrix(pr, builds) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::matrix(pr);
+        let note = templates::upsert_comment(self, pr, &text, &key, "MatrixComment", |comment| {
+            events::MatrixCommentEvent {
+                pr: pr.to_owned(),
+                builds: builds.to_owned(),
+                comment: comment
+            }
+        });
+        if let Err(err) = note {
+            return Err(err);
+        }
+        if self.credentials.post_build {
+            for build in builds {
+                if let Err(err) = self.post_status(&pr.from_commit, &Gitlab::make_status(build)) {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn upsert_comment(&self, pr: &::PullRequest, key: &templates::CommentKey, text: &str)
+            -> Result<(), RepositoryError> {
+        templates::upsert_comment(self, pr, text, key, "Notice", |comment| events::NoticeEvent {
+            pr: pr.to_owned(),
+            kind: key.kind.to_owned(),
+            comment: comment
+        }).map(|_| ())
+    }
+
+    fn report_check(&self, pr: &::PullRequest, check: &::CheckResult) -> Result<(), RepositoryError> {
+        let status = Status {
+            state: match check.passed {
+                true => StatusState::Success,
+                false => StatusState::Failed
+            },
+            name: format!("pr_demon/{}", check.name),
+            target_url: pr.web_url.to_owned(),
+            description: check.summary.to_owned()
+        };
+        self.post_status(&pr.from_commit, &status)
+    }
+
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+            -> Result<::PullRequest, RepositoryError> {
+        let body = json!({
+            "title": title,
+            "description": description,
+            "source_branch": branch(from_ref),
+            "target_branch": branch(to_ref)
+        }).to_string();
+        let url = format!("{}/merge_requests", self.project_url());
+
+        let pr = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<MergeRequest>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match pr {
+            Ok(pr) => Ok(pr.to_pull_request()),
+            Err(err) => Err(RepositoryError::api("creating Merge Request", err))
+        }
+    }
+
+    fn decline_pr(&self, pr: &::PullRequest) -> Result<(), RepositoryError> {
+        let url = format!("{}/merge_requests/{}", self.project_url(), pr.id);
+        let body = json!({ "state_event": "close" }).to_string();
+        let declined = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<MergeRequest>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match declined {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("declining Merge Request", err))
+        }
+    }
+
+    // The merge method is a project setting in GitLab, so only the "squash" strategy changes anything
+    fn merge_pr(&self, pr: &::PullRequest, strategy: Option<&str>, message: Option<&str>)
+            -> Result<(), RepositoryError> {
+        let mut body = serde_json::Map::new();
+        if let Some(message) = message {
+            let key = match strategy {
+                Some("squash") => "squash_commit_message",
+                _ => "merge_commit_message"
+            };
+            body.insert(key.to_owned(), json!(message));
+        }
+        body.insert("squash".to_owned(), json!(strategy == Some("squash")));
+        // Merging fails rather than merging a commit that has not been built should the branch have moved on
+        body.insert("sha".to_owned(), json!(pr.from_commit));
+        let body = serde_json::Value::Object(body).to_string();
+        let url = format!("{}/merge_requests/{}/merge", self.project_url(), pr.id);
+        let merged = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<MergeRequest>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match merged {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("merging Merge Request", err))
+        }
+    }
+
+    // GitLab assigns reviewers by their IDs, and replaces the ones assigned before
+    fn add_reviewers(&self, pr: &::PullRequest, reviewers: &[String]) -> Result<(), RepositoryError> {
+        let url = format!("{}/merge_requests/{}", self.project_url(), pr.id);
+        let current = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<MergeRequest>(&url, &headers.headers)
+        });
+        let mut reviewer_ids = match current {
+            Ok(current) => current.reviewers.iter().map(|reviewer| reviewer.id).collect::<Vec<_>>(),
+            Err(err) => return Err(RepositoryError::api("adding reviewer", err))
+        };
+        for reviewer in reviewers {
+            let users_url = format!("{}/api/v4/users?username={}", self.base_url(), reviewer);
+            let users = credentials::authorized(&*self.provider(), |mut headers| {
+                headers.add_accept_json_header();
+                rest::get::<Vec<User>>(&users_url, &headers.headers)
+            });
+            match users {
+                Ok(ref users) if users.is_empty() => println!("Unable to add unknown reviewer {}", reviewer),
+                Ok(users) => reviewer_ids.extend(users.iter().map(|user| user.id)),
+                Err(err) => return Err(RepositoryError::api("adding reviewer", err))
+            }
+        }
+        reviewer_ids.sort();
+        reviewer_ids.dedup();
+
+        let body = json!({ "reviewer_ids": reviewer_ids }).to_string();
+        let added = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<MergeRequest>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match added {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("adding reviewer", err))
+        }
+    }
+
+    fn create_branch(&self, name: &str, start_point: &str) -> Result<(), RepositoryError> {
+        let url = format!("{}/repository/branches", self.project_url());
+        let body = json!({ "branch": branch(name), "ref": branch(start_point) }).to_string();
+        let created = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match created {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("creating branch", err))
+        }
+    }
+}
+
+impl Gitlab {
+    pub fn new(credentials: &GitlabCredentials, messages: &i18n::Messages,
+        broadcaster: &fanout::Fanout<fanout::Message>) -> Result<Gitlab, String> {
+        let renderer = match templates::Renderer::new(&credentials.templates, messages, &credentials.project) {
+            Ok(renderer) => renderer,
+            Err(err) => return Err(err)
+        };
+
+        Ok(Gitlab {
+            credentials: credentials.to_owned(),
+            broadcaster: broadcaster.to_owned(),
+            renderer: renderer
+        })
+    }
+
+    fn base_url(&self) -> &str {
+        base_url(&self.credentials.base_url)
+    }
+
+    fn project_url(&self) -> String {
+        project_url(&self.credentials.base_url, &self.credentials.project)
+    }
+
+    fn get_pr_list_by_state(&self, state: &str) -> Result<Vec<::PullRequest>, RepositoryError> {
+        let url = format!("{}/merge_requests?state={}&order_by=updated_at&per_page=100", self.project_url(), state);
+
+        let prs = self.get_all::<MergeRequest>(&url);
+        match prs {
+            Ok(ref prs) => Ok(prs.iter().map(MergeRequest::to_pull_request).collect()),
+            Err(err) => Err(RepositoryError::api("getting list of Merge Requests", err))
+        }
+    }
+
+    // Collects the values of every page, asking for the page numbered in the `X-Next-Page` header until it is empty
+    fn get_all<T>(&self, url: &str) -> Result<Vec<T>, ApiError> where T: DeserializeOwned {
+        rest::get_all(url, |url| credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get_raw(url, &headers.headers)
+        }), rest::next_page)
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credentials.credential_provider,
+            credentials::Credential::Bearer(self.credentials.token.to_owned()))
+    }
+
+    fn report_build(&self, pr: &::PullRequest, build: &::BuildDetails, template: &str)
+            -> Result<(), RepositoryError> {
+        let text = match self.renderer.render_build(template, pr, build) {
+            Ok(text) => text,
+            Err(err) => return Err(RepositoryError::Template(err))
+        };
+        let key = templates::CommentKey::status(pr, build);
+        let note = templates::upsert_comment(self, pr, &text, &key, "Comment", |comment| events::CommentEvent {
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            comment: comment
+        });
+        if let Err(err) = note {
+            return Err(err);
+        }
+        match self.credentials.post_build {
+            true => self.post_status(&pr.from_commit, &Gitlab::make_status(build)),
+            false => Ok(())
+        }
+    }
+
+    // Only the daemon's own notes are returned
+    fn get_notes(&self, pr_id: i32) -> Result<Vec<Note>, RepositoryError> {
+        let url = format!("{}/merge_requests/{}/notes?per_page=100", self.project_url(), pr_id);
+
+        let notes = self.get_all::<Note>(&url);
+        match notes {
+            Ok(notes) => Ok(notes),
+            Err(err) => Err(RepositoryError::api("getting list of notes", err))
+        }
+    }
+
+    fn post_status(&self, commit: &str, status: &Status) -> Result<(), RepositoryError> {
+        let body = serde_json::to_string(status).unwrap();
+        let url = format!("{}/statuses/{}", self.project_url(), commit);
+
+        let posted = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<serde_json::Value>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match posted {
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("posting build", err))
+        }
+    }
+
+    fn make_status(build: &::BuildDetails) -> Status {
+        let state = match build.state {
+            ::BuildState::Finished => {
+                match build.status {
+                    ::BuildStatus::Success => StatusState::Success,
+                    _ => StatusState::Failed
+                }
+            },
+            ::BuildState::Running => StatusState::Running,
+            _ => StatusState::Pending
+        };
+
+        Status {
+            state: state,
+            name: build.build_id.to_owned(),
+            target_url: build.web_url.to_owned(),
+            description: build.status_text.to_owned().unwrap_or_default()
+        }
+    }
+}
+
+impl templates::CommentApi for Gitlab {
+    type Comment = Note;
+
+    fn renderer(&self) -> &templates::Renderer {
+        &self.renderer
+    }
+
+    fn superseded_comments(&self) -> Option<&str> {
+        self.credentials.superseded_comments.as_ref().map(String::as_str)
+    }
+
+    fn get_own_comments(&self, pr_id: i32) -> Result<Vec<Note>, RepositoryError> {
+        match self.get_notes(pr_id) {
+            Ok(notes) => Ok(notes.into_iter()
+                .filter(|note| !note.system && note.author.username == self.credentials.username)
+                .collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn post_comment(&self, pr_id: i32, text: &str) -> Result<Note, RepositoryError> {
+        let body = json!({ "body": text }).to_string();
+        let url = format!("{}/merge_requests/{}/notes", self.project_url(), pr_id);
+
+        let note = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Note>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match note {
+            Ok(note) => Ok(note),
+            Err(err) => Err(RepositoryError::api("posting note", err))
+        }
+    }
+
+    fn edit_comment(&self, pr_id: i32, note: &Note, text: &str) -> Result<Note, RepositoryError> {
+        let body = json!({ "body": text }).to_string();
+        let url = format!("{}/merge_requests/{}/notes/{}", self.project_url(), pr_id, note.id);
+
+        let edited = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::put::<Note>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match edited {
+            Ok(note) => Ok(note),
+            Err(err) => Err(RepositoryError::api("editing note", err))
+        }
+    }
+
+    fn delete_comment(&self, pr_id: i32, note: &Note) -> Result<(), RepositoryError> {
+        let url = format!("{}/merge_requests/{}/notes/{}", self.project_url(), pr_id, note.id);
+
+        match credentials::authorized(&*self.provider(), |headers| rest::delete_raw(&url, &headers.headers)) {
+            Ok(ref response) if !response.status.is_success() => {
+                Err(RepositoryError::api("deleting note", ApiError::Status(response.status)))
+            },
+            Ok(_) => Ok(()),
+            Err(err) => Err(RepositoryError::api("deleting note", err))
+        }
+    }
+
+    fn broadcast<T>(&self, opcode: &str, payload: &T) where T : Serialize {
+        let opcode = fanout::OpCode::Custom {
+            payload: format!("Gitlab::{}", opcode)
+        };
+        let message = fanout::Message::new(opcode, payload);
+        self.broadcaster.broadcast(&message);
+    }
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Pipeline {
+    id: i32,
+    sha: String,
+    status: String,
+    web_url: String,
+    created_at: Option<String>,
+    coverage: Option<String>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+struct Job {
+    id: i32,
+    name: String,
+    stage: String,
+    status: String,
+    web_url: String
+}
+
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+struct PipelineRequest {
+    #[serde(rename = "ref")]
+    reference: String,
+    variables: Vec<PipelineVariable>
+}
+
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+struct PipelineVariable {
+    key: String,
+    value: String
+}
+
+// Pipelines and their jobs share their statuses. Pipelines waiting for a manual job are still running.
+fn pipeline_state(status: &str) -> (::BuildState, ::BuildStatus) {
+    match status {
+        "success" => (::BuildState::Finished, ::BuildStatus::Success),
+        "failed" | "canceled" => (::BuildState::Finished, ::BuildStatus::Failure),
+        "skipped" => (::BuildState::Finished, ::BuildStatus::Unknown),
+        "running" | "manual" => (::BuildState::Running, ::BuildStatus::Unknown),
+        _ => (::BuildState::Queued, ::BuildStatus::Unknown)
+    }
+}
+
+impl Pipeline {
+    fn to_build_details(&self, project: &str) -> ::BuildDetails {
+        let (state, status) = pipeline_state(&self.status);
+        let queue_wait = match state {
+            ::BuildState::Queued => self.created_at.as_ref()
+                .and_then(|created_at| parse_timestamp(created_at))
+                .map(|created_at| time::now_utc().to_timespec().sec - created_at / 1000),
+            _ => None
+        };
+        ::BuildDetails {
+            id: self.id,
+            build_id: project.to_owned(),
+            web_url: self.web_url.to_owned(),
+            commit: Some(self.sha.to_owned()),
+            state: state,
+            status: status,
+            status_text: Some(self.status.to_owned()),
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: queue_wait,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+}
+
+impl Job {
+    fn to_build_part(&self) -> ::BuildPart {
+        let (state, status) = pipeline_state(&self.status);
+        ::BuildPart {
+            name: format!("{}: {}", self.stage, self.name),
+            web_url: self.web_url.to_owned(),
+            state: state,
+            status: status
+        }
+    }
+}
+
+// Runs the project's pipelines for pull request branches, which have to be branches of the project itself
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct GitlabCiCredentials {
+    #[serde(default)]
+    pub token: String,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    pub base_url: Option<String>,
+    pub project: String,
+    // CI/CD variables every pipeline is created with
+    pub variables: Option<BTreeMap<String, String>>,
+    pub log_lines: Option<usize>,
+    pub coverage_threshold: Option<f64>
+}
+
+pub struct GitlabCiFactory;
+
+impl registry::BuilderFactory for GitlabCiFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+        match serde_json::from_value::<GitlabCiCredentials>(settings.to_owned()) {
+            Ok(credentials) => Ok(vec![Box::new(credentials) as Box<::ContinuousIntegrator>]),
+            Err(err) => Err(ConfigError::Parse(err))
+        }
+    }
+}
+
+impl GitlabCiCredentials {
+    fn project_url(&self) -> String {
+        project_url(&self.base_url, &self.project)
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credential_provider, credentials::Credential::Bearer(self.token.to_owned()))
+    }
+
+    fn get_pipelines(&self, query: &[(&str, &str)]) -> Result<Vec<Pipeline>, BuilderError> {
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        for &(name, value) in query {
+            serializer.append_pair(name, value);
+        }
+        let url = format!("{}/pipelines?{}", self.project_url(), serializer.finish());
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Vec<Pipeline>>(&url, &headers.headers)
+        });
+        match response {
+            Ok(pipelines) => Ok(pipelines),
+            Err(err) => Err(BuilderError::api("getting list of pipelines", err))
+        }
+    }
+
+    fn get_pipeline(&self, id: i32) -> Result<Pipeline, BuilderError> {
+        let url = format!("{}/pipelines/{}", self.project_url(), id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Pipeline>(&url, &headers.headers)
+        });
+        match response {
+            Ok(pipeline) => Ok(pipeline),
+            Err(err) => Err(BuilderError::api("getting pipeline", err))
+        }
+    }
+
+    fn get_jobs(&self, pipeline_id: i32) -> Result<Vec<Job>, BuilderError> {
+        let url = format!("{}/pipelines/{}/jobs?per_page=100", self.project_url(), pipeline_id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Vec<Job>>(&url, &headers.headers)
+        });
+        match response {
+            Ok(jobs) => Ok(jobs),
+            Err(err) => Err(BuilderError::api("getting pipeline jobs", err))
+        }
+    }
+
+    fn get_job_log_tail(&self, job_id: i32, lines: usize) -> Result<String, BuilderError> {
+        let url = format!("{}/jobs/{}/trace", self.project_url(), job_id);
+
+        let response = credentials::authorized(&*self.provider(), |headers| rest::get_raw(&url, &headers.headers));
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => return Err(BuilderError::api("getting job log", err))
+        };
+        if response.status != hyper::StatusCode::OK {
+            return Err(BuilderError::api("getting job log", ApiError::Status(response.status)));
+        }
+
+        let mut tail = response.body.lines().rev().take(lines).collect::<Vec<_>>();
+        tail.reverse();
+        Ok(tail.join("\n"))
+    }
+}
+
+fn parse_coverage(pipeline: &Pipeline) -> Result<Option<f64>, BuilderError> {
+    match pipeline.coverage {
+        Some(ref coverage) => match coverage.parse::<f64>() {
+            Ok(percentage) => Ok(Some(percentage)),
+            Err(err) => Err(BuilderError::InvalidResponse(format!("Error parsing coverage {}", err)))
+        },
+        None => Ok(None)
+    }
+}
+
+impl ::ContinuousIntegrator for GitlabCiCredentials {
+    fn name(&self) -> Option<&str> {
+        Some(&self.project)
+    }
+
+    fn get_build_list(&self, branch: &str) -> Result<Vec<::Build>, BuilderError> {
+        match self.get_pipelines(&[("ref", branch), ("order_by", "id"), ("sort", "desc"), ("per_page", "20")]) {
+            Ok(pipelines) => Ok(pipelines.iter().map(|pipeline| ::Build { id: pipeline.id }).collect()),
+            Err(err) => Err(err)
+        }
+    }
+
+    // Each job of the pipeline is reported as a part of the build
+    fn get_build(&self, build_id: i32) -> Result<::BuildDetails, BuilderError> {
+        let pipeline = match self.get_pipeline(build_id) {
+            Ok(pipeline) => pipeline,
+            Err(err) => return Err(err)
+        };
+        let jobs = match self.get_jobs(build_id) {
+            Ok(jobs) => jobs,
+            Err(err) => return Err(err)
+        };
+        let mut build_details = pipeline.to_build_details(&self.project);
+        build_details.parts = jobs.iter().map(Job::to_build_part).collect();
+        if let Some(lines) = self.log_lines {
+            if build_details.state == ::BuildState::Finished && build_details.status == ::BuildStatus::Failure {
+                if let Some(job) = jobs.iter().find(|job| job.status == "failed") {
+                    build_details.log_excerpt = self.get_job_log_tail(job.id, lines).ok();
+                }
+            }
+        }
+        Ok(build_details)
+    }
+
+    // Pipelines are created for the head of the branch, which is the pull request's latest commit
+    fn queue_build(&self, branch: &str) -> Result<::BuildDetails, BuilderError> {
+        let body = serde_json::to_string(&PipelineRequest {
+            reference: branch.to_owned(),
+            variables: self.variables.as_ref().map_or(vec![], |variables| variables.iter()
+                .map(|(key, value)| PipelineVariable { key: key.to_owned(), value: value.to_owned() })
+                .collect())
+        }).unwrap();
+        let url = format!("{}/pipeline", self.project_url());
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Pipeline>(&url, &body, &headers.headers, &hyper::StatusCode::CREATED)
+        });
+        match response {
+            Ok(pipeline) => Ok(pipeline.to_build_details(&self.project)),
+            Err(err) => Err(BuilderError::api("creating pipeline", err))
+        }
+    }
+
+    fn get_personal_build_list(&self, _: &str) -> Result<Vec<::Build>, BuilderError> {
+        Ok(vec![])
+    }
+
+    fn queue_personal_build(&self, _: &str, _: &str) -> Result<::BuildDetails, BuilderError> {
+        Err(BuilderError::InvalidResponse("GitLab CI does not support personal builds".to_owned()))
+    }
+
+    // Pipelines have no tags, pins or comments
+    fn tag_build(&self, _: &::BuildDetails, _: &Vec<String>) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn pin_build(&self, _: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn comment_build(&self, _: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    fn cancel_build(&self, build: &::BuildDetails, _: &str) -> Result<(), BuilderError> {
+        let url = format!("{}/pipelines/{}/cancel", self.project_url(), build.id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::post_raw(&url, "", &headers.headers)
+        });
+        match response {
+            Ok(response) => {
+                match response.status {
+                    status if status.is_success() => Ok(()),
+                    e @ _ => Err(BuilderError::api("cancelling pipeline", ApiError::Status(e)))
+                }
+            },
+            Err(err) => Err(BuilderError::api("cancelling pipeline", err))
+        }
+    }
+
+    // Coverage is what the pipeline's jobs parsed from their logs, compared to the target branch's latest success
+    fn get_coverage(&self, build: &::BuildDetails, target_branch: &str) -> Result<Option<::Coverage>, BuilderError> {
+        let percentage = match self.get_pipeline(build.id).and_then(|pipeline| parse_coverage(&pipeline)) {
+            Ok(Some(percentage)) => percentage,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(err)
+        };
+
+        let query = [("ref", target_branch), ("status", "success"), ("order_by", "id"), ("sort", "desc"),
+            ("per_page", "1")];
+        let delta = match self.get_pipelines(&query) {
+            Ok(ref pipelines) if !pipelines.is_empty() => {
+                match self.get_pipeline(pipelines[0].id).and_then(|pipeline| parse_coverage(&pipeline)) {
+                    Ok(Some(target_percentage)) => Some(percentage - target_percentage),
+                    _ => None
+                }
+            },
+            _ => None
+        };
+
+        Ok(Some(::Coverage {
+            percentage: percentage,
+            delta: delta,
+            threshold: self.coverage_threshold
+        }))
+    }
+}
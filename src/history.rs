@@ -0,0 +1,453 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use serde_json;
+use time;
+
+use ::errors::ConfigError;
+use ::fanout::{Message, OpCode};
+
+static USAGE: &'static str = "Usage: export [--from YYYY-MM-DD] [--until YYYY-MM-DD] [--repository NAME]...";
+static COLUMNS: &'static str =
+    "time,repository,event,pull_request,author,branch,target,commit,build,build_number,status,queue_wait";
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct HistoryConfig {
+    // A file of JSON lines that every event is appended to
+    pub path: String,
+    // The name recorded with every event, for exports to be filtered by
+    pub repository: String
+}
+
+// Something that happened to a pull request or one of its builds
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Record {
+    // Seconds since the epoch
+    pub at: i64,
+    pub repository: String,
+    // "opened", "updated" for a new commit, "merged", or "build_scheduled", "build_queued", "build_running" and
+    // "build_finished"
+    pub event: String,
+    pub pr: Option<i32>,
+    pub author: Option<String>,
+    pub branch: Option<String>,
+    pub target: Option<String>,
+    pub commit: Option<String>,
+    pub build: Option<String>,
+    pub build_number: Option<i32>,
+    // "success" or "failure" for finished builds
+    pub status: Option<String>,
+    pub queue_wait: Option<i64>
+}
+
+impl Record {
+    fn pull_request(config: &HistoryConfig, event: &str, pr: &::PullRequest, at: i64) -> Record {
+        Record {
+            at: at,
+            repository: config.repository.to_owned(),
+            event: event.to_owned(),
+            pr: Some(pr.id),
+            author: Some(pr.author.username.as_ref().unwrap_or(&pr.author.email).to_owned()),
+            branch: Some(pr.branch_name()),
+            target: Some(pr.target_branch_name()),
+            commit: Some(pr.from_commit.to_owned()),
+            build: None,
+            build_number: None,
+            status: None,
+            queue_wait: None
+        }
+    }
+}
+
+// What has been recorded so far, so that the pull requests and builds seen on every poll are recorded only when they
+// change
+pub struct History {
+    config: HistoryConfig,
+    commits: HashMap<i32, String>,
+    builds: HashMap<i32, String>,
+    merged: HashSet<i32>,
+    // Builds belong to the pull request being handled
+    current: Option<(i32, String)>
+}
+
+lazy_static! {
+    static ref HISTORY: Mutex<Option<History>> = Mutex::new(None);
+}
+
+pub fn read(path: &str) -> Result<Vec<Record>, ConfigError> {
+    let mut text = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        Ok(_) => {},
+        Err(ref err) if err.kind() == ErrorKind::NotFound => {},
+        Err(err) => return Err(ConfigError::Read(err))
+    }
+    let mut records = vec![];
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str::<Record>(line) {
+            Ok(record) => records.push(record),
+            Err(err) => return Err(ConfigError::Parse(err))
+        }
+    }
+    Ok(records)
+}
+
+impl History {
+    // A history without a file yet is empty
+    pub fn load(config: &HistoryConfig) -> Result<History, ConfigError> {
+        let mut history = History {
+            config: config.to_owned(),
+            commits: HashMap::new(),
+            builds: HashMap::new(),
+            merged: HashSet::new(),
+            current: None
+        };
+        let records = match read(&config.path) {
+            Ok(records) => records,
+            Err(err) => return Err(err)
+        };
+        for record in records.into_iter().filter(|record| record.repository == config.repository) {
+            history.remember(&record);
+        }
+        Ok(history)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty() && self.builds.is_empty() && self.merged.is_empty()
+    }
+
+    fn remember(&mut self, record: &Record) {
+        match (record.event.as_str(), record.pr, record.build_number) {
+            ("merged", Some(pr), _) => {
+                self.merged.insert(pr);
+            },
+            (_, Some(pr), None) => {
+                self.commits.insert(pr, record.commit.to_owned().unwrap_or_default());
+            },
+            (event, _, Some(build_number)) => {
+                self.builds.insert(build_number, event.to_owned());
+            },
+            _ => {}
+        }
+    }
+
+    // The record of a broadcast, unless it is no news
+    pub fn observe(&mut self, message: &Message, at: i64) -> Option<Record> {
+        let event = match message.opcode {
+            OpCode::OpenPullRequest => {
+                let pr = match message.payload::<::PullRequest>() {
+                    Ok(pr) => pr,
+                    Err(_) => return None
+                };
+                self.current = Some((pr.id, pr.from_commit.to_owned()));
+                let event = match self.commits.get(&pr.id) {
+                    Some(commit) if commit == &pr.from_commit => return None,
+                    Some(_) => "updated",
+                    None => "opened"
+                };
+                return Some(Record::pull_request(&self.config, event, &pr, at));
+            },
+            OpCode::BuildScheduled => "build_scheduled",
+            OpCode::BuildQueued => "build_queued",
+            OpCode::BuildRunning => "build_running",
+            OpCode::BuildFinished { .. } => "build_finished",
+            _ => return None
+        };
+        let build = match message.payload::<::BuildDetails>() {
+            Ok(build) => build,
+            Err(_) => return None
+        };
+        if self.builds.get(&build.id).map_or(false, |last| last == event) {
+            return None;
+        }
+        let status = match message.opcode {
+            OpCode::BuildFinished { success } => Some(if success { "success" } else { "failure" }.to_owned()),
+            _ => None
+        };
+        Some(Record {
+            at: at,
+            repository: self.config.repository.to_owned(),
+            event: event.to_owned(),
+            pr: self.current.as_ref().map(|&(pr, _)| pr),
+            author: None,
+            branch: None,
+            target: None,
+            commit: build.commit.to_owned().or_else(|| self.current.as_ref().map(|&(_, ref commit)| commit.clone())),
+            build: Some(build.build_id.to_owned()),
+            build_number: Some(build.id),
+            status: status,
+            queue_wait: build.queue_wait
+        })
+    }
+
+    pub fn append(&mut self, record: &Record) -> Result<(), ConfigError> {
+        let line = serde_json::to_string(record).expect("Records should be serializable");
+        let appended = OpenOptions::new().create(true).append(true).open(&self.config.path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        match appended {
+            Ok(_) => {
+                self.remember(record);
+                Ok(())
+            },
+            Err(err) => Err(ConfigError::Read(err))
+        }
+    }
+}
+
+fn with_history<T, F>(config: &HistoryConfig, action: F) -> Result<T, ConfigError>
+        where F: FnOnce(&mut History) -> Result<T, ConfigError> {
+    let mut history = HISTORY.lock().expect("History should not be poisoned");
+    if history.is_none() {
+        match History::load(config) {
+            Ok(loaded) => *history = Some(loaded),
+            Err(err) => return Err(err)
+        }
+    }
+    action(history.as_mut().expect("History should be loaded"))
+}
+
+fn now() -> i64 {
+    time::now_utc().to_timespec().sec
+}
+
+// Appends the broadcasts that are news to the history in the background
+pub fn record(config: &HistoryConfig, subscriber: Receiver<Message>) {
+    let config = config.to_owned();
+    thread::spawn(move || {
+        for message in subscriber.iter() {
+            let recorded = with_history(&config, |history| match history.observe(&message, now()) {
+                Some(record) => history.append(&record),
+                None => Ok(())
+            });
+            if let Err(err) = recorded {
+                println!("Unable to record {:?} in the history: {}", message.opcode, err);
+            }
+        }
+    });
+}
+
+// Records pull requests merged since the last poll, as of their last change. Pull requests merged before the history
+// was started are left out.
+pub fn record_merged(repository: &::Repository, config: &HistoryConfig) {
+    let pull_requests = match repository.get_merged_pr_list() {
+        Ok(pull_requests) => pull_requests,
+        Err(err) => {
+            println!("Error getting merged Pull Requests: {}", err);
+            return;
+        }
+    };
+    let recorded = with_history(config, |history| {
+        let started = !history.is_empty();
+        let unrecorded = pull_requests.iter().filter(|pr| !history.merged.contains(&pr.id)).collect::<Vec<_>>();
+        for pr in unrecorded {
+            if !started {
+                history.merged.insert(pr.id);
+                continue;
+            }
+            let at = pr.updated.map_or_else(now, |updated| updated / 1000);
+            if let Err(err) = history.append(&Record::pull_request(config, "merged", pr, at)) {
+                return Err(err);
+            }
+        }
+        Ok(())
+    });
+    if let Err(err) = recorded {
+        println!("Unable to record merged Pull Requests in the history: {}", err);
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ExportFilter {
+    // Seconds since the epoch, inclusive
+    pub from: Option<i64>,
+    // Seconds since the epoch, exclusive
+    pub until: Option<i64>,
+    // Every repository when empty
+    pub repositories: Vec<String>
+}
+
+impl ExportFilter {
+    pub fn parse(args: &[String]) -> Result<ExportFilter, String> {
+        let mut filter = ExportFilter { from: None, until: None, repositories: vec![] };
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(USAGE.to_owned())
+            };
+            match flag.as_str() {
+                "--repository" => filter.repositories.push(value.to_owned()),
+                "--from" | "--until" => match parse_date(value) {
+                    Ok(date) if flag == "--from" => filter.from = Some(date),
+                    Ok(date) => filter.until = Some(date),
+                    Err(err) => return Err(err)
+                },
+                _ => return Err(USAGE.to_owned())
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, record: &Record) -> bool {
+        self.from.map_or(true, |from| record.at >= from) && self.until.map_or(true, |until| record.at < until) &&
+            (self.repositories.is_empty() || self.repositories.contains(&record.repository))
+    }
+}
+
+// Midnight UTC of a YYYY-MM-DD date
+fn parse_date(date: &str) -> Result<i64, String> {
+    match time::strptime(date, "%Y-%m-%d") {
+        Ok(tm) => Ok(tm.to_timespec().sec),
+        Err(_) => Err(format!("Invalid date {}, expected YYYY-MM-DD", date))
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    match field.contains(&[',', '"', '\n'][..]) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_owned()
+    }
+}
+
+pub fn csv(records: &[Record]) -> String {
+    let mut lines = vec![COLUMNS.to_owned()];
+    for record in records {
+        let time = time::at_utc(time::Timespec::new(record.at, 0)).rfc3339().to_string();
+        let optional = |field: Option<String>| csv_field(&field.unwrap_or_default());
+        lines.push([
+            time,
+            csv_field(&record.repository),
+            csv_field(&record.event),
+            optional(record.pr.map(|pr| pr.to_string())),
+            optional(record.author.to_owned()),
+            optional(record.branch.to_owned()),
+            optional(record.target.to_owned()),
+            optional(record.commit.to_owned()),
+            optional(record.build.to_owned()),
+            optional(record.build_number.map(|number| number.to_string())),
+            optional(record.status.to_owned()),
+            optional(record.queue_wait.map(|wait| wait.to_string()))
+        ].join(","));
+    }
+    lines.join("\n")
+}
+
+// The `export` command: the recorded events matching the arguments, as CSV
+pub fn export(config: &HistoryConfig, args: &[String]) -> Result<String, String> {
+    let filter = match ExportFilter::parse(args) {
+        Ok(filter) => filter,
+        Err(err) => return Err(err)
+    };
+    match read(&config.path) {
+        Ok(records) => Ok(csv(&records.into_iter().filter(|record| filter.matches(record)).collect::<Vec<_>>())),
+        Err(err) => Err(format!("Unable to read the history: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use fanout::{Message, OpCode};
+    use stubs::pull_request;
+    use super::{csv, export, ExportFilter, History, HistoryConfig, Record};
+
+    fn config(name: &str) -> HistoryConfig {
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        HistoryConfig {
+            path: path.to_string_lossy().into_owned(),
+            repository: "pr_demon".to_owned()
+        }
+    }
+
+    fn build(id: i32, state: ::BuildState) -> ::BuildDetails {
+        ::BuildDetails {
+            id: id,
+            build_id: "Linux".to_owned(),
+            web_url: "http://www.goodbuilds.com/1".to_owned(),
+            commit: None,
+            state: state,
+            status: ::BuildStatus::Success,
+            status_text: None,
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: Some(30),
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn only_changes_are_recorded() {
+        let config = config("pr_demon_history_changes.jsonl");
+        let mut history = History::load(&config).unwrap();
+        let opened = Message::new(OpCode::OpenPullRequest, &pull_request());
+        let queued = Message::new(OpCode::BuildQueued, &build(7, ::BuildState::Queued));
+        let finished = Message::new(OpCode::BuildFinished { success: false }, &build(7, ::BuildState::Finished));
+
+        let mut events = vec![];
+        for (at, message) in [&opened, &queued, &opened, &queued, &finished, &finished].iter().enumerate() {
+            if let Some(record) = history.observe(message, at as i64) {
+                history.append(&record).unwrap();
+                events.push((record.event, record.pr, record.status));
+            }
+        }
+        assert_eq!(vec![
+            ("opened".to_owned(), Some(111), None),
+            ("build_queued".to_owned(), Some(111), None),
+            ("build_finished".to_owned(), Some(111), Some("failure".to_owned()))
+        ], events);
+
+        // The file is read back on restart
+        let mut history = History::load(&config).unwrap();
+        assert_eq!(None, history.observe(&opened, 10));
+        let updated = ::PullRequest { from_commit: "0000000".to_owned(), ..pull_request() };
+        let record = history.observe(&Message::new(OpCode::OpenPullRequest, &updated), 11).unwrap();
+        assert_eq!("updated", record.event);
+        assert_eq!(Some("aaron".to_owned()), record.author);
+    }
+
+    #[test]
+    fn exports_are_filtered_csv() {
+        let config = config("pr_demon_history_export.jsonl");
+        let mut history = History::load(&config).unwrap();
+        let pr = pull_request();
+        // 2026-01-01 and 2026-01-02, at midnight UTC
+        history.append(&Record::pull_request(&config, "opened", &pr, 1767225600)).unwrap();
+        history.append(&Record::pull_request(&config, "merged", &pr, 1767312000)).unwrap();
+        let other = Record::pull_request(&HistoryConfig { repository: "other".to_owned(), ..config.clone() },
+            "opened", &pr, 1767225600);
+        history.append(&other).unwrap();
+
+        let exported = export(&config, &args(&["--until", "2026-01-02", "--repository", "pr_demon"])).unwrap();
+        assert_eq!(vec![
+            "time,repository,event,pull_request,author,branch,target,commit,build,build_number,status,queue_wait",
+            concat!("2026-01-01T00:00:00Z,pr_demon,opened,111,aaron,branch_name,master,",
+                "363c1dfda4cdf5a01c2d210e49942c8c8e7e898b,,,,")
+        ], exported.lines().collect::<Vec<_>>());
+        assert_eq!(4, export(&config, &args(&[])).unwrap().lines().count());
+        assert!(export(&config, &args(&["--from"])).is_err());
+        assert!(export(&config, &args(&["--from", "yesterday"])).is_err());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        let record = Record {
+            author: Some("Aaron \"A\", Xiao".to_owned()),
+            ..Record::pull_request(&config("pr_demon_history_quoted.jsonl"), "opened", &pull_request(), 0)
+        };
+        assert!(csv(&[record]).contains(",\"Aaron \"\"A\"\", Xiao\","));
+        assert_eq!(ExportFilter { from: Some(0), until: None, repositories: vec![] },
+            ExportFilter::parse(&args(&["--from", "1970-01-01"])).unwrap());
+    }
+}
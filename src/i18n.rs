@@ -0,0 +1,447 @@
+use ::labels::Labels;
+
+type Catalog = &'static [(&'static str, &'static str)];
+
+static EN: Catalog = &[
+    ("check.comment", "{marker} **{summary}**\n\n{violations}"),
+    ("check.comment.passed", "{marker} **{summary}**"),
+    ("check.commit_messages.passed", "Commit messages follow the conventions"),
+    ("check.commit_messages.failed", "Commit messages do not follow the conventions"),
+    ("check.commit_messages.conventional", "`{commit}` does not follow Conventional Commits: {subject}"),
+    ("check.commit_messages.ticket", "`{commit}` does not reference a ticket matching `{pattern}`"),
+    ("check.commit_messages.length", "`{commit}` has a subject of {length} characters, more than {max}"),
+    ("check.policy.passed", "The pull request follows the policy"),
+    ("check.policy.failed", "The pull request does not follow the policy"),
+    ("check.policy.title", "The title does not match `{pattern}`"),
+    ("check.policy.description", "The description is empty"),
+    ("check.policy.missing_section", "The description has no \"{section}\" section"),
+    ("check.policy.empty_section", "The \"{section}\" section of the description is empty"),
+    ("check.changelog.passed", "The changelog is updated"),
+    ("check.changelog.unneeded", "No changelog entry is needed"),
+    ("check.changelog.exempt", "The changelog entry is waived"),
+    ("check.changelog.missing", "The changelog is not updated"),
+    ("check.changelog.add", "Add an entry to one of {paths}"),
+    ("check.dependencies.passed", "The dependencies follow the policy"),
+    ("check.dependencies.failed", "The dependencies violate the policy"),
+    ("check.coverage.passed", "Coverage did not drop: {coverage}"),
+    ("check.coverage.warned", "Coverage dropped: {coverage}"),
+    ("check.coverage.failed", "Coverage dropped: {coverage}"),
+    ("check.coverage.delta", "{builder} {percentage}% ({delta}% vs {branch})"),
+    ("check.coverage.unknown", "{builder} {percentage}% (nothing to compare with on {branch})"),
+    ("check.coverage.dropped", "{builder} dropped by {delta}%, more than the {tolerance}% tolerated"),
+    ("check.benchmarks.passed", "No benchmark regressed"),
+    ("check.benchmarks.failed", "{count} benchmarks regressed"),
+    ("check.benchmarks.regressed", "{name} is {change}% worse than on {branch}, more than the {threshold}% allowed"),
+    ("benchmarks.comment", "{marker} **{summary}**\n\n{table}"),
+    ("benchmarks.header", "| Benchmark | {branch} | This pull request | Change |\n| --- | ---: | ---: | ---: |"),
+    ("benchmarks.new", "new"),
+    ("preview.deployed", "{marker} [Preview environment]({url}) of commit {commit} is ready"),
+    ("backport.created", "{marker} Backported to `{target}` in [#{id}]({url})"),
+    ("backport.conflicts", "{marker} Backporting to `{target}` needs manual resolution, \
+these files conflict:\n\n{files}"),
+    ("backport.description", "Backport of #{id}: {url}\n\n{description}"),
+    ("stale.reminder", "{marker} This pull request has had no activity for {days} days."),
+    ("stale.reminder.close", "It will be declined after {days} days without activity unless it is updated."),
+    ("stale.closed", "{marker} This pull request has been declined after {days} days without activity. \
+Reopen it to continue."),
+    ("stale.exempt", "Add {exempt} to its description to keep it open."),
+    ("release.title", "Release {version}"),
+    ("release.tracking", "Tracks `{branch}` and merges its fixes back into `{base}` once the release is done."),
+    ("release.stabilizing", "{marker} `{branch}` is stabilizing, so this pull request is on hold."),
+    ("release.allow", "Add {allow} to its description if it is needed for the release."),
+    ("check.large_files.passed", "No binary or large files are added"),
+    ("check.large_files.failed", "{count} files should not be committed"),
+    ("check.large_files.binary", "{file} is a binary file"),
+    ("check.large_files.large", "{file} is {size} KB, more than the {limit} KB allowed"),
+    ("check.secrets.passed", "No secrets found in the changes"),
+    ("check.secrets.failed", "{count} possible secrets found. They are not repeated here: remove them from the \
+history and revoke them."),
+    ("check.secrets.found", "{kind} in {file} on line {line}"),
+    ("welcome.comment", "Welcome, {author}, and thank you for your first pull request!"),
+    ("welcome.guidelines", "Please have a look at the [contribution guidelines]({url}) while it waits for review."),
+    ("welcome.ci", "Every commit pushed to it is built automatically and the results are posted here. \
+The builds have to pass before it can be merged."),
+    ("check.jira.passed", "The referenced Jira issues are ready for review"),
+    ("check.jira.failed", "The referenced Jira issues are not ready for review"),
+    ("check.jira.missing", "The pull request does not reference a Jira issue"),
+    ("check.jira.unknown", "{key} does not exist in Jira"),
+    ("check.jira.status", "{key} is {status}, not one of {allowed}"),
+    ("check.rules.passed", "The rules pass"),
+    ("check.rules.warned", "The rules pass with warnings"),
+    ("check.rules.failed", "The rules fail"),
+    ("label.pending", "⏳"),
+    ("label.success", "✔️"),
+    ("label.failure", "❌"),
+    ("label.warning", "⚠️"),
+    ("label.pending_text", "queued"),
+    ("label.success_text", "successful"),
+    ("label.failure_text", "failed"),
+    ("template.queued", "{{labels.pending}} [Build]({{build.web_url}}) for commit {{commit}} \
+{{labels.pending_text}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}"),
+    ("template.success", "{{labels.success}} [Build]({{build.web_url}}) for commit {{commit}} is \
+**{{labels.success_text}}**: {{status_text}}\
+{{#if coverage}}\n\n{{coverage}}{{/if}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if steps}}\n\n{{steps}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}"),
+    ("template.failure", "{{labels.failure}} [Build]({{build.web_url}}) for commit {{commit}} has \
+**{{labels.failure_text}}**\
+{{#if test_table}}{{else}}: {{status_text}}{{/if}}\
+{{#if coverage}}\n\n{{coverage}}{{/if}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if steps}}\n\n{{steps}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}\
+{{#if test_failures}}\n\n{{test_failures}}{{/if}}\
+{{#if test_table}}\n\n{{test_table}}{{/if}}\
+{{#if log_excerpt}}\n\n{{log_excerpt}}{{/if}}"),
+    ("template.matrix", "{{#if failed}}{{labels.failure}} Builds for commit {{commit}} have \
+**{{labels.failure_text}}**\
+{{else}}{{#if pending}}{{labels.pending}} Builds for commit {{commit}} {{labels.pending_text}}\
+{{else}}{{labels.success}} Builds for commit {{commit}} are **{{labels.success_text}}**{{/if}}{{/if}}\n\n\
+| Configuration | Status | Build |\n|---|---|---|\n\
+{{#each builds}}| {{#if build.component}}{{build.component}}: {{/if}}{{build.build_id}} | {{marker}} | \
+[{{build.id}}]({{build.web_url}}) |\n{{/each}}"),
+    ("template.summary", "**Build summary** for Pull Request #{{pr.id}}\n\n\
+| Commit | Status | Build |\n|---|---|---|\n\
+{{#each rows}}| {{row.commit}} | {{marker}} | [{{row.build_id}}]({{row.web_url}}) |\n{{/each}}"),
+    ("plain.template.queued", "{{labels.pending}} Build for commit {{commit}} {{labels.pending_text}}\n\
+{{build.web_url}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}"),
+    ("plain.template.success", "{{labels.success}} Build for commit {{commit}} is {{labels.success_text}}: \
+{{status_text}}\n{{build.web_url}}\
+{{#if coverage}}\n\n{{coverage}}{{/if}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if steps}}\n\n{{steps}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}"),
+    ("plain.template.failure", "{{labels.failure}} Build for commit {{commit}} has {{labels.failure_text}}\
+{{#if test_table}}{{else}}: {{status_text}}{{/if}}\n{{build.web_url}}\
+{{#if coverage}}\n\n{{coverage}}{{/if}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if steps}}\n\n{{steps}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}\
+{{#if test_failures}}\n\n{{test_failures}}{{/if}}\
+{{#if test_table}}\n\n{{test_table}}{{/if}}\
+{{#if log_excerpt}}\n\n{{log_excerpt}}{{/if}}"),
+    ("plain.template.matrix", "{{#if failed}}{{labels.failure}} Builds for commit {{commit}} have \
+{{labels.failure_text}}\
+{{else}}{{#if pending}}{{labels.pending}} Builds for commit {{commit}} {{labels.pending_text}}\
+{{else}}{{labels.success}} Builds for commit {{commit}} are {{labels.success_text}}{{/if}}{{/if}}\n\n\
+{{#each builds}}{{marker}} {{#if build.component}}{{build.component}}: {{/if}}\
+{{build.build_id}}: {{build.web_url}}\n{{/each}}"),
+    ("plain.template.summary", "Build summary for Pull Request #{{pr.id}}\n\n\
+{{#each rows}}{{marker}} {{row.commit}}: {{row.web_url}}\n{{/each}}"),
+    ("coverage.summary", "Coverage: **{percentage}%**{delta}{threshold}"),
+    ("plain.coverage.summary", "Coverage: {percentage}%{delta}{threshold}"),
+    ("coverage.delta", " ({delta}% vs target branch)"),
+    ("coverage.threshold_met", " {marker} meets the {threshold}% threshold"),
+    ("coverage.threshold_missed", " {marker} is below the {threshold}% threshold"),
+    ("tests.muted.one", "{count} failure is known and muted"),
+    ("tests.muted.other", "{count} failures are known and muted"),
+    ("tests.investigated.one", "{count} failure is under investigation"),
+    ("tests.investigated.other", "{count} failures are under investigation"),
+    ("tests.new.one", "{count} new failure"),
+    ("tests.new.other", "{count} new failures"),
+    ("tests.header", "| Test | Status | Duration | Link |"),
+    ("tests.details", "Details"),
+    ("tests.more", "and {count} more…"),
+    ("parts.header", "| Part | Status |"),
+    ("steps.header", "| Step | Status | Duration |"),
+    ("log.summary", "Build log"),
+    ("comment.superseded", "Superseded by commit {commit}"),
+    ("command.denied", "Sorry @{user}, `{command}` needs the {role} role, which you do not have."),
+    ("command.rebuild", "Queued a new build, as @{user} asked."),
+    ("command.cancel", "Cancelled the running builds, as @{user} asked."),
+    ("command.cancel.none", "@{user}, there is no running build to cancel."),
+    ("command.cancel.build", "Cancelled by @{user} from the pull request"),
+    ("command.skip", "Skipping this pull request until `resume` is asked for, as @{user} asked."),
+    ("command.resume", "Building this pull request again, as @{user} asked."),
+    ("skip_ci.skipped", "Not building commit {commit}, as `{skip}` asks to skip it."),
+    ("comment.truncated", "✂️ Comment truncated"),
+    ("comment.truncated_link", "✂️ Comment truncated, see the [full output]({url})"),
+    ("plain.comment.truncated_link", "✂️ Comment truncated, see the full output at {url}"),
+    ("queue.delayed", "{marker} Build has been waiting in the queue for more than {minutes} minutes"),
+    ("flaky.note", "{marker} Possibly flaky: this commit passed an earlier build without any change"),
+    ("retry.count", "Retries after infrastructure failures: {count}"),
+    ("size.summary", "Size: **{class}**, {lines} lines changed in {files} files"),
+    ("plain.size.summary", "Size: {class}, {lines} lines changed in {files} files"),
+    ("size.warning", "{marker} This pull request changes {lines} lines in {files} files, which makes it {class}. \
+Please consider splitting it into smaller pull requests, which are easier to review."),
+    ("slack.opened", "Pull Request <{url}|#{id}> opened by {author}: {title}"),
+    ("slack.failed", "{marker} Tests for Pull Request <{url}|#{id}> have {failed}: {title}\n<{build_url}|{status}>"),
+    ("slack.succeeded", "{marker} Tests for Pull Request <{url}|#{id}> have passed: {title}"),
+    ("telegram.failure", "{marker} Tests for Pull Request #{id} have {failed}")
+];
+
+// Markers are left to the English catalog
+static DE: Catalog = &[
+    ("check.commit_messages.passed", "Commit-Nachrichten folgen den Konventionen"),
+    ("check.commit_messages.failed", "Commit-Nachrichten folgen nicht den Konventionen"),
+    ("check.commit_messages.conventional", "`{commit}` folgt nicht Conventional Commits: {subject}"),
+    ("check.commit_messages.ticket", "`{commit}` verweist auf kein Ticket passend zu `{pattern}`"),
+    ("check.commit_messages.length", "`{commit}` hat einen Betreff von {length} Zeichen, mehr als {max}"),
+    ("check.policy.passed", "Der Pull Request entspricht den Richtlinien"),
+    ("check.policy.failed", "Der Pull Request entspricht nicht den Richtlinien"),
+    ("check.policy.title", "Der Titel passt nicht zu `{pattern}`"),
+    ("check.policy.description", "Die Beschreibung ist leer"),
+    ("check.policy.missing_section", "Der Beschreibung fehlt der Abschnitt \"{section}\""),
+    ("check.policy.empty_section", "Der Abschnitt \"{section}\" der Beschreibung ist leer"),
+    ("check.changelog.passed", "Das Changelog ist aktualisiert"),
+    ("check.changelog.unneeded", "Kein Changelog-Eintrag nötig"),
+    ("check.changelog.exempt", "Auf den Changelog-Eintrag wird verzichtet"),
+    ("check.changelog.missing", "Das Changelog ist nicht aktualisiert"),
+    ("check.changelog.add", "Füge einen Eintrag zu einer von {paths} hinzu"),
+    ("check.dependencies.passed", "Die Abhängigkeiten entsprechen den Richtlinien"),
+    ("check.dependencies.failed", "Die Abhängigkeiten verstoßen gegen die Richtlinien"),
+    ("check.coverage.passed", "Die Testabdeckung ist nicht gesunken: {coverage}"),
+    ("check.coverage.warned", "Die Testabdeckung ist gesunken: {coverage}"),
+    ("check.coverage.failed", "Die Testabdeckung ist gesunken: {coverage}"),
+    ("check.coverage.delta", "{builder} {percentage}% ({delta}% gegenüber {branch})"),
+    ("check.coverage.unknown", "{builder} {percentage}% (kein Vergleichswert auf {branch})"),
+    ("check.coverage.dropped", "{builder} ist um {delta}% gesunken, mehr als die tolerierten {tolerance}%"),
+    ("check.benchmarks.passed", "Kein Benchmark hat sich verschlechtert"),
+    ("check.benchmarks.failed", "{count} Benchmarks haben sich verschlechtert"),
+    ("check.benchmarks.regressed", "{name} ist {change}% schlechter als auf {branch}, \
+mehr als die erlaubten {threshold}%"),
+    ("benchmarks.comment", "{marker} **{summary}**\n\n{table}"),
+    ("benchmarks.header", "| Benchmark | {branch} | Dieser Pull Request | Änderung |\n\
+| --- | ---: | ---: | ---: |"),
+    ("benchmarks.new", "neu"),
+    ("preview.deployed", "{marker} [Vorschauumgebung]({url}) für Commit {commit} ist bereit"),
+    ("backport.created", "{marker} Nach `{target}` zurückportiert in [#{id}]({url})"),
+    ("backport.conflicts", "{marker} Das Zurückportieren nach `{target}` muss von Hand gelöst werden, \
+diese Dateien haben Konflikte:\n\n{files}"),
+    ("backport.description", "Zurückportierung von #{id}: {url}\n\n{description}"),
+    ("stale.reminder", "{marker} In diesem Pull Request hat sich seit {days} Tagen nichts getan."),
+    ("stale.reminder.close", "Er wird nach {days} Tagen ohne Aktivität abgelehnt, wenn er nicht aktualisiert wird."),
+    ("stale.closed", "{marker} Dieser Pull Request wurde nach {days} Tagen ohne Aktivität abgelehnt. \
+Öffne ihn erneut, um weiterzumachen."),
+    ("stale.exempt", "Füge {exempt} zur Beschreibung hinzu, um ihn offen zu halten."),
+    ("release.title", "Release {version}"),
+    ("release.tracking", "Verfolgt `{branch}` und führt seine Korrekturen nach dem Release in `{base}` zurück."),
+    ("release.stabilizing", "{marker} `{branch}` wird stabilisiert, deshalb wartet dieser Pull Request."),
+    ("release.allow", "Füge {allow} zur Beschreibung hinzu, wenn er für das Release gebraucht wird."),
+    ("check.large_files.passed", "Es werden keine binären oder großen Dateien hinzugefügt"),
+    ("check.large_files.failed", "{count} Dateien sollten nicht committet werden"),
+    ("check.large_files.binary", "{file} ist eine Binärdatei"),
+    ("check.large_files.large", "{file} ist {size} KB groß, mehr als die erlaubten {limit} KB"),
+    ("check.secrets.passed", "Keine Geheimnisse in den Änderungen gefunden"),
+    ("check.secrets.failed", "{count} mögliche Geheimnisse gefunden. Sie werden hier nicht wiederholt: entferne sie \
+aus der Historie und widerrufe sie."),
+    ("check.secrets.found", "{kind} in {file} in Zeile {line}"),
+    ("welcome.comment", "Willkommen, {author}, und danke für deinen ersten Pull Request!"),
+    ("welcome.guidelines", "Wirf einen Blick in die [Richtlinien für Beiträge]({url}), \
+während er auf das Review wartet."),
+    ("welcome.ci", "Jeder Commit darin wird automatisch gebaut und die Ergebnisse werden hier gepostet. \
+Die Builds müssen erfolgreich sein, bevor er gemergt werden kann."),
+    ("check.jira.passed", "Die referenzierten Jira-Tickets sind bereit für das Review"),
+    ("check.jira.failed", "Die referenzierten Jira-Tickets sind nicht bereit für das Review"),
+    ("check.jira.missing", "Der Pull Request verweist auf kein Jira-Ticket"),
+    ("check.jira.unknown", "{key} existiert nicht in Jira"),
+    ("check.jira.status", "{key} ist {status}, nicht {allowed}"),
+    ("check.rules.passed", "Die Regeln sind erfüllt"),
+    ("check.rules.warned", "Die Regeln sind mit Warnungen erfüllt"),
+    ("check.rules.failed", "Die Regeln sind nicht erfüllt"),
+    ("label.pending_text", "in der Warteschlange"),
+    ("label.success_text", "erfolgreich"),
+    ("label.failure_text", "fehlgeschlagen"),
+    ("template.queued", "{{labels.pending}} [Build]({{build.web_url}}) für Commit {{commit}} \
+{{labels.pending_text}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}"),
+    ("template.success", "{{labels.success}} [Build]({{build.web_url}}) für Commit {{commit}} ist \
+**{{labels.success_text}}**: {{status_text}}\
+{{#if coverage}}\n\n{{coverage}}{{/if}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if steps}}\n\n{{steps}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}"),
+    ("template.failure", "{{labels.failure}} [Build]({{build.web_url}}) für Commit {{commit}} ist \
+**{{labels.failure_text}}**\
+{{#if test_table}}{{else}}: {{status_text}}{{/if}}\
+{{#if coverage}}\n\n{{coverage}}{{/if}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if steps}}\n\n{{steps}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}\
+{{#if test_failures}}\n\n{{test_failures}}{{/if}}\
+{{#if test_table}}\n\n{{test_table}}{{/if}}\
+{{#if log_excerpt}}\n\n{{log_excerpt}}{{/if}}"),
+    ("template.matrix", "{{#if failed}}{{labels.failure}} Builds für Commit {{commit}} sind \
+**{{labels.failure_text}}**\
+{{else}}{{#if pending}}{{labels.pending}} Builds für Commit {{commit}} {{labels.pending_text}}\
+{{else}}{{labels.success}} Builds für Commit {{commit}} sind **{{labels.success_text}}**{{/if}}{{/if}}\n\n\
+| Konfiguration | Status | Build |\n|---|---|---|\n\
+{{#each builds}}| {{#if build.component}}{{build.component}}: {{/if}}{{build.build_id}} | {{marker}} | \
+[{{build.id}}]({{build.web_url}}) |\n{{/each}}"),
+    ("template.summary", "**Build-Übersicht** für Pull Request #{{pr.id}}\n\n\
+| Commit | Status | Build |\n|---|---|---|\n\
+{{#each rows}}| {{row.commit}} | {{marker}} | [{{row.build_id}}]({{row.web_url}}) |\n{{/each}}"),
+    ("plain.template.queued", "{{labels.pending}} Build für Commit {{commit}} {{labels.pending_text}}\n\
+{{build.web_url}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}"),
+    ("plain.template.success", "{{labels.success}} Build für Commit {{commit}} ist {{labels.success_text}}: \
+{{status_text}}\n{{build.web_url}}\
+{{#if coverage}}\n\n{{coverage}}{{/if}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if steps}}\n\n{{steps}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}"),
+    ("plain.template.failure", "{{labels.failure}} Build für Commit {{commit}} ist {{labels.failure_text}}\
+{{#if test_table}}{{else}}: {{status_text}}{{/if}}\n{{build.web_url}}\
+{{#if coverage}}\n\n{{coverage}}{{/if}}\
+{{#if parts}}\n\n{{parts}}{{/if}}\
+{{#if steps}}\n\n{{steps}}{{/if}}\
+{{#if size}}\n\n{{size}}{{/if}}\
+{{#if notes}}\n\n{{notes}}{{/if}}\
+{{#if test_failures}}\n\n{{test_failures}}{{/if}}\
+{{#if test_table}}\n\n{{test_table}}{{/if}}\
+{{#if log_excerpt}}\n\n{{log_excerpt}}{{/if}}"),
+    ("plain.template.matrix", "{{#if failed}}{{labels.failure}} Builds für Commit {{commit}} sind \
+{{labels.failure_text}}\
+{{else}}{{#if pending}}{{labels.pending}} Builds für Commit {{commit}} {{labels.pending_text}}\
+{{else}}{{labels.success}} Builds für Commit {{commit}} sind {{labels.success_text}}{{/if}}{{/if}}\n\n\
+{{#each builds}}{{marker}} {{#if build.component}}{{build.component}}: {{/if}}\
+{{build.build_id}}: {{build.web_url}}\n{{/each}}"),
+    ("plain.template.summary", "Build-Übersicht für Pull Request #{{pr.id}}\n\n\
+{{#each rows}}{{marker}} {{row.commit}}: {{row.web_url}}\n{{/each}}"),
+    ("coverage.summary", "Testabdeckung: **{percentage}%**{delta}{threshold}"),
+    ("plain.coverage.summary", "Testabdeckung: {percentage}%{delta}{threshold}"),
+    ("coverage.delta", " ({delta}% gegenüber dem Zielbranch)"),
+    ("coverage.threshold_met", " {marker} erfüllt den Schwellenwert von {threshold}%"),
+    ("coverage.threshold_missed", " {marker} liegt unter dem Schwellenwert von {threshold}%"),
+    ("tests.muted.one", "{count} Fehlschlag ist bekannt und stummgeschaltet"),
+    ("tests.muted.other", "{count} Fehlschläge sind bekannt und stummgeschaltet"),
+    ("tests.investigated.one", "{count} Fehlschlag wird untersucht"),
+    ("tests.investigated.other", "{count} Fehlschläge werden untersucht"),
+    ("tests.new.one", "{count} neuer Fehlschlag"),
+    ("tests.new.other", "{count} neue Fehlschläge"),
+    ("tests.header", "| Test | Status | Dauer | Link |"),
+    ("tests.more", "und {count} weitere…"),
+    ("parts.header", "| Teil | Status |"),
+    ("steps.header", "| Schritt | Status | Dauer |"),
+    ("log.summary", "Build-Log"),
+    ("comment.superseded", "Ersetzt durch Commit {commit}"),
+    ("command.denied", "Entschuldigung @{user}, `{command}` erfordert die Rolle {role}, die du nicht hast."),
+    ("command.rebuild", "Neuer Build eingereiht, wie von @{user} gewünscht."),
+    ("command.cancel", "Laufende Builds abgebrochen, wie von @{user} gewünscht."),
+    ("command.cancel.none", "@{user}, es läuft kein Build, der abgebrochen werden könnte."),
+    ("command.cancel.build", "Von @{user} im Pull Request abgebrochen"),
+    ("command.skip", "Dieser Pull Request wird bis zu einem `resume` übersprungen, wie von @{user} gewünscht."),
+    ("command.resume", "Dieser Pull Request wird wieder gebaut, wie von @{user} gewünscht."),
+    ("skip_ci.skipped", "Commit {commit} wird nicht gebaut, da `{skip}` darum bittet."),
+    ("comment.truncated", "✂️ Kommentar gekürzt"),
+    ("comment.truncated_link", "✂️ Kommentar gekürzt, siehe [vollständige Ausgabe]({url})"),
+    ("plain.comment.truncated_link", "✂️ Kommentar gekürzt, vollständige Ausgabe unter {url}"),
+    ("queue.delayed", "{marker} Build wartet seit mehr als {minutes} Minuten in der Warteschlange"),
+    ("flaky.note", "{marker} Möglicherweise instabil: dieser Commit hat einen früheren \
+Build ohne Änderung bestanden"),
+    ("retry.count", "Wiederholungen nach Infrastrukturfehlern: {count}"),
+    ("size.summary", "Größe: **{class}**, {lines} geänderte Zeilen in {files} Dateien"),
+    ("plain.size.summary", "Größe: {class}, {lines} geänderte Zeilen in {files} Dateien"),
+    ("size.warning", "{marker} Dieser Pull Request ändert {lines} Zeilen in {files} Dateien und ist damit {class}. \
+Bitte teile ihn nach Möglichkeit in kleinere Pull Requests auf, die leichter zu reviewen sind."),
+    ("slack.opened", "Pull Request <{url}|#{id}> von {author} geöffnet: {title}"),
+    ("slack.failed", "{marker} Tests für Pull Request <{url}|#{id}> sind {failed}: {title}\n<{build_url}|{status}>"),
+    ("slack.succeeded", "{marker} Tests für Pull Request <{url}|#{id}> sind erfolgreich: {title}"),
+    ("telegram.failure", "{marker} Tests für Pull Request #{id} sind {failed}")
+];
+
+#[derive(Clone, Debug)]
+pub struct Messages {
+    catalog: Catalog,
+    labels: Labels
+}
+
+impl Messages {
+    pub fn new(locale: &Option<String>, labels: &Option<Labels>) -> Messages {
+        let catalog = match locale.as_ref().map(|locale| locale.as_str()) {
+            None | Some("en") => EN,
+            Some("de") => DE,
+            Some(locale) => {
+                println!("No messages for locale {} -- falling back to English", locale);
+                EN
+            }
+        };
+
+        let get = |key: &str| -> Option<String> {
+            Messages::lookup(catalog, key).or(Messages::lookup(EN, key)).map(|message| message.to_owned())
+        };
+        let defaults = Labels {
+            pending: get("label.pending"),
+            success: get("label.success"),
+            failure: get("label.failure"),
+            warning: get("label.warning"),
+            pending_text: get("label.pending_text"),
+            success_text: get("label.success_text"),
+            failure_text: get("label.failure_text")
+        };
+
+        Messages {
+            catalog: catalog,
+            labels: Labels::resolve(labels, &defaults)
+        }
+    }
+
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    // Looks the key up in the configured locale, then in English
+    pub fn get<'a>(&self, key: &'a str) -> &'a str {
+        match Messages::lookup(self.catalog, key).or(Messages::lookup(EN, key)) {
+            Some(message) => message,
+            None => key
+        }
+    }
+
+    pub fn format(&self, key: &str, arguments: &[(&str, &str)]) -> String {
+        arguments.iter().fold(self.get(key).to_owned(), |message, &(name, value)| {
+            message.replace(&format!("{{{}}}", name), value)
+        })
+    }
+
+    fn lookup(catalog: Catalog, key: &str) -> Option<&'static str> {
+        catalog.iter()
+            .find(|&&(entry, _)| entry == key)
+            .map(|&(_, message)| message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Messages;
+
+    #[test]
+    fn messages_default_to_english() {
+        let messages = Messages::new(&None, &None);
+        assert_eq!("Superseded by commit abc", messages.format("comment.superseded", &[("commit", "abc")]));
+        assert_eq!("failed", messages.labels().failure_text());
+    }
+
+    #[test]
+    fn missing_german_messages_fall_back_to_english() {
+        let messages = Messages::new(&Some("de".to_owned()), &None);
+        assert_eq!("Ersetzt durch Commit abc", messages.format("comment.superseded", &[("commit", "abc")]));
+        assert_eq!("fehlgeschlagen", messages.labels().failure_text());
+        assert_eq!("❌", messages.labels().failure());
+        assert_eq!("Details", messages.get("tests.details"));
+    }
+
+    #[test]
+    fn unknown_locales_fall_back_to_english() {
+        let messages = Messages::new(&Some("xx".to_owned()), &None);
+        assert_eq!("Build log", messages.get("log.summary"));
+    }
+}
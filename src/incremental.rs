@@ -0,0 +1,145 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+
+use ::fanout::{Fanout, Message, OpCode};
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct IncrementalConfig {
+    // Every pull request is processed on every this many polls regardless, 20 unless set
+    pub full_every: Option<u32>
+}
+
+// Which pull requests have changed since they were last processed
+pub struct Cursor {
+    full_every: u32,
+    // The latest `updated` of the pull requests processed so far
+    newest: Option<i64>,
+    // Pull requests whose builds had not finished when they were last processed, which changes nothing about the
+    // pull requests themselves
+    unsettled: HashSet<i32>,
+    polls: u32,
+    events: Receiver<Message>
+}
+
+lazy_static! {
+    // One cursor for each watched repository, named by the tag of its fanout
+    static ref CURSORS: Mutex<HashMap<String, Cursor>> = Mutex::new(HashMap::new());
+}
+
+impl Cursor {
+    pub fn new(config: &IncrementalConfig, events: Receiver<Message>) -> Cursor {
+        Cursor {
+            full_every: config.full_every.unwrap_or(20),
+            newest: None,
+            unsettled: HashSet::new(),
+            polls: 0,
+            events: events
+        }
+    }
+
+    // Build events name their pull request, as the workers of a scheduler handle several at once
+    fn observe(&mut self, message: &Message) {
+        let pr = match message.pr {
+            Some(pr) => pr,
+            None => return
+        };
+        match message.opcode {
+            OpCode::BuildNotFound | OpCode::BuildScheduled | OpCode::BuildQueued | OpCode::BuildRunning => {
+                self.unsettled.insert(pr);
+            },
+            OpCode::BuildFinished { .. } => {
+                self.unsettled.remove(&pr);
+            },
+            _ => {}
+        }
+    }
+
+    // The pull requests to process this poll: all of them on the first and every `full_every`th poll, and otherwise
+    // those updated since the last poll or with builds still to report
+    pub fn select(&mut self, pull_requests: Vec<::PullRequest>) -> Vec<::PullRequest> {
+        let events = self.events.try_iter().collect::<Vec<_>>();
+        for message in &events {
+            self.observe(message);
+        }
+        let full = self.polls.is_multiple_of(self.full_every.max(1));
+        self.polls += 1;
+        let newest = self.newest;
+        let selected = pull_requests.into_iter().filter(|pr| {
+            full || self.unsettled.contains(&pr.id) || match (pr.updated, newest) {
+                (Some(updated), Some(newest)) => updated > newest,
+                _ => true
+            }
+        }).collect::<Vec<_>>();
+        self.newest = selected.iter().filter_map(|pr| pr.updated).chain(newest).max();
+        self.unsettled.retain(|id| !selected.iter().any(|pr| pr.id == *id));
+        selected
+    }
+}
+
+// Keeps a cursor across polls, following the builds through `fanout` from the first poll on
+pub fn select(pull_requests: Vec<::PullRequest>, config: &IncrementalConfig, fanout: &Fanout<Message>)
+        -> Vec<::PullRequest> {
+    let mut cursors = CURSORS.lock().expect("Cursors should not be poisoned");
+    cursors.entry(fanout.tag().unwrap_or("").to_owned())
+        .or_insert_with(|| Cursor::new(config, fanout.to_owned().subscribe()))
+        .select(pull_requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use fanout::{Message, OpCode};
+    use stubs::pull_request;
+    use super::{Cursor, IncrementalConfig};
+
+    fn pr(id: i32, updated: i64) -> ::PullRequest {
+        ::PullRequest { id: id, updated: Some(updated), ..pull_request() }
+    }
+
+    fn ids(pull_requests: &[::PullRequest]) -> Vec<i32> {
+        pull_requests.iter().map(|pr| pr.id).collect()
+    }
+
+    #[test]
+    fn only_changed_pull_requests_are_selected_between_full_polls() {
+        let (tx, rx) = channel();
+        let mut cursor = Cursor::new(&IncrementalConfig { full_every: Some(3) }, rx);
+        assert_eq!(vec![1, 2], ids(&cursor.select(vec![pr(1, 100), pr(2, 200)])));
+        assert_eq!(Vec::<i32>::new(), ids(&cursor.select(vec![pr(1, 100), pr(2, 200)])));
+        assert_eq!(vec![1, 3], ids(&cursor.select(vec![pr(1, 300), pr(2, 200), pr(3, 250)])));
+        // Every third poll reconciles everything
+        assert_eq!(vec![1, 2, 3], ids(&cursor.select(vec![pr(1, 300), pr(2, 200), pr(3, 250)])));
+        drop(tx);
+    }
+
+    #[test]
+    fn pull_requests_with_unfinished_builds_stay_selected() {
+        let (tx, rx) = channel();
+        let mut cursor = Cursor::new(&IncrementalConfig { full_every: None }, rx);
+        assert_eq!(vec![1, 2], ids(&cursor.select(vec![pr(1, 100), pr(2, 200)])));
+        tx.send(Message::new(OpCode::BuildQueued, &"build").about(&pr(1, 100))).unwrap();
+        tx.send(Message::new(OpCode::BuildFinished { success: true }, &"build").about(&pr(2, 200))).unwrap();
+
+        assert_eq!(vec![1], ids(&cursor.select(vec![pr(1, 100), pr(2, 200)])));
+        tx.send(Message::new(OpCode::BuildFinished { success: true }, &"build").about(&pr(1, 100))).unwrap();
+        assert_eq!(Vec::<i32>::new(), ids(&cursor.select(vec![pr(1, 100), pr(2, 200)])));
+    }
+
+    #[test]
+    fn builds_of_interleaved_pull_requests_are_told_apart() {
+        let (tx, rx) = channel();
+        let mut cursor = Cursor::new(&IncrementalConfig { full_every: None }, rx);
+        assert_eq!(vec![1, 2], ids(&cursor.select(vec![pr(1, 100), pr(2, 200)])));
+        // Two workers handle both pull requests at once, so the second is opened before the build of the first
+        tx.send(Message::new(OpCode::OpenPullRequest, &pr(1, 100))).unwrap();
+        tx.send(Message::new(OpCode::OpenPullRequest, &pr(2, 200))).unwrap();
+        tx.send(Message::new(OpCode::BuildRunning, &"build").about(&pr(1, 100))).unwrap();
+        tx.send(Message::new(OpCode::BuildFinished { success: true }, &"build").about(&pr(2, 200))).unwrap();
+
+        assert_eq!(vec![1], ids(&cursor.select(vec![pr(1, 100), pr(2, 200)])));
+        tx.send(Message::new(OpCode::OpenPullRequest, &pr(2, 200))).unwrap();
+        tx.send(Message::new(OpCode::BuildFinished { success: false }, &"build").about(&pr(1, 100))).unwrap();
+        assert_eq!(Vec::<i32>::new(), ids(&cursor.select(vec![pr(1, 100), pr(2, 200)])));
+    }
+}
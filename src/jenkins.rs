@@ -0,0 +1,501 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use ::credentials;
+use ::errors::{ApiError, BuilderError, ConfigError};
+use ::registry;
+use ::rest;
+use hyper;
+use serde_json;
+use time;
+use url::form_urlencoded;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+static DEFAULT_BRANCH_PARAMETER: &'static str = "BRANCH";
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct JenkinsCredentials {
+    pub username: Option<String>,
+    // An API token, which Jenkins accepts without a crumb, or else a password
+    pub token: Option<String>,
+    pub password: Option<String>,
+    pub credential_provider: Option<credentials::ProviderConfig>,
+    pub base_url: String,
+    // The job's full name, folders included, such as `team/pr_demon`
+    pub job: String,
+    pub matrix: Option<Vec<String>>,
+    // The build parameter holding the branch to build, `BRANCH` unless set
+    pub branch_parameter: Option<String>,
+    // Further parameters every build is queued with
+    pub parameters: Option<BTreeMap<String, String>>,
+    // Whether a CSRF crumb is sent with every POST, as it is when a password is used unless set
+    pub crumb: Option<bool>,
+    pub pin_merged: Option<bool>,
+    pub log_lines: Option<usize>
+}
+
+pub struct JenkinsFactory;
+
+impl registry::BuilderFactory for JenkinsFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+        match serde_json::from_value::<JenkinsCredentials>(settings.to_owned()) {
+            Ok(credentials) => Ok(credentials.jobs().into_iter()
+                .map(|job| Box::new(job) as Box<::ContinuousIntegrator>)
+                .collect()),
+            Err(err) => Err(ConfigError::Parse(err))
+        }
+    }
+}
+
+impl JenkinsCredentials {
+    pub fn jobs(&self) -> Vec<JenkinsCredentials> {
+        let mut jobs = vec![self.job.to_owned()];
+        if let Some(ref matrix) = self.matrix {
+            for job in matrix {
+                if !jobs.contains(job) {
+                    jobs.push(job.to_owned());
+                }
+            }
+        }
+
+        jobs.into_iter().map(|job| {
+            JenkinsCredentials {
+                job: job,
+                matrix: None,
+                ..self.clone()
+            }
+        }).collect()
+    }
+
+    fn server_url(&self) -> &str {
+        self.base_url.trim_end_matches('/')
+    }
+
+    // Each folder of the job's full name is a job of its own in the URL
+    fn job_url(&self) -> String {
+        let path: Vec<String> = self.job.split('/')
+            .filter(|name| !name.is_empty())
+            .map(|name| format!("job/{}", utf8_percent_encode(name, PATH_SEGMENT_ENCODE_SET)))
+            .collect();
+        format!("{}/{}", self.server_url(), path.join("/"))
+    }
+
+    fn build_url(&self, number: i32) -> String {
+        format!("{}/{}", self.job_url(), number)
+    }
+
+    fn branch_parameter(&self) -> &str {
+        self.branch_parameter.as_ref().map_or(DEFAULT_BRANCH_PARAMETER, String::as_str)
+    }
+
+    // Queue items have IDs of their own, so they are told apart from build numbers by being negative
+    fn get_queued_builds(&self, branch: &str) -> Result<Vec<::Build>, BuilderError> {
+        let url = format!("{}/queue/api/json?tree=items[id,task[url],actions[parameters[name,value]]]",
+            self.server_url());
+        let job_url = format!("{}/", self.job_url());
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Queue>(&url, &headers.headers)
+        });
+        match response {
+            Ok(queue) => {
+                let mut items: Vec<QueueItem> = queue.items.into_iter()
+                    .filter(|item| item.task.as_ref().map_or(false, |task| task.url == job_url))
+                    .filter(|item| parameter(&item.actions, self.branch_parameter()) == Some(branch))
+                    .collect();
+                items.sort_by_key(|item| Reverse(item.id));
+                Ok(items.iter().map(|item| ::Build { id: -item.id }).collect())
+            },
+            Err(err) => Err(BuilderError::api("getting queued builds", err))
+        }
+    }
+
+    fn get_queue_item(&self, id: i32) -> Result<::BuildDetails, BuilderError> {
+        let url = format!("{}/queue/item/{}/api/json", self.server_url(), id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<QueueItem>(&url, &headers.headers)
+        });
+        let item = match response {
+            Ok(item) => item,
+            Err(err) => return Err(BuilderError::api("getting queued build", err))
+        };
+        if let Some(ref executable) = item.executable {
+            return self.get_job_build(executable.number);
+        }
+        let cancelled = item.cancelled == Some(true);
+        Ok(::BuildDetails {
+            id: -item.id,
+            build_id: self.job.to_owned(),
+            web_url: self.job_url(),
+            commit: None,
+            state: match cancelled {
+                true => ::BuildState::Finished,
+                false => ::BuildState::Queued
+            },
+            status: match cancelled {
+                true => ::BuildStatus::Failure,
+                false => ::BuildStatus::Unknown
+            },
+            status_text: match cancelled {
+                true => Some("Cancelled".to_owned()),
+                false => item.why.to_owned()
+            },
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: item.in_queue_since.map(|since| time::now_utc().to_timespec().sec - since / 1000),
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        })
+    }
+
+    fn get_job_build(&self, number: i32) -> Result<::BuildDetails, BuilderError> {
+        let url = format!("{}/api/json?tree=number,url,building,result,keepLog,\
+                           actions[parameters[name,value],lastBuiltRevision[SHA1]]", self.build_url(number));
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Build>(&url, &headers.headers)
+        });
+        match response {
+            Ok(build) => {
+                let mut build_details = build.to_build_details(&self.job);
+                if let Some(lines) = self.log_lines {
+                    if build_details.state == ::BuildState::Finished
+                            && build_details.status == ::BuildStatus::Failure {
+                        build_details.log_excerpt = self.get_console_tail(&build.url, lines).ok();
+                    }
+                }
+                Ok(build_details)
+            },
+            Err(err) => Err(BuilderError::api("getting build", err))
+        }
+    }
+
+    fn get_console_tail(&self, build_url: &str, lines: usize) -> Result<String, BuilderError> {
+        let url = format!("{}consoleText", build_url);
+
+        let response = credentials::authorized(&*self.provider(), |headers| rest::get_raw(&url, &headers.headers));
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => return Err(BuilderError::api("getting console output", err))
+        };
+        if response.status != hyper::StatusCode::OK {
+            return Err(BuilderError::api("getting console output", ApiError::Status(response.status)));
+        }
+
+        let mut tail = response.body.lines().rev().take(lines).collect::<Vec<_>>();
+        tail.reverse();
+        Ok(tail.join("\n"))
+    }
+
+    fn uses_crumb(&self) -> bool {
+        self.crumb.unwrap_or(self.token.is_none() && self.password.is_some())
+    }
+
+    // Posts a form, with a crumb from the crumb issuer if one is needed
+    fn post_form(&self, url: &str, form: &[(&str, &str)]) -> Result<rest::Response, ApiError> {
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        for &(name, value) in form {
+            serializer.append_pair(name, value);
+        }
+        let body = serializer.finish();
+        let crumb_url = format!("{}/crumbIssuer/api/json", self.server_url());
+
+        credentials::authorized(&*self.provider(), |mut headers| {
+            if self.uses_crumb() {
+                headers.add_accept_json_header();
+                let crumb = match rest::get::<Crumb>(&crumb_url, &headers.headers) {
+                    Ok(crumb) => crumb,
+                    Err(err) => return Err(err)
+                };
+                headers.set(&crumb.crumb_request_field, &crumb.crumb);
+            }
+            headers.add_content_type_form_header();
+            rest::post_raw(url, &body, &headers.headers)
+        })
+    }
+
+    // Jenkins answers most forms by redirecting to the page they were posted from
+    fn submit(&self, context: &str, url: &str, form: &[(&str, &str)]) -> Result<(), BuilderError> {
+        match self.post_form(url, form) {
+            Ok(response) => {
+                match response.status {
+                    status if status.is_success() || status.is_redirection() => Ok(()),
+                    e @ _ => Err(BuilderError::api(context, ApiError::Status(e)))
+                }
+            },
+            Err(err) => Err(BuilderError::api(context, err))
+        }
+    }
+
+    fn credential(&self) -> credentials::Credential {
+        match (&self.username, &self.token, &self.password) {
+            (&Some(ref username), &Some(ref token), _) => {
+                credentials::Credential::Basic { username: username.to_owned(), password: token.to_owned() }
+            },
+            (&Some(ref username), &None, &Some(ref password)) => {
+                credentials::Credential::Basic { username: username.to_owned(), password: password.to_owned() }
+            },
+            _ => credentials::Credential::Anonymous
+        }
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credential_provider, self.credential())
+    }
+}
+
+// The value of the build parameter `name`, if it is a string
+fn parameter<'a>(actions: &'a [Action], name: &str) -> Option<&'a str> {
+    actions.iter()
+        .filter_map(|action| action.parameters.as_ref())
+        .flat_map(|parameters| parameters.iter())
+        .find(|parameter| parameter.name == name)
+        .and_then(|parameter| parameter.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Crumb {
+    pub crumb: String,
+    pub crumb_request_field: String
+}
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct Queue {
+    pub items: Vec<QueueItem>
+}
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueItem {
+    pub id: i32,
+    pub task: Option<Task>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    pub why: Option<String>,
+    pub in_queue_since: Option<i64>,
+    pub cancelled: Option<bool>,
+    pub executable: Option<Executable>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Task {
+    pub url: String
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Executable {
+    pub number: i32,
+    pub url: String
+}
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Action {
+    pub parameters: Option<Vec<Parameter>>,
+    pub last_built_revision: Option<Revision>
+}
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct Parameter {
+    pub name: String,
+    pub value: Option<serde_json::Value>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Revision {
+    #[serde(rename = "SHA1")]
+    pub sha1: String
+}
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct BuildList {
+    pub builds: Vec<BuildListItem>
+}
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct BuildListItem {
+    pub number: i32,
+    #[serde(default)]
+    pub actions: Vec<Action>
+}
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Build {
+    pub number: i32,
+    pub url: String,
+    pub building: bool,
+    pub result: Option<String>,
+    pub keep_log: Option<bool>,
+    #[serde(default)]
+    pub actions: Vec<Action>
+}
+
+impl Build {
+    fn to_build_details(&self, job: &str) -> ::BuildDetails {
+        let commit = self.actions.iter()
+            .filter_map(|action| action.last_built_revision.as_ref())
+            .map(|revision| revision.sha1.to_owned())
+            .next();
+        // A build that has not checked out its commit yet is as good as queued
+        let state = match (self.building, &commit) {
+            (false, _) => ::BuildState::Finished,
+            (true, &Some(_)) => ::BuildState::Running,
+            (true, &None) => ::BuildState::Queued
+        };
+        let status = match self.result.as_ref().map(String::as_str) {
+            None => ::BuildStatus::Unknown,
+            Some("SUCCESS") => ::BuildStatus::Success,
+            Some(_) => ::BuildStatus::Failure
+        };
+        let status_text = match status {
+            ::BuildStatus::Failure => self.result.to_owned(),
+            _ => None
+        };
+        ::BuildDetails {
+            id: self.number,
+            build_id: job.to_owned(),
+            web_url: format!("{}console", self.url),
+            commit: commit,
+            state: state,
+            status: status,
+            status_text: status_text,
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+}
+
+impl ::ContinuousIntegrator for JenkinsCredentials {
+    fn pin_merged(&self) -> bool {
+        self.pin_merged == Some(true)
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some(&self.job)
+    }
+
+    fn get_build_list(&self, branch: &str) -> Result<Vec<::Build>, BuilderError> {
+        let mut builds = match self.get_queued_builds(branch) {
+            Ok(builds) => builds,
+            Err(err) => return Err(err)
+        };
+        let url = format!("{}/api/json?tree=builds[number,actions[parameters[name,value]]]{{0,100}}",
+            self.job_url());
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<BuildList>(&url, &headers.headers)
+        });
+        match response {
+            Ok(build_list) => {
+                builds.extend(build_list.builds.iter()
+                    .filter(|build| parameter(&build.actions, self.branch_parameter()) == Some(branch))
+                    .map(|build| ::Build { id: build.number }));
+                Ok(builds)
+            },
+            Err(err) => Err(BuilderError::api("getting list of builds", err))
+        }
+    }
+
+    fn get_build(&self, build_id: i32) -> Result<::BuildDetails, BuilderError> {
+        match build_id < 0 {
+            true => self.get_queue_item(-build_id),
+            false => self.get_job_build(build_id)
+        }
+    }
+
+    // Jenkins only tells where the queued build went in a header, so it is looked up in the queue instead
+    fn queue_build(&self, branch: &str) -> Result<::BuildDetails, BuilderError> {
+        let mut form = vec![(self.branch_parameter(), branch)];
+        if let Some(ref parameters) = self.parameters {
+            form.extend(parameters.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+        }
+        let url = format!("{}/buildWithParameters", self.job_url());
+
+        if let Err(err) = self.submit("queuing build", &url, &form) {
+            return Err(err);
+        }
+        match self.get_queued_builds(branch) {
+            Ok(ref builds) if !builds.is_empty() => self.get_build(builds[0].id),
+            Ok(_) => Err(BuilderError::InvalidResponse(format!("The build of {} was not found in the queue", branch))),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn get_personal_build_list(&self, _: &str) -> Result<Vec<::Build>, BuilderError> {
+        Ok(vec![])
+    }
+
+    fn queue_personal_build(&self, _: &str, _: &str) -> Result<::BuildDetails, BuilderError> {
+        Err(BuilderError::InvalidResponse("Jenkins does not support personal builds".to_owned()))
+    }
+
+    // Jenkins builds have no tags
+    fn tag_build(&self, _: &::BuildDetails, _: &Vec<String>) -> Result<(), BuilderError> {
+        Ok(())
+    }
+
+    // Builds are kept forever rather than pinned. The flag is toggled, so it is only set if it is not already.
+    fn pin_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        let url = format!("{}/api/json?tree=number,url,building,result,keepLog", self.build_url(build.id));
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Build>(&url, &headers.headers)
+        });
+        match response {
+            Ok(ref build) if build.keep_log == Some(true) => return Ok(()),
+            Ok(_) => {},
+            Err(err) => return Err(BuilderError::api("pinning build", err))
+        }
+        let url = format!("{}/toggleLogKeep", self.build_url(build.id));
+        match self.submit("pinning build", &url, &[]) {
+            Ok(_) => self.comment_build(build, comment),
+            Err(err) => Err(err)
+        }
+    }
+
+    // The comment becomes the build's description, which queued builds do not have yet
+    fn comment_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        if build.id < 0 {
+            return Ok(());
+        }
+        let url = format!("{}/submitDescription", self.build_url(build.id));
+        self.submit("commenting on build", &url, &[("description", comment)])
+    }
+
+    // Queued builds are removed from the queue, running ones are aborted
+    fn cancel_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        if build.id < 0 {
+            let url = format!("{}/queue/cancelItem", self.server_url());
+            let id = (-build.id).to_string();
+            return self.submit("cancelling build", &url, &[("id", id.as_str())]);
+        }
+        let url = format!("{}/stop", self.build_url(build.id));
+        match self.submit("cancelling build", &url, &[]) {
+            Ok(_) => self.comment_build(build, comment),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn get_coverage(&self, _: &::BuildDetails, _: &str) -> Result<Option<::Coverage>, BuilderError> {
+        Ok(None)
+    }
+}
+
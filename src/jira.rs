@@ -0,0 +1,310 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use hyper;
+use regex::Regex;
+use serde_json;
+
+use ::checks::{self, Report};
+use ::errors::{ApiError, ConfigError, Error, RepositoryError};
+use ::i18n::Messages;
+use ::pipeline::{Context, Outcome, Stage};
+use ::registry;
+use ::rest;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct JiraConfig {
+    pub base_url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    // Only keys of these projects, such as ["PD"], are issue keys. Any project unless set.
+    pub projects: Option<Vec<String>>,
+    // Statuses the referenced issues have to be in, such as ["In Progress", "In Review"]. Any status unless set.
+    pub allowed_statuses: Option<Vec<String>>,
+    // Pull requests referencing no issue fail the check
+    pub require_issue: Option<bool>,
+    // Transitions applied to the referenced issues, by name, when a pull request is first seen and when it is merged
+    pub opened_transition: Option<String>,
+    pub merged_transition: Option<String>,
+    // `status` unless configured
+    pub report: Option<Report>
+}
+
+#[derive(Deserialize, Debug)]
+struct Issue {
+    fields: IssueFields
+}
+
+#[derive(Deserialize, Debug)]
+struct IssueFields {
+    status: IssueStatus
+}
+
+#[derive(Deserialize, Debug)]
+struct IssueStatus {
+    name: String
+}
+
+#[derive(Deserialize, Debug)]
+struct Transitions {
+    transitions: Vec<Transition>
+}
+
+#[derive(Deserialize, Debug)]
+struct Transition {
+    id: String,
+    name: String
+}
+
+lazy_static! {
+    static ref ISSUE_KEY: Regex = Regex::new(r"\b([A-Z][A-Z0-9_]+)-[0-9]+\b")
+        .expect("Issue key pattern should be valid");
+    // Pull requests whose issues have been transitioned as opened, and the merged pull requests seen so far
+    static ref OPENED: Mutex<HashSet<i32>> = Mutex::new(HashSet::new());
+    static ref MERGED: Mutex<Option<HashSet<i32>>> = Mutex::new(None);
+}
+
+// The issue keys in `text`, in order and without duplicates
+pub fn issue_keys(text: &str, projects: &Option<Vec<String>>) -> Vec<String> {
+    let mut keys: Vec<String> = vec![];
+    for captures in ISSUE_KEY.captures_iter(text) {
+        let key = captures[0].to_owned();
+        let known_project = match *projects {
+            Some(ref projects) => projects.iter().any(|project| *project == captures[1]),
+            None => true
+        };
+        if known_project && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+// Issue keys are looked for in the title, then in the branch, where they may be written in lower case
+pub fn pull_request_keys(pr: &::PullRequest, projects: &Option<Vec<String>>) -> Vec<String> {
+    let text = format!("{}\n{}", pr.title, pr.branch_name().to_uppercase());
+    issue_keys(&text, projects)
+}
+
+pub struct Jira {
+    config: JiraConfig
+}
+
+impl Jira {
+    pub fn new(config: &JiraConfig) -> Jira {
+        Jira { config: config.to_owned() }
+    }
+
+    fn headers(&self) -> rest::Headers {
+        let mut headers = rest::Headers::new();
+        headers.add_accept_json_header();
+        match (&self.config.token, &self.config.username, &self.config.password) {
+            (&Some(ref token), _, _) => { headers.add_bearer_authorization_header(token); },
+            (_, &Some(ref username), &Some(ref password)) => {
+                headers.add_basic_authorization_header(username, password);
+            },
+            _ => {}
+        }
+        headers
+    }
+
+    // The status of the issue, or None if it does not exist
+    pub fn get_status(&self, key: &str) -> Result<Option<String>, ApiError> {
+        let url = format!("{}/rest/api/2/issue/{}?fields=status", self.config.base_url, key);
+        let response = match rest::get_raw(&url, &self.headers().headers) {
+            Ok(response) => response,
+            Err(err) => return Err(err)
+        };
+        match response.status {
+            hyper::StatusCode::NOT_FOUND => Ok(None),
+            hyper::StatusCode::OK => match serde_json::from_str::<Issue>(&response.body) {
+                Ok(issue) => Ok(Some(issue.fields.status.name)),
+                Err(err) => Err(ApiError::Parse { body: response.body, source: err })
+            },
+            status => Err(ApiError::Status(status))
+        }
+    }
+
+    // Applies the transition named `name`, ignoring case. Returns false when the issue has no such transition, which
+    // usually means it is past it already.
+    pub fn transition(&self, key: &str, name: &str) -> Result<bool, ApiError> {
+        let url = format!("{}/rest/api/2/issue/{}/transitions", self.config.base_url, key);
+        let transitions = match rest::get::<Transitions>(&url, &self.headers().headers) {
+            Ok(transitions) => transitions.transitions,
+            Err(err) => return Err(err)
+        };
+        let name = name.to_lowercase();
+        let transition = match transitions.iter().find(|transition| transition.name.to_lowercase() == name) {
+            Some(transition) => transition,
+            None => return Ok(false)
+        };
+
+        let body = json!({ "transition": { "id": transition.id } }).to_string();
+        let mut headers = self.headers();
+        headers.add_content_type_json_header();
+        match rest::post_raw(&url, &body, &headers.headers) {
+            Ok(ref response) if response.status == hyper::StatusCode::NO_CONTENT => Ok(true),
+            Ok(response) => Err(ApiError::Status(response.status)),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn transition_all(&self, keys: &[String], name: &str) {
+        for key in keys {
+            match self.transition(key, name) {
+                Ok(true) => println!("Transitioned {} with {}", key, name),
+                Ok(false) => println!("{} cannot be transitioned with {}", key, name),
+                Err(err) => println!("Unable to transition {} with {}: {}", key, name, err)
+            }
+        }
+    }
+
+    pub fn violations(&self, keys: &[String], statuses: &[Option<String>], messages: &Messages) -> Vec<String> {
+        if keys.is_empty() && self.config.require_issue == Some(true) {
+            return vec![messages.get("check.jira.missing").to_owned()];
+        }
+        keys.iter().zip(statuses.iter()).filter_map(|(key, status)| match (status, &self.config.allowed_statuses) {
+            (&None, _) => Some(messages.format("check.jira.unknown", &[("key", key)])),
+            (&Some(ref status), &Some(ref allowed))
+                    if !allowed.iter().any(|allowed| allowed.to_lowercase() == status.to_lowercase()) => {
+                Some(messages.format("check.jira.status", &[
+                    ("key", key),
+                    ("status", status),
+                    ("allowed", &allowed.join(", "))
+                ]))
+            },
+            _ => None
+        }).collect()
+    }
+}
+
+// Applies the merged transition to the issues of pull requests merged since the daemon started
+pub fn transition_merged(repository: &::Repository, config: &JiraConfig) {
+    let transition = match config.merged_transition {
+        Some(ref transition) => transition,
+        None => return
+    };
+    let pull_requests = match repository.get_merged_pr_list() {
+        Ok(pull_requests) => pull_requests,
+        Err(err) => {
+            println!("Error getting merged Pull Requests: {}", err);
+            return;
+        }
+    };
+
+    let mut merged = MERGED.lock().expect("Merged pull requests should not be poisoned");
+    let first_poll = merged.is_none();
+    let seen = merged.get_or_insert_with(HashSet::new);
+    let jira = Jira::new(config);
+    for pr in pull_requests {
+        if seen.insert(pr.id) && !first_poll {
+            jira.transition_all(&pull_request_keys(&pr, &config.projects), transition);
+        }
+    }
+}
+
+// Checks the Jira issues the pull request references, applying the opened transition the first time it is seen
+pub struct JiraStage;
+
+impl Stage for JiraStage {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let config = match context.config.jira {
+            Some(ref config) => config,
+            None => return Err(Error::from(ConfigError::Invalid("The jira stage needs a jira section".to_owned())))
+        };
+        let jira = Jira::new(config);
+        let keys = pull_request_keys(context.pr, &config.projects);
+
+        if let Some(ref transition) = config.opened_transition {
+            if OPENED.lock().expect("Opened pull requests should not be poisoned").insert(context.pr.id) {
+                jira.transition_all(&keys, transition);
+            }
+        }
+
+        let mut statuses = vec![];
+        for key in &keys {
+            match jira.get_status(key) {
+                Ok(status) => statuses.push(status),
+                Err(err) => return Err(Error::from(RepositoryError::api(&format!("getting Jira issue {}", key), err)))
+            }
+        }
+
+        // Issues can be moved at any time, so their statuses are part of what is checked
+        let revision = keys.iter().zip(statuses.iter())
+            .fold(context.pr.from_commit.to_owned(), |revision, (key, status)| {
+                format!("{}\n{}:{}", revision, key, status.as_ref().map_or("", String::as_str))
+            });
+        let report = config.report.unwrap_or(Report::Status);
+        checks::run_check(context, "jira", &revision, report, |context| {
+            let violations = jira.violations(&keys, &statuses, context.messages);
+            let summary = match violations.is_empty() {
+                true => context.messages.get("check.jira.passed"),
+                false => context.messages.get("check.jira.failed")
+            };
+            Ok(::CheckResult {
+                name: "jira".to_owned(),
+                passed: violations.is_empty(),
+                summary: summary.to_owned(),
+                violations: violations
+            })
+        })
+    }
+}
+
+pub struct JiraStageFactory;
+
+impl registry::StageFactory for JiraStageFactory {
+    fn create(&self, _: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        Ok(Box::new(JiraStage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use i18n::Messages;
+    use stubs::pull_request;
+    use super::{issue_keys, pull_request_keys, Jira, JiraConfig};
+
+    fn config() -> JiraConfig {
+        JiraConfig {
+            base_url: "https://jira.example.com".to_owned(),
+            username: None,
+            password: None,
+            token: None,
+            projects: None,
+            allowed_statuses: Some(vec!["In Progress".to_owned(), "In Review".to_owned()]),
+            require_issue: Some(true),
+            opened_transition: None,
+            merged_transition: None,
+            report: None
+        }
+    }
+
+    #[test]
+    fn issue_keys_are_found_once() {
+        assert_eq!(
+            vec!["PD-1".to_owned(), "OPS2-30".to_owned()],
+            issue_keys("PD-1: Fix OPS2-30 and PD-1, not UTF-8 or X-1", &Some(vec!["PD".to_owned(), "OPS2".to_owned()]))
+        );
+        assert_eq!(vec!["UTF-8".to_owned()], issue_keys("Handle UTF-8", &None));
+    }
+
+    #[test]
+    fn branches_are_searched_ignoring_case() {
+        let pr = ::PullRequest { from_ref: "refs/heads/feature/pd-480-jira".to_owned(), ..pull_request() };
+        assert_eq!(vec!["PD-480".to_owned()], pull_request_keys(&pr, &None));
+    }
+
+    #[test]
+    fn issues_must_exist_and_be_in_an_allowed_status() {
+        let jira = Jira::new(&config());
+        let messages = Messages::new(&None, &None);
+        let keys = vec!["PD-1".to_owned(), "PD-2".to_owned(), "PD-3".to_owned()];
+        let statuses = vec![Some("in review".to_owned()), None, Some("Done".to_owned())];
+        assert_eq!(vec![
+            "PD-2 does not exist in Jira",
+            "PD-3 is Done, not one of In Progress, In Review"
+        ], jira.violations(&keys, &statuses, &messages));
+        assert_eq!(vec!["The pull request does not reference a Jira issue"], jira.violations(&[], &[], &messages));
+    }
+}
@@ -0,0 +1,110 @@
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Labels {
+    pub pending: Option<String>,
+    pub success: Option<String>,
+    pub failure: Option<String>,
+    pub warning: Option<String>,
+    pub pending_text: Option<String>,
+    pub success_text: Option<String>,
+    pub failure_text: Option<String>
+}
+
+impl Labels {
+    // Fills in every label that has not been configured, so that the accessors below never fall back
+    pub fn resolve(labels: &Option<Labels>, defaults: &Labels) -> Labels {
+        let labels = match *labels {
+            Some(ref labels) => labels.to_owned(),
+            None => return defaults.to_owned()
+        };
+
+        Labels {
+            pending: labels.pending.or(defaults.pending.to_owned()),
+            success: labels.success.or(defaults.success.to_owned()),
+            failure: labels.failure.or(defaults.failure.to_owned()),
+            warning: labels.warning.or(defaults.warning.to_owned()),
+            pending_text: labels.pending_text.or(defaults.pending_text.to_owned()),
+            success_text: labels.success_text.or(defaults.success_text.to_owned()),
+            failure_text: labels.failure_text.or(defaults.failure_text.to_owned())
+        }
+    }
+
+    pub fn pending(&self) -> &str {
+        Labels::label(&self.pending)
+    }
+
+    pub fn success(&self) -> &str {
+        Labels::label(&self.success)
+    }
+
+    pub fn failure(&self) -> &str {
+        Labels::label(&self.failure)
+    }
+
+    pub fn warning(&self) -> &str {
+        Labels::label(&self.warning)
+    }
+
+    pub fn failure_text(&self) -> &str {
+        Labels::label(&self.failure_text)
+    }
+
+    pub fn status_marker(&self, state: &::BuildState, status: &::BuildStatus) -> &str {
+        match (state, status) {
+            (&::BuildState::Finished, &::BuildStatus::Success) => self.success(),
+            (&::BuildState::Finished, _) => self.failure(),
+            _ => self.pending()
+        }
+    }
+
+    fn label(label: &Option<String>) -> &str {
+        match *label {
+            Some(ref label) => label,
+            None => ""
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Labels;
+    use super::super::{BuildState, BuildStatus};
+
+    fn defaults() -> Labels {
+        Labels {
+            pending: Some("⏳".to_owned()),
+            success: Some("✔️".to_owned()),
+            failure: Some("❌".to_owned()),
+            warning: Some("⚠️".to_owned()),
+            pending_text: Some("queued".to_owned()),
+            success_text: Some("successful".to_owned()),
+            failure_text: Some("failed".to_owned())
+        }
+    }
+
+    #[test]
+    fn unconfigured_labels_fall_back_to_defaults() {
+        let labels = Labels::resolve(&None, &defaults());
+        assert_eq!("⏳", labels.status_marker(&BuildState::Running, &BuildStatus::Unknown));
+        assert_eq!("✔️", labels.status_marker(&BuildState::Finished, &BuildStatus::Success));
+        assert_eq!("❌", labels.status_marker(&BuildState::Finished, &BuildStatus::Failure));
+        assert_eq!("failed", labels.failure_text());
+    }
+
+    #[test]
+    fn configured_labels_override_defaults() {
+        let labels = Labels::resolve(&Some(Labels {
+            pending: Some("[..]".to_owned()),
+            success: Some("[OK]".to_owned()),
+            failure: Some("[FAIL]".to_owned()),
+            warning: None,
+            pending_text: None,
+            success_text: None,
+            failure_text: Some("broken".to_owned())
+        }), &defaults());
+        assert_eq!("[..]", labels.status_marker(&BuildState::Queued, &BuildStatus::Unknown));
+        assert_eq!("[OK]", labels.status_marker(&BuildState::Finished, &BuildStatus::Success));
+        assert_eq!("[FAIL]", labels.status_marker(&BuildState::Finished, &BuildStatus::Failure));
+        assert_eq!("⚠️", labels.warning());
+        assert_eq!("broken", labels.failure_text());
+    }
+}
@@ -0,0 +1,222 @@
+use serde_json;
+
+use ::checks::{self, Report};
+use ::components;
+use ::errors::{ConfigError, Error};
+use ::i18n::Messages;
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct LargeFileRules {
+    // Files larger than this are flagged, 1024 unless set
+    pub max_kilobytes: Option<u64>,
+    // Binary files are flagged unless set to false
+    pub binary: Option<bool>,
+    // Files under these paths, such as "docs/images", are never flagged
+    pub allow: Option<Vec<String>>,
+    // Flagged files only warn instead of failing the check
+    pub warn: Option<bool>,
+    // `both` unless configured
+    pub report: Option<Report>
+}
+
+// A file a pull request adds or changes, as it is after the change
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct AddedFile {
+    pub path: String,
+    // In bytes. Measured from a diff, this is only what the change adds.
+    pub size: u64,
+    pub binary: bool
+}
+
+// The files a unified diff adds or changes, with the bytes of the lines it adds
+pub fn added_files(diff: &str) -> Vec<AddedFile> {
+    let mut files: Vec<(AddedFile, bool)> = vec![];
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            let path = line.rfind(" b/").map_or("", |start| &line[start + 3..]);
+            files.push((AddedFile { path: path.to_owned(), size: 0, binary: false }, false));
+            in_hunk = false;
+            continue;
+        }
+        let &mut (ref mut file, ref mut deleted) = match files.last_mut() {
+            Some(last) => last,
+            None => continue
+        };
+        if in_hunk && line.starts_with('+') {
+            // The marker stands in for the line feed
+            file.size += line.len() as u64;
+        } else if line.starts_with("@@") {
+            in_hunk = true;
+        } else if !in_hunk && line.starts_with("deleted file mode") {
+            *deleted = true;
+        } else if !in_hunk && (line.starts_with("Binary files ") || line == "GIT binary patch") {
+            file.binary = true;
+        }
+    }
+    files.into_iter().filter(|&(_, deleted)| !deleted).map(|(file, _)| file).collect()
+}
+
+// The files the pull request adds or changes, measured with the local clone when one is configured and from the
+// repository's diff otherwise
+#[cfg(feature = "git")]
+fn changes(pr: &::PullRequest, repository: &::Repository, config: &::Config) -> Result<Vec<AddedFile>, String> {
+    let git = match config.git {
+        Some(ref git) => git,
+        None => return diff_changes(pr, repository)
+    };
+    let clone = match ::git::LocalClone::open(git) {
+        Ok(clone) => clone,
+        Err(err) => return Err(err.to_string())
+    };
+    let files = clone.fetch_pull_request(pr).and_then(|_| {
+        clone.added_files(&format!("refs/pr_demon/{}/to", pr.id), &format!("refs/pr_demon/{}/from", pr.id))
+    });
+    files.map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "git"))]
+fn changes(pr: &::PullRequest, repository: &::Repository, _: &::Config) -> Result<Vec<AddedFile>, String> {
+    diff_changes(pr, repository)
+}
+
+fn diff_changes(pr: &::PullRequest, repository: &::Repository) -> Result<Vec<AddedFile>, String> {
+    match repository.get_pr_diff(pr) {
+        Ok(diff) => Ok(added_files(&diff)),
+        Err(err) => Err(err.to_string())
+    }
+}
+
+// Flags binary files and files above a size limit, to keep build artifacts and large assets out of the repository
+pub struct LargeFileCheck {
+    rules: LargeFileRules
+}
+
+impl LargeFileCheck {
+    pub fn new(rules: &LargeFileRules) -> LargeFileCheck {
+        LargeFileCheck { rules: rules.to_owned() }
+    }
+
+    pub fn violations(&self, files: &[AddedFile], messages: &Messages) -> Vec<String> {
+        let limit = self.rules.max_kilobytes.unwrap_or(1024);
+        let allowed = |file: &&AddedFile| self.rules.allow.iter().flat_map(|allow| allow.iter())
+            .any(|path| components::is_under(&file.path, path));
+        let mut violations = vec![];
+        for file in files.iter().filter(|file| !allowed(file)) {
+            let quoted = format!("`{}`", file.path);
+            if file.binary && self.rules.binary != Some(false) {
+                violations.push(messages.format("check.large_files.binary", &[("file", &quoted)]));
+            }
+            if file.size > limit * 1024 {
+                violations.push(messages.format("check.large_files.large", &[
+                    ("file", &quoted),
+                    ("size", &file.size.div_ceil(1024).to_string()),
+                    ("limit", &limit.to_string())
+                ]));
+            }
+        }
+        violations
+    }
+
+    fn check(&self, context: &Context) -> Result<::CheckResult, Error> {
+        let files = match changes(context.pr, context.repository, context.config) {
+            Ok(files) => files,
+            Err(err) => return Err(Error::from(ConfigError::Invalid(format!("Unable to find the changes: {}", err))))
+        };
+        let violations = self.violations(&files, context.messages);
+        let summary = match violations.is_empty() {
+            true => context.messages.get("check.large_files.passed").to_owned(),
+            false => context.messages.format("check.large_files.failed", &[("count", &violations.len().to_string())])
+        };
+        Ok(::CheckResult {
+            name: "large_files".to_owned(),
+            passed: violations.is_empty() || self.rules.warn == Some(true),
+            summary: summary,
+            violations: violations
+        })
+    }
+}
+
+impl Stage for LargeFileCheck {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let report = self.rules.report.unwrap_or(Report::Both);
+        checks::run_check(context, "large_files", &context.pr.from_commit, report, |context| self.check(context))
+    }
+}
+
+pub struct LargeFileCheckFactory;
+
+impl registry::StageFactory for LargeFileCheckFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match pipeline::parse_settings::<LargeFileRules>(settings) {
+            Ok(rules) => Ok(Box::new(LargeFileCheck::new(&rules))),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use i18n::Messages;
+    use super::{added_files, AddedFile, LargeFileCheck, LargeFileRules};
+
+    static DIFF: &'static str = "diff --git a/src/main.rs b/src/main.rs
+index 1111111..2222222 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,2 +1,3 @@
+ fn main() {
++    run();
+-}
+diff --git a/logo.png b/logo.png
+new file mode 100644
+index 0000000..3333333
+Binary files /dev/null and b/logo.png differ
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+index 4444444..0000000
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+";
+
+    fn rules() -> LargeFileRules {
+        LargeFileRules {
+            max_kilobytes: Some(1),
+            binary: None,
+            allow: Some(vec!["docs/images".to_owned()]),
+            warn: None,
+            report: None
+        }
+    }
+
+    #[test]
+    fn diffs_list_the_added_files() {
+        assert_eq!(vec![
+            AddedFile { path: "src/main.rs".to_owned(), size: 11, binary: false },
+            AddedFile { path: "logo.png".to_owned(), size: 0, binary: true }
+        ], added_files(DIFF));
+    }
+
+    #[test]
+    fn binary_and_large_files_are_flagged() {
+        let messages = Messages::new(&None, &None);
+        let check = LargeFileCheck::new(&rules());
+        let files = vec![
+            AddedFile { path: "logo.png".to_owned(), size: 100, binary: true },
+            AddedFile { path: "dump.sql".to_owned(), size: 4000, binary: false },
+            AddedFile { path: "docs/images/diagram.png".to_owned(), size: 4000, binary: true },
+            AddedFile { path: "src/main.rs".to_owned(), size: 1024, binary: false }
+        ];
+        let violations = check.violations(&files, &messages);
+        assert_eq!(2, violations.len());
+        assert!(violations[0].contains("`logo.png`"));
+        assert!(violations[1].contains("`dump.sql`") && violations[1].contains("4 KB"));
+
+        let check = LargeFileCheck::new(&LargeFileRules { binary: Some(false), ..rules() });
+        assert_eq!(1, check.violations(&files, &messages).len());
+    }
+}
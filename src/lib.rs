@@ -0,0 +1,1861 @@
+extern crate base64;
+extern crate bytes;
+extern crate futures_util;
+#[cfg(feature = "git")]
+extern crate git2;
+extern crate handlebars;
+#[cfg(feature = "webhook")]
+extern crate hmac;
+extern crate http_body_util;
+#[cfg(feature = "webhook")]
+extern crate httpdate;
+extern crate hyper;
+#[cfg(feature = "rustls")]
+extern crate hyper_rustls;
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+extern crate hyper_tls;
+extern crate hyper_util;
+#[macro_use]
+extern crate lazy_static;
+#[cfg(feature = "spnego")]
+extern crate libgssapi;
+extern crate regex;
+#[cfg(feature = "rules")]
+extern crate rhai;
+#[cfg(feature = "rustls")]
+extern crate rustls_tls;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "webhook")]
+extern crate sha2;
+extern crate time;
+extern crate tokio;
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+extern crate tokio_native_tls;
+#[cfg(feature = "rustls")]
+extern crate tokio_rustls;
+extern crate tokio_stream;
+#[cfg(any(feature = "bitbucket", feature = "teamcity", feature = "jenkins", feature = "gitlab", feature = "buildkite",
+    feature = "drone"))]
+extern crate url;
+
+pub mod access;
+#[cfg(feature = "azure_devops")]
+pub mod azure_devops;
+#[cfg(feature = "git")]
+pub mod backport;
+pub mod badges;
+pub mod benchmarks;
+pub mod branches;
+#[cfg(feature = "bitbucket")]
+pub mod bitbucket;
+#[cfg(feature = "bitbucket_cloud")]
+pub mod bitbucket_cloud;
+#[cfg(feature = "buildkite")]
+pub mod buildkite;
+pub mod cancel;
+pub mod cassette;
+pub mod chaos;
+pub mod checks;
+pub mod commands;
+pub mod components;
+pub mod coverage;
+pub mod credentials;
+pub mod digest;
+#[cfg(feature = "drone")]
+pub mod drone;
+mod environment;
+pub mod errors;
+pub mod events;
+#[cfg(feature = "exec")]
+pub mod exec;
+pub mod fanout;
+pub mod flaky;
+#[cfg(feature = "git")]
+pub mod git;
+#[cfg(feature = "gitea")]
+pub mod gitea;
+#[cfg(feature = "github")]
+pub mod github;
+#[cfg(feature = "gitlab")]
+pub mod gitlab;
+pub mod history;
+pub mod i18n;
+pub mod incremental;
+#[cfg(feature = "jenkins")]
+pub mod jenkins;
+pub mod jira;
+pub mod labels;
+pub mod large_files;
+pub mod listener;
+pub mod merge;
+pub mod merger;
+mod netrc;
+pub mod permissions;
+pub mod pipeline;
+#[cfg(feature = "plugin")]
+pub mod plugin;
+pub mod preview;
+pub mod registry;
+pub mod release;
+mod rest;
+pub mod retry;
+pub mod reviewers;
+pub mod routing;
+mod runtime;
+#[cfg(feature = "rules")]
+pub mod rules;
+pub mod scan;
+pub mod scheduler;
+mod scrub;
+pub mod secrets;
+pub mod seen;
+pub mod shard;
+pub mod size;
+pub mod skip_ci;
+#[cfg(feature = "slack")]
+pub mod slack;
+pub mod stale;
+mod store;
+#[cfg(test)]
+mod stubs;
+#[cfg(feature = "teamcity")]
+pub mod teamcity;
+#[cfg(feature = "telegram")]
+pub mod telegram;
+pub mod templates;
+pub mod watches;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+pub mod welcome;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::boxed::Box;
+use std::thread;
+use errors::{BuilderError, ConfigError, Error, RepositoryError};
+use fanout::{Fanout, Message, OpCode};
+
+/// Daemon configuration, usually read from a JSON file with `read_config` and `parse_config`.
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct Config { // TODO: Rename fields
+    #[cfg(feature = "teamcity")]
+    pub teamcity: Option<teamcity::TeamcityCredentials>,
+    #[cfg(feature = "bitbucket")]
+    pub bitbucket: Option<bitbucket::BitbucketCredentials>,
+    #[cfg(feature = "telegram")]
+    pub telegram: Option<telegram::TelegramCredentials>,
+    pub repository: Option<registry::BackendConfig>,
+    pub builder: Option<registry::BackendConfig>,
+    // Repositories watched along with their builders instead of `repository` and `builder`
+    pub watches: Option<Vec<watches::Watch>>,
+    pub notifiers: Option<Vec<registry::BackendConfig>>,
+    pub pipeline: Option<Vec<registry::BackendConfig>>,
+    pub run_interval: u64,
+    pub stdout_broadcast: Option<bool>,
+    pub queue_alert: Option<QueueAlert>,
+    pub labels: Option<labels::Labels>,
+    pub locale: Option<String>,
+    pub cassette: Option<cassette::CassetteConfig>,
+    pub chaos: Option<chaos::ChaosConfig>,
+    pub size: Option<size::SizeConfig>,
+    pub jira: Option<jira::JiraConfig>,
+    pub coverage: Option<coverage::CoverageConfig>,
+    pub benchmarks: Option<benchmarks::BenchmarkConfig>,
+    pub preview: Option<preview::PreviewConfig>,
+    pub release: Option<release::ReleaseConfig>,
+    pub merge: Option<merge::MergeConfig>,
+    pub badges: Option<badges::BadgeConfig>,
+    pub history: Option<history::HistoryConfig>,
+    pub incremental: Option<incremental::IncrementalConfig>,
+    pub shard: Option<shard::ShardConfig>,
+    pub seen: Option<seen::SeenConfig>,
+    pub cancel: Option<cancel::CancelConfig>,
+    pub retry: Option<retry::RetryConfig>,
+    pub flaky: Option<flaky::FlakyConfig>,
+    pub scheduler: Option<scheduler::SchedulerConfig>,
+    pub skip_ci: Option<skip_ci::SkipCiConfig>,
+    #[cfg(feature = "webhook")]
+    pub webhook: Option<webhook::WebhookConfig>,
+    #[cfg(feature = "git")]
+    pub git: Option<git::GitConfig>,
+    #[cfg(feature = "git")]
+    pub backport: Option<backport::BackportConfig>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct QueueAlert {
+    pub threshold: i64,
+    pub comment: Option<bool>
+}
+
+pub trait UsernameAndPassword {
+    fn username(&self) -> &String;
+    fn password(&self) -> &String;
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct PullRequest {
+    pub id: i32,
+    pub web_url: String,
+    pub from_ref: String,
+    pub from_commit: String,
+    pub to_ref: String,
+    pub from_fork: bool,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    // When the pull request last changed, in milliseconds since the epoch, if the repository knows
+    #[serde(default)]
+    pub updated: Option<i64>,
+    pub author: User,
+    // The usernames of the reviewers assigned so far
+    #[serde(default)]
+    pub reviewers: Vec<String>,
+    // The usernames of the reviewers who approved the pull request, for repositories that tell along with the list
+    #[serde(default)]
+    pub approvals: Vec<String>,
+    // Filled in when `size` is configured
+    #[serde(default)]
+    pub size: Option<size::Size>,
+    // The number of tasks left open, for repositories that tell
+    #[serde(default)]
+    pub open_tasks: Option<i32>,
+    // The names of the labels on the pull request, for repositories that have them
+    #[serde(default)]
+    pub labels: Vec<String>
+}
+
+impl PullRequest {
+    fn branch_name(&self) -> String {
+        PullRequest::strip_ref(&self.from_ref)
+    }
+
+    fn target_branch_name(&self) -> String {
+        PullRequest::strip_ref(&self.to_ref)
+    }
+
+    fn description(&self) -> String {
+        format!("Pull Request #{}: {}\n{}", self.id, self.title, self.web_url)
+    }
+
+    fn build_tags(&self) -> Vec<String> {
+        vec![format!("pr-{}", self.id), self.target_branch_name()]
+    }
+
+    fn strip_ref(git_ref: &str) -> String {
+        git_ref.split('/').skip(2).collect::<Vec<_>>().join("/")
+    }
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct User {
+    pub name: String,
+    pub email: String,
+    // The login, which reviewers are assigned by, if the repository knows
+    #[serde(default)]
+    pub username: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Commit {
+    pub id: String,
+    pub message: String,
+    #[serde(default)]
+    pub merge: bool,
+    #[serde(default)]
+    pub author: Option<User>
+}
+
+/// A comment on a pull request, by anyone.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Comment {
+    pub id: i64,
+    // The username of the author, or whatever else the repository names users by
+    pub author: String,
+    pub text: String
+}
+
+/// The result of a check the daemon runs itself, such as the commit message conventions.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct CheckResult {
+    pub name: String,
+    pub passed: bool,
+    pub summary: String,
+    pub violations: Vec<String>
+}
+
+/// A source of pull requests that build results are reported back to, such as Bitbucket. Shared by the scheduler's
+/// workers.
+pub trait Repository: Send + Sync {
+    fn get_pr_list(&self) -> Result<Vec<PullRequest>, RepositoryError>;
+    fn get_merged_pr_list(&self) -> Result<Vec<PullRequest>, RepositoryError>;
+    fn get_pr_diff(&self, pr: &PullRequest) -> Result<String, RepositoryError>;
+    fn get_pr_commits(&self, pr: &PullRequest) -> Result<Vec<Commit>, RepositoryError>;
+    /// Every comment on the pull request, including the daemon's own, such as those asking for commands.
+    fn get_pr_comments(&self, pr: &PullRequest) -> Result<Vec<Comment>, RepositoryError>;
+    /// The usernames of the reviewers who approved the pull request, looked up only when needed, such as by the
+    /// merger, on repositories that do not list approvals along with pull requests.
+    fn get_pr_approvals(&self, pr: &PullRequest) -> Result<Vec<String>, RepositoryError>;
+    fn build_queued(&self, pr: &PullRequest, build: &BuildDetails) -> Result<(), RepositoryError>;
+    fn build_running(&self, pr: &PullRequest, build: &BuildDetails) -> Result<(), RepositoryError>;
+    fn build_success(&self, pr: &PullRequest, build: &BuildDetails) -> Result<(), RepositoryError>;
+    fn build_failure(&self, pr: &PullRequest, build: &BuildDetails) -> Result<(), RepositoryError>;
+    fn build_matrix(&self, pr: &PullRequest, builds: &Vec<BuildDetails>) -> Result<(), RepositoryError>;
+    /// Posts `text` as the comment identified by `key`, or edits the comment posted with that key before.
+    fn upsert_comment(&self, pr: &PullRequest, key: &templates::CommentKey, text: &str)
+        -> Result<(), RepositoryError>;
+    /// Reports `check` on the pull request's latest commit, alongside its builds.
+    fn report_check(&self, pr: &PullRequest, check: &CheckResult) -> Result<(), RepositoryError>;
+    /// Opens a pull request from `from_ref` into `to_ref`, both full refs such as `refs/heads/master`.
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+        -> Result<PullRequest, RepositoryError>;
+    /// Declines, or closes, the pull request without merging it.
+    fn decline_pr(&self, pr: &PullRequest) -> Result<(), RepositoryError>;
+    /// Adds the users, by username, to the reviewers of the pull request.
+    fn add_reviewers(&self, pr: &PullRequest, reviewers: &[String]) -> Result<(), RepositoryError>;
+    /// Creates the branch `name` at `start_point`, a full ref such as `refs/heads/master`.
+    fn create_branch(&self, name: &str, start_point: &str) -> Result<(), RepositoryError>;
+    /// Merges the pull request with `strategy`, or the repository's default strategy, and `message`, or the
+    /// repository's default message.
+    fn merge_pr(&self, pr: &PullRequest, strategy: Option<&str>, message: Option<&str>) -> Result<(), RepositoryError>;
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Build {
+    pub id: i32
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub enum BuildState {
+    Queued,
+    Finished,
+    Running
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub enum BuildStatus {
+    Success,
+    Failure,
+    Unknown
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct BuildDetails {
+    pub id: i32,
+    pub build_id: String,
+    pub web_url: String,
+    pub commit: Option<String>,
+    pub state: BuildState,
+    pub status: BuildStatus,
+    pub status_text: Option<String>,
+    pub log_excerpt: Option<String>,
+    pub coverage: Option<Coverage>,
+    pub test_failures: Option<TestFailures>,
+    pub queue_wait: Option<i64>,
+    #[serde(default)]
+    pub parts: Vec<BuildPart>,
+    #[serde(default)]
+    pub steps: Vec<BuildStep>,
+    #[serde(default)]
+    pub notes: Vec<String>,
+    // The monorepo components the build was routed for, when `components` routing is used
+    #[serde(default)]
+    pub component: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct BuildPart {
+    pub name: String,
+    pub web_url: String,
+    pub state: BuildState,
+    pub status: BuildStatus
+}
+
+// A step of a finished build, in the order the steps ran
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct BuildStep {
+    pub name: String,
+    pub state: BuildState,
+    pub status: BuildStatus,
+    // In milliseconds
+    pub duration: Option<i64>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct TestFailures {
+    pub muted: i32,
+    pub investigated: i32,
+    pub new: i32,
+    pub web_url: Option<String>,
+    #[serde(default)]
+    pub tests: Vec<TestResult>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct TestResult {
+    pub name: String,
+    pub status: String,
+    pub duration: Option<i64>,
+    pub web_url: Option<String>
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct Coverage {
+    pub percentage: f64,
+    pub delta: Option<f64>,
+    pub threshold: Option<f64>
+}
+
+// A metric a build measured, such as the time taken by a benchmark
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct Benchmark {
+    pub name: String,
+    pub value: f64,
+    pub unit: Option<String>
+}
+
+/// A build server that pull requests are built on, such as TeamCity. Shared by the scheduler's workers.
+pub trait ContinuousIntegrator: Send + Sync {
+    fn get_build_list(&self, branch: &str) -> Result<Vec<Build>, BuilderError>;
+    fn get_build(&self, build_id: i32) -> Result<BuildDetails, BuilderError>;
+    fn queue_build(&self, branch: &str) -> Result<BuildDetails, BuilderError>;
+    fn get_personal_build_list(&self, commit: &str) -> Result<Vec<Build>, BuilderError>;
+    fn queue_personal_build(&self, diff: &str, description: &str) -> Result<BuildDetails, BuilderError>;
+    fn tag_build(&self, build: &BuildDetails, tags: &Vec<String>) -> Result<(), BuilderError>;
+    fn pin_build(&self, build: &BuildDetails, comment: &str) -> Result<(), BuilderError>;
+    fn comment_build(&self, build: &BuildDetails, comment: &str) -> Result<(), BuilderError>;
+    fn cancel_build(&self, build: &BuildDetails, comment: &str) -> Result<(), BuilderError>;
+    fn get_coverage(&self, build: &BuildDetails, target_branch: &str) -> Result<Option<Coverage>, BuilderError>;
+
+    // Builders that publish no benchmarks have none
+    fn get_benchmarks(&self, _: &BuildDetails) -> Result<Vec<Benchmark>, BuilderError> {
+        Ok(vec![])
+    }
+
+    fn pin_merged(&self) -> bool {
+        false
+    }
+
+    fn personal_builds(&self) -> bool {
+        false
+    }
+
+    // Identifies the builder to `components` routing, like the build configuration of a TeamCity builder
+    fn name(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Polls the configured repository forever, building every open pull request and reporting the results.
+pub fn run(config: &Config) {
+    run_with_registry(config, &registry::Registry::with_defaults())
+}
+
+/// Like `run`, but creates backends with the factories in `registry`, which may include third party backends.
+pub fn run_with_registry(config: &Config, registry: &registry::Registry) {
+    let mut fanout = Fanout::<Message>::new();
+    if let Some(true) = config.stdout_broadcast {
+        let subscriber = fanout.subscribe();
+        thread::spawn(move || {
+            for message in subscriber.iter() {
+                println!("Fanout broadcast received: {:?} {}", message.opcode, message.payload)
+            }
+        });
+    }
+
+    if let Some(ref cassette) = config.cassette {
+        cassette::insert(cassette::Cassette::load(cassette).unwrap());
+    }
+    if let Some(ref chaos) = config.chaos {
+        println!("{}Chaos mode: failing {}% of requests", prefix(0), chaos.rate * 100.0);
+        chaos::enable(chaos::Chaos::new(chaos).unwrap());
+    }
+    if let Some(ref seen) = config.seen {
+        seen::enable(seen::SeenStore::load(seen).unwrap());
+    }
+    if let Some(ref cancel) = config.cancel {
+        cancel::enable(cancel);
+    }
+    if let Some(ref retry) = config.retry {
+        retry::enable(retry).unwrap();
+    }
+    if let Some(ref flaky) = config.flaky {
+        flaky::enable(flaky::OutcomeStore::load(flaky).unwrap());
+    }
+
+    let messages = i18n::Messages::new(&config.locale, &config.labels);
+    let mut watchers = match config.watches {
+        Some(ref watches) => watches::create(watches, registry, &messages, &fanout).unwrap(),
+        None => {
+            let repository_backend = config.repository.clone().or_else(|| legacy_repository(config))
+                .expect("A repository must be configured");
+            let repository = registry.create_repository(&repository_backend, &messages, &fanout).unwrap();
+            let builder_backend = config.builder.clone().or_else(|| legacy_builder(config))
+                .expect("A builder must be configured");
+            let builders = registry.create_builders(&builder_backend).unwrap();
+            vec![watches::Watcher::new(repository, builders, &fanout)]
+        }
+    };
+    let pipeline = registry.create_pipeline(&config.pipeline).unwrap();
+    let mut notifiers = config.notifiers.clone().unwrap_or(vec![]);
+    if let Some(notifier) = legacy_notifier(config) {
+        notifiers.push(notifier);
+    }
+    for notifier in &notifiers {
+        registry.start_notifier(notifier, fanout.subscribe(), &messages).unwrap();
+    }
+    let _badges = config.badges.as_ref().map(|badges| badges::serve(badges, fanout.subscribe()).unwrap());
+    if let Some(ref history) = config.history {
+        history::record(history, fanout.subscribe());
+    }
+    #[cfg(feature = "webhook")]
+    let receiver = config.webhook.as_ref().map(|webhook| webhook::receive(webhook).unwrap());
+
+    let scheduler = config.scheduler.as_ref().map(|scheduler| scheduler::Scheduler::new(scheduler).unwrap());
+
+    // Watched repositories take turns, unless a scheduler shares its workers among all of them
+    loop {
+        let poll = |watcher: &mut watches::Watcher| {
+            if let Some(ref name) = watcher.name {
+                println!("{}Watching {}", prefix(0), name);
+            }
+            if let Err(err) = watcher.poll(&pipeline, config, &messages, scheduler.as_ref()) {
+                println!("{}Error getting Pull Requests: {}", prefix(0), err);
+            }
+        };
+        match scheduler {
+            Some(_) => thread::scope(|scope| {
+                for watcher in &mut watchers {
+                    scope.spawn(move || poll(watcher));
+                }
+            }),
+            None => watchers.iter_mut().for_each(poll)
+        }
+        // Polls again once something changed instead of straight away
+        #[cfg(feature = "webhook")]
+        {
+            if let Some(ref receiver) = receiver {
+                receiver.wait();
+            }
+        }
+    }
+}
+
+/// Runs a command given on the command line after the configuration, such as `release cut 1.2`, against the
+/// configured repository instead of polling it, or `export` of the recorded history. Returns what to print.
+pub fn run_command(config: &Config, args: &[String]) -> Result<String, String> {
+    match (args.first().map(String::as_str), &config.history) {
+        (Some("export"), &Some(ref history)) => return history::export(history, &args[1..]),
+        (Some("export"), &None) => return Err("The export command needs a history section".to_owned()),
+        _ => {}
+    }
+    let messages = i18n::Messages::new(&config.locale, &config.labels);
+    let repository_backend = match config.repository.clone().or_else(|| legacy_repository(config)) {
+        Some(backend) => backend,
+        None => return Err("A repository must be configured".to_owned())
+    };
+    let repository = match registry::Registry::with_defaults().create_repository(&repository_backend, &messages,
+            &Fanout::<Message>::new()) {
+        Ok(repository) => repository,
+        Err(err) => return Err(err.to_string())
+    };
+    match (args.first().map(String::as_str), &config.release) {
+        (Some("release"), &Some(ref release)) => release::command(&*repository, release, &args[1..], &messages),
+        (Some("release"), &None) => Err("The release commands need a release section".to_owned()),
+        _ => Err(format!("Unknown command {}", args.join(" ")))
+    }
+}
+
+/// Runs a single polling cycle of `run`: pins merged builds if a builder asks for it, then runs `pipeline` for every
+/// open pull request. `pinned` remembers the merged pull requests already pinned across cycles.
+pub fn poll_once(repository: &Repository, matrix: &Vec<&ContinuousIntegrator>, pipeline: &pipeline::Pipeline,
+        config: &Config, messages: &i18n::Messages, fanout: &Fanout<Message>, pinned: &mut HashSet<i32>,
+        scheduler: Option<&scheduler::Scheduler>) -> Result<(), RepositoryError> {
+    let sleep_duration = std::time::Duration::new(config.run_interval, 0);
+    let now = time::now_utc().to_timespec().sec;
+    let shard = config.shard.as_ref().map(|sharding| match shard::announce(sharding, now) {
+        Ok(shard) => shard,
+        Err(err) => {
+            println!("{}Unable to find the other instances, processing nothing: {}", prefix(0), err);
+            shard::Shard::new(&sharding.instance, vec![])
+        }
+    });
+    // Merged pull requests are left to a single instance
+    if shard.as_ref().map_or(true, |shard| shard.owns("merged")) {
+        if matrix.iter().any(|ci| ci.pin_merged()) {
+            pin_merged_builds(repository, matrix, fanout.tag(), pinned);
+        }
+        if let Some(ref jira) = config.jira {
+            jira::transition_merged(repository, jira);
+        }
+        if let Some(ref coverage) = config.coverage {
+            coverage::record_merged(repository, matrix, coverage);
+        }
+        if let Some(ref benchmarks) = config.benchmarks {
+            benchmarks::record_merged(repository, matrix, benchmarks);
+        }
+        if let Some(ref history) = config.history {
+            history::record_merged(repository, history);
+        }
+        backport_merged(repository, config, messages);
+    }
+
+    let pull_requests = match repository.get_pr_list() {
+        Err(err) => return Err(err),
+        Ok(prs) => {
+            println!("{}{} Open Pull Requests Found", prefix(0), prs.len());
+            prs
+        }
+    };
+    if let Some(ref preview) = config.preview {
+        preview::tear_down_closed(&pull_requests, preview);
+    }
+    seen::forget_closed(fanout.tag(), &pull_requests);
+    flaky::forget_closed(fanout.tag(), &pull_requests);
+    cancel::cancel_closed(fanout.tag(), &pull_requests, matrix,
+        |pr| shard.as_ref().map_or(true, |shard| shard.owns_pr(pr)));
+    let pull_requests = match shard {
+        Some(ref shard) => pull_requests.into_iter().filter(|pr| shard.owns_pr(pr)).collect::<Vec<_>>(),
+        None => pull_requests
+    };
+    let pull_requests = match config.incremental {
+        Some(ref incremental) => {
+            let changed = incremental::select(pull_requests, incremental, fanout);
+            println!("{}{} changed since the last poll", prefix(0), changed.len());
+            changed
+        },
+        None => pull_requests
+    };
+
+    // Pull requests are prioritized by the size their commit was measured at, if it was, as measuring them is left to
+    // the workers
+    let pull_requests = match (scheduler.map_or(false, |scheduler| scheduler.prioritized()), &config.size) {
+        (true, &Some(_)) => pull_requests.into_iter().map(|pr| PullRequest { size: size::known(&pr), ..pr }).collect(),
+        _ => pull_requests
+    };
+
+    let handle = |pr: &PullRequest| {
+        println!("{}Pull Request #{} ({})", prefix(1), pr.id, pr.web_url);
+        let pr = match config.size {
+            Some(ref size_config) => size::measure(pr, repository, config, size_config, messages),
+            None => pr.to_owned()
+        };
+        let context = pipeline::Context {
+            pr: &pr,
+            repository: repository,
+            matrix: matrix,
+            config: config,
+            messages: messages,
+            fanout: fanout
+        };
+        let outcome = match skip_ci::marker(&pr, repository, config.skip_ci.as_ref()) {
+            Some(marker) => skip_ci::skip(&pr, repository, &marker, messages),
+            None => pipeline.run(&context)
+        };
+        match outcome {
+            Ok(pipeline::Outcome::Skip(reason)) => println!("{}Skipped: {}", prefix(2), reason),
+            Ok(pipeline::Outcome::Continue) => {},
+            Err(handled_pr) => println!("{}{}", prefix(2), handled_pr)
+        }
+    };
+    // Pull requests are handled one after the other, pausing between them, unless a scheduler hands them to its
+    // workers in order of priority, where the worker limits throttle instead so no slot is held while sleeping
+    match scheduler {
+        Some(scheduler) => {
+            let prioritized = pull_requests.iter().map(|pr| (scheduler.score(pr, now * 1000), pr)).collect();
+            scheduler.run(prioritized, |&pr| handle(pr))
+        },
+        None => pull_requests.iter().for_each(|pr| {
+            handle(pr);
+            std::thread::sleep(sleep_duration);
+        })
+    }
+    Ok(())
+}
+
+// Backends configured with their own top level section are created through the registry like any other
+#[cfg(any(feature = "bitbucket", feature = "teamcity", feature = "telegram"))]
+fn section_backend<T>(kind: &str, section: &Option<T>) -> Option<registry::BackendConfig>
+        where T: serde::Serialize {
+    section.as_ref().map(|section| registry::BackendConfig {
+        kind: kind.to_owned(),
+        settings: serde_json::to_value(section).expect("Configuration should be serializable")
+    })
+}
+
+#[cfg(feature = "bitbucket")]
+fn legacy_repository(config: &Config) -> Option<registry::BackendConfig> {
+    section_backend("bitbucket", &config.bitbucket)
+}
+
+#[cfg(not(feature = "bitbucket"))]
+fn legacy_repository(_: &Config) -> Option<registry::BackendConfig> {
+    None
+}
+
+#[cfg(feature = "teamcity")]
+fn legacy_builder(config: &Config) -> Option<registry::BackendConfig> {
+    section_backend("teamcity", &config.teamcity)
+}
+
+#[cfg(not(feature = "teamcity"))]
+fn legacy_builder(_: &Config) -> Option<registry::BackendConfig> {
+    None
+}
+
+#[cfg(feature = "git")]
+fn backport_merged(repository: &Repository, config: &Config, messages: &i18n::Messages) {
+    if let (&Some(ref backport), &Some(ref git)) = (&config.backport, &config.git) {
+        backport::backport_merged(repository, git, backport, messages);
+    }
+}
+
+#[cfg(not(feature = "git"))]
+fn backport_merged(_: &Repository, _: &Config, _: &i18n::Messages) {}
+
+#[cfg(feature = "telegram")]
+fn legacy_notifier(config: &Config) -> Option<registry::BackendConfig> {
+    section_backend("telegram", &config.telegram)
+}
+
+#[cfg(not(feature = "telegram"))]
+fn legacy_notifier(_: &Config) -> Option<registry::BackendConfig> {
+    None
+}
+
+/// Reads the configuration from `path`, or from `reader` when `path` is `-`.
+pub fn read_config<R>(path: &str, reader: R) -> Result<String, ConfigError>
+        where R : std::io::Read {
+    let mut file : Box<std::io::Read> = match path {
+        "-" => {
+            Box::new(reader)
+        },
+        path @ _ => {
+            match File::open(path) {
+                Ok(f) => Box::new(f),
+                Err(err) => return Err(ConfigError::Read(err))
+            }
+        }
+    };
+
+    let mut json = String::new();
+    match file.read_to_string(&mut json) {
+        Ok(_) => Ok(json),
+        Err(err) => Err(ConfigError::Read(err))
+    }
+}
+
+/// Parses a JSON configuration, substituting `"${NAME}"` strings with environment variables and filling in missing
+/// credentials from the `credentials` sets and from variables such as `BITBUCKET_PASSWORD` and `TEAMCITY_TOKEN`.
+pub fn parse_config(json: &str) -> Result<Config, ConfigError> {
+    let config = match serde_json::from_str(json) {
+        Ok(config) => config,
+        Err(err) => return Err(ConfigError::Parse(err))
+    };
+    let config = match environment::resolve(config, &|name: &str| std::env::var(name).ok()) {
+        Ok(config) => config,
+        Err(err) => return Err(err)
+    };
+    match serde_json::from_value(config) {
+        Ok(x) => Ok(x),
+        Err(err) => Err(ConfigError::Parse(err))
+    }
+}
+
+/// The latest build of the commit `pr` is at on `ci`, if there is one.
+pub fn get_latest_build(pr: &PullRequest, ci: &ContinuousIntegrator) -> Option<BuildDetails> {
+    let branch_name = pr.branch_name();
+    let pr_commit = &pr.from_commit;
+
+    println!("{}Branch: {}", prefix(2), branch_name);
+    println!("{}Commit: {}", prefix(2), pr_commit);
+    println!("{}Finding latest build from branch", prefix(2));
+
+    let latest_build = match ci.get_build_list(&branch_name) {
+        Ok(ref build_list) => {
+            if build_list.is_empty() {
+                println!("{}Build does not exist -- running build", prefix(2));
+                None
+            } else {
+                let latest_build_id = build_list.first().unwrap().id;
+                match ci.get_build(latest_build_id) {
+                    Ok(build) =>  {
+                        println!("{}Latest Build Found {}", prefix(2), build.web_url);
+                        Some(build)
+                    },
+                    Err(err) => {
+                        println!("{}Unable to retrieve information for build ID {}: {}", prefix(2), latest_build_id, err);
+                        None
+                    }
+                }
+            }
+        },
+        Err(err) => {
+            println!("{}Error fetching builds -- queuing anyway: {}", prefix(2), err);
+            None
+        }
+    };
+
+    match latest_build {
+        None => None,
+        Some(ref build) => {
+            match build.commit {
+                Some(ref commit) => {
+                    if commit == pr_commit {
+                        println!("{}Commit matches -- skipping", prefix(2));
+                        Some(build.to_owned())
+                    } else {
+                        println!("{}Commit does not match with {} -- scheduling build", prefix(2), commit);
+                        cancel::superseded(pr, ci, build);
+                        None
+                    }
+                },
+                None if build.state == BuildState::Queued => {
+                    println!("{}Build is queued -- skipping", prefix(2));
+                    Some(build.to_owned())
+                },
+                _ => {
+                    println!("{}Unknown error -- scheduling build", prefix(2));
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Finds or schedules a build of `pr` on `ci` and reports its status to `repo`.
+pub fn handle_pull_request(pr: &PullRequest, repo: &Repository, ci: &ContinuousIntegrator, personal: bool,
+        queue_alert: &Option<QueueAlert>, messages: &i18n::Messages, fanout: &Fanout<Message>) -> Result<(), Error> {
+    fanout.broadcast(&Message::new(OpCode::OpenPullRequest, &pr));
+
+    let latest_build = match personal {
+        true => get_latest_personal_build(pr, ci),
+        false => get_latest_build(pr, ci)
+    };
+
+    match latest_build {
+        None if seen::unchanged(fanout.tag(), pr) => {
+            println!("{}Build already queued for this commit -- not queuing again", prefix(2));
+            Ok(())
+        },
+        None => {
+            fanout.broadcast(&Message::new(OpCode::BuildNotFound, &pr).about(pr));
+            let scheduled_build = match personal {
+                true => schedule_personal_build(pr, ci, repo),
+                false => schedule_build(pr, ci, repo)
+            };
+            scheduled_build.map(|build| {
+                seen::record(fanout.tag(), pr);
+                fanout.broadcast(&Message::new(OpCode::BuildScheduled, &build).about(pr));
+            })
+        },
+        Some(build) => {
+            let build = with_coverage(pr, build, ci);
+            let build = check_queue_wait(build, queue_alert, messages, fanout);
+            let build = match review_build(pr, ci, build, messages, fanout) {
+                retry::Action::Report(build) => build,
+                retry::Action::Retry(retry) => {
+                    println!("{}Building commit again -- retry {}", prefix(2), retry);
+                    let scheduled_build = match personal {
+                        true => schedule_personal_build(pr, ci, repo),
+                        false => schedule_build(pr, ci, repo)
+                    };
+                    return scheduled_build.map(|build| {
+                        fanout.broadcast(&Message::new(OpCode::BuildScheduled, &build).about(pr))
+                    });
+                }
+            };
+            fanout.broadcast(&Message::new(OpCode::BuildFound, &build).about(pr));
+            check_build_status(pr, &build, repo)
+                .map_err(Error::from)
+                .map(|(build_state, build_status)| {
+                    fanout.broadcast(&Message::new(build_opcode(&build_state, &build_status), &build).about(pr));
+                })
+        }
+    }
+}
+
+fn get_latest_personal_build(pr: &PullRequest, ci: &ContinuousIntegrator) -> Option<BuildDetails> {
+    println!("{}Commit: {}", prefix(2), pr.from_commit);
+    println!("{}Finding latest personal build for commit", prefix(2));
+
+    match ci.get_personal_build_list(&pr.from_commit) {
+        Ok(ref build_list) if !build_list.is_empty() => {
+            let latest_build_id = build_list.first().unwrap().id;
+            match ci.get_build(latest_build_id) {
+                Ok(build) => {
+                    println!("{}Latest Personal Build Found {}", prefix(2), build.web_url);
+                    // Personal builds are looked up by commit, but report the revision they were applied on
+                    Some(BuildDetails { commit: Some(pr.from_commit.to_owned()), ..build })
+                },
+                Err(err) => {
+                    println!("{}Unable to retrieve information for build ID {}: {}", prefix(2), latest_build_id, err);
+                    None
+                }
+            }
+        },
+        Ok(_) => {
+            println!("{}Personal build does not exist -- running build", prefix(2));
+            None
+        },
+        Err(err) => {
+            println!("{}Error fetching personal builds -- queuing anyway: {}", prefix(2), err);
+            None
+        }
+    }
+}
+
+/// Finds or schedules a build of `pr` on every build configuration in `matrix` and reports them to `repo` together.
+pub fn handle_pull_request_matrix(pr: &PullRequest, repo: &Repository, matrix: &Vec<&ContinuousIntegrator>,
+        queue_alert: &Option<QueueAlert>, messages: &i18n::Messages, fanout: &Fanout<Message>) -> Result<(), Error> {
+    let routes: Vec<(&ContinuousIntegrator, Option<String>)> = matrix.iter().map(|ci| (*ci, None)).collect();
+    handle_pull_request_components(pr, repo, &routes, queue_alert, messages, fanout)
+}
+
+/// Like `handle_pull_request_matrix`, but labels each build with the monorepo components it was routed for.
+pub fn handle_pull_request_components(pr: &PullRequest, repo: &Repository,
+        routes: &Vec<(&ContinuousIntegrator, Option<String>)>, queue_alert: &Option<QueueAlert>,
+        messages: &i18n::Messages, fanout: &Fanout<Message>) -> Result<(), Error> {
+    fanout.broadcast(&Message::new(OpCode::OpenPullRequest, &pr));
+
+    let mut builds = Vec::<BuildDetails>::new();
+    let unchanged = seen::unchanged(fanout.tag(), pr);
+    let mut queued_any = false;
+    for &(ci, ref component) in routes {
+        let found = match get_latest_build(pr, ci) {
+            None if unchanged => {
+                println!("{}Build already queued for this commit -- not queuing again", prefix(2));
+                builds.push(BuildDetails { component: component.to_owned(), ..pending_build(pr, ci) });
+                continue;
+            },
+            None => None,
+            Some(build) => {
+                let build = with_coverage(pr, build, ci);
+                let build = check_queue_wait(build, queue_alert, messages, fanout);
+                match review_build(pr, ci, build, messages, fanout) {
+                    retry::Action::Report(build) => Some(build),
+                    retry::Action::Retry(retry) => {
+                        println!("{}Building commit again -- retry {}", prefix(2), retry);
+                        None
+                    }
+                }
+            }
+        };
+        let build = match found {
+            None => {
+                fanout.broadcast(&Message::new(OpCode::BuildNotFound, &pr).about(pr));
+                println!("{}Scheduling build", prefix(2));
+                match ci.queue_build(&pr.branch_name()) {
+                    Err(err) => {
+                        println!("{}Error queuing build: {}", prefix(2), err);
+                        return Err(Error::from(err))
+                    },
+                    Ok(queued) => {
+                        println!("{}Build Queued: {}", prefix(2), queued.web_url);
+                        annotate_build(pr, &queued, ci, &pr.build_tags());
+                        fanout.broadcast(&Message::new(OpCode::BuildScheduled, &queued).about(pr));
+                        queued_any = true;
+                        queued
+                    }
+                }
+            },
+            Some(build) => {
+                fanout.broadcast(&Message::new(OpCode::BuildFound, &build).about(pr));
+                fanout.broadcast(&Message::new(build_opcode(&build.state, &build.status), &build).about(pr));
+                build
+            }
+        };
+        builds.push(BuildDetails { component: component.to_owned(), ..build });
+    }
+    if queued_any {
+        seen::record(fanout.tag(), pr);
+    }
+
+    repo.build_matrix(pr, &builds).map_err(Error::from)
+}
+
+// Stands in for a build queued on an earlier poll that `ci` does not list yet, so that the matrix keeps its row
+fn pending_build(pr: &PullRequest, ci: &ContinuousIntegrator) -> BuildDetails {
+    BuildDetails {
+        id: 0,
+        build_id: ci.name().unwrap_or("").to_owned(),
+        web_url: pr.web_url.to_owned(),
+        commit: Some(pr.from_commit.to_owned()),
+        state: BuildState::Queued,
+        status: BuildStatus::Unknown,
+        status_text: None,
+        log_excerpt: None,
+        coverage: None,
+        test_failures: None,
+        queue_wait: None,
+        parts: vec![],
+        steps: vec![],
+        notes: vec![],
+        component: None
+    }
+}
+
+// Pull requests pinned are remembered in `pinned`, and in the seen store across restarts if one is enabled
+fn pin_merged_builds(repo: &Repository, matrix: &Vec<&ContinuousIntegrator>, tag: Option<&str>,
+        pinned: &mut HashSet<i32>) {
+    let pull_requests = match repo.get_merged_pr_list() {
+        Err(err) => {
+            println!("{}Error getting merged Pull Requests: {}", prefix(0), err);
+            return;
+        },
+        Ok(prs) => prs
+    };
+
+    let unpinned = pull_requests.iter().filter(|pr| !pinned.contains(&pr.id) && !seen::pinned(tag, pr))
+        .collect::<Vec<_>>();
+    for pr in unpinned {
+        println!("{}Merged Pull Request #{} ({})", prefix(1), pr.id, pr.web_url);
+        let comment = format!("Merged in Pull Request #{}: {}\n{}", pr.id, pr.title, pr.web_url);
+        let mut all_pinned = true;
+        for ci in matrix {
+            match get_latest_build(pr, *ci) {
+                Some(ref build) if build.state == BuildState::Finished && build.status == BuildStatus::Success => {
+                    match ci.pin_build(build, &comment) {
+                        Ok(_) => println!("{}Build pinned: {}", prefix(2), build.web_url),
+                        Err(err) => {
+                            println!("{}Error pinning build: {}", prefix(2), err);
+                            all_pinned = false;
+                        }
+                    }
+                },
+                _ => println!("{}No successful build for merged commit -- not pinning", prefix(2))
+            }
+        }
+
+        if all_pinned {
+            pinned.insert(pr.id);
+            seen::record_pinned(tag, pr);
+        }
+    }
+}
+
+fn build_opcode(build_state: &BuildState, build_status: &BuildStatus) -> OpCode {
+    match *build_state {
+        BuildState::Queued => OpCode::BuildQueued,
+        BuildState::Running => OpCode::BuildRunning,
+        BuildState::Finished => OpCode::BuildFinished { success: *build_status == BuildStatus::Success }
+    }
+}
+
+fn with_coverage(pr: &PullRequest, build: BuildDetails, ci: &ContinuousIntegrator) -> BuildDetails {
+    if build.state != BuildState::Finished {
+        return build;
+    }
+
+    match ci.get_coverage(&build, &pr.target_branch_name()) {
+        Ok(coverage) => BuildDetails { coverage: coverage, ..build },
+        Err(err) => {
+            println!("{}Error fetching coverage: {}", prefix(2), err);
+            build
+        }
+    }
+}
+
+// Whether the commit of a found build should be built again, after an infrastructure failure or a flaky one, or else
+// the build to report
+fn review_build(pr: &PullRequest, ci: &ContinuousIntegrator, build: BuildDetails, messages: &i18n::Messages,
+        fanout: &Fanout<Message>) -> retry::Action {
+    match retry::check(fanout.tag(), pr, ci, build, messages) {
+        retry::Action::Report(build) => flaky::check(fanout.tag(), pr, ci, build, messages),
+        retry => retry
+    }
+}
+
+fn check_queue_wait(build: BuildDetails, queue_alert: &Option<QueueAlert>, messages: &i18n::Messages,
+        fanout: &Fanout<Message>) -> BuildDetails {
+    let (threshold, comment) = match *queue_alert {
+        Some(ref queue_alert) => (queue_alert.threshold, queue_alert.comment == Some(true)),
+        None => return build
+    };
+
+    match build.queue_wait {
+        Some(queue_wait) if build.state == BuildState::Queued && queue_wait > threshold => {
+            println!("{}Build has been queued for {} seconds", prefix(2), queue_wait);
+            fanout.broadcast(&Message::new(OpCode::BuildQueueDelayed, &build));
+            let mut build = build;
+            if comment {
+                build.notes.push(messages.format("queue.delayed",
+                    &[("marker", messages.labels().warning()), ("minutes", &(threshold / 60).to_string())]));
+            }
+            build
+        },
+        _ => build
+    }
+}
+
+fn schedule_build(pr: &PullRequest, ci: &ContinuousIntegrator, repo: &Repository)
+    -> Result<BuildDetails, Error> {
+    println!("{}Scheduling build", prefix(2));
+    let queued_build = ci.queue_build(&pr.branch_name());
+    match queued_build {
+        Err(err) => {
+            println!("{}Error queuing build: {}", prefix(2), err);
+            Err(Error::from(err))
+        },
+        Ok(queued) => {
+            println!("{}Build Queued: {}", prefix(2), queued.web_url);
+            annotate_build(pr, &queued, ci, &pr.build_tags());
+            repo.build_queued(pr, &queued).map_err(Error::from).and(Ok(queued))
+        }
+    }
+}
+
+fn schedule_personal_build(pr: &PullRequest, ci: &ContinuousIntegrator, repo: &Repository)
+    -> Result<BuildDetails, Error> {
+    println!("{}Scheduling personal build", prefix(2));
+    let diff = match repo.get_pr_diff(pr) {
+        Ok(diff) => diff,
+        Err(err) => {
+            println!("{}Error fetching diff: {}", prefix(2), err);
+            return Err(Error::from(err))
+        }
+    };
+
+    let description = format!("Pull Request #{}: {}", pr.id, pr.title);
+    match ci.queue_personal_build(&diff, &description) {
+        Err(err) => {
+            println!("{}Error queuing personal build: {}", prefix(2), err);
+            Err(Error::from(err))
+        },
+        Ok(queued) => {
+            println!("{}Personal Build Queued: {}", prefix(2), queued.web_url);
+            let mut tags = pr.build_tags();
+            tags.push(pr.from_commit.to_owned());
+            annotate_build(pr, &queued, ci, &tags);
+            let queued = BuildDetails { commit: Some(pr.from_commit.to_owned()), ..queued };
+            repo.build_queued(pr, &queued).map_err(Error::from).and(Ok(queued))
+        }
+    }
+}
+
+fn annotate_build(pr: &PullRequest, build: &BuildDetails, ci: &ContinuousIntegrator, tags: &Vec<String>) {
+    match ci.tag_build(build, tags) {
+        Ok(_) => println!("{}Build tagged with {}", prefix(2), tags.join(", ")),
+        Err(err) => println!("{}Error tagging build: {}", prefix(2), err)
+    }
+    if let Err(err) = ci.comment_build(build, &pr.description()) {
+        println!("{}Error commenting on build: {}", prefix(2), err);
+    }
+}
+
+fn check_build_status(pr: &PullRequest, build: &BuildDetails, repo: &Repository)
+    -> Result<(BuildState, BuildStatus), RepositoryError> {
+    println!("{}Build exists: {}", prefix(2), build.web_url);
+    match build.state {
+        BuildState::Finished => match build.status {
+            BuildStatus::Success => repo.build_success(pr, build).and(Ok((BuildState::Finished, BuildStatus::Success))),
+            ref status @ _  => repo.build_failure(pr, build).and(Ok((BuildState::Finished, status.to_owned())))
+        },
+        BuildState::Running => repo.build_running(pr, build).and(Ok((BuildState::Running, build.status.to_owned()))),
+        BuildState::Queued  => repo.build_queued(pr, build).and(Ok((BuildState::Queued, build.status.to_owned())))
+    }
+}
+
+fn format_time() -> String {
+    time::strftime("%Y-%m-%d %T %z", &time::now()).unwrap()
+}
+
+fn prefix(x: usize) -> String {
+    format!("[{}]{} ", format_time(), std::iter::repeat_n("    ", x).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::{i18n, QueueAlert, ContinuousIntegrator, Build};
+    use super::{BuildDetails, BuildStatus, BuildState, Coverage};
+    use errors::{BuilderError, Error, RepositoryError};
+    use stubs::{StubBuild, StubRepository, pull_request};
+    use super::{read_config, get_latest_build, schedule_build};
+    #[cfg(all(feature = "bitbucket", feature = "teamcity", feature = "telegram"))]
+    use super::parse_config;
+    use super::{check_build_status, handle_pull_request_matrix, pin_merged_builds, with_coverage};
+    use super::{get_latest_personal_build, schedule_personal_build, check_queue_wait};
+    use std::collections::HashSet;
+    use fanout::{Fanout, Message};
+    use std::fs::File;
+    use std::io::{Read, Cursor};
+
+    fn build_success() -> BuildDetails {
+        BuildDetails {
+            id: 213232321,
+            build_id: "somethingsomething".to_owned(),
+            web_url: "http://www.goodbuilds.com/213213221".to_owned(),
+            commit: Some("363c1dfda4cdf5a01c2d210e49942c8c8e7e898b".to_owned()),
+            state: BuildState::Finished,
+            status: BuildStatus::Success,
+            status_text: Some("Build passed with flying colours".to_owned()),
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+    fn build_queuing() -> BuildDetails {
+        BuildDetails {
+            id: 213232321,
+            build_id: "somethingsomething".to_owned(),
+            web_url: "http://www.goodbuilds.com/1111".to_owned(),
+            commit: Some("363c1dfda4cdf5a01c2d210e49942c8c8e7e898b".to_owned()),
+            state: BuildState::Queued,
+            status: BuildStatus::Unknown,
+            status_text: None,
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+    fn build_running() -> BuildDetails {
+        BuildDetails {
+            id: 213232321,
+            build_id: "somethingsomething".to_owned(),
+            web_url: "http://www.goodbuilds.com/1111".to_owned(),
+            commit: Some("363c1dfda4cdf5a01c2d210e49942c8c8e7e898b".to_owned()),
+            state: BuildState::Running,
+            status: BuildStatus::Success,
+            status_text: None,
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+
+    fn build_failure() -> BuildDetails {
+        BuildDetails {
+            id: 213232321,
+            build_id: "somethingsomething".to_owned(),
+            web_url: "http://www.goodbuilds.com/213213221".to_owned(),
+            commit: Some("363c1dfda4cdf5a01c2d210e49942c8c8e7e898b".to_owned()),
+            state: BuildState::Finished,
+            status: BuildStatus::Failure,
+            status_text: Some("Build failed with walking monochrome".to_owned()),
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+    #[test]
+    fn it_reads_from_config_file() {
+        let mut expected = String::new();
+        if let Err(err) = File::open("tests/fixtures/config.json")
+                                .unwrap().read_to_string(&mut expected) {
+                                    panic!("Unable to read fixture: {}", err);
+                                }
+        let actual = read_config("tests/fixtures/config.json", Cursor::new("")).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn it_reads_fron_stdin_when_presented_with_dash() {
+        let payload = "foo bar baz";
+        let input = Cursor::new(payload);
+
+        let actual = read_config("-", input).unwrap();
+        assert_eq!(payload, actual);
+    }
+
+    #[test]
+    #[cfg(all(feature = "bitbucket", feature = "teamcity", feature = "telegram"))]
+    fn it_reads_and_parses_a_config_file() {
+        use super::{bitbucket, labels, teamcity, telegram, templates, Config};
+
+        let expected = Config {
+            bitbucket: Some(bitbucket::BitbucketCredentials {
+                username: "username".to_owned(),
+                password: "password".to_owned(),
+                credential_provider: None,
+                base_url: "https://www.example.com/bb/rest/api/latest".to_owned(),
+                project_slug: "foo".to_owned(),
+                repo_slug: "bar".to_owned(),
+                post_build: false,
+                templates: Some(templates::Templates {
+                    directory: Some("/app/src/config/templates".to_owned()),
+                    queued: None,
+                    success: None,
+                    failure: Some("❌ [Build]({{build.web_url}}) for {{commit}} has **failed**".to_owned()),
+                    matrix: None,
+                    summary: None,
+                    max_length: Some(30000),
+                    paste_url: None,
+                    overrides: Some(vec![templates::TemplateOverride {
+                        repository: None,
+                        target_branch: Some("release/".to_owned()),
+                        queued: None,
+                        success: None,
+                        failure: Some("❌ Release build for {{commit}} has **failed**, do not merge".to_owned()),
+                        matrix: None,
+                        summary: None
+                    }]),
+                    plain_text: Some(false)
+                }),
+                summary_comment: Some(false),
+                superseded_comments: Some("collapse".to_owned()),
+                all_commits: None,
+                approve: None
+            }),
+            teamcity: Some(teamcity::TeamcityCredentials {
+                username: Some("username".to_owned()),
+                password: Some("password".to_owned()),
+                token: None,
+                credential_provider: None,
+                build_id: "foobar".to_owned(),
+                base_url: "https://www.foobar.com/rest".to_owned(),
+                matrix: Some(vec!["foobar_windows".to_owned()]),
+                pin_merged: Some(false),
+                log_lines: Some(50),
+                coverage_threshold: Some(80.0),
+                personal_builds: Some(false),
+                composite: Some(false),
+                steps: None,
+                benchmark_prefix: None
+            }),
+            telegram: Some(telegram::TelegramCredentials {
+                enabled: true,
+                api_token: "XXX:XXXX".to_owned(),
+                room: -1234567890i64
+            }),
+            repository: None,
+            builder: None,
+            watches: None,
+            notifiers: None,
+            pipeline: None,
+            run_interval: 999,
+            stdout_broadcast: Some(false),
+            queue_alert: Some(QueueAlert {
+                threshold: 1800,
+                comment: Some(true)
+            }),
+            labels: Some(labels::Labels {
+                pending: None,
+                success: Some("[OK]".to_owned()),
+                failure: Some("[FAIL]".to_owned()),
+                warning: None,
+                pending_text: None,
+                success_text: None,
+                failure_text: None
+            }),
+            locale: Some("en".to_owned()),
+            cassette: None,
+            chaos: None,
+            size: None,
+            jira: None,
+            coverage: None,
+            benchmarks: None,
+            preview: None,
+            release: None,
+            merge: None,
+            badges: None,
+            history: None,
+            incremental: None,
+            shard: None,
+            seen: None,
+            cancel: None,
+            retry: None,
+            flaky: None,
+            scheduler: None,
+            skip_ci: None,
+            #[cfg(feature = "webhook")]
+            webhook: None,
+            #[cfg(feature = "git")]
+            git: None,
+            #[cfg(feature = "git")]
+            backport: None
+        };
+
+        let json_string = read_config("tests/fixtures/config.json", Cursor::new("")).unwrap();
+        let actual = parse_config(&json_string).unwrap();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn pull_request_build_tags_contain_pr_number_and_target_branch() {
+        let expected = vec!["pr-111".to_owned(), "master".to_owned()];
+        assert_eq!(expected, pull_request().build_tags());
+    }
+
+    #[test]
+    fn get_latest_build_returns_latest_buiild_successfully() {
+        let expected = &build_success();
+        let stub_build = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }, Build { id: 21323232 }]),
+            build: Ok(expected.to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let actual = get_latest_build(&pull_request(), &stub_build).unwrap();
+        assert_eq!(expected, &actual);
+    }
+
+    #[test]
+    fn get_latest_build_returns_none_if_no_builds_found() {
+        let stub_build = StubBuild {
+            build_list: Ok(vec![]),
+            build: Err("ignored".to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+        let actual = get_latest_build(&pull_request(), &stub_build);
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn get_latest_build_returns_none_if_commit_mismatches() {
+        let mut build = build_success();
+        build.commit = Some("foobar".to_owned());
+
+        let stub_build = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }, Build { id: 21323232 }]),
+            build: Ok(build.to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let actual = get_latest_build(&pull_request(), &stub_build);
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn get_latest_build_returns_build_if_build_queued() {
+        let expected = &build_queuing();
+        let stub_build = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }, Build { id: 21323232 }]),
+            build: Ok(expected.to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let actual = get_latest_build(&pull_request(), &stub_build).unwrap();
+        assert_eq!(expected, &actual);
+    }
+
+    #[test]
+    fn get_latest_build_returns_none_for_error_fetching_build_list() {
+        let stub_build = StubBuild {
+            build_list: Err("foobar".to_owned()),
+            build: Err("This does not matter".to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let actual = get_latest_build(&pull_request(), &stub_build);
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn get_latest_build_returns_none_for_error_fetching_build() {
+        let stub_build = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }, Build { id: 21323232 }]),
+            build: Err("foobar".to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let actual = get_latest_build(&pull_request(), &stub_build);
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn get_latest_build_returns_none_for_pathlogical_errors() {
+        let mut build = build_success();
+        build.commit = None;
+
+        let stub_build = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }, Build { id: 21323232 }]),
+            build: Ok(build.to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let actual = get_latest_build(&pull_request(), &stub_build);
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn schedule_build_returns_build_on_scheduling() {
+        let build = build_queuing();
+        let stub_build = StubBuild {
+            build_list: Err("This does not matter".to_owned()),
+            build: Err("This does not matter".to_owned()),
+            queued: Ok(build.to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let stub_repo = StubRepository {
+            pr_list: Err("This does not matter".to_owned()),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let actual = schedule_build(&pull_request(), &stub_build, &stub_repo);
+        assert_eq!(Some(build), actual.ok());
+    }
+
+    #[test]
+    fn check_build_status_returns_correct_state_and_status_on_build_success() {
+        let build = build_success();
+        let stub_repo = StubRepository {
+            pr_list: Err("This does not matter".to_owned()),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let actual = check_build_status(&pull_request(), &build, &stub_repo);
+        assert_eq!(Some((BuildState::Finished, BuildStatus::Success)), actual.ok());
+    }
+
+    #[test]
+    fn check_build_status_returns_correct_state_and_status_on_build_failure() {
+        let build = build_failure();
+        let stub_repo = StubRepository {
+            pr_list: Err("This does not matter".to_owned()),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let actual = check_build_status(&pull_request(), &build, &stub_repo);
+        assert_eq!(Some((BuildState::Finished, BuildStatus::Failure)), actual.ok());
+    }
+
+    #[test]
+    fn check_build_status_returns_correct_state_and_status_for_queued_builds() {
+        let build = build_queuing();
+
+        let stub_repo = StubRepository {
+            pr_list: Err("This does not matter".to_owned()),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let actual = check_build_status(&pull_request(), &build, &stub_repo);
+        assert_eq!(Some((BuildState::Queued, BuildStatus::Unknown)), actual.ok());
+    }
+
+    #[test]
+    fn check_build_status_returns_correct_state_and_status_for_running_builds() {
+        let build = build_running();
+
+        let stub_repo = StubRepository {
+            pr_list: Err("This does not matter".to_owned()),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let actual = check_build_status(&pull_request(), &build, &stub_repo);
+        assert_eq!(Some((BuildState::Running, BuildStatus::Success)), actual.ok());
+    }
+
+    #[test]
+    fn handle_pull_request_matrix_reports_all_builds_to_repository() {
+        let linux = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }]),
+            build: Ok(build_success()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+        let windows = StubBuild {
+            build_list: Ok(vec![]),
+            build: Err("This does not matter".to_owned()),
+            queued: Ok(build_queuing()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let stub_repo = StubRepository {
+            pr_list: Err("This does not matter".to_owned()),
+            success: Err("This does not matter".to_owned()),
+            running: Err("This does not matter".to_owned()),
+            failure: Err("This does not matter".to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let matrix: Vec<&ContinuousIntegrator> = vec![&linux, &windows];
+        let actual = handle_pull_request_matrix(&pull_request(), &stub_repo, &matrix, &None,
+            &i18n::Messages::new(&None, &None), &Fanout::<Message>::new());
+        assert_eq!(Some(()), actual.ok());
+    }
+
+    #[test]
+    fn handle_pull_request_matrix_returns_error_when_a_build_cannot_be_queued() {
+        let linux = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }]),
+            build: Ok(build_success()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+        let windows = StubBuild {
+            build_list: Ok(vec![]),
+            build: Err("This does not matter".to_owned()),
+            queued: Err("foobar".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let stub_repo = StubRepository {
+            pr_list: Err("This does not matter".to_owned()),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let matrix: Vec<&ContinuousIntegrator> = vec![&linux, &windows];
+        let actual = handle_pull_request_matrix(&pull_request(), &stub_repo, &matrix, &None,
+            &i18n::Messages::new(&None, &None), &Fanout::<Message>::new());
+        match actual {
+            Err(Error::Builder(BuilderError::InvalidResponse(ref message))) => assert_eq!("foobar", message),
+            _ => panic!("Expected the queuing error to be returned")
+        }
+    }
+
+    #[test]
+    fn pin_merged_builds_remembers_pinned_pull_requests() {
+        let stub_build = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }]),
+            build: Ok(build_success()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let stub_repo = StubRepository {
+            pr_list: Ok(vec![pull_request()]),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let mut pinned = HashSet::<i32>::new();
+        let matrix: Vec<&ContinuousIntegrator> = vec![&stub_build];
+        pin_merged_builds(&stub_repo, &matrix, None, &mut pinned);
+        assert!(pinned.contains(&pull_request().id));
+    }
+
+    #[test]
+    fn pin_merged_builds_retries_failed_pins() {
+        let stub_build = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }]),
+            build: Ok(build_success()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Err("foobar".to_owned()),
+            coverage: Ok(None)
+        };
+
+        let stub_repo = StubRepository {
+            pr_list: Ok(vec![pull_request()]),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let mut pinned = HashSet::<i32>::new();
+        let matrix: Vec<&ContinuousIntegrator> = vec![&stub_build];
+        pin_merged_builds(&stub_repo, &matrix, None, &mut pinned);
+        assert!(pinned.is_empty());
+    }
+
+    #[test]
+    fn with_coverage_attaches_coverage_to_finished_builds() {
+        let coverage = Coverage {
+            percentage: 85.5,
+            delta: Some(-1.25),
+            threshold: Some(80.0)
+        };
+        let stub_build = StubBuild {
+            build_list: Err("This does not matter".to_owned()),
+            build: Err("This does not matter".to_owned()),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(Some(coverage.to_owned()))
+        };
+
+        let actual = with_coverage(&pull_request(), build_success(), &stub_build);
+        assert_eq!(Some(coverage), actual.coverage);
+
+        let actual = with_coverage(&pull_request(), build_running(), &stub_build);
+        assert_eq!(None, actual.coverage);
+    }
+
+    #[test]
+    fn get_latest_personal_build_reports_the_pull_request_commit() {
+        let mut build = build_success();
+        build.commit = Some("foobar".to_owned());
+
+        let stub_build = StubBuild {
+            build_list: Ok(vec![Build { id: 213232321 }]),
+            build: Ok(build),
+            queued: Err("This does not matter".to_owned()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let actual = get_latest_personal_build(&pull_request(), &stub_build).unwrap();
+        assert_eq!(Some(pull_request().from_commit), actual.commit);
+    }
+
+    #[test]
+    fn schedule_personal_build_fails_when_diff_is_unavailable() {
+        let stub_build = StubBuild {
+            build_list: Err("This does not matter".to_owned()),
+            build: Err("This does not matter".to_owned()),
+            queued: Ok(build_queuing()),
+            tagged: Ok(()),
+            pinned: Ok(()),
+            coverage: Ok(None)
+        };
+
+        let stub_repo = StubRepository {
+            pr_list: Err("This does not matter".to_owned()),
+            success: Ok(()),
+            running: Ok(()),
+            failure: Ok(()),
+            queued: Ok(()),
+            matrix: Ok(()),
+            diff: Err("foobar".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+
+        let actual = schedule_personal_build(&pull_request(), &stub_build, &stub_repo);
+        match actual {
+            Err(Error::Repository(RepositoryError::Template(ref message))) => assert_eq!("foobar", message),
+            _ => panic!("Expected the diff error to be returned")
+        }
+    }
+
+    #[test]
+    fn check_queue_wait_adds_note_when_threshold_is_exceeded() {
+        let queue_alert = Some(QueueAlert {
+            threshold: 600,
+            comment: Some(true)
+        });
+
+        let mut build = build_queuing();
+        build.queue_wait = Some(900);
+        let actual = check_queue_wait(build, &queue_alert, &i18n::Messages::new(&None, &None), &Fanout::<Message>::new());
+        assert_eq!(1, actual.notes.len());
+
+        let mut build = build_queuing();
+        build.queue_wait = Some(300);
+        let actual = check_queue_wait(build, &queue_alert, &i18n::Messages::new(&None, &None), &Fanout::<Message>::new());
+        assert!(actual.notes.is_empty());
+    }
+}
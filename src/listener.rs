@@ -0,0 +1,342 @@
+use std::convert::Infallible;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use std::fs::File;
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+use std::io::Read;
+#[cfg(feature = "rustls")]
+use std::io::BufReader;
+use std::net::{self, SocketAddr};
+use std::sync::Arc;
+use bytes::Bytes;
+use futures_util::future::{self, Either, Future, FutureExt};
+use futures_util::stream::StreamExt;
+use http_body_util::{BodyExt, Full};
+use hyper::{HeaderMap, Method, StatusCode};
+use hyper::body::Incoming;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+#[cfg(feature = "rustls")]
+use rustls_tls::{RootCertStore, ServerConfig};
+#[cfg(feature = "rustls")]
+use rustls_tls::pki_types::{CertificateDer, PrivateKeyDer};
+#[cfg(feature = "rustls")]
+use rustls_tls::pki_types::pem::PemObject;
+#[cfg(feature = "rustls")]
+use rustls_tls::server::WebPkiClientVerifier;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tokio::task;
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+use tokio_native_tls::native_tls::{self, Identity};
+#[cfg(feature = "rustls")]
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::TcpListenerStream;
+
+use ::errors::ConfigError;
+use ::runtime;
+
+// Where one of the daemon's own HTTP endpoints listens, such as the webhook receiver
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct ListenerConfig {
+    // Such as "0.0.0.0:8080"
+    pub address: String,
+    pub tls: Option<TlsConfig>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct TlsConfig {
+    // PEM certificate chain and private key, used with the `rustls` feature
+    pub certificate: Option<String>,
+    pub key: Option<String>,
+    // PKCS #12 archive of the certificate and key and its password, used with the `native-tls` feature
+    pub identity: Option<String>,
+    pub password: Option<String>,
+    // PEM certificates of the CAs that client certificates must be issued by. Clients without one are refused.
+    pub client_ca: Option<String>
+}
+
+// A request to one of the daemon's own endpoints, read in full
+pub struct Request {
+    pub method: Method,
+    // With the query string
+    pub path: String,
+    pub headers: HeaderMap,
+    pub remote_addr: SocketAddr,
+    pub body: Vec<u8>
+}
+
+impl Request {
+    // The first value of the header, when it is text
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>
+}
+
+impl Response {
+    pub fn new(status: StatusCode, body: &[u8]) -> Response {
+        Response {
+            status: status,
+            headers: HeaderMap::new(),
+            body: body.to_vec()
+        }
+    }
+
+    pub fn with_header(mut self, name: HeaderName, value: &'static str) -> Response {
+        self.headers.insert(name, HeaderValue::from_static(value));
+        self
+    }
+}
+
+// Answers requests to an endpoint. Handlers are called on threads of their own, so they may block.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: Request) -> Response;
+}
+
+impl<F> Handler for F where F: Fn(Request) -> Response + Send + Sync + 'static {
+    fn handle(&self, request: Request) -> Response {
+        self(request)
+    }
+}
+
+// An endpoint being served, until this is dropped
+pub struct Listening {
+    pub socket: SocketAddr,
+    _shutdown: oneshot::Sender<()>
+}
+
+enum Acceptor {
+    Plain,
+    #[cfg(feature = "rustls")]
+    Rustls(TlsAcceptor),
+    #[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+    Native(::tokio_native_tls::TlsAcceptor)
+}
+
+pub fn listen<H>(config: &ListenerConfig, handler: H) -> Result<Listening, ConfigError> where H: Handler {
+    let acceptor = match config.tls {
+        None => Acceptor::Plain,
+        Some(ref tls) => match acceptor(tls) {
+            Ok(acceptor) => acceptor,
+            Err(err) => return Err(err)
+        }
+    };
+    let listener = net::TcpListener::bind(config.address.as_str())
+        .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+        .and_then(|listener| listener.local_addr().map(|socket| (listener, socket)));
+    let (listener, socket) = match listener {
+        Ok(listener) => listener,
+        Err(err) => return Err(ConfigError::Invalid(format!("Unable to listen on {}: {}", config.address, err)))
+    };
+    let runtime = runtime::runtime();
+    let listener = {
+        let _entered = runtime.enter();
+        match TcpListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(err) => return Err(ConfigError::Invalid(format!("Unable to listen on {}: {}", config.address, err)))
+        }
+    };
+
+    let (shutdown, stopped) = oneshot::channel();
+    let handler = Arc::new(handler);
+    runtime.spawn(TcpListenerStream::new(listener).take_until(stopped).for_each(move |stream| {
+        if let Ok(stream) = stream {
+            accept(&acceptor, stream, handler.clone());
+        }
+        future::ready(())
+    }));
+    Ok(Listening {
+        socket: socket,
+        _shutdown: shutdown
+    })
+}
+
+// Connections that fail their handshake are dropped
+fn accept<H>(acceptor: &Acceptor, stream: TcpStream, handler: Arc<H>) where H: Handler {
+    let remote = match stream.peer_addr() {
+        Ok(remote) => remote,
+        Err(_) => return
+    };
+    match *acceptor {
+        Acceptor::Plain => {
+            task::spawn(serve(stream, remote, handler));
+        },
+        #[cfg(feature = "rustls")]
+        Acceptor::Rustls(ref acceptor) => {
+            task::spawn(acceptor.accept(stream).then(move |stream| match stream {
+                Ok(stream) => Either::Left(serve(stream, remote, handler)),
+                Err(_) => Either::Right(future::ready(()))
+            }));
+        },
+        // The handshake borrows the acceptor, so it is made on a thread that can wait for it
+        #[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+        Acceptor::Native(ref acceptor) => {
+            let acceptor = acceptor.clone();
+            task::spawn_blocking(move || {
+                let runtime = Handle::current();
+                if let Ok(stream) = runtime.block_on(acceptor.accept(stream)) {
+                    runtime.spawn(serve(stream, remote, handler));
+                }
+            });
+        }
+    }
+}
+
+fn serve<S, H>(stream: S, remote: SocketAddr, handler: Arc<H>) -> impl Future<Output = ()>
+        where S: AsyncRead + AsyncWrite + Unpin + Send + 'static, H: Handler {
+    let service = service_fn(move |request| respond(request, remote, handler.clone()));
+    http1::Builder::new().serve_connection(TokioIo::new(stream), service).map(|_| ())
+}
+
+fn respond<H>(request: ::hyper::Request<Incoming>, remote: SocketAddr, handler: Arc<H>)
+        -> impl Future<Output = Result<::hyper::Response<Full<Bytes>>, Infallible>> where H: Handler {
+    let (parts, body) = request.into_parts();
+    body.collect().then(move |body| match body {
+        Ok(body) => {
+            let request = Request {
+                method: parts.method,
+                path: parts.uri.path_and_query().map_or("/", |path| path.as_str()).to_owned(),
+                headers: parts.headers,
+                remote_addr: remote,
+                body: body.to_bytes().to_vec()
+            };
+            Either::Left(task::spawn_blocking(move || handler.handle(request)))
+        },
+        Err(_) => Either::Right(future::ok(Response::new(StatusCode::BAD_REQUEST, b"")))
+    }).map(|response| {
+        let response = response.unwrap_or_else(|_| Response::new(StatusCode::INTERNAL_SERVER_ERROR, b""));
+        let mut sent = ::hyper::Response::new(Full::new(Bytes::from(response.body)));
+        *sent.status_mut() = response.status;
+        *sent.headers_mut() = response.headers;
+        Ok(sent)
+    })
+}
+
+#[cfg(feature = "rustls")]
+fn acceptor(tls: &TlsConfig) -> Result<Acceptor, ConfigError> {
+    let (certificate, key) = match (&tls.certificate, &tls.key) {
+        (&Some(ref certificate), &Some(ref key)) => (certificate, key),
+        _ => return Err(ConfigError::Invalid("TLS listeners need a certificate and a key".to_owned()))
+    };
+    let certificates = match read_certificates(certificate) {
+        Ok(certificates) => certificates,
+        Err(err) => return Err(err)
+    };
+    let key = match read_key(key) {
+        Ok(key) => key,
+        Err(err) => return Err(err)
+    };
+
+    let verifier = match tls.client_ca {
+        Some(ref client_ca) => {
+            let mut roots = RootCertStore::empty();
+            match read_certificates(client_ca) {
+                Ok(certificates) => roots.add_parsable_certificates(certificates),
+                Err(err) => return Err(err)
+            };
+            match WebPkiClientVerifier::builder(Arc::new(roots)).build() {
+                Ok(verifier) => verifier,
+                Err(err) => return Err(ConfigError::Invalid(format!("Unable to use {}: {}", client_ca, err)))
+            }
+        },
+        None => WebPkiClientVerifier::no_client_auth()
+    };
+    match ServerConfig::builder().with_client_cert_verifier(verifier).with_single_cert(certificates, key) {
+        Ok(server_config) => Ok(Acceptor::Rustls(TlsAcceptor::from(Arc::new(server_config)))),
+        Err(err) => Err(ConfigError::Invalid(format!("Unable to use {}: {}", certificate, err)))
+    }
+}
+
+#[cfg(feature = "rustls")]
+fn read_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, ConfigError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => return Err(ConfigError::Read(err))
+    };
+    match CertificateDer::pem_reader_iter(BufReader::new(file)).collect::<Result<Vec<_>, _>>() {
+        Ok(ref certificates) if certificates.is_empty() => {
+            Err(ConfigError::Invalid(format!("No certificates found in {}", path)))
+        },
+        Ok(certificates) => Ok(certificates),
+        Err(_) => Err(ConfigError::Invalid(format!("Unable to parse certificates in {}", path)))
+    }
+}
+
+// PKCS #8, PKCS #1 and SEC1 keys are all accepted
+#[cfg(feature = "rustls")]
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>, ConfigError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => return Err(ConfigError::Read(err))
+    };
+    match PrivateKeyDer::from_pem_reader(BufReader::new(file)) {
+        Ok(key) => Ok(key),
+        Err(_) => Err(ConfigError::Invalid(format!("No private key found in {}", path)))
+    }
+}
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+fn acceptor(tls: &TlsConfig) -> Result<Acceptor, ConfigError> {
+    if tls.client_ca.is_some() {
+        return Err(ConfigError::Invalid("Client certificates need the rustls feature".to_owned()));
+    }
+    let identity = match tls.identity {
+        Some(ref identity) => identity,
+        None => return Err(ConfigError::Invalid("TLS listeners need a PKCS #12 identity".to_owned()))
+    };
+    let mut archive = vec![];
+    if let Err(err) = File::open(identity).and_then(|mut file| file.read_to_end(&mut archive)) {
+        return Err(ConfigError::Read(err));
+    }
+    let password = tls.password.as_ref().map_or("", String::as_str);
+    match Identity::from_pkcs12(&archive, password).and_then(native_tls::TlsAcceptor::new) {
+        Ok(acceptor) => Ok(Acceptor::Native(acceptor.into())),
+        Err(err) => Err(ConfigError::Invalid(format!("Unable to load {}: {}", identity, err)))
+    }
+}
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+fn acceptor(_: &TlsConfig) -> Result<Acceptor, ConfigError> {
+    Err(ConfigError::Invalid("TLS listeners need the native-tls or rustls feature".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{HeaderMap, StatusCode};
+    use errors::ConfigError;
+    use rest;
+    use super::{listen, ListenerConfig, Request, Response, TlsConfig};
+
+    #[test]
+    fn plain_listeners_serve_requests() {
+        let config = ListenerConfig { address: "127.0.0.1:0".to_owned(), tls: None };
+        let listening = listen(&config, |request: Request| {
+            Response::new(StatusCode::OK, format!("{} {}", request.method, request.path).as_bytes())
+        }).unwrap();
+
+        let response = rest::get_raw(&format!("http://{}/foo?bar=1", listening.socket), &HeaderMap::new());
+        assert_eq!(Some("GET /foo?bar=1".to_owned()), response.ok().map(|response| response.body));
+    }
+
+    #[test]
+    fn tls_listeners_without_a_certificate_are_refused() {
+        let config = ListenerConfig {
+            address: "127.0.0.1:0".to_owned(),
+            tls: Some(TlsConfig { certificate: None, key: None, identity: None, password: None, client_ca: None })
+        };
+        match listen(&config, |_: Request| Response::new(StatusCode::OK, b"")) {
+            Err(ConfigError::Invalid(_)) => {},
+            _ => panic!("Expected the listener to be refused")
+        }
+    }
+}
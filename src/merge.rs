@@ -0,0 +1,152 @@
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct MergeConfig {
+    // The first rule for the pull request's target branch applies
+    pub rules: Vec<MergeRule>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct MergeRule {
+    // Pull requests into branches starting with this. Any branch unless set.
+    pub target_branch: Option<String>,
+    // A Bitbucket merge strategy such as "squash", "no-ff" or "rebase-ff-only". The repository's default unless set.
+    pub strategy: Option<String>,
+    // The commit message, with `{title}`, `{id}`, `{author}`, `{branch}`, `{description}` and `{co_authors}` filled in.
+    // The repository's default message unless set.
+    pub message: Option<String>,
+    // The description is cut down to this many characters, 1000 unless set
+    pub max_description: Option<usize>
+}
+
+impl MergeConfig {
+    pub fn rule(&self, pr: &::PullRequest) -> Option<&MergeRule> {
+        let branch = pr.target_branch_name();
+        self.rules.iter()
+            .find(|rule| rule.target_branch.as_ref().map_or(true, |prefix| branch.starts_with(prefix.as_str())))
+    }
+}
+
+// `Co-authored-by` trailers for everyone other than the author who wrote one of the commits or was credited by one
+pub fn co_authors(pr: &::PullRequest, commits: &[::Commit]) -> Vec<String> {
+    let mut co_authors: Vec<String> = vec![];
+    let author = pr.author.email.to_lowercase();
+    let written = commits.iter().filter_map(|commit| commit.author.as_ref())
+        .filter(|user| !user.email.is_empty() && user.email.to_lowercase() != author)
+        .map(|user| format!("Co-authored-by: {} <{}>", user.name, user.email));
+    let credited = commits.iter().flat_map(|commit| commit.message.lines())
+        .filter(|line| line.to_lowercase().starts_with("co-authored-by:") && !line.to_lowercase().contains(&author))
+        .map(|line| format!("Co-authored-by: {}", line["co-authored-by:".len()..].trim()));
+    for trailer in written.chain(credited) {
+        if !co_authors.iter().any(|known| known.to_lowercase() == trailer.to_lowercase()) {
+            co_authors.push(trailer);
+        }
+    }
+    co_authors
+}
+
+// Cuts the text down to `max` characters, marking the cut
+pub fn truncate(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text.to_owned()
+    }
+}
+
+impl MergeRule {
+    // Blank lines left by empty parts of the template are dropped
+    pub fn message(&self, pr: &::PullRequest, commits: &[::Commit]) -> Option<String> {
+        let template = match self.message {
+            Some(ref template) => template,
+            None => return None
+        };
+        let description = truncate(pr.description.trim(), self.max_description.unwrap_or(1000));
+        let message = template.replace("{title}", &pr.title)
+            .replace("{id}", &pr.id.to_string())
+            .replace("{author}", &pr.author.name)
+            .replace("{branch}", &pr.branch_name())
+            .replace("{description}", &description)
+            .replace("{co_authors}", &co_authors(pr, commits).join("\n"));
+        let paragraphs = message.split("\n\n").map(str::trim).filter(|paragraph| !paragraph.is_empty());
+        Some(paragraphs.collect::<Vec<_>>().join("\n\n"))
+    }
+}
+
+// Merges the pull request as the rule for its target branch says, or with the repository's defaults
+pub fn merge(repository: &::Repository, pr: &::PullRequest, config: Option<&MergeConfig>)
+        -> Result<(), ::errors::RepositoryError> {
+    merge_as(repository, pr, config, None)
+}
+
+// Like `merge`, but with `strategy` in place of the rule's when set
+pub fn merge_as(repository: &::Repository, pr: &::PullRequest, config: Option<&MergeConfig>, strategy: Option<&str>)
+        -> Result<(), ::errors::RepositoryError> {
+    let rule = match config.and_then(|config| config.rule(pr)) {
+        Some(rule) => rule,
+        None => return repository.merge_pr(pr, strategy, None)
+    };
+    let message = match rule.message {
+        Some(_) => match repository.get_pr_commits(pr) {
+            Ok(commits) => rule.message(pr, &commits),
+            Err(err) => return Err(err)
+        },
+        None => None
+    };
+    let strategy = strategy.or_else(|| rule.strategy.as_ref().map(String::as_str));
+    repository.merge_pr(pr, strategy, message.as_ref().map(String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use stubs::pull_request;
+    use super::{co_authors, truncate, MergeConfig, MergeRule};
+
+    fn commit(message: &str, author: Option<(&str, &str)>) -> ::Commit {
+        ::Commit {
+            id: "abc".to_owned(),
+            message: message.to_owned(),
+            merge: false,
+            author: author.map(|(name, email)| ::User {
+                name: name.to_owned(),
+                email: email.to_owned(),
+                username: None
+            })
+        }
+    }
+
+    fn rule(target_branch: Option<&str>, strategy: &str) -> MergeRule {
+        MergeRule {
+            target_branch: target_branch.map(str::to_owned),
+            strategy: Some(strategy.to_owned()),
+            message: Some("{title} (#{id})\n\n{description}\n\n{co_authors}".to_owned()),
+            max_description: Some(12)
+        }
+    }
+
+    #[test]
+    fn the_first_rule_for_the_target_branch_applies() {
+        let config = MergeConfig { rules: vec![rule(Some("release/"), "no-ff"), rule(None, "squash")] };
+        let pr = ::PullRequest { to_ref: "refs/heads/release/1.2".to_owned(), ..pull_request() };
+        assert_eq!(Some("no-ff".to_owned()), config.rule(&pr).and_then(|rule| rule.strategy.clone()));
+        assert_eq!(Some("squash".to_owned()), config.rule(&pull_request()).and_then(|rule| rule.strategy.clone()));
+    }
+
+    #[test]
+    fn co_authors_are_credited_once() {
+        let commits = vec![
+            commit("Fix it", Some(("Aaron Xiao Ming", "aaron@xiao.ming"))),
+            commit("Test it", Some(("Bea", "bea@example.com"))),
+            commit("Pair on it\n\nCo-authored-by: Bea <BEA@example.com>\nCo-authored-by: Cy <cy@example.com>", None)
+        ];
+        assert_eq!(vec!["Co-authored-by: Bea <bea@example.com>".to_owned(),
+            "Co-authored-by: Cy <cy@example.com>".to_owned()], co_authors(&pull_request(), &commits));
+    }
+
+    #[test]
+    fn messages_are_built_from_the_template() {
+        let pr = ::PullRequest { description: "Handles the empty case.".to_owned(), ..pull_request() };
+        let commits = vec![commit("Test it", Some(("Bea", "bea@example.com")))];
+        let message = "A very important PR (#111)\n\nHandles the…\n\nCo-authored-by: Bea <bea@example.com>";
+        assert_eq!(Some(message.to_owned()), rule(None, "squash").message(&pr, &commits));
+        assert_eq!(Some("A very important PR (#111)".to_owned()), rule(None, "squash").message(&pull_request(), &[]));
+        assert_eq!("naïve", truncate("naïve", 5));
+    }
+}
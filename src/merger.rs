@@ -0,0 +1,110 @@
+use serde_json;
+
+use ::errors::{ConfigError, Error};
+use ::merge;
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct MergerConfig {
+    // Reviewers who need to have approved the pull request, 1 unless set
+    pub approvals: Option<usize>,
+    // A merge strategy of the repository, such as "no-ff" for a merge commit or "squash" on Bitbucket, in place of
+    // the `merge` section's
+    pub strategy: Option<String>,
+    // Only tells which pull requests would have been merged
+    pub dry_run: Option<bool>
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Readiness {
+    Ready,
+    // Why the pull request is not merged yet
+    Waiting(String)
+}
+
+impl MergerConfig {
+    // `approvals` are the usernames of the reviewers who approved the pull request
+    pub fn readiness(&self, approvals: &[String]) -> Readiness {
+        let needed = self.approvals.unwrap_or(1);
+        match approvals.len() < needed {
+            true => Readiness::Waiting(format!("{} of {} approvals", approvals.len(), needed)),
+            false => Readiness::Ready
+        }
+    }
+}
+
+// Whether the latest build of the pull request's commit has succeeded on every builder
+fn builds_passed(pr: &::PullRequest, matrix: &Vec<&::ContinuousIntegrator>) -> bool {
+    !matrix.is_empty() && matrix.iter().all(|ci| match ::get_latest_build(pr, *ci) {
+        Some(ref build) => build.state == ::BuildState::Finished && build.status == ::BuildStatus::Success,
+        None => false
+    })
+}
+
+impl Stage for MergerConfig {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let pr = context.pr;
+        // Approvals are only looked up once the builds have passed, as most repositories need a request for them
+        let readiness = match builds_passed(pr, context.matrix) {
+            true => match context.repository.get_pr_approvals(pr) {
+                Ok(approvals) => self.readiness(&approvals),
+                Err(err) => return Err(Error::from(err))
+            },
+            false => Readiness::Waiting("Not every build has passed".to_owned())
+        };
+        match readiness {
+            Readiness::Waiting(reason) => {
+                println!("Not merging Pull Request #{}: {}", pr.id, reason);
+                Ok(Outcome::Continue)
+            },
+            Readiness::Ready if self.dry_run == Some(true) => {
+                println!("Would merge Pull Request #{}, but this is a dry run", pr.id);
+                Ok(Outcome::Continue)
+            },
+            Readiness::Ready => {
+                let strategy = self.strategy.as_ref().map(String::as_str);
+                match merge::merge_as(context.repository, pr, context.config.merge.as_ref(), strategy) {
+                    Ok(_) => Ok(Outcome::Skip("Merged".to_owned())),
+                    Err(err) => Err(Error::from(err))
+                }
+            }
+        }
+    }
+}
+
+pub struct MergerFactory;
+
+impl registry::StageFactory for MergerFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        let config = match pipeline::parse_settings::<MergerConfig>(settings) {
+            Ok(config) => config,
+            Err(err) => return Err(err)
+        };
+        match config.approvals {
+            Some(0) => Err(ConfigError::Invalid("The merger needs at least one approval".to_owned())),
+            _ => Ok(Box::new(config))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MergerConfig, Readiness};
+
+    fn config(approvals: Option<usize>) -> MergerConfig {
+        MergerConfig {
+            approvals: approvals,
+            strategy: Some("squash".to_owned()),
+            dry_run: None
+        }
+    }
+
+    #[test]
+    fn approved_pull_requests_are_ready() {
+        let approvals = vec!["bea".to_owned()];
+        assert_eq!(Readiness::Ready, config(None).readiness(&approvals));
+        assert_eq!(Readiness::Waiting("1 of 2 approvals".to_owned()), config(Some(2)).readiness(&approvals));
+        assert_eq!(Readiness::Waiting("0 of 1 approvals".to_owned()), config(None).readiness(&[]));
+    }
+}
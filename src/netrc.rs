@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Entry {
+    // `None` for the `default` entry
+    pub machine: Option<String>,
+    pub login: Option<String>,
+    pub password: Option<String>
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Netrc {
+    entries: Vec<Entry>
+}
+
+impl Netrc {
+    pub fn read(path: &str) -> Option<Netrc> {
+        let mut contents = String::new();
+        match File::open(path).and_then(|mut file| file.read_to_string(&mut contents)) {
+            Ok(_) => Some(Netrc::parse(&contents)),
+            Err(_) => None
+        }
+    }
+
+    pub fn parse(contents: &str) -> Netrc {
+        let mut entries = vec![];
+        let mut lines = contents.lines();
+        let mut tokens = vec![];
+        while let Some(line) = lines.next() {
+            for word in line.split_whitespace() {
+                match word {
+                    // Macro definitions run until the next blank line and are of no interest here
+                    "macdef" => {
+                        for line in lines.by_ref() {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    },
+                    word if word.starts_with('#') => break,
+                    word => tokens.push(word.to_owned())
+                }
+            }
+        }
+
+        let mut tokens = tokens.into_iter();
+        let mut entry: Option<Entry> = None;
+        while let Some(token) = tokens.next() {
+            match token.as_str() {
+                "machine" | "default" => {
+                    if let Some(entry) = entry.take() {
+                        entries.push(entry);
+                    }
+                    entry = Some(Entry {
+                        machine: match token.as_str() {
+                            "machine" => tokens.next(),
+                            _ => None
+                        },
+                        login: None,
+                        password: None
+                    });
+                },
+                "login" => {
+                    let login = tokens.next();
+                    if let Some(ref mut entry) = entry {
+                        entry.login = login;
+                    }
+                },
+                "password" => {
+                    let password = tokens.next();
+                    if let Some(ref mut entry) = entry {
+                        entry.password = password;
+                    }
+                },
+                "account" => {
+                    tokens.next();
+                },
+                _ => {}
+            }
+        }
+        if let Some(entry) = entry {
+            entries.push(entry);
+        }
+
+        Netrc {
+            entries: entries
+        }
+    }
+
+    // The entry for `host`, or the `default` entry
+    pub fn find(&self, host: &str) -> Option<&Entry> {
+        self.entries.iter()
+            .find(|entry| entry.machine.as_ref().map_or(false, |machine| machine.eq_ignore_ascii_case(host)))
+            .or_else(|| self.entries.iter().find(|entry| entry.machine.is_none()))
+    }
+}
+
+// The host of a URL such as `https://user@host:8443/path`
+pub fn host(url: &str) -> Option<&str> {
+    let authority = match url.find("://") {
+        Some(start) => &url[start + 3..],
+        None => return None
+    };
+    let authority = &authority[..authority.find(|character| "/?#".contains(character)).unwrap_or(authority.len())];
+    let host = match authority.rfind('@') {
+        Some(at) => &authority[at + 1..],
+        None => authority
+    };
+    let host = match host.rfind(':') {
+        Some(colon) if !host.ends_with(']') => &host[..colon],
+        _ => host
+    };
+    match host.is_empty() {
+        true => None,
+        false => Some(host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{host, Netrc};
+
+    static NETRC: &'static str = "# Build servers
+machine bitbucket.example.com login pr_demon password hunter2
+machine teamcity.example.com
+    login ci
+    account ignored
+    password swordfish
+
+macdef init
+machine evil.example.com login mallory password oops
+
+default login anonymous password guest
+";
+
+    #[test]
+    fn entries_are_found_by_host() {
+        let netrc = Netrc::parse(NETRC);
+        let bitbucket = netrc.find("Bitbucket.example.com").unwrap();
+        assert_eq!((Some("pr_demon"), Some("hunter2")),
+            (bitbucket.login.as_ref().map(String::as_str), bitbucket.password.as_ref().map(String::as_str)));
+        let teamcity = netrc.find("teamcity.example.com").unwrap();
+        assert_eq!(Some("swordfish".to_owned()), teamcity.password);
+    }
+
+    #[test]
+    fn unknown_hosts_use_the_default_entry() {
+        let netrc = Netrc::parse(NETRC);
+        assert_eq!(Some("anonymous".to_owned()), netrc.find("evil.example.com").unwrap().login);
+        assert_eq!(None, Netrc::parse("machine a login b password c").find("d"));
+    }
+
+    #[test]
+    fn hosts_are_extracted_from_urls() {
+        assert_eq!(Some("bitbucket.example.com"), host("https://bitbucket.example.com/rest"));
+        assert_eq!(Some("teamcity.example.com"), host("https://ci@teamcity.example.com:8443/app/rest"));
+        assert_eq!(None, host("teamcity.example.com"));
+    }
+}
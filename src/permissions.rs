@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use ::i18n::Messages;
+
+// Each role may do everything the roles before it may
+#[derive(Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Retest,
+    Merge,
+    Admin
+}
+
+impl Role {
+    fn name(&self) -> &'static str {
+        match *self {
+            Role::Retest => "retest",
+            Role::Merge => "merge",
+            Role::Admin => "admin"
+        }
+    }
+}
+
+// Who may issue which comment commands
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Permissions {
+    // Roles of Bitbucket users, by username
+    pub users: Option<BTreeMap<String, Role>>,
+    // Roles of members of Bitbucket groups
+    pub groups: Option<BTreeMap<String, Role>>,
+    // The role of everyone else, `retest` unless configured. `null` denies them every command.
+    #[serde(default = "default_role")]
+    pub default_role: Option<Role>,
+    // The role each command needs, for commands other than `retest` and `merge` which need the role of the same name
+    pub commands: Option<BTreeMap<String, Role>>
+}
+
+fn default_role() -> Option<Role> {
+    Some(Role::Retest)
+}
+
+impl Default for Permissions {
+    fn default() -> Permissions {
+        Permissions {
+            users: None,
+            groups: None,
+            default_role: default_role(),
+            commands: None
+        }
+    }
+}
+
+impl Permissions {
+    // The highest role the user has, directly or through one of their groups
+    pub fn role(&self, user: &str, groups: &[String]) -> Option<Role> {
+        let direct = self.users.as_ref().and_then(|users| users.get(user)).cloned();
+        let inherited = self.groups.as_ref().and_then(|roles| {
+            groups.iter().filter_map(|group| roles.get(group)).max().cloned()
+        });
+        match (direct, inherited) {
+            (None, None) => self.default_role,
+            (direct, inherited) => direct.max(inherited)
+        }
+    }
+
+    pub fn required_role(&self, command: &str) -> Role {
+        let configured = self.commands.as_ref().and_then(|commands| commands.get(command)).cloned();
+        match (configured, command) {
+            (Some(role), _) => role,
+            (None, "retest") => Role::Retest,
+            (None, "merge") => Role::Merge,
+            (None, _) => Role::Admin
+        }
+    }
+
+    // On denial, returns the reply to post explaining why
+    pub fn authorize(&self, command: &str, user: &str, groups: &[String], messages: &Messages) -> Result<(), String> {
+        let required = self.required_role(command);
+        match self.role(user, groups) {
+            Some(role) if role >= required => Ok(()),
+            _ => Err(messages.format("command.denied", &[("user", user), ("command", command),
+                ("role", required.name())]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use i18n::Messages;
+    use super::{Permissions, Role};
+
+    fn permissions() -> Permissions {
+        let mut users = BTreeMap::new();
+        users.insert("alice".to_owned(), Role::Admin);
+        let mut groups = BTreeMap::new();
+        groups.insert("maintainers".to_owned(), Role::Merge);
+        Permissions {
+            users: Some(users),
+            groups: Some(groups),
+            ..Permissions::default()
+        }
+    }
+
+    #[test]
+    fn anyone_may_retest_but_only_maintainers_may_merge() {
+        let permissions = permissions();
+        let messages = Messages::new(&None, &None);
+        let maintainers = vec!["maintainers".to_owned()];
+
+        assert!(permissions.authorize("retest", "mallory", &[], &messages).is_ok());
+        assert!(permissions.authorize("merge", "bob", &maintainers, &messages).is_ok());
+        assert_eq!(Err("Sorry @mallory, `merge` needs the merge role, which you do not have.".to_owned()),
+            permissions.authorize("merge", "mallory", &[], &messages));
+    }
+
+    #[test]
+    fn unknown_commands_need_admin() {
+        let permissions = permissions();
+        let messages = Messages::new(&None, &None);
+        assert!(permissions.authorize("pause", "alice", &[], &messages).is_ok());
+        assert!(permissions.authorize("pause", "bob", &["maintainers".to_owned()], &messages).is_err());
+    }
+
+    #[test]
+    fn the_highest_role_wins() {
+        let permissions = permissions();
+        assert_eq!(Some(Role::Admin), permissions.role("alice", &["maintainers".to_owned()]));
+        assert_eq!(Some(Role::Merge), permissions.role("bob", &["maintainers".to_owned(), "others".to_owned()]));
+        assert_eq!(None, Permissions { default_role: None, ..permissions }.role("mallory", &[]));
+    }
+
+    #[test]
+    fn default_role_defaults_to_retest() {
+        let permissions = ::serde_json::from_str::<Permissions>("{}").unwrap();
+        assert_eq!(Some(Role::Retest), permissions.default_role);
+    }
+}
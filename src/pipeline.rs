@@ -0,0 +1,273 @@
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use ::errors::{ConfigError, Error};
+use ::fanout::{Fanout, Message};
+use ::i18n::Messages;
+use ::registry;
+
+// Everything a stage may need to handle one pull request
+pub struct Context<'a> {
+    pub pr: &'a ::PullRequest,
+    pub repository: &'a ::Repository,
+    pub matrix: &'a Vec<&'a ::ContinuousIntegrator>,
+    pub config: &'a ::Config,
+    pub messages: &'a Messages,
+    pub fanout: &'a Fanout<Message>
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Outcome {
+    Continue,
+    // Stops the pipeline for this pull request without reporting an error
+    Skip(String)
+}
+
+// Shared by the scheduler's workers handling pull requests at the same time
+pub trait Stage: Sync {
+    fn run(&self, context: &Context) -> Result<Outcome, Error>;
+}
+
+// Runs its stages in order for each pull request, usually filters first, then the build and anything reacting to it
+pub struct Pipeline {
+    stages: Vec<Box<Stage>>
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline {
+            stages: vec![]
+        }
+    }
+
+    pub fn push(&mut self, stage: Box<Stage>) -> &mut Pipeline {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        for stage in &self.stages {
+            match stage.run(context) {
+                Ok(Outcome::Continue) => {},
+                Ok(skipped) => return Ok(skipped),
+                Err(err) => return Err(err)
+            }
+        }
+        Ok(Outcome::Continue)
+    }
+}
+
+// Finds or schedules builds of the pull request and reports them to the repository
+pub struct BuildStage;
+
+impl Stage for BuildStage {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        if context.matrix.is_empty() {
+            return Ok(Outcome::Skip("No builder configured".to_owned()));
+        }
+        build_on(context, context.matrix)
+    }
+}
+
+// Builds the pull request on the one builder of `matrix`, personally for forks when its first builder supports that,
+// or on all of them together otherwise. `matrix` must not be empty.
+pub fn build_on(context: &Context, matrix: &Vec<&::ContinuousIntegrator>) -> Result<Outcome, Error> {
+    let personal = context.pr.from_fork && matrix[0].personal_builds();
+    let handled_pr = match (personal, matrix.len()) {
+        (true, _) | (false, 1) => {
+            ::handle_pull_request(context.pr, context.repository, matrix[0], personal, &context.config.queue_alert,
+                context.messages, context.fanout)
+        },
+        _ => {
+            ::handle_pull_request_matrix(context.pr, context.repository, matrix, &context.config.queue_alert,
+                context.messages, context.fanout)
+        }
+    };
+    handled_pr.map(|_| Outcome::Continue)
+}
+
+// Only lets through pull requests targeting a branch starting with one of `branches`
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct TargetBranchFilter {
+    pub branches: Vec<String>
+}
+
+impl Stage for TargetBranchFilter {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let target_branch = context.pr.target_branch_name();
+        match self.branches.iter().any(|branch| target_branch.starts_with(branch.as_str())) {
+            true => Ok(Outcome::Continue),
+            false => Ok(Outcome::Skip(format!("Target branch {} is not built", target_branch)))
+        }
+    }
+}
+
+// Skips pull requests whose title contains one of `skip`, ignoring case, such as "WIP"
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct TitleFilter {
+    pub skip: Vec<String>
+}
+
+impl Stage for TitleFilter {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let title = context.pr.title.to_lowercase();
+        match self.skip.iter().find(|skip| title.contains(&skip.to_lowercase())) {
+            Some(skip) => Ok(Outcome::Skip(format!("Title contains {}", skip))),
+            None => Ok(Outcome::Continue)
+        }
+    }
+}
+
+pub fn parse_settings<T>(settings: &serde_json::Value) -> Result<T, ConfigError> where T: DeserializeOwned {
+    match serde_json::from_value::<T>(settings.to_owned()) {
+        Ok(settings) => Ok(settings),
+        Err(err) => Err(ConfigError::Parse(err))
+    }
+}
+
+pub struct BuildStageFactory;
+
+impl registry::StageFactory for BuildStageFactory {
+    fn create(&self, _: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        Ok(Box::new(BuildStage))
+    }
+}
+
+pub struct TargetBranchFilterFactory;
+
+impl registry::StageFactory for TargetBranchFilterFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match parse_settings::<TargetBranchFilter>(settings) {
+            Ok(filter) => Ok(Box::new(filter)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+pub struct TitleFilterFactory;
+
+impl registry::StageFactory for TitleFilterFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match parse_settings::<TitleFilter>(settings) {
+            Ok(filter) => Ok(Box::new(filter)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use errors::{Error, RepositoryError};
+    use fanout::{Fanout, Message};
+    use i18n::Messages;
+    use stubs::{StubRepository, pull_request};
+    use super::{BuildStage, Context, Outcome, Pipeline, Stage, TargetBranchFilter, TitleFilter};
+
+    struct CountingStage {
+        runs: Arc<AtomicUsize>,
+        outcome: Result<Outcome, String>
+    }
+
+    impl Stage for CountingStage {
+        fn run(&self, _: &Context) -> Result<Outcome, Error> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            self.outcome.clone().map_err(|err| Error::from(RepositoryError::Template(err)))
+        }
+    }
+
+    fn counting(outcome: Result<Outcome, String>) -> (Box<Stage>, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let stage = CountingStage {
+            runs: runs.clone(),
+            outcome: outcome
+        };
+        (Box::new(stage), runs)
+    }
+
+    fn repository() -> StubRepository {
+        StubRepository {
+            pr_list: Ok(vec![]),
+            queued: Ok(()),
+            running: Ok(()),
+            success: Ok(()),
+            failure: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        }
+    }
+
+    fn run(pipeline: &Pipeline, pr: &::PullRequest) -> Result<Outcome, Error> {
+        let repository = repository();
+        let config = ::parse_config(r#"{ "run_interval": 0 }"#).unwrap();
+        let messages = Messages::new(&None, &None);
+        let fanout = Fanout::<Message>::new();
+        pipeline.run(&Context {
+            pr: pr,
+            repository: &repository,
+            matrix: &vec![],
+            config: &config,
+            messages: &messages,
+            fanout: &fanout
+        })
+    }
+
+    #[test]
+    fn stages_after_a_skip_are_not_run() {
+        let (skipping, skipping_runs) = counting(Ok(Outcome::Skip("not today".to_owned())));
+        let (after, after_runs) = counting(Ok(Outcome::Continue));
+        let mut pipeline = Pipeline::new();
+        pipeline.push(skipping).push(after);
+
+        assert_eq!(Some(Outcome::Skip("not today".to_owned())), run(&pipeline, &pull_request()).ok());
+        assert_eq!(1, skipping_runs.load(Ordering::SeqCst));
+        assert_eq!(0, after_runs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn stage_errors_stop_the_pipeline() {
+        let (failing, _) = counting(Err("foobar".to_owned()));
+        let (after, after_runs) = counting(Ok(Outcome::Continue));
+        let mut pipeline = Pipeline::new();
+        pipeline.push(failing).push(after);
+
+        match run(&pipeline, &pull_request()) {
+            Err(Error::Repository(RepositoryError::Template(ref message))) => assert_eq!("foobar", message),
+            _ => panic!("Expected the stage error")
+        }
+        assert_eq!(0, after_runs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn target_branch_filter_matches_prefixes() {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Box::new(TargetBranchFilter { branches: vec!["release/".to_owned()] }));
+
+        let release = ::PullRequest { to_ref: "refs/heads/release/1.0".to_owned(), ..pull_request() };
+        assert_eq!(Some(Outcome::Continue), run(&pipeline, &release).ok());
+        assert_eq!(Some(Outcome::Skip("Target branch master is not built".to_owned())),
+            run(&pipeline, &pull_request()).ok());
+    }
+
+    #[test]
+    fn title_filter_ignores_case() {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Box::new(TitleFilter { skip: vec!["WIP".to_owned()] }));
+
+        let wip = ::PullRequest { title: "wip: A very important PR".to_owned(), ..pull_request() };
+        assert_eq!(Some(Outcome::Skip("Title contains WIP".to_owned())), run(&pipeline, &wip).ok());
+        assert_eq!(Some(Outcome::Continue), run(&pipeline, &pull_request()).ok());
+    }
+
+    #[test]
+    fn build_stage_skips_without_builders() {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Box::new(BuildStage));
+
+        assert_eq!(Some(Outcome::Skip("No builder configured".to_owned())), run(&pipeline, &pull_request()).ok());
+    }
+}
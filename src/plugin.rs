@@ -0,0 +1,358 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use ::errors::{ApiError, BuilderError, ConfigError, RepositoryError};
+use ::fanout::{Fanout, Message};
+use ::i18n::Messages;
+use ::registry;
+
+pub static PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct PluginConfig {
+    pub command: String,
+    pub args: Option<Vec<String>>,
+    pub pin_merged: Option<bool>,
+    pub personal_builds: Option<bool>,
+    // How `components` routing refers to the builder
+    pub name: Option<String>,
+    // Whether the plugin answers `get_benchmarks`
+    pub benchmarks: Option<bool>
+}
+
+#[derive(Serialize)]
+struct Request<'a, P: 'a> {
+    version: u32,
+    id: u64,
+    method: &'a str,
+    params: &'a P
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: u64,
+    result: Option<serde_json::Value>,
+    error: Option<String>
+}
+
+#[derive(Deserialize)]
+struct Handshake {
+    version: u32
+}
+
+struct PluginProcess {
+    // Kept so that the plugin lives as long as the daemon holds on to it
+    #[allow(dead_code)]
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64
+}
+
+// A plugin executable speaking newline delimited JSON requests and responses over its stdin and stdout
+pub struct Plugin {
+    config: PluginConfig,
+    process: Mutex<PluginProcess>
+}
+
+impl Plugin {
+    pub fn spawn(config: &PluginConfig, role: &str) -> Result<Plugin, ConfigError> {
+        let mut child = match Command::new(&config.command)
+                .args(config.args.clone().unwrap_or(vec![]))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn() {
+            Ok(child) => child,
+            Err(err) => return Err(ConfigError::Invalid(format!("Unable to start plugin {}: {}", config.command, err)))
+        };
+        let stdin = child.stdin.take().expect("Plugin stdin should be piped");
+        let stdout = BufReader::new(child.stdout.take().expect("Plugin stdout should be piped"));
+
+        let plugin = Plugin {
+            config: config.to_owned(),
+            process: Mutex::new(PluginProcess {
+                child: child,
+                stdin: stdin,
+                stdout: stdout,
+                next_id: 0
+            })
+        };
+
+        let params = json!({ "version": PROTOCOL_VERSION, "role": role });
+        match plugin.call::<_, Handshake>("handshake", &params) {
+            Ok(ref handshake) if handshake.version == PROTOCOL_VERSION => Ok(plugin),
+            Ok(handshake) => Err(ConfigError::Invalid(format!("Plugin {} speaks protocol version {}, expected {}",
+                config.command, handshake.version, PROTOCOL_VERSION))),
+            Err(err) => Err(ConfigError::Invalid(format!("Plugin {} failed the handshake: {}", config.command, err)))
+        }
+    }
+
+    fn call<P, T>(&self, method: &str, params: &P) -> Result<T, ApiError> where P: Serialize, T: DeserializeOwned {
+        let mut process = self.process.lock().expect("Plugin process lock should not be poisoned");
+        let id = process.next_id;
+        process.next_id += 1;
+
+        let request = serde_json::to_string(&Request {
+            version: PROTOCOL_VERSION,
+            id: id,
+            method: method,
+            params: params
+        }).expect("Plugin request should be serializable");
+        if let Err(err) = writeln!(process.stdin, "{}", request).and_then(|_| process.stdin.flush()) {
+            return Err(ApiError::Io(err));
+        }
+
+        let mut line = String::new();
+        if let Err(err) = process.stdout.read_line(&mut line) {
+            return Err(ApiError::Io(err));
+        }
+        let response = match serde_json::from_str::<Response>(&line) {
+            Ok(response) => response,
+            Err(err) => return Err(ApiError::Parse { body: line, source: err })
+        };
+
+        match response {
+            Response { id: response_id, .. } if response_id != id => {
+                Err(ApiError::Remote(format!("Expected a response to request {}, got {}", id, response_id)))
+            },
+            Response { error: Some(error), .. } => Err(ApiError::Remote(error)),
+            Response { result, .. } => {
+                let result = result.unwrap_or(serde_json::Value::Null);
+                match serde_json::from_value(result.clone()) {
+                    Ok(decoded) => Ok(decoded),
+                    Err(err) => Err(ApiError::Parse { body: result.to_string(), source: err })
+                }
+            }
+        }
+    }
+
+    fn repository_call<P, T>(&self, method: &str, params: &P) -> Result<T, RepositoryError>
+            where P: Serialize, T: DeserializeOwned {
+        self.call(method, params).map_err(|err| RepositoryError::api(&format!("calling plugin {}", method), err))
+    }
+
+    fn builder_call<P, T>(&self, method: &str, params: &P) -> Result<T, BuilderError>
+            where P: Serialize, T: DeserializeOwned {
+        self.call(method, params).map_err(|err| BuilderError::api(&format!("calling plugin {}", method), err))
+    }
+}
+
+impl ::Repository for Plugin {
+    fn get_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.repository_call("get_pr_list", &json!({}))
+    }
+
+    fn get_merged_pr_list(&self) -> Result<Vec<::PullRequest>, RepositoryError> {
+        self.repository_call("get_merged_pr_list", &json!({}))
+    }
+
+    fn get_pr_diff(&self, pr: &::PullRequest) -> Result<String, RepositoryError> {
+        self.repository_call("get_pr_diff", &json!({ "pr": pr }))
+    }
+
+    fn get_pr_commits(&self, pr: &::PullRequest) -> Result<Vec<::Commit>, RepositoryError> {
+        self.repository_call("get_pr_commits", &json!({ "pr": pr }))
+    }
+
+    fn get_pr_comments(&self, pr: &::PullRequest) -> Result<Vec<::Comment>, RepositoryError> {
+        self.repository_call("get_pr_comments", &json!({ "pr": pr }))
+    }
+
+    // Plugins list approvals along with the pull requests
+    fn get_pr_approvals(&self, pr: &::PullRequest) -> Result<Vec<String>, RepositoryError> {
+        Ok(pr.approvals.to_owned())
+    }
+
+    fn build_queued(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.repository_call("build_queued", &json!({ "pr": pr, "build": build }))
+    }
+
+    fn build_running(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.repository_call("build_running", &json!({ "pr": pr, "build": build }))
+    }
+
+    fn build_success(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.repository_call("build_success", &json!({ "pr": pr, "build": build }))
+    }
+
+    fn build_failure(&self, pr: &::PullRequest, build: &::BuildDetails) -> Result<(), RepositoryError> {
+        self.repository_call("build_failure", &json!({ "pr": pr, "build": build }))
+    }
+
+    fn build_matrix(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>) -> Result<(), RepositoryError> {
+        self.repository_call("build_matrix", &json!({ "pr": pr, "builds": builds }))
+    }
+
+    fn upsert_comment(&self, pr: &::PullRequest, key: &::templates::CommentKey, text: &str)
+            -> Result<(), RepositoryError> {
+        self.repository_call("upsert_comment", &json!({ "pr": pr, "key": key, "text": text }))
+    }
+
+    fn report_check(&self, pr: &::PullRequest, check: &::CheckResult) -> Result<(), RepositoryError> {
+        self.repository_call("report_check", &json!({ "pr": pr, "check": check }))
+    }
+
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+            -> Result<::PullRequest, RepositoryError> {
+        self.repository_call("create_pull_request", &json!({
+            "from_ref": from_ref,
+            "to_ref": to_ref,
+            "title": title,
+            "description": description
+        }))
+    }
+
+    fn decline_pr(&self, pr: &::PullRequest) -> Result<(), RepositoryError> {
+        self.repository_call("decline_pr", &json!({ "pr": pr }))
+    }
+
+    fn add_reviewers(&self, pr: &::PullRequest, reviewers: &[String]) -> Result<(), RepositoryError> {
+        self.repository_call("add_reviewers", &json!({ "pr": pr, "reviewers": reviewers }))
+    }
+
+    fn create_branch(&self, name: &str, start_point: &str) -> Result<(), RepositoryError> {
+        self.repository_call("create_branch", &json!({ "name": name, "start_point": start_point }))
+    }
+
+    fn merge_pr(&self, pr: &::PullRequest, strategy: Option<&str>, message: Option<&str>)
+            -> Result<(), RepositoryError> {
+        self.repository_call("merge_pr", &json!({ "pr": pr, "strategy": strategy, "message": message }))
+    }
+}
+
+impl ::ContinuousIntegrator for Plugin {
+    fn get_build_list(&self, branch: &str) -> Result<Vec<::Build>, BuilderError> {
+        self.builder_call("get_build_list", &json!({ "branch": branch }))
+    }
+
+    fn get_build(&self, build_id: i32) -> Result<::BuildDetails, BuilderError> {
+        self.builder_call("get_build", &json!({ "build_id": build_id }))
+    }
+
+    fn queue_build(&self, branch: &str) -> Result<::BuildDetails, BuilderError> {
+        self.builder_call("queue_build", &json!({ "branch": branch }))
+    }
+
+    fn get_personal_build_list(&self, commit: &str) -> Result<Vec<::Build>, BuilderError> {
+        self.builder_call("get_personal_build_list", &json!({ "commit": commit }))
+    }
+
+    fn queue_personal_build(&self, diff: &str, description: &str) -> Result<::BuildDetails, BuilderError> {
+        self.builder_call("queue_personal_build", &json!({ "diff": diff, "description": description }))
+    }
+
+    fn tag_build(&self, build: &::BuildDetails, tags: &Vec<String>) -> Result<(), BuilderError> {
+        self.builder_call("tag_build", &json!({ "build": build, "tags": tags }))
+    }
+
+    fn pin_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        self.builder_call("pin_build", &json!({ "build": build, "comment": comment }))
+    }
+
+    fn comment_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        self.builder_call("comment_build", &json!({ "build": build, "comment": comment }))
+    }
+
+    fn cancel_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        self.builder_call("cancel_build", &json!({ "build": build, "comment": comment }))
+    }
+
+    fn get_coverage(&self, build: &::BuildDetails, target_branch: &str) -> Result<Option<::Coverage>, BuilderError> {
+        self.builder_call("get_coverage", &json!({ "build": build, "target_branch": target_branch }))
+    }
+
+    fn get_benchmarks(&self, build: &::BuildDetails) -> Result<Vec<::Benchmark>, BuilderError> {
+        match self.config.benchmarks {
+            Some(true) => self.builder_call("get_benchmarks", &json!({ "build": build })),
+            _ => Ok(vec![])
+        }
+    }
+
+    fn pin_merged(&self) -> bool {
+        self.config.pin_merged == Some(true)
+    }
+
+    fn personal_builds(&self) -> bool {
+        self.config.personal_builds == Some(true)
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.config.name.as_ref().map(String::as_str)
+    }
+}
+
+fn parse_config(settings: &serde_json::Value) -> Result<PluginConfig, ConfigError> {
+    match serde_json::from_value::<PluginConfig>(settings.to_owned()) {
+        Ok(config) => Ok(config),
+        Err(err) => Err(ConfigError::Parse(err))
+    }
+}
+
+pub struct PluginFactory;
+
+impl registry::RepositoryFactory for PluginFactory {
+    fn create(&self, settings: &serde_json::Value, _: &Messages, _: &Fanout<Message>)
+            -> Result<Box<::Repository>, ConfigError> {
+        match parse_config(settings).and_then(|config| Plugin::spawn(&config, "repository")) {
+            Ok(plugin) => Ok(Box::new(plugin)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+impl registry::BuilderFactory for PluginFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+        match parse_config(settings).and_then(|config| Plugin::spawn(&config, "builder")) {
+            Ok(plugin) => Ok(vec![Box::new(plugin)]),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{Plugin, PluginConfig};
+    use ::Repository;
+
+    fn plugin_config(script: &str) -> PluginConfig {
+        PluginConfig {
+            command: "sh".to_owned(),
+            args: Some(vec!["-c".to_owned(), script.to_owned()]),
+            pin_merged: None,
+            personal_builds: None,
+            name: None,
+            benchmarks: None
+        }
+    }
+
+    #[test]
+    fn plugin_calls_are_answered_over_stdio() {
+        let plugin = Plugin::spawn(&plugin_config(r#"
+            read request; echo '{"id":0,"result":{"version":1}}'
+            read request; echo '{"id":1,"result":[]}'
+        "#), "repository").ok().unwrap();
+        assert_eq!(Some(vec![]), plugin.get_pr_list().ok());
+    }
+
+    #[test]
+    fn plugin_errors_are_returned() {
+        let plugin = Plugin::spawn(&plugin_config(r#"
+            read request; echo '{"id":0,"result":{"version":1}}'
+            read request; echo '{"id":1,"error":"unauthorized"}'
+        "#), "repository").ok().unwrap();
+        assert_eq!(Some("Error calling plugin get_pr_list: unauthorized".to_owned()),
+            plugin.get_pr_list().err().map(|err| err.to_string()));
+    }
+
+    #[test]
+    fn plugins_speaking_another_version_are_rejected() {
+        let plugin = Plugin::spawn(&plugin_config(r#"
+            read request; echo '{"id":0,"result":{"version":2}}'
+        "#), "repository");
+        assert!(plugin.is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::Mutex;
+use serde_json;
+
+use ::errors::{ConfigError, Error};
+use ::pipeline::{Context, Outcome, Stage};
+use ::registry;
+use ::rest;
+use ::templates::CommentKey;
+
+// A command run with the pull request in `PR_DEMON_*` environment variables, or a URL posted the pull request as JSON
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Hook {
+    pub command: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub url: Option<String>,
+    pub token: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct PreviewConfig {
+    // Prints the preview's URL as its last line, or answers `{"url": ...}`. It runs again for every new commit.
+    pub deploy: Hook,
+    pub teardown: Option<Hook>
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Deployment {
+    pub pr: ::PullRequest,
+    pub url: String
+}
+
+// The previews deployed so far, by pull request
+#[derive(Default)]
+pub struct Deployments {
+    deployed: HashMap<i32, Deployment>
+}
+
+lazy_static! {
+    static ref DEPLOYMENTS: Mutex<Deployments> = Mutex::new(Deployments::default());
+}
+
+impl Deployments {
+    pub fn get(&self, pr: i32) -> Option<&Deployment> {
+        self.deployed.get(&pr)
+    }
+
+    pub fn insert(&mut self, deployment: Deployment) {
+        self.deployed.insert(deployment.pr.id, deployment);
+    }
+
+    // Forgets and returns the previews of pull requests that are no longer open
+    pub fn closed(&mut self, open: &[::PullRequest]) -> Vec<Deployment> {
+        let closed = self.deployed.keys().filter(|id| !open.iter().any(|pr| pr.id == **id)).cloned()
+            .collect::<Vec<_>>();
+        closed.iter().filter_map(|id| self.deployed.remove(id)).collect()
+    }
+}
+
+// The `url` of a JSON answer, or the last line of any other
+pub fn deployed_url(output: &str) -> Option<String> {
+    if let Ok(answer) = serde_json::from_str::<serde_json::Value>(output) {
+        if let Some(url) = answer.get("url").and_then(|url| url.as_str()) {
+            return Some(url.to_owned());
+        }
+    }
+    output.lines().map(str::trim).rfind(|line| !line.is_empty()).map(str::to_owned)
+}
+
+impl Hook {
+    pub fn run(&self, action: &str, pr: &::PullRequest, url: Option<&str>) -> Result<String, String> {
+        match (&self.command, &self.url) {
+            (&Some(ref command), _) => self.run_command(command, action, pr, url),
+            (&None, &Some(ref endpoint)) => self.post(endpoint, action, pr, url),
+            (&None, &None) => Err(format!("The {} hook needs a command or a url", action))
+        }
+    }
+
+    fn run_command(&self, command: &str, action: &str, pr: &::PullRequest, url: Option<&str>)
+            -> Result<String, String> {
+        let output = Command::new(command)
+            .args(self.args.clone().unwrap_or(vec![]))
+            .env("PR_DEMON_ACTION", action)
+            .env("PR_DEMON_PR_ID", pr.id.to_string())
+            .env("PR_DEMON_COMMIT", &pr.from_commit)
+            .env("PR_DEMON_FROM_REF", &pr.from_ref)
+            .env("PR_DEMON_TO_REF", &pr.to_ref)
+            .env("PR_DEMON_PREVIEW_URL", url.unwrap_or(""))
+            .output();
+        match output {
+            Ok(ref output) if output.status.success() => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Ok(output) => Err(format!("{} exited with {}: {}", command, output.status,
+                String::from_utf8_lossy(&output.stderr).trim())),
+            Err(err) => Err(format!("Unable to run {}: {}", command, err))
+        }
+    }
+
+    fn post(&self, endpoint: &str, action: &str, pr: &::PullRequest, url: Option<&str>) -> Result<String, String> {
+        let body = json!({ "action": action, "pr": pr, "url": url }).to_string();
+        let mut headers = rest::Headers::new();
+        headers.add_content_type_json_header();
+        if let Some(ref token) = self.token {
+            headers.add_bearer_authorization_header(token);
+        }
+        match rest::post_raw(endpoint, &body, &headers.headers) {
+            Ok(ref response) if response.status.is_success() => Ok(response.body.to_owned()),
+            Ok(response) => Err(format!("{} answered {}", endpoint, response.status)),
+            Err(err) => Err(format!("Unable to reach {}: {}", endpoint, err))
+        }
+    }
+}
+
+// Tears down the previews of pull requests that have been merged or closed since the last poll
+pub fn tear_down_closed(open: &[::PullRequest], config: &PreviewConfig) {
+    let closed = DEPLOYMENTS.lock().expect("Deployments should not be poisoned").closed(open);
+    for deployment in closed {
+        let teardown = match config.teardown {
+            Some(ref teardown) => teardown,
+            None => continue
+        };
+        match teardown.run("teardown", &deployment.pr, Some(&deployment.url)) {
+            Ok(_) => println!("Tore down the preview of Pull Request #{} at {}", deployment.pr.id, deployment.url),
+            Err(err) => println!("Unable to tear down the preview of Pull Request #{}: {}", deployment.pr.id, err)
+        }
+    }
+}
+
+// Deploys a preview of each commit whose builds have all succeeded and links it in a comment
+pub struct PreviewStage;
+
+impl Stage for PreviewStage {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let config = match context.config.preview {
+            Some(ref config) => config,
+            None => {
+                return Err(Error::from(ConfigError::Invalid("The preview stage needs a preview section".to_owned())))
+            }
+        };
+        let previous = match DEPLOYMENTS.lock().expect("Deployments should not be poisoned").get(context.pr.id) {
+            Some(deployment) if deployment.pr.from_commit == context.pr.from_commit => return Ok(Outcome::Continue),
+            Some(deployment) => Some(deployment.url.to_owned()),
+            None => None
+        };
+        let succeeded = context.matrix.iter().all(|ci| match ::get_latest_build(context.pr, *ci) {
+            Some(build) => build.state == ::BuildState::Finished && build.status == ::BuildStatus::Success,
+            None => false
+        });
+        if !succeeded {
+            return Ok(Outcome::Continue);
+        }
+
+        let url = match config.deploy.run("deploy", context.pr, previous.as_ref().map(String::as_str)) {
+            Ok(output) => match deployed_url(&output) {
+                Some(url) => url,
+                None => return Err(Error::from(ConfigError::Invalid("The deploy hook returned no URL".to_owned())))
+            },
+            Err(err) => {
+                let message = format!("Unable to deploy a preview of Pull Request #{}: {}", context.pr.id, err);
+                return Err(Error::from(ConfigError::Invalid(message)));
+            }
+        };
+        DEPLOYMENTS.lock().expect("Deployments should not be poisoned").insert(Deployment {
+            pr: context.pr.to_owned(),
+            url: url.to_owned()
+        });
+
+        let text = context.messages.format("preview.deployed", &[
+            ("marker", context.messages.labels().success()),
+            ("url", &url),
+            ("commit", &context.pr.from_commit)
+        ]);
+        match context.repository.upsert_comment(context.pr, &CommentKey::notice("preview", context.pr), &text) {
+            Ok(_) => Ok(Outcome::Continue),
+            Err(err) => Err(Error::from(err))
+        }
+    }
+}
+
+pub struct PreviewStageFactory;
+
+impl registry::StageFactory for PreviewStageFactory {
+    fn create(&self, _: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        Ok(Box::new(PreviewStage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stubs::pull_request;
+    use super::{deployed_url, Deployment, Deployments, Hook};
+
+    #[test]
+    fn urls_are_answered_as_json_or_printed_last() {
+        assert_eq!(Some("https://pr-1.example.com".to_owned()), deployed_url(r#"{"url": "https://pr-1.example.com"}"#));
+        assert_eq!(Some("https://pr-1.example.com".to_owned()),
+            deployed_url("Deploying...\nhttps://pr-1.example.com\n\n"));
+        assert_eq!(None, deployed_url(""));
+    }
+
+    #[test]
+    fn commands_see_the_pull_request() {
+        let hook = Hook {
+            command: Some("sh".to_owned()),
+            args: Some(vec!["-c".to_owned(), "echo \"https://pr-$PR_DEMON_PR_ID.example.com\"".to_owned()]),
+            url: None,
+            token: None
+        };
+        let output = hook.run("deploy", &pull_request(), None).unwrap();
+        assert_eq!(Some(format!("https://pr-{}.example.com", pull_request().id)), deployed_url(&output));
+    }
+
+    #[test]
+    fn previews_of_pull_requests_no_longer_open_are_closed() {
+        let mut deployments = Deployments::default();
+        let open = ::PullRequest { id: 2, ..pull_request() };
+        let closed = ::PullRequest { id: 3, ..pull_request() };
+        for pr in &[open.clone(), closed.clone()] {
+            deployments.insert(Deployment { pr: pr.clone(), url: format!("https://pr-{}.example.com", pr.id) });
+        }
+
+        assert_eq!(vec![Deployment { pr: closed, url: "https://pr-3.example.com".to_owned() }],
+            deployments.closed(&[open]));
+        assert!(deployments.get(2).is_some());
+        assert!(deployments.get(3).is_none());
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use serde_json;
+
+use ::errors::ConfigError;
+use ::fanout::{Fanout, Message};
+use ::i18n::Messages;
+use ::pipeline::{self, Pipeline, Stage};
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct BackendConfig {
+    #[serde(rename = "type", alias = "provider")]
+    pub kind: String,
+    #[serde(default)]
+    pub settings: serde_json::Value
+}
+
+pub trait RepositoryFactory {
+    fn create(&self, settings: &serde_json::Value, messages: &Messages, fanout: &Fanout<Message>)
+        -> Result<Box<::Repository>, ConfigError>;
+}
+
+// A single builder configuration may expand to several builders, one per entry of a build matrix
+pub trait BuilderFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError>;
+}
+
+pub trait NotifierFactory {
+    fn start(&self, settings: &serde_json::Value, subscriber: Receiver<Message>, messages: &Messages)
+        -> Result<(), ConfigError>;
+}
+
+pub trait StageFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError>;
+}
+
+pub struct Registry {
+    repositories: HashMap<String, Box<RepositoryFactory>>,
+    builders: HashMap<String, Box<BuilderFactory>>,
+    notifiers: HashMap<String, Box<NotifierFactory>>,
+    stages: HashMap<String, Box<StageFactory>>
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            repositories: HashMap::new(),
+            builders: HashMap::new(),
+            notifiers: HashMap::new(),
+            stages: HashMap::new()
+        }
+    }
+
+    pub fn with_defaults() -> Registry {
+        let mut registry = Registry::new();
+        registry.register_stage("build", Box::new(pipeline::BuildStageFactory))
+            .register_stage("commands", Box::new(::commands::CommandFactory))
+            .register_stage("target_branch", Box::new(pipeline::TargetBranchFilterFactory))
+            .register_stage("title", Box::new(pipeline::TitleFilterFactory))
+            .register_stage("branches", Box::new(::branches::BranchFiltersFactory))
+            .register_stage("commit_messages", Box::new(::checks::CommitMessageCheckFactory))
+            .register_stage("policy", Box::new(::checks::PullRequestPolicyFactory))
+            .register_stage("changelog", Box::new(::checks::ChangelogCheckFactory))
+            .register_stage("large_files", Box::new(::large_files::LargeFileCheckFactory))
+            .register_stage("secrets", Box::new(::secrets::SecretScanFactory))
+            .register_stage("dependencies", Box::new(::scan::ScannerFactory))
+            .register_stage("components", Box::new(::components::ComponentRoutingFactory))
+            .register_stage("routing", Box::new(::routing::MetadataRoutingFactory))
+            .register_stage("jira", Box::new(::jira::JiraStageFactory))
+            .register_stage("coverage", Box::new(::coverage::CoverageStageFactory))
+            .register_stage("benchmarks", Box::new(::benchmarks::BenchmarkStageFactory))
+            .register_stage("preview", Box::new(::preview::PreviewStageFactory))
+            .register_stage("stale", Box::new(::stale::StaleFactory))
+            .register_stage("reviewers", Box::new(::reviewers::ReviewerFactory))
+            .register_stage("release", Box::new(::release::ReleaseStageFactory))
+            .register_stage("merger", Box::new(::merger::MergerFactory))
+            .register_stage("welcome", Box::new(::welcome::WelcomeFactory));
+        #[cfg(feature = "rules")]
+        registry.register_stage("rules", Box::new(::rules::RulesFactory));
+        #[cfg(feature = "bitbucket")]
+        registry.register_repository("bitbucket", Box::new(::bitbucket::BitbucketFactory));
+        #[cfg(feature = "bitbucket_cloud")]
+        registry.register_repository("bitbucket_cloud", Box::new(::bitbucket_cloud::BitbucketCloudFactory));
+        #[cfg(feature = "github")]
+        registry.register_repository("github", Box::new(::github::GithubFactory));
+        #[cfg(feature = "gitea")]
+        registry.register_repository("gitea", Box::new(::gitea::GiteaFactory));
+        #[cfg(feature = "gitlab")]
+        registry.register_repository("gitlab", Box::new(::gitlab::GitlabFactory));
+        #[cfg(feature = "azure_devops")]
+        registry.register_repository("azure_devops", Box::new(::azure_devops::AzureDevopsFactory));
+        #[cfg(feature = "teamcity")]
+        registry.register_builder("teamcity", Box::new(::teamcity::TeamcityFactory));
+        #[cfg(feature = "jenkins")]
+        registry.register_builder("jenkins", Box::new(::jenkins::JenkinsFactory));
+        #[cfg(feature = "gitlab")]
+        registry.register_builder("gitlab", Box::new(::gitlab::GitlabCiFactory));
+        #[cfg(feature = "buildkite")]
+        registry.register_builder("buildkite", Box::new(::buildkite::BuildkiteFactory));
+        #[cfg(feature = "drone")]
+        registry.register_builder("drone", Box::new(::drone::DroneFactory));
+        #[cfg(feature = "exec")]
+        registry.register_builder("exec", Box::new(::exec::ExecFactory));
+        #[cfg(feature = "telegram")]
+        registry.register_notifier("telegram", Box::new(::telegram::TelegramFactory));
+        #[cfg(feature = "slack")]
+        registry.register_notifier("slack", Box::new(::slack::SlackFactory));
+        #[cfg(feature = "plugin")]
+        {
+            registry.register_repository("plugin", Box::new(::plugin::PluginFactory));
+            registry.register_builder("plugin", Box::new(::plugin::PluginFactory));
+        }
+        registry
+    }
+
+    pub fn register_repository(&mut self, kind: &str, factory: Box<RepositoryFactory>) -> &mut Registry {
+        self.repositories.insert(kind.to_owned(), factory);
+        self
+    }
+
+    pub fn register_builder(&mut self, kind: &str, factory: Box<BuilderFactory>) -> &mut Registry {
+        self.builders.insert(kind.to_owned(), factory);
+        self
+    }
+
+    pub fn register_notifier(&mut self, kind: &str, factory: Box<NotifierFactory>) -> &mut Registry {
+        self.notifiers.insert(kind.to_owned(), factory);
+        self
+    }
+
+    pub fn register_stage(&mut self, kind: &str, factory: Box<StageFactory>) -> &mut Registry {
+        self.stages.insert(kind.to_owned(), factory);
+        self
+    }
+
+    pub fn create_repository(&self, backend: &BackendConfig, messages: &Messages, fanout: &Fanout<Message>)
+            -> Result<Box<::Repository>, ConfigError> {
+        match self.repositories.get(&backend.kind) {
+            Some(factory) => factory.create(&backend.settings, messages, fanout),
+            None => Err(ConfigError::UnknownType(backend.kind.to_owned()))
+        }
+    }
+
+    pub fn create_builders(&self, backend: &BackendConfig) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+        match self.builders.get(&backend.kind) {
+            Some(factory) => factory.create(&backend.settings),
+            None => Err(ConfigError::UnknownType(backend.kind.to_owned()))
+        }
+    }
+
+    pub fn start_notifier(&self, backend: &BackendConfig, subscriber: Receiver<Message>, messages: &Messages)
+            -> Result<(), ConfigError> {
+        // Any notifier can have its events coalesced into a digest
+        let subscriber = match backend.settings.get("digest") {
+            Some(digest) => match serde_json::from_value::<::digest::DigestConfig>(digest.to_owned()) {
+                Ok(digest) => ::digest::coalesce(subscriber, &digest),
+                Err(err) => return Err(ConfigError::Parse(err))
+            },
+            None => subscriber
+        };
+        match self.notifiers.get(&backend.kind) {
+            Some(factory) => factory.start(&backend.settings, subscriber, messages),
+            None => Err(ConfigError::UnknownType(backend.kind.to_owned()))
+        }
+    }
+
+    pub fn create_stage(&self, stage: &BackendConfig) -> Result<Box<Stage>, ConfigError> {
+        match self.stages.get(&stage.kind) {
+            Some(factory) => factory.create(&stage.settings),
+            None => Err(ConfigError::UnknownType(stage.kind.to_owned()))
+        }
+    }
+
+    // Without configured stages, every pull request is built
+    pub fn create_pipeline(&self, stages: &Option<Vec<BackendConfig>>) -> Result<Pipeline, ConfigError> {
+        let mut pipeline = Pipeline::new();
+        let stages = match *stages {
+            Some(ref stages) => stages,
+            None => {
+                pipeline.push(Box::new(pipeline::BuildStage));
+                return Ok(pipeline);
+            }
+        };
+        for stage in stages {
+            match self.create_stage(stage) {
+                Ok(stage) => pipeline.push(stage),
+                Err(err) => return Err(err)
+            };
+        }
+        Ok(pipeline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use errors::ConfigError;
+    use super::{BackendConfig, BuilderFactory, Registry};
+
+    struct StubBuilderFactory;
+
+    impl BuilderFactory for StubBuilderFactory {
+        fn create(&self, _: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+            Ok(vec![])
+        }
+    }
+
+    fn backend(kind: &str) -> BackendConfig {
+        BackendConfig {
+            kind: kind.to_owned(),
+            settings: serde_json::Value::Null
+        }
+    }
+
+    #[test]
+    fn registered_factories_are_found_by_type() {
+        let mut registry = Registry::new();
+        registry.register_builder("stub", Box::new(StubBuilderFactory));
+        assert_eq!(0, registry.create_builders(&backend("stub")).ok().unwrap().len());
+    }
+
+    #[test]
+    fn unknown_types_are_rejected() {
+        let registry = Registry::with_defaults();
+        match registry.create_builders(&backend("bamboo")) {
+            Err(ConfigError::UnknownType(ref kind)) => assert_eq!("bamboo", kind),
+            _ => panic!("Expected an unknown type error")
+        }
+    }
+
+    #[test]
+    fn pipelines_are_created_from_stage_types() {
+        let registry = Registry::with_defaults();
+        let stages = vec![
+            BackendConfig {
+                kind: "title".to_owned(),
+                settings: serde_json::from_str(r#"{ "skip": ["WIP"] }"#).unwrap()
+            },
+            backend("build")
+        ];
+        assert!(registry.create_pipeline(&Some(stages)).is_ok());
+
+        match registry.create_pipeline(&Some(vec![backend("approval")])) {
+            Err(ConfigError::UnknownType(ref kind)) => assert_eq!("approval", kind),
+            _ => panic!("Expected an unknown type error")
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "teamcity")]
+    fn default_builders_are_created_from_settings() {
+        let registry = Registry::with_defaults();
+        let backend = BackendConfig {
+            kind: "teamcity".to_owned(),
+            settings: serde_json::from_str(r#"{
+                "token": "secret",
+                "base_url": "https://teamcity.example.com/app/rest",
+                "build_id": "Build_Linux",
+                "matrix": ["Build_Windows"]
+            }"#).unwrap()
+        };
+        assert_eq!(2, registry.create_builders(&backend).ok().unwrap().len());
+    }
+}
@@ -0,0 +1,242 @@
+use serde_json;
+
+use ::errors::{ConfigError, Error};
+use ::i18n::Messages;
+use ::pipeline::{Context, Outcome, Stage};
+use ::registry;
+use ::store::JsonStore;
+use ::templates::CommentKey;
+
+static USAGE: &'static str = "Usage: release cut <version> [<base branch>] | release stabilize <version> | \
+release open <version> | release list";
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct ReleaseConfig {
+    // The JSON file the release commands record release branches in, and the daemon reads them from
+    pub path: String,
+    // Release branches are named `<prefix><version>`, `release/` unless set
+    pub branch_prefix: Option<String>,
+    // The branch releases are cut from unless given on the command line, `master` unless set
+    pub base: Option<String>,
+    // Markers in the title or description, such as "#release-fix", that let a pull request into a stabilizing release
+    // branch
+    pub allow: Option<Vec<String>>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Release {
+    pub version: String,
+    pub branch: String,
+    pub base: String,
+    // The pull request merging the release branch back into its base
+    pub tracking: i32,
+    // Only allowed pull requests get into a stabilizing release branch
+    pub stabilizing: bool
+}
+
+// The release commands run in their own process, so the file is read again every time
+pub struct Releases {
+    releases: JsonStore<Vec<Release>>
+}
+
+impl Releases {
+    // A file that does not exist yet has no releases
+    pub fn load(path: &str) -> Result<Releases, ConfigError> {
+        JsonStore::load(&Some(path.to_owned())).map(|releases| Releases { releases: releases })
+    }
+
+    pub fn get(&self, branch: &str) -> Option<&Release> {
+        self.releases.iter().find(|release| release.branch == branch)
+    }
+
+    pub fn get_mut(&mut self, branch: &str) -> Option<&mut Release> {
+        self.releases.iter_mut().find(|release| release.branch == branch)
+    }
+
+    pub fn insert(&mut self, release: Release) {
+        self.releases.retain(|old| old.branch != release.branch);
+        self.releases.push(release);
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.releases.save()
+    }
+}
+
+impl ReleaseConfig {
+    pub fn branch_name(&self, version: &str) -> String {
+        format!("{}{}", self.branch_prefix.as_ref().map_or("release/", String::as_str), version)
+    }
+
+    pub fn is_allowed(&self, pr: &::PullRequest) -> bool {
+        let text = format!("{}\n{}", pr.title, pr.description).to_lowercase();
+        self.allow.as_ref().map_or(false, |allow| allow.iter().any(|marker| text.contains(&marker.to_lowercase())))
+    }
+}
+
+// Cuts `<prefix><version>` from the base branch and opens the pull request tracking it, which merges the release's
+// fixes back once it is done. The release branch starts out stabilizing.
+fn cut(repository: &::Repository, config: &ReleaseConfig, version: &str, base: Option<&String>, messages: &Messages)
+        -> Result<String, String> {
+    let mut releases = match Releases::load(&config.path) {
+        Ok(releases) => releases,
+        Err(err) => return Err(err.to_string())
+    };
+    let branch = config.branch_name(version);
+    if releases.get(&branch).is_some() {
+        return Err(format!("{} has already been cut", branch));
+    }
+    let base = base.or(config.base.as_ref()).map_or("master", String::as_str);
+    if let Err(err) = repository.create_branch(&branch, &format!("refs/heads/{}", base)) {
+        return Err(err.to_string());
+    }
+
+    let title = messages.format("release.title", &[("version", version)]);
+    let description = messages.format("release.tracking", &[("branch", &branch), ("base", base)]);
+    let tracking = repository.create_pull_request(&format!("refs/heads/{}", branch), &format!("refs/heads/{}", base),
+        &title, &description);
+    let tracking = match tracking {
+        Ok(tracking) => tracking,
+        Err(err) => return Err(format!("Cut {}, but unable to open its tracking pull request: {}", branch, err))
+    };
+    releases.insert(Release {
+        version: version.to_owned(),
+        branch: branch.to_owned(),
+        base: base.to_owned(),
+        tracking: tracking.id,
+        stabilizing: true
+    });
+    match releases.save() {
+        Ok(_) => Ok(format!("Cut {} from {}, tracked by #{} {}", branch, base, tracking.id, tracking.web_url)),
+        Err(err) => Err(err.to_string())
+    }
+}
+
+fn set_stabilizing(config: &ReleaseConfig, version: &str, stabilizing: bool) -> Result<String, String> {
+    let mut releases = match Releases::load(&config.path) {
+        Ok(releases) => releases,
+        Err(err) => return Err(err.to_string())
+    };
+    let branch = config.branch_name(version);
+    match releases.get_mut(&branch) {
+        Some(release) => release.stabilizing = stabilizing,
+        None => return Err(format!("{} has not been cut", branch))
+    }
+    match (releases.save(), stabilizing) {
+        (Ok(_), true) => Ok(format!("{} is stabilizing", branch)),
+        (Ok(_), false) => Ok(format!("{} is open", branch)),
+        (Err(err), _) => Err(err.to_string())
+    }
+}
+
+fn list(config: &ReleaseConfig) -> Result<String, String> {
+    let releases = match Releases::load(&config.path) {
+        Ok(releases) => releases,
+        Err(err) => return Err(err.to_string())
+    };
+    Ok(releases.releases.iter().map(|release| {
+        let state = if release.stabilizing { "stabilizing" } else { "open" };
+        format!("{} from {}, {}, tracked by #{}", release.branch, release.base, state, release.tracking)
+    }).collect::<Vec<_>>().join("\n"))
+}
+
+// Runs `release cut|stabilize|open|list` and returns what to print
+pub fn command(repository: &::Repository, config: &ReleaseConfig, args: &[String], messages: &Messages)
+        -> Result<String, String> {
+    match (args.first().map(String::as_str), args.get(1)) {
+        (Some("cut"), Some(version)) if args.len() <= 3 => cut(repository, config, version, args.get(2), messages),
+        (Some("stabilize"), Some(version)) if args.len() == 2 => set_stabilizing(config, version, true),
+        (Some("open"), Some(version)) if args.len() == 2 => set_stabilizing(config, version, false),
+        (Some("list"), None) => list(config),
+        _ => Err(USAGE.to_owned())
+    }
+}
+
+// Keeps pull requests out of stabilizing release branches unless they are marked as allowed
+pub struct ReleaseStage;
+
+impl Stage for ReleaseStage {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let config = match context.config.release {
+            Some(ref config) => config,
+            None => {
+                return Err(Error::from(ConfigError::Invalid("The release stage needs a release section".to_owned())))
+            }
+        };
+        let releases = match Releases::load(&config.path) {
+            Ok(releases) => releases,
+            Err(err) => return Err(Error::from(err))
+        };
+        let branch = match releases.get(&context.pr.target_branch_name()) {
+            Some(release) if release.stabilizing && !config.is_allowed(context.pr) => release.branch.to_owned(),
+            _ => return Ok(Outcome::Continue)
+        };
+
+        let mut text = context.messages.format("release.stabilizing", &[
+            ("marker", context.messages.labels().warning()),
+            ("branch", &branch)
+        ]);
+        if let Some(marker) = config.allow.as_ref().and_then(|allow| allow.first()) {
+            let allow = context.messages.format("release.allow", &[("allow", &format!("`{}`", marker))]);
+            text = format!("{} {}", text, allow);
+        }
+        match context.repository.upsert_comment(context.pr, &CommentKey::notice("release", context.pr), &text) {
+            Ok(_) => Ok(Outcome::Skip(format!("{} is stabilizing", branch))),
+            Err(err) => Err(Error::from(err))
+        }
+    }
+}
+
+pub struct ReleaseStageFactory;
+
+impl registry::StageFactory for ReleaseStageFactory {
+    fn create(&self, _: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        Ok(Box::new(ReleaseStage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use stubs::pull_request;
+    use super::{Release, ReleaseConfig, Releases};
+
+    fn config(path: &str) -> ReleaseConfig {
+        ReleaseConfig {
+            path: path.to_owned(),
+            branch_prefix: None,
+            base: None,
+            allow: Some(vec!["#release-fix".to_owned()])
+        }
+    }
+
+    #[test]
+    fn only_allowed_pull_requests_get_into_stabilizing_branches() {
+        let config = config("releases.json");
+        assert_eq!("release/1.2", config.branch_name("1.2"));
+        assert!(!config.is_allowed(&pull_request()));
+        assert!(config.is_allowed(&::PullRequest { title: "Fix the crash #Release-Fix".to_owned(), ..pull_request() }));
+    }
+
+    #[test]
+    fn releases_are_kept_in_the_file() {
+        let path = env::temp_dir().join("pr_demon_releases_test.json");
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let release = Release {
+            version: "1.2".to_owned(),
+            branch: "release/1.2".to_owned(),
+            base: "master".to_owned(),
+            tracking: 7,
+            stabilizing: true
+        };
+
+        let mut releases = Releases::load(path).unwrap();
+        assert!(releases.get("release/1.2").is_none());
+        releases.insert(release.clone());
+        releases.save().unwrap();
+        assert_eq!(Some(&release), Releases::load(path).unwrap().get("release/1.2"));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,119 +1,528 @@
-use std::io::Read;
-use rustc_serialize::{json, Decodable};
-use hyper;
-use hyper::client::Client;
-use hyper::header::{Authorization, Basic, Accept, qitem, ContentType};
-use hyper::mime::{Mime, TopLevel, SubLevel, Attr, Value};
+use std::io;
+use std::sync::Mutex;
+use base64;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{HeaderMap, Method, Request, StatusCode};
+use hyper::header::{self, HeaderName, HeaderValue};
+#[cfg(feature = "rustls")]
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+use hyper_tls::HttpsConnector;
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+use hyper_tls::native_tls::TlsConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use serde::de::DeserializeOwned;
+use serde_json;
+use ::cassette;
+use ::chaos::{self, Fault};
+use ::credentials::Credential;
+use ::errors::ApiError;
+use ::runtime;
+
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+type Connector = HttpsConnector<HttpConnector>;
+// Without a TLS backend only plain HTTP URLs can be used
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+type Connector = HttpConnector;
+
+lazy_static! {
+    // Shared by every request, so that connections are kept alive between them
+    static ref CLIENT: Mutex<Option<Client<Connector, Full<Bytes>>>> = Mutex::new(None);
+}
+
+// A response that has been read in full, so that it can be recorded and replayed
+pub struct Response {
+    pub status: StatusCode,
+    pub body: String,
+    pub headers: HeaderMap
+}
+
+// The response headers that paged APIs point at their next page with
+static PAGING_HEADERS: [&'static str; 2] = ["Link", "X-Next-Page"];
+
+// A page of a Bitbucket Server style paged API, read with `get_paged`
+#[cfg(any(feature = "bitbucket", test))]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub values: Vec<T>,
+    pub is_last_page: bool,
+    pub next_page_start: Option<i32>
+}
 
 pub struct Headers {
-    pub headers: hyper::header::Headers
+    pub headers: HeaderMap
 }
 
 impl Headers {
+    // Every request names the daemon, as GitHub refuses those without a user agent
     pub fn new() -> Headers {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, HeaderValue::from_static("pr_demon"));
         Headers {
-            headers: hyper::header::Headers::new()
+            headers: headers
         }
     }
 
-    pub fn add_authorization_header(&mut self, credentials: &::UsernameAndPassword)
-            -> &mut Headers {
-        self.headers.set(
-           Authorization(
-               Basic {
-                   username: credentials.username().clone(),
-                   password: Some(credentials.password().clone())
-               }
-           )
-        );
+    // Values that cannot be sent in a header, such as those with line breaks, are left out
+    pub fn set(&mut self, name: &str, value: &str) -> &mut Headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            self.headers.insert(name, value);
+        }
         self
     }
 
+    pub fn add_credential_header(&mut self, credential: &Credential) -> &mut Headers {
+        match *credential {
+            Credential::Anonymous => self,
+            Credential::Basic { ref username, ref password } => self.add_basic_authorization_header(username, password),
+            Credential::Bearer(ref token) => self.add_bearer_authorization_header(token),
+            Credential::Negotiate(ref token) => self.set("Authorization", &format!("Negotiate {}", token))
+        }
+    }
+
+    pub fn add_basic_authorization_header(&mut self, username: &str, password: &str)
+            -> &mut Headers {
+        let encoded = base64::encode(format!("{}:{}", username, password).as_bytes());
+        self.set("Authorization", &format!("Basic {}", encoded))
+    }
+
+    pub fn add_bearer_authorization_header(&mut self, token: &str) -> &mut Headers {
+        self.set("Authorization", &format!("Bearer {}", token))
+    }
+
     pub fn add_accept_json_header(&mut self) -> &mut Headers {
-        self.headers.set(
-            Accept(vec![
-                qitem(Mime(TopLevel::Application, SubLevel::Json,
-                           vec![(Attr::Charset, Value::Utf8)])),
-            ])
-        );
-        self
+        self.set("Accept", "application/json; charset=utf-8")
     }
 
     pub fn add_content_type_json_header(&mut self) -> &mut Headers {
-        self.headers.set(
-            ContentType(Mime(TopLevel::Application, SubLevel::Json,
-                             vec![(Attr::Charset, Value::Utf8)]))
-        );
-        self
+        self.set("Content-Type", "application/json; charset=utf-8")
+    }
+
+    pub fn add_content_type_text_header(&mut self) -> &mut Headers {
+        self.set("Content-Type", "text/plain; charset=utf-8")
+    }
+
+    pub fn add_content_type_form_header(&mut self) -> &mut Headers {
+        self.set("Content-Type", "application/x-www-form-urlencoded")
     }
 
     pub fn add_content_type_xml_header(&mut self) -> &mut Headers {
-        self.headers.set(
-            ContentType(Mime(TopLevel::Application, SubLevel::Xml,
-                             vec![(Attr::Charset, Value::Utf8)]))
-        );
-        self
+        self.set("Content-Type", "application/xml; charset=utf-8")
+    }
+}
+
+pub fn get<T>(url: &str, headers: &HeaderMap) -> Result<T, ApiError>
+    where T: DeserializeOwned {
+    request(url, Method::GET, &None, headers, &StatusCode::OK)
+}
+
+pub fn get_raw(url: &str, headers: &HeaderMap)
+        -> Result<Response, ApiError> {
+    request_raw(url, Method::GET, &None, headers)
+}
+
+#[cfg(any(feature = "azure_devops", feature = "bitbucket", feature = "bitbucket_cloud", feature = "buildkite",
+    feature = "drone", feature = "gitea", feature = "github", feature = "gitlab", feature = "teamcity"))]
+pub fn post<T>(url: &str, body: &str, headers: &HeaderMap, status_code: &StatusCode)
+         -> Result<T, ApiError> where T: DeserializeOwned {
+    request(url, Method::POST, &Some(body.to_owned()), headers, status_code)
+}
+
+pub fn post_raw(url: &str, body: &str, headers: &HeaderMap)
+        -> Result<Response, ApiError> {
+    request_raw(url, Method::POST, &Some(body.to_owned()), headers)
+}
+
+#[cfg(any(feature = "azure_devops", feature = "bitbucket", feature = "bitbucket_cloud", feature = "github",
+    feature = "gitlab"))]
+pub fn put<T>(url: &str, body: &str, headers: &HeaderMap, status_code: &StatusCode)
+         -> Result<T, ApiError> where T: DeserializeOwned {
+    request(url, Method::PUT, &Some(body.to_owned()), headers, status_code)
+}
+
+#[cfg(any(feature = "buildkite", feature = "teamcity"))]
+pub fn put_raw(url: &str, body: &str, headers: &HeaderMap)
+        -> Result<Response, ApiError> {
+    request_raw(url, Method::PUT, &Some(body.to_owned()), headers)
+}
+
+#[cfg(any(feature = "azure_devops", feature = "bitbucket", feature = "bitbucket_cloud", feature = "drone",
+    feature = "gitea", feature = "github", feature = "gitlab"))]
+pub fn delete_raw(url: &str, headers: &HeaderMap)
+        -> Result<Response, ApiError> {
+    request_raw(url, Method::DELETE, &None, headers)
+}
+
+#[cfg(any(feature = "azure_devops", feature = "gitea", feature = "github"))]
+pub fn patch<T>(url: &str, body: &str, headers: &HeaderMap, status_code: &StatusCode)
+         -> Result<T, ApiError> where T: DeserializeOwned {
+    request(url, Method::PATCH, &Some(body.to_owned()), headers, status_code)
+}
+
+// Collects the values of every page of `url`, which may already have a query string, by following `nextPageStart`
+// until `isLastPage`. Each page is fetched with `fetch`, so that every request can refresh its credentials.
+#[cfg(any(feature = "bitbucket", test))]
+pub fn get_paged<T, F>(url: &str, fetch: F) -> Result<Vec<T>, ApiError> where F: Fn(&str) -> Result<Page<T>, ApiError> {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let mut values = vec![];
+    let mut start = 0;
+    loop {
+        let page = match fetch(&format!("{}{}start={}", url, separator, start)) {
+            Ok(page) => page,
+            Err(err) => return Err(err)
+        };
+        values.extend(page.values);
+        match page.next_page_start {
+            // Servers that do not move forward would otherwise be asked for the same page forever
+            Some(next) if !page.is_last_page && next > start => start = next,
+            _ => return Ok(values)
+        }
+    }
+}
+
+// Collects the values of every page of `url`, each a JSON array, following the URL that `next` finds in the response
+// to the page before. Each page is fetched with `fetch`, so that every request can refresh its credentials.
+#[cfg(any(feature = "gitea", feature = "github", feature = "gitlab", test))]
+pub fn get_all<T, F, N>(url: &str, fetch: F, next: N) -> Result<Vec<T>, ApiError>
+        where T: DeserializeOwned, F: Fn(&str) -> Result<Response, ApiError>, N: Fn(&str, &Response) -> Option<String> {
+    get_all_while(url, fetch, next, |_| true)
+}
+
+// Like `get_all`, stopping after the first page that `more` finds no further pages are needed after, such as when
+// the values are sorted and the page ends with one too old to be of interest
+#[cfg(any(feature = "gitea", feature = "github", feature = "gitlab", test))]
+pub fn get_all_while<T, F, N, M>(url: &str, fetch: F, next: N, more: M) -> Result<Vec<T>, ApiError>
+        where T: DeserializeOwned, F: Fn(&str) -> Result<Response, ApiError>, N: Fn(&str, &Response) -> Option<String>,
+        M: Fn(&[T]) -> bool {
+    let mut values = vec![];
+    let mut url = url.to_owned();
+    loop {
+        let response = match fetch(&url) {
+            Ok(response) => response,
+            Err(err) => return Err(err)
+        };
+        let next_url = next(&url, &response);
+        let page = match decode::<Vec<T>>(response, &StatusCode::OK) {
+            Ok(page) => page,
+            Err(err) => return Err(err)
+        };
+        let wanted = more(&page);
+        values.extend(page);
+        match next_url {
+            // Servers that do not move forward would otherwise be asked for the same page forever
+            Some(next_url) if wanted && next_url != url => url = next_url,
+            _ => return Ok(values)
+        }
+    }
+}
+
+// The `rel="next"` URL of the `Link` header, with which GitHub and Gitea page
+#[cfg(any(feature = "gitea", feature = "github", test))]
+pub fn next_link(_: &str, response: &Response) -> Option<String> {
+    let links = match header(response, "Link") {
+        Some(links) => links,
+        None => return None
+    };
+    let next = links.split(',').find(|link| link.split(';').skip(1).any(|param| param.trim() == "rel=\"next\""));
+    next.and_then(|link| {
+        let link = link.trim();
+        match (link.find('<'), link.find('>')) {
+            (Some(start), Some(end)) if start < end => Some(link[start + 1..end].to_owned()),
+            _ => None
+        }
+    })
+}
+
+// `url` asking for the page numbered in the `X-Next-Page` header, with which GitLab pages. The header is empty on the
+// last page.
+#[cfg(any(feature = "gitlab", test))]
+pub fn next_page(url: &str, response: &Response) -> Option<String> {
+    let page = match header(response, "X-Next-Page") {
+        Some(ref page) if !page.trim().is_empty() => page.trim().to_owned(),
+        _ => return None
+    };
+    let (path, query) = match url.find('?') {
+        Some(index) => (&url[..index], &url[index + 1..]),
+        None => (url, "")
+    };
+    let mut params = query.split('&').filter(|param| !param.is_empty() && !param.starts_with("page="))
+        .collect::<Vec<_>>();
+    let page_param = format!("page={}", page);
+    params.push(&page_param);
+    Some(format!("{}?{}", path, params.join("&")))
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    let values = response.headers.get_all(name).iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
     }
 }
 
-pub fn get<T>(url: &str, headers: &hyper::header::Headers) -> Result<T, String>
-    where T: Decodable {
-    request(url, hyper::method::Method::Get, &None, headers, &hyper::status::StatusCode::Ok)
+// Bypasses any cassette, for requests whose bodies and responses are themselves credentials
+pub fn post_unrecorded<T>(url: &str, body: &str, headers: &HeaderMap,
+                          status_code: &StatusCode) -> Result<T, ApiError> where T: DeserializeOwned {
+    let fault = chaos::fault();
+    let sent = match inject(fault) {
+        Some(injected) => injected,
+        None => send(url, Method::POST, &Some(body.to_owned()), headers)
+            .map(|response| garble(response, fault))
+    };
+    match sent {
+        Ok(response) => decode(response, status_code),
+        Err(err) => Err(err)
+    }
 }
 
-pub fn post<T>(url: &str, body: &str, headers: &hyper::header::Headers, status_code: &hyper::status::StatusCode)
-         -> Result<T, String> where T: Decodable {
-    request(url, hyper::method::Method::Post, &Some(body.to_owned()), headers, status_code)
+fn client() -> Result<Client<Connector, Full<Bytes>>, ApiError> {
+    let mut client = CLIENT.lock().expect("HTTP client should not be poisoned");
+    if let Some(ref client) = *client {
+        return Ok(client.clone());
+    }
+    let connector = match connector() {
+        Ok(connector) => connector,
+        Err(err) => return Err(err)
+    };
+    let built = Client::builder(TokioExecutor::new()).build(connector);
+    *client = Some(built.clone());
+    Ok(built)
 }
 
-pub fn post_raw(url: &str, body: &str, headers: &hyper::header::Headers)
-        -> Result<hyper::client::response::Response, hyper::Error> {
-    request_raw(url, hyper::method::Method::Post, &Some(body.to_owned()), headers)
+// Verifies servers against the Mozilla root certificates bundled with webpki-roots
+#[cfg(feature = "rustls")]
+fn connector() -> Result<Connector, ApiError> {
+    Ok(HttpsConnectorBuilder::new().with_webpki_roots().https_or_http().enable_http1().build())
 }
 
-pub fn put<T>(url: &str, body: &str, headers: &hyper::header::Headers, status_code: &hyper::status::StatusCode)
-         -> Result<T, String> where T: Decodable {
-    request(url, hyper::method::Method::Put, &Some(body.to_owned()), headers, status_code)
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+fn connector() -> Result<Connector, ApiError> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    match TlsConnector::new() {
+        Ok(tls) => Ok(HttpsConnector::from((http, tls.into()))),
+        Err(err) => Err(ApiError::Http(Box::new(err)))
+    }
+}
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+fn connector() -> Result<Connector, ApiError> {
+    Ok(HttpConnector::new())
 }
 
 fn request_raw(url: &str,
-               method: hyper::method::Method,
+               method: Method,
                body: &Option<String>,
-               headers: &hyper::header::Headers) -> Result<hyper::client::response::Response, hyper::Error> {
-    let client = Client::new();
-    let client = client.request(method, url);
-    let client = match *body {
-        Some(ref body_content) => client.body(body_content),
-        None => client
+               headers: &HeaderMap) -> Result<Response, ApiError> {
+    let method_name = method.to_string();
+    if let Some(replayed) = cassette::replay(&method_name, url, body) {
+        return replayed.and_then(|interaction| {
+            let status = match StatusCode::from_u16(interaction.status) {
+                Ok(status) => status,
+                Err(err) => return Err(ApiError::Http(Box::new(err)))
+            };
+            let mut headers = Headers { headers: HeaderMap::new() };
+            for (name, value) in interaction.headers {
+                headers.set(&name, &value);
+            }
+            authorized(Response {
+                status: status,
+                body: interaction.response,
+                headers: headers.headers
+            })
+        });
+    }
+
+    let fault = chaos::fault();
+    if let Some(injected) = inject(fault) {
+        return injected;
+    }
+    let response = match send(url, method, body, headers) {
+        Ok(response) => response,
+        Err(err) => return Err(err)
+    };
+    let recorded = cassette::record(cassette::Interaction {
+        method: method_name,
+        url: url.to_owned(),
+        body: body.to_owned(),
+        status: response.status.as_u16(),
+        response: response.body.to_owned(),
+        headers: PAGING_HEADERS.iter()
+            .filter_map(|&name| header(&response, name).map(|value| (name.to_owned(), value)))
+            .collect()
+    });
+    recorded.and_then(|_| authorized(garble(response, fault)))
+}
+
+// Chaos mode fails some requests without sending them...
+fn inject(fault: Option<Fault>) -> Option<Result<Response, ApiError>> {
+    match fault {
+        Some(Fault::Timeout) => Some(Err(ApiError::Io(io::Error::new(io::ErrorKind::TimedOut, "Injected timeout")))),
+        Some(Fault::ServerError) => Some(Ok(Response {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            body: "Injected server error".to_owned(),
+            headers: HeaderMap::new()
+        })),
+        _ => None
+    }
+}
+
+// ...and cuts the responses of others short, after they are recorded
+fn garble(response: Response, fault: Option<Fault>) -> Response {
+    match fault {
+        Some(Fault::MalformedJson) => {
+            let half = response.body.chars().count() / 2;
+            Response {
+                body: format!("{}{{", response.body.chars().take(half).collect::<String>()),
+                ..response
+            }
+        },
+        _ => response
+    }
+}
+
+fn send(url: &str,
+        method: Method,
+        body: &Option<String>,
+        headers: &HeaderMap) -> Result<Response, ApiError> {
+    let client = match client() {
+        Ok(client) => client,
+        Err(err) => return Err(err)
+    };
+    let mut request = match Request::builder().method(method).uri(url)
+            .body(Full::new(Bytes::from(body.to_owned().unwrap_or_default()))) {
+        Ok(request) => request,
+        Err(err) => return Err(ApiError::Http(Box::new(err)))
+    };
+    *request.headers_mut() = headers.to_owned();
+
+    let (parts, response_body) = match runtime::runtime().block_on(client.request(request)) {
+        Ok(response) => response.into_parts(),
+        Err(err) => return Err(ApiError::Http(Box::new(err)))
     };
+    let response_body = match runtime::runtime().block_on(response_body.collect()) {
+        Ok(collected) => collected.to_bytes(),
+        Err(err) => return Err(ApiError::Http(Box::new(err)))
+    };
+    match String::from_utf8(response_body.to_vec()) {
+        Ok(response_body) => Ok(Response {
+            status: parts.status,
+            body: response_body,
+            headers: parts.headers
+        }),
+        Err(err) => Err(ApiError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+}
 
-    client.headers(headers.to_owned())
-        .send()
+// Rejected credentials are always an error, so that `credentials::authorized` can refresh them and retry
+fn authorized(response: Response) -> Result<Response, ApiError> {
+    match response.status {
+        StatusCode::UNAUTHORIZED => Err(ApiError::Status(response.status)),
+        _ => Ok(response)
+    }
 }
 
 fn request<T>(url: &str,
-              method: hyper::method::Method,
+              method: Method,
               body: &Option<String>,
-              headers: &hyper::header::Headers,
-              status_code: &hyper::status::StatusCode)
-                    -> Result<T, String> where T: Decodable {
-    let mut response = match request_raw(url, method, body, headers) {
-        Ok(response) => response,
-        Err(err) => return Err(err.to_string())
-    };
+              headers: &HeaderMap,
+              status_code: &StatusCode)
+                    -> Result<T, ApiError> where T: DeserializeOwned {
+    match request_raw(url, method, body, headers) {
+        Ok(response) => decode(response, status_code),
+        Err(err) => Err(err)
+    }
+}
 
+fn decode<T>(response: Response, status_code: &StatusCode)
+        -> Result<T, ApiError> where T: DeserializeOwned {
     match response.status {
         ref status if status == status_code => (),
-        e @ _ => return Err(e.to_string())
+        e @ _ => return Err(ApiError::Status(e))
     };
 
-    let mut json_string = String::new();
-    if let Err(err) = response.read_to_string(&mut json_string) {
-        return Err(err.to_string())
+    match serde_json::from_str(&response.body) {
+        Ok(decoded) => Ok(decoded),
+        Err(err) => Err(ApiError::Parse { body: response.body, source: err })
     }
+}
 
-    match json::decode(&json_string) {
-        Ok(decoded) => Ok(decoded),
-        Err(err) => Err(format!("Error parsing response: {} {}", json_string, err))
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use hyper::{HeaderMap, StatusCode};
+    use super::{get_all, get_all_while, get_paged, next_link, next_page, Headers, Page, Response};
+
+    fn response(body: &str, header: Option<(&str, &str)>) -> Response {
+        let mut headers = Headers { headers: HeaderMap::new() };
+        if let Some((name, value)) = header {
+            headers.set(name, value);
+        }
+        Response {
+            status: StatusCode::OK,
+            body: body.to_owned(),
+            headers: headers.headers
+        }
+    }
+
+    #[test]
+    fn pages_are_followed_until_the_last() {
+        let requested = RefCell::new(vec![]);
+        let values = get_paged("http://foo/bar?state=OPEN", |url| {
+            requested.borrow_mut().push(url.to_owned());
+            let start = requested.borrow().len() as i32 - 1;
+            Ok(Page {
+                values: vec![start * 25],
+                is_last_page: start == 2,
+                next_page_start: if start == 2 { None } else { Some((start + 1) * 25) }
+            })
+        });
+        assert_eq!(vec![0, 25, 50], values.unwrap());
+        assert_eq!(vec!["http://foo/bar?state=OPEN&start=0", "http://foo/bar?state=OPEN&start=25",
+            "http://foo/bar?state=OPEN&start=50"], *requested.borrow());
+    }
+
+    #[test]
+    fn next_links_are_followed_until_there_is_none() {
+        let requested = RefCell::new(vec![]);
+        let values = get_all::<i32, _, _>("http://foo/pulls?per_page=2", |url| {
+            requested.borrow_mut().push(url.to_owned());
+            Ok(match requested.borrow().len() {
+                1 => response("[1, 2]", Some(("Link", "<http://foo/pulls?per_page=2&page=2>; rel=\"next\", \
+                    <http://foo/pulls?per_page=2&page=2>; rel=\"last\""))),
+                _ => response("[3]", Some(("Link", "<http://foo/pulls?per_page=2&page=1>; rel=\"first\"")))
+            })
+        }, next_link);
+        assert_eq!(vec![1, 2, 3], values.unwrap());
+        assert_eq!(vec!["http://foo/pulls?per_page=2", "http://foo/pulls?per_page=2&page=2"], *requested.borrow());
+    }
+
+    #[test]
+    fn paging_stops_once_no_more_values_are_wanted() {
+        let requested = RefCell::new(vec![]);
+        let values = get_all_while::<i32, _, _, _>("http://foo/pulls", |url| {
+            requested.borrow_mut().push(url.to_owned());
+            let page = requested.borrow().len();
+            Ok(response(&format!("[{}, {}]", 10 - page * 2, 9 - page * 2),
+                Some(("Link", &format!("<http://foo/pulls?page={}>; rel=\"next\"", page + 1)))))
+        }, next_link, |page| page.last().map_or(false, |&value| value > 5));
+        assert_eq!(vec![8, 7, 6, 5], values.unwrap());
+        assert_eq!(vec!["http://foo/pulls", "http://foo/pulls?page=2"], *requested.borrow());
+    }
+
+    #[test]
+    fn next_page_numbers_replace_the_page_asked_for() {
+        let url = "http://foo/merge_requests?state=opened&page=1&per_page=100";
+        assert_eq!(Some("http://foo/merge_requests?state=opened&per_page=100&page=2".to_owned()),
+            next_page(url, &response("[]", Some(("X-Next-Page", "2")))));
+        assert_eq!(None, next_page(url, &response("[]", Some(("X-Next-Page", "")))));
+        assert_eq!(None, next_page(url, &response("[]", None)));
     }
 }
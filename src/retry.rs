@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use regex::Regex;
+use time;
+
+use ::errors::ConfigError;
+use ::i18n::Messages;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct RetryConfig {
+    // Regular expressions matched against the status text of failed builds, telling infrastructure failures apart
+    pub patterns: Vec<String>,
+    // Builds of the same commit queued again on the same builder, 3 unless set
+    pub max_retries: Option<u32>,
+    // Seconds before the first retry, doubled for each further one, 60 unless set
+    pub backoff: Option<i64>
+}
+
+// What to do with a build that was found
+#[derive(PartialEq, Clone, Debug)]
+pub enum Action {
+    Report(::BuildDetails),
+    // Queues the commit again, for the retry numbered so
+    Retry(u32)
+}
+
+// A commit built on one of the builders of a pull request
+#[derive(Eq, PartialEq, Hash, Clone, Debug)]
+pub struct Key {
+    pub repository: Option<String>,
+    pub pr: i32,
+    pub builder: String,
+    pub commit: String
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+struct Attempts {
+    retries: u32,
+    // The failed build the backoff is counted for, and when it was first seen
+    failed: Option<(i32, i64)>
+}
+
+pub struct Retries {
+    patterns: Vec<Regex>,
+    max_retries: u32,
+    backoff: i64,
+    attempts: HashMap<Key, Attempts>
+}
+
+lazy_static! {
+    static ref RETRIES: Mutex<Option<Retries>> = Mutex::new(None);
+}
+
+impl Retries {
+    pub fn new(config: &RetryConfig) -> Result<Retries, ConfigError> {
+        let mut patterns = vec![];
+        for pattern in &config.patterns {
+            match Regex::new(pattern) {
+                Ok(regex) => patterns.push(regex),
+                Err(err) => return Err(ConfigError::Invalid(format!("Invalid retry pattern {}: {}", pattern, err)))
+            }
+        }
+        Ok(Retries {
+            patterns: patterns,
+            max_retries: config.max_retries.unwrap_or(3),
+            backoff: config.backoff.unwrap_or(60),
+            attempts: HashMap::new()
+        })
+    }
+
+    fn infrastructure_failure(&self, build: &::BuildDetails) -> bool {
+        build.state == ::BuildState::Finished && build.status == ::BuildStatus::Failure
+            && build.status_text.as_ref().map_or(false, |text| self.patterns.iter().any(|regex| regex.is_match(text)))
+    }
+
+    // Retries a failed build once the backoff for the retries of its commit so far has passed, as long as any are left
+    pub fn decide(&mut self, key: &Key, build: &::BuildDetails, now: i64) -> Option<u32> {
+        // Earlier commits of the pull request will not be built again
+        self.attempts.retain(|other, _| other.repository != key.repository || other.pr != key.pr
+            || other.builder != key.builder || other.commit == key.commit);
+        if !self.infrastructure_failure(build) {
+            return None;
+        }
+        let (max_retries, backoff) = (self.max_retries, self.backoff);
+        let attempts = self.attempts.entry(key.to_owned()).or_default();
+        if attempts.retries >= max_retries {
+            return None;
+        }
+        let failed_at = match attempts.failed {
+            Some((id, failed_at)) if id == build.id => failed_at,
+            _ => {
+                attempts.failed = Some((build.id, now));
+                now
+            }
+        };
+        // Saturates rather than overflowing, for high retry limits
+        let wait = 2i64.checked_pow(attempts.retries).and_then(|factor| backoff.checked_mul(factor))
+            .unwrap_or(i64::MAX);
+        match now - failed_at >= wait {
+            true => {
+                attempts.retries += 1;
+                attempts.failed = None;
+                Some(attempts.retries)
+            },
+            false => None
+        }
+    }
+
+    pub fn retries(&self, key: &Key) -> u32 {
+        self.attempts.get(key).map_or(0, |attempts| attempts.retries)
+    }
+}
+
+pub fn enable(config: &RetryConfig) -> Result<(), ConfigError> {
+    match Retries::new(config) {
+        Ok(retries) => {
+            *RETRIES.lock().expect("Retries should not be poisoned") = Some(retries);
+            Ok(())
+        },
+        Err(err) => Err(err)
+    }
+}
+
+// Whether the commit `build` is of should be built again after an infrastructure failure, or else `build` with a
+// note on how often it has been. Builds are always reported when retries are not enabled.
+pub fn check(repository: Option<&str>, pr: &::PullRequest, ci: &::ContinuousIntegrator, build: ::BuildDetails,
+        messages: &Messages) -> Action {
+    let mut enabled = RETRIES.lock().expect("Retries should not be poisoned");
+    let retries = match *enabled {
+        Some(ref mut retries) => retries,
+        None => return Action::Report(build)
+    };
+    let key = Key {
+        repository: repository.map(str::to_owned),
+        pr: pr.id,
+        builder: ci.name().unwrap_or("").to_owned(),
+        commit: build.commit.to_owned().unwrap_or(pr.from_commit.to_owned())
+    };
+    if let Some(retry) = retries.decide(&key, &build, time::now_utc().to_timespec().sec) {
+        return Action::Retry(retry);
+    }
+    match retries.retries(&key) {
+        0 => Action::Report(build),
+        count => {
+            let mut build = build;
+            build.notes.push(messages.format("retry.count", &[("count", &count.to_string())]));
+            Action::Report(build)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Key, Retries, RetryConfig};
+
+    fn retries() -> Retries {
+        Retries::new(&RetryConfig {
+            patterns: vec!["(?i)agent (lost|disconnected)".to_owned(), "^Out of disk space".to_owned()],
+            max_retries: Some(2),
+            backoff: Some(60)
+        }).unwrap()
+    }
+
+    fn key(commit: &str) -> Key {
+        Key { repository: None, pr: 1, builder: "Build".to_owned(), commit: commit.to_owned() }
+    }
+
+    fn failed(id: i32, status_text: &str) -> ::BuildDetails {
+        ::BuildDetails {
+            id: id,
+            build_id: "Build".to_owned(),
+            web_url: format!("http://www.goodbuilds.com/{}", id),
+            commit: Some("abc".to_owned()),
+            state: ::BuildState::Finished,
+            status: ::BuildStatus::Failure,
+            status_text: Some(status_text.to_owned()),
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+    #[test]
+    fn infrastructure_failures_are_retried_with_exponential_backoff() {
+        let mut retries = retries();
+        assert_eq!(None, retries.decide(&key("abc"), &failed(1, "Build agent lost"), 1000));
+        assert_eq!(Some(1), retries.decide(&key("abc"), &failed(1, "Build agent lost"), 1060));
+        assert_eq!(None, retries.decide(&key("abc"), &failed(2, "Out of disk space"), 2000));
+        assert_eq!(None, retries.decide(&key("abc"), &failed(2, "Out of disk space"), 2119));
+        assert_eq!(Some(2), retries.decide(&key("abc"), &failed(2, "Out of disk space"), 2120));
+        assert_eq!(None, retries.decide(&key("abc"), &failed(3, "Agent disconnected"), 9000));
+        assert_eq!(2, retries.retries(&key("abc")));
+    }
+
+    #[test]
+    fn other_failures_are_not_retried() {
+        let mut retries = retries();
+        assert_eq!(None, retries.decide(&key("abc"), &failed(1, "Tests failed: 3"), 1000));
+        assert_eq!(None, retries.decide(&key("abc"), &failed(1, "Tests failed: 3"), 9000));
+        let running = ::BuildDetails { state: ::BuildState::Running, ..failed(1, "Agent lost") };
+        assert_eq!(None, retries.decide(&key("abc"), &running, 9000));
+    }
+
+    #[test]
+    fn new_commits_start_over() {
+        let mut retries = retries();
+        retries.decide(&key("abc"), &failed(1, "Agent lost"), 1000);
+        assert_eq!(Some(1), retries.decide(&key("abc"), &failed(1, "Agent lost"), 1060));
+        assert_eq!(None, retries.decide(&key("def"), &failed(2, "Tests failed"), 1100));
+        assert_eq!(0, retries.retries(&key("abc")));
+    }
+
+    #[test]
+    fn long_backoffs_saturate() {
+        let mut retries = Retries::new(&RetryConfig {
+            patterns: vec!["Agent lost".to_owned()],
+            max_retries: Some(100),
+            backoff: Some(60)
+        }).unwrap();
+        retries.attempts.insert(key("abc"), super::Attempts { retries: 70, failed: Some((1, 1000)) });
+        assert_eq!(None, retries.decide(&key("abc"), &failed(1, "Agent lost"), 1000 + (60 << 40)));
+        assert_eq!(70, retries.retries(&key("abc")));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(Retries::new(&RetryConfig { patterns: vec!["(".to_owned()], max_retries: None, backoff: None })
+            .is_err());
+    }
+}
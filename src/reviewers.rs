@@ -0,0 +1,218 @@
+use std::sync::Mutex;
+use serde_json;
+use time;
+
+use ::errors::{ConfigError, Error};
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+use ::store::JsonStore;
+
+static DAY: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct ReviewerConfig {
+    // The usernames reviewers are picked from, the least loaded first
+    pub pool: Vec<String>,
+    // How many reviewers each pull request should end up with, 1 unless set
+    pub count: Option<usize>,
+    // Days an assignment counts towards a reviewer's load, 30 unless set
+    pub window: Option<i64>,
+    // A JSON file keeping recent assignments across restarts. Kept in memory only unless set.
+    pub path: Option<String>,
+    pub out_of_office: Option<Vec<Absence>>
+}
+
+// A reviewer who is not assigned anything from `from` until `until`, both inclusive dates such as `2018-07-23`
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Absence {
+    pub reviewer: String,
+    pub from: String,
+    pub until: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Assignment {
+    pub reviewer: String,
+    pub pr: i32,
+    // Milliseconds since the epoch
+    pub at: i64
+}
+
+pub struct AssignmentStore {
+    assignments: JsonStore<Vec<Assignment>>
+}
+
+lazy_static! {
+    static ref STORE: Mutex<Option<AssignmentStore>> = Mutex::new(None);
+}
+
+impl AssignmentStore {
+    // A store without a file yet is empty
+    pub fn load(path: &Option<String>) -> Result<AssignmentStore, ConfigError> {
+        JsonStore::load(path).map(|assignments| AssignmentStore { assignments: assignments })
+    }
+
+    // How many pull requests the reviewer has been assigned since `since`
+    pub fn load_of(&self, reviewer: &str, since: i64) -> usize {
+        self.assignments.iter().filter(|assignment| assignment.reviewer == reviewer && assignment.at >= since).count()
+    }
+
+    // Forgets the assignments made before `since` and rewrites the file
+    pub fn record(&mut self, assignments: Vec<Assignment>, since: i64) -> Result<(), ConfigError> {
+        self.assignments.retain(|assignment| assignment.at >= since);
+        self.assignments.extend(assignments);
+        self.assignments.save()
+    }
+}
+
+fn with_store<T, F>(config: &ReviewerConfig, action: F) -> Result<T, ConfigError>
+        where F: FnOnce(&mut AssignmentStore) -> T {
+    let mut store = STORE.lock().expect("Assignment store should not be poisoned");
+    if store.is_none() {
+        match AssignmentStore::load(&config.path) {
+            Ok(loaded) => *store = Some(loaded),
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(action(store.as_mut().expect("Assignment store should be loaded")))
+}
+
+impl ReviewerConfig {
+    pub fn since(&self, now: i64) -> i64 {
+        now - self.window.unwrap_or(30) * DAY
+    }
+
+    // ISO dates compare the same as strings and as days
+    pub fn is_away(&self, reviewer: &str, today: &str) -> bool {
+        self.out_of_office.as_ref().map_or(false, |absences| absences.iter().any(|absence| {
+            absence.reviewer == reviewer && absence.from.as_str() <= today && today <= absence.until.as_str()
+        }))
+    }
+
+    // The reviewers the pull request still needs, those assigned the fewest pull requests lately first and in the
+    // pool's order among equals
+    pub fn pick(&self, pr: &::PullRequest, store: &AssignmentStore, now: i64, today: &str) -> Vec<String> {
+        let wanted = self.count.unwrap_or(1).saturating_sub(pr.reviewers.len());
+        let since = self.since(now);
+        let mut eligible = self.pool.iter().enumerate()
+            .filter(|&(_, reviewer)| {
+                !pr.reviewers.contains(reviewer) && pr.author.username.as_ref() != Some(reviewer)
+                    && !self.is_away(reviewer, today)
+            })
+            .map(|(index, reviewer)| (store.load_of(reviewer, since), index, reviewer))
+            .collect::<Vec<_>>();
+        eligible.sort();
+        eligible.into_iter().take(wanted).map(|(_, _, reviewer)| reviewer.to_owned()).collect()
+    }
+}
+
+// Assigns reviewers to pull requests that have fewer than configured, spreading the work across the pool
+impl Stage for ReviewerConfig {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let now = time::now_utc();
+        let millis = now.to_timespec().sec * 1000;
+        let today = time::strftime("%Y-%m-%d", &now).expect("The date format should be valid");
+        let assigned = with_store(self, |store| {
+            let picked = self.pick(context.pr, store, millis, &today);
+            if picked.is_empty() {
+                return Ok(picked);
+            }
+            if let Err(err) = context.repository.add_reviewers(context.pr, &picked) {
+                return Err(Error::from(err));
+            }
+            let assignments = picked.iter().map(|reviewer| Assignment {
+                reviewer: reviewer.to_owned(),
+                pr: context.pr.id,
+                at: millis
+            }).collect();
+            match store.record(assignments, self.since(millis)) {
+                Ok(_) => Ok(picked),
+                Err(err) => Err(Error::from(err))
+            }
+        });
+        match assigned {
+            Ok(Ok(ref picked)) if picked.is_empty() => Ok(Outcome::Continue),
+            Ok(Ok(picked)) => {
+                println!("Assigned {} to Pull Request #{}", picked.join(", "), context.pr.id);
+                Ok(Outcome::Continue)
+            },
+            Ok(Err(err)) => Err(err),
+            Err(err) => Err(Error::from(err))
+        }
+    }
+}
+
+pub struct ReviewerFactory;
+
+impl registry::StageFactory for ReviewerFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        let config = match pipeline::parse_settings::<ReviewerConfig>(settings) {
+            Ok(config) => config,
+            Err(err) => return Err(err)
+        };
+        for absence in config.out_of_office.iter().flat_map(|absences| absences.iter()) {
+            let invalid = [&absence.from, &absence.until].iter().find(|date| time::strptime(date, "%Y-%m-%d").is_err())
+                .cloned();
+            if let Some(date) = invalid {
+                return Err(ConfigError::Invalid(format!("{} is not a date such as 2018-07-23", date)));
+            }
+        }
+        Ok(Box::new(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stubs::pull_request;
+    use super::{Absence, Assignment, AssignmentStore, ReviewerConfig, DAY};
+
+    fn config() -> ReviewerConfig {
+        ReviewerConfig {
+            pool: vec!["alice".to_owned(), "bob".to_owned(), "carol".to_owned(), "aaron".to_owned()],
+            count: Some(2),
+            window: Some(7),
+            path: None,
+            out_of_office: Some(vec![Absence {
+                reviewer: "carol".to_owned(),
+                from: "2018-07-23".to_owned(),
+                until: "2018-07-27".to_owned()
+            }])
+        }
+    }
+
+    fn store(reviewers: &[(&str, i64)]) -> AssignmentStore {
+        let mut store = AssignmentStore::load(&None).unwrap();
+        let assignments = reviewers.iter().map(|&(reviewer, at)| Assignment {
+            reviewer: reviewer.to_owned(),
+            pr: 1,
+            at: at
+        }).collect();
+        store.record(assignments, 0).unwrap();
+        store
+    }
+
+    #[test]
+    fn the_least_loaded_reviewers_are_picked() {
+        let store = store(&[("alice", 10 * DAY), ("alice", 10 * DAY), ("bob", 10 * DAY)]);
+        assert_eq!(vec!["carol".to_owned(), "bob".to_owned()],
+            config().pick(&pull_request(), &store, 10 * DAY, "2018-07-20"));
+        // Assignments older than the window no longer count
+        assert_eq!(vec!["alice".to_owned(), "bob".to_owned()],
+            config().pick(&pull_request(), &store, 20 * DAY, "2018-07-20"));
+    }
+
+    #[test]
+    fn absent_reviewers_authors_and_assigned_reviewers_are_skipped() {
+        let store = store(&[]);
+        assert_eq!(vec!["alice".to_owned(), "bob".to_owned()],
+            config().pick(&pull_request(), &store, 0, "2018-07-27"));
+        assert_eq!(vec!["carol".to_owned(), "bob".to_owned()],
+            ReviewerConfig { pool: vec!["carol".to_owned(), "bob".to_owned()], ..config() }
+                .pick(&pull_request(), &store, 0, "2018-07-28"));
+
+        let pr = ::PullRequest { reviewers: vec!["alice".to_owned()], ..pull_request() };
+        assert_eq!(vec!["bob".to_owned()], config().pick(&pr, &store, 0, "2018-07-20"));
+        let pr = ::PullRequest { reviewers: vec!["alice".to_owned(), "dave".to_owned()], ..pull_request() };
+        assert!(config().pick(&pr, &store, 0, "2018-07-20").is_empty());
+    }
+}
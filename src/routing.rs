@@ -0,0 +1,176 @@
+use serde_json;
+
+use ::branches;
+use ::components;
+use ::errors::{ConfigError, Error};
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+
+// Pull requests matching every condition set are built on `builders` only, named like the `components` builders
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Route {
+    // Titles starting with one of these, ignoring case
+    pub title_prefixes: Option<Vec<String>>,
+    // Target branches matching one of these patterns, as `branches` matches them
+    pub target_branches: Option<Vec<String>>,
+    // Authors with one of these usernames
+    pub authors: Option<Vec<String>>,
+    // Whether tasks are left open, for repositories reporting them. Never matches elsewhere.
+    pub open_tasks: Option<bool>,
+    // Pull requests with one of these labels, ignoring case, for repositories that have labels
+    pub labels: Option<Vec<String>>,
+    pub builders: Vec<String>
+}
+
+// Builds pull requests on the builders of the first route their metadata matches, in place of the `build` stage
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct MetadataRouting {
+    pub routes: Vec<Route>,
+    // Builders for pull requests matching no route. Every builder unless set.
+    pub other: Option<Vec<String>>
+}
+
+fn any<F: Fn(&String) -> bool>(values: &Option<Vec<String>>, matches: F) -> bool {
+    values.as_ref().map_or(true, |values| values.iter().any(matches))
+}
+
+impl Route {
+    pub fn matches(&self, pr: &::PullRequest) -> bool {
+        let title = pr.title.to_lowercase();
+        let target = pr.target_branch_name();
+        any(&self.title_prefixes, |prefix| title.starts_with(&prefix.to_lowercase()))
+            && any(&self.target_branches, |pattern| branches::glob_matches(pattern, &target))
+            && any(&self.authors, |author| pr.author.username.as_ref() == Some(author))
+            && self.open_tasks.map_or(true, |open| pr.open_tasks.map(|tasks| tasks > 0) == Some(open))
+            && any(&self.labels, |label| pr.labels.iter().any(|pr_label| pr_label.eq_ignore_ascii_case(label)))
+    }
+}
+
+impl MetadataRouting {
+    // The builders, by their index in `names`, the pull request should be built on
+    pub fn route(&self, pr: &::PullRequest, names: &[Option<&str>]) -> Result<Vec<usize>, ConfigError> {
+        let (builders, owner) = match self.routes.iter().position(|route| route.matches(pr)) {
+            Some(index) => (&self.routes[index].builders, format!("route {}", index + 1)),
+            None => match self.other {
+                Some(ref builders) => (builders, "other pull requests".to_owned()),
+                None => return Ok((0..names.len()).collect())
+            }
+        };
+        let mut indices = vec![];
+        for builder in builders {
+            match components::position(names, builder, &owner) {
+                Ok(index) => indices.push(index),
+                Err(err) => return Err(err)
+            }
+        }
+        Ok(indices)
+    }
+}
+
+impl Stage for MetadataRouting {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let names: Vec<_> = context.matrix.iter().map(|ci| ci.name()).collect();
+        let routes = match self.route(context.pr, &names) {
+            Ok(indices) => indices.into_iter().map(|index| (context.matrix[index], None)).collect::<Vec<_>>(),
+            Err(err) => return Err(Error::from(err))
+        };
+        if routes.is_empty() {
+            return Ok(Outcome::Skip("Routed to no builder".to_owned()));
+        }
+
+        ::handle_pull_request_components(context.pr, context.repository, &routes, &context.config.queue_alert,
+            context.messages, context.fanout).map(|_| Outcome::Continue)
+    }
+}
+
+pub struct MetadataRoutingFactory;
+
+impl registry::StageFactory for MetadataRoutingFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match pipeline::parse_settings::<MetadataRouting>(settings) {
+            Ok(routing) => Ok(Box::new(routing)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stubs::pull_request;
+    use super::{MetadataRouting, Route};
+
+    fn route(builders: &[&str]) -> Route {
+        Route {
+            title_prefixes: None,
+            target_branches: None,
+            authors: None,
+            open_tasks: None,
+            labels: None,
+            builders: builders.iter().map(|&builder| builder.to_owned()).collect()
+        }
+    }
+
+    fn routing(other: Option<Vec<String>>) -> MetadataRouting {
+        MetadataRouting {
+            routes: vec![
+                Route { title_prefixes: Some(vec!["docs:".to_owned()]), ..route(&["Lint"]) },
+                Route {
+                    target_branches: Some(vec!["release/*".to_owned()]),
+                    open_tasks: Some(false),
+                    ..route(&["Build", "Release"])
+                }
+            ],
+            other: other
+        }
+    }
+
+    #[test]
+    fn pull_requests_are_routed_by_the_first_matching_route() {
+        let names = [Some("Build"), Some("Lint"), Some("Release")];
+        let docs = ::PullRequest { title: "Docs: Fix typo".to_owned(), ..pull_request() };
+        assert_eq!(vec![1], routing(None).route(&docs, &names).unwrap());
+
+        let release = ::PullRequest {
+            to_ref: "refs/heads/release/2.0".to_owned(),
+            open_tasks: Some(0),
+            ..pull_request()
+        };
+        assert_eq!(vec![0, 2], routing(None).route(&release, &names).unwrap());
+        let unfinished = ::PullRequest { open_tasks: Some(2), ..release.clone() };
+        assert_eq!(vec![0, 1, 2], routing(None).route(&unfinished, &names).unwrap());
+        let untold = ::PullRequest { open_tasks: None, ..release };
+        assert_eq!(vec![0], routing(Some(vec!["Build".to_owned()])).route(&untold, &names).unwrap());
+    }
+
+    #[test]
+    fn authors_are_matched_by_username() {
+        let routing = MetadataRouting {
+            routes: vec![Route { authors: Some(vec!["renovate".to_owned()]), ..route(&["Lint"]) }],
+            other: None
+        };
+        let mut pr = pull_request();
+        pr.author.username = Some("renovate".to_owned());
+        assert_eq!(vec![0], routing.route(&pr, &[Some("Lint"), Some("Build")]).unwrap());
+        pr.author.username = None;
+        assert_eq!(vec![0, 1], routing.route(&pr, &[Some("Lint"), Some("Build")]).unwrap());
+    }
+
+    #[test]
+    fn labels_are_matched_ignoring_case() {
+        let routing = MetadataRouting {
+            routes: vec![Route { labels: Some(vec!["documentation".to_owned()]), ..route(&["Lint"]) }],
+            other: None
+        };
+        let mut pr = pull_request();
+        pr.labels = vec!["bug".to_owned(), "Documentation".to_owned()];
+        assert_eq!(vec![0], routing.route(&pr, &[Some("Lint"), Some("Build")]).unwrap());
+        pr.labels = vec![];
+        assert_eq!(vec![0, 1], routing.route(&pr, &[Some("Lint"), Some("Build")]).unwrap());
+    }
+
+    #[test]
+    fn unknown_builders_are_rejected() {
+        let docs = ::PullRequest { title: "docs: Fix typo".to_owned(), ..pull_request() };
+        assert!(routing(None).route(&docs, &[Some("Build")]).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use rhai::{self, Dynamic, Engine, Scope, AST};
+use serde::Serialize;
+use serde_json;
+
+use ::checks::{self, Report};
+use ::errors::{ConfigError, Error};
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+use ::size;
+
+// Stops scripts that loop forever from stalling the daemon
+static MAX_OPERATIONS: u64 = 1000000;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct RulesConfig {
+    // A Rhai script calling `warn(message)` and `fail(message)`
+    pub path: String,
+    // `comment` unless configured
+    pub report: Option<Report>
+}
+
+// What a script has found, in the order it called `fail` and `warn`
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct Findings {
+    pub failures: Vec<String>,
+    pub warnings: Vec<String>
+}
+
+#[derive(Serialize)]
+struct Changes {
+    files: Vec<String>,
+    insertions: usize,
+    deletions: usize
+}
+
+// Runs a Danger style script with the pull request as `pr`, its diff stats as `changes` and the latest builds of its
+// commit as `builds`. Failures fail the check and warnings are only commented on.
+pub struct Rules {
+    config: RulesConfig,
+    ast: AST
+}
+
+fn to_dynamic<T>(value: &T) -> Result<Dynamic, Error> where T: Serialize {
+    rhai::serde::to_dynamic(value).map_err(|err| Error::from(ConfigError::Invalid(format!("Rule input: {}", err))))
+}
+
+impl Rules {
+    pub fn new(config: &RulesConfig) -> Result<Rules, ConfigError> {
+        let mut source = String::new();
+        if let Err(err) = File::open(&config.path).and_then(|mut file| file.read_to_string(&mut source)) {
+            return Err(ConfigError::Read(err));
+        }
+        Rules::compile(config, &source)
+    }
+
+    pub fn compile(config: &RulesConfig, source: &str) -> Result<Rules, ConfigError> {
+        match Engine::new().compile(source) {
+            Ok(ast) => Ok(Rules { config: config.to_owned(), ast: ast }),
+            Err(err) => Err(ConfigError::Invalid(format!("Invalid rules in {}: {}", config.path, err)))
+        }
+    }
+
+    pub fn evaluate(&self, pr: &::PullRequest, changes: Dynamic, builds: &[::BuildDetails])
+            -> Result<Findings, Error> {
+        let findings = Arc::new(Mutex::new(Findings::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let failures = findings.clone();
+        engine.register_fn("fail", move |message: &str| failures.lock().unwrap().failures.push(message.to_owned()));
+        let warnings = findings.clone();
+        engine.register_fn("warn", move |message: &str| warnings.lock().unwrap().warnings.push(message.to_owned()));
+
+        let mut scope = Scope::new();
+        match (to_dynamic(pr), to_dynamic(&builds)) {
+            (Ok(pr), Ok(builds)) => {
+                scope.push_dynamic("pr", pr);
+                scope.push_dynamic("changes", changes);
+                scope.push_dynamic("builds", builds);
+            },
+            (Err(err), _) | (_, Err(err)) => return Err(err)
+        }
+        if let Err(err) = engine.run_ast_with_scope(&mut scope, &self.ast) {
+            return Err(Error::from(ConfigError::Invalid(format!("Rules in {} failed: {}", self.config.path, err))));
+        }
+        let findings = findings.lock().unwrap().to_owned();
+        Ok(findings)
+    }
+
+    fn check(&self, context: &Context, builds: &[::BuildDetails]) -> Result<::CheckResult, Error> {
+        let changes = match size::changes(context.pr, context.repository, context.config) {
+            Ok((files, insertions, deletions)) => Changes {
+                files: files,
+                insertions: insertions,
+                deletions: deletions
+            },
+            Err(err) => {
+                println!("Unable to find the changes of Pull Request #{}: {}", context.pr.id, err);
+                Changes { files: vec![], insertions: 0, deletions: 0 }
+            }
+        };
+        let findings = match to_dynamic(&changes).and_then(|changes| self.evaluate(context.pr, changes, builds)) {
+            Ok(findings) => findings,
+            Err(err) => return Err(err)
+        };
+
+        let labels = context.messages.labels();
+        let summary = match (findings.failures.is_empty(), findings.warnings.is_empty()) {
+            (true, true) => context.messages.get("check.rules.passed"),
+            (true, false) => context.messages.get("check.rules.warned"),
+            (false, _) => context.messages.get("check.rules.failed")
+        };
+        let violations = findings.failures.iter().map(|failure| format!("{} {}", labels.failure(), failure))
+            .chain(findings.warnings.iter().map(|warning| format!("{} {}", labels.warning(), warning)))
+            .collect();
+        Ok(::CheckResult {
+            name: "rules".to_owned(),
+            passed: findings.failures.is_empty(),
+            summary: summary.to_owned(),
+            violations: violations
+        })
+    }
+}
+
+impl Stage for Rules {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let builds: Vec<_> = context.matrix.iter().filter_map(|ci| ::get_latest_build(context.pr, *ci)).collect();
+        // Rules may look at the builds, so they are evaluated again whenever one of them changes
+        let revision = builds.iter().fold(context.pr.from_commit.to_owned(), |revision, build| {
+            format!("{}\n{}:{:?}:{:?}", revision, build.id, build.state, build.status)
+        });
+        let report = self.config.report.unwrap_or(Report::Comment);
+        checks::run_check(context, "rules", &revision, report, |context| self.check(context, &builds))
+    }
+}
+
+pub struct RulesFactory;
+
+impl registry::StageFactory for RulesFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        let config = match pipeline::parse_settings::<RulesConfig>(settings) {
+            Ok(config) => config,
+            Err(err) => return Err(err)
+        };
+        match Rules::new(&config) {
+            Ok(rules) => Ok(Box::new(rules)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use errors::ConfigError;
+    use stubs::pull_request;
+    use super::{to_dynamic, Changes, Findings, Rules, RulesConfig};
+
+    static RULES: &'static str = r#"
+        if !pr.title.starts_with("PD-") {
+            fail("The title does not start with a ticket");
+        }
+        if changes.insertions + changes.deletions > 500 {
+            warn(`${changes.files.len()} files changed, consider splitting`);
+        }
+        for build in builds {
+            if build.status == "Failure" {
+                fail(`${build.build_id} failed`);
+            }
+        }
+    "#;
+
+    fn config() -> RulesConfig {
+        RulesConfig { path: "rules.rhai".to_owned(), report: None }
+    }
+
+    #[test]
+    fn scripts_report_failures_and_warnings() {
+        let rules = Rules::compile(&config(), RULES).unwrap();
+        let changes = Changes { files: vec!["a.rs".to_owned(), "b.rs".to_owned()], insertions: 600, deletions: 0 };
+        assert_eq!(Findings {
+            failures: vec!["The title does not start with a ticket".to_owned()],
+            warnings: vec!["2 files changed, consider splitting".to_owned()]
+        }, rules.evaluate(&pull_request(), to_dynamic(&changes).unwrap(), &[]).unwrap());
+
+        let pr = ::PullRequest { title: "PD-479 Add rules".to_owned(), ..pull_request() };
+        let changes = Changes { files: vec![], insertions: 1, deletions: 1 };
+        assert_eq!(Findings::default(), rules.evaluate(&pr, to_dynamic(&changes).unwrap(), &[]).unwrap());
+    }
+
+    #[test]
+    fn endless_scripts_are_stopped() {
+        let rules = Rules::compile(&config(), "loop { }").unwrap();
+        let changes = Changes { files: vec![], insertions: 0, deletions: 0 };
+        assert!(rules.evaluate(&pull_request(), to_dynamic(&changes).unwrap(), &[]).is_err());
+    }
+
+    #[test]
+    fn invalid_scripts_are_rejected() {
+        match Rules::compile(&config(), "fail(") {
+            Err(ConfigError::Invalid(_)) => {},
+            _ => panic!("Expected the script to be rejected")
+        }
+    }
+}
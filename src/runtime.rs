@@ -0,0 +1,14 @@
+use tokio::runtime::{Builder, Runtime};
+
+lazy_static! {
+    // Drives the HTTP client and the daemon's own HTTP endpoints. The rest of the daemon blocks on it.
+    static ref RUNTIME: Runtime = Builder::new_multi_thread()
+        .enable_io()
+        .thread_name("pr_demon-http")
+        .build()
+        .expect("The HTTP runtime should start");
+}
+
+pub fn runtime() -> &'static Runtime {
+    &RUNTIME
+}
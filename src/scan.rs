@@ -0,0 +1,176 @@
+use std::path::Path;
+use std::process::Command;
+use serde_json;
+
+use ::checks::{self, Report};
+use ::components;
+use ::errors::{ConfigError, Error};
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+use ::size;
+
+// Manifests and lock files of the common package managers
+static DEFAULT_MANIFESTS: &'static [&'static str] = &[
+    "Cargo.toml", "Cargo.lock", "package.json", "package-lock.json", "yarn.lock", "pom.xml", "build.gradle",
+    "requirements.txt", "Pipfile.lock", "go.mod", "go.sum", "Gemfile.lock", "composer.lock"
+];
+
+// Runs a dependency or license scanner, such as cargo-deny or OWASP Dependency-Check, when a pull request changes a
+// manifest. The scanner exits with a failure on policy violations and prints one offending dependency per line.
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct ScanConfig {
+    pub command: String,
+    pub args: Option<Vec<String>>,
+    // Where the scanner runs, usually a checkout it updates to `PR_DEMON_COMMIT` itself
+    pub directory: Option<String>,
+    // File names, or paths, of the manifests that trigger a scan. The common package managers' unless set.
+    pub manifests: Option<Vec<String>>,
+    // `both` unless configured
+    pub report: Option<Report>
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ScanResult {
+    pub passed: bool,
+    pub offenders: Vec<String>
+}
+
+pub struct Scanner {
+    config: ScanConfig
+}
+
+impl Scanner {
+    pub fn new(config: &ScanConfig) -> Scanner {
+        Scanner { config: config.to_owned() }
+    }
+
+    pub fn is_manifest(&self, file: &str) -> bool {
+        let name = Path::new(file).file_name().map_or("".into(), |name| name.to_string_lossy());
+        match self.config.manifests {
+            Some(ref manifests) => {
+                manifests.iter().any(|manifest| *manifest == name || components::is_under(file, manifest))
+            },
+            None => DEFAULT_MANIFESTS.iter().any(|manifest| *manifest == name)
+        }
+    }
+
+    // Runs the scanner with the pull request described in `PR_DEMON_*` environment variables
+    pub fn scan(&self, pr: &::PullRequest, manifests: &[String]) -> Result<ScanResult, String> {
+        let mut command = Command::new(&self.config.command);
+        command.args(self.config.args.clone().unwrap_or(vec![]))
+            .env("PR_DEMON_PR_ID", pr.id.to_string())
+            .env("PR_DEMON_COMMIT", &pr.from_commit)
+            .env("PR_DEMON_FROM_REF", &pr.from_ref)
+            .env("PR_DEMON_TO_REF", &pr.to_ref)
+            .env("PR_DEMON_MANIFESTS", manifests.join("\n"));
+        if let Some(ref directory) = self.config.directory {
+            command.current_dir(directory);
+        }
+
+        let output = match command.output() {
+            Ok(output) => output,
+            Err(err) => return Err(format!("Unable to run {}: {}", self.config.command, err))
+        };
+        // Scanners that crash rather than report violations are told apart by printing nothing
+        let offenders: Vec<String> = String::from_utf8_lossy(&output.stdout).lines()
+            .map(|line| line.trim().to_owned())
+            .filter(|line| !line.is_empty())
+            .collect();
+        if !output.status.success() && offenders.is_empty() {
+            return Err(format!("{} exited with {}: {}", self.config.command, output.status,
+                String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(ScanResult {
+            passed: output.status.success(),
+            offenders: offenders
+        })
+    }
+
+    fn check(&self, context: &Context, manifests: &[String]) -> Result<::CheckResult, Error> {
+        let result = match self.scan(context.pr, manifests) {
+            Ok(result) => result,
+            Err(err) => return Err(Error::from(ConfigError::Invalid(err)))
+        };
+        let summary = match result.passed {
+            true => context.messages.get("check.dependencies.passed"),
+            false => context.messages.get("check.dependencies.failed")
+        };
+        Ok(::CheckResult {
+            name: "dependencies".to_owned(),
+            passed: result.passed,
+            summary: summary.to_owned(),
+            violations: match result.passed {
+                true => vec![],
+                false => result.offenders
+            }
+        })
+    }
+}
+
+impl Stage for Scanner {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let files = match size::changes(context.pr, context.repository, context.config) {
+            Ok((files, _, _)) => files,
+            Err(err) => return Err(Error::from(ConfigError::Invalid(format!("Unable to find the changes: {}", err))))
+        };
+        let manifests: Vec<String> = files.into_iter().filter(|file| self.is_manifest(file)).collect();
+        if manifests.is_empty() {
+            return Ok(Outcome::Continue);
+        }
+        let report = self.config.report.unwrap_or(Report::Both);
+        checks::run_check(context, "dependencies", &context.pr.from_commit, report,
+            |context| self.check(context, &manifests))
+    }
+}
+
+pub struct ScannerFactory;
+
+impl registry::StageFactory for ScannerFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match pipeline::parse_settings::<ScanConfig>(settings) {
+            Ok(config) => Ok(Box::new(Scanner::new(&config))),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stubs::pull_request;
+    use super::{ScanConfig, ScanResult, Scanner};
+
+    fn shell_scanner(script: &str) -> Scanner {
+        Scanner::new(&ScanConfig {
+            command: "sh".to_owned(),
+            args: Some(vec!["-c".to_owned(), script.to_owned()]),
+            directory: None,
+            manifests: None,
+            report: None
+        })
+    }
+
+    #[test]
+    fn manifests_are_recognized_by_name_or_path() {
+        let scanner = shell_scanner("");
+        assert!(scanner.is_manifest("crates/core/Cargo.toml"));
+        assert!(!scanner.is_manifest("src/package.rs"));
+
+        let scanner = Scanner::new(&ScanConfig { manifests: Some(vec!["deps".to_owned()]), ..scanner.config });
+        assert!(scanner.is_manifest("deps/versions.txt"));
+        assert!(!scanner.is_manifest("Cargo.toml"));
+    }
+
+    #[test]
+    fn offending_dependencies_are_listed() {
+        let scanner = shell_scanner("echo \"openssl 0.9 ($PR_DEMON_MANIFESTS): GPL-3.0\"; echo; exit 1");
+        assert_eq!(Ok(ScanResult { passed: false, offenders: vec!["openssl 0.9 (Cargo.lock): GPL-3.0".to_owned()] }),
+            scanner.scan(&pull_request(), &["Cargo.lock".to_owned()]));
+        assert_eq!(Ok(ScanResult { passed: true, offenders: vec![] }),
+            shell_scanner("exit 0").scan(&pull_request(), &[]));
+    }
+
+    #[test]
+    fn silent_failures_are_errors() {
+        assert!(shell_scanner("echo broken >&2; exit 2").scan(&pull_request(), &[]).is_err());
+    }
+}
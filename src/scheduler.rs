@@ -0,0 +1,286 @@
+use std::cmp::{self, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use ::errors::ConfigError;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct SchedulerConfig {
+    // Pull requests handled at the same time across every watched repository
+    pub workers: usize,
+    // Pull requests of the same repository handled at the same time, `workers` unless set
+    pub per_repository: Option<usize>,
+    // Pull requests are handled in the order they are listed in unless set
+    pub priority: Option<PriorityConfig>
+}
+
+// Pull requests with fewer points are handled first
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct PriorityConfig {
+    // Points for each line the pull request changes, as measured with the `size` section, 1 unless set
+    pub size_weight: Option<i64>,
+    // Points taken off for each hour since the pull request was last updated, 10 unless set
+    pub age_weight: Option<i64>
+}
+
+impl PriorityConfig {
+    // `now` in milliseconds, like the times pull requests are updated at
+    pub fn score(&self, pr: &::PullRequest, now: i64) -> i64 {
+        let lines = pr.size.as_ref().map_or(0, |size| size.lines() as i64);
+        let hours = pr.updated.map_or(0, |updated| cmp::max(now - updated, 0) / 3_600_000);
+        lines * self.size_weight.unwrap_or(1) - hours * self.age_weight.unwrap_or(10)
+    }
+}
+
+// Hands pull requests to workers, at most `per_repository` of them for each repository and at most `workers` across
+// every repository polled at the same time
+pub struct Scheduler {
+    per_repository: usize,
+    priority: Option<PriorityConfig>,
+    slots: Slots
+}
+
+// A counting semaphore handing its slots to the waiter with the fewest points first, and to the earliest of those.
+// Slots are given back when their permit is dropped.
+struct Slots {
+    state: Mutex<Waiting>,
+    freed: Condvar
+}
+
+struct Waiting {
+    free: usize,
+    queue: BinaryHeap<Reverse<(i64, u64)>>,
+    tickets: u64
+}
+
+struct Permit<'a> {
+    slots: &'a Slots
+}
+
+impl Slots {
+    fn new(count: usize) -> Slots {
+        Slots {
+            state: Mutex::new(Waiting { free: count, queue: BinaryHeap::new(), tickets: 0 }),
+            freed: Condvar::new()
+        }
+    }
+
+    fn acquire<'a>(&'a self, score: i64) -> Permit<'a> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = Reverse((score, state.tickets));
+        state.tickets += 1;
+        state.queue.push(ticket);
+        while state.free == 0 || state.queue.peek() != Some(&ticket) {
+            state = self.freed.wait(state).unwrap();
+        }
+        state.queue.pop();
+        state.free -= 1;
+        // The next waiter might be able to take a slot as well
+        self.freed.notify_all();
+        Permit { slots: self }
+    }
+
+    #[cfg(test)]
+    fn free(&self) -> usize {
+        self.state.lock().unwrap().free
+    }
+
+    #[cfg(test)]
+    fn waiting(&self) -> usize {
+        self.state.lock().unwrap().queue.len()
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        self.slots.state.lock().unwrap().free += 1;
+        self.slots.freed.notify_all();
+    }
+}
+
+impl Scheduler {
+    pub fn new(config: &SchedulerConfig) -> Result<Scheduler, ConfigError> {
+        let per_repository = config.per_repository.unwrap_or(config.workers);
+        if config.workers == 0 || per_repository == 0 {
+            return Err(ConfigError::Invalid("The scheduler needs at least one worker".to_owned()));
+        }
+        Ok(Scheduler {
+            per_repository: cmp::min(per_repository, config.workers),
+            priority: config.priority.to_owned(),
+            slots: Slots::new(config.workers)
+        })
+    }
+
+    pub fn prioritized(&self) -> bool {
+        self.priority.is_some()
+    }
+
+    // The points of `pr`, all the same when pull requests are not prioritized
+    pub fn score(&self, pr: &::PullRequest, now: i64) -> i64 {
+        self.priority.as_ref().map_or(0, |priority| priority.score(pr, now))
+    }
+
+    // Handles every item, those with fewer points first but possibly finishing out of order, returning once all of
+    // them are handled. Items with the same points are started in the order they are given in.
+    pub fn run<T, F>(&self, items: Vec<(i64, T)>, handle: F) where T: Sync, F: Fn(&T) + Sync {
+        let mut items = items;
+        items.sort_by_key(|&(score, _)| score);
+        let next = AtomicUsize::new(0);
+        let workers = cmp::min(self.per_repository, items.len());
+        let items = &items;
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    match items.get(index) {
+                        Some(&(score, ref item)) => {
+                            let _permit = self.slots.acquire(score);
+                            handle(item)
+                        },
+                        None => break
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Barrier, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use stubs::pull_request;
+    use super::{PriorityConfig, Scheduler, SchedulerConfig, Slots};
+
+    #[derive(Default)]
+    struct Counter {
+        running: AtomicUsize,
+        peak: AtomicUsize
+    }
+
+    // Holds the first `size` items passing through it until all of them are being handled at once
+    struct Gate {
+        size: usize,
+        entered: AtomicUsize,
+        barrier: Barrier
+    }
+
+    impl Gate {
+        fn new(size: usize) -> Gate {
+            Gate { size: size, entered: AtomicUsize::new(0), barrier: Barrier::new(size) }
+        }
+
+        fn pass(&self) {
+            if self.entered.fetch_add(1, Ordering::SeqCst) < self.size {
+                self.barrier.wait();
+            }
+        }
+    }
+
+    fn scheduler(workers: usize, per_repository: Option<usize>) -> Scheduler {
+        Scheduler::new(&SchedulerConfig { workers: workers, per_repository: per_repository, priority: None }).unwrap()
+    }
+
+    // Runs every item on `scheduler`, counting the items being handled at once by each of `counters`
+    fn run(scheduler: &Scheduler, items: usize, gate: &Gate, counters: &[&Counter]) {
+        let handled = Mutex::new(vec![]);
+        scheduler.run((0..items).map(|item| (0, item)).collect(), |&item| {
+            for counter in counters {
+                let running = counter.running.fetch_add(1, Ordering::SeqCst) + 1;
+                counter.peak.fetch_max(running, Ordering::SeqCst);
+            }
+            gate.pass();
+            for counter in counters {
+                counter.running.fetch_sub(1, Ordering::SeqCst);
+            }
+            handled.lock().unwrap().push(item);
+        });
+        let mut handled = handled.into_inner().unwrap();
+        handled.sort();
+        assert_eq!((0..items).collect::<Vec<_>>(), handled);
+    }
+
+    // The most items handled at once, after checking `limit` of them could be
+    fn peak(scheduler: &Scheduler, items: usize, limit: usize) -> usize {
+        let counter = Counter::default();
+        run(scheduler, items, &Gate::new(limit), &[&counter]);
+        counter.peak.into_inner()
+    }
+
+    #[test]
+    fn every_item_is_handled_within_the_repository_limit() {
+        assert!(peak(&scheduler(8, Some(3)), 10, 3) <= 3);
+        assert!(peak(&scheduler(8, None), 2, 2) <= 2);
+        assert_eq!(0, peak(&scheduler(8, None), 0, 0));
+        assert!(peak(&scheduler(2, Some(5)), 10, 2) <= 2);
+    }
+
+    #[test]
+    fn repositories_share_the_global_limit() {
+        let scheduler = scheduler(4, Some(3));
+        let gate = Gate::new(4);
+        let (global, first, second) = (Counter::default(), Counter::default(), Counter::default());
+        thread::scope(|scope| {
+            scope.spawn(|| run(&scheduler, 12, &gate, &[&global, &first]));
+            scope.spawn(|| run(&scheduler, 12, &gate, &[&global, &second]));
+        });
+        assert!(first.peak.into_inner() <= 3 && second.peak.into_inner() <= 3);
+        assert!(global.peak.into_inner() <= 4);
+        assert_eq!(4, scheduler.slots.free());
+    }
+
+    #[test]
+    fn workers_are_required() {
+        assert!(Scheduler::new(&SchedulerConfig { workers: 0, per_repository: None, priority: None }).is_err());
+        assert!(Scheduler::new(&SchedulerConfig { workers: 2, per_repository: Some(0), priority: None }).is_err());
+    }
+
+    #[test]
+    fn items_with_fewer_points_are_handled_first() {
+        let handled = Mutex::new(vec![]);
+        scheduler(1, None).run(vec![(5, "large"), (-20, "old"), (5, "larger"), (0, "new")], |&item| {
+            handled.lock().unwrap().push(item);
+        });
+        assert_eq!(vec!["old", "new", "large", "larger"], handled.into_inner().unwrap());
+    }
+
+    #[test]
+    fn freed_slots_go_to_the_waiter_with_the_fewest_points() {
+        let slots = Slots::new(1);
+        let handled = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            let permit = slots.acquire(0);
+            for &score in &[30, 10, 20] {
+                let (slots, handled) = (&slots, &handled);
+                let waiting = slots.waiting() + 1;
+                scope.spawn(move || {
+                    let _permit = slots.acquire(score);
+                    handled.lock().unwrap().push(score);
+                });
+                while slots.waiting() < waiting {
+                    thread::yield_now();
+                }
+            }
+            drop(permit);
+        });
+        assert_eq!(vec![10, 20, 30], handled.into_inner().unwrap());
+    }
+
+    #[test]
+    fn small_and_old_pull_requests_score_fewer_points() {
+        let priority = PriorityConfig { size_weight: None, age_weight: Some(5) };
+        let hour = 3_600_000;
+        let mut pr = pull_request();
+        pr.updated = Some(10 * hour);
+        pr.size = None;
+        assert_eq!(0, priority.score(&pr, 10 * hour + 1000));
+        assert_eq!(-15, priority.score(&pr, 13 * hour));
+        pr.size = Some(::size::Size { class: ::size::SizeClass::Small, files: 2, insertions: 40, deletions: 10 });
+        assert_eq!(35, priority.score(&pr, 13 * hour));
+        pr.updated = None;
+        assert_eq!(50, priority.score(&pr, 13 * hour));
+    }
+}
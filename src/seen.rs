@@ -0,0 +1,201 @@
+use std::sync::Mutex;
+use time;
+
+use ::errors::ConfigError;
+use ::store::JsonStore;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct SeenConfig {
+    // A JSON file keeping the commits across restarts. Kept in memory only unless set.
+    pub path: Option<String>,
+    // Seconds after which a commit is queued again when its build is still not found, 3600 unless set
+    pub expire_after: Option<i64>
+}
+
+// The commit a pull request's source branch was at when a build of it was last queued
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Seen {
+    // The watch the pull request belongs to, when several repositories are watched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    pub id: i32,
+    pub commit: String,
+    // Seconds since the epoch, 0 for commits recorded before this was kept
+    #[serde(default)]
+    pub recorded: i64,
+    // Merged pull requests whose builds have been pinned, at their merged commit, rather than open ones
+    #[serde(default)]
+    pub pinned: bool
+}
+
+pub struct SeenStore {
+    seen: JsonStore<Vec<Seen>>,
+    expire_after: i64
+}
+
+lazy_static! {
+    static ref STORE: Mutex<Option<SeenStore>> = Mutex::new(None);
+}
+
+impl SeenStore {
+    // A store without a file yet is empty
+    pub fn load(config: &SeenConfig) -> Result<SeenStore, ConfigError> {
+        JsonStore::load(&config.path).map(|seen| SeenStore {
+            seen: seen,
+            expire_after: config.expire_after.unwrap_or(3600)
+        })
+    }
+
+    // Commits recorded longer than `expire_after` ago count as changed, in case their build was never queued after
+    // all or has since been deleted
+    pub fn unchanged(&self, repository: Option<&str>, pr: &::PullRequest, now: i64) -> bool {
+        self.seen.iter().any(|seen| seen.repository.as_ref().map(String::as_str) == repository && seen.id == pr.id
+            && !seen.pinned && seen.commit == pr.from_commit && now - seen.recorded < self.expire_after)
+    }
+
+    pub fn is_pinned(&self, repository: Option<&str>, pr: &::PullRequest) -> bool {
+        self.seen.iter().any(|seen| seen.repository.as_ref().map(String::as_str) == repository && seen.id == pr.id
+            && seen.pinned)
+    }
+
+    // Replaces the pull request's previous commit and rewrites the file
+    pub fn record(&mut self, repository: Option<&str>, pr: &::PullRequest, now: i64) -> Result<(), ConfigError> {
+        self.seen.retain(|seen| seen.repository.as_ref().map(String::as_str) != repository || seen.id != pr.id
+            || seen.pinned);
+        self.seen.push(Seen {
+            repository: repository.map(str::to_owned),
+            id: pr.id,
+            commit: pr.from_commit.to_owned(),
+            recorded: now,
+            pinned: false
+        });
+        self.seen.save()
+    }
+
+    // Remembers that the builds of the merged pull request have been pinned, so that they are not pinned again
+    pub fn record_pinned(&mut self, repository: Option<&str>, pr: &::PullRequest, now: i64)
+            -> Result<(), ConfigError> {
+        self.seen.push(Seen {
+            repository: repository.map(str::to_owned),
+            id: pr.id,
+            commit: pr.from_commit.to_owned(),
+            recorded: now,
+            pinned: true
+        });
+        self.seen.save()
+    }
+
+    // Pull requests of the repository that are no longer open will not be built again
+    pub fn forget_closed(&mut self, repository: Option<&str>, open: &[::PullRequest]) -> Result<(), ConfigError> {
+        let before = self.seen.len();
+        self.seen.retain(|seen| seen.repository.as_ref().map(String::as_str) != repository || seen.pinned
+            || open.iter().any(|pr| pr.id == seen.id));
+        match self.seen.len() == before {
+            true => Ok(()),
+            false => self.seen.save()
+        }
+    }
+}
+
+pub fn enable(store: SeenStore) {
+    *STORE.lock().expect("Seen commits should not be poisoned") = Some(store);
+}
+
+// Whether a build has already been queued for the commit the pull request is at. Never when no store is enabled.
+pub fn unchanged(repository: Option<&str>, pr: &::PullRequest) -> bool {
+    STORE.lock().expect("Seen commits should not be poisoned").as_ref()
+        .map_or(false, |store| store.unchanged(repository, pr, time::now_utc().to_timespec().sec))
+}
+
+pub fn record(repository: Option<&str>, pr: &::PullRequest) {
+    if let Some(ref mut store) = *STORE.lock().expect("Seen commits should not be poisoned") {
+        if let Err(err) = store.record(repository, pr, time::now_utc().to_timespec().sec) {
+            println!("Unable to store the commit of Pull Request #{}: {}", pr.id, err);
+        }
+    }
+}
+
+// Whether the builds of the merged pull request have been pinned. Never when no store is enabled.
+pub fn pinned(repository: Option<&str>, pr: &::PullRequest) -> bool {
+    STORE.lock().expect("Seen commits should not be poisoned").as_ref()
+        .map_or(false, |store| store.is_pinned(repository, pr))
+}
+
+pub fn record_pinned(repository: Option<&str>, pr: &::PullRequest) {
+    if let Some(ref mut store) = *STORE.lock().expect("Seen commits should not be poisoned") {
+        if let Err(err) = store.record_pinned(repository, pr, time::now_utc().to_timespec().sec) {
+            println!("Unable to store the pinned builds of Pull Request #{}: {}", pr.id, err);
+        }
+    }
+}
+
+pub fn forget_closed(repository: Option<&str>, open: &[::PullRequest]) {
+    if let Some(ref mut store) = *STORE.lock().expect("Seen commits should not be poisoned") {
+        if let Err(err) = store.forget_closed(repository, open) {
+            println!("Unable to store the commits of open Pull Requests: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stubs::pull_request;
+    use super::{SeenConfig, SeenStore};
+
+    fn pr(id: i32, commit: &str) -> ::PullRequest {
+        ::PullRequest { id: id, from_commit: commit.to_owned(), ..pull_request() }
+    }
+
+    fn store() -> SeenStore {
+        SeenStore::load(&SeenConfig { path: None, expire_after: Some(600) }).unwrap()
+    }
+
+    #[test]
+    fn only_new_commits_are_changed() {
+        let mut store = store();
+        assert!(!store.unchanged(None, &pr(1, "abc"), 1000));
+        store.record(None, &pr(1, "abc"), 1000).unwrap();
+        assert!(store.unchanged(None, &pr(1, "abc"), 1000));
+        assert!(!store.unchanged(None, &pr(1, "def"), 1000));
+        assert!(!store.unchanged(Some("frontend"), &pr(1, "abc"), 1000));
+
+        store.record(None, &pr(1, "def"), 1000).unwrap();
+        assert!(!store.unchanged(None, &pr(1, "abc"), 1000));
+        assert!(store.unchanged(None, &pr(1, "def"), 1000));
+    }
+
+    #[test]
+    fn recorded_commits_expire() {
+        let mut store = store();
+        store.record(None, &pr(1, "abc"), 1000).unwrap();
+        assert!(store.unchanged(None, &pr(1, "abc"), 1599));
+        assert!(!store.unchanged(None, &pr(1, "abc"), 1600));
+        // Queuing it again starts over
+        store.record(None, &pr(1, "abc"), 1600).unwrap();
+        assert!(store.unchanged(None, &pr(1, "abc"), 2000));
+    }
+
+    #[test]
+    fn closed_pull_requests_are_forgotten() {
+        let mut store = store();
+        store.record(None, &pr(1, "abc"), 1000).unwrap();
+        store.record(None, &pr(2, "def"), 1000).unwrap();
+        store.record(Some("frontend"), &pr(1, "abc"), 1000).unwrap();
+        store.forget_closed(None, &[pr(2, "def")]).unwrap();
+        assert!(!store.unchanged(None, &pr(1, "abc"), 1000));
+        assert!(store.unchanged(None, &pr(2, "def"), 1000));
+        assert!(store.unchanged(Some("frontend"), &pr(1, "abc"), 1000));
+    }
+
+    #[test]
+    fn pinned_pull_requests_are_kept_once_closed() {
+        let mut store = store();
+        store.record(None, &pr(1, "abc"), 1000).unwrap();
+        store.record_pinned(None, &pr(1, "abc"), 1000).unwrap();
+        assert!(store.is_pinned(None, &pr(1, "abc")));
+        assert!(!store.is_pinned(Some("frontend"), &pr(1, "abc")));
+        store.forget_closed(None, &[]).unwrap();
+        assert!(store.is_pinned(None, &pr(1, "abc")));
+        assert!(!store.unchanged(None, &pr(1, "abc"), 1000));
+    }
+}
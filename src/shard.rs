@@ -0,0 +1,144 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use ::errors::ConfigError;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct ShardConfig {
+    // This instance's name, unique among the instances sharing `directory`
+    pub instance: String,
+    // A directory every instance can write to, such as on a shared volume, where they announce themselves
+    pub directory: String,
+    // Seconds after which an instance that stopped announcing itself is taken to be gone, 60 unless set
+    pub expiry: Option<i64>
+}
+
+// The instances alive as of this poll, and which of them this is
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Shard {
+    instance: String,
+    members: Vec<String>
+}
+
+// FNV-1a, which unlike the standard library's hasher is the same in every build, as instances of different versions
+// have to agree. Keys only differing in their last bytes hash alike, so the result is mixed with MurmurHash3's
+// finalizer before scores are compared.
+fn hash(text: &str) -> u64 {
+    let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+impl Shard {
+    pub fn new(instance: &str, members: Vec<String>) -> Shard {
+        Shard {
+            instance: instance.to_owned(),
+            members: members
+        }
+    }
+
+    // Rendezvous hashing: the member scoring highest for the key owns it, so that only the keys of an instance that
+    // joins or leaves change hands
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        self.members.iter().max_by_key(|member| (hash(&format!("{}/{}", member, key)), member.to_owned()))
+            .map(String::as_str)
+    }
+
+    pub fn owns(&self, key: &str) -> bool {
+        self.owner(key) == Some(self.instance.as_str())
+    }
+
+    // Pull requests are told apart across repositories by their URL
+    pub fn owns_pr(&self, pr: &::PullRequest) -> bool {
+        self.owns(&pr.web_url)
+    }
+}
+
+fn last_seen(path: &Path) -> Option<i64> {
+    let mut text = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        Ok(_) => text.trim().parse::<i64>().ok(),
+        Err(_) => None
+    }
+}
+
+// Records that this instance is alive and finds the others that are
+pub fn announce(config: &ShardConfig, now: i64) -> Result<Shard, ConfigError> {
+    let directory = Path::new(&config.directory);
+    let written = fs::create_dir_all(directory)
+        .and_then(|_| File::create(directory.join(&config.instance)))
+        .and_then(|mut file| write!(file, "{}", now));
+    if let Err(err) = written {
+        return Err(ConfigError::Write(err));
+    }
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) => return Err(ConfigError::Read(err))
+    };
+    let expiry = config.expiry.unwrap_or(60);
+    let mut members = entries.filter_map(|entry| entry.ok())
+        .filter(|entry| last_seen(&entry.path()).map_or(false, |seen| now - seen <= expiry))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    members.sort();
+    Ok(Shard::new(&config.instance, members))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use super::{announce, Shard, ShardConfig};
+
+    fn members(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn every_key_has_exactly_one_owner() {
+        let shards = ["a", "b", "c"].iter().map(|name| Shard::new(name, members(&["a", "b", "c"]))).collect::<Vec<_>>();
+        let mut owned = vec![0; 3];
+        for pr in 0..300 {
+            let key = format!("http://www.foobar.com/pr/{}", pr);
+            let owners = shards.iter().enumerate().filter(|&(_, shard)| shard.owns(&key)).map(|(index, _)| index)
+                .collect::<Vec<_>>();
+            assert_eq!(1, owners.len());
+            owned[owners[0]] += 1;
+        }
+        assert!(owned.iter().all(|&count| count > 50), "Unbalanced shards: {:?}", owned);
+    }
+
+    #[test]
+    fn only_the_keys_of_a_leaving_instance_move() {
+        let before = Shard::new("a", members(&["a", "b", "c"]));
+        let after = Shard::new("a", members(&["a", "b"]));
+        for pr in 0..300 {
+            let key = pr.to_string();
+            if before.owner(&key) != Some("c") {
+                assert_eq!(before.owner(&key), after.owner(&key));
+            }
+        }
+        assert_eq!(None, Shard::new("a", vec![]).owner("1"));
+    }
+
+    #[test]
+    fn instances_that_stop_announcing_themselves_expire() {
+        let directory = env::temp_dir().join("pr_demon_shard_test");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        write!(fs::File::create(directory.join("b")).unwrap(), "{}", 950).unwrap();
+        write!(fs::File::create(directory.join("c")).unwrap(), "{}", 900).unwrap();
+
+        let config = ShardConfig {
+            instance: "a".to_owned(),
+            directory: directory.to_string_lossy().into_owned(),
+            expiry: Some(60)
+        };
+        assert_eq!(Shard::new("a", members(&["a", "b"])), announce(&config, 1000).unwrap());
+    }
+}
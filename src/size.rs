@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ::i18n::Messages;
+use ::templates::CommentKey;
+
+// The most changed lines of an XS, S, M and L pull request
+static DEFAULT_LIMITS: [usize; 4] = [10, 100, 500, 1000];
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+pub enum SizeClass {
+    #[serde(rename = "XS")]
+    ExtraSmall,
+    #[serde(rename = "S")]
+    Small,
+    #[serde(rename = "M")]
+    Medium,
+    #[serde(rename = "L")]
+    Large,
+    #[serde(rename = "XL")]
+    ExtraLarge
+}
+
+impl SizeClass {
+    pub fn label(&self) -> &'static str {
+        match *self {
+            SizeClass::ExtraSmall => "XS",
+            SizeClass::Small => "S",
+            SizeClass::Medium => "M",
+            SizeClass::Large => "L",
+            SizeClass::ExtraLarge => "XL"
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Size {
+    pub class: SizeClass,
+    pub files: usize,
+    pub insertions: usize,
+    pub deletions: usize
+}
+
+impl Size {
+    pub fn lines(&self) -> usize {
+        self.insertions + self.deletions
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct SizeConfig {
+    // The most changed lines of an XS, S, M and L pull request. Anything larger is XL.
+    pub limits: Option<Vec<usize>>,
+    // Pull requests of this size or larger are asked to be split in a comment
+    pub warn: Option<SizeClass>
+}
+
+impl SizeConfig {
+    pub fn classify(&self, files: usize, insertions: usize, deletions: usize) -> Size {
+        let limits = self.limits.as_ref().map_or(&DEFAULT_LIMITS[..], Vec::as_slice);
+        let classes = [SizeClass::ExtraSmall, SizeClass::Small, SizeClass::Medium, SizeClass::Large];
+        let class = classes.iter().zip(limits.iter())
+            .find(|&(_, &limit)| insertions + deletions <= limit)
+            .map_or(SizeClass::ExtraLarge, |(&class, _)| class);
+        Size {
+            class: class,
+            files: files,
+            insertions: insertions,
+            deletions: deletions
+        }
+    }
+}
+
+lazy_static! {
+    // Sizes by pull request and commit, so that each commit is measured and warned about once
+    static ref SIZES: Mutex<HashMap<(i32, String), Size>> = Mutex::new(HashMap::new());
+}
+
+// Counts the files, added lines and removed lines of a unified diff
+pub fn count_diff(diff: &str) -> (usize, usize, usize) {
+    let (mut files, mut insertions, mut deletions) = (0, 0, 0);
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            files += 1;
+            in_hunk = false;
+        } else if line.starts_with("@@") {
+            in_hunk = true;
+        } else if in_hunk && line.starts_with('+') {
+            insertions += 1;
+        } else if in_hunk && line.starts_with('-') {
+            deletions += 1;
+        }
+    }
+    (files, insertions, deletions)
+}
+
+// The paths of the files a unified diff changes, as they are after the change
+pub fn changed_files(diff: &str) -> Vec<String> {
+    diff.lines()
+        .filter(|line| line.starts_with("diff --git "))
+        .filter_map(|line| line.rfind(" b/").map(|start| line[start + 3..].to_owned()))
+        .collect()
+}
+
+// The files the pull request changes and its added and removed lines, counted with the local clone when one is
+// configured and from the repository's diff otherwise
+#[cfg(feature = "git")]
+pub fn changes(pr: &::PullRequest, repository: &::Repository, config: &::Config)
+        -> Result<(Vec<String>, usize, usize), String> {
+    let git = match config.git {
+        Some(ref git) => git,
+        None => return diff_changes(pr, repository)
+    };
+    let clone = match ::git::LocalClone::open(git) {
+        Ok(clone) => clone,
+        Err(err) => return Err(err.to_string())
+    };
+    let changes = clone.fetch_pull_request(pr).and_then(|_| {
+        clone.changes(&format!("refs/pr_demon/{}/to", pr.id), &format!("refs/pr_demon/{}/from", pr.id))
+    });
+    match changes {
+        Ok(changes) => Ok((changes.files, changes.insertions, changes.deletions)),
+        Err(err) => Err(err.to_string())
+    }
+}
+
+#[cfg(not(feature = "git"))]
+pub fn changes(pr: &::PullRequest, repository: &::Repository, _: &::Config)
+        -> Result<(Vec<String>, usize, usize), String> {
+    diff_changes(pr, repository)
+}
+
+fn diff_changes(pr: &::PullRequest, repository: &::Repository) -> Result<(Vec<String>, usize, usize), String> {
+    match repository.get_pr_diff(pr) {
+        Ok(diff) => {
+            let (_, insertions, deletions) = count_diff(&diff);
+            Ok((changed_files(&diff), insertions, deletions))
+        },
+        Err(err) => Err(err.to_string())
+    }
+}
+
+// The size `pr` was measured at for its latest commit, if it was, without measuring or commenting on it
+pub fn known(pr: &::PullRequest) -> Option<Size> {
+    SIZES.lock().expect("Size cache should not be poisoned").get(&(pr.id, pr.from_commit.to_owned())).cloned()
+}
+
+// Returns `pr` with its size, measured with the local clone when one is configured and with the repository's diff
+// otherwise. Pull requests at least as large as `warn` get a comment asking for them to be split.
+pub fn measure(pr: &::PullRequest, repository: &::Repository, config: &::Config, size_config: &SizeConfig,
+        messages: &Messages) -> ::PullRequest {
+    if let Some(size) = known(pr) {
+        return ::PullRequest { size: Some(size), ..pr.to_owned() };
+    }
+
+    let size = match changes(pr, repository, config) {
+        Ok((files, insertions, deletions)) => size_config.classify(files.len(), insertions, deletions),
+        Err(err) => {
+            println!("Unable to measure Pull Request #{}: {}", pr.id, err);
+            return pr.to_owned();
+        }
+    };
+    println!("Pull Request #{} is {}: {} lines in {} files", pr.id, size.class.label(), size.lines(), size.files);
+
+    match size_config.warn {
+        Some(warn) if size.class >= warn => {
+            let text = messages.format("size.warning", &[
+                ("marker", messages.labels().warning()),
+                ("class", size.class.label()),
+                ("lines", &size.lines().to_string()),
+                ("files", &size.files.to_string())
+            ]);
+            if let Err(err) = repository.upsert_comment(pr, &CommentKey::notice("size", pr), &text) {
+                println!("Unable to post size warning: {}", err);
+            }
+        },
+        _ => {}
+    }
+
+    let key = (pr.id, pr.from_commit.to_owned());
+    SIZES.lock().expect("Size cache should not be poisoned").insert(key, size.to_owned());
+    ::PullRequest { size: Some(size), ..pr.to_owned() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use i18n::Messages;
+    use stubs::{pull_request, StubRepository};
+    use super::{changed_files, count_diff, known, measure, SizeClass, SizeConfig};
+
+    static DIFF: &'static str = "diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ extern crate hyper;
+--- a removed line that looks like a header
++++ an added line that looks like a header
++another added line
+diff --git a/README.md b/README.md
+new file mode 100644
+--- /dev/null
++++ b/README.md
+@@ -0,0 +1 @@
++# pr_demon
+";
+
+    #[test]
+    fn diffs_are_counted_by_hunk() {
+        assert_eq!((2, 3, 1), count_diff(DIFF));
+        assert_eq!(vec!["src/lib.rs".to_owned(), "README.md".to_owned()], changed_files(DIFF));
+    }
+
+    #[test]
+    fn sizes_are_classified_by_changed_lines() {
+        let config = SizeConfig { limits: None, warn: None };
+        assert_eq!(SizeClass::ExtraSmall, config.classify(1, 5, 5).class);
+        assert_eq!(SizeClass::Small, config.classify(1, 6, 5).class);
+        assert_eq!(SizeClass::ExtraLarge, config.classify(40, 2000, 0).class);
+
+        let config = SizeConfig { limits: Some(vec![1, 2]), warn: None };
+        assert_eq!(SizeClass::Small, config.classify(1, 2, 0).class);
+        assert_eq!(SizeClass::ExtraLarge, config.classify(1, 3, 0).class);
+    }
+
+    #[test]
+    fn large_pull_requests_are_warned_about_once() {
+        let repository = repository();
+        let config = ::parse_config(r#"{ "run_interval": 0 }"#).unwrap();
+        let size_config = SizeConfig { limits: Some(vec![1, 2, 3, 4]), warn: Some(SizeClass::Large) };
+        let messages = Messages::new(&None, &None);
+        let pr = ::PullRequest { id: 474, ..pull_request() };
+
+        let measured = measure(&pr, &repository, &config, &size_config, &messages);
+        assert_eq!(Some(SizeClass::Large), measured.size.map(|size| size.class));
+        measure(&pr, &repository, &config, &size_config, &messages);
+        let comments = repository.comments.lock().unwrap();
+        assert_eq!(1, comments.len());
+        assert!(comments[0].starts_with("⚠️ This pull request changes 4 lines in 2 files, which makes it L."));
+    }
+
+    #[test]
+    fn known_sizes_are_looked_up_without_measuring() {
+        let repository = repository();
+        let config = ::parse_config(r#"{ "run_interval": 0 }"#).unwrap();
+        let size_config = SizeConfig { limits: None, warn: Some(SizeClass::ExtraSmall) };
+        let messages = Messages::new(&None, &None);
+        let pr = ::PullRequest { id: 529, ..pull_request() };
+
+        assert_eq!(None, known(&pr));
+        assert!(repository.comments.lock().unwrap().is_empty());
+        measure(&pr, &repository, &config, &size_config, &messages);
+        assert_eq!(Some(4), known(&pr).map(|size| size.lines()));
+        assert_eq!(None, known(&::PullRequest { from_commit: "fedcba".to_owned(), ..pr }));
+    }
+
+    fn repository() -> StubRepository {
+        StubRepository {
+            pr_list: Ok(vec![]),
+            queued: Ok(()),
+            running: Ok(()),
+            success: Ok(()),
+            failure: Ok(()),
+            matrix: Ok(()),
+            diff: Ok(DIFF.to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        }
+    }
+
+    #[test]
+    fn classes_are_written_as_labels() {
+        assert_eq!("\"XL\"", ::serde_json::to_string(&SizeClass::ExtraLarge).unwrap());
+        assert!(SizeClass::Large < SizeClass::ExtraLarge);
+    }
+}
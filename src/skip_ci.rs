@@ -0,0 +1,95 @@
+use ::errors::Error;
+use ::i18n::Messages;
+use ::pipeline::Outcome;
+use ::templates::CommentKey;
+
+static DEFAULT_MARKERS: &'static [&'static str] = &["[skip ci]", "[ci skip]"];
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[derive(Default)]
+pub struct SkipCiConfig {
+    // Markers that keep a pull request from being built, `[skip ci]` and `[ci skip]` unless set. None if empty.
+    pub markers: Option<Vec<String>>,
+    // Whether the message of the pull request's latest commit is looked at as well, as it is unless set to false
+    pub commits: Option<bool>
+}
+
+
+impl SkipCiConfig {
+    // The first marker `text` contains, ignoring case
+    pub fn find(&self, text: &str) -> Option<String> {
+        let text = text.to_lowercase();
+        let defaults: Vec<String> = DEFAULT_MARKERS.iter().map(|&marker| marker.to_owned()).collect();
+        self.markers.as_ref().unwrap_or(&defaults).iter()
+            .find(|marker| !marker.is_empty() && text.contains(&marker.to_lowercase()))
+            .cloned()
+    }
+}
+
+// The marker in the pull request's title, description or latest commit message, if any
+pub fn marker(pr: &::PullRequest, repository: &::Repository, config: Option<&SkipCiConfig>) -> Option<String> {
+    let default = SkipCiConfig::default();
+    let config = config.unwrap_or(&default);
+    if let Some(marker) = config.find(&format!("{}\n{}", pr.title, pr.description)) {
+        return Some(marker);
+    }
+    if config.commits == Some(false) {
+        return None;
+    }
+    match repository.get_pr_commits(pr) {
+        Ok(commits) => commits.iter()
+            .find(|commit| commit.id == pr.from_commit)
+            .and_then(|commit| config.find(&commit.message)),
+        Err(_) => None
+    }
+}
+
+// Comments that the pull request's commit is not built, once per commit
+pub fn skip(pr: &::PullRequest, repository: &::Repository, marker: &str, messages: &Messages)
+        -> Result<Outcome, Error> {
+    let text = messages.format("skip_ci.skipped", &[("commit", &pr.from_commit), ("skip", marker)]);
+    match repository.upsert_comment(pr, &CommentKey::check("skip_ci", pr), &text) {
+        Ok(_) => Ok(Outcome::Skip(format!("{} found", marker))),
+        Err(err) => Err(Error::from(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use stubs::{StubRepository, pull_request};
+    use super::{marker, SkipCiConfig};
+
+    fn repository(message: &str) -> StubRepository {
+        let pr = pull_request();
+        StubRepository {
+            pr_list: Ok(vec![]),
+            queued: Ok(()),
+            running: Ok(()),
+            success: Ok(()),
+            failure: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![::Commit {
+                id: pr.from_commit.to_owned(),
+                message: message.to_owned(),
+                merge: false,
+                author: None
+            }]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        }
+    }
+
+    #[test]
+    fn markers_are_found_in_the_title_description_or_latest_commit() {
+        let pr = ::PullRequest { title: "Fix typo [CI skip]".to_owned(), ..pull_request() };
+        assert_eq!(Some("[ci skip]".to_owned()), marker(&pr, &repository("Fix typo"), None));
+        assert_eq!(Some("[skip ci]".to_owned()), marker(&pull_request(), &repository("Docs\n\n[skip ci]"), None));
+        assert_eq!(None, marker(&pull_request(), &repository("Fix typo"), None));
+        let config = SkipCiConfig { markers: None, commits: Some(false) };
+        assert_eq!(None, marker(&pull_request(), &repository("Docs [skip ci]"), Some(&config)));
+        let config = SkipCiConfig { markers: Some(vec!["#no-build".to_owned()]), commits: None };
+        assert_eq!(None, marker(&pr, &repository("Fix typo"), Some(&config)));
+    }
+}
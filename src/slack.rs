@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+use serde_json;
+use time;
+
+use ::errors::ConfigError;
+use ::events::{CommentEvent, MatrixCommentEvent};
+use ::fanout::{Message, OpCode};
+use ::i18n::Messages;
+use ::registry;
+use ::rest;
+
+static EVENTS: [&'static str; 3] = ["opened", "failed", "succeeded"];
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct SlackConfig {
+    // An incoming webhook, which posts to the channel it was created for unless given another
+    pub webhook_url: String,
+    pub channel: Option<String>,
+    // The events posted, of "opened", "failed" and "succeeded". All of them unless set.
+    pub events: Option<Vec<String>>,
+    // Channels some events are posted to in place of `channel`, by event
+    pub routes: Option<BTreeMap<String, String>>,
+    pub username: Option<String>
+}
+
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct SlackMessage {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>
+}
+
+pub struct SlackFactory;
+
+impl registry::NotifierFactory for SlackFactory {
+    fn start(&self, settings: &serde_json::Value, subscriber: Receiver<Message>, messages: &Messages)
+            -> Result<(), ConfigError> {
+        let config = match serde_json::from_value::<SlackConfig>(settings.to_owned()) {
+            Ok(config) => config,
+            Err(err) => return Err(ConfigError::Parse(err))
+        };
+        let events = config.events.iter().flat_map(|events| events.iter())
+            .chain(config.routes.iter().flat_map(|routes| routes.keys()));
+        for event in events {
+            if !EVENTS.contains(&event.as_str()) {
+                return Err(ConfigError::Invalid(format!("Unknown Slack event {}", event)));
+            }
+        }
+
+        let mut notifier = Notifier::new(&config, messages, time::now_utc().to_timespec().sec * 1000);
+        thread::spawn(move || {
+            for message in subscriber.iter() {
+                if let Some(slack_message) = notifier.notify(&message) {
+                    notifier.send(&slack_message);
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+// Slack takes these characters as the start of links and entities
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Turns broadcasts into Slack messages, each pull request and build outcome once
+pub struct Notifier {
+    config: SlackConfig,
+    messages: Messages,
+    // Milliseconds since the epoch, like the times pull requests are updated at
+    started: i64,
+    posted: HashSet<String>
+}
+
+impl Notifier {
+    pub fn new(config: &SlackConfig, messages: &Messages, started: i64) -> Notifier {
+        Notifier {
+            config: config.to_owned(),
+            messages: messages.to_owned(),
+            started: started,
+            posted: HashSet::new()
+        }
+    }
+
+    // Pull requests are seen again on every poll, and only those updated since the daemon started count as opened
+    fn opened(&mut self, repository: &str, pr: &::PullRequest) -> Option<(&'static str, String)> {
+        let recent = pr.updated.map_or(false, |updated| updated >= self.started);
+        if !self.posted.insert(format!("{}/{}", repository, pr.id)) || !recent {
+            return None;
+        }
+        Some(("opened", self.messages.format("slack.opened", &[
+            ("url", &pr.web_url),
+            ("id", &pr.id.to_string()),
+            ("author", &escape(&pr.author.name)),
+            ("title", &escape(&pr.title))
+        ])))
+    }
+
+    fn finished(&mut self, repository: &str, pr: &::PullRequest, builds: &[::BuildDetails])
+            -> Option<(&'static str, String)> {
+        if builds.is_empty() || builds.iter().any(|build| build.state != ::BuildState::Finished) {
+            return None;
+        }
+        let failed = builds.iter().find(|build| build.status == ::BuildStatus::Failure);
+        let builders = builds.iter().map(|build| build.build_id.as_str()).collect::<Vec<_>>().join(",");
+        let key = format!("{}/{}/{}/{}/{}", repository, pr.id, pr.from_commit, builders, failed.is_some());
+        if !self.posted.insert(key) {
+            return None;
+        }
+        match failed {
+            Some(build) => Some(("failed", self.messages.format("slack.failed", &[
+                ("marker", self.messages.labels().failure()),
+                ("url", &pr.web_url),
+                ("id", &pr.id.to_string()),
+                ("failed", self.messages.labels().failure_text()),
+                ("title", &escape(&pr.title)),
+                ("status", &escape(build.status_text.as_ref().unwrap_or(&build.build_id))),
+                ("build_url", &build.web_url)
+            ]))),
+            None => Some(("succeeded", self.messages.format("slack.succeeded", &[
+                ("marker", self.messages.labels().success()),
+                ("url", &pr.web_url),
+                ("id", &pr.id.to_string()),
+                ("title", &escape(&pr.title))
+            ])))
+        }
+    }
+
+    // The Slack message for a broadcast, if it is about an event to post
+    pub fn notify(&mut self, message: &Message) -> Option<SlackMessage> {
+        let repository = message.repository.to_owned().unwrap_or_default();
+        let event = match message.opcode {
+            OpCode::OpenPullRequest => match message.payload::<::PullRequest>() {
+                Ok(pr) => self.opened(&repository, &pr),
+                Err(_) => None
+            },
+            OpCode::Custom { ref payload } if payload.ends_with("::Comment::Post")
+                    || payload.ends_with("::Comment::Update") => match message.payload::<CommentEvent>() {
+                Ok(event) => self.finished(&repository, &event.pr, &[event.build]),
+                Err(_) => None
+            },
+            OpCode::Custom { ref payload } if payload.ends_with("::MatrixComment::Post")
+                    || payload.ends_with("::MatrixComment::Update") => match message.payload::<MatrixCommentEvent>() {
+                Ok(event) => self.finished(&repository, &event.pr, &event.builds),
+                Err(_) => None
+            },
+            _ => None
+        };
+        let (event, text) = match event {
+            Some((event, text)) => (event, text),
+            None => return None
+        };
+        if !self.config.events.as_ref().map_or(true, |events| events.iter().any(|wanted| wanted == event)) {
+            return None;
+        }
+        Some(SlackMessage {
+            text: match message.repository {
+                Some(ref repository) => format!("[{}] {}", escape(repository), text),
+                None => text
+            },
+            channel: self.config.routes.as_ref().and_then(|routes| routes.get(event)).or(self.config.channel.as_ref())
+                .map(String::to_owned),
+            username: self.config.username.to_owned()
+        })
+    }
+
+    fn send(&self, message: &SlackMessage) {
+        let body = serde_json::to_string(message).expect("Slack messages should be serializable");
+        let mut headers = rest::Headers::new();
+        headers.add_content_type_json_header();
+        match rest::post_raw(&self.config.webhook_url, &body, &headers.headers) {
+            Ok(ref response) if response.status.is_success() => {},
+            Ok(response) => println!("Error posting to Slack: {} {}", response.status, response.body),
+            Err(err) => println!("Error posting to Slack: {}", err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use events::CommentEvent;
+    use fanout::{Message, OpCode};
+    use i18n::Messages;
+    use stubs::pull_request;
+    use super::{escape, Notifier, SlackConfig};
+
+    fn config() -> SlackConfig {
+        let mut routes = BTreeMap::new();
+        routes.insert("failed".to_owned(), "#build-failures".to_owned());
+        SlackConfig {
+            webhook_url: "https://hooks.slack.com/services/T0/B0/X".to_owned(),
+            channel: Some("#pull-requests".to_owned()),
+            events: None,
+            routes: Some(routes),
+            username: None
+        }
+    }
+
+    fn finished(status: ::BuildStatus) -> Message {
+        let build = ::BuildDetails {
+            id: 1,
+            build_id: "Build".to_owned(),
+            web_url: "http://www.goodbuilds.com/1".to_owned(),
+            commit: Some(pull_request().from_commit),
+            state: ::BuildState::Finished,
+            status: status,
+            status_text: Some("Tests failed: 1 <flaky>".to_owned()),
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        };
+        let event = CommentEvent { pr: pull_request(), build: build, comment: None };
+        Message::new(OpCode::Custom { payload: "Bitbucket::Comment::Update".to_owned() }, &event)
+    }
+
+    #[test]
+    fn slack_entities_are_escaped() {
+        assert_eq!("Fix &lt;b&gt; &amp; &lt;i&gt;", escape("Fix <b> & <i>"));
+    }
+
+    #[test]
+    fn pull_requests_updated_since_the_start_are_announced_once() {
+        let mut notifier = Notifier::new(&config(), &Messages::new(&None, &None), 1000);
+        let old = ::PullRequest { id: 1, updated: Some(999), ..pull_request() };
+        assert_eq!(None, notifier.notify(&Message::new(OpCode::OpenPullRequest, &old)));
+        let new = ::PullRequest { id: 2, updated: Some(1500), ..pull_request() };
+        let message = notifier.notify(&Message::new(OpCode::OpenPullRequest, &new)).unwrap();
+        assert_eq!(Some("#pull-requests".to_owned()), message.channel);
+        assert!(message.text.contains("<http://www.foobar.com/pr/111|#2>"), "{}", message.text);
+        assert_eq!(None, notifier.notify(&Message::new(OpCode::OpenPullRequest, &new)));
+    }
+
+    #[test]
+    fn build_outcomes_are_routed_by_event() {
+        let mut notifier = Notifier::new(&config(), &Messages::new(&None, &None), 1000);
+        let failed = notifier.notify(&finished(::BuildStatus::Failure)).unwrap();
+        assert_eq!(Some("#build-failures".to_owned()), failed.channel);
+        assert!(failed.text.contains("Tests failed: 1 &lt;flaky&gt;"), "{}", failed.text);
+        assert_eq!(None, notifier.notify(&finished(::BuildStatus::Failure)));
+        let succeeded = notifier.notify(&finished(::BuildStatus::Success)).unwrap();
+        assert_eq!(Some("#pull-requests".to_owned()), succeeded.channel);
+    }
+
+    #[test]
+    fn only_chosen_events_are_posted() {
+        let config = SlackConfig { events: Some(vec!["failed".to_owned()]), ..config() };
+        let mut notifier = Notifier::new(&config, &Messages::new(&None, &None), 1000);
+        assert_eq!(None, notifier.notify(&finished(::BuildStatus::Success)));
+        assert!(notifier.notify(&finished(::BuildStatus::Failure)).is_some());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use serde_json;
+use time;
+
+use ::errors::{ConfigError, Error};
+use ::i18n::Messages;
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+use ::templates::CommentKey;
+
+static DAY: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct StaleConfig {
+    // Days without activity before a reminder is posted, and before the pull request is declined. Stale pull requests
+    // are never declined unless `close_after` is set, and declined without a reminder unless `remind_after` is.
+    pub remind_after: Option<i64>,
+    pub close_after: Option<i64>,
+    // Markers in the title or description, such as "#keep-open", that exempt the pull request
+    pub exempt: Option<Vec<String>>
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Activity {
+    Active,
+    Stale,
+    Abandoned
+}
+
+lazy_static! {
+    // The last update of each pull request a reminder has been posted for, by watched repository and ID
+    static ref REMINDED: Mutex<HashMap<(Option<String>, i32), i64>> = Mutex::new(HashMap::new());
+}
+
+impl StaleConfig {
+    pub fn is_exempt(&self, pr: &::PullRequest) -> bool {
+        let text = format!("{}\n{}", pr.title, pr.description).to_lowercase();
+        self.exempt.as_ref().map_or(false, |exempt| exempt.iter().any(|marker| text.contains(&marker.to_lowercase())))
+    }
+
+    // How long the pull request has gone without activity at `now`, both in milliseconds since the epoch
+    pub fn activity(&self, pr: &::PullRequest, now: i64) -> Activity {
+        let updated = match pr.updated {
+            Some(updated) if !self.is_exempt(pr) => updated,
+            _ => return Activity::Active
+        };
+        let inactive = now - updated;
+        match self.close_after {
+            Some(close_after) if inactive >= close_after * DAY => Activity::Abandoned,
+            _ => match self.remind_after {
+                Some(remind_after) if inactive >= remind_after * DAY => Activity::Stale,
+                _ => Activity::Active
+            }
+        }
+    }
+}
+
+// Tells how to keep the pull request open, if markers exempt it
+fn with_exempt_hint(config: &StaleConfig, text: String, messages: &Messages) -> String {
+    match config.exempt.as_ref().and_then(|exempt| exempt.first()) {
+        Some(marker) => {
+            format!("{} {}", text, messages.format("stale.exempt", &[("exempt", &format!("`{}`", marker))]))
+        },
+        None => text
+    }
+}
+
+impl Stage for StaleConfig {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let now = time::now_utc().to_timespec().sec * 1000;
+        let labels = context.messages.labels();
+        let key = CommentKey::notice("stale", context.pr);
+        match self.activity(context.pr, now) {
+            Activity::Active => Ok(Outcome::Continue),
+            Activity::Stale => {
+                let updated = context.pr.updated.unwrap_or(now);
+                let mut reminded = REMINDED.lock().expect("Reminders should not be poisoned");
+                let reminder = (context.fanout.tag().map(str::to_owned), context.pr.id);
+                if reminded.get(&reminder) == Some(&updated) {
+                    return Ok(Outcome::Continue);
+                }
+                let mut text = context.messages.format("stale.reminder", &[
+                    ("marker", labels.warning()),
+                    ("days", &self.remind_after.unwrap_or_default().to_string())
+                ]);
+                if let Some(close_after) = self.close_after {
+                    let close = context.messages.format("stale.reminder.close", &[("days", &close_after.to_string())]);
+                    text = with_exempt_hint(self, format!("{} {}", text, close), context.messages);
+                }
+                if let Err(err) = context.repository.upsert_comment(context.pr, &key, &text) {
+                    return Err(Error::from(err));
+                }
+                reminded.insert(reminder, updated);
+                Ok(Outcome::Continue)
+            },
+            Activity::Abandoned => {
+                let days = self.close_after.unwrap_or_default().to_string();
+                let text = context.messages.format("stale.closed", &[("marker", labels.failure()), ("days", &days)]);
+                let text = with_exempt_hint(self, text, context.messages);
+                let declined = context.repository.upsert_comment(context.pr, &key, &text)
+                    .and_then(|_| context.repository.decline_pr(context.pr));
+                match declined {
+                    Ok(_) => Ok(Outcome::Skip(format!("Declined after {} days without activity", days))),
+                    Err(err) => Err(Error::from(err))
+                }
+            }
+        }
+    }
+}
+
+pub struct StaleFactory;
+
+impl registry::StageFactory for StaleFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        let config = match pipeline::parse_settings::<StaleConfig>(settings) {
+            Ok(config) => config,
+            Err(err) => return Err(err)
+        };
+        match (config.remind_after, config.close_after) {
+            (None, None) => Err(ConfigError::Invalid("The stale stage needs remind_after or close_after".to_owned())),
+            (Some(remind_after), Some(close_after)) if close_after <= remind_after => {
+                Err(ConfigError::Invalid("close_after has to be longer than remind_after".to_owned()))
+            },
+            _ => Ok(Box::new(config))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use fanout::{Fanout, Message};
+    use i18n::Messages;
+    use pipeline::{Context, Stage};
+    use stubs::{pull_request, StubRepository};
+    use registry::StageFactory;
+    use super::{Activity, StaleConfig, StaleFactory, DAY};
+
+    fn config() -> StaleConfig {
+        StaleConfig {
+            remind_after: Some(14),
+            close_after: Some(30),
+            exempt: Some(vec!["#keep-open".to_owned()])
+        }
+    }
+
+    #[test]
+    fn inactive_pull_requests_are_reminded_then_abandoned() {
+        let pr = ::PullRequest { updated: Some(0), ..pull_request() };
+        assert_eq!(Activity::Active, config().activity(&pr, 13 * DAY));
+        assert_eq!(Activity::Stale, config().activity(&pr, 14 * DAY));
+        assert_eq!(Activity::Abandoned, config().activity(&pr, 30 * DAY));
+        assert_eq!(Activity::Stale, StaleConfig { close_after: None, ..config() }.activity(&pr, 300 * DAY));
+    }
+
+    #[test]
+    fn pull_requests_are_declined_without_a_reminder_unless_reminders_are_set() {
+        let pr = ::PullRequest { updated: Some(0), ..pull_request() };
+        let decline_only = StaleConfig { remind_after: None, ..config() };
+        assert_eq!(Activity::Active, decline_only.activity(&pr, 29 * DAY));
+        assert_eq!(Activity::Abandoned, decline_only.activity(&pr, 30 * DAY));
+    }
+
+    #[test]
+    fn stale_stages_need_a_threshold() {
+        let factory = StaleFactory;
+        assert!(factory.create(&json!({ "close_after": 30 })).is_ok());
+        assert!(factory.create(&json!({ "remind_after": 14 })).is_ok());
+        assert!(factory.create(&json!({ "remind_after": 14, "close_after": 7 })).is_err());
+        assert!(factory.create(&json!({ "exempt": ["#keep-open"] })).is_err());
+    }
+
+    #[test]
+    fn exempt_pull_requests_are_always_active() {
+        let pr = ::PullRequest {
+            updated: Some(0),
+            description: "Waiting for upstream #Keep-Open".to_owned(),
+            ..pull_request()
+        };
+        assert_eq!(Activity::Active, config().activity(&pr, 300 * DAY));
+        assert_eq!(Activity::Active, config().activity(&pull_request(), 300 * DAY));
+    }
+
+    #[test]
+    fn watches_sharing_a_pull_request_id_are_each_reminded_once() {
+        let repository = StubRepository {
+            pr_list: Ok(vec![]),
+            queued: Ok(()),
+            running: Ok(()),
+            success: Ok(()),
+            failure: Ok(()),
+            matrix: Ok(()),
+            diff: Ok("".to_owned()),
+            commits: Ok(vec![]),
+            comments: Mutex::new(vec![]),
+            checks: Mutex::new(vec![])
+        };
+        let stale = StaleConfig { close_after: None, ..config() };
+        let config = ::parse_config(r#"{ "run_interval": 0 }"#).unwrap();
+        let messages = Messages::new(&None, &None);
+        let pr = ::PullRequest { id: 506, updated: Some(0), ..pull_request() };
+        let fanout = Fanout::<Message>::new();
+        for watch in &["frontend", "backend", "frontend", "backend"] {
+            let context = Context {
+                pr: &pr,
+                repository: &repository,
+                matrix: &vec![],
+                config: &config,
+                messages: &messages,
+                fanout: &fanout.tagged(watch)
+            };
+            assert!(stale.run(&context).is_ok());
+        }
+        assert_eq!(2, repository.comments.lock().unwrap().len());
+    }
+}
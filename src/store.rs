@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::ops::{Deref, DerefMut};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use ::errors::ConfigError;
+
+// A value kept in a JSON file across restarts, or in memory only without a path
+pub struct JsonStore<T> {
+    path: Option<String>,
+    value: T
+}
+
+impl<T> JsonStore<T> where T: Serialize + DeserializeOwned + Default {
+    // A store without a file yet holds the default value
+    pub fn load(path: &Option<String>) -> Result<JsonStore<T>, ConfigError> {
+        let mut json = String::new();
+        if let Some(ref path) = *path {
+            match File::open(path).and_then(|mut file| file.read_to_string(&mut json)) {
+                Ok(_) => {},
+                Err(ref err) if err.kind() == ErrorKind::NotFound => {},
+                Err(err) => return Err(ConfigError::Read(err))
+            }
+        }
+        let value = match json.is_empty() {
+            true => T::default(),
+            false => match serde_json::from_str::<T>(&json) {
+                Ok(value) => value,
+                Err(err) => return Err(ConfigError::Parse(err))
+            }
+        };
+        Ok(JsonStore {
+            path: path.to_owned(),
+            value: value
+        })
+    }
+
+    // Rewrites the whole file
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(())
+        };
+        let json = serde_json::to_string_pretty(&self.value).expect("Stored values should be serializable");
+        match File::create(path).and_then(|mut file| file.write_all(json.as_bytes())) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(ConfigError::Write(err))
+        }
+    }
+}
+
+impl<T> Deref for JsonStore<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for JsonStore<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use errors::ConfigError;
+    use super::JsonStore;
+
+    #[test]
+    fn values_round_trip_through_the_file() {
+        let path = env::temp_dir().join("pr_demon_json_store").to_string_lossy().into_owned();
+        let _ = fs::remove_file(&path);
+        let mut store = JsonStore::<Vec<String>>::load(&Some(path.to_owned())).unwrap();
+        assert!(store.is_empty());
+        store.push("abc".to_owned());
+        store.save().unwrap();
+        assert_eq!(vec!["abc".to_owned()], *JsonStore::<Vec<String>>::load(&Some(path.to_owned())).unwrap());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn write_failures_are_reported_as_such() {
+        let path = env::temp_dir().join("pr_demon_json_store_missing").join("store.json");
+        let store = JsonStore::<Vec<String>>::load(&Some(path.to_string_lossy().into_owned())).unwrap();
+        match store.save() {
+            Err(ConfigError::Write(_)) => {},
+            other => panic!("Expected a write error, not {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// Stub backends and fixtures shared by the unit tests
+use std::sync::Mutex;
+use ::{Build, BuildDetails, CheckResult, Comment, Commit, ContinuousIntegrator, Coverage, PullRequest, Repository, User};
+use errors::{BuilderError, RepositoryError};
+use templates::CommentKey;
+
+pub struct StubBuild {
+    pub build_list: Result<Vec<Build>, String>,
+    pub build: Result<BuildDetails, String>,
+    pub queued: Result<BuildDetails, String>,
+    pub tagged: Result<(), String>,
+    pub pinned: Result<(), String>,
+    pub coverage: Result<Option<Coverage>, String>
+}
+
+impl ContinuousIntegrator for StubBuild {
+    fn get_build_list(&self, _: &str) -> Result<Vec<Build>, BuilderError> {
+        self.build_list.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn get_build(&self, _: i32) -> Result<BuildDetails, BuilderError> {
+        self.build.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn queue_build(&self, _: &str) -> Result<BuildDetails, BuilderError> {
+        self.queued.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn get_personal_build_list(&self, _: &str) -> Result<Vec<Build>, BuilderError> {
+        self.build_list.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn queue_personal_build(&self, _: &str, _: &str) -> Result<BuildDetails, BuilderError> {
+        self.queued.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn tag_build(&self, _: &BuildDetails, _: &Vec<String>) -> Result<(), BuilderError> {
+        self.tagged.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn pin_build(&self, _: &BuildDetails, _: &str) -> Result<(), BuilderError> {
+        self.pinned.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn comment_build(&self, _: &BuildDetails, _: &str) -> Result<(), BuilderError> {
+        self.tagged.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn cancel_build(&self, _: &BuildDetails, _: &str) -> Result<(), BuilderError> {
+        self.tagged.clone().map_err(BuilderError::InvalidResponse)
+    }
+
+    fn get_coverage(&self, _: &BuildDetails, _: &str) -> Result<Option<Coverage>, BuilderError> {
+        self.coverage.clone().map_err(BuilderError::InvalidResponse)
+    }
+}
+
+pub struct StubRepository {
+    pub pr_list: Result<Vec<PullRequest>, String>,
+    pub queued: Result<(), String>,
+    pub running: Result<(), String>,
+    pub success: Result<(), String>,
+    pub failure: Result<(), String>,
+    pub matrix: Result<(), String>,
+    pub diff: Result<String, String>,
+    pub commits: Result<Vec<Commit>, String>,
+    pub comments: Mutex<Vec<String>>,
+    pub checks: Mutex<Vec<CheckResult>>
+}
+
+impl Repository for StubRepository {
+    fn get_pr_list(&self) -> Result<Vec<PullRequest>, RepositoryError> {
+        self.pr_list.clone().map_err(RepositoryError::Template)
+    }
+
+    fn get_merged_pr_list(&self) -> Result<Vec<PullRequest>, RepositoryError> {
+        self.pr_list.clone().map_err(RepositoryError::Template)
+    }
+
+    fn get_pr_diff(&self, _: &PullRequest) -> Result<String, RepositoryError> {
+        self.diff.clone().map_err(RepositoryError::Template)
+    }
+
+    fn get_pr_commits(&self, _: &PullRequest) -> Result<Vec<Commit>, RepositoryError> {
+        self.commits.clone().map_err(RepositoryError::Template)
+    }
+
+    fn get_pr_comments(&self, _: &PullRequest) -> Result<Vec<Comment>, RepositoryError> {
+        Ok(vec![])
+    }
+
+    fn get_pr_approvals(&self, pr: &PullRequest) -> Result<Vec<String>, RepositoryError> {
+        Ok(pr.approvals.to_owned())
+    }
+
+    fn build_queued(&self, _: &PullRequest, _: &BuildDetails) -> Result<(), RepositoryError> {
+        self.queued.clone().map_err(RepositoryError::Template)
+    }
+
+    fn build_running(&self, _: &PullRequest, _: &BuildDetails) -> Result<(), RepositoryError> {
+        self.running.clone().map_err(RepositoryError::Template)
+    }
+
+    fn build_success(&self, _: &PullRequest, _: &BuildDetails) -> Result<(), RepositoryError> {
+        self.success.clone().map_err(RepositoryError::Template)
+    }
+
+    fn build_failure(&self, _: &PullRequest, _: &BuildDetails) -> Result<(), RepositoryError> {
+        self.failure.clone().map_err(RepositoryError::Template)
+    }
+
+    fn build_matrix(&self, _: &PullRequest, _: &Vec<BuildDetails>) -> Result<(), RepositoryError> {
+        self.matrix.clone().map_err(RepositoryError::Template)
+    }
+
+    fn upsert_comment(&self, _: &PullRequest, _: &CommentKey, text: &str) -> Result<(), RepositoryError> {
+        self.comments.lock().unwrap().push(text.to_owned());
+        Ok(())
+    }
+
+    fn report_check(&self, _: &PullRequest, check: &CheckResult) -> Result<(), RepositoryError> {
+        self.checks.lock().unwrap().push(check.to_owned());
+        Ok(())
+    }
+
+    fn create_pull_request(&self, from_ref: &str, to_ref: &str, title: &str, description: &str)
+            -> Result<PullRequest, RepositoryError> {
+        Ok(PullRequest {
+            from_ref: from_ref.to_owned(),
+            to_ref: to_ref.to_owned(),
+            title: title.to_owned(),
+            description: description.to_owned(),
+            ..pull_request()
+        })
+    }
+
+    fn decline_pr(&self, _: &PullRequest) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    fn add_reviewers(&self, _: &PullRequest, _: &[String]) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    fn create_branch(&self, _: &str, _: &str) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    fn merge_pr(&self, _: &PullRequest, _: Option<&str>, _: Option<&str>) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+}
+
+pub fn pull_request() -> PullRequest {
+    PullRequest {
+        id: 111,
+        web_url: "http://www.foobar.com/pr/111".to_owned(),
+        from_ref: "refs/heads/branch_name".to_owned(),
+        from_commit: "363c1dfda4cdf5a01c2d210e49942c8c8e7e898b".to_owned(),
+        to_ref: "refs/heads/master".to_owned(),
+        from_fork: false,
+        title: "A very important PR".to_owned(),
+        description: "".to_owned(),
+        updated: None,
+        author: User {
+            name: "Aaron Xiao Ming".to_owned(),
+            email: "aaron@xiao.ming".to_owned(),
+            username: Some("aaron".to_owned())
+        },
+        reviewers: vec![],
+        approvals: vec![],
+        size: None,
+        open_tasks: None,
+        labels: vec![]
+    }
+}
@@ -1,188 +1,462 @@
+use std::sync::Arc;
+use ::credentials;
+use ::errors::{ApiError, BuilderError, ConfigError};
+use ::registry;
 use ::rest;
 use hyper;
+use serde_json;
+use time;
 use url::percent_encoding::{utf8_percent_encode, QUERY_ENCODE_SET};
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
 pub struct TeamcityCredentials {
-    pub username: String,
-    pub password: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    pub credential_provider: Option<credentials::ProviderConfig>,
     pub base_url: String,
-    pub build_id: String
+    pub build_id: String,
+    pub matrix: Option<Vec<String>>,
+    pub pin_merged: Option<bool>,
+    pub log_lines: Option<usize>,
+    pub coverage_threshold: Option<f64>,
+    pub personal_builds: Option<bool>,
+    pub composite: Option<bool>,
+    // Reports the duration of each step of finished builds
+    pub steps: Option<bool>,
+    // Build statistic values whose key starts with the prefix, such as `benchmark.`, are benchmarks
+    pub benchmark_prefix: Option<String>
 }
 
-impl ::UsernameAndPassword for TeamcityCredentials {
-    fn username(&self) -> &String {
-        &self.username
+pub struct TeamcityFactory;
+
+impl registry::BuilderFactory for TeamcityFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Vec<Box<::ContinuousIntegrator>>, ConfigError> {
+        match serde_json::from_value::<TeamcityCredentials>(settings.to_owned()) {
+            Ok(ref credentials) if credentials.credential().is_none() && credentials.credential_provider.is_none() => {
+                Err(ConfigError::Invalid("TeamCity needs a token, a username and password or a credential_provider"
+                    .to_owned()))
+            },
+            Ok(credentials) => Ok(credentials.build_configurations().into_iter()
+                .map(|build_configuration| Box::new(build_configuration) as Box<::ContinuousIntegrator>)
+                .collect()),
+            Err(err) => Err(ConfigError::Parse(err))
+        }
     }
+}
 
-    fn password(&self) -> &String {
-        &self.password
+impl TeamcityCredentials {
+    pub fn build_configurations(&self) -> Vec<TeamcityCredentials> {
+        let mut build_ids = vec![self.build_id.to_owned()];
+        if let Some(ref matrix) = self.matrix {
+            for build_id in matrix {
+                if !build_ids.contains(build_id) {
+                    build_ids.push(build_id.to_owned());
+                }
+            }
+        }
+
+        build_ids.into_iter().map(|build_id| {
+            TeamcityCredentials {
+                build_id: build_id,
+                matrix: None,
+                ..self.clone()
+            }
+        }).collect()
+    }
+
+    fn get_build_log_tail(&self, build_id: i32, lines: usize) -> Result<String, BuilderError> {
+        // The build log is served by the web UI rather than the REST API
+        let url = format!("{}/downloadBuildLog.html?buildId={}&plain=true", self.server_url(), build_id);
+
+        let response = credentials::authorized(&*self.provider(), |headers| rest::get_raw(&url, &headers.headers));
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => return Err(BuilderError::api("getting build log", err))
+        };
+        if response.status != hyper::StatusCode::OK {
+            return Err(BuilderError::api("getting build log", ApiError::Status(response.status)));
+        }
+
+        let mut tail = response.body.lines().rev().take(lines).collect::<Vec<_>>();
+        tail.reverse();
+        Ok(tail.join("\n"))
+    }
+
+    fn get_composite_parts(&self, build_id: i32) -> Result<Vec<::BuildPart>, BuilderError> {
+        let url = format!("{}/builds?locator=snapshotDependency:(to:(id:{}),includeInitial:false),\
+                           defaultFilter:false,state:any", self.base_url, build_id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<BuildList>(&url, &headers.headers)
+        });
+        match response {
+            Ok(build_list) => {
+                Ok(build_list.build.unwrap_or(vec![]).into_iter().map(|part| {
+                    ::BuildPart {
+                        name: part.build_type_id,
+                        web_url: part.web_url,
+                        state: part.state.to_build_state(),
+                        status: match part.status {
+                            None => ::BuildStatus::Unknown,
+                            Some(status) => status.to_build_status()
+                        }
+                    }
+                }).collect())
+            },
+            Err(err) => Err(BuilderError::api("getting composite build parts", err))
+        }
+    }
+
+    fn get_test_failures(&self, build_id: i32) -> Result<::TestFailures, BuilderError> {
+        let url = format!("{}/testOccurrences?locator=build:(id:{}),status:FAILURE,count:10000\
+                           &fields=count,testOccurrence(name,status,duration,currentlyMuted,currentlyInvestigated,test(id))",
+            self.base_url, build_id);
+        let tests_url = format!("{}/viewLog.html?buildId={}&tab=testsInfo", self.server_url(), build_id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<TestOccurrenceList>(&url, &headers.headers)
+        });
+        match response {
+            Ok(test_occurrences) => {
+                let mut test_failures = ::TestFailures {
+                    muted: 0,
+                    investigated: 0,
+                    new: 0,
+                    web_url: Some(tests_url.to_owned()),
+                    tests: vec![]
+                };
+                for test in test_occurrences.test_occurrence.unwrap_or(vec![]) {
+                    match (test.currently_muted, test.currently_investigated) {
+                        (Some(true), _) => test_failures.muted += 1,
+                        (_, Some(true)) => test_failures.investigated += 1,
+                        _ => test_failures.new += 1
+                    }
+                    test_failures.tests.push(::TestResult {
+                        web_url: test.test.as_ref().map(|t| format!("{}&testNameId={}", tests_url, t.id)),
+                        name: test.name,
+                        status: test.status.unwrap_or("FAILURE".to_owned()),
+                        duration: test.duration
+                    });
+                }
+                Ok(test_failures)
+            },
+            Err(err) => Err(BuilderError::api("getting failed tests", err))
+        }
+    }
+
+    // TeamCity only reports how long each step took, so a failed build is taken to have failed in the last step it ran
+    fn get_steps(&self, build: &::BuildDetails) -> Result<Vec<::BuildStep>, BuilderError> {
+        let url = format!("{}/buildTypes/id:{}/steps", self.base_url, self.build_id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<StepList>(&url, &headers.headers)
+        });
+        let steps = match response {
+            Ok(steps) => steps.step.unwrap_or(vec![]),
+            Err(err) => return Err(BuilderError::api("getting build steps", err))
+        };
+        let statistics = match self.get_statistics(&format!("id:{}", build.id)) {
+            Ok(statistics) => statistics,
+            Err(err) => return Err(err)
+        };
+        let mut steps: Vec<::BuildStep> = steps.into_iter()
+            .filter(|step| step.disabled != Some(true))
+            .map(|step| {
+                let key = format!("buildStageDuration:buildStep{}", step.id);
+                let duration = statistics.iter()
+                    .find(|property| property.name == key)
+                    .and_then(|property| property.value.parse::<i64>().ok());
+                ::BuildStep {
+                    name: step.name,
+                    state: match duration {
+                        Some(_) => ::BuildState::Finished,
+                        None => ::BuildState::Queued
+                    },
+                    status: match duration {
+                        Some(_) => ::BuildStatus::Success,
+                        None => ::BuildStatus::Unknown
+                    },
+                    duration: duration
+                }
+            })
+            .collect();
+        if build.status != ::BuildStatus::Success {
+            if let Some(step) = steps.iter_mut().rev().find(|step| step.state == ::BuildState::Finished) {
+                step.status = build.status.to_owned();
+            }
+        }
+        Ok(steps)
+    }
+
+    fn get_statistics(&self, build_locator: &str) -> Result<Vec<Property>, BuilderError> {
+        let url = format!("{}/builds/{}/statistics", self.base_url, build_locator);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Statistics>(&url, &headers.headers)
+        });
+        match response {
+            Ok(statistics) => Ok(statistics.property.unwrap_or(vec![])),
+            Err(err) => Err(BuilderError::api("getting build statistics", err))
+        }
+    }
+
+    fn get_line_coverage(&self, build_locator: &str) -> Result<Option<f64>, BuilderError> {
+        let properties = match self.get_statistics(build_locator) {
+            Ok(properties) => properties,
+            Err(err) => return Err(err)
+        };
+        match properties.iter().find(|property| property.name == "CodeCoverageL") {
+            Some(property) => match property.value.parse::<f64>() {
+                Ok(percentage) => Ok(Some(percentage)),
+                Err(err) => Err(BuilderError::InvalidResponse(format!("Error parsing coverage {}", err)))
+            },
+            None => Ok(None)
+        }
+    }
+
+    // Statistic values named with the benchmark prefix, without it
+    fn benchmarks(&self, properties: Vec<Property>) -> Result<Vec<::Benchmark>, BuilderError> {
+        let prefix = match self.benchmark_prefix {
+            Some(ref prefix) => prefix,
+            None => return Ok(vec![])
+        };
+        let mut benchmarks = vec![];
+        for property in properties.into_iter().filter(|property| property.name.starts_with(prefix.as_str())) {
+            match property.value.parse::<f64>() {
+                Ok(value) => benchmarks.push(::Benchmark {
+                    name: property.name[prefix.len()..].to_owned(),
+                    value: value,
+                    unit: None
+                }),
+                Err(err) => {
+                    return Err(BuilderError::InvalidResponse(format!("Error parsing {}: {}", property.name, err)))
+                }
+            }
+        }
+        Ok(benchmarks)
+    }
+
+    fn upload_personal_change(&self, diff: &str, description: &str) -> Result<String, BuilderError> {
+        let encoded_description = utf8_percent_encode(description, QUERY_ENCODE_SET).collect::<String>();
+        let url = format!("{}/uploadDiffChanges.html?description={}&commitType=0",
+            self.server_url(), encoded_description);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_content_type_text_header();
+            rest::post_raw(&url, diff, &headers.headers)
+        });
+        match response {
+            Ok(ref response) if response.status != hyper::StatusCode::OK => {
+                Err(BuilderError::api("uploading diff", ApiError::Status(response.status)))
+            },
+            Ok(response) => Ok(response.body.trim().to_owned()),
+            Err(err) => Err(BuilderError::api("uploading diff", err))
+        }
+    }
+
+    fn server_url(&self) -> &str {
+        self.base_url.trim_end_matches('/').trim_end_matches("/app/rest")
+    }
+
+    fn credential(&self) -> Option<credentials::Credential> {
+        match (&self.token, &self.username, &self.password) {
+            (&Some(ref token), _, _) => Some(credentials::Credential::Bearer(token.to_owned())),
+            (&None, &Some(ref username), &Some(ref password)) => {
+                Some(credentials::Credential::Basic { username: username.to_owned(), password: password.to_owned() })
+            },
+            _ => None
+        }
+    }
+
+    fn provider(&self) -> Arc<credentials::CredentialProvider> {
+        credentials::provider_for(&self.credential_provider,
+            self.credential().unwrap_or(credentials::Credential::Anonymous))
     }
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_camel_case_types)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
 pub enum BuildState {
-    queued,
-    finished,
-    running
+    Queued,
+    Finished,
+    Running
 }
 
 impl BuildState {
     fn to_build_state(self) -> ::BuildState {
         match self {
-            BuildState::queued => ::BuildState::Queued,
-            BuildState::finished => ::BuildState::Finished,
-            BuildState::running => ::BuildState::Running
+            BuildState::Queued => ::BuildState::Queued,
+            BuildState::Finished => ::BuildState::Finished,
+            BuildState::Running => ::BuildState::Running
         }
     }
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_camel_case_types)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum BuildStatus {
-    SUCCESS,
-    FAILURE,
-    UNKNOWN
+    Success,
+    Failure,
+    Unknown
 }
 
 impl BuildStatus {
     fn to_build_status(self) -> ::BuildStatus {
         match self {
-            BuildStatus::SUCCESS => ::BuildStatus::Success,
-            BuildStatus::FAILURE => ::BuildStatus::Failure,
-            BuildStatus::UNKNOWN => ::BuildStatus::Unknown
+            BuildStatus::Success => ::BuildStatus::Success,
+            BuildStatus::Failure => ::BuildStatus::Failure,
+            BuildStatus::Unknown => ::BuildStatus::Unknown
         }
     }
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct BuildList {
     pub count: i32,
     pub href: String,
     pub build: Option<Vec<BuildListItem>>
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct BuildListItem {
     pub id: i32,
-    pub buildTypeId: String,
+    pub build_type_id: String,
     pub status: Option<BuildStatus>,
     pub state: BuildState,
     pub running: Option<bool>,
-    pub percentageComplete: Option<i32>,
-    pub branchName: String,
-    pub defaultBranch: Option<bool>,
+    pub percentage_complete: Option<i32>,
+    pub branch_name: String,
+    pub default_branch: Option<bool>,
     pub href: String,
-    pub webUrl: String
+    pub web_url: String
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Build {
     pub id: i32,
-    pub buildTypeId: String,
+    pub build_type_id: String,
     pub status: Option<BuildStatus>,
     pub state: BuildState,
-    pub failedToStart: Option<bool>,
-    pub branchName: String,
-    pub defaultBranch: Option<bool>,
+    pub failed_to_start: Option<bool>,
+    pub branch_name: String,
+    pub default_branch: Option<bool>,
     pub href: String,
-    pub webUrl: String,
-    pub statusText: Option<String>,
-    pub buildType: BuildType,
-    pub queuedDate: String,
-    pub startDate: Option<String>,
-    pub finishDate:  Option<String>,
-    pub lastChanges: Option<LastChanges>,
+    pub web_url: String,
+    pub status_text: Option<String>,
+    pub build_type: BuildType,
+    pub queued_date: String,
+    pub start_date: Option<String>,
+    pub finish_date:  Option<String>,
+    pub last_changes: Option<LastChanges>,
     pub changes: Href,
     pub revisions: Revisions,
     pub agent: Option<Agent>,
-    pub testOccurrences: Option<TestOccurences>,
+    pub test_occurrences: Option<TestOccurences>,
     pub artifacts: Href,
-    pub relatedIssues: Option<Href>,
+    pub related_issues: Option<Href>,
     pub properties: Properties,
     pub statistics: Option<Href>
 }
 
 impl Build {
     fn to_build_details(&self) -> ::BuildDetails {
-        let commit = match self.revisions.revision {
-            None => None,
-            // Should not panic because None would have caught a non-existent vector
-            Some(ref revisions) => Some(revisions.first().unwrap().version.to_owned())
-        };
+        let commit = self.revisions.revision.as_ref().map(|revisions| revisions.first().unwrap().version.to_owned());
         let status = match self.status {
             None => ::BuildStatus::Unknown,
             Some(ref status) => status.clone().to_build_status()
         };
         ::BuildDetails {
             id: self.id,
-            build_id: self.buildTypeId.to_owned(),
-            web_url: self.webUrl.to_owned(),
+            build_id: self.build_type_id.to_owned(),
+            web_url: self.web_url.to_owned(),
             commit: commit,
             state: self.state.clone().to_build_state(),
             status: status,
-            status_text: self.statusText.to_owned()
+            status_text: self.status_text.to_owned(),
+            log_excerpt: None,
+            coverage: None,
+            test_failures: None,
+            queue_wait: self.queue_wait(),
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+    fn queue_wait(&self) -> Option<i64> {
+        if self.state != BuildState::Queued {
+            return None;
+        }
+
+        match time::strptime(&self.queued_date, "%Y%m%dT%H%M%S%z") {
+            Ok(queued_date) => Some((time::now_utc().to_timespec() - queued_date.to_timespec()).num_seconds()),
+            Err(_) => None
         }
     }
 
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct BuildType {
     pub id: String,
     pub name: String,
-    pub projectName: String,
-    pub projectId: String,
+    pub project_name: String,
+    pub project_id: String,
     pub href: String,
-    pub webUrl: String
+    pub web_url: String
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct LastChanges {
     pub count: i32,
     pub change: Vec<Change>
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Change {
     pub id: i32,
     pub version: String,
     pub username: String,
     pub date: String,
     pub href: String,
-    pub webUrl: String
+    pub web_url: String
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct Href {
     pub href: String
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct Revisions {
     pub count: i32,
     pub revision: Option<Vec<Revision>>
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct Revision {
     pub version: String
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
-#[allow(non_snake_case)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Agent {
     pub name: String,
-    pub typeId: i32
+    pub type_id: i32
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct TestOccurences {
     pub count: i32,
     pub href: String,
@@ -191,36 +465,105 @@ pub struct TestOccurences {
     pub default: bool
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct StepList {
+    pub step: Option<Vec<Step>>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Step {
+    pub id: String,
+    pub name: String,
+    pub disabled: Option<bool>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TestOccurrenceList {
+    pub count: i32,
+    pub test_occurrence: Option<Vec<TestOccurrence>>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TestOccurrence {
+    pub name: String,
+    pub status: Option<String>,
+    pub duration: Option<i64>,
+    pub currently_muted: Option<bool>,
+    pub currently_investigated: Option<bool>,
+    pub test: Option<Test>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Test {
+    pub id: String
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Tags {
+    pub tag: Vec<Tag>
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Tag {
+    pub name: String
+}
+
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildCancelRequest {
+    pub comment: String,
+    pub readd_into_queue: bool
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct Properties {
     pub count: i32,
     pub property: Vec<Property>
 }
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct Statistics {
+    pub property: Option<Vec<Property>>
+}
+
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 pub struct Property {
     pub name: String,
     pub value: String
 }
 
 impl ::ContinuousIntegrator for TeamcityCredentials {
-    fn get_build_list(&self, branch: &str) -> Result<Vec<::Build>, String> {
-        let mut headers = rest::Headers::new();
-        headers.add_authorization_header(self as &::UsernameAndPassword)
-            .add_accept_json_header();
+    fn pin_merged(&self) -> bool {
+        self.pin_merged == Some(true)
+    }
+
+    fn personal_builds(&self) -> bool {
+        self.personal_builds == Some(true)
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some(&self.build_id)
+    }
 
+    fn get_build_list(&self, branch: &str) -> Result<Vec<::Build>, BuilderError> {
         let encoded_branch = utf8_percent_encode(branch, QUERY_ENCODE_SET).collect::<String>();
         let query_string = format!("state:any,branch:(name:{})", encoded_branch);
         let url = format!("{}/buildTypes/id:{}/builds?locator={}",
             self.base_url, self.build_id, query_string);
 
-        match rest::get::<BuildList>(&url, &headers.headers) {
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<BuildList>(&url, &headers.headers)
+        });
+        match response {
             Ok(build_list) => {
                 Ok(
                     match build_list.build {
                         None => vec![],
                         Some(ref builds) => {
-                            builds.iter().map(|ref build| {
+                            builds.iter().map(|build| {
                                 ::Build {
                                     id: build.id
                                 }
@@ -229,29 +572,50 @@ impl ::ContinuousIntegrator for TeamcityCredentials {
                     }
                 )
             },
-            Err(err) =>  Err(format!("Error getting list of builds {}", err))
+            Err(err) => Err(BuilderError::api("getting list of builds", err))
         }
     }
 
-    fn get_build(&self, build_id: i32) -> Result<::BuildDetails, String> {
-        let mut headers = rest::Headers::new();
-        headers.add_authorization_header(self as &::UsernameAndPassword)
-            .add_accept_json_header();
-
+    fn get_build(&self, build_id: i32) -> Result<::BuildDetails, BuilderError> {
         let url = format!("{}/builds/id:{}", self.base_url, build_id);
 
-        match rest::get::<Build>(&url, &headers.headers) {
-            Ok(build) => Ok(build.to_build_details()),
-            Err(err) => Err(format!("Error getting build {}", err))
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<Build>(&url, &headers.headers)
+        });
+        match response {
+            Ok(build) => {
+                let mut build_details = build.to_build_details();
+                if let Some(lines) = self.log_lines {
+                    if build_details.state == ::BuildState::Finished
+                            && build_details.status == ::BuildStatus::Failure {
+                        build_details.log_excerpt = self.get_build_log_tail(build.id, lines).ok();
+                    }
+                }
+                if build_details.state == ::BuildState::Finished
+                        && build_details.status == ::BuildStatus::Failure {
+                    build_details.test_failures = self.get_test_failures(build.id).ok();
+                }
+                if self.steps == Some(true) && build_details.state == ::BuildState::Finished {
+                    build_details.steps = self.get_steps(&build_details).unwrap_or(vec![]);
+                }
+                if let Some(true) = self.composite {
+                    build_details.parts = match self.get_composite_parts(build.id) {
+                        Ok(parts) => parts,
+                        Err(err) => return Err(err)
+                    };
+                    // Only report the composite build as finished once every part has finished
+                    if build_details.parts.iter().any(|part| part.state != ::BuildState::Finished) {
+                        build_details.state = ::BuildState::Running;
+                    }
+                }
+                Ok(build_details)
+            },
+            Err(err) => Err(BuilderError::api("getting build", err))
         }
     }
 
-    fn queue_build(&self, branch: &str) -> Result<::BuildDetails, String> {
-        let mut headers = rest::Headers::new();
-        headers.add_authorization_header(self as &::UsernameAndPassword)
-            .add_accept_json_header()
-            .add_content_type_xml_header();
-
+    fn queue_build(&self, branch: &str) -> Result<::BuildDetails, BuilderError> {
         // FIXME: Format a proper template instead!
         let body = format!("<build branchName=\"{}\">
                           <buildType id=\"{}\"/>
@@ -259,9 +623,202 @@ impl ::ContinuousIntegrator for TeamcityCredentials {
                         </build>", branch, self.build_id);
         let url = format!("{}/buildQueue", self.base_url);
 
-        match rest::post::<Build>(&url, &body, &headers.headers, &hyper::status::StatusCode::Ok) {
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_xml_header();
+            rest::post::<Build>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match response {
+            Ok(build) => Ok(build.to_build_details()),
+            Err(err) => Err(BuilderError::api("queuing build", err))
+        }
+    }
+
+    fn get_personal_build_list(&self, commit: &str) -> Result<Vec<::Build>, BuilderError> {
+        let url = format!("{}/builds?locator=buildType:(id:{}),personal:true,state:any,tag:{}",
+            self.base_url, self.build_id, commit);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header();
+            rest::get::<BuildList>(&url, &headers.headers)
+        });
+        match response {
+            Ok(build_list) => {
+                Ok(
+                    match build_list.build {
+                        None => vec![],
+                        Some(ref builds) => {
+                            builds.iter().map(|build| {
+                                ::Build {
+                                    id: build.id
+                                }
+                            }).collect()
+                        }
+                    }
+                )
+            },
+            Err(err) => Err(BuilderError::api("getting list of personal builds", err))
+        }
+    }
+
+    fn queue_personal_build(&self, diff: &str, description: &str) -> Result<::BuildDetails, BuilderError> {
+        let change_id = match self.upload_personal_change(diff, description) {
+            Ok(change_id) => change_id,
+            Err(err) => return Err(err)
+        };
+
+        let body = format!("<build personal=\"true\">
+                          <buildType id=\"{}\"/>
+                          <lastChanges><change id=\"{}\" personal=\"true\"/></lastChanges>
+                          <comment><text>{}</text></comment>
+                        </build>", self.build_id, change_id, escape_xml(description));
+        let url = format!("{}/buildQueue", self.base_url);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_xml_header();
+            rest::post::<Build>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match response {
             Ok(build) => Ok(build.to_build_details()),
-            Err(err) => Err(format!("Error queuing build {}", err))
+            Err(err) => Err(BuilderError::api("queuing personal build", err))
+        }
+    }
+
+    fn tag_build(&self, build: &::BuildDetails, tags: &Vec<String>) -> Result<(), BuilderError> {
+        let body = serde_json::to_string(&Tags {
+            tag: tags.iter().map(|name| Tag { name: name.to_owned() }).collect()
+        }).unwrap();
+        let url = format!("{}/builds/id:{}/tags", self.base_url, build.id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_accept_json_header()
+                .add_content_type_json_header();
+            rest::post::<Tags>(&url, &body, &headers.headers, &hyper::StatusCode::OK)
+        });
+        match response {
+            Ok(_) => Ok(()),
+            Err(err) => Err(BuilderError::api("tagging build", err))
+        }
+    }
+
+    fn pin_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        let url = format!("{}/builds/id:{}/pin", self.base_url, build.id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_content_type_text_header();
+            rest::put_raw(&url, comment, &headers.headers)
+        });
+        match response {
+            Ok(response) => {
+                match response.status {
+                    status if status.is_success() => Ok(()),
+                    e @ _ => Err(BuilderError::api("pinning build", ApiError::Status(e)))
+                }
+            },
+            Err(err) => Err(BuilderError::api("pinning build", err))
+        }
+    }
+
+    fn comment_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        let url = format!("{}/builds/id:{}/comment", self.base_url, build.id);
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_content_type_text_header();
+            rest::put_raw(&url, comment, &headers.headers)
+        });
+        match response {
+            Ok(response) => {
+                match response.status {
+                    status if status.is_success() => Ok(()),
+                    e @ _ => Err(BuilderError::api("commenting on build", ApiError::Status(e)))
+                }
+            },
+            Err(err) => Err(BuilderError::api("commenting on build", err))
+        }
+    }
+
+    // Queued builds are removed from the queue, running ones are stopped
+    fn cancel_build(&self, build: &::BuildDetails, comment: &str) -> Result<(), BuilderError> {
+        let body = serde_json::to_string(&BuildCancelRequest {
+            comment: comment.to_owned(),
+            readd_into_queue: false
+        }).unwrap();
+        let url = match build.state {
+            ::BuildState::Queued => format!("{}/buildQueue/id:{}", self.base_url, build.id),
+            _ => format!("{}/builds/id:{}", self.base_url, build.id)
+        };
+
+        let response = credentials::authorized(&*self.provider(), |mut headers| {
+            headers.add_content_type_json_header();
+            rest::post_raw(&url, &body, &headers.headers)
+        });
+        match response {
+            Ok(response) => {
+                match response.status {
+                    status if status.is_success() => Ok(()),
+                    e @ _ => Err(BuilderError::api("cancelling build", ApiError::Status(e)))
+                }
+            },
+            Err(err) => Err(BuilderError::api("cancelling build", err))
+        }
+    }
+
+    fn get_coverage(&self, build: &::BuildDetails, target_branch: &str) -> Result<Option<::Coverage>, BuilderError> {
+        let percentage = match self.get_line_coverage(&format!("id:{}", build.id)) {
+            Ok(Some(percentage)) => percentage,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(err)
+        };
+
+        let encoded_branch = utf8_percent_encode(target_branch, QUERY_ENCODE_SET).collect::<String>();
+        let target_locator = format!("buildType:(id:{}),branch:(name:{}),status:SUCCESS,state:finished,count:1",
+            self.build_id, encoded_branch);
+        let delta = match self.get_line_coverage(&target_locator) {
+            Ok(Some(target_percentage)) => Some(percentage - target_percentage),
+            _ => None
+        };
+
+        Ok(Some(::Coverage {
+            percentage: percentage,
+            delta: delta,
+            threshold: self.coverage_threshold
+        }))
+    }
+
+    fn get_benchmarks(&self, build: &::BuildDetails) -> Result<Vec<::Benchmark>, BuilderError> {
+        if self.benchmark_prefix.is_none() {
+            return Ok(vec![]);
+        }
+        match self.get_statistics(&format!("id:{}", build.id)) {
+            Ok(properties) => self.benchmarks(properties),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use errors::ConfigError;
+    use registry::BuilderFactory;
+    use super::TeamcityFactory;
+
+    #[test]
+    fn builders_need_credentials() {
+        let settings = json!({ "base_url": "https://teamcity.example.com/app/rest", "build_id": "Build" });
+        match TeamcityFactory.create(&settings) {
+            Err(ConfigError::Invalid(_)) => {},
+            _ => panic!("Expected TeamCity without credentials to be rejected")
         }
+        let settings = json!({ "base_url": "https://teamcity.example.com/app/rest", "build_id": "Build",
+            "username": "user", "password": "secret" });
+        assert_eq!(1, TeamcityFactory.create(&settings).ok().map_or(0, |builders| builders.len()));
     }
 }
@@ -1,27 +1,61 @@
 use std::sync::mpsc::Receiver;
 use std::thread;
 use std::time;
-use telegram_bot;
-use rustc_serialize::{json, Decodable};
+use serde::{de, Deserialize, Deserializer};
+use serde_json;
 
+use errors::ConfigError;
+use events::CommentEvent;
 use fanout::{Message, OpCode};
-use json_dictionary::JsonDictionary;
+use i18n::Messages;
+use registry;
+use rest;
 
-#[derive(RustcDecodable, Eq, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
 pub struct TelegramCredentials {
     pub enabled: bool,
     pub api_token: String,
+    #[serde(deserialize_with = "deserialize_room")]
     pub room: i64
 }
 
-impl TelegramCredentials {
-    pub fn announce_from(&self, subscriber: Receiver<Message>) -> Result<(), String> {
-        let api = match telegram_bot::Api::from_token(self.api_token.as_str()) {
-            Ok(x) => x,
-            Err(err) => return Err(format!("{}", err))
+// Existing configurations give the room as a string, so accept both forms
+fn deserialize_room<'de, D>(deserializer: D) -> Result<i64, D::Error> where D: Deserializer<'de> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Room {
+        Number(i64),
+        Text(String)
+    }
+
+    match Room::deserialize(deserializer) {
+        Ok(Room::Number(room)) => Ok(room),
+        Ok(Room::Text(room)) => room.parse().map_err(de::Error::custom),
+        Err(err) => Err(err)
+    }
+}
+
+pub struct TelegramFactory;
+
+impl registry::NotifierFactory for TelegramFactory {
+    fn start(&self, settings: &serde_json::Value, subscriber: Receiver<Message>, messages: &Messages)
+            -> Result<(), ConfigError> {
+        let credentials = match serde_json::from_value::<TelegramCredentials>(settings.to_owned()) {
+            Ok(credentials) => credentials,
+            Err(err) => return Err(ConfigError::Parse(err))
         };
+        if !credentials.enabled {
+            return Ok(());
+        }
+        credentials.announce_from(subscriber, messages);
+        Ok(())
+    }
+}
 
-        let room = self.room;
+impl TelegramCredentials {
+    pub fn announce_from(&self, subscriber: Receiver<Message>, messages: &Messages) {
+        let credentials = self.to_owned();
+        let messages = messages.to_owned();
 
         thread::spawn(move || {
             let telegram_sleep_duration = time::Duration::new(1, 0);
@@ -30,45 +64,47 @@ impl TelegramCredentials {
                     OpCode::Custom { payload: ref custom_payload }
                         if custom_payload == "Bitbucket::Comment::Update"
                             || custom_payload == "Bitbucket::Comment::Post" => {
-                        // panic if payload cannot be deserialized
-                        let dictionary: JsonDictionary = json::decode(&message.payload).unwrap();
-                        // should panic if the deserialization failed
-                        let build = Self::unwrap_from_json_dictionary::<::BuildDetails>(&dictionary, "build").unwrap();
+                        let (pr, build) = match message.payload::<CommentEvent>() {
+                            Ok(event) => (event.pr, event.build),
+                            Err(err) => {
+                                println!("Unable to decode comment event: {}", err);
+                                continue;
+                            }
+                        };
                         if build.state != ::BuildState::Finished  || build.status == ::BuildStatus::Success {
                             continue;
                         }
 
-                        // should panic if the deserialization failed
-                        let pr = Self::unwrap_from_json_dictionary::<::PullRequest>(&dictionary, "pr").unwrap();
-
                         let status_text = match build.status_text {
                             Some(text) => text,
                             None => "".to_owned()
                         };
-                        let message_text = format!("⚠ Tests for Pull Request #{} have failed\n{}\n{}\nBy {}\n{}\n{}",
-                            pr.id, status_text, pr.title, pr.author.name, pr.web_url, build.web_url);
+                        let headline = messages.format("telegram.failure", &[
+                            ("marker", messages.labels().warning()),
+                            ("id", &pr.id.to_string()),
+                            ("failed", messages.labels().failure_text())
+                        ]);
+                        let message_text = format!("{}\n{}\n{}\nBy {}\n{}\n{}",
+                            headline, status_text, pr.title, pr.author.name, pr.web_url, build.web_url);
 
-                        Self::send_message(&api, room, message_text);
+                        credentials.send_message(message_text);
                         thread::sleep(telegram_sleep_duration);
                     }
                     _ => {} // noop
                 };
             }
         });
-        Ok(())
-    }
-
-    fn send_message(api: &telegram_bot::Api, room: i64, message: String) {
-        if let Err(err) = api.send_message(room, message, None, None, None, None) {
-            println!("{}", err)
-        }
     }
 
-    fn unwrap_from_json_dictionary<T>(dictionary: &JsonDictionary, key: &str)
-         -> Result<T, ()> where T : Decodable {
-        match dictionary.get::<T>(key) {
-            Some(Ok(result)) => Ok(result),
-            _ => Err(())
+    fn send_message(&self, message: String) {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.api_token);
+        let body = json!({ "chat_id": self.room, "text": message }).to_string();
+        let mut headers = rest::Headers::new();
+        headers.add_content_type_json_header();
+        match rest::post_raw(&url, &body, &headers.headers) {
+            Ok(ref response) if response.status.is_success() => {},
+            Ok(response) => println!("Error sending Telegram message: {} {}", response.status, response.body),
+            Err(err) => println!("Error sending Telegram message: {}", err)
         }
     }
 }
@@ -0,0 +1,1220 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+use handlebars::{Handlebars, no_escape};
+use serde::Serialize;
+use serde_json;
+
+use ::errors::RepositoryError;
+use ::events;
+use ::i18n::Messages;
+use ::labels::Labels;
+use ::rest;
+
+// Rows shown in the test failure table before linking to the rest
+static TEST_TABLE_ROWS: usize = 10;
+
+// Bitbucket Server rejects comments longer than 32768 characters
+static MAX_COMMENT_LENGTH: usize = 32000;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct Templates {
+    pub directory: Option<String>,
+    pub queued: Option<String>,
+    pub success: Option<String>,
+    pub failure: Option<String>,
+    pub matrix: Option<String>,
+    pub summary: Option<String>,
+    pub max_length: Option<usize>,
+    pub paste_url: Option<String>,
+    pub overrides: Option<Vec<TemplateOverride>>,
+    pub plain_text: Option<bool>
+}
+
+// Templates used instead of the ones above for a repository and/or target branch prefix
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct TemplateOverride {
+    pub repository: Option<String>,
+    pub target_branch: Option<String>,
+    pub queued: Option<String>,
+    pub success: Option<String>,
+    pub failure: Option<String>,
+    pub matrix: Option<String>,
+    pub summary: Option<String>
+}
+
+impl TemplateOverride {
+    fn template(&self, name: &str) -> &Option<String> {
+        match name {
+            "queued" => &self.queued,
+            "success" => &self.success,
+            "failure" => &self.failure,
+            "matrix" => &self.matrix,
+            _ => &self.summary
+        }
+    }
+}
+
+static MARKER_PREFIX: &'static str = "<!-- pr_demon ";
+static MARKER_SUFFIX: &'static str = " -->";
+static PLAIN_MARKER_PREFIX: &'static str = "pr_demon ";
+
+// Identifies the comments posted by the daemon, independently of how they are rendered
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct CommentKey {
+    pub kind: String,
+    pub pr: i32,
+    pub commit: Option<String>,
+    pub build: Option<String>
+}
+
+impl CommentKey {
+    pub fn status(pr: &::PullRequest, build: &::BuildDetails) -> CommentKey {
+        CommentKey {
+            kind: "status".to_owned(),
+            pr: pr.id,
+            commit: Some(pr.from_commit.to_owned()),
+            build: Some(build.build_id.to_owned())
+        }
+    }
+
+    pub fn matrix(pr: &::PullRequest) -> CommentKey {
+        CommentKey {
+            kind: "matrix".to_owned(),
+            pr: pr.id,
+            commit: Some(pr.from_commit.to_owned()),
+            build: None
+        }
+    }
+
+    pub fn summary(pr: &::PullRequest) -> CommentKey {
+        CommentKey {
+            kind: "summary".to_owned(),
+            pr: pr.id,
+            commit: None,
+            build: None
+        }
+    }
+
+    // Failed checks are reported per commit, so that the reports of earlier commits are superseded
+    pub fn check(kind: &str, pr: &::PullRequest) -> CommentKey {
+        CommentKey {
+            kind: kind.to_owned(),
+            pr: pr.id,
+            commit: Some(pr.from_commit.to_owned()),
+            build: None
+        }
+    }
+
+    // Comments about the pull request as a whole, posted once and kept up to date
+    pub fn notice(kind: &str, pr: &::PullRequest) -> CommentKey {
+        CommentKey {
+            kind: kind.to_owned(),
+            pr: pr.id,
+            commit: None,
+            build: None
+        }
+    }
+
+    // Appends the key as an HTML comment, which is not shown when the comment is rendered
+    pub fn mark(&self, text: &str) -> String {
+        let encoded = serde_json::to_string(self).expect("Comment key should be serializable");
+        format!("{}\n\n{}{}{}", text, MARKER_PREFIX, encoded, MARKER_SUFFIX)
+    }
+
+    // Without markdown the key cannot be hidden, so it is appended as the last line instead
+    pub fn mark_plain(&self, text: &str) -> String {
+        let encoded = serde_json::to_string(self).expect("Comment key should be serializable");
+        format!("{}\n\n{}{}", text, PLAIN_MARKER_PREFIX, encoded)
+    }
+
+    pub fn parse(text: &str) -> Option<CommentKey> {
+        let start = match text.rfind(&format!("{}{{", PLAIN_MARKER_PREFIX)) {
+            Some(index) => index + PLAIN_MARKER_PREFIX.len(),
+            None => return None
+        };
+        let encoded = text[start..].lines().next().unwrap_or("");
+        serde_json::from_str(encoded.trim_end().trim_end_matches(MARKER_SUFFIX)).ok()
+    }
+}
+
+// A comment the daemon has posted on a pull request
+pub trait OwnComment: Clone {
+    fn text(&self) -> &str;
+    fn to_event(&self) -> events::PostedComment;
+}
+
+// The comment calls a repository backend makes through its API, for `upsert_comment` to post, edit and supersede the
+// daemon's comments with
+pub trait CommentApi {
+    type Comment: OwnComment;
+
+    fn renderer(&self) -> &Renderer;
+    // How comments about earlier commits are superseded, if they are
+    fn superseded_comments(&self) -> Option<&str>;
+    fn get_own_comments(&self, pr_id: i32) -> Result<Vec<Self::Comment>, RepositoryError>;
+    fn post_comment(&self, pr_id: i32, text: &str) -> Result<Self::Comment, RepositoryError>;
+    fn edit_comment(&self, pr_id: i32, comment: &Self::Comment, text: &str) -> Result<Self::Comment, RepositoryError>;
+    fn delete_comment(&self, pr_id: i32, comment: &Self::Comment) -> Result<(), RepositoryError>;
+    fn broadcast<T>(&self, opcode: &str, payload: &T) where T : Serialize;
+
+    fn matching_comment<'a>(&self, comments: &'a [Self::Comment], key: &CommentKey) -> Option<&'a Self::Comment> {
+        comments.iter().find(|&comment| CommentKey::parse(comment.text()).as_ref() == Some(key))
+    }
+
+    // Whether the comment is about another commit than the one the pull request is at
+    fn is_outdated(&self, pr: &::PullRequest, comment: &Self::Comment) -> bool {
+        match CommentKey::parse(comment.text()) {
+            Some(key) => key.commit.is_some() && key.commit.as_ref() != Some(&pr.from_commit),
+            None => false
+        }
+    }
+}
+
+// Posts `text` marked with `key`, or edits the comment marked so already, and broadcasts `<event>::<opcode>`. Comments
+// about earlier commits are superseded when a new one is posted.
+pub fn upsert_comment<A, F, T>(api: &A, pr: &::PullRequest, text: &str, key: &CommentKey, event: &str, make_event: F)
+        -> Result<A::Comment, RepositoryError>
+        where A: CommentApi, F: Fn(Option<events::PostedComment>) -> T, T: Serialize {
+    let text = api.renderer().mark(key, text);
+    let (comment, opcode) = match api.get_own_comments(pr.id) {
+        Ok(ref comments) => match api.matching_comment(comments, key) {
+            Some(comment) if comment.text() == text => (Ok(comment.to_owned()), "Existing"),
+            Some(comment) => (api.edit_comment(pr.id, comment, &text), "Update"),
+            None => {
+                supersede_comments(api, pr, comments);
+                (api.post_comment(pr.id, &text), "Post")
+            }
+        },
+        Err(err) => (Err(err), "Error")
+    };
+
+    api.broadcast(&format!("{}::{}", event, opcode), &make_event(comment.as_ref().ok().map(OwnComment::to_event)));
+    comment
+}
+
+fn supersede_comments<A>(api: &A, pr: &::PullRequest, comments: &[A::Comment]) where A: CommentApi {
+    let style = match api.superseded_comments() {
+        Some(style) => style,
+        None => return
+    };
+
+    for comment in comments.iter()
+            .filter(|&comment| api.is_outdated(pr, comment))
+            .filter(|&comment| style == "delete" || !api.renderer().is_superseded(comment.text())) {
+        let superseded = match style {
+            "delete" => api.delete_comment(pr.id, comment),
+            _ => {
+                let text = api.renderer().make_superseded(style, &pr.from_commit, comment.text());
+                api.edit_comment(pr.id, comment, &text).map(|_| ())
+            }
+        };
+        if let Err(err) = superseded {
+            println!("Unable to mark comment {} as superseded: {}", comment.to_event().id, err);
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct SummaryRow {
+    pub commit: String,
+    pub build_id: i32,
+    pub web_url: String,
+    pub state: ::BuildState,
+    pub status: ::BuildStatus
+}
+
+#[derive(Serialize)]
+struct BuildContext {
+    labels: Labels,
+    pr: ::PullRequest,
+    build: ::BuildDetails,
+    commit: String,
+    status_text: String,
+    coverage: Option<String>,
+    parts: Option<String>,
+    steps: Option<String>,
+    notes: Option<String>,
+    size: Option<String>,
+    test_failures: Option<String>,
+    test_table: Option<String>,
+    log_excerpt: Option<String>
+}
+
+#[derive(Serialize)]
+struct MatrixContext {
+    labels: Labels,
+    pr: ::PullRequest,
+    commit: String,
+    failed: bool,
+    pending: bool,
+    builds: Vec<MatrixRow>
+}
+
+#[derive(Serialize)]
+struct MatrixRow {
+    build: ::BuildDetails,
+    marker: String
+}
+
+#[derive(Serialize)]
+struct SummaryContext {
+    labels: Labels,
+    pr: ::PullRequest,
+    build: ::BuildDetails,
+    rows: Vec<SummaryRowContext>
+}
+
+#[derive(Serialize)]
+struct SummaryRowContext {
+    row: SummaryRow,
+    marker: String
+}
+
+pub struct Renderer {
+    handlebars: Handlebars,
+    messages: Messages,
+    max_length: usize,
+    paste_url: Option<String>,
+    pastes: Mutex<HashMap<String, String>>,
+    plain_text: bool,
+    // Target branch prefixes of the overrides that apply to this repository, by registration order
+    overrides: Vec<(String, Option<String>)>
+}
+
+impl Renderer {
+    pub fn new(templates: &Option<Templates>, messages: &Messages, repository: &str) -> Result<Renderer, String> {
+        let mut handlebars = Handlebars::new();
+        handlebars.register_escape_fn(no_escape);
+        let plain_text = templates.as_ref().and_then(|templates| templates.plain_text) == Some(true);
+
+        for name in ["queued", "success", "failure", "matrix", "summary"] {
+            let template = match Renderer::load_template(templates, name) {
+                Ok(Some(template)) => template,
+                Ok(None) if plain_text => messages.get(&format!("plain.template.{}", name)).to_owned(),
+                Ok(None) => messages.get(&format!("template.{}", name)).to_owned(),
+                Err(err) => return Err(err)
+            };
+            if let Err(err) = handlebars.register_template_string(name, template) {
+                return Err(format!("Unable to parse {} template: {}", name, err));
+            }
+        }
+
+        let mut overrides = Vec::new();
+        let configured_overrides = templates.as_ref()
+            .and_then(|templates| templates.overrides.to_owned())
+            .unwrap_or(vec![]);
+        let applicable_overrides = configured_overrides.iter()
+            .filter(|template_override| match template_override.repository {
+                Some(ref override_repository) => override_repository == repository,
+                None => true
+            })
+            .enumerate();
+        for (index, template_override) in applicable_overrides {
+            let prefix = format!("override{}", index);
+            for name in ["queued", "success", "failure", "matrix", "summary"] {
+                if let Some(ref template) = *template_override.template(name) {
+                    let override_name = format!("{}.{}", prefix, name);
+                    if let Err(err) = handlebars.register_template_string(&override_name, template) {
+                        return Err(format!("Unable to parse {} template: {}", override_name, err));
+                    }
+                }
+            }
+            overrides.push((prefix, template_override.target_branch.to_owned()));
+        }
+
+        Ok(Renderer {
+            handlebars: handlebars,
+            messages: messages.to_owned(),
+            max_length: templates.as_ref().and_then(|templates| templates.max_length).unwrap_or(MAX_COMMENT_LENGTH),
+            paste_url: templates.as_ref().and_then(|templates| templates.paste_url.to_owned()),
+            pastes: Mutex::new(HashMap::new()),
+            plain_text: plain_text,
+            overrides: overrides
+        })
+    }
+
+    // Looks the template up through the overrides, falling back to the repository wide template
+    fn template_name(&self, name: &str, pr: &::PullRequest) -> String {
+        let target_branch = pr.target_branch_name();
+        self.overrides.iter()
+            .filter(|&&(_, ref branch_prefix)| match *branch_prefix {
+                Some(ref branch_prefix) => target_branch.starts_with(branch_prefix.as_str()),
+                None => true
+            })
+            .map(|&(ref prefix, _)| format!("{}.{}", prefix, name))
+            .find(|override_name| self.handlebars.get_template(override_name).is_some())
+            .unwrap_or(name.to_owned())
+    }
+
+    pub fn render_build(&self, template: &str, pr: &::PullRequest, build: &::BuildDetails)
+            -> Result<String, String> {
+        let test_failures = match build.test_failures {
+            Some(ref test_failures) if test_failures.muted + test_failures.investigated + test_failures.new > 0 => {
+                Some(make_test_failures_summary(test_failures, &self.messages))
+            },
+            _ => None
+        };
+        let test_table = match build.test_failures {
+            Some(ref test_failures) if !test_failures.tests.is_empty() => {
+                Some(make_test_table(test_failures, &self.messages, self.plain_text))
+            },
+            _ => None
+        };
+
+        let context = BuildContext {
+            labels: self.messages.labels().to_owned(),
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            commit: pr.from_commit.to_owned(),
+            status_text: build.status_text.to_owned().unwrap_or("".to_owned()),
+            coverage: build.coverage.as_ref().map(|coverage| make_coverage_summary(coverage, &self.messages, self.plain_text)),
+            parts: match build.parts.is_empty() {
+                true => None,
+                false => Some(make_parts_table(&build.parts, &self.messages, self.plain_text))
+            },
+            steps: match build.steps.is_empty() {
+                true => None,
+                false => Some(make_steps_table(&build.steps, &self.messages, self.plain_text))
+            },
+            notes: match build.notes.is_empty() {
+                true => None,
+                false => Some(build.notes.join("\n"))
+            },
+            size: pr.size.as_ref().map(|size| make_size_summary(size, &self.messages, self.plain_text)),
+            test_failures: test_failures,
+            test_table: test_table,
+            log_excerpt: build.log_excerpt.as_ref().map(|log| make_log_excerpt(log, &self.messages, self.plain_text))
+        };
+        self.render(&self.template_name(template, pr), &context, &CommentKey::status(pr, build), Some(&build.web_url))
+    }
+
+    pub fn render_matrix(&self, pr: &::PullRequest, builds: &Vec<::BuildDetails>) -> Result<String, String> {
+        let rows = builds.iter().map(|build| {
+            MatrixRow {
+                build: build.to_owned(),
+                marker: self.messages.labels().status_marker(&build.state, &build.status).to_owned()
+            }
+        }).collect::<Vec<_>>();
+
+        let context = MatrixContext {
+            labels: self.messages.labels().to_owned(),
+            pr: pr.to_owned(),
+            commit: pr.from_commit.to_owned(),
+            failed: builds.iter().any(|build| {
+                build.state == ::BuildState::Finished && build.status != ::BuildStatus::Success
+            }),
+            pending: builds.iter().any(|build| build.state != ::BuildState::Finished),
+            builds: rows
+        };
+        self.render(&self.template_name("matrix", pr), &context, &CommentKey::matrix(pr), None)
+    }
+
+    pub fn render_summary(&self, pr: &::PullRequest, build: &::BuildDetails, rows: &Vec<SummaryRow>)
+            -> Result<String, String> {
+        let context = SummaryContext {
+            labels: self.messages.labels().to_owned(),
+            pr: pr.to_owned(),
+            build: build.to_owned(),
+            rows: rows.iter().map(|row| {
+                SummaryRowContext {
+                    row: row.to_owned(),
+                    marker: self.messages.labels().status_marker(&row.state, &row.status).to_owned()
+                }
+            }).collect()
+        };
+        self.render(&self.template_name("summary", pr), &context, &CommentKey::summary(pr), Some(&build.web_url))
+    }
+
+    // Leaves room for the marker of `key`, which the rendered comment is posted with
+    fn render<T>(&self, template: &str, context: &T, key: &CommentKey, full_output_url: Option<&str>)
+            -> Result<String, String> where T: Serialize {
+        match self.handlebars.render(template, context) {
+            Ok(text) => Ok(self.limit_length(text.trim_end(), self.room(key, ""), full_output_url)),
+            Err(err) => Err(format!("Unable to render {} template: {}", template, err))
+        }
+    }
+
+    // Truncates text longer than `max_length`, linking to the full text when it can be pasted
+    fn limit_length(&self, text: &str, max_length: usize, full_output_url: Option<&str>) -> String {
+        if text.chars().count() <= max_length {
+            return text.to_owned();
+        }
+
+        let url = match self.paste(text) {
+            Some(url) => Some(url),
+            None => full_output_url.map(|url| url.to_owned())
+        };
+        let indicator = match url {
+            Some(ref url) if self.plain_text => self.messages.format("plain.comment.truncated_link", &[("url", url)]),
+            Some(ref url) => self.messages.format("comment.truncated_link", &[("url", url)]),
+            None => self.messages.get("comment.truncated").to_owned()
+        };
+
+        let length = max_length.saturating_sub(indicator.chars().count() + 2);
+        let truncated = text.chars().take(length).collect::<String>();
+        let truncated = match truncated.rfind('\n') {
+            Some(index) => truncated[..index].to_owned(),
+            None => truncated
+        };
+        format!("{}\n\n{}", truncated.trim_end(), indicator)
+    }
+
+    fn paste(&self, text: &str) -> Option<String> {
+        let paste_url = match self.paste_url {
+            Some(ref paste_url) => paste_url,
+            None => return None
+        };
+        if let Some(url) = self.pastes.lock().unwrap().get(text) {
+            return Some(url.to_owned());
+        }
+
+        let mut headers = rest::Headers::new();
+        headers.add_content_type_text_header();
+        let response = match rest::post_raw(paste_url, text, &headers.headers) {
+            Ok(response) => response,
+            Err(err) => {
+                println!("Error pasting comment text {}", err);
+                return None;
+            }
+        };
+        if !response.status.is_success() {
+            println!("Error pasting comment text {}", response.status);
+            return None;
+        }
+
+        let url = response.body.trim().to_owned();
+        self.pastes.lock().unwrap().insert(text.to_owned(), url.to_owned());
+        Some(url)
+    }
+
+    // Marks `text` with `key`, truncating the text so that the marked comment is not too long to be posted
+    pub fn mark(&self, key: &CommentKey, text: &str) -> String {
+        self.mark_with(key, text, "")
+    }
+
+    // Like `mark`, with `trailer` kept whole between the possibly truncated text and the marker
+    pub fn mark_with(&self, key: &CommentKey, text: &str, trailer: &str) -> String {
+        let text = self.limit_length(text, self.room(key, trailer), None);
+        self.append_marker(key, &format!("{}{}", text, trailer))
+    }
+
+    // How long text can be for it not to be too long to be posted once followed by `trailer` and marked with `key`
+    fn room(&self, key: &CommentKey, trailer: &str) -> usize {
+        self.max_length.saturating_sub(self.append_marker(key, trailer).chars().count())
+    }
+
+    fn append_marker(&self, key: &CommentKey, text: &str) -> String {
+        match self.plain_text {
+            true => key.mark_plain(text),
+            false => key.mark(text)
+        }
+    }
+
+    pub fn is_superseded(&self, text: &str) -> bool {
+        let superseded = self.messages.format("comment.superseded", &[("commit", "")]);
+        text.contains(superseded.trim())
+    }
+
+    pub fn make_superseded(&self, style: &str, commit: &str, text: &str) -> String {
+        let superseded = self.messages.format("comment.superseded", &[("commit", commit)]);
+        match style {
+            _ if self.plain_text => format!("{}\n\n{}", superseded, text),
+            "strike" => {
+                let struck = text.lines()
+                    .map(|line| match line.trim() {
+                        "" => "".to_owned(),
+                        line if line.starts_with(MARKER_PREFIX) => line.to_owned(),
+                        line => format!("~~{}~~", line)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!("{}\n\n{}", superseded, struck)
+            },
+            _ => format!("<details><summary>{}</summary>\n\n{}\n</details>", superseded, text)
+        }
+    }
+
+    fn load_template(templates: &Option<Templates>, name: &str) -> Result<Option<String>, String> {
+        let templates = match *templates {
+            Some(ref templates) => templates,
+            None => return Ok(None)
+        };
+
+        let inline = match name {
+            "queued" => &templates.queued,
+            "success" => &templates.success,
+            "failure" => &templates.failure,
+            "matrix" => &templates.matrix,
+            _ => &templates.summary
+        };
+        if let Some(ref template) = *inline {
+            return Ok(Some(template.to_owned()));
+        }
+
+        match templates.directory {
+            None => Ok(None),
+            Some(ref directory) => {
+                let path = Path::new(directory).join(format!("{}.hbs", name));
+                if !path.exists() {
+                    return Ok(None);
+                }
+
+                let mut template = String::new();
+                match File::open(&path).and_then(|mut file| file.read_to_string(&mut template)) {
+                    Ok(_) => Ok(Some(template)),
+                    Err(err) => Err(format!("Unable to read template {}: {}", path.display(), err))
+                }
+            }
+        }
+    }
+}
+
+
+fn make_coverage_summary(coverage: &::Coverage, messages: &Messages, plain_text: bool) -> String {
+    let delta = match coverage.delta {
+        Some(delta) => messages.format("coverage.delta", &[("delta", &format!("{:+.2}", delta))]),
+        None => "".to_owned()
+    };
+    let threshold = match coverage.threshold {
+        Some(threshold) if coverage.percentage >= threshold => {
+            messages.format("coverage.threshold_met",
+                &[("marker", messages.labels().success()), ("threshold", &format!("{:.2}", threshold))])
+        },
+        Some(threshold) => {
+            messages.format("coverage.threshold_missed",
+                &[("marker", messages.labels().failure()), ("threshold", &format!("{:.2}", threshold))])
+        },
+        None => "".to_owned()
+    };
+    let summary = if plain_text { "plain.coverage.summary" } else { "coverage.summary" };
+    messages.format(summary,
+        &[("percentage", &format!("{:.2}", coverage.percentage)), ("delta", &delta), ("threshold", &threshold)])
+}
+
+fn make_size_summary(size: &::size::Size, messages: &Messages, plain_text: bool) -> String {
+    let summary = if plain_text { "plain.size.summary" } else { "size.summary" };
+    messages.format(summary,
+        &[("class", size.class.label()), ("lines", &size.lines().to_string()), ("files", &size.files.to_string())])
+}
+
+fn make_test_failures_summary(test_failures: &::TestFailures, messages: &Messages) -> String {
+    let plural = |key: &str, count: i32| {
+        let key = format!("{}.{}", key, if count == 1 { "one" } else { "other" });
+        messages.format(&key, &[("count", &count.to_string())])
+    };
+
+    let mut known = Vec::<String>::new();
+    if test_failures.muted > 0 {
+        known.push(plural("tests.muted", test_failures.muted));
+    }
+    if test_failures.investigated > 0 {
+        known.push(plural("tests.investigated", test_failures.investigated));
+    }
+    known.push(plural("tests.new", test_failures.new));
+    known.join(", ")
+}
+
+fn make_test_table(test_failures: &::TestFailures, messages: &Messages, plain_text: bool) -> String {
+    if plain_text {
+        return make_test_list(test_failures, messages);
+    }
+
+    let rows = test_failures.tests.iter().take(TEST_TABLE_ROWS).map(|test| {
+        let duration = match test.duration {
+            Some(duration) => format!("{:.2}s", duration as f64 / 1000.0),
+            None => "".to_owned()
+        };
+        let link = match test.web_url {
+            Some(ref web_url) => format!("[{}]({})", messages.get("tests.details"), web_url),
+            None => "".to_owned()
+        };
+        format!("| {} | {} | {} | {} |", test.name, test.status, duration, link)
+    }).collect::<Vec<_>>().join("\n");
+
+    let more = match test_failures.tests.len() {
+        count if count > TEST_TABLE_ROWS => {
+            let more = messages.format("tests.more", &[("count", &(count - TEST_TABLE_ROWS).to_string())]);
+            match test_failures.web_url {
+                Some(ref web_url) => format!("\n\n[{}]({})", more, web_url),
+                None => format!("\n\n{}", more)
+            }
+        },
+        _ => "".to_owned()
+    };
+
+    format!("{}\n|---|---|---|---|\n{}{}", messages.get("tests.header"), rows, more)
+}
+
+fn make_test_list(test_failures: &::TestFailures, messages: &Messages) -> String {
+    let mut lines = test_failures.tests.iter().take(TEST_TABLE_ROWS).map(|test| {
+        let duration = match test.duration {
+            Some(duration) => format!(", {:.2}s", duration as f64 / 1000.0),
+            None => "".to_owned()
+        };
+        let link = match test.web_url {
+            Some(ref web_url) => format!(" {}", web_url),
+            None => "".to_owned()
+        };
+        format!("- {} ({}{}){}", test.name, test.status, duration, link)
+    }).collect::<Vec<_>>();
+
+    let count = test_failures.tests.len();
+    if count > TEST_TABLE_ROWS {
+        let more = messages.format("tests.more", &[("count", &(count - TEST_TABLE_ROWS).to_string())]);
+        lines.push(match test_failures.web_url {
+            Some(ref web_url) => format!("{} {}", more, web_url),
+            None => more
+        });
+    }
+    lines.join("\n")
+}
+
+fn make_log_excerpt(log: &str, messages: &Messages, plain_text: bool) -> String {
+    if plain_text {
+        return format!("{}:\n{}", messages.get("log.summary"), log);
+    }
+
+    // Longer than any run of backticks in the log, which would otherwise end the code block early
+    let longest = log.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(::std::cmp::max(3, longest + 1));
+    format!("<details><summary>{}</summary>\n\n{}\n{}\n{}\n</details>", messages.get("log.summary"), fence, log, fence)
+}
+
+fn make_parts_table(parts: &Vec<::BuildPart>, messages: &Messages, plain_text: bool) -> String {
+    if plain_text {
+        return parts.iter().map(|part| {
+            format!("{} {}: {}", messages.labels().status_marker(&part.state, &part.status), part.name, part.web_url)
+        }).collect::<Vec<_>>().join("\n");
+    }
+
+    let rows = parts.iter().map(|part| {
+        format!("| [{}]({}) | {} |", part.name, part.web_url,
+            messages.labels().status_marker(&part.state, &part.status))
+    }).collect::<Vec<_>>().join("\n");
+
+    format!("{}\n|---|---|\n{}", messages.get("parts.header"), rows)
+}
+
+fn make_steps_table(steps: &Vec<::BuildStep>, messages: &Messages, plain_text: bool) -> String {
+    let duration = |step: &::BuildStep| match step.duration {
+        Some(duration) => format!("{:.2}s", duration as f64 / 1000.0),
+        None => "".to_owned()
+    };
+    if plain_text {
+        return steps.iter().map(|step| {
+            let marker = messages.labels().status_marker(&step.state, &step.status);
+            format!("{} {} {}", marker, step.name, duration(step)).trim_end().to_owned()
+        }).collect::<Vec<_>>().join("\n");
+    }
+
+    let rows = steps.iter().map(|step| {
+        format!("| {} | {} | {} |", step.name, messages.labels().status_marker(&step.state, &step.status),
+            duration(step))
+    }).collect::<Vec<_>>().join("\n");
+
+    format!("{}\n|---|---|---|\n{}", messages.get("steps.header"), rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use serde::Serialize;
+    use super::{make_log_excerpt, upsert_comment, CommentApi, CommentKey, OwnComment, Renderer, SummaryRow,
+                TemplateOverride, Templates};
+    use super::super::errors::RepositoryError;
+    use super::super::events::PostedComment;
+    use super::super::i18n::Messages;
+    use super::super::labels::Labels;
+    use super::super::size::SizeConfig;
+    use super::super::{BuildDetails, BuildState, BuildStatus, BuildStep, PullRequest, TestFailures, TestResult, User};
+
+    fn pull_request() -> PullRequest {
+        PullRequest {
+            id: 111,
+            web_url: "http://www.foobar.com/pr/111".to_owned(),
+            from_ref: "refs/heads/branch_name".to_owned(),
+            from_commit: "363c1dfda4cdf5a01c2d210e49942c8c8e7e898b".to_owned(),
+            to_ref: "refs/heads/master".to_owned(),
+            from_fork: false,
+            title: "A very important PR".to_owned(),
+            description: "".to_owned(),
+            updated: None,
+            author: User {
+                name: "Aaron Xiao Ming".to_owned(),
+                email: "aaron@xiao.ming".to_owned(),
+                username: Some("aaron".to_owned())
+            },
+            reviewers: vec![],
+            approvals: vec![],
+            size: None,
+            open_tasks: None,
+            labels: vec![]
+        }
+    }
+
+    fn build_failure() -> BuildDetails {
+        BuildDetails {
+            id: 213232321,
+            build_id: "somethingsomething".to_owned(),
+            web_url: "http://www.goodbuilds.com/213213221".to_owned(),
+            commit: Some("363c1dfda4cdf5a01c2d210e49942c8c8e7e898b".to_owned()),
+            state: BuildState::Finished,
+            status: BuildStatus::Failure,
+            status_text: Some("Build failed with walking monochrome".to_owned()),
+            log_excerpt: Some("error: aborting".to_owned()),
+            coverage: None,
+            test_failures: None,
+            queue_wait: None,
+            parts: vec![],
+            steps: vec![],
+            notes: vec![],
+            component: None
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct StubComment {
+        id: i64,
+        text: String
+    }
+
+    impl OwnComment for StubComment {
+        fn text(&self) -> &str {
+            &self.text
+        }
+
+        fn to_event(&self) -> PostedComment {
+            PostedComment { id: self.id, version: 0, text: self.text.to_owned() }
+        }
+    }
+
+    struct StubComments {
+        renderer: Renderer,
+        superseded_comments: Option<String>,
+        comments: RefCell<Vec<StubComment>>,
+        broadcasts: RefCell<Vec<String>>
+    }
+
+    impl CommentApi for StubComments {
+        type Comment = StubComment;
+
+        fn renderer(&self) -> &Renderer {
+            &self.renderer
+        }
+
+        fn superseded_comments(&self) -> Option<&str> {
+            self.superseded_comments.as_ref().map(String::as_str)
+        }
+
+        fn get_own_comments(&self, _: i32) -> Result<Vec<StubComment>, RepositoryError> {
+            Ok(self.comments.borrow().clone())
+        }
+
+        fn post_comment(&self, _: i32, text: &str) -> Result<StubComment, RepositoryError> {
+            let id = self.comments.borrow().iter().map(|comment| comment.id).max().unwrap_or(0) + 1;
+            let comment = StubComment { id: id, text: text.to_owned() };
+            self.comments.borrow_mut().push(comment.clone());
+            Ok(comment)
+        }
+
+        fn edit_comment(&self, _: i32, comment: &StubComment, text: &str) -> Result<StubComment, RepositoryError> {
+            let edited = StubComment { id: comment.id, text: text.to_owned() };
+            for existing in self.comments.borrow_mut().iter_mut().filter(|existing| existing.id == comment.id) {
+                *existing = edited.clone();
+            }
+            Ok(edited)
+        }
+
+        fn delete_comment(&self, _: i32, comment: &StubComment) -> Result<(), RepositoryError> {
+            self.comments.borrow_mut().retain(|existing| existing.id != comment.id);
+            Ok(())
+        }
+
+        fn broadcast<T>(&self, opcode: &str, _: &T) where T : Serialize {
+            self.broadcasts.borrow_mut().push(opcode.to_owned());
+        }
+    }
+
+    fn stub_comments(superseded_comments: Option<&str>) -> StubComments {
+        StubComments {
+            renderer: Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap(),
+            superseded_comments: superseded_comments.map(str::to_owned),
+            comments: RefCell::new(vec![]),
+            broadcasts: RefCell::new(vec![])
+        }
+    }
+
+    #[test]
+    fn comments_are_posted_once_and_then_updated() {
+        let api = stub_comments(None);
+        let key = CommentKey::status(&pull_request(), &build_failure());
+        for text in &["Queued", "Queued", "Failed"] {
+            upsert_comment(&api, &pull_request(), text, &key, "Comment", |comment| comment).unwrap();
+        }
+        assert_eq!(vec!["Comment::Post", "Comment::Existing", "Comment::Update"], *api.broadcasts.borrow());
+        assert_eq!(1, api.comments.borrow().len());
+        assert!(api.comments.borrow()[0].text.starts_with("Failed"));
+    }
+
+    #[test]
+    fn comments_about_earlier_commits_are_superseded() {
+        let api = stub_comments(Some("delete"));
+        let key = CommentKey::status(&pull_request(), &build_failure());
+        upsert_comment(&api, &pull_request(), "Failed", &key, "Comment", |comment| comment).unwrap();
+        upsert_comment(&api, &pull_request(), "Notice", &CommentKey::notice("notice", &pull_request()), "Notice",
+            |comment| comment).unwrap();
+
+        let pr = PullRequest { from_commit: "abc".to_owned(), ..pull_request() };
+        upsert_comment(&api, &pr, "Queued", &CommentKey::status(&pr, &build_failure()), "Comment", |comment| comment)
+            .unwrap();
+        let comments = api.comments.borrow();
+        assert_eq!(vec![2, 3], comments.iter().map(|comment| comment.id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn default_templates_render_build_comments() {
+        let renderer = Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap();
+        let expected = "❌ [Build](http://www.goodbuilds.com/213213221) for commit \
+                        363c1dfda4cdf5a01c2d210e49942c8c8e7e898b has **failed**: Build failed with walking monochrome\
+                        \n\n<details><summary>Build log</summary>\n\n```\nerror: aborting\n```\n</details>";
+
+        let actual = renderer.render_build("failure", &pull_request(), &build_failure()).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn sized_pull_requests_show_their_size() {
+        let renderer = Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap();
+        let size = SizeConfig { limits: None, warn: None }.classify(3, 120, 30);
+        let pr = PullRequest { size: Some(size), ..pull_request() };
+
+        let actual = renderer.render_build("failure", &pr, &build_failure()).unwrap();
+        assert!(actual.contains("\n\nSize: **M**, 150 lines changed in 3 files\n\n"));
+    }
+
+    #[test]
+    fn build_steps_are_rendered_as_a_table() {
+        let renderer = Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap();
+        let step = |name: &str, state: BuildState, status: BuildStatus, duration: Option<i64>| BuildStep {
+            name: name.to_owned(),
+            state: state,
+            status: status,
+            duration: duration
+        };
+        let build = BuildDetails {
+            steps: vec![
+                step("Compile", BuildState::Finished, BuildStatus::Success, Some(61500)),
+                step("Test", BuildState::Finished, BuildStatus::Failure, Some(1250)),
+                step("Package", BuildState::Queued, BuildStatus::Unknown, None)
+            ],
+            ..build_failure()
+        };
+
+        let actual = renderer.render_build("failure", &pull_request(), &build).unwrap();
+        assert!(actual.contains("\n\n| Step | Status | Duration |\n|---|---|---|\n| Compile | ✔️ | 61.50s |\n\
+                                 | Test | ❌ | 1.25s |\n| Package | ⏳ |  |"));
+    }
+
+    #[test]
+    fn matrix_rows_name_their_components() {
+        let renderer = Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap();
+        let builds = vec![
+            BuildDetails { component: Some("frontend, backend".to_owned()), ..build_failure() },
+            BuildDetails { build_id: "Docs".to_owned(), ..build_failure() }
+        ];
+
+        let actual = renderer.render_matrix(&pull_request(), &builds).unwrap();
+        assert!(actual.contains("\n| frontend, backend: somethingsomething | ❌ | [213232321]"));
+        assert!(actual.contains("\n| Docs | ❌ | [213232321]"));
+    }
+
+    #[test]
+    fn inline_templates_override_defaults() {
+        let templates = Some(Templates {
+            directory: None,
+            queued: None,
+            success: None,
+            failure: Some("PR #{{pr.id}} broke {{build.build_id}}".to_owned()),
+            matrix: None,
+            summary: None,
+            max_length: None,
+            paste_url: None,
+            overrides: None,
+            plain_text: None
+        });
+        let renderer = Renderer::new(&templates, &Messages::new(&None, &None), "repo").unwrap();
+
+        let actual = renderer.render_build("failure", &pull_request(), &build_failure()).unwrap();
+        assert_eq!("PR #111 broke somethingsomething", actual);
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let templates = Some(Templates {
+            directory: None,
+            queued: Some("{{#if pr}}".to_owned()),
+            success: None,
+            failure: None,
+            matrix: None,
+            summary: None,
+            max_length: None,
+            paste_url: None,
+            overrides: None,
+            plain_text: None
+        });
+
+        assert!(Renderer::new(&templates, &Messages::new(&None, &None), "repo").is_err());
+    }
+
+    #[test]
+    fn summary_template_renders_a_row_per_commit() {
+        let renderer = Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap();
+        let rows = vec![
+            SummaryRow {
+                commit: "ffffff".to_owned(),
+                build_id: 1,
+                web_url: "http://www.goodbuilds.com/1".to_owned(),
+                state: BuildState::Finished,
+                status: BuildStatus::Failure
+            },
+            SummaryRow {
+                commit: "363c1dfda4cdf5a01c2d210e49942c8c8e7e898b".to_owned(),
+                build_id: 213232321,
+                web_url: "http://www.goodbuilds.com/213213221".to_owned(),
+                state: BuildState::Running,
+                status: BuildStatus::Unknown
+            }
+        ];
+        let expected = "**Build summary** for Pull Request #111\n\n\
+                        | Commit | Status | Build |\n|---|---|---|\n\
+                        | ffffff | ❌ | [1](http://www.goodbuilds.com/1) |\n\
+                        | 363c1dfda4cdf5a01c2d210e49942c8c8e7e898b | ⏳ | [213232321](http://www.goodbuilds.com/213213221) |";
+
+        let actual = renderer.render_summary(&pull_request(), &build_failure(), &rows).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn superseded_comments_are_collapsed_by_default() {
+        let renderer = Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap();
+        let actual = renderer.make_superseded("collapse", "abc", "❌ Build for commit def has **failed**");
+        assert_eq!("<details><summary>Superseded by commit abc</summary>\n\n\
+                    ❌ Build for commit def has **failed**\n</details>", actual);
+        assert!(renderer.is_superseded(&actual));
+    }
+
+    #[test]
+    fn superseded_comments_can_be_struck_through() {
+        let renderer = Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap();
+        let actual = renderer.make_superseded("strike", "abc", "❌ Build has **failed**\n\nCoverage: 80%");
+        assert_eq!("Superseded by commit abc\n\n~~❌ Build has **failed**~~\n\n~~Coverage: 80%~~", actual);
+        assert!(renderer.is_superseded(&actual));
+        assert!(!renderer.is_superseded("❌ Build has **failed**"));
+    }
+
+    #[test]
+    fn failed_tests_are_rendered_as_a_truncated_table() {
+        let renderer = Renderer::new(&None, &Messages::new(&None, &None), "repo").unwrap();
+        let mut build = build_failure();
+        build.log_excerpt = None;
+        build.test_failures = Some(TestFailures {
+            muted: 0,
+            investigated: 0,
+            new: 12,
+            web_url: Some("http://www.goodbuilds.com/tests".to_owned()),
+            tests: (0..12).map(|i| TestResult {
+                name: format!("test_{}", i),
+                status: "FAILURE".to_owned(),
+                duration: Some(1500),
+                web_url: None
+            }).collect()
+        });
+
+        let actual = renderer.render_build("failure", &pull_request(), &build).unwrap();
+        assert!(actual.starts_with("❌ [Build](http://www.goodbuilds.com/213213221) for commit \
+                                    363c1dfda4cdf5a01c2d210e49942c8c8e7e898b has **failed**\n\n12 new failures"));
+        assert!(actual.contains("| Test | Status | Duration | Link |\n|---|---|---|---|\n| test_0 | FAILURE | 1.50s |  |"));
+        assert!(actual.contains("| test_9 | FAILURE | 1.50s |  |"));
+        assert!(!actual.contains("test_10"));
+        assert!(actual.ends_with("[and 2 more…](http://www.goodbuilds.com/tests)"));
+    }
+
+    #[test]
+    fn labels_replace_default_markers() {
+        let messages = Messages::new(&None, &Some(Labels {
+            pending: None,
+            success: None,
+            failure: Some("[FAIL]".to_owned()),
+            warning: None,
+            pending_text: None,
+            success_text: None,
+            failure_text: Some("broken".to_owned())
+        }));
+        let renderer = Renderer::new(&None, &messages, "repo").unwrap();
+        let mut build = build_failure();
+        build.log_excerpt = None;
+
+        let actual = renderer.render_build("failure", &pull_request(), &build).unwrap();
+        assert_eq!("[FAIL] [Build](http://www.goodbuilds.com/213213221) for commit \
+                    363c1dfda4cdf5a01c2d210e49942c8c8e7e898b has **broken**: Build failed with walking monochrome", actual);
+    }
+
+    #[test]
+    fn german_messages_are_used_for_default_templates() {
+        let renderer = Renderer::new(&None, &Messages::new(&Some("de".to_owned()), &None), "repo").unwrap();
+        let mut build = build_failure();
+        build.log_excerpt = None;
+
+        let actual = renderer.render_build("failure", &pull_request(), &build).unwrap();
+        assert_eq!("❌ [Build](http://www.goodbuilds.com/213213221) für Commit \
+                    363c1dfda4cdf5a01c2d210e49942c8c8e7e898b ist **fehlgeschlagen**: Build failed with walking monochrome",
+                    actual);
+    }
+
+    #[test]
+    fn long_comments_are_truncated_with_a_link() {
+        let templates = Some(Templates {
+            directory: None,
+            queued: None,
+            success: None,
+            failure: None,
+            matrix: None,
+            summary: None,
+            max_length: Some(400),
+            paste_url: None,
+            overrides: None,
+            plain_text: None
+        });
+        let renderer = Renderer::new(&templates, &Messages::new(&None, &None), "repo").unwrap();
+        let mut build = build_failure();
+        build.log_excerpt = Some(vec!["error: aborting"; 100].join("\n"));
+
+        let actual = renderer.render_build("failure", &pull_request(), &build).unwrap();
+        let key = CommentKey::status(&pull_request(), &build);
+        assert_eq!(key.mark(&actual), renderer.mark(&key, &actual));
+        assert!(key.mark(&actual).chars().count() <= 400);
+        assert!(actual.starts_with("❌ [Build](http://www.goodbuilds.com/213213221)"));
+        assert!(actual.ends_with("✂️ Comment truncated, see the [full output](http://www.goodbuilds.com/213213221)"));
+    }
+
+    #[test]
+    fn marked_comments_are_truncated_with_their_marker_and_trailer_kept() {
+        let templates = Some(Templates {
+            directory: None,
+            queued: None,
+            success: None,
+            failure: None,
+            matrix: None,
+            summary: None,
+            max_length: Some(300),
+            paste_url: None,
+            overrides: None,
+            plain_text: None
+        });
+        let renderer = Renderer::new(&templates, &Messages::new(&None, &None), "repo").unwrap();
+        let key = CommentKey::notice("stale", &pull_request());
+        let text = vec!["This pull request has had no activity."; 20].join("\n");
+
+        let marked = renderer.mark(&key, &text);
+        assert!(marked.chars().count() <= 300);
+        assert!(marked.contains("✂️ Comment truncated"));
+        assert_eq!(Some(key.to_owned()), CommentKey::parse(&marked));
+
+        let marked = renderer.mark_with(&key, &text, "\n\n[//]: # (rows)");
+        assert!(marked.chars().count() <= 300);
+        assert!(marked.contains("✂️ Comment truncated\n\n[//]: # (rows)\n\n"));
+        assert_eq!(Some(key.to_owned()), CommentKey::parse(&marked));
+        assert_eq!(key.mark("short\n\n[//]: # (rows)"), renderer.mark_with(&key, "short", "\n\n[//]: # (rows)"));
+    }
+
+    #[test]
+    fn log_fences_are_longer_than_backticks_in_the_log() {
+        let messages = Messages::new(&None, &None);
+        let plain = make_log_excerpt("error: aborting", &messages, false);
+        assert!(plain.contains("\n```\nerror: aborting\n```\n"), "{}", plain);
+        let quoted = make_log_excerpt("error: ```` in `main`", &messages, false);
+        assert!(quoted.contains("\n`````\nerror: ```` in `main`\n`````\n"), "{}", quoted);
+    }
+
+    #[test]
+    fn comment_keys_round_trip_through_hidden_markers() {
+        let key = CommentKey::status(&pull_request(), &build_failure());
+        let text = key.mark("❌ Build has **failed**");
+        assert_eq!("❌ Build has **failed**\n\n<!-- pr_demon {\"kind\":\"status\",\"pr\":111,\
+                    \"commit\":\"363c1dfda4cdf5a01c2d210e49942c8c8e7e898b\",\"build\":\"somethingsomething\"} -->",
+                    text);
+        assert_eq!(Some(key), CommentKey::parse(&text));
+        assert_eq!(None, CommentKey::parse("❌ Build has **failed**"));
+    }
+
+    #[test]
+    fn template_overrides_are_looked_up_by_repository_and_target_branch() {
+        let template_override = |repository: &str, target_branch: &str, failure: &str| TemplateOverride {
+            repository: Some(repository.to_owned()),
+            target_branch: Some(target_branch.to_owned()),
+            queued: None,
+            success: None,
+            failure: Some(failure.to_owned()),
+            matrix: None,
+            summary: None
+        };
+        let templates = Some(Templates {
+            directory: None,
+            queued: None,
+            success: None,
+            failure: Some("Failed".to_owned()),
+            matrix: None,
+            summary: None,
+            max_length: None,
+            paste_url: None,
+            overrides: Some(vec![
+                template_override("other", "release/", "Other release failed"),
+                template_override("repo", "release/", "Release failed"),
+                template_override("repo", "", "Repo failed")
+            ]),
+            plain_text: None
+        });
+        let renderer = Renderer::new(&templates, &Messages::new(&None, &None), "repo").unwrap();
+
+        let mut pr = pull_request();
+        pr.to_ref = "refs/heads/release/1.0".to_owned();
+        assert_eq!("Release failed", renderer.render_build("failure", &pr, &build_failure()).unwrap());
+
+        pr.to_ref = "refs/heads/master".to_owned();
+        assert_eq!("Repo failed", renderer.render_build("failure", &pr, &build_failure()).unwrap());
+
+        // Overrides without a template for the state fall back to the repository wide template
+        let success = renderer.render_build("success", &pr, &build_failure()).unwrap();
+        assert!(success.starts_with("✔️ [Build]"));
+    }
+
+    #[test]
+    fn plain_text_mode_renders_without_markdown() {
+        let templates = Some(Templates {
+            directory: None,
+            queued: None,
+            success: None,
+            failure: None,
+            matrix: None,
+            summary: None,
+            max_length: None,
+            paste_url: None,
+            overrides: None,
+            plain_text: Some(true)
+        });
+        let renderer = Renderer::new(&templates, &Messages::new(&None, &None), "repo").unwrap();
+
+        let actual = renderer.render_build("failure", &pull_request(), &build_failure()).unwrap();
+        assert_eq!("❌ Build for commit 363c1dfda4cdf5a01c2d210e49942c8c8e7e898b has failed: \
+                    Build failed with walking monochrome\nhttp://www.goodbuilds.com/213213221\n\n\
+                    Build log:\nerror: aborting", actual);
+
+        let key = CommentKey::summary(&pull_request());
+        let marked = renderer.mark(&key, &actual);
+        assert!(marked.ends_with("\n\npr_demon {\"kind\":\"summary\",\"pr\":111,\"commit\":null,\"build\":null}"));
+        assert_eq!(Some(key), CommentKey::parse(&marked));
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+
+use ::errors::{ConfigError, RepositoryError};
+use ::fanout::{Fanout, Message};
+use ::i18n::Messages;
+use ::pipeline::Pipeline;
+use ::registry::{BackendConfig, Registry};
+use ::scheduler::Scheduler;
+
+// One of several repositories watched by the same daemon, with the builder building its pull requests
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct Watch {
+    // Tags the messages broadcast about the repository, unique among the watches
+    pub name: String,
+    pub repository: BackendConfig,
+    pub builder: BackendConfig
+}
+
+pub struct Watcher {
+    pub name: Option<String>,
+    repository: Box<::Repository>,
+    builders: Vec<Box<::ContinuousIntegrator>>,
+    fanout: Fanout<Message>,
+    pinned: HashSet<i32>
+}
+
+impl Watcher {
+    // A watcher whose messages are broadcast through `fanout` as they are, for a daemon watching one repository
+    pub fn new(repository: Box<::Repository>, builders: Vec<Box<::ContinuousIntegrator>>, fanout: &Fanout<Message>)
+            -> Watcher {
+        Watcher {
+            name: None,
+            repository: repository,
+            builders: builders,
+            fanout: fanout.to_owned(),
+            pinned: HashSet::new()
+        }
+    }
+
+    pub fn poll(&mut self, pipeline: &Pipeline, config: &::Config, messages: &Messages, scheduler: Option<&Scheduler>)
+            -> Result<(), RepositoryError> {
+        let matrix: Vec<&::ContinuousIntegrator> = self.builders.iter().map(|builder| &**builder).collect();
+        ::poll_once(&*self.repository, &matrix, pipeline, config, messages, &self.fanout, &mut self.pinned, scheduler)
+    }
+}
+
+pub fn validate(watches: &[Watch]) -> Result<(), ConfigError> {
+    if watches.is_empty() {
+        return Err(ConfigError::Invalid("At least one repository must be watched".to_owned()));
+    }
+    let mut names = HashSet::new();
+    match watches.iter().find(|watch| !names.insert(watch.name.as_str())) {
+        Some(watch) => Err(ConfigError::Invalid(format!("{} is watched more than once", watch.name))),
+        None => Ok(())
+    }
+}
+
+// A watcher for each watch, each with a fanout of its own whose messages reach `fanout` tagged with its name
+pub fn create(watches: &[Watch], registry: &Registry, messages: &Messages, fanout: &Fanout<Message>)
+        -> Result<Vec<Watcher>, ConfigError> {
+    if let Err(err) = validate(watches) {
+        return Err(err);
+    }
+    let mut watchers = vec![];
+    for watch in watches {
+        let tagged = fanout.tagged(&watch.name);
+        let repository = match registry.create_repository(&watch.repository, messages, &tagged) {
+            Ok(repository) => repository,
+            Err(err) => return Err(err)
+        };
+        let builders = match registry.create_builders(&watch.builder) {
+            Ok(builders) => builders,
+            Err(err) => return Err(err)
+        };
+        watchers.push(Watcher {
+            name: Some(watch.name.to_owned()),
+            repository: repository,
+            builders: builders,
+            fanout: tagged,
+            pinned: HashSet::new()
+        });
+    }
+    Ok(watchers)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use super::{validate, Watch};
+
+    fn watch(name: &str) -> Watch {
+        serde_json::from_value(json!({
+            "name": name,
+            "repository": { "type": "bitbucket", "settings": {} },
+            "builder": { "type": "teamcity", "settings": {} }
+        })).unwrap()
+    }
+
+    #[test]
+    fn watches_need_unique_names() {
+        assert!(validate(&[watch("frontend"), watch("backend")]).is_ok());
+        assert!(validate(&[watch("frontend"), watch("backend"), watch("frontend")]).is_err());
+        assert!(validate(&[]).is_err());
+    }
+}
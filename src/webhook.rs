@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use httpdate;
+use hyper::{Method, StatusCode};
+use serde_json;
+use sha2::Sha256;
+use time;
+
+use ::access::constant_time_eq;
+use ::errors::{ConfigError, WebhookError};
+use ::listener::{self, ListenerConfig, Listening, Request, Response};
+
+static DEFAULT_REPLAY_WINDOW: i64 = 300;
+static DEFAULT_FALLBACK_INTERVAL: u64 = 600;
+// The Bitbucket Server events that may need a pull request built
+static TRIGGERING_EVENTS: &'static [&'static str] = &["pr:opened", "pr:from_ref_updated", "pr:comment:added"];
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Bitbucket,
+    Github,
+    Gitlab
+}
+
+impl Source {
+    // The header carrying the signature, or the shared token for GitLab
+    pub fn signature_header(&self) -> &'static str {
+        match *self {
+            Source::Bitbucket => "X-Hub-Signature",
+            Source::Github => "X-Hub-Signature-256",
+            Source::Gitlab => "X-Gitlab-Token"
+        }
+    }
+
+    pub fn delivery_header(&self) -> &'static str {
+        match *self {
+            Source::Bitbucket => "X-Request-Id",
+            Source::Github => "X-GitHub-Delivery",
+            Source::Gitlab => "X-Gitlab-Event-UUID"
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct WebhookConfig {
+    // Used for repositories without their own entry in `secrets`
+    pub secret: Option<String>,
+    // Keyed by repository, such as "PROJECT/repo"
+    pub secrets: Option<BTreeMap<String, String>>,
+    // In seconds, how old a delivery may be and how long delivery IDs are remembered
+    pub replay_window: Option<i64>,
+    // Where the receiver listens for Bitbucket Server deliveries, needed when the daemon runs one
+    pub listener: Option<ListenerConfig>,
+    // In seconds, how long the receiver waits for a delivery before polling anyway
+    pub fallback_interval: Option<u64>
+}
+
+// What the receiver read from an incoming request
+pub struct Delivery<'a> {
+    pub source: Source,
+    pub repository: &'a str,
+    pub signature: Option<&'a str>,
+    pub id: Option<&'a str>,
+    // Seconds since the epoch, from the request's Date header
+    pub timestamp: Option<i64>,
+    pub body: &'a [u8]
+}
+
+pub struct Verifier {
+    config: WebhookConfig,
+    seen: Mutex<HashMap<String, i64>>
+}
+
+impl Verifier {
+    pub fn new(config: &WebhookConfig) -> Verifier {
+        Verifier {
+            config: config.to_owned(),
+            seen: Mutex::new(HashMap::new())
+        }
+    }
+
+    // Unsigned deliveries, deliveries for repositories without a secret and replayed deliveries are all rejected
+    pub fn verify(&self, delivery: &Delivery, now: i64) -> Result<(), WebhookError> {
+        let secret = match self.secret(delivery.repository) {
+            Some(secret) => secret,
+            None => return Err(WebhookError::UnknownRepository(delivery.repository.to_owned()))
+        };
+        let signature = match delivery.signature {
+            Some(signature) => signature,
+            None => return Err(WebhookError::Unsigned)
+        };
+
+        let expected = match delivery.source {
+            Source::Gitlab => secret.to_owned(),
+            Source::Bitbucket | Source::Github => format!("sha256={}", sign(secret, delivery.body))
+        };
+        if !constant_time_eq(expected.as_bytes(), signature.as_bytes()) {
+            return Err(WebhookError::InvalidSignature);
+        }
+
+        let window = self.config.replay_window.unwrap_or(DEFAULT_REPLAY_WINDOW);
+        if let Some(timestamp) = delivery.timestamp {
+            if (now - timestamp).abs() > window {
+                return Err(WebhookError::Stale(now - timestamp));
+            }
+        }
+        if let Some(id) = delivery.id {
+            let mut seen = self.seen.lock().expect("Delivery lock should not be poisoned");
+            seen.retain(|_, &mut received| now - received <= window);
+            if seen.contains_key(id) {
+                return Err(WebhookError::Replayed(id.to_owned()));
+            }
+            seen.insert(id.to_owned(), now);
+        }
+        Ok(())
+    }
+
+    fn secret(&self, repository: &str) -> Option<&str> {
+        let secret = self.config.secrets.as_ref().and_then(|secrets| secrets.get(repository));
+        secret.or(self.config.secret.as_ref()).map(String::as_str)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Event {
+    #[serde(rename = "pullRequest")]
+    pull_request: Option<EventPullRequest>
+}
+
+#[derive(Deserialize, Debug)]
+struct EventPullRequest {
+    #[serde(rename = "toRef")]
+    to_ref: EventRef
+}
+
+#[derive(Deserialize, Debug)]
+struct EventRef {
+    repository: EventRepository
+}
+
+#[derive(Deserialize, Debug)]
+struct EventRepository {
+    slug: String,
+    project: EventProject
+}
+
+#[derive(Deserialize, Debug)]
+struct EventProject {
+    key: String
+}
+
+// Whether a Bitbucket Server event, from its X-Event-Key header, should have the pull requests polled
+pub fn triggers(event_key: &str) -> bool {
+    TRIGGERING_EVENTS.contains(&event_key)
+}
+
+// The repository a Bitbucket Server pull request event is about, such as "PROJECT/repo"
+pub fn event_repository(body: &[u8]) -> Option<String> {
+    let event = match serde_json::from_slice::<Event>(body) {
+        Ok(event) => event,
+        Err(_) => return None
+    };
+    event.pull_request.map(|pr| format!("{}/{}", pr.to_ref.repository.project.key, pr.to_ref.repository.slug))
+}
+
+// Receives Bitbucket Server deliveries for as long as it is kept, so that the daemon polls when something changed
+pub struct WebhookReceiver {
+    _listening: Listening,
+    deliveries: Receiver<String>,
+    fallback_interval: Duration
+}
+
+impl WebhookReceiver {
+    // Until a verified delivery of a triggering event arrives or the fallback interval passes
+    pub fn wait(&self) {
+        match self.deliveries.recv_timeout(self.fallback_interval) {
+            Ok(repository) => println!("Webhook delivery received for {}", repository),
+            Err(RecvTimeoutError::Timeout) => println!("No webhook delivery received, polling anyway"),
+            Err(RecvTimeoutError::Disconnected) => return
+        }
+        // Deliveries that arrived meanwhile are all handled by the same poll
+        while self.deliveries.try_recv().is_ok() {}
+    }
+}
+
+pub fn receive(config: &WebhookConfig) -> Result<WebhookReceiver, ConfigError> {
+    let listener = match config.listener {
+        Some(ref listener) => listener,
+        None => return Err(ConfigError::Invalid("The webhook receiver needs a listener".to_owned()))
+    };
+    let (sender, deliveries) = channel();
+    let verifier = Verifier::new(config);
+    let sender = Mutex::new(sender);
+    let listening = match listener::listen(listener, move |request: Request| {
+        Response::new(handle(&request, &verifier, &sender), b"")
+    }) {
+        Ok(listening) => listening,
+        Err(err) => return Err(err)
+    };
+    Ok(WebhookReceiver {
+        _listening: listening,
+        deliveries: deliveries,
+        fallback_interval: Duration::from_secs(config.fallback_interval.unwrap_or(DEFAULT_FALLBACK_INTERVAL))
+    })
+}
+
+fn handle(request: &Request, verifier: &Verifier, sender: &Mutex<Sender<String>>) -> StatusCode {
+    if request.method != Method::POST {
+        return StatusCode::METHOD_NOT_ALLOWED;
+    }
+    let event_key = request.header("X-Event-Key").unwrap_or_default();
+    // Such as the test connection's diagnostics:ping, which is not about a pull request
+    let repository = match event_repository(&request.body) {
+        Some(repository) => repository,
+        None => return StatusCode::NO_CONTENT
+    };
+    let delivery = Delivery {
+        source: Source::Bitbucket,
+        repository: &repository,
+        signature: request.header(Source::Bitbucket.signature_header()),
+        id: request.header(Source::Bitbucket.delivery_header()),
+        timestamp: request.header("Date")
+            .and_then(|date| httpdate::parse_http_date(date).ok())
+            .and_then(|date| date.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs() as i64),
+        body: &request.body
+    };
+    if let Err(err) = verifier.verify(&delivery, time::now_utc().to_timespec().sec) {
+        println!("Webhook delivery refused: {}", err);
+        return StatusCode::UNAUTHORIZED;
+    }
+    if triggers(event_key) {
+        let _ = sender.lock().expect("Delivery sender should not be poisoned").send(repository);
+    }
+    StatusCode::NO_CONTENT
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use errors::WebhookError;
+    use super::{event_repository, triggers, Delivery, Source, Verifier, WebhookConfig};
+
+    static BODY: &'static [u8] = b"The quick brown fox jumps over the lazy dog";
+    static SIGNATURE: &'static str = "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8";
+
+    fn verifier() -> Verifier {
+        let mut secrets = BTreeMap::new();
+        secrets.insert("FOO/bar".to_owned(), "key".to_owned());
+        Verifier::new(&WebhookConfig {
+            secret: None,
+            secrets: Some(secrets),
+            replay_window: Some(300),
+            listener: None,
+            fallback_interval: None
+        })
+    }
+
+    fn delivery<'a>(source: Source, signature: Option<&'a str>, id: Option<&'a str>) -> Delivery<'a> {
+        Delivery {
+            source: source,
+            repository: "FOO/bar",
+            signature: signature,
+            id: id,
+            timestamp: Some(1000),
+            body: BODY
+        }
+    }
+
+    #[test]
+    fn signed_deliveries_are_accepted() {
+        let verifier = verifier();
+        assert!(verifier.verify(&delivery(Source::Bitbucket, Some(SIGNATURE), None), 1000).is_ok());
+        assert!(verifier.verify(&delivery(Source::Github, Some(SIGNATURE), None), 1000).is_ok());
+        assert!(verifier.verify(&delivery(Source::Gitlab, Some("key"), None), 1000).is_ok());
+    }
+
+    #[test]
+    fn unsigned_and_mismatched_deliveries_are_rejected() {
+        let verifier = verifier();
+        match verifier.verify(&delivery(Source::Github, None, None), 1000) {
+            Err(WebhookError::Unsigned) => {},
+            _ => panic!("Expected an unsigned delivery to be rejected")
+        }
+        match verifier.verify(&delivery(Source::Github, Some("sha256=00"), None), 1000) {
+            Err(WebhookError::InvalidSignature) => {},
+            _ => panic!("Expected a mismatched signature to be rejected")
+        }
+        let other = Delivery { repository: "FOO/baz", ..delivery(Source::Github, Some(SIGNATURE), None) };
+        match verifier.verify(&other, 1000) {
+            Err(WebhookError::UnknownRepository(ref repository)) => assert_eq!("FOO/baz", repository),
+            _ => panic!("Expected a repository without a secret to be rejected")
+        }
+    }
+
+    #[test]
+    fn replayed_and_stale_deliveries_are_rejected() {
+        let verifier = verifier();
+        assert!(verifier.verify(&delivery(Source::Github, Some(SIGNATURE), Some("1")), 1000).is_ok());
+        match verifier.verify(&delivery(Source::Github, Some(SIGNATURE), Some("1")), 1010) {
+            Err(WebhookError::Replayed(ref id)) => assert_eq!("1", id),
+            _ => panic!("Expected a replayed delivery to be rejected")
+        }
+        match verifier.verify(&delivery(Source::Github, Some(SIGNATURE), Some("2")), 1301) {
+            Err(WebhookError::Stale(301)) => {},
+            _ => panic!("Expected a stale delivery to be rejected")
+        }
+    }
+
+    #[test]
+    fn pull_request_events_trigger_polls() {
+        assert!(triggers("pr:opened"));
+        assert!(triggers("pr:from_ref_updated"));
+        assert!(triggers("pr:comment:added"));
+        assert!(!triggers("pr:reviewer:approved"));
+        assert!(!triggers("diagnostics:ping"));
+    }
+
+    #[test]
+    fn events_name_their_repository() {
+        let body = json!({
+            "eventKey": "pr:opened",
+            "pullRequest": {
+                "id": 1,
+                "toRef": { "id": "refs/heads/master", "repository": { "slug": "bar", "project": { "key": "FOO" } } }
+            }
+        }).to_string();
+        assert_eq!(Some("FOO/bar".to_owned()), event_repository(body.as_bytes()));
+        assert_eq!(None, event_repository(b"{\"test\": true}"));
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use serde_json;
+
+use ::errors::{ConfigError, Error};
+use ::i18n::Messages;
+use ::pipeline::{self, Context, Outcome, Stage};
+use ::registry;
+use ::store::JsonStore;
+use ::templates::CommentKey;
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct WelcomeConfig {
+    // A JSON file of the authors whose pull requests have been processed, kept across restarts. Kept in memory only
+    // unless set.
+    pub path: Option<String>,
+    // Replaces the default welcome, with `{author}` standing for the author's name
+    pub message: Option<String>,
+    // A link to the contribution guidelines
+    pub guidelines: Option<String>
+}
+
+// The authors whose pull requests the daemon has seen
+pub struct History {
+    authors: JsonStore<BTreeSet<String>>
+}
+
+lazy_static! {
+    static ref HISTORY: Mutex<Option<History>> = Mutex::new(None);
+}
+
+impl History {
+    // A history without a file yet is empty
+    pub fn load(path: &Option<String>) -> Result<History, ConfigError> {
+        JsonStore::load(path).map(|authors| History { authors: authors })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.authors.is_empty()
+    }
+
+    pub fn contains(&self, author: &str) -> bool {
+        self.authors.contains(author)
+    }
+
+    // Rewrites the file when one of the authors is new
+    pub fn record(&mut self, authors: Vec<String>) -> Result<(), ConfigError> {
+        let before = self.authors.len();
+        self.authors.extend(authors);
+        match self.authors.len() > before {
+            true => self.authors.save(),
+            false => Ok(())
+        }
+    }
+}
+
+// Authors are known by their username, or by their email address where the repository has no usernames
+pub fn author_key(author: &::User) -> String {
+    author.username.as_ref().unwrap_or(&author.email).to_lowercase()
+}
+
+fn with_history<T, F>(config: &WelcomeConfig, repository: &::Repository, action: F) -> Result<T, Error>
+        where F: FnOnce(&mut History) -> Result<T, Error> {
+    let mut history = HISTORY.lock().expect("History should not be poisoned");
+    if history.is_none() {
+        let mut loaded = match History::load(&config.path) {
+            Ok(loaded) => loaded,
+            Err(err) => return Err(Error::from(err))
+        };
+        // Authors of pull requests merged before the history was started are no newcomers
+        if loaded.is_empty() {
+            let merged = match repository.get_merged_pr_list() {
+                Ok(merged) => merged,
+                Err(err) => return Err(Error::from(err))
+            };
+            if let Err(err) = loaded.record(merged.iter().map(|pr| author_key(&pr.author)).collect()) {
+                return Err(Error::from(err));
+            }
+        }
+        *history = Some(loaded);
+    }
+    action(history.as_mut().expect("History should be loaded"))
+}
+
+impl WelcomeConfig {
+    pub fn text(&self, author: &::User, messages: &Messages) -> String {
+        let mut text = match self.message {
+            Some(ref message) => message.replace("{author}", &author.name),
+            None => messages.format("welcome.comment", &[("author", &author.name)])
+        };
+        if let Some(ref guidelines) = self.guidelines {
+            text = format!("{}\n\n{}", text, messages.format("welcome.guidelines", &[("url", guidelines)]));
+        }
+        format!("{}\n\n{}", text, messages.get("welcome.ci"))
+    }
+}
+
+// Welcomes authors on their first pull request, once
+impl Stage for WelcomeConfig {
+    fn run(&self, context: &Context) -> Result<Outcome, Error> {
+        let author = author_key(&context.pr.author);
+        with_history(self, context.repository, |history| {
+            if history.contains(&author) {
+                return Ok(Outcome::Continue);
+            }
+            let text = self.text(&context.pr.author, context.messages);
+            let key = CommentKey::notice("welcome", context.pr);
+            if let Err(err) = context.repository.upsert_comment(context.pr, &key, &text) {
+                return Err(Error::from(err));
+            }
+            match history.record(vec![author.to_owned()]) {
+                Ok(_) => Ok(Outcome::Continue),
+                Err(err) => Err(Error::from(err))
+            }
+        })
+    }
+}
+
+pub struct WelcomeFactory;
+
+impl registry::StageFactory for WelcomeFactory {
+    fn create(&self, settings: &serde_json::Value) -> Result<Box<Stage>, ConfigError> {
+        match pipeline::parse_settings::<WelcomeConfig>(settings) {
+            Ok(config) => Ok(Box::new(config)),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use i18n::Messages;
+    use stubs::pull_request;
+    use super::{author_key, History, WelcomeConfig};
+
+    #[test]
+    fn authors_are_remembered() {
+        let mut history = History::load(&None).unwrap();
+        let author = pull_request().author;
+        assert_eq!("aaron", author_key(&author));
+        assert_eq!("aaron@xiao.ming", author_key(&::User { username: None, ..author }));
+
+        assert!(!history.contains("aaron"));
+        history.record(vec!["aaron".to_owned()]).unwrap();
+        assert!(history.contains("aaron"));
+    }
+
+    #[test]
+    fn welcomes_link_the_guidelines() {
+        let messages = Messages::new(&None, &None);
+        let config = WelcomeConfig {
+            path: None,
+            message: Some("Hi {author}!".to_owned()),
+            guidelines: Some("https://example.com/CONTRIBUTING.md".to_owned())
+        };
+        let text = config.text(&pull_request().author, &messages);
+        assert!(text.starts_with("Hi Aaron Xiao Ming!\n\n"));
+        assert!(text.contains("(https://example.com/CONTRIBUTING.md)"));
+        assert!(text.ends_with(messages.get("welcome.ci")));
+    }
+}
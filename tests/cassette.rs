@@ -0,0 +1,38 @@
+// Records a daemon cycle against the mock servers in `support` and replays it without them
+#![cfg(all(feature = "bitbucket", feature = "teamcity"))]
+
+extern crate hyper;
+extern crate pr_demon;
+extern crate serde_json;
+
+mod support;
+
+use std::env;
+use pr_demon::cassette::{self, Cassette, CassetteConfig, CassetteMode};
+use support::{Harness, BUILD_QUEUE, COMMENTS};
+
+fn cassette_config(mode: CassetteMode) -> CassetteConfig {
+    CassetteConfig {
+        mode: mode,
+        path: env::temp_dir().join("pr_demon_cassette_cycle.json").to_string_lossy().into_owned()
+    }
+}
+
+#[test]
+fn recorded_cycles_are_replayed_without_the_network() {
+    let mut harness = Harness::new();
+
+    cassette::insert(Cassette::load(&cassette_config(CassetteMode::Record)).unwrap());
+    assert!(harness.run_cycle().is_ok());
+    cassette::eject();
+    let bitbucket_requests = harness.bitbucket.requests().len();
+    let teamcity_requests = harness.teamcity.requests().len();
+    assert_eq!(1, harness.teamcity.requests_to("POST", BUILD_QUEUE).len());
+    assert_eq!(1, harness.bitbucket.requests_to("POST", COMMENTS).len());
+
+    cassette::insert(Cassette::load(&cassette_config(CassetteMode::Replay)).unwrap());
+    assert!(harness.run_cycle().is_ok());
+    assert_eq!(Some(true), cassette::eject().map(|replayed| replayed.is_finished()));
+    assert_eq!(bitbucket_requests, harness.bitbucket.requests().len());
+    assert_eq!(teamcity_requests, harness.teamcity.requests().len());
+}
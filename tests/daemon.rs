@@ -0,0 +1,548 @@
+// Drives daemon cycles against the mock repository and builder servers in `support`
+#![cfg(all(feature = "bitbucket", feature = "teamcity"))]
+
+extern crate hyper;
+extern crate pr_demon;
+// Only the scenarios of other backends build their JSON inline
+#[cfg_attr(any(feature = "azure_devops", feature = "bitbucket_cloud", feature = "gitea", feature = "github",
+    feature = "gitlab", feature = "jenkins"), macro_use)]
+extern crate serde_json;
+
+mod support;
+
+#[cfg(feature = "azure_devops")]
+use pr_demon::azure_devops::{AzureDevops, AzureDevopsCredentials};
+#[cfg(feature = "bitbucket_cloud")]
+use pr_demon::bitbucket_cloud::{BitbucketCloud, BitbucketCloudCredentials};
+#[cfg(feature = "buildkite")]
+use pr_demon::buildkite::BuildkiteCredentials;
+#[cfg(feature = "drone")]
+use pr_demon::drone::DroneCredentials;
+#[cfg(feature = "gitea")]
+use pr_demon::gitea::{Gitea, GiteaCredentials};
+#[cfg(feature = "github")]
+use pr_demon::github::{Github, GithubCredentials};
+#[cfg(feature = "gitlab")]
+use pr_demon::gitlab::{Gitlab, GitlabCredentials};
+#[cfg(feature = "jenkins")]
+use pr_demon::jenkins::JenkinsCredentials;
+use support::Harness;
+use support::{ACTIVITIES, BUILD, BUILD_QUEUE, BUILD_STATUS, BUILDS, COMMENTS, OLDER_BUILD_STATUS, PARTICIPANTS,
+    PR_COMMITS, PULL_REQUEST, PULL_REQUESTS, USERS};
+
+#[test]
+fn pull_requests_without_builds_are_queued_and_commented_on() {
+    let mut harness = Harness::new();
+    assert!(harness.run_cycle().is_ok());
+
+    let queued = harness.teamcity.requests_to("POST", BUILD_QUEUE);
+    assert_eq!(1, queued.len());
+    assert!(queued[0].body.contains("branchName=\"feature/frobnicator\""));
+
+    let comments = harness.bitbucket.requests_to("POST", COMMENTS);
+    assert_eq!(1, comments.len());
+    let text = support::comment_text(&comments[0]);
+    assert!(text.contains("[Build](https://teamcity.example.com/viewLog.html"));
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+}
+
+#[test]
+fn failed_builds_are_reported_without_queuing_again() {
+    let mut harness = Harness::new();
+    harness.teamcity.on_fixture("GET", BUILDS, 200, "teamcity/builds.json")
+        .on_fixture("GET", BUILD, 200, "teamcity/build_failed.json");
+    assert!(harness.run_cycle().is_ok());
+
+    assert_eq!(0, harness.teamcity.requests_to("POST", BUILD_QUEUE).len());
+    let comments = harness.bitbucket.requests_to("POST", COMMENTS);
+    assert_eq!(1, comments.len());
+    let text = support::comment_text(&comments[0]);
+    assert!(text.contains("has **failed**: Tests failed: 1, passed: 41"));
+}
+
+#[test]
+fn pull_request_list_failures_end_the_cycle() {
+    let mut harness = Harness::new();
+    harness.bitbucket.fail_next("GET", PULL_REQUESTS, 500, 1);
+
+    assert!(harness.run_cycle().is_err());
+    assert!(harness.teamcity.requests().is_empty());
+
+    assert!(harness.run_cycle().is_ok());
+    assert_eq!(1, harness.teamcity.requests_to("POST", BUILD_QUEUE).len());
+}
+
+#[test]
+fn comment_failures_are_retried_on_the_next_cycle() {
+    let mut harness = Harness::new();
+    harness.bitbucket.fail_next("GET", ACTIVITIES, 503, 1);
+
+    assert!(harness.run_cycle().is_ok());
+    assert_eq!(0, harness.bitbucket.requests_to("POST", COMMENTS).len());
+
+    harness.teamcity.on_fixture("GET", BUILDS, 200, "teamcity/builds.json")
+        .on_fixture("GET", BUILD, 200, "teamcity/build_queued.json");
+    assert!(harness.run_cycle().is_ok());
+    assert_eq!(1, harness.teamcity.requests_to("POST", BUILD_QUEUE).len());
+    assert_eq!(1, harness.bitbucket.requests_to("POST", COMMENTS).len());
+}
+
+fn approving(harness: &mut Harness) {
+    harness.approve = Some(true);
+    harness.teamcity.on_fixture("GET", BUILDS, 200, "teamcity/builds.json")
+        .on_fixture("GET", BUILD, 200, "teamcity/build_succeeded.json");
+    harness.bitbucket.on_fixture("GET", USERS, 200, "bitbucket/users.json")
+        .on("PUT", &format!("{}/pr_demon-bot", PARTICIPANTS), 200, &support::fixture("bitbucket/participant.json"));
+}
+
+#[test]
+fn successful_builds_are_approved_as_the_user_looked_up() {
+    let mut harness = Harness::new();
+    approving(&mut harness);
+    assert!(harness.run_cycle().is_ok());
+    assert!(harness.run_cycle().is_ok());
+
+    assert_eq!(0, harness.bitbucket.requests_to("GET", PULL_REQUEST).len());
+    let users = harness.bitbucket.requests_to("GET", USERS);
+    assert_eq!(1, users.len());
+    assert!(users[0].path.ends_with("?filter=pr_demon"));
+    // The listed pull request never shows the approval, so it is given on each cycle
+    let approvals = harness.bitbucket.requests_to("PUT", &format!("{}/pr_demon-bot", PARTICIPANTS));
+    assert_eq!(2, approvals.len());
+    assert!(approvals[0].body.contains("\"status\":\"APPROVED\""));
+}
+
+#[test]
+fn approvals_already_given_are_left_alone() {
+    let mut harness = Harness::new();
+    approving(&mut harness);
+    let mut prs = serde_json::from_str::<serde_json::Value>(&support::fixture("bitbucket/pull_requests.json"))
+        .unwrap();
+    prs["values"][0]["participants"] =
+        serde_json::from_str(&format!("[{}]", support::fixture("bitbucket/participant.json"))).unwrap();
+    harness.bitbucket.on("GET", PULL_REQUESTS, 200, &prs.to_string());
+    assert!(harness.run_cycle().is_ok());
+
+    assert_eq!(0, harness.bitbucket.requests_to("GET", PULL_REQUEST).len());
+    assert_eq!(0, harness.bitbucket.requests_to("GET", USERS).len());
+    assert!(harness.bitbucket.requests().iter().all(|request| request.method != "PUT"));
+}
+
+#[test]
+fn review_failures_do_not_stop_build_statuses() {
+    let mut harness = Harness::new();
+    approving(&mut harness);
+    harness.post_build = true;
+    harness.bitbucket.fail_next("PUT", &format!("{}/pr_demon-bot", PARTICIPANTS), 409, 1)
+        .on("POST", BUILD_STATUS, 204, "");
+    assert!(harness.run_cycle().is_ok());
+
+    assert_eq!(1, harness.bitbucket.requests_to("PUT", &format!("{}/pr_demon-bot", PARTICIPANTS)).len());
+    let statuses = harness.bitbucket.requests_to("POST", BUILD_STATUS);
+    assert_eq!(1, statuses.len());
+    assert!(statuses[0].body.contains("\"state\":\"SUCCESSFUL\""));
+}
+
+#[test]
+fn older_commits_get_statuses_once_for_each_finished_state() {
+    let mut harness = Harness::new();
+    harness.post_build = true;
+    harness.all_commits = Some(true);
+    harness.teamcity.on_fixture("GET", BUILDS, 200, "teamcity/builds.json")
+        .on_fixture("GET", BUILD, 200, "teamcity/build_queued.json");
+    harness.bitbucket.on_fixture("GET", PR_COMMITS, 200, "bitbucket/commits.json")
+        .on("POST", BUILD_STATUS, 204, "")
+        .on("POST", OLDER_BUILD_STATUS, 204, "");
+    assert!(harness.run_cycle().is_ok());
+    assert_eq!(1, harness.bitbucket.requests_to("POST", BUILD_STATUS).len());
+    assert_eq!(0, harness.bitbucket.requests_to("POST", OLDER_BUILD_STATUS).len());
+
+    harness.teamcity.on_fixture("GET", BUILD, 200, "teamcity/build_failed.json");
+    assert!(harness.run_cycle().is_ok());
+    assert!(harness.run_cycle().is_ok());
+    assert_eq!(3, harness.bitbucket.requests_to("POST", BUILD_STATUS).len());
+    assert_eq!(1, harness.bitbucket.requests_to("POST", OLDER_BUILD_STATUS).len());
+
+    harness.teamcity.on_fixture("GET", BUILD, 200, "teamcity/build_succeeded.json");
+    assert!(harness.run_cycle().is_ok());
+    let older = harness.bitbucket.requests_to("POST", OLDER_BUILD_STATUS);
+    assert_eq!(2, older.len());
+    assert!(older[1].body.contains("\"state\":\"SUCCESSFUL\""));
+}
+
+#[cfg(feature = "github")]
+#[test]
+fn github_pull_requests_are_commented_on_and_the_comment_updated() {
+    use support::{Daemon, MockServer};
+    use support::{BUILD_QUEUE, GITHUB_COMMENT, GITHUB_COMMENTS, GITHUB_PULL_REQUESTS};
+    let github = MockServer::start();
+    github.on_fixture("GET", GITHUB_PULL_REQUESTS, 200, "github/pull_requests.json")
+        .on("GET", GITHUB_COMMENTS, 200, "[]")
+        .on_fixture("POST", GITHUB_COMMENTS, 201, "github/comment.json")
+        .on_fixture("PATCH", GITHUB_COMMENT, 200, "github/comment.json");
+    let teamcity = support::teamcity();
+    let mut daemon = Daemon::new();
+    let credentials = GithubCredentials {
+        username: "pr_demon".to_owned(),
+        token: "token".to_owned(),
+        credential_provider: None,
+        api_url: Some(github.base_url.to_owned()),
+        owner: "foo".to_owned(),
+        repo: "bar".to_owned(),
+        post_build: false,
+        templates: None,
+        superseded_comments: None,
+        graphql: None,
+        merged_within: None
+    };
+    let repository = Github::new(&credentials, &daemon.messages, &daemon.fanout).unwrap();
+    let builder = support::teamcity_credentials(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    let posted = github.requests_to("POST", GITHUB_COMMENTS);
+    assert_eq!(1, posted.len());
+    let text = support::body_field(&posted[0], "body");
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+
+    github.on("GET", GITHUB_COMMENTS, 200, &json!([{ "id": 20, "body": text, "user": { "login": "pr_demon" } }])
+        .to_string());
+    support::teamcity_succeeds(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    assert_eq!(1, github.requests_to("POST", GITHUB_COMMENTS).len());
+    let edited = github.requests_to("PATCH", GITHUB_COMMENT);
+    assert_eq!(1, edited.len());
+    assert!(support::body_field(&edited[0], "body").contains("is **successful**: Tests passed: 42"));
+}
+
+#[cfg(feature = "gitlab")]
+#[test]
+fn gitlab_merge_requests_are_commented_on_and_the_note_updated() {
+    use support::{Daemon, MockServer};
+    use support::{BUILD_QUEUE, GITLAB_MERGE_REQUESTS, GITLAB_NOTE, GITLAB_NOTES};
+    let gitlab = MockServer::start();
+    gitlab.on_fixture("GET", GITLAB_MERGE_REQUESTS, 200, "gitlab/merge_requests.json")
+        .on("GET", GITLAB_NOTES, 200, "[]")
+        .on_fixture("POST", GITLAB_NOTES, 201, "gitlab/note.json")
+        .on_fixture("PUT", GITLAB_NOTE, 200, "gitlab/note.json");
+    let teamcity = support::teamcity();
+    let mut daemon = Daemon::new();
+    let credentials = GitlabCredentials {
+        username: "pr_demon".to_owned(),
+        token: "token".to_owned(),
+        credential_provider: None,
+        base_url: Some(gitlab.base_url.to_owned()),
+        project: "foo/bar".to_owned(),
+        post_build: false,
+        templates: None,
+        superseded_comments: None
+    };
+    let repository = Gitlab::new(&credentials, &daemon.messages, &daemon.fanout).unwrap();
+    let builder = support::teamcity_credentials(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    let posted = gitlab.requests_to("POST", GITLAB_NOTES);
+    assert_eq!(1, posted.len());
+    let text = support::body_field(&posted[0], "body");
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+
+    let author = json!({ "id": 1, "username": "pr_demon", "name": "PR Demon" });
+    gitlab.on("GET", GITLAB_NOTES, 200, &json!([{ "id": 30, "body": text, "author": author, "system": false }])
+        .to_string());
+    support::teamcity_succeeds(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    assert_eq!(1, gitlab.requests_to("POST", GITLAB_NOTES).len());
+    let edited = gitlab.requests_to("PUT", GITLAB_NOTE);
+    assert_eq!(1, edited.len());
+    assert!(support::body_field(&edited[0], "body").contains("is **successful**: Tests passed: 42"));
+}
+
+#[cfg(feature = "gitea")]
+#[test]
+fn gitea_pull_requests_are_commented_on_and_the_comment_updated() {
+    use support::{Daemon, MockServer};
+    use support::{BUILD_QUEUE, GITEA_COMMENT, GITEA_COMMENTS, GITEA_PULL_REQUESTS};
+    let gitea = MockServer::start();
+    gitea.on_fixture("GET", GITEA_PULL_REQUESTS, 200, "gitea/pull_requests.json")
+        .on("GET", GITEA_COMMENTS, 200, "[]")
+        .on_fixture("POST", GITEA_COMMENTS, 201, "gitea/comment.json")
+        .on_fixture("PATCH", GITEA_COMMENT, 200, "gitea/comment.json");
+    let teamcity = support::teamcity();
+    let mut daemon = Daemon::new();
+    let credentials = GiteaCredentials {
+        username: "pr_demon".to_owned(),
+        token: "token".to_owned(),
+        credential_provider: None,
+        base_url: gitea.base_url.to_owned(),
+        owner: "foo".to_owned(),
+        repo: "bar".to_owned(),
+        post_build: false,
+        templates: None,
+        superseded_comments: None
+    };
+    let repository = Gitea::new(&credentials, &daemon.messages, &daemon.fanout).unwrap();
+    let builder = support::teamcity_credentials(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    let posted = gitea.requests_to("POST", GITEA_COMMENTS);
+    assert_eq!(1, posted.len());
+    let text = support::body_field(&posted[0], "body");
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+
+    let user = json!({ "login": "pr_demon", "full_name": "PR Demon", "email": "pr_demon@example.com" });
+    gitea.on("GET", GITEA_COMMENTS, 200, &json!([{ "id": 40, "body": text, "user": user }]).to_string());
+    support::teamcity_succeeds(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    assert_eq!(1, gitea.requests_to("POST", GITEA_COMMENTS).len());
+    let edited = gitea.requests_to("PATCH", GITEA_COMMENT);
+    assert_eq!(1, edited.len());
+    assert!(support::body_field(&edited[0], "body").contains("is **successful**: Tests passed: 42"));
+}
+
+#[cfg(feature = "azure_devops")]
+#[test]
+fn azure_devops_pull_requests_are_commented_on_and_the_thread_updated() {
+    use support::{Daemon, MockServer};
+    use support::{AZURE_COMMENT, AZURE_ITERATIONS, AZURE_PULL_REQUESTS, AZURE_THREADS, BUILD_QUEUE};
+    let azure = MockServer::start();
+    azure.on_fixture("GET", AZURE_PULL_REQUESTS, 200, "azure_devops/pull_requests.json")
+        .on_fixture("GET", AZURE_ITERATIONS, 200, "azure_devops/iterations.json")
+        .on("GET", AZURE_THREADS, 200, "{ \"count\": 0, \"value\": [] }")
+        .on_fixture("POST", AZURE_THREADS, 200, "azure_devops/thread.json")
+        .on_fixture("PATCH", AZURE_COMMENT, 200, "azure_devops/comment.json");
+    let teamcity = support::teamcity();
+    let mut daemon = Daemon::new();
+    let credentials = AzureDevopsCredentials {
+        username: "pr_demon@example.com".to_owned(),
+        token: "token".to_owned(),
+        credential_provider: None,
+        base_url: Some(azure.base_url.to_owned()),
+        organization: "foo".to_owned(),
+        project: "bar".to_owned(),
+        repository: "baz".to_owned(),
+        post_build: false,
+        templates: None,
+        superseded_comments: None
+    };
+    let repository = AzureDevops::new(&credentials, &daemon.messages, &daemon.fanout).unwrap();
+    let builder = support::teamcity_credentials(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    let posted = azure.requests_to("POST", AZURE_THREADS);
+    assert_eq!(1, posted.len());
+    let thread = serde_json::from_str::<serde_json::Value>(&posted[0].body).unwrap();
+    let text = thread["comments"][0]["content"].as_str().unwrap().to_owned();
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+
+    let mut thread = serde_json::from_str::<serde_json::Value>(&support::fixture("azure_devops/thread.json")).unwrap();
+    thread["comments"][0]["content"] = json!(text);
+    azure.on("GET", AZURE_THREADS, 200, &json!({ "count": 1, "value": [thread] }).to_string());
+    support::teamcity_succeeds(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    assert_eq!(1, azure.requests_to("POST", AZURE_THREADS).len());
+    let edited = azure.requests_to("PATCH", AZURE_COMMENT);
+    assert_eq!(1, edited.len());
+    assert!(support::body_field(&edited[0], "content").contains("is **successful**: Tests passed: 42"));
+}
+
+#[cfg(feature = "bitbucket_cloud")]
+#[test]
+fn bitbucket_cloud_pull_requests_are_commented_on_and_the_comment_updated() {
+    use support::{Daemon, MockServer};
+    use support::{BUILD_QUEUE, CLOUD_COMMENT, CLOUD_COMMENTS, CLOUD_PULL_REQUESTS};
+    let cloud = MockServer::start();
+    cloud.on_fixture("GET", CLOUD_PULL_REQUESTS, 200, "bitbucket_cloud/pull_requests.json")
+        .on("GET", CLOUD_COMMENTS, 200, "{ \"values\": [] }")
+        .on_fixture("POST", CLOUD_COMMENTS, 201, "bitbucket_cloud/comment.json")
+        .on_fixture("PUT", CLOUD_COMMENT, 200, "bitbucket_cloud/comment.json");
+    let teamcity = support::teamcity();
+    let mut daemon = Daemon::new();
+    let credentials = BitbucketCloudCredentials {
+        username: "pr_demon".to_owned(),
+        app_password: "password".to_owned(),
+        credential_provider: None,
+        account_id: "557058:pr_demon".to_owned(),
+        api_url: Some(cloud.base_url.to_owned()),
+        workspace: "foo".to_owned(),
+        repo_slug: "bar".to_owned(),
+        post_build: false,
+        templates: None,
+        superseded_comments: None
+    };
+    let repository = BitbucketCloud::new(&credentials, &daemon.messages, &daemon.fanout).unwrap();
+    let builder = support::teamcity_credentials(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    let posted = cloud.requests_to("POST", CLOUD_COMMENTS);
+    assert_eq!(1, posted.len());
+    let comment = serde_json::from_str::<serde_json::Value>(&posted[0].body).unwrap();
+    let text = comment["content"]["raw"].as_str().unwrap().to_owned();
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+
+    let user = json!({ "display_name": "PR Demon", "account_id": "557058:pr_demon" });
+    cloud.on("GET", CLOUD_COMMENTS, 200, &json!({ "values": [{ "id": 60, "content": { "raw": text }, "user": user }] })
+        .to_string());
+    support::teamcity_succeeds(&teamcity);
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, teamcity.requests_to("POST", BUILD_QUEUE).len());
+    assert_eq!(1, cloud.requests_to("POST", CLOUD_COMMENTS).len());
+    let edited = cloud.requests_to("PUT", CLOUD_COMMENT);
+    assert_eq!(1, edited.len());
+    let comment = serde_json::from_str::<serde_json::Value>(&edited[0].body).unwrap();
+    assert!(comment["content"]["raw"].as_str().unwrap().contains("is **successful**: Tests passed: 42"));
+}
+
+#[cfg(feature = "jenkins")]
+#[test]
+fn jenkins_builds_are_commented_on_and_the_comment_updated() {
+    use pr_demon::bitbucket::Bitbucket;
+    use support::{Daemon, MockServer};
+    use support::{COMMENT, COMMENTS, JENKINS_BUILD, JENKINS_BUILDS, JENKINS_BUILD_WITH_PARAMETERS, JENKINS_QUEUE,
+        JENKINS_QUEUE_ITEM};
+    let bitbucket = support::bitbucket();
+    let jenkins = MockServer::start();
+    let mut queued = support::json_fixture("jenkins/queue_item.json");
+    queued["task"] = json!({ "url": format!("{}/job/frobnicator/", jenkins.base_url) });
+    jenkins.once("GET", JENKINS_QUEUE, 200, "{ \"items\": [] }")
+        .on("GET", JENKINS_QUEUE, 200, &json!({ "items": [queued] }).to_string())
+        .on_fixture("GET", JENKINS_QUEUE_ITEM, 200, "jenkins/queue_item.json")
+        .on("GET", JENKINS_BUILDS, 200, "{ \"builds\": [] }")
+        .on("POST", JENKINS_BUILD_WITH_PARAMETERS, 201, "");
+    let mut daemon = Daemon::new();
+    let repository = Bitbucket::new(&support::bitbucket_credentials(&bitbucket), &daemon.messages, &daemon.fanout)
+        .unwrap();
+    let builder = JenkinsCredentials {
+        username: Some("pr_demon".to_owned()),
+        token: Some("token".to_owned()),
+        password: None,
+        credential_provider: None,
+        base_url: jenkins.base_url.to_owned(),
+        job: "frobnicator".to_owned(),
+        matrix: None,
+        branch_parameter: None,
+        parameters: None,
+        crumb: None,
+        pin_merged: None,
+        log_lines: None
+    };
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    let queued = jenkins.requests_to("POST", JENKINS_BUILD_WITH_PARAMETERS);
+    assert_eq!(1, queued.len());
+    assert_eq!("BRANCH=feature%2Ffrobnicator", queued[0].body);
+    let posted = bitbucket.requests_to("POST", COMMENTS);
+    assert_eq!(1, posted.len());
+    let text = support::comment_text(&posted[0]);
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+
+    support::bitbucket_has_commented(&bitbucket, &text);
+    jenkins.on("GET", JENKINS_QUEUE, 200, "{ \"items\": [] }")
+        .on_fixture("GET", JENKINS_BUILDS, 200, "jenkins/builds.json")
+        .on_fixture("GET", JENKINS_BUILD, 200, "jenkins/build_succeeded.json");
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, jenkins.requests_to("POST", JENKINS_BUILD_WITH_PARAMETERS).len());
+    assert_eq!(1, bitbucket.requests_to("POST", COMMENTS).len());
+    let edited = bitbucket.requests_to("PUT", COMMENT);
+    assert_eq!(1, edited.len());
+    assert!(support::comment_text(&edited[0]).contains("is **successful**"));
+}
+
+#[cfg(feature = "buildkite")]
+#[test]
+fn buildkite_builds_are_commented_on_and_the_comment_updated() {
+    use pr_demon::bitbucket::Bitbucket;
+    use support::{Daemon, MockServer};
+    use support::{BUILDKITE_BUILD, BUILDKITE_BUILDS, COMMENT, COMMENTS};
+    let bitbucket = support::bitbucket();
+    let buildkite = MockServer::start();
+    buildkite.on("GET", BUILDKITE_BUILDS, 200, "[]")
+        .on_fixture("POST", BUILDKITE_BUILDS, 201, "buildkite/build_scheduled.json");
+    let mut daemon = Daemon::new();
+    let repository = Bitbucket::new(&support::bitbucket_credentials(&bitbucket), &daemon.messages, &daemon.fanout)
+        .unwrap();
+    let builder = BuildkiteCredentials {
+        token: "token".to_owned(),
+        credential_provider: None,
+        api_url: Some(buildkite.base_url.to_owned()),
+        organization: "foo".to_owned(),
+        pipeline: "frobnicator".to_owned(),
+        matrix: None,
+        env: None
+    };
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    let queued = buildkite.requests_to("POST", BUILDKITE_BUILDS);
+    assert_eq!(1, queued.len());
+    assert_eq!("feature/frobnicator", support::body_field(&queued[0], "branch"));
+    let posted = bitbucket.requests_to("POST", COMMENTS);
+    assert_eq!(1, posted.len());
+    let text = support::comment_text(&posted[0]);
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+
+    support::bitbucket_has_commented(&bitbucket, &text);
+    buildkite.on("GET", BUILDKITE_BUILDS, 200, &format!("[{}]", support::fixture("buildkite/build_passed.json")))
+        .on_fixture("GET", BUILDKITE_BUILD, 200, "buildkite/build_passed.json");
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, buildkite.requests_to("POST", BUILDKITE_BUILDS).len());
+    assert_eq!(1, bitbucket.requests_to("POST", COMMENTS).len());
+    let edited = bitbucket.requests_to("PUT", COMMENT);
+    assert_eq!(1, edited.len());
+    assert!(support::comment_text(&edited[0]).contains("is **successful**: passed"));
+}
+
+#[cfg(feature = "drone")]
+#[test]
+fn drone_builds_are_commented_on_and_the_comment_updated() {
+    use pr_demon::bitbucket::Bitbucket;
+    use support::{Daemon, MockServer};
+    use support::{COMMENT, COMMENTS, DRONE_BUILD, DRONE_BUILDS};
+    let bitbucket = support::bitbucket();
+    let drone = MockServer::start();
+    drone.on("GET", DRONE_BUILDS, 200, "[]")
+        .on_fixture("POST", DRONE_BUILDS, 200, "drone/build_pending.json");
+    let mut daemon = Daemon::new();
+    let repository = Bitbucket::new(&support::bitbucket_credentials(&bitbucket), &daemon.messages, &daemon.fanout)
+        .unwrap();
+    let builder = DroneCredentials {
+        token: "token".to_owned(),
+        credential_provider: None,
+        server: drone.base_url.to_owned(),
+        repo: "foo/frobnicator".to_owned(),
+        parameters: None
+    };
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    let queued = drone.requests_to("POST", DRONE_BUILDS);
+    assert_eq!(1, queued.len());
+    assert!(queued[0].path.ends_with("?branch=feature%2Ffrobnicator"));
+    let posted = bitbucket.requests_to("POST", COMMENTS);
+    assert_eq!(1, posted.len());
+    let text = support::comment_text(&posted[0]);
+    assert!(text.contains("<!-- pr_demon {\"kind\":\"status\",\"pr\":1"));
+
+    support::bitbucket_has_commented(&bitbucket, &text);
+    drone.on("GET", DRONE_BUILDS, 200, &format!("[{}]", support::fixture("drone/build_succeeded.json")))
+        .on_fixture("GET", DRONE_BUILD, 200, "drone/build_succeeded.json");
+    assert!(daemon.run_cycle(&repository, &builder).is_ok());
+
+    assert_eq!(1, drone.requests_to("POST", DRONE_BUILDS).len());
+    assert_eq!(1, bitbucket.requests_to("POST", COMMENTS).len());
+    let edited = bitbucket.requests_to("PUT", COMMENT);
+    assert_eq!(1, edited.len());
+    assert!(support::comment_text(&edited[0]).contains("is **successful**: success"));
+}
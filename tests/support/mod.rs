@@ -0,0 +1,377 @@
+// In-process mock servers standing in for repositories and builders, and harnesses driving daemon cycles against them
+#![allow(dead_code)]
+
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use hyper::StatusCode;
+use serde_json::Value;
+
+use pr_demon;
+use pr_demon::bitbucket::{Bitbucket, BitbucketCredentials};
+use pr_demon::errors::RepositoryError;
+use pr_demon::fanout::{Fanout, Message};
+use pr_demon::i18n::Messages;
+use pr_demon::listener::{self, Listening, ListenerConfig, Request, Response};
+use pr_demon::pipeline::{BuildStage, Pipeline};
+use pr_demon::teamcity::TeamcityCredentials;
+
+pub static PULL_REQUESTS: &'static str = "/api/latest/projects/foo/repos/bar/pull-requests";
+pub static PULL_REQUEST: &'static str = "/api/latest/projects/foo/repos/bar/pull-requests/1";
+pub static PARTICIPANTS: &'static str = "/api/latest/projects/foo/repos/bar/pull-requests/1/participants";
+pub static USERS: &'static str = "/api/latest/users";
+pub static BUILD_STATUS: &'static str = "/build-status/1.0/commits/c0ffee0000000000000000000000000000000001";
+pub static OLDER_BUILD_STATUS: &'static str = "/build-status/1.0/commits/c0ffee0000000000000000000000000000000002";
+pub static PR_COMMITS: &'static str = "/api/latest/projects/foo/repos/bar/pull-requests/1/commits";
+pub static ACTIVITIES: &'static str = "/api/latest/projects/foo/repos/bar/pull-requests/1/activities";
+pub static COMMENTS: &'static str = "/api/latest/projects/foo/repos/bar/pull-requests/1/comments";
+pub static COMMENT: &'static str = "/api/latest/projects/foo/repos/bar/pull-requests/1/comments/10";
+pub static BUILDS: &'static str = "/app/rest/buildTypes/id:Build_Linux/builds";
+pub static BUILD_QUEUE: &'static str = "/app/rest/buildQueue";
+pub static BUILD: &'static str = "/app/rest/builds/id:100";
+pub static GITHUB_PULL_REQUESTS: &'static str = "/repos/foo/bar/pulls";
+pub static GITHUB_COMMENTS: &'static str = "/repos/foo/bar/issues/1/comments";
+pub static GITHUB_COMMENT: &'static str = "/repos/foo/bar/issues/comments/20";
+pub static GITLAB_MERGE_REQUESTS: &'static str = "/api/v4/projects/foo%2Fbar/merge_requests";
+pub static GITLAB_NOTES: &'static str = "/api/v4/projects/foo%2Fbar/merge_requests/1/notes";
+pub static GITLAB_NOTE: &'static str = "/api/v4/projects/foo%2Fbar/merge_requests/1/notes/30";
+pub static GITEA_PULL_REQUESTS: &'static str = "/api/v1/repos/foo/bar/pulls";
+pub static GITEA_COMMENTS: &'static str = "/api/v1/repos/foo/bar/issues/1/comments";
+pub static GITEA_COMMENT: &'static str = "/api/v1/repos/foo/bar/issues/comments/40";
+pub static AZURE_PULL_REQUESTS: &'static str = "/foo/bar/_apis/git/repositories/baz/pullrequests";
+pub static AZURE_THREADS: &'static str = "/foo/bar/_apis/git/repositories/baz/pullRequests/1/threads";
+pub static AZURE_ITERATIONS: &'static str = "/foo/bar/_apis/git/repositories/baz/pullRequests/1/iterations";
+pub static AZURE_COMMENT: &'static str = "/foo/bar/_apis/git/repositories/baz/pullRequests/1/threads/50/comments/1";
+pub static CLOUD_PULL_REQUESTS: &'static str = "/repositories/foo/bar/pullrequests";
+pub static CLOUD_COMMENTS: &'static str = "/repositories/foo/bar/pullrequests/1/comments";
+pub static CLOUD_COMMENT: &'static str = "/repositories/foo/bar/pullrequests/1/comments/60";
+pub static JENKINS_QUEUE: &'static str = "/queue/api/json";
+pub static JENKINS_QUEUE_ITEM: &'static str = "/queue/item/7/api/json";
+pub static JENKINS_BUILDS: &'static str = "/job/frobnicator/api/json";
+pub static JENKINS_BUILD_WITH_PARAMETERS: &'static str = "/job/frobnicator/buildWithParameters";
+pub static JENKINS_BUILD: &'static str = "/job/frobnicator/12/api/json";
+pub static BUILDKITE_BUILDS: &'static str = "/organizations/foo/pipelines/frobnicator/builds";
+pub static BUILDKITE_BUILD: &'static str = "/organizations/foo/pipelines/frobnicator/builds/5";
+pub static DRONE_BUILDS: &'static str = "/api/repos/foo/frobnicator/builds";
+pub static DRONE_BUILD: &'static str = "/api/repos/foo/frobnicator/builds/3";
+
+pub fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/mock/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let mut contents = String::new();
+    match File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)) {
+        Ok(_) => contents,
+        Err(err) => panic!("Unable to read fixture {}: {}", path, err)
+    }
+}
+
+pub fn json_fixture(name: &str) -> Value {
+    match ::serde_json::from_str(&fixture(name)) {
+        Ok(json) => json,
+        Err(err) => panic!("Unable to parse fixture {}: {}", name, err)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub body: String
+}
+
+#[derive(Clone)]
+struct MockResponse {
+    status: StatusCode,
+    body: String
+}
+
+struct Route {
+    method: String,
+    path: String,
+    // Scripted responses are served once each, in order, before falling back to the canned response
+    scripted: VecDeque<MockResponse>,
+    canned: Option<MockResponse>
+}
+
+impl Route {
+    // Routes ignore the query string unless they include one
+    fn matches(&self, method: &str, path: &str) -> bool {
+        self.method == method && (self.path == path || self.path == path.split('?').next().unwrap_or(path))
+    }
+}
+
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<RecordedRequest>
+}
+
+fn respond(state: &Mutex<State>, request: Request) -> Response {
+    let method = request.method.to_string();
+    let mock_response = {
+        let mut state = state.lock().expect("Mock server lock should not be poisoned");
+        state.requests.push(RecordedRequest {
+            method: method.to_owned(),
+            path: request.path.to_owned(),
+            body: String::from_utf8_lossy(&request.body).into_owned()
+        });
+        match state.routes.iter_mut().rev().find(|route| route.matches(&method, &request.path)) {
+            Some(route) => route.scripted.pop_front().or(route.canned.clone()),
+            None => None
+        }
+    };
+
+    let mock_response = mock_response.unwrap_or(MockResponse {
+        status: StatusCode::NOT_FOUND,
+        body: "".to_owned()
+    });
+    Response::new(mock_response.status, mock_response.body.as_bytes())
+}
+
+pub struct MockServer {
+    pub base_url: String,
+    state: Arc<Mutex<State>>,
+    // Serves for as long as the mock server is kept
+    _listening: Listening
+}
+
+impl MockServer {
+    pub fn start() -> MockServer {
+        let state = Arc::new(Mutex::new(State {
+            routes: vec![],
+            requests: vec![]
+        }));
+        let served = state.clone();
+        let config = ListenerConfig { address: "127.0.0.1:0".to_owned(), tls: None };
+        let listening = listener::listen(&config, move |request: Request| respond(&served, request))
+            .expect("Mock server should start");
+
+        MockServer {
+            base_url: format!("http://{}", listening.socket),
+            state: state,
+            _listening: listening
+        }
+    }
+
+    fn with_route<F>(&self, method: &str, path: &str, update: F) where F: FnOnce(&mut Route) {
+        let mut state = self.state.lock().expect("Mock server lock should not be poisoned");
+        if !state.routes.iter().any(|route| route.method == method && route.path == path) {
+            state.routes.push(Route {
+                method: method.to_owned(),
+                path: path.to_owned(),
+                scripted: VecDeque::new(),
+                canned: None
+            });
+        }
+        let route = state.routes.iter_mut().find(|route| route.method == method && route.path == path).unwrap();
+        update(route);
+    }
+
+    // Answers every matching request with `body`
+    pub fn on(&self, method: &str, path: &str, status: u16, body: &str) -> &MockServer {
+        self.with_route(method, path, |route| {
+            route.canned = Some(MockResponse {
+                status: StatusCode::from_u16(status).expect("Mock responses should have a valid status"),
+                body: body.to_owned()
+            });
+        });
+        self
+    }
+
+    pub fn on_fixture(&self, method: &str, path: &str, status: u16, name: &str) -> &MockServer {
+        self.on(method, path, status, &fixture(name))
+    }
+
+    // Answers the next `times` matching requests with `status` before going back to the canned response
+    pub fn fail_next(&self, method: &str, path: &str, status: u16, times: usize) -> &MockServer {
+        for _ in 0..times {
+            self.once(method, path, status, "");
+        }
+        self
+    }
+
+    // Answers the next matching request with `body`, after any answers scripted before it
+    pub fn once(&self, method: &str, path: &str, status: u16, body: &str) -> &MockServer {
+        self.with_route(method, path, |route| {
+            route.scripted.push_back(MockResponse {
+                status: StatusCode::from_u16(status).expect("Mock responses should have a valid status"),
+                body: body.to_owned()
+            });
+        });
+        self
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().expect("Mock server lock should not be poisoned").requests.clone()
+    }
+
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<RecordedRequest> {
+        self.requests().into_iter()
+            .filter(|request| request.method == method && request.path.split('?').next() == Some(path))
+            .collect()
+    }
+}
+
+// What the daemon keeps between cycles, polling whichever repository it is given with a single builder
+pub struct Daemon {
+    config: pr_demon::Config,
+    pub messages: Messages,
+    pub fanout: Fanout<Message>,
+    pinned: HashSet<i32>
+}
+
+impl Daemon {
+    pub fn new() -> Daemon {
+        let config = pr_demon::parse_config(r#"{ "run_interval": 0 }"#).expect("Harness config should parse");
+        let messages = Messages::new(&config.locale, &config.labels);
+        Daemon {
+            config: config,
+            messages: messages,
+            fanout: Fanout::new(),
+            pinned: HashSet::new()
+        }
+    }
+
+    pub fn run_cycle(&mut self, repository: &pr_demon::Repository, builder: &pr_demon::ContinuousIntegrator)
+            -> Result<(), RepositoryError> {
+        let matrix: Vec<&pr_demon::ContinuousIntegrator> = vec![builder];
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Box::new(BuildStage));
+        pr_demon::poll_once(repository, &matrix, &pipeline, &self.config, &self.messages, &self.fanout,
+            &mut self.pinned, None)
+    }
+}
+
+// A mock Bitbucket server with one open pull request and no comments, taking the comments posted to it
+pub fn bitbucket() -> MockServer {
+    let bitbucket = MockServer::start();
+    bitbucket.on_fixture("GET", PULL_REQUESTS, 200, "bitbucket/pull_requests.json")
+        .on_fixture("GET", ACTIVITIES, 200, "bitbucket/activities.json")
+        .on_fixture("POST", COMMENTS, 201, "bitbucket/comment.json");
+    bitbucket
+}
+
+// Lists a comment of the daemon's with `text` on the pull request from then on, to be edited
+pub fn bitbucket_has_commented(bitbucket: &MockServer, text: &str) {
+    let mut comment = json_fixture("bitbucket/comment.json");
+    comment["text"] = Value::String(text.to_owned());
+    let mut activity = ::serde_json::from_str::<Value>(
+        r#"{ "id": 1, "createdDate": 1500000000000, "action": "COMMENTED", "commentAction": "ADDED" }"#).unwrap();
+    activity["user"] = comment["author"].clone();
+    activity["comment"] = comment;
+    let mut activities = json_fixture("bitbucket/activities.json");
+    activities["size"] = Value::from(1);
+    activities["values"] = Value::Array(vec![activity]);
+    bitbucket.on("GET", ACTIVITIES, 200, &activities.to_string())
+        .on_fixture("PUT", COMMENT, 200, "bitbucket/comment.json");
+}
+
+pub fn bitbucket_credentials(bitbucket: &MockServer) -> BitbucketCredentials {
+    BitbucketCredentials {
+        username: "pr_demon".to_owned(),
+        password: "password".to_owned(),
+        credential_provider: None,
+        base_url: bitbucket.base_url.to_owned(),
+        project_slug: "foo".to_owned(),
+        repo_slug: "bar".to_owned(),
+        post_build: false,
+        templates: None,
+        summary_comment: None,
+        superseded_comments: None,
+        all_commits: None,
+        approve: None
+    }
+}
+
+// A mock TeamCity server with no builds yet, queuing them as build 100
+pub fn teamcity() -> MockServer {
+    let teamcity = MockServer::start();
+    teamcity.on_fixture("GET", BUILDS, 200, "teamcity/builds_empty.json")
+        .on_fixture("POST", BUILD_QUEUE, 200, "teamcity/build_queued.json")
+        .on_fixture("POST", &format!("{}/tags", BUILD), 200, "teamcity/tags.json")
+        .on("PUT", &format!("{}/comment", BUILD), 204, "");
+    teamcity
+}
+
+pub fn teamcity_credentials(teamcity: &MockServer) -> TeamcityCredentials {
+    TeamcityCredentials {
+        username: None,
+        password: None,
+        token: Some("token".to_owned()),
+        credential_provider: None,
+        base_url: format!("{}/app/rest", teamcity.base_url),
+        build_id: "Build_Linux".to_owned(),
+        matrix: None,
+        pin_merged: None,
+        log_lines: None,
+        coverage_threshold: None,
+        personal_builds: None,
+        composite: None,
+        steps: None,
+        benchmark_prefix: None
+    }
+}
+
+// Builds 100 of TeamCity as finished successfully from then on
+pub fn teamcity_succeeds(teamcity: &MockServer) {
+    teamcity.on_fixture("GET", BUILDS, 200, "teamcity/builds.json")
+        .on_fixture("GET", BUILD, 200, "teamcity/build_succeeded.json");
+}
+
+// A daemon polling a mock Bitbucket repository with one open pull request, built by a mock TeamCity server
+pub struct Harness {
+    pub bitbucket: MockServer,
+    pub teamcity: MockServer,
+    // Bitbucket settings left out of the defaults
+    pub post_build: bool,
+    pub all_commits: Option<bool>,
+    pub approve: Option<bool>,
+    // Created on the first cycle and kept, like the daemon keeps its repository
+    repository: Option<Bitbucket>,
+    daemon: Daemon
+}
+
+impl Harness {
+    pub fn new() -> Harness {
+        Harness {
+            bitbucket: bitbucket(),
+            teamcity: teamcity(),
+            post_build: false,
+            all_commits: None,
+            approve: None,
+            repository: None,
+            daemon: Daemon::new()
+        }
+    }
+
+    pub fn repository(&self) -> Bitbucket {
+        let credentials = BitbucketCredentials {
+            post_build: self.post_build,
+            all_commits: self.all_commits,
+            approve: self.approve,
+            ..bitbucket_credentials(&self.bitbucket)
+        };
+        Bitbucket::new(&credentials, &self.daemon.messages, &self.daemon.fanout).expect("Default templates should load")
+    }
+
+    pub fn run_cycle(&mut self) -> Result<(), RepositoryError> {
+        if self.repository.is_none() {
+            self.repository = Some(self.repository());
+        }
+        let builder = teamcity_credentials(&self.teamcity);
+        self.daemon.run_cycle(self.repository.as_ref().unwrap(), &builder)
+    }
+}
+
+// The text of a comment posted or edited by the daemon
+pub fn comment_text(request: &RecordedRequest) -> String {
+    body_field(request, "text")
+}
+
+// A string field of the JSON body of a request, such as the text of a comment on backends other than Bitbucket
+pub fn body_field(request: &RecordedRequest, field: &str) -> String {
+    match ::serde_json::from_str::<::serde_json::Value>(&request.body) {
+        Ok(ref json) => json[field].as_str().unwrap_or("").to_owned(),
+        Err(_) => "".to_owned()
+    }
+}